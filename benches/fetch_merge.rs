@@ -0,0 +1,69 @@
+//! Fetch merging on a hotspot panel: scores dense clusters of variants with one
+//! fetch per variant (`--fetch-merge-distance 0`) and with nearby variants merged
+//! into one fetch and pileup traversal (the default), on the synthetic test data.
+//!
+//! Run with `cargo bench --bench fetch_merge`; VLOD_BENCH_ITERATIONS sets the
+//! number of timed runs of each configuration (default 20).
+
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use vlod_rs::{
+    bam::DEFAULT_FETCH_MERGE_DISTANCE, lod::calculate_detectability_scores_until, testdata::write_test_data,
+    LodConfig, Variant,
+};
+
+/// Variants of each cluster, two bases apart around a covered test site
+const CLUSTER_SIZE: u64 = 24;
+
+/// Panel of clusters around the covered sites of the test data, repeated so that
+/// fetch overhead dominates as it does on large hotspot panels
+fn hotspot_panel(sites: &[Variant]) -> Vec<Variant> {
+    let mut panel = Vec::new();
+    for _ in 0..10 {
+        for site in sites {
+            for i in 0..CLUSTER_SIZE {
+                let pos = site.pos - CLUSTER_SIZE + 2 * i;
+                panel.push(Variant::new(site.chrom.clone(), pos, site.ref_allele.clone(), site.alt_allele.clone()));
+            }
+        }
+    }
+    panel
+}
+
+/// Median wall time of scoring `panel` under `config` on one thread
+fn time_scoring(panel: &[Variant], bam: &std::path::Path, config: &LodConfig, iterations: usize) -> Duration {
+    let stop = AtomicBool::new(false);
+    let mut times: Vec<Duration> = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            let results = calculate_detectability_scores_until(panel.to_vec(), bam, config, 1, &stop)
+                .expect("scoring the benchmark panel");
+            assert_eq!(results.len(), panel.len());
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[times.len() / 2]
+}
+
+fn main() {
+    let iterations = std::env::var("VLOD_BENCH_ITERATIONS")
+        .ok()
+        .and_then(|iterations| iterations.parse().ok())
+        .filter(|&iterations| iterations > 0)
+        .unwrap_or(20);
+    let dir = tempfile::tempdir().expect("creating a scratch directory");
+    let data = write_test_data(dir.path()).expect("writing the test data");
+    let sites: Vec<Variant> = data.expected.iter().take(2).map(|(variant, _)| variant.clone()).collect();
+    let panel = hotspot_panel(&sites);
+
+    let unmerged = LodConfig { fetch_merge_distance: 0, ..LodConfig::default() };
+    let merged = LodConfig { fetch_merge_distance: DEFAULT_FETCH_MERGE_DISTANCE, ..LodConfig::default() };
+    let unmerged_time = time_scoring(&panel, &data.bam, &unmerged, iterations);
+    let merged_time = time_scoring(&panel, &data.bam, &merged, iterations);
+
+    println!("fetch_merge: {} variants, median of {} runs", panel.len(), iterations);
+    println!("  one fetch per variant:  {:>10.3?}", unmerged_time);
+    println!("  merged fetches ({} bp): {:>10.3?}", DEFAULT_FETCH_MERGE_DISTANCE, merged_time);
+    println!("  speedup: {:.2}x", unmerged_time.as_secs_f64() / merged_time.as_secs_f64());
+}
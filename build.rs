@@ -0,0 +1,7 @@
+//! Compiles the gRPC service definition when the `grpc` feature is enabled
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto/vlod.proto");
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/vlod.proto").expect("proto/vlod.proto compiles");
+}
@@ -0,0 +1,142 @@
+//! Build provenance: crate and htslib versions, default model parameters and the
+//! available models, recorded in every output so that wrappers need not shell out
+//! to `--version`
+
+use crate::{
+    bam::RetryPolicy,
+    lod::{FFPE_DEAMINATION_ERROR_RATE, FFPE_MAX_ARTIFACT_VAF, TSV_SCHEMA_VERSION},
+    LodConfig, DEFAULT_DETECTION_THRESHOLD,
+};
+use rust_htslib::htslib;
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+
+/// Parameters of `LodConfig::default()`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DefaultModelParameters {
+    pub p_tp: f64,
+    pub p_fp: f64,
+    pub p_se: f64,
+    pub ffpe_deamination_error_rate: f64,
+    pub ffpe_max_artifact_vaf: f64,
+    pub io_retries: u32,
+    pub io_retry_backoff_ms: u64,
+}
+
+/// A model vlod can apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub name: String,
+    /// `score`, `prior` or `probability`
+    pub kind: String,
+    pub description: String,
+}
+
+/// Provenance of this vlod build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct About {
+    pub vlod_version: String,
+    /// Version of the htslib vlod is linked against
+    pub htslib_version: String,
+    /// Schema version of the detectability TSV
+    pub tsv_schema: u32,
+    /// Score threshold of the run that wrote the output; the default threshold in
+    /// outputs that are not tied to one run's configuration
+    pub detection_threshold: f64,
+    pub default_config: DefaultModelParameters,
+    pub models: Vec<ModelInfo>,
+}
+
+impl About {
+    /// Single-line JSON, for embedding in text output headers
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("provenance serializes")
+    }
+}
+
+/// `#about=<json>` comment line heading text reports
+pub fn about_comment() -> String {
+    format!("#about={}", about().to_json())
+}
+
+/// `#about=<json>` comment line heading the results of a run under `config`
+pub fn run_about_comment(config: &LodConfig) -> String {
+    format!("#about={}", run_about(config).to_json())
+}
+
+/// Models available in this build
+fn model_registry() -> Vec<ModelInfo> {
+    let model = |name: &str, kind: &str, description: &str| ModelInfo {
+        name: name.to_string(),
+        kind: kind.to_string(),
+        description: description.to_string(),
+    };
+    vec![
+        model("lod", "score", "log10 likelihood ratio of a true variant at the observed VAF against sequencing error"),
+        model("ffpe-deamination", "prior", "raised error prior of low-VAF C>T/G>A changes in FFPE mode"),
+        model("platt", "probability", "logistic fit of truth against score"),
+        model("isotonic", "probability", "monotone step function fitted by pool-adjacent-violators"),
+    ]
+}
+
+/// Version of the linked htslib
+pub fn htslib_version() -> String {
+    // SAFETY: hts_version returns a pointer to a static NUL-terminated string
+    unsafe { CStr::from_ptr(htslib::hts_version()) }.to_string_lossy().into_owned()
+}
+
+/// Crate and htslib versions, default model parameters and the model registry
+pub fn about() -> About {
+    let config = LodConfig::default();
+    let RetryPolicy {
+        max_retries,
+        initial_backoff,
+    } = config.io_retry;
+    About {
+        vlod_version: env!("CARGO_PKG_VERSION").to_string(),
+        htslib_version: htslib_version(),
+        tsv_schema: TSV_SCHEMA_VERSION,
+        detection_threshold: DEFAULT_DETECTION_THRESHOLD,
+        default_config: DefaultModelParameters {
+            p_tp: config.p_tp,
+            p_fp: config.p_fp,
+            p_se: config.p_se,
+            ffpe_deamination_error_rate: FFPE_DEAMINATION_ERROR_RATE,
+            ffpe_max_artifact_vaf: FFPE_MAX_ARTIFACT_VAF,
+            io_retries: max_retries,
+            io_retry_backoff_ms: initial_backoff.as_millis() as u64,
+        },
+        models: model_registry(),
+    }
+}
+
+/// Provenance of a run under `config`, recording its score threshold
+pub fn run_about(config: &LodConfig) -> About {
+    About {
+        detection_threshold: config.score_threshold,
+        ..about()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_about() {
+        let about = about();
+        assert_eq!(about.vlod_version, env!("CARGO_PKG_VERSION"));
+        assert!(!about.htslib_version.is_empty());
+        assert_eq!(about.default_config.p_se, LodConfig::default().p_se);
+
+        // Embedded in single header lines
+        let json = about.to_json();
+        assert!(!json.contains('\n'));
+        assert_eq!(serde_json::from_str::<About>(&json).unwrap(), about);
+
+        let strict = LodConfig { score_threshold: 3.0, ..LodConfig::default() };
+        assert_eq!(about.detection_threshold, DEFAULT_DETECTION_THRESHOLD);
+        assert_eq!(run_about(&strict).detection_threshold, 3.0);
+        assert!(run_about_comment(&strict).contains("\"detection_threshold\":3.0"));
+    }
+}
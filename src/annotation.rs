@@ -0,0 +1,214 @@
+//! Coordinate-keyed joins of annotation sources onto results: an INFO field of an
+//! annotation VCF (e.g. ClinVar's `CLNSIG`) looked up by contig, position, REF and
+//! ALT, with `chr1`/`1` contig aliasing; and the stratification of results by
+//! ClinVar clinical significance
+
+use crate::{contig::canonical_contig_name, utils::open_text_input, DetectabilityResult, Variant, VlodResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::BufRead;
+use std::path::Path;
+
+/// INFO field of the ClinVar VCF holding the clinical significance
+pub const CLINVAR_SIGNIFICANCE_FIELD: &str = "CLNSIG";
+
+/// `(canonical contig, pos, ref, alt)` of an annotated allele
+type AnnotationKey = (String, u64, String, String);
+
+fn annotation_key(chrom: &str, pos: u64, ref_allele: &str, alt_allele: &str) -> AnnotationKey {
+    (canonical_contig_name(chrom), pos, ref_allele.to_ascii_uppercase(), alt_allele.to_ascii_uppercase())
+}
+
+/// Values of one INFO field of an annotation VCF for the alleles of interest
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationTable {
+    pub field: String,
+    values: HashMap<AnnotationKey, String>,
+}
+
+impl AnnotationTable {
+    /// Read `field` from the records of an annotation VCF (optionally gzipped) that
+    /// match `variants`; other records are not kept
+    pub fn read_vcf<P: AsRef<Path>>(
+        path: P,
+        field: &str,
+        variants: &[Variant],
+        max_line_length: usize,
+    ) -> VlodResult<Self> {
+        Self::from_vcf_reader(open_text_input(path, max_line_length)?, field, variants)
+    }
+
+    /// Read `field` from annotation VCF text for the alleles of `variants`. A
+    /// value with one comma-separated entry per ALT is split between the ALTs.
+    pub fn from_vcf_reader<R: BufRead>(reader: R, field: &str, variants: &[Variant]) -> VlodResult<Self> {
+        let wanted: HashSet<AnnotationKey> = variants
+            .iter()
+            .map(|variant| annotation_key(&variant.chrom, variant.pos, &variant.ref_allele, &variant.alt_allele))
+            .collect();
+        let prefix = format!("{}=", field);
+        let mut values = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') {
+                continue;
+            }
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 8 {
+                continue;
+            }
+            let Ok(pos) = columns[1].parse::<u64>() else {
+                continue;
+            };
+            let Some(value) = columns[7].split(';').find_map(|entry| entry.strip_prefix(prefix.as_str())) else {
+                continue;
+            };
+            let alts: Vec<&str> = columns[4].split(',').collect();
+            let per_allele: Vec<&str> = value.split(',').collect();
+            for (index, alt) in alts.iter().enumerate() {
+                let key = annotation_key(columns[0], pos, columns[3], alt);
+                if !wanted.contains(&key) {
+                    continue;
+                }
+                let value = if alts.len() > 1 && per_allele.len() == alts.len() { per_allele[index] } else { value };
+                values.insert(key, value.to_string());
+            }
+        }
+        Ok(AnnotationTable { field: field.to_string(), values })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Annotation of a variant, if the source has its allele
+    pub fn get(&self, variant: &Variant) -> Option<&str> {
+        self.values
+            .get(&annotation_key(&variant.chrom, variant.pos, &variant.ref_allele, &variant.alt_allele))
+            .map(String::as_str)
+    }
+}
+
+/// Whether a ClinVar clinical significance is pathogenic or likely pathogenic
+/// (`Pathogenic`, `Likely_pathogenic`, `Pathogenic/Likely_pathogenic`, ...);
+/// conflicting classifications are not
+pub fn is_pathogenic(significance: &str) -> bool {
+    significance
+        .split(['/', '|', ','])
+        .map(|term| term.trim().to_ascii_lowercase())
+        .any(|term| term == "pathogenic" || term == "likely_pathogenic")
+}
+
+/// Results and those not Detectable for one clinical significance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignificanceCounts {
+    pub results: usize,
+    pub not_detectable: usize,
+}
+
+/// Results stratified by ClinVar clinical significance, recorded in the run summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SignificanceSummary {
+    /// Counts per significance value; results without one are counted as `none`
+    pub by_significance: BTreeMap<String, SignificanceCounts>,
+    /// Pathogenic and likely pathogenic results
+    pub pathogenic: SignificanceCounts,
+    /// Percentage of the pathogenic results not Detectable (None without any)
+    pub pct_pathogenic_not_detectable: Option<f64>,
+}
+
+impl SignificanceSummary {
+    pub fn new(results: &[DetectabilityResult]) -> Self {
+        let mut summary = SignificanceSummary::default();
+        for result in results {
+            let significance = result.clinical_significance.as_deref();
+            let not_detectable = usize::from(!result.detectability_condition.is_detectable());
+            let counts = summary.by_significance.entry(significance.unwrap_or("none").to_string()).or_default();
+            counts.results += 1;
+            counts.not_detectable += not_detectable;
+            if significance.is_some_and(is_pathogenic) {
+                summary.pathogenic.results += 1;
+                summary.pathogenic.not_detectable += not_detectable;
+            }
+        }
+        summary.pct_pathogenic_not_detectable = (summary.pathogenic.results > 0).then(|| {
+            100.0 * summary.pathogenic.not_detectable as f64 / summary.pathogenic.results as f64
+        });
+        summary
+    }
+
+    /// Log the share of pathogenic variants that are not Detectable
+    pub fn log(&self) {
+        if let Some(percent) = self.pct_pathogenic_not_detectable {
+            log::info!(
+                "  Pathogenic variants not detectable: {} of {} ({:.1}%)",
+                self.pathogenic.not_detectable,
+                self.pathogenic.results,
+                percent
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DetectabilityCondition;
+
+    fn variant(chrom: &str, pos: u64, ref_allele: &str, alt: &str) -> Variant {
+        Variant::new(chrom.to_string(), pos, ref_allele.to_string(), alt.to_string())
+    }
+
+    #[test]
+    fn test_annotation_join() {
+        let vcf = "##fileformat=VCFv4.1\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   1\t100\t1\tA\tG\t.\t.\tALLELEID=1;CLNSIG=Pathogenic\n\
+                   1\t200\t2\tC\tT,A\t.\t.\tCLNSIG=Benign,Likely_pathogenic\n\
+                   1\t300\t3\tG\tC,T\t.\t.\tCLNSIG=Uncertain_significance\n\
+                   1\t400\t4\tT\tC\t.\t.\tALLELEID=4\n";
+        let variants = vec![
+            variant("chr1", 100, "A", "G"),
+            variant("chr1", 200, "C", "A"),
+            variant("chr1", 300, "G", "T"),
+            variant("chr1", 400, "T", "C"),
+            variant("chr1", 100, "A", "T"),
+        ];
+        let table = AnnotationTable::from_vcf_reader(vcf.as_bytes(), CLINVAR_SIGNIFICANCE_FIELD, &variants).unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(&variants[0]), Some("Pathogenic"));
+        assert_eq!(table.get(&variants[1]), Some("Likely_pathogenic"));
+        assert_eq!(table.get(&variants[2]), Some("Uncertain_significance"));
+        assert_eq!(table.get(&variants[3]), None);
+        assert_eq!(table.get(&variants[4]), None);
+
+        assert!(is_pathogenic("Pathogenic/Likely_pathogenic"));
+        assert!(is_pathogenic("Likely_pathogenic|drug_response"));
+        assert!(!is_pathogenic("Conflicting_classifications_of_pathogenicity"));
+        assert!(!is_pathogenic("Benign"));
+    }
+
+    #[test]
+    fn test_significance_summary() {
+        let result = |significance: Option<&str>, condition: DetectabilityCondition| {
+            let mut result = DetectabilityResult::new(variant("chr1", 100, "A", "G"), 1.0, condition, 50, 2);
+            result.clinical_significance = significance.map(str::to_string);
+            result
+        };
+        let results = vec![
+            result(Some("Pathogenic"), DetectabilityCondition::Detectable),
+            result(Some("Likely_pathogenic"), DetectabilityCondition::NonDetectable),
+            result(Some("Pathogenic"), DetectabilityCondition::NoCoverage),
+            result(Some("Benign"), DetectabilityCondition::NonDetectable),
+            result(None, DetectabilityCondition::Detectable),
+        ];
+        let summary = SignificanceSummary::new(&results);
+        assert_eq!(summary.pathogenic, SignificanceCounts { results: 3, not_detectable: 2 });
+        assert!((summary.pct_pathogenic_not_detectable.unwrap() - 200.0 / 3.0).abs() < 1e-9);
+        assert_eq!(summary.by_significance["Pathogenic"], SignificanceCounts { results: 2, not_detectable: 1 });
+        assert_eq!(summary.by_significance["none"], SignificanceCounts { results: 1, not_detectable: 0 });
+        assert_eq!(SignificanceSummary::new(&[]).pct_pathogenic_not_detectable, None);
+    }
+}
@@ -0,0 +1,288 @@
+//! Local assembly of reads around a locus for resolving complex pileup evidence
+
+use crate::Variant;
+use std::collections::{HashMap, HashSet};
+
+/// Number of bases assembled on either side of the variant
+pub const ASSEMBLY_FLANK: u64 = 75;
+
+/// K-mer size used for the assembly graph
+const KMER_SIZE: usize = 21;
+
+/// Minimum number of occurrences for a k-mer to be used in the graph
+const MIN_KMER_COUNT: u32 = 2;
+
+// Smith-Waterman scoring for haplotype-to-contig alignment
+const MATCH_SCORE: i32 = 2;
+const MISMATCH_PENALTY: i32 = -3;
+const GAP_PENALTY: i32 = -5;
+
+/// Reads and window consensus gathered around a locus
+#[derive(Debug, Clone)]
+pub struct LocusWindow {
+    /// 0-based reference position of the first consensus base
+    pub start: u64,
+    /// Majority base per reference position across aligned reads ('N' where uncovered)
+    pub consensus: Vec<u8>,
+    /// Full read sequences (including soft clips) overlapping the window
+    pub reads: Vec<Vec<u8>>,
+}
+
+/// Build the REF and ALT haplotypes by substituting each allele into the window consensus
+pub fn build_haplotypes(window: &LocusWindow, variant: &Variant) -> Option<(Vec<u8>, Vec<u8>)> {
+    let offset = variant.pos.checked_sub(1)?.checked_sub(window.start)? as usize;
+    let ref_end = offset + variant.ref_allele.len();
+    if ref_end > window.consensus.len() {
+        return None;
+    }
+
+    let left = &window.consensus[..offset];
+    let right = &window.consensus[ref_end..];
+
+    let haplotype = |allele: &str| -> Vec<u8> {
+        let mut seq = Vec::with_capacity(left.len() + allele.len() + right.len());
+        seq.extend_from_slice(left);
+        seq.extend(allele.bytes().map(|b| b.to_ascii_uppercase()));
+        seq.extend_from_slice(right);
+        seq
+    };
+
+    Some((haplotype(&variant.ref_allele), haplotype(&variant.alt_allele)))
+}
+
+/// Count solid k-mers across all reads, skipping k-mers containing ambiguous bases
+fn count_kmers(reads: &[Vec<u8>], k: usize) -> HashMap<&[u8], u32> {
+    let mut counts = HashMap::new();
+    for read in reads {
+        if read.len() < k {
+            continue;
+        }
+        for kmer in read.windows(k) {
+            if kmer.iter().all(|b| matches!(b, b'A' | b'C' | b'G' | b'T')) {
+                *counts.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Greedily assemble contigs from the de Bruijn graph of solid k-mers
+pub fn assemble_contigs(reads: &[Vec<u8>], k: usize, min_count: u32) -> Vec<Vec<u8>> {
+    let counts = count_kmers(reads, k);
+    let mut seeds: Vec<(&[u8], u32)> = counts
+        .iter()
+        .filter(|(_, &count)| count >= min_count)
+        .map(|(&kmer, &count)| (kmer, count))
+        .collect();
+    // Most abundant first, lexicographic to keep the output deterministic
+    seeds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let solid = |kmer: &[u8]| counts.get(kmer).is_some_and(|&c| c >= min_count);
+    let mut used: HashSet<Vec<u8>> = HashSet::new();
+    let mut contigs = Vec::new();
+
+    for (seed, _) in seeds {
+        if used.contains(seed) {
+            continue;
+        }
+        used.insert(seed.to_vec());
+        let mut contig = seed.to_vec();
+
+        // Extend to the right
+        loop {
+            let suffix = &contig[contig.len() - (k - 1)..];
+            let next = best_extension(suffix, &counts, &used, &solid, true);
+            match next {
+                Some((base, kmer)) => {
+                    used.insert(kmer);
+                    contig.push(base);
+                }
+                None => break,
+            }
+        }
+
+        // Extend to the left
+        loop {
+            let prefix = contig[..k - 1].to_vec();
+            let next = best_extension(&prefix, &counts, &used, &solid, false);
+            match next {
+                Some((base, kmer)) => {
+                    used.insert(kmer);
+                    contig.insert(0, base);
+                }
+                None => break,
+            }
+        }
+
+        contigs.push(contig);
+    }
+
+    contigs
+}
+
+/// Pick the most abundant unused solid k-mer extending `overlap` by one base
+fn best_extension(
+    overlap: &[u8],
+    counts: &HashMap<&[u8], u32>,
+    used: &HashSet<Vec<u8>>,
+    solid: &dyn Fn(&[u8]) -> bool,
+    rightwards: bool,
+) -> Option<(u8, Vec<u8>)> {
+    let mut best: Option<(u8, Vec<u8>, u32)> = None;
+
+    for base in [b'A', b'C', b'G', b'T'] {
+        let mut kmer = Vec::with_capacity(overlap.len() + 1);
+        if rightwards {
+            kmer.extend_from_slice(overlap);
+            kmer.push(base);
+        } else {
+            kmer.push(base);
+            kmer.extend_from_slice(overlap);
+        }
+
+        if !solid(&kmer) || used.contains(&kmer) {
+            continue;
+        }
+        let count = counts[kmer.as_slice()];
+        if best.as_ref().is_none_or(|(_, _, c)| count > *c) {
+            best = Some((base, kmer, count));
+        }
+    }
+
+    best.map(|(base, kmer, _)| (base, kmer))
+}
+
+/// Local alignment score of `query` against `target` (Smith-Waterman, linear gaps)
+pub fn local_alignment_score(query: &[u8], target: &[u8]) -> i32 {
+    let mut prev = vec![0i32; target.len() + 1];
+    let mut curr = vec![0i32; target.len() + 1];
+    let mut best = 0;
+
+    for &q in query {
+        for (j, &t) in target.iter().enumerate() {
+            let diagonal = prev[j] + if q == t { MATCH_SCORE } else { MISMATCH_PENALTY };
+            let score = diagonal
+                .max(prev[j + 1] + GAP_PENALTY)
+                .max(curr[j] + GAP_PENALTY)
+                .max(0);
+            curr[j + 1] = score;
+            best = best.max(score);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    best
+}
+
+/// Assemble the locus and count reads carrying ALT-specific sequence found in
+/// contigs that align better to the ALT haplotype than to the REF haplotype
+pub fn assembly_support(window: &LocusWindow, variant: &Variant) -> u32 {
+    let Some((ref_haplotype, alt_haplotype)) = build_haplotypes(window, variant) else {
+        return 0;
+    };
+    if alt_haplotype.len() < KMER_SIZE {
+        return 0;
+    }
+
+    let contigs = assemble_contigs(&window.reads, KMER_SIZE, MIN_KMER_COUNT);
+    let alt_contigs: Vec<&Vec<u8>> = contigs
+        .iter()
+        .filter(|contig| {
+            local_alignment_score(contig, &alt_haplotype) > local_alignment_score(contig, &ref_haplotype)
+        })
+        .collect();
+    if alt_contigs.is_empty() {
+        return 0;
+    }
+
+    // ALT-specific k-mers that were actually assembled into an ALT-supporting contig
+    let ref_kmers: HashSet<&[u8]> = ref_haplotype.windows(KMER_SIZE).collect();
+    let contig_kmers: HashSet<&[u8]> = alt_contigs
+        .iter()
+        .flat_map(|contig| contig.windows(KMER_SIZE))
+        .collect();
+    let signature: HashSet<&[u8]> = alt_haplotype
+        .windows(KMER_SIZE)
+        .filter(|kmer| !ref_kmers.contains(kmer) && contig_kmers.contains(kmer))
+        .collect();
+
+    window
+        .reads
+        .iter()
+        .filter(|read| read.len() >= KMER_SIZE && read.windows(KMER_SIZE).any(|kmer| signature.contains(kmer)))
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: &[u8] = b"GATTACAGGCTTACCGATGCAAGTCCTAGGA";
+    const RIGHT: &[u8] = b"TTGCAGCATCGGATCCAAGTGACTGCATGCA";
+
+    fn window_with_reads(alt_reads: usize, ref_reads: usize) -> LocusWindow {
+        let mut consensus = LEFT.to_vec();
+        consensus.push(b'C');
+        consensus.extend_from_slice(RIGHT);
+
+        let mut alt_read = LEFT.to_vec();
+        alt_read.extend_from_slice(b"CTTT");
+        alt_read.extend_from_slice(RIGHT);
+
+        let mut reads = vec![alt_read; alt_reads];
+        reads.extend(std::iter::repeat_n(consensus.clone(), ref_reads));
+
+        LocusWindow {
+            start: 1000,
+            consensus,
+            reads,
+        }
+    }
+
+    fn insertion() -> Variant {
+        Variant::new("chr1".to_string(), 1000 + LEFT.len() as u64 + 1, "C".to_string(), "CTTT".to_string())
+    }
+
+    #[test]
+    fn test_build_haplotypes() {
+        let window = window_with_reads(0, 1);
+        let (ref_hap, alt_hap) = build_haplotypes(&window, &insertion()).unwrap();
+
+        assert_eq!(ref_hap, window.consensus);
+        assert_eq!(alt_hap.len(), ref_hap.len() + 3);
+        assert_eq!(&alt_hap[LEFT.len()..LEFT.len() + 4], b"CTTT");
+
+        let outside = Variant::new("chr1".to_string(), 10, "C".to_string(), "T".to_string());
+        assert!(build_haplotypes(&window, &outside).is_none());
+    }
+
+    #[test]
+    fn test_assemble_contigs_reconstructs_read() {
+        let window = window_with_reads(0, 3);
+        let contigs = assemble_contigs(&window.reads, KMER_SIZE, MIN_KMER_COUNT);
+
+        assert_eq!(contigs.len(), 1);
+        assert_eq!(contigs[0], window.consensus);
+    }
+
+    #[test]
+    fn test_local_alignment_score() {
+        assert_eq!(local_alignment_score(b"ACGT", b"ACGT"), 8);
+        assert_eq!(local_alignment_score(b"ACGT", b"TTACGTTT"), 8);
+        assert!(local_alignment_score(b"ACGTACGT", b"ACGAACGT") < 16);
+        assert_eq!(local_alignment_score(b"", b"ACGT"), 0);
+    }
+
+    #[test]
+    fn test_assembly_support() {
+        let window = window_with_reads(4, 6);
+        assert_eq!(assembly_support(&window, &insertion()), 4);
+
+        let window = window_with_reads(0, 6);
+        assert_eq!(assembly_support(&window, &insertion()), 0);
+
+        // A single ALT read is not enough to form solid k-mers
+        let window = window_with_reads(1, 6);
+        assert_eq!(assembly_support(&window, &insertion()), 0);
+    }
+}
@@ -0,0 +1,197 @@
+//! Append-only audit log (JSON lines) for clinical traceability: each run of the
+//! command line, watch mode and the servers is recorded under a request id with
+//! who asked for it, when, the samples and configuration hash involved, and the
+//! SHA-256 of every output it wrote
+
+use crate::{integrity::sha256_file, utils::ensure_parent_dirs, VlodError, VlodResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Requests numbered so far by this process
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A new request id, unique across processes: the start time in nanoseconds, the
+/// process id and a per-process counter, in hex
+pub fn new_request_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+    let counter = REQUEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{:x}-{:x}-{:x}", nanos, std::process::id(), counter)
+}
+
+/// The operating system user running vlod
+pub fn current_user() -> String {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|user| !user.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Point in a request's life an entry records. A request with a `started` entry
+/// and no later one did not complete (e.g. the process was killed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Started,
+    Finished,
+    /// Stopped by a signal or a client going away; outputs may be partial
+    Interrupted,
+    Failed,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub request_id: String,
+    pub event: AuditEvent,
+    /// Mode that served the request: `run`, `watch`, `serve` or `grpc`
+    pub mode: String,
+    /// What was asked for (`analyze`, `merge`, `query`)
+    pub action: String,
+    /// Who asked: the user running vlod, or a server's client
+    pub actor: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<String>,
+    /// Hash of the model settings deciding DET/DETS (see `incremental::config_hash`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    /// SHA-256 of each output, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+    /// The query answered, or why the request failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditEntry {
+    /// A request by the current user, to be filled in and recorded
+    pub fn new(mode: &str, action: &str, request_id: &str) -> Self {
+        AuditEntry {
+            time: 0,
+            request_id: request_id.to_string(),
+            event: AuditEvent::Started,
+            mode: mode.to_string(),
+            action: action.to_string(),
+            actor: current_user(),
+            samples: Vec::new(),
+            config_hash: None,
+            inputs: Vec::new(),
+            outputs: BTreeMap::new(),
+            detail: None,
+        }
+    }
+
+    /// Record the SHA-256 of outputs; those not written (e.g. skipped by an
+    /// interrupted run) are left out
+    pub fn add_outputs<P: AsRef<Path>>(&mut self, paths: impl IntoIterator<Item = P>) -> VlodResult<()> {
+        for path in paths {
+            let path = path.as_ref();
+            if path.is_file() {
+                self.outputs.insert(path.to_string_lossy().to_string(), sha256_file(path)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Audit log opened for appending; entries are written whole and synced to disk,
+/// so that concurrent requests and processes do not interleave them
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        ensure_parent_dirs(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(path.as_ref()).map_err(|e| {
+            VlodError::Io(std::io::Error::new(
+                e.kind(),
+                format!("cannot open audit log {}: {}", path.as_ref().display(), e),
+            ))
+        })?;
+        Ok(AuditLog { file: Mutex::new(file) })
+    }
+
+    /// Append an entry as `event`, stamped with the current time
+    pub fn record(&self, entry: &AuditEntry, event: AuditEvent) -> VlodResult<()> {
+        let entry = AuditEntry {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+            event,
+            ..entry.clone()
+        };
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| VlodError::InvalidConfig(format!("cannot write audit entry: {}", e)))?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Read the entries of an audit log
+pub fn read_audit_log<P: AsRef<Path>>(path: P) -> VlodResult<Vec<AuditEntry>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    let reader = BufReader::new(File::open(path.as_ref())?);
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| VlodError::Parse {
+            file: source.clone(),
+            line: index as u64 + 1,
+            column: None,
+            message: format!("invalid audit entry: {}", e),
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("audit.jsonl");
+        let output = dir.path().join("results.tsv");
+        std::fs::write(&output, "Chrom\tPos\n").unwrap();
+
+        let request_id = new_request_id();
+        assert_ne!(request_id, new_request_id());
+        let mut entry = AuditEntry::new("run", "analyze", &request_id);
+        entry.samples = vec!["S1".to_string()];
+        entry.config_hash = Some("abc".to_string());
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record(&entry, AuditEvent::Started).unwrap();
+        entry.add_outputs([&output, &dir.path().join("skipped.tsv")]).unwrap();
+        log.record(&entry, AuditEvent::Finished).unwrap();
+        // Reopening appends
+        AuditLog::open(&path).unwrap().record(&entry, AuditEvent::Finished).unwrap();
+
+        let entries = read_audit_log(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].event, AuditEvent::Started);
+        assert!(entries[0].outputs.is_empty());
+        assert_eq!(entries[1].request_id, request_id);
+        assert_eq!(entries[1].samples, ["S1"]);
+        assert_eq!(entries[1].outputs.len(), 1);
+        assert_eq!(entries[1].outputs[&output.to_string_lossy().to_string()], sha256_file(&output).unwrap());
+        assert!(entries[1].time > 0);
+    }
+}
@@ -1,9 +1,376 @@
 //! BAM file processing and pileup analysis
 
-use crate::{LodConfig, Variant, VlodError, VlodResult};
-use rust_htslib::bam::{pileup::Alignment, IndexedReader, Read};
-use std::collections::HashMap;
-use std::path::Path;
+#[cfg(feature = "assembly")]
+use crate::assembly::{assembly_support, LocusWindow, ASSEMBLY_FLANK};
+use crate::{
+    contig::{is_non_primary_contig, ContigPolicy},
+    lod::{calculate_variant_lod_score, detectability_result},
+    observer::Observer,
+    noise::{base_index, BaseCounts, NoiseProfile},
+    read_filter::passes_all,
+    reference::ReferenceFasta,
+    regions::{AmpliconSet, BedRegion},
+    titration::downsample_draw,
+    utils::{append_extension, has_extension},
+    DetectabilityResult, LodConfig, OrientationCounts, PairOrientation, StrandCounts, Variant, VlodError, VlodResult,
+};
+use rust_htslib::bam::{
+    pileup::{Alignment, Indel},
+    record::Cigar,
+    IndexedReader, Read, Reader, Record,
+};
+use rust_htslib::htslib;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Maximum fraction of mismatching bases tolerated when matching a read tail
+/// against the expected insertion sequence
+const INSERTION_TAIL_MAX_MISMATCH_RATE: f64 = 0.1;
+
+/// Fewest inserted bases a read tail must carry to support an insertion (fewer for
+/// shorter insertions, which must then be carried whole)
+const MIN_INSERTION_TAIL_OVERLAP: usize = 3;
+
+/// Reference bases after an insertion against which soft-clipped bases past the
+/// inserted sequence are compared
+const INSERTION_CLIP_REFERENCE_BASES: u64 = 150;
+
+/// Quality byte htslib stores for every base of a read whose QUAL is `*`
+const MISSING_BASE_QUALITY: u8 = 0xff;
+
+/// Quality given to the bases of reads without qualities unless configured
+pub const DEFAULT_MISSING_QUALITY: u8 = 30;
+
+/// Variants at most this many bases apart are read with one fetch unless configured
+pub const DEFAULT_FETCH_MERGE_DISTANCE: u64 = 200;
+
+/// Reads a pileup column holds unless configured; deeper columns are truncated
+pub const DEFAULT_MAX_PILEUP_DEPTH: u32 = 1_000_000;
+
+/// Retries of BAM fetch/pileup operations that fail with an IO or htslib error,
+/// as happens transiently on NFS or object storage. The reader is reopened before
+/// each retry, waiting `initial_backoff`, then twice as long on each further retry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the given retry (0 for the first)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX))
+    }
+
+    /// Whether an error may be transient; malformed variants and unknown contigs are not
+    pub fn is_retryable(error: &VlodError) -> bool {
+        matches!(error.root(), VlodError::Htslib(_) | VlodError::Io(_))
+    }
+}
+
+/// Caps the number of BAM readers open at once across worker threads. Workers
+/// block in `acquire` until a reader slot is free.
+#[derive(Debug)]
+pub struct ReaderLimit {
+    max_open: usize,
+    /// Readers currently open and the most open at any one time
+    state: Mutex<(usize, usize)>,
+    released: Condvar,
+}
+
+/// A reader slot, released when dropped
+pub struct ReaderPermit<'a> {
+    limit: &'a ReaderLimit,
+}
+
+impl ReaderLimit {
+    /// A limit of `max_open` readers (None for no limit)
+    pub fn new(max_open: Option<usize>) -> Self {
+        Self {
+            max_open: max_open.unwrap_or(usize::MAX).max(1),
+            state: Mutex::new((0, 0)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait for a free reader slot
+    pub fn acquire(&self) -> ReaderPermit<'_> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.0 >= self.max_open {
+            state = self.released.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.0 += 1;
+        state.1 = state.1.max(state.0);
+        ReaderPermit { limit: self }
+    }
+
+    /// Most readers open at any one time so far
+    pub fn peak(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).1
+    }
+}
+
+impl Drop for ReaderPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limit.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0 -= 1;
+        self.limit.released.notify_one();
+    }
+}
+
+/// Allele supported by a single read at the variant site
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadAllele {
+    Ref,
+    Alt(String),
+    /// ALT support recovered from a soft-clipped read tail
+    SoftClippedAlt(String),
+    /// Called bases matching neither REF nor any ALT (a third allele)
+    Other,
+    /// The read carries a deletion over an SNV or MNV site
+    Deleted,
+}
+
+/// Per-read attributes recorded alongside the allele a read supports
+#[derive(Debug, Clone, Default)]
+pub struct ReadContext<'a> {
+    /// Amplicon the read was attributed to, when amplicons are configured
+    pub amplicon: Option<&'a str>,
+    /// Read-pair orientation, for paired reads
+    pub orientation: Option<PairOrientation>,
+    /// The read maps to the reverse strand
+    pub reverse: bool,
+}
+
+/// REF/ALT counts for reads attributed to a single amplicon
+#[derive(Debug, Clone, Default)]
+pub struct AmpliconCounts {
+    pub ref_count: u32,
+    pub alt_counts: HashMap<String, u32>,
+}
+
+/// Denominator of the VAF scored by the LOD model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VafDefinition {
+    /// ALT reads over all reads assigned to an allele, including other ALTs
+    #[default]
+    Total,
+    /// ALT reads over REF plus ALT reads, ignoring reads of other ALT alleles
+    RefAlt,
+}
+
+impl VafDefinition {
+    /// VAF of `alt_reads` among `ref_reads` REF and `total_reads` allele-assigned reads
+    pub fn vaf(self, alt_reads: u32, ref_reads: u32, total_reads: u32) -> f64 {
+        let depth = match self {
+            VafDefinition::Total => total_reads,
+            VafDefinition::RefAlt => ref_reads + alt_reads,
+        };
+        if depth == 0 {
+            0.0
+        } else {
+            alt_reads as f64 / depth as f64
+        }
+    }
+}
+
+impl FromStr for VafDefinition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "total" => Ok(VafDefinition::Total),
+            "ref-alt" => Ok(VafDefinition::RefAlt),
+            _ => Err(format!("unknown VAF definition '{}' (expected total or ref-alt)", s)),
+        }
+    }
+}
+
+impl fmt::Display for VafDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VafDefinition::Total => "total",
+            VafDefinition::RefAlt => "ref-alt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether reads deleted at an SNV or MNV site count toward its informative depth
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletedReadPolicy {
+    /// Deleted reads are counted in `deleted_at_site` only
+    #[default]
+    Exclude,
+    /// Deleted reads also count toward the depth, as reads not supporting the ALT
+    Count,
+}
+
+impl FromStr for DeletedReadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "exclude" => Ok(DeletedReadPolicy::Exclude),
+            "count" => Ok(DeletedReadPolicy::Count),
+            _ => Err(format!("unknown deleted read policy '{}' (expected exclude or count)", s)),
+        }
+    }
+}
+
+impl fmt::Display for DeletedReadPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeletedReadPolicy::Exclude => "exclude",
+            DeletedReadPolicy::Count => "count",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Evidence from fragments shorter than their reads, whose mates overlap completely
+/// and read through into the adapter (typical of degraded cfDNA)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShortFragmentPolicy {
+    /// Count both mates
+    #[default]
+    Keep,
+    /// Count the fragment once, from its first mate
+    Collapse,
+    /// Count neither mate
+    Exclude,
+}
+
+impl FromStr for ShortFragmentPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(ShortFragmentPolicy::Keep),
+            "collapse" => Ok(ShortFragmentPolicy::Collapse),
+            "exclude" => Ok(ShortFragmentPolicy::Exclude),
+            _ => Err(format!(
+                "unknown short fragment policy '{}' (expected keep, collapse or exclude)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ShortFragmentPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShortFragmentPolicy::Keep => "keep",
+            ShortFragmentPolicy::Collapse => "collapse",
+            ShortFragmentPolicy::Exclude => "exclude",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Handling of reads whose QUAL is `*`, which some aligners emit for secondary
+/// or converted records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingQualityPolicy {
+    /// Count the read, giving each of its bases this quality
+    Default(u8),
+    /// Leave the read out of the pileup
+    Exclude,
+}
+
+impl Default for MissingQualityPolicy {
+    fn default() -> Self {
+        MissingQualityPolicy::Default(DEFAULT_MISSING_QUALITY)
+    }
+}
+
+impl FromStr for MissingQualityPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        if lower == "exclude" {
+            return Ok(MissingQualityPolicy::Exclude);
+        }
+        match lower.strip_prefix("default:").map(str::parse::<u8>) {
+            Some(Ok(quality)) if quality < MISSING_BASE_QUALITY => Ok(MissingQualityPolicy::Default(quality)),
+            _ => Err(format!(
+                "unknown missing quality policy '{}' (expected default:QUALITY or exclude)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MissingQualityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingQualityPolicy::Default(quality) => write!(f, "default:{}", quality),
+            MissingQualityPolicy::Exclude => write!(f, "exclude"),
+        }
+    }
+}
+
+/// What to do at a site whose pileup column reached the maximum depth, where the
+/// pileup drops reads and the counts would be partial
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DepthCapPolicy {
+    /// Count the site again by iterating over its reads directly
+    #[default]
+    Iterate,
+    /// Keep the partial counts and flag the result `depth_capped`
+    Flag,
+}
+
+impl FromStr for DepthCapPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "iterate" => Ok(DepthCapPolicy::Iterate),
+            "flag" => Ok(DepthCapPolicy::Flag),
+            _ => Err(format!("unknown depth cap policy '{}' (expected iterate or flag)", s)),
+        }
+    }
+}
+
+impl fmt::Display for DepthCapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepthCapPolicy::Iterate => write!(f, "iterate"),
+            DepthCapPolicy::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// Whether a read has no base qualities (QUAL `*`)
+pub fn has_missing_quality(record: &Record) -> bool {
+    record.qual().first() == Some(&MISSING_BASE_QUALITY)
+}
+
+/// Quality of the read base at `qpos`, or `missing` when the read has no qualities
+pub fn base_quality(record: &Record, qpos: usize, missing: u8) -> u8 {
+    match record.qual().get(qpos) {
+        Some(&quality) if quality != MISSING_BASE_QUALITY => quality,
+        _ => missing,
+    }
+}
 
 /// Represents allele counts at a specific position
 #[derive(Debug, Clone)]
@@ -11,6 +378,39 @@ pub struct AlleleCounts {
     pub ref_count: u32,
     pub alt_counts: HashMap<String, u32>,
     pub total_count: u32,
+    /// Reads whose bases match neither REF nor any ALT (not part of `total_count`)
+    pub other_count: u32,
+    /// Reads carrying a deletion over an SNV or MNV site; part of `total_count` only
+    /// under `DeletedReadPolicy::Count`
+    pub deleted_at_site: u32,
+    /// ALT reads recovered from soft-clipped read tails (subset of `alt_counts`)
+    pub alt_softclip_support: HashMap<String, u32>,
+    /// Reads overlapping the site in the pileup, including those matching neither allele
+    pub site_depth: u32,
+    /// Reads of `site_depth` from fragments shorter than the read (see `is_short_fragment`)
+    pub short_fragment_reads: u32,
+    /// ALT reads supported by local assembly, for loci that were assembled
+    pub assembly_support: HashMap<String, u32>,
+    /// Counts split by amplicon of origin, keyed by amplicon name
+    pub amplicon_counts: BTreeMap<String, AmpliconCounts>,
+    /// Counts over downsampled reads, one per configured titration fraction
+    pub titration: Vec<AlleleCounts>,
+    /// ALT reads by read-pair orientation
+    pub alt_orientation: HashMap<String, OrientationCounts>,
+    /// REF reads on the forward and reverse strand
+    pub ref_strand: [u32; 2],
+    /// ALT reads on the forward and reverse strand
+    pub alt_strand: HashMap<String, [u32; 2]>,
+    /// Why the site was not read (e.g. its contig is not in the BAM)
+    pub not_assessable: Option<String>,
+    /// The pileup column reached the maximum depth and the counts cover only part
+    /// of the reads (`DepthCapPolicy::Flag`)
+    pub depth_capped: bool,
+    /// Reads without base qualities (QUAL `*`) met at the site
+    pub missing_quality_reads: u32,
+    /// BAM operations retried after a transient error while reading the site's
+    /// fetch group (carried by the group's first variant)
+    pub io_retries: u32,
 }
 
 impl AlleleCounts {
@@ -19,6 +419,29 @@ impl AlleleCounts {
             ref_count: 0,
             alt_counts: HashMap::new(),
             total_count: 0,
+            other_count: 0,
+            deleted_at_site: 0,
+            alt_softclip_support: HashMap::new(),
+            site_depth: 0,
+            short_fragment_reads: 0,
+            assembly_support: HashMap::new(),
+            amplicon_counts: BTreeMap::new(),
+            titration: Vec::new(),
+            alt_orientation: HashMap::new(),
+            ref_strand: [0; 2],
+            alt_strand: HashMap::new(),
+            not_assessable: None,
+            depth_capped: false,
+            missing_quality_reads: 0,
+            io_retries: 0,
+        }
+    }
+
+    /// Empty counts of a site that was not read
+    pub fn not_assessable(reason: String) -> Self {
+        Self {
+            not_assessable: Some(reason),
+            ..Self::new()
         }
     }
 
@@ -32,226 +455,1389 @@ impl AlleleCounts {
         self.total_count += 1;
     }
 
-    pub fn get_alt_count(&self, allele: &str) -> u32 {
-        self.alt_counts.get(allele).copied().unwrap_or(0)
+    /// Count an ALT read whose support was recovered from a soft-clipped tail
+    pub fn add_alt_softclip(&mut self, allele: String) {
+        *self.alt_softclip_support.entry(allele.clone()).or_insert(0) += 1;
+        self.add_alt(allele);
+    }
+
+    /// Record the allele supported by one read, along with its per-read attributes
+    pub fn add_read(&mut self, allele: ReadAllele, context: &ReadContext) {
+        match &allele {
+            ReadAllele::Ref => self.add_ref(),
+            ReadAllele::Alt(alt) => self.add_alt(alt.clone()),
+            ReadAllele::SoftClippedAlt(alt) => self.add_alt_softclip(alt.clone()),
+            ReadAllele::Other => self.other_count += 1,
+            ReadAllele::Deleted => self.deleted_at_site += 1,
+        }
+
+        if let (ReadAllele::Alt(alt) | ReadAllele::SoftClippedAlt(alt), Some(orientation)) =
+            (&allele, context.orientation)
+        {
+            self.alt_orientation.entry(alt.clone()).or_default().add(orientation);
+        }
+
+        let strand = usize::from(context.reverse);
+        match &allele {
+            ReadAllele::Ref => self.ref_strand[strand] += 1,
+            ReadAllele::Alt(alt) | ReadAllele::SoftClippedAlt(alt) => {
+                self.alt_strand.entry(alt.clone()).or_default()[strand] += 1;
+            }
+            ReadAllele::Other | ReadAllele::Deleted => {}
+        }
+
+        if let Some(amplicon) = context.amplicon {
+            let counts = self.amplicon_counts.entry(amplicon.to_string()).or_default();
+            match allele {
+                ReadAllele::Ref => counts.ref_count += 1,
+                ReadAllele::Alt(alt) | ReadAllele::SoftClippedAlt(alt) => {
+                    *counts.alt_counts.entry(alt).or_insert(0) += 1;
+                }
+                ReadAllele::Other | ReadAllele::Deleted => {}
+            }
+        }
+    }
+
+    pub fn get_alt_count(&self, allele: &str) -> u32 {
+        self.alt_counts.get(allele).copied().unwrap_or(0)
+    }
+
+    pub fn get_alt_orientation(&self, allele: &str) -> OrientationCounts {
+        self.alt_orientation.get(allele).copied().unwrap_or_default()
+    }
+
+    /// REF and ALT reads of an allele by mapped strand
+    pub fn get_strand_counts(&self, allele: &str) -> StrandCounts {
+        let [alt_forward, alt_reverse] = self.alt_strand.get(allele).copied().unwrap_or_default();
+        StrandCounts {
+            ref_forward: self.ref_strand[0],
+            ref_reverse: self.ref_strand[1],
+            alt_forward,
+            alt_reverse,
+        }
+    }
+
+    pub fn get_alt_softclip_support(&self, allele: &str) -> u32 {
+        self.alt_softclip_support.get(allele).copied().unwrap_or(0)
+    }
+
+    pub fn get_vaf(&self, allele: &str) -> f64 {
+        self.vaf(allele, VafDefinition::Total)
+    }
+
+    /// VAF of an ALT allele under a VAF definition
+    pub fn vaf(&self, allele: &str, definition: VafDefinition) -> f64 {
+        definition.vaf(self.get_alt_count(allele), self.ref_count, self.total_count)
+    }
+
+    /// Count the reads deleted at the site toward the depth, here and in the
+    /// titration subsamples
+    pub fn count_deleted_reads(&mut self) {
+        self.total_count += self.deleted_at_site;
+        for subsample in &mut self.titration {
+            subsample.count_deleted_reads();
+        }
+    }
+
+    /// Fraction of the reads overlapping the site that come from short fragments
+    pub fn short_fragment_fraction(&self) -> f64 {
+        if self.site_depth == 0 {
+            0.0
+        } else {
+            self.short_fragment_reads as f64 / self.site_depth as f64
+        }
+    }
+
+    /// Fraction of reads with called bases at the site that support a third allele
+    pub fn other_fraction(&self) -> f64 {
+        other_allele_fraction(self.other_count, self.total_count)
+    }
+
+    /// Whether some reads at the site could not be cleanly assigned to REF or ALT
+    pub fn has_conflicting_evidence(&self) -> bool {
+        self.site_depth > self.total_count || !self.alt_softclip_support.is_empty()
+    }
+
+    /// VAF used for scoring: assembly-based when the locus was assembled, pileup-based
+    /// under `definition` otherwise
+    pub fn get_scoring_vaf(&self, allele: &str, definition: VafDefinition) -> f64 {
+        match self.assembly_support.get(allele) {
+            Some(&support) => {
+                let depth = self.site_depth.max(support);
+                if depth == 0 {
+                    0.0
+                } else {
+                    support as f64 / depth as f64
+                }
+            }
+            None => self.vaf(allele, definition),
+        }
+    }
+}
+
+/// How an insertion running off the end of a read was recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadEndInsertion {
+    /// Insertion truncated by the end of the read (trailing insertion op only)
+    Truncated,
+    /// Inserted bases (partly) hidden in a trailing soft clip
+    SoftClipped,
+}
+
+/// Original strand of a bisulfite-converted read, which determines the conversion it can carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BisulfiteStrand {
+    /// Original top strand: unmethylated C reads as T
+    Top,
+    /// Original bottom strand: unmethylated C reads as A on the reference strand (G>A)
+    Bottom,
+}
+
+impl BisulfiteStrand {
+    /// Infer the original strand for directional libraries from the read orientation
+    fn of_record(record: &Record) -> Self {
+        let forward = !record.is_reverse();
+        let top = if record.is_paired() && record.is_last_in_template() {
+            !forward
+        } else {
+            forward
+        };
+
+        if top {
+            BisulfiteStrand::Top
+        } else {
+            BisulfiteStrand::Bottom
+        }
+    }
+
+    /// Whether an observed read base is compatible with an expected reference-strand base
+    fn base_matches(self, observed: u8, expected: u8) -> bool {
+        observed == expected
+            || match self {
+                BisulfiteStrand::Top => expected == b'C' && observed == b'T',
+                BisulfiteStrand::Bottom => expected == b'G' && observed == b'A',
+            }
+    }
+
+    fn allele_matches(self, observed: &[u8], allele: &str) -> bool {
+        observed.len() == allele.len()
+            && observed
+                .iter()
+                .zip(allele.bytes())
+                .all(|(&o, e)| self.base_matches(o, e))
+    }
+}
+
+/// BAM analyzer for processing variants
+pub struct BamAnalyzer {
+    bam_reader: IndexedReader,
+    bam_path: PathBuf,
+    index_path: PathBuf,
+    config: LodConfig,
+    /// Operations retried by this analyzer after a transient error
+    retries: u64,
+    /// Regions fetched for variant pileups by this analyzer
+    fetches: u64,
+    /// `config.reference`, opened on the first insertion analysed
+    reference: Option<ReferenceFasta>,
+}
+
+/// Reference sequences (name, length) in BAM header order
+pub fn bam_contigs<P: AsRef<Path>>(bam_path: P) -> VlodResult<Vec<(String, u64)>> {
+    let reader = Reader::from_path(bam_path.as_ref())?;
+    let header = reader.header();
+    Ok(header
+        .target_names()
+        .iter()
+        .enumerate()
+        .map(|(tid, name)| {
+            (
+                String::from_utf8_lossy(name).to_string(),
+                header.target_len(tid as u32).unwrap_or(0),
+            )
+        })
+        .collect())
+}
+
+/// Reference sequence names in BAM header order
+pub fn bam_contig_order<P: AsRef<Path>>(bam_path: P) -> VlodResult<Vec<String>> {
+    Ok(bam_contigs(bam_path)?.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Where the index of a BAM may be: `<bam>.bai`, or `<name>.bai` replacing a
+/// `.bam` extension, then the same names of a `.csi` (the only index that can hold
+/// positions past 2^29 on long contigs). The index name is appended rather than
+/// substituted so that names with dots (`sample.v2.bam`) and without a `.bam`
+/// extension resolve too.
+pub fn bam_index_candidates(bam_path: &Path) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    for extension in ["bai", "csi"] {
+        candidates.push(append_extension(bam_path, extension));
+        if has_extension(bam_path, "bam") {
+            candidates.push(bam_path.with_extension(extension));
+        }
+    }
+    candidates
+}
+
+impl BamAnalyzer {
+    pub fn new<P: AsRef<Path>>(bam_path: P) -> VlodResult<Self> {
+        let bam_path = bam_path.as_ref();
+        
+        // Check for a BAI or CSI index file next to the BAM file
+        let candidates = bam_index_candidates(bam_path);
+        let index_path = match candidates.iter().find(|index_path| index_path.exists()) {
+            Some(index_path) => index_path.clone(),
+            None => {
+                let expected: Vec<String> = candidates.iter().map(|path| path.display().to_string()).collect();
+                return Err(VlodError::FileNotFound(format!(
+                    "BAM index file not found. Expected {}",
+                    expected.join(" or ")
+                )));
+            }
+        };
+        
+        let bam_reader = IndexedReader::from_path_and_index(bam_path, &index_path)
+            .map_err(|e| VlodError::from(e).context(format!("opening {:?} with index {:?}", bam_path, index_path)))?;
+        Ok(BamAnalyzer {
+            bam_reader,
+            bam_path: bam_path.to_path_buf(),
+            index_path,
+            config: LodConfig::default(),
+            retries: 0,
+            fetches: 0,
+            reference: None,
+        })
+    }
+
+    /// Operations retried by this analyzer after a transient error
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+
+    /// Regions fetched for variant pileups by this analyzer
+    pub fn fetches(&self) -> u64 {
+        self.fetches
+    }
+
+    /// Run a fetch/pileup operation, reopening the reader and retrying with
+    /// exponential backoff while it fails with a retryable error
+    fn with_retry<T>(&mut self, mut operation: impl FnMut(&mut Self) -> VlodResult<T>) -> VlodResult<T> {
+        let policy = self.config.io_retry;
+        let mut retry = 0;
+        loop {
+            match operation(self) {
+                Err(error) if retry < policy.max_retries && RetryPolicy::is_retryable(&error) => {
+                    let backoff = policy.backoff(retry);
+                    log::warn!(
+                        "Transient error reading {:?} ({}); retry {} of {} in {:?}",
+                        self.bam_path,
+                        error,
+                        retry + 1,
+                        policy.max_retries,
+                        backoff
+                    );
+                    std::thread::sleep(backoff);
+                    retry += 1;
+                    self.retries += 1;
+                    // A failed read may leave the handle mid-block; start afresh
+                    if let Ok(reader) = IndexedReader::from_path_and_index(&self.bam_path, &self.index_path) {
+                        self.bam_reader = reader;
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Use the read-level options (amplicons, bisulfite mode, ...) of a configuration
+    pub fn with_config(mut self, config: &LodConfig) -> Self {
+        self.config = config.clone();
+        self
+    }
+
+    /// Length of a contig in the BAM header (None when the BAM has no such contig)
+    fn contig_length(&self, chrom: &str) -> Option<u64> {
+        let header = self.bam_reader.header();
+        header.tid(chrom.as_bytes()).and_then(|tid| header.target_len(tid))
+    }
+
+    /// Locus at which a variant is read under the configured contig policy, or why
+    /// it cannot be read
+    pub fn resolve_locus(&self, variant: &Variant) -> Result<Variant, String> {
+        let locus = match &self.config.alt_contig_map {
+            Some(map) if self.config.contig_policy == ContigPolicy::Map && map.contains(&variant.chrom) => map
+                .map_variant(variant)
+                .map_err(|reason| format!("alt contig {}", reason))?,
+            _ => variant.clone(),
+        };
+        let skip = self.config.contig_policy == ContigPolicy::Skip;
+        match self.contig_length(&locus.chrom) {
+            None => Err("contig not in BAM".to_string()),
+            Some(0) if skip => Err("zero-length contig".to_string()),
+            Some(_) if skip && is_non_primary_contig(&locus.chrom) => Err("unplaced contig".to_string()),
+            Some(_) => Ok(locus),
+        }
+    }
+
+    /// Reference bases following the REF allele of an insertion site, for the
+    /// soft-clipped bases of reads past the inserted sequence; None without a
+    /// reference, at other sites or on contigs the reference lacks
+    fn downstream_reference(&mut self, variant: &Variant) -> VlodResult<Option<Vec<u8>>> {
+        let is_insertion = variant
+            .alt_allele
+            .split(',')
+            .any(|alt| alt.len() > variant.ref_allele.len() && alt.starts_with(&variant.ref_allele));
+        let Some(path) = self.config.reference.as_ref().filter(|_| is_insertion) else {
+            return Ok(None);
+        };
+        if self.reference.is_none() {
+            self.reference = Some(ReferenceFasta::open(path, false)?);
+        }
+        let Some(reference) = self.reference.as_ref().filter(|reference| reference.has_contig(&variant.chrom)) else {
+            return Ok(None);
+        };
+        let start = variant.pos + variant.ref_allele.len() as u64;
+        let bases = reference.fetch(&variant.chrom, start, start + INSERTION_CLIP_REFERENCE_BASES - 1)?;
+        Ok(Some(bases.into_bytes()))
+    }
+
+    fn tid(&self, chrom: &str) -> VlodResult<u32> {
+        self.bam_reader.header().tid(chrom.as_bytes())
+            .ok_or_else(|| VlodError::InvalidVariant(format!("Unknown chromosome: {}", chrom)))
+    }
+
+    /// Analyze a single variant and return allele counts
+    pub fn analyze_variant(&mut self, variant: &Variant) -> VlodResult<AlleleCounts> {
+        let mut counts = self.analyze_variants(std::slice::from_ref(variant))?;
+        Ok(counts.pop().unwrap_or_else(AlleleCounts::new))
+    }
+
+    /// Analyze variants on one contig with a single fetch spanning all of them and
+    /// one pileup traversal, returning their allele counts in order. Dense hotspots
+    /// then cost one index lookup instead of one per variant.
+    pub fn analyze_variants(&mut self, variants: &[Variant]) -> VlodResult<Vec<AlleleCounts>> {
+        self.with_retry(|analyzer| analyzer.analyze_variants_once(variants))
+    }
+
+    fn analyze_variants_once(&mut self, variants: &[Variant]) -> VlodResult<Vec<AlleleCounts>> {
+        let Some(first) = variants.first() else {
+            return Ok(Vec::new());
+        };
+        let tid = self.tid(&first.chrom)?;
+
+        // Fetch one region covering every variant, padded for indels
+        let (start, end) = variants
+            .iter()
+            .map(fetch_window)
+            .fold((u64::MAX, 0), |(start, end), window| (start.min(window.0), end.max(window.1)));
+        self.bam_reader.fetch((tid, start, end))?;
+        self.fetches += 1;
+
+        let downstream = variants
+            .iter()
+            .map(|variant| self.downstream_reference(variant))
+            .collect::<VlodResult<Vec<_>>>()?;
+        let config = &self.config;
+        let mut sites: Vec<PileupSite> = variants
+            .iter()
+            .zip(downstream)
+            .map(|(variant, downstream)| PileupSite { downstream, ..PileupSite::new(variant, config) })
+            .collect();
+        let last_site = sites.iter().map(|site| site.pos).max().unwrap_or(0);
+
+        let mut pileup = self.bam_reader.pileup();
+        pileup.set_max_depth(config.max_pileup_depth);
+
+        // Sites whose column reached the maximum depth, to count by read iteration
+        let mut capped: Vec<usize> = Vec::new();
+        for p in pileup {
+            let p = p?;
+            let pos = p.pos() as u64;
+            if pos > last_site {
+                break;
+            }
+
+            // Several VCF records may share a position
+            for (index, site) in sites.iter_mut().enumerate().filter(|(_, site)| site.pos == pos) {
+                if p.depth() >= config.max_pileup_depth {
+                    log::debug!(
+                        "Pileup at {}:{} reached the maximum depth {} ({})",
+                        site.variant.chrom,
+                        site.variant.pos,
+                        config.max_pileup_depth,
+                        config.depth_cap
+                    );
+                    match config.depth_cap {
+                        DepthCapPolicy::Iterate => {
+                            capped.push(index);
+                            continue;
+                        }
+                        DepthCapPolicy::Flag => site.counts.depth_capped = true,
+                    }
+                }
+                for alignment in p.alignments() {
+                    site.count_read(&SiteRead::from_pileup(&alignment), config);
+                }
+            }
+        }
+
+        for index in capped {
+            count_site_by_iteration(&mut self.bam_reader, tid, &mut sites[index], config)?;
+            self.fetches += 1;
+        }
+
+        Ok(sites.into_iter().map(|site| site.finish(config)).collect())
+    }
+
+    /// Analyze a single variant by iterating over its reads instead of a pileup:
+    /// the alternative counting engine, used to spot-check the pileup counts
+    pub fn analyze_variant_by_iteration(&mut self, variant: &Variant) -> VlodResult<AlleleCounts> {
+        self.with_retry(|analyzer| analyzer.analyze_variant_by_iteration_once(variant))
+    }
+
+    fn analyze_variant_by_iteration_once(&mut self, variant: &Variant) -> VlodResult<AlleleCounts> {
+        let tid = self.tid(&variant.chrom)?;
+        let downstream = self.downstream_reference(variant)?;
+        let config = &self.config;
+        let mut site = PileupSite { downstream, ..PileupSite::new(variant, config) };
+        count_site_by_iteration(&mut self.bam_reader, tid, &mut site, config)?;
+        self.fetches += 1;
+        Ok(site.finish(config))
+    }
+
+    /// Count A/C/G/T read bases at a 0-based position (deletions and unknown bases
+    /// are ignored), from the reads and bases that would be counted at a variant
+    /// there under the configuration
+    pub fn base_counts(&mut self, chrom: &str, pos: u64) -> VlodResult<BaseCounts> {
+        self.with_retry(|analyzer| analyzer.base_counts_once(chrom, pos))
+    }
+
+    fn base_counts_once(&mut self, chrom: &str, pos: u64) -> VlodResult<BaseCounts> {
+        let tid = self.tid(chrom)?;
+        self.bam_reader.fetch((tid, pos, pos + 1))?;
+
+        let config = &self.config;
+        let mut pileup = self.bam_reader.pileup();
+        pileup.set_max_depth(config.max_pileup_depth);
+
+        let mut counts = [0; 4];
+        for p in pileup {
+            let p = p?;
+            if p.pos() as u64 != pos {
+                continue;
+            }
+
+            for alignment in p.alignments() {
+                if alignment.is_del() || alignment.is_refskip() {
+                    continue;
+                }
+                let Some(qpos) = alignment.qpos() else {
+                    continue;
+                };
+                let record = alignment.record();
+                if !passes_read_filters(&record, config) {
+                    continue;
+                }
+                let fallback_quality = if has_missing_quality(&record) {
+                    match config.missing_quality {
+                        MissingQualityPolicy::Default(quality) => quality,
+                        MissingQualityPolicy::Exclude => continue,
+                    }
+                } else {
+                    DEFAULT_MISSING_QUALITY
+                };
+                if base_quality(&record, qpos, fallback_quality) < config.min_base_quality {
+                    continue;
+                }
+                if is_short_fragment(&record) {
+                    match config.short_fragments {
+                        ShortFragmentPolicy::Exclude => continue,
+                        ShortFragmentPolicy::Collapse if record.is_last_in_template() => continue,
+                        _ => {}
+                    }
+                }
+                if let Some(index) = base_index(record.seq()[qpos]) {
+                    counts[index] += 1;
+                }
+            }
+            break;
+        }
+
+        Ok(counts)
+    }
+
+    /// Estimated read load at each (contig, 1-based position), from the BAM index
+    /// alone: the compressed bytes of the BGZF blocks a fetch of the position would
+    /// read, plus one. Deep sites read megabytes where ordinary ones read a block or
+    /// two; contigs missing from the BAM estimate 1.
+    pub fn index_load_estimates(&self, positions: &[(&str, u64)]) -> VlodResult<Vec<u64>> {
+        let index = IndexHandle::load(&self.bam_reader, &self.bam_path, &self.index_path)?;
+        let header = self.bam_reader.header();
+        Ok(positions
+            .iter()
+            .map(|&(chrom, pos)| {
+                let Some(tid) = header.tid(chrom.as_bytes()) else {
+                    return 1;
+                };
+                let start = pos.saturating_sub(1) as i64;
+                index.chunk_bytes(tid as i32, start, start + 1).map_or(1, |bytes| bytes + 1)
+            })
+            .collect())
+    }
+
+    /// Read depth at each base of the 0-based half-open interval [start, end):
+    /// reads with a base there (not deleted or skipped) that pass the mapping
+    /// quality and tag filters. Columns reaching `max_pileup_depth` are counted
+    /// again by read iteration, or left capped, as `depth_cap` has variant sites
+    /// counted. Unknown contigs are an error.
+    pub fn region_depths(&mut self, chrom: &str, start: u64, end: u64) -> VlodResult<Vec<u32>> {
+        self.with_retry(|analyzer| analyzer.region_depths_once(chrom, start, end))
+    }
+
+    fn region_depths_once(&mut self, chrom: &str, start: u64, end: u64) -> VlodResult<Vec<u32>> {
+        let tid = self.tid(chrom)?;
+        let mut depths = vec![0; end.saturating_sub(start) as usize];
+        if depths.is_empty() {
+            return Ok(depths);
+        }
+        let config = &self.config;
+        self.bam_reader.fetch((tid, start, end))?;
+
+        let mut pileup = self.bam_reader.pileup();
+        pileup.set_max_depth(config.max_pileup_depth);
+        let mut capped = Vec::new();
+        for p in pileup {
+            let p = p?;
+            let pos = p.pos() as u64;
+            if pos < start || pos >= end {
+                continue;
+            }
+            if p.depth() >= config.max_pileup_depth && config.depth_cap == DepthCapPolicy::Iterate {
+                capped.push(pos);
+                continue;
+            }
+            depths[(pos - start) as usize] = p
+                .alignments()
+                .filter(|alignment| !alignment.is_del() && !alignment.is_refskip())
+                .filter(|alignment| passes_read_filters(&alignment.record(), config))
+                .count() as u32;
+        }
+
+        for pos in capped {
+            self.bam_reader.fetch((tid, pos, pos + 1))?;
+            let mut depth = 0;
+            for record in self.bam_reader.records() {
+                let record = record?;
+                if record.is_unmapped() || !passes_read_filters(&record, config) {
+                    continue;
+                }
+                if SiteRead::from_record(record, pos as i64).is_some_and(|read| read.qpos.is_some()) {
+                    depth += 1;
+                }
+            }
+            depths[(pos - start) as usize] = depth;
+        }
+        Ok(depths)
+    }
+
+    /// Collect reads and a majority-base consensus over the assembly window around a variant
+    #[cfg(feature = "assembly")]
+    pub fn collect_locus_window(&mut self, variant: &Variant) -> VlodResult<LocusWindow> {
+        self.with_retry(|analyzer| analyzer.collect_locus_window_once(variant))
+    }
+
+    #[cfg(feature = "assembly")]
+    fn collect_locus_window_once(&mut self, variant: &Variant) -> VlodResult<LocusWindow> {
+        use rust_htslib::bam::ext::BamRecordExtensions;
+
+        let tid = self.tid(&variant.chrom)?;
+        let start = variant.pos.saturating_sub(1).saturating_sub(ASSEMBLY_FLANK);
+        let end = variant.pos.saturating_sub(1) + variant.ref_allele.len() as u64 + ASSEMBLY_FLANK;
+        self.bam_reader.fetch((tid, start, end))?;
+
+        let mut base_counts = vec![[0u32; 4]; (end - start) as usize];
+        let mut reads = Vec::new();
+
+        for record in self.bam_reader.records() {
+            let record = record?;
+            if record.is_unmapped() {
+                continue;
+            }
+
+            let seq = record.seq().as_bytes();
+            for [qpos, rpos] in record.aligned_pairs() {
+                if rpos < start as i64 || rpos >= end as i64 {
+                    continue;
+                }
+                if let Some(idx) = b"ACGT".iter().position(|&b| b == seq[qpos as usize]) {
+                    base_counts[(rpos - start as i64) as usize][idx] += 1;
+                }
+            }
+            reads.push(seq);
+        }
+
+        let consensus = base_counts
+            .iter()
+            .map(|counts| match counts.iter().enumerate().max_by_key(|(_, &c)| c) {
+                Some((idx, &c)) if c > 0 => b"ACGT"[idx],
+                _ => b'N',
+            })
+            .collect();
+
+        Ok(LocusWindow {
+            start,
+            consensus,
+            reads,
+        })
+    }
+
+    fn classify_snv_mnv(
+        read: &SiteRead,
+        variant: &Variant,
+        alt_alleles: &[&str],
+        bisulfite: bool,
+    ) -> Option<ReadAllele> {
+        if read.is_del {
+            return Some(ReadAllele::Deleted);
+        }
+
+        let qpos = read.qpos?;
+        let record = &read.record;
+        let seq = record.seq();
+        let ref_len = variant.ref_allele.len();
+
+        if bisulfite {
+            if qpos + ref_len > seq.len() {
+                return None;
+            }
+            let observed: Vec<u8> = (qpos..qpos + ref_len).map(|i| seq[i]).collect();
+            return Self::classify_bisulfite(&observed, BisulfiteStrand::of_record(record), variant, alt_alleles);
+        }
+
+        if ref_len == 1 {
+            // SNV
+            if qpos < seq.len() {
+                let base = seq[qpos] as char;
+                let base_str = base.to_string();
+                
+                if base_str == variant.ref_allele {
+                    return Some(ReadAllele::Ref);
+                } else if alt_alleles.contains(&base_str.as_str()) {
+                    return Some(ReadAllele::Alt(base_str));
+                } else if is_called(base_str.as_bytes()) {
+                    return Some(ReadAllele::Other);
+                }
+            }
+        } else {
+            // MNV
+            if qpos + ref_len <= seq.len() {
+                let read_seq: String = (qpos..qpos + ref_len)
+                    .map(|i| seq[i] as char)
+                    .collect();
+                
+                if read_seq == variant.ref_allele {
+                    return Some(ReadAllele::Ref);
+                } else if alt_alleles.contains(&read_seq.as_str()) {
+                    return Some(ReadAllele::Alt(read_seq));
+                } else if is_called(read_seq.as_bytes()) {
+                    return Some(ReadAllele::Other);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Match read bases against REF/ALT treating conversion-compatible mismatches as
+    /// matches; reads compatible with more than one allele are uninformative
+    fn classify_bisulfite(
+        observed: &[u8],
+        strand: BisulfiteStrand,
+        variant: &Variant,
+        alt_alleles: &[&str],
+    ) -> Option<ReadAllele> {
+        let supports_ref = strand.allele_matches(observed, &variant.ref_allele);
+        let matching_alts: Vec<&str> = alt_alleles
+            .iter()
+            .copied()
+            .filter(|alt| strand.allele_matches(observed, alt))
+            .collect();
+
+        match (supports_ref, matching_alts.as_slice()) {
+            (true, []) => Some(ReadAllele::Ref),
+            (false, [alt]) => Some(ReadAllele::Alt(alt.to_string())),
+            (false, []) if is_called(observed) => Some(ReadAllele::Other),
+            _ => None,
+        }
+    }
+
+    fn classify_indel(
+        read: &SiteRead,
+        variant: &Variant,
+        alt_alleles: &[&str],
+        downstream: Option<&[u8]>,
+    ) -> Option<ReadAllele> {
+        let indel = read.indel;
+        let mut supports_ref = false;
+        
+        for &alt_allele in alt_alleles {
+            let expected_indel = alt_allele.len() as i32 - variant.ref_allele.len() as i32;
+
+            // Insertions at read ends are often truncated or soft-clipped by the aligner
+            let recovered = match indel {
+                Indel::None | Indel::Ins(_) if expected_indel > 0 => {
+                    Self::recover_read_end_insertion(read, &variant.ref_allele, alt_allele, downstream)
+                }
+                _ => None,
+            };
+            
+            match (indel, recovered) {
+                (Indel::Ins(n), _) if expected_indel > 0 && n == expected_indel as u32 => {
+                    return Some(ReadAllele::Alt(alt_allele.to_string()));
+                }
+                (Indel::Del(n), _) if expected_indel < 0 && n == expected_indel.unsigned_abs() => {
+                    return Some(ReadAllele::Alt(alt_allele.to_string()));
+                }
+                (_, Some(ReadEndInsertion::SoftClipped)) => {
+                    return Some(ReadAllele::SoftClippedAlt(alt_allele.to_string()));
+                }
+                (_, Some(ReadEndInsertion::Truncated)) => {
+                    return Some(ReadAllele::Alt(alt_allele.to_string()));
+                }
+                (Indel::None, None) => {
+                    supports_ref = true;
+                }
+                _ => {}
+            }
+        }
+
+        supports_ref.then_some(ReadAllele::Ref)
+    }
+
+    /// Check whether a read ending right after the REF bases carries the expected
+    /// inserted sequence in its unaligned tail (trailing insertion and/or soft clip),
+    /// followed by the `downstream` reference bases when the clip runs past it
+    fn recover_read_end_insertion(
+        read: &SiteRead,
+        ref_allele: &str,
+        alt_allele: &str,
+        downstream: Option<&[u8]>,
+    ) -> Option<ReadEndInsertion> {
+        if !alt_allele.starts_with(ref_allele) {
+            return None;
+        }
+        let inserted = &alt_allele.as_bytes()[ref_allele.len()..];
+
+        let qpos = read.qpos?;
+        let record = &read.record;
+        let (tail, soft_clipped) = unaligned_tail(record, qpos + ref_allele.len())?;
+
+        if !insertion_tail_matches(&tail, soft_clipped, inserted, downstream) {
+            None
+        } else if soft_clipped {
+            Some(ReadEndInsertion::SoftClipped)
+        } else {
+            Some(ReadEndInsertion::Truncated)
+        }
+    }
+}
+
+/// 0-based start and end of the region fetched for a variant, padded by its
+/// longest allele for indels
+fn fetch_window(variant: &Variant) -> (u64, u64) {
+    let max_len = variant
+        .alt_allele
+        .split(',')
+        .map(str::len)
+        .chain([variant.ref_allele.len()])
+        .max()
+        .unwrap_or(1) as u64;
+    (variant.pos.saturating_sub(1), variant.pos.saturating_add(max_len))
+}
+
+/// Split loci into fetch groups: runs of readable loci on one contig, in position
+/// order, each at most `merge_distance` bases after the previous one. Loci that
+/// cannot be read, and every locus when `merge_distance` is 0, form groups of
+/// their own.
+pub fn fetch_groups(loci: &[Result<Variant, String>], merge_distance: u64) -> Vec<Range<usize>> {
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    for (index, locus) in loci.iter().enumerate() {
+        let joins = match (groups.last(), locus) {
+            (Some(group), Ok(locus)) if merge_distance > 0 => match &loci[group.end - 1] {
+                Ok(previous) => {
+                    previous.chrom == locus.chrom
+                        && locus.pos >= previous.pos
+                        && locus.pos - previous.pos <= merge_distance
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        };
+        match groups.last_mut() {
+            Some(group) if joins => group.end = index + 1,
+            _ => groups.push(index..index + 1),
+        }
+    }
+    groups
+}
+
+/// A read at a variant site, from a pileup column or located through its CIGAR
+/// when the site's reads are iterated directly
+struct SiteRead {
+    record: Record,
+    /// Query position of the read base at the site (None in a deletion or skip)
+    qpos: Option<usize>,
+    is_del: bool,
+    is_refskip: bool,
+    /// Insertion or deletion right after the site base
+    indel: Indel,
+}
+
+impl SiteRead {
+    fn from_pileup(alignment: &Alignment) -> Self {
+        SiteRead {
+            record: alignment.record(),
+            qpos: alignment.qpos(),
+            is_del: alignment.is_del(),
+            is_refskip: alignment.is_refskip(),
+            indel: alignment.indel(),
+        }
+    }
+
+    /// Locate a 0-based reference position in a read as a pileup column would
+    /// (None when the read does not reach it)
+    fn from_record(record: Record, pos: i64) -> Option<Self> {
+        if pos < record.pos() {
+            return None;
+        }
+        let cigar = record.cigar();
+        let mut ref_pos = record.pos();
+        let mut query_pos = 0;
+        for (index, op) in cigar.iter().enumerate() {
+            match *op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    let end = ref_pos + len as i64;
+                    if pos < end {
+                        let offset = (pos - ref_pos) as usize;
+                        let indel = if pos + 1 == end {
+                            match cigar.iter().skip(index + 1).find(|op| !matches!(op, Cigar::Pad(_))) {
+                                Some(Cigar::Ins(len)) => Indel::Ins(*len),
+                                Some(Cigar::Del(len)) => Indel::Del(*len),
+                                _ => Indel::None,
+                            }
+                        } else {
+                            Indel::None
+                        };
+                        return Some(SiteRead {
+                            qpos: Some(query_pos + offset),
+                            is_del: false,
+                            is_refskip: false,
+                            indel,
+                            record,
+                        });
+                    }
+                    ref_pos = end;
+                    query_pos += len as usize;
+                }
+                Cigar::Del(len) | Cigar::RefSkip(len) => {
+                    let end = ref_pos + len as i64;
+                    if pos < end {
+                        return Some(SiteRead {
+                            qpos: None,
+                            is_del: matches!(op, Cigar::Del(_)),
+                            is_refskip: matches!(op, Cigar::RefSkip(_)),
+                            indel: Indel::None,
+                            record,
+                        });
+                    }
+                    ref_pos = end;
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) => query_pos += len as usize,
+                Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+        None
+    }
+}
+
+/// Whether a read passes the minimum mapping quality and `--read-filter` tag
+/// filters of a configuration
+fn passes_read_filters(record: &Record, config: &LodConfig) -> bool {
+    record.mapq() >= config.min_mapping_quality
+        && (config.read_filters.is_empty() || passes_all(&config.read_filters, record))
+}
+
+/// Count a site by iterating over the reads overlapping it, without a pileup
+fn count_site_by_iteration(
+    reader: &mut IndexedReader,
+    tid: u32,
+    site: &mut PileupSite,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    reader.fetch((tid, site.pos, site.pos + 1))?;
+    for record in reader.records() {
+        let record = record?;
+        // The pileup leaves out unmapped reads only
+        if record.is_unmapped() {
+            continue;
+        }
+        if let Some(read) = SiteRead::from_record(record, site.pos as i64) {
+            site.count_read(&read, config);
+        }
+    }
+    Ok(())
+}
+
+/// A variant being counted during a pileup traversal or read iteration
+struct PileupSite<'a> {
+    variant: &'a Variant,
+    /// 0-based position of the pileup column read
+    pos: u64,
+    alt_alleles: Vec<&'a str>,
+    is_snv_mnv: bool,
+    candidate_amplicons: Vec<&'a BedRegion>,
+    /// Reference bases after the REF allele of an insertion site (see
+    /// `BamAnalyzer::downstream_reference`)
+    downstream: Option<Vec<u8>>,
+    counts: AlleleCounts,
+}
+
+impl<'a> PileupSite<'a> {
+    fn new(variant: &'a Variant, config: &'a LodConfig) -> Self {
+        let alt_alleles: Vec<&str> = variant.alt_allele.split(',').collect();
+        let is_snv_mnv = alt_alleles.iter().map(|alt| alt.len()).max() == Some(variant.ref_allele.len());
+        let pos = variant.pos.saturating_sub(1);
+        let mut counts = AlleleCounts::new();
+        counts.titration = config.titration_fractions.iter().map(|_| AlleleCounts::new()).collect();
+        PileupSite {
+            variant,
+            pos,
+            alt_alleles,
+            is_snv_mnv,
+            candidate_amplicons: config
+                .amplicons
+                .as_ref()
+                .map(|a| a.covering(&variant.chrom, pos))
+                .unwrap_or_default(),
+            downstream: None,
+            counts,
+        }
+    }
+
+    /// Counts of the site once all its reads were seen
+    fn finish(self, config: &LodConfig) -> AlleleCounts {
+        let mut counts = self.counts;
+        if config.deleted_reads == DeletedReadPolicy::Count {
+            counts.count_deleted_reads();
+        }
+        counts
+    }
+
+    /// Count one read at the site
+    fn count_read(&mut self, read: &SiteRead, config: &LodConfig) {
+        if read.is_refskip || !passes_read_filters(&read.record, config) {
+            return;
+        }
+        let fallback_quality = if has_missing_quality(&read.record) {
+            self.counts.missing_quality_reads += 1;
+            match config.missing_quality {
+                MissingQualityPolicy::Default(quality) => quality,
+                MissingQualityPolicy::Exclude => return,
+            }
+        } else {
+            DEFAULT_MISSING_QUALITY
+        };
+        let ref_len = self.variant.ref_allele.len();
+        // Bases of an SNV or MNV below the minimum quality are not evidence
+        if config.min_base_quality > 0 && self.is_snv_mnv {
+            if let Some(qpos) = read.qpos {
+                let record = &read.record;
+                let end = (qpos + ref_len).min(record.seq_len());
+                if (qpos..end).any(|i| base_quality(record, i, fallback_quality) < config.min_base_quality) {
+                    return;
+                }
+            }
+        }
+        self.counts.site_depth += 1;
+        if is_short_fragment(&read.record) {
+            self.counts.short_fragment_reads += 1;
+            match config.short_fragments {
+                ShortFragmentPolicy::Exclude => return,
+                ShortFragmentPolicy::Collapse if read.record.is_last_in_template() => return,
+                _ => {}
+            }
+        }
+        // Depth alone: reads with a base at the site, without classifying alleles
+        if config.coverage_only {
+            if !read.is_del {
+                self.counts.total_count += 1;
+            }
+            return;
+        }
+
+        let read_allele = if self.is_snv_mnv {
+            BamAnalyzer::classify_snv_mnv(read, self.variant, &self.alt_alleles, config.bisulfite)
+        } else {
+            BamAnalyzer::classify_indel(read, self.variant, &self.alt_alleles, self.downstream.as_deref())
+        };
+        let Some(read_allele) = read_allele else {
+            return;
+        };
+
+        let record = &read.record;
+        let amplicon = if self.candidate_amplicons.is_empty() {
+            None
+        } else {
+            AmpliconSet::assign(
+                &self.candidate_amplicons,
+                record.pos(),
+                record.cigar().end_pos(),
+                record.is_reverse(),
+            )
+        };
+        let context = ReadContext {
+            amplicon,
+            orientation: pair_orientation(record),
+            reverse: record.is_reverse(),
+        };
+
+        if !config.titration_fractions.is_empty() {
+            let draw = downsample_draw(record.qname());
+            for (counts, &fraction) in self.counts.titration.iter_mut().zip(&config.titration_fractions) {
+                if draw < fraction {
+                    counts.add_read(read_allele.clone(), &context);
+                }
+            }
+        }
+
+        self.counts.add_read(read_allele, &context);
+    }
+}
+
+/// A BAM index loaded by htslib, destroyed when dropped. Only used to read the
+/// chunk offsets of region queries, which the safe reader API does not expose.
+struct IndexHandle(*mut htslib::hts_idx_t);
+
+impl IndexHandle {
+    /// Load the index at `index_path` of the BAM open in `reader`
+    fn load(reader: &IndexedReader, bam_path: &Path, index_path: &Path) -> VlodResult<Self> {
+        let c_path = |path: &Path| {
+            CString::new(path.to_string_lossy().as_bytes())
+                .map_err(|_| VlodError::InvalidConfig(format!("invalid path: {}", path.display())))
+        };
+        let (bam, index) = (c_path(bam_path)?, c_path(index_path)?);
+        // SAFETY: `htsfile` is the open file handle of `reader`, borrowed for the
+        // whole call, and both paths are NUL-terminated strings that outlive it.
+        // htslib only reads them and returns a newly allocated index (or null)
+        // that nothing else references.
+        let handle = unsafe { htslib::sam_index_load2(reader.htsfile(), bam.as_ptr(), index.as_ptr()) };
+        if handle.is_null() {
+            return Err(VlodError::FileNotFound(format!("could not load BAM index {:?}", index_path)));
+        }
+        Ok(IndexHandle(handle))
+    }
+
+    /// Compressed bytes of the BGZF chunks a fetch of the 0-based half-open
+    /// interval [start, end) of `tid` would read; None when htslib cannot query it
+    fn chunk_bytes(&self, tid: i32, start: i64, end: i64) -> Option<u64> {
+        // SAFETY: the index is live and owned by `self` for the whole call;
+        // `sam_itr_queryi` only reads it and returns a newly allocated iterator
+        // (or null), which `IteratorHandle` then owns.
+        let itr = IteratorHandle(unsafe { htslib::sam_itr_queryi(self.0, tid, start, end) });
+        if itr.0.is_null() {
+            return None;
+        }
+        // SAFETY: the iterator is non-null and stays allocated until `itr` is
+        // dropped at the end of this function, after the last use of `offsets`.
+        // htslib sets `off` to an array of exactly `n_off` chunk pairs (null when
+        // there are none) owned by the iterator, and nothing modifies the iterator
+        // while the slice is borrowed.
+        let offsets = unsafe {
+            let query = &*itr.0;
+            match usize::try_from(query.n_off) {
+                Ok(n_off) if n_off > 0 && !query.off.is_null() => std::slice::from_raw_parts(query.off, n_off),
+                _ => &[],
+            }
+        };
+        // Virtual offsets keep the compressed offset of their BGZF block in the
+        // upper 48 bits
+        Some(offsets.iter().map(|pair| (pair.v >> 16).saturating_sub(pair.u >> 16)).sum())
+    }
+}
+
+impl Drop for IndexHandle {
+    fn drop(&mut self) {
+        // SAFETY: the pointer was returned non-null by `sam_index_load2`, is owned
+        // by this handle alone and is destroyed only here
+        unsafe { htslib::hts_idx_destroy(self.0) }
+    }
+}
+
+/// An htslib region iterator, destroyed when dropped
+struct IteratorHandle(*mut htslib::hts_itr_t);
+
+impl Drop for IteratorHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: a non-null pointer was returned by `sam_itr_queryi`, is owned
+            // by this handle alone and is destroyed only here
+            unsafe { htslib::hts_itr_destroy(self.0) }
+        }
+    }
+}
+
+/// Return the read bases from `tail_start` onwards if nothing past that point is
+/// aligned to the reference, along with whether the tail includes a soft clip
+fn unaligned_tail(record: &Record, tail_start: usize) -> Option<(Vec<u8>, bool)> {
+    let mut tail_len = 0;
+    let mut soft_clipped = false;
+
+    for op in record.cigar().iter().rev() {
+        match op {
+            Cigar::HardClip(_) => continue,
+            Cigar::SoftClip(n) => {
+                tail_len += *n as usize;
+                soft_clipped = true;
+            }
+            Cigar::Ins(n) => tail_len += *n as usize,
+            _ => break,
+        }
+    }
+
+    let seq_len = record.seq_len();
+    if tail_len == 0 || tail_start + tail_len != seq_len {
+        return None;
     }
 
-    pub fn get_vaf(&self, allele: &str) -> f64 {
-        if self.total_count == 0 {
-            0.0
-        } else {
-            self.get_alt_count(allele) as f64 / self.total_count as f64
-        }
-    }
+    let seq = record.seq();
+    let tail = (tail_start..seq_len).map(|i| seq[i]).collect();
+    Some((tail, soft_clipped))
 }
 
-/// BAM analyzer for processing variants
-pub struct BamAnalyzer {
-    bam_reader: IndexedReader,
+/// Whether the unaligned tail of a read carries an insertion: at least
+/// `MIN_INSERTION_TAIL_OVERLAP` of the inserted bases (or all of a shorter
+/// insertion), then, for a soft clip running past them, the reference bases that
+/// follow when known. Without a soft clip the whole tail is inserted sequence and
+/// must not be longer than the insertion.
+fn insertion_tail_matches(tail: &[u8], soft_clipped: bool, inserted: &[u8], downstream: Option<&[u8]>) -> bool {
+    if !soft_clipped && tail.len() > inserted.len() {
+        return false;
+    }
+    if tail.len() < inserted.len().min(MIN_INSERTION_TAIL_OVERLAP) || !matches_with_tolerance(tail, inserted) {
+        return false;
+    }
+    match (tail.get(inserted.len()..), downstream) {
+        (Some(past), Some(downstream)) if !past.is_empty() => matches_with_tolerance(past, downstream),
+        _ => true,
+    }
 }
 
-impl BamAnalyzer {
-    pub fn new<P: AsRef<Path>>(bam_path: P) -> VlodResult<Self> {
-        let bam_path = bam_path.as_ref();
-        
-        // Check for BAI index file next to the BAM file
-        let bai_path = bam_path.with_extension("bam.bai");
-        let alt_bai_path = bam_path.with_extension("bai");
-        
-        let bam_reader = if bai_path.exists() {
-            IndexedReader::from_path_and_index(bam_path, &bai_path)?
-        } else if alt_bai_path.exists() {
-            IndexedReader::from_path_and_index(bam_path, &alt_bai_path)?
-        } else {
-            return Err(VlodError::FileNotFound(format!(
-                "BAM index file not found. Expected {} or {}",
-                bai_path.display(),
-                alt_bai_path.display()
-            )));
-        };
-        
-        Ok(BamAnalyzer { bam_reader })
+/// Compare observed bases against the expected prefix, tolerating a small
+/// fraction of mismatches over the overlapping length
+fn matches_with_tolerance(observed: &[u8], expected: &[u8]) -> bool {
+    let overlap = observed.len().min(expected.len());
+    if overlap == 0 {
+        return false;
     }
 
-    /// Analyze a single variant and return allele counts
-    pub fn analyze_variant(&mut self, variant: &Variant) -> VlodResult<AlleleCounts> {
-        let tid = self.bam_reader.header().tid(variant.chrom.as_bytes())
-            .ok_or_else(|| VlodError::InvalidVariant(format!("Unknown chromosome: {}", variant.chrom)))?;
-
-        // Fetch only the specific region around the variant
-        // For indels, we need a slightly larger window
-        let ref_len = variant.ref_allele.len();
-        let alt_lens: Vec<usize> = variant.alt_allele.split(',').map(|a| a.len()).collect();
-        let max_len = (*alt_lens.iter().max().unwrap_or(&1)).max(ref_len) as u32;
-        
-        // Fetch region with some padding for indels
-        let start = variant.pos.saturating_sub(1); // Convert to 0-based
-        let end = variant.pos.saturating_add(max_len); // Inclusive end
-        
-        self.bam_reader.fetch((tid, start, end))?;
-
-        let mut pileup = self.bam_reader.pileup();
-        pileup.set_max_depth(1_000_000);
+    let mismatches = observed[..overlap]
+        .iter()
+        .zip(&expected[..overlap])
+        .filter(|(a, b)| !a.eq_ignore_ascii_case(b))
+        .count();
 
-        let mut allele_counts = AlleleCounts::new();
-        let alt_alleles: Vec<&str> = variant.alt_allele.split(',').collect();
+    mismatches as f64 <= overlap as f64 * INSERTION_TAIL_MAX_MISMATCH_RATE
+}
 
-        for p in pileup {
-            let p = p?;
-            
-            // Check if this is the position we're interested in
-            if p.pos() as u32 != variant.pos - 1 {
-                continue;
-            }
+/// Whether read bases are all called (A, C, G or T)
+fn is_called(bases: &[u8]) -> bool {
+    !bases.is_empty()
+        && bases
+            .iter()
+            .all(|base| matches!(base.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T'))
+}
 
-            for alignment in p.alignments() {
-                if alignment.is_refskip() {
-                    continue;
-                }
+/// Fraction of third-allele reads among `other_reads` and `allele_reads` reads
+/// supporting REF or an ALT
+pub fn other_allele_fraction(other_reads: u32, allele_reads: u32) -> f64 {
+    let reads = other_reads + allele_reads;
+    if reads == 0 {
+        0.0
+    } else {
+        other_reads as f64 / reads as f64
+    }
+}
 
-                let ref_len = variant.ref_allele.len();
-                let alt_len = alt_alleles.iter().map(|a| a.len()).max().unwrap_or(0);
+/// Process a chunk of variants in parallel, stopping before the next fetch once
+/// `stop` is set or the observer cancels the run. Variants at most
+/// `config.fetch_merge_distance` bases apart are read with one fetch and pileup.
+/// Each result is passed to the observer as soon as it is scored.
+pub fn process_variant_chunk(
+    variants: &[Variant],
+    bam_path: &Path,
+    config: &LodConfig,
+    stop: &AtomicBool,
+    observer: &dyn Observer,
+) -> VlodResult<Vec<DetectabilityResult>> {
+    let mut analyzer = BamAnalyzer::new(bam_path)?.with_config(config);
+    let mut results = Vec::new();
+    let loci: Vec<Result<Variant, String>> = variants.iter().map(|variant| analyzer.resolve_locus(variant)).collect();
 
-                if ref_len == alt_len {
-                    // SNV or MNV
-                    Self::process_snv_mnv(&alignment, variant, &alt_alleles, &mut allele_counts)?;
-                } else {
-                    // Indel
-                    Self::process_indel(&alignment, variant, &alt_alleles, &mut allele_counts)?;
-                }
-            }
-            
-            // Since we fetched a specific region and found our position, we can break
+    for group in fetch_groups(&loci, config.fetch_merge_distance) {
+        if stop.load(Ordering::Relaxed) || observer.is_cancelled() {
             break;
         }
+        let group_variants = &variants[group.clone()];
+        for variant in group_variants {
+            observer.on_variant_start(variant);
+        }
 
-        Ok(allele_counts)
-    }
-
-    fn process_snv_mnv(
-        alignment: &Alignment,
-        variant: &Variant,
-        alt_alleles: &[&str],
-        allele_counts: &mut AlleleCounts,
-    ) -> VlodResult<()> {
-        if alignment.is_del() {
-            return Ok(());
+        // Variants the contig policy does not read are reported, not fatal
+        if let Err(reason) = &loci[group.start] {
+            let variant = &variants[group.start];
+            log::debug!("{}:{} not assessable: {}", variant.chrom, variant.pos, reason);
+            let counts = AlleleCounts::not_assessable(reason.clone());
+            observer.on_variant_done(variant, &counts);
+            for alt_allele in variant.alt_allele.split(',') {
+                let variant_copy = Variant::new(
+                    variant.chrom.clone(),
+                    variant.pos,
+                    variant.ref_allele.clone(),
+                    alt_allele.to_string(),
+                );
+                let result = detectability_result(variant_copy, f64::NEG_INFINITY, counts.clone(), config);
+                observer.on_result(&result);
+                results.push(result);
+            }
+            continue;
         }
+        let group_loci: Vec<Variant> = loci[group.clone()].iter().filter_map(|locus| locus.clone().ok()).collect();
 
-        let qpos = alignment.qpos();
-        if qpos.is_none() {
-            return Ok(());
+        // Name the variant and BAM in errors; htslib messages alone locate neither
+        let context = |variant: &Variant, locus: &Variant| {
+            let mapped = if locus.chrom != variant.chrom || locus.pos != variant.pos {
+                format!(" (at {}:{})", locus.chrom, locus.pos)
+            } else {
+                String::new()
+            };
+            format!(
+                "reading {}:{}:{}>{}{} from {:?}",
+                variant.chrom, variant.pos, variant.ref_allele, variant.alt_allele, mapped, bam_path
+            )
+        };
+        let retries = analyzer.retries();
+        let mut group_counts = analyzer.analyze_variants(&group_loci).map_err(|e| {
+            let nearby = match group_loci.len() {
+                1 => String::new(),
+                n => format!(" and {} nearby variants", n - 1),
+            };
+            e.context(format!("{}{}", context(&group_variants[0], &group_loci[0]), nearby))
+        })?;
+        if let Some(first) = group_counts.first_mut() {
+            first.io_retries = (analyzer.retries() - retries) as u32;
         }
 
-        let qpos = qpos.unwrap();
-        let record = alignment.record();
-        let seq = record.seq();
-        let ref_len = variant.ref_allele.len();
+        for (index, allele_counts) in group_counts.into_iter().enumerate() {
+            let variant = &group_variants[index];
+            let alt_alleles: Vec<&str> = variant.alt_allele.split(',').collect();
+            #[cfg_attr(not(feature = "assembly"), allow(unused_mut))]
+            let mut allele_counts = allele_counts;
 
-        if ref_len == 1 {
-            // SNV
-            if qpos < seq.len() {
-                let base = seq[qpos] as char;
-                let base_str = base.to_string();
-                
-                if base_str == variant.ref_allele {
-                    allele_counts.add_ref();
-                } else if alt_alleles.contains(&base_str.as_str()) {
-                    allele_counts.add_alt(base_str);
-                }
-            }
-        } else {
-            // MNV
-            if qpos + ref_len <= seq.len() {
-                let read_seq: String = (qpos..qpos + ref_len)
-                    .map(|i| seq[i] as char)
-                    .collect();
-                
-                if read_seq == variant.ref_allele {
-                    allele_counts.add_ref();
-                } else if alt_alleles.contains(&read_seq.as_str()) {
-                    allele_counts.add_alt(read_seq);
+            // Let local assembly decide support where the pileup is ambiguous
+            #[cfg(feature = "assembly")]
+            if config.local_assembly && allele_counts.has_conflicting_evidence() {
+                let locus = &group_loci[index];
+                let window = analyzer.collect_locus_window(locus).map_err(|e| e.context(context(variant, locus)))?;
+                for &alt_allele in &alt_alleles {
+                    let allele_variant = Variant::new(
+                        variant.chrom.clone(),
+                        variant.pos,
+                        variant.ref_allele.clone(),
+                        alt_allele.to_string(),
+                    );
+                    let support = assembly_support(&window, &allele_variant);
+                    allele_counts.assembly_support.insert(alt_allele.to_string(), support);
                 }
             }
-        }
+            observer.on_variant_done(variant, &allele_counts);
 
-        Ok(())
-    }
+            for (alt_index, alt_allele) in alt_alleles.into_iter().enumerate() {
+                let vaf = allele_counts.get_scoring_vaf(alt_allele, config.vaf_definition);
 
-    fn process_indel(
-        alignment: &Alignment,
-        variant: &Variant,
-        alt_alleles: &[&str],
-        allele_counts: &mut AlleleCounts,
-    ) -> VlodResult<()> {
-        use rust_htslib::bam::pileup::Indel;
-        
-        let indel = alignment.indel();
-        
-        for &alt_allele in alt_alleles {
-            let expected_indel = alt_allele.len() as i32 - variant.ref_allele.len() as i32;
-            
-            match indel {
-                Indel::Ins(n) if expected_indel > 0 && n == expected_indel as u32 => {
-                    allele_counts.add_alt(alt_allele.to_string());
-                }
-                Indel::Del(n) if expected_indel < 0 && n == expected_indel.abs() as u32 => {
-                    allele_counts.add_alt(alt_allele.to_string());
-                }
-                Indel::None => {
-                    allele_counts.add_ref();
+                let variant_copy = Variant::new(
+                    variant.chrom.clone(),
+                    variant.pos,
+                    variant.ref_allele.clone(),
+                    alt_allele.to_string(),
+                );
+
+                // Calculate LOD score
+                let lod = calculate_variant_lod_score(vaf, &variant_copy, config);
+
+                // Per-read tallies stay with the first ALT, so that sums over results
+                // count each read once
+                let mut counts = allele_counts.clone();
+                if alt_index > 0 {
+                    counts.missing_quality_reads = 0;
+                    counts.io_retries = 0;
                 }
-                _ => {}
+                let result = detectability_result(variant_copy, lod, counts, config);
+                observer.on_result(&result);
+                results.push(result);
             }
         }
+    }
 
-        Ok(())
+    Ok(results)
+}
+
+/// Read-pair orientation of a paired read
+pub fn pair_orientation(record: &Record) -> Option<PairOrientation> {
+    if !record.is_paired() {
+        return None;
+    }
+    if record.is_first_in_template() != record.is_reverse() {
+        Some(PairOrientation::F1R2)
+    } else {
+        Some(PairOrientation::F2R1)
     }
 }
 
-/// Process a chunk of variants in parallel
-pub fn process_variant_chunk(
-    variants: &[Variant],
+/// Whether a read comes from a fragment shorter than the read: both mates then
+/// cover the whole fragment and read through into the adapter
+pub fn is_short_fragment(record: &Record) -> bool {
+    record.is_paired()
+        && !record.is_unmapped()
+        && !record.is_mate_unmapped()
+        && record.tid() == record.mtid()
+        && record.insert_size() != 0
+        && (record.insert_size().unsigned_abs() as usize) < record.seq_len()
+}
+
+/// Sample background base counts at 0-based positions into a noise profile,
+/// filtering reads and bases as `config` does at the variants
+pub fn sample_background_noise(
     bam_path: &Path,
+    positions: &[(String, u64)],
     config: &LodConfig,
-) -> VlodResult<Vec<(Variant, f64, u32, u32)>> {
-    let mut analyzer = BamAnalyzer::new(bam_path)?;
-    let mut results = Vec::new();
-
-    for variant in variants {
-        let allele_counts = analyzer.analyze_variant(variant)?;
-        
-        // Process each alternative allele
-        let alt_alleles: Vec<&str> = variant.alt_allele.split(',').collect();
-        for alt_allele in alt_alleles {
-            let alt_count = allele_counts.get_alt_count(alt_allele);
-            let vaf = allele_counts.get_vaf(alt_allele);
-            
-            // Calculate LOD score
-            let lod_value = (config.p_tp * vaf) / ((1.0 - vaf) * config.p_se + vaf * config.p_fp);
-            let lod = if lod_value > 0.0 {
-                lod_value.log10()
-            } else {
-                f64::NEG_INFINITY
-            };
-
-            let variant_copy = Variant::new(
-                variant.chrom.clone(),
-                variant.pos,
-                variant.ref_allele.clone(),
-                alt_allele.to_string(),
-            );
+) -> VlodResult<NoiseProfile> {
+    let mut analyzer = BamAnalyzer::new(bam_path)?.with_config(config);
+    let mut profile = NoiseProfile::default();
 
-            results.push((
-                variant_copy,
-                lod,
-                allele_counts.total_count,
-                alt_count,
-            ));
-        }
+    for (chrom, pos) in positions {
+        let counts = analyzer.base_counts(chrom, *pos)?;
+        profile.add_site(&counts);
     }
 
-    Ok(results)
+    if profile.sites() < positions.len() {
+        log::info!(
+            "Used {} of {} sampled background positions (others were shallow or variant)",
+            profile.sites(),
+            positions.len()
+        );
+    }
+    Ok(profile)
 }
 
 #[cfg(test)]
@@ -283,6 +1869,15 @@ mod tests {
         
         assert_eq!(counts.get_vaf("G"), 2.0 / 3.0);
         assert_eq!(counts.get_vaf("T"), 0.0);
+
+        // Reads of another ALT count towards the total but not towards REF+ALT
+        counts.add_alt("C".to_string());
+        assert_eq!(counts.vaf("G", VafDefinition::Total), 0.5);
+        assert_eq!(counts.vaf("G", VafDefinition::RefAlt), 2.0 / 3.0);
+        assert_eq!(counts.get_scoring_vaf("G", VafDefinition::RefAlt), 2.0 / 3.0);
+        assert_eq!(AlleleCounts::new().vaf("G", VafDefinition::RefAlt), 0.0);
+        assert_eq!("Ref-Alt".parse::<VafDefinition>(), Ok(VafDefinition::RefAlt));
+        assert_eq!(VafDefinition::RefAlt.to_string(), "ref-alt");
     }
 
     #[test]
@@ -292,6 +1887,289 @@ mod tests {
         assert_eq!(counts.total_count, 0);
     }
 
+    #[test]
+    fn test_softclip_support_counts_as_alt() {
+        let mut counts = AlleleCounts::new();
+        counts.add_ref();
+        counts.add_alt("ATT".to_string());
+        counts.add_alt_softclip("ATT".to_string());
+
+        assert_eq!(counts.get_alt_count("ATT"), 2);
+        assert_eq!(counts.get_alt_softclip_support("ATT"), 1);
+        assert_eq!(counts.get_alt_softclip_support("AT"), 0);
+        assert_eq!(counts.total_count, 3);
+    }
+
+    #[test]
+    fn test_matches_with_tolerance() {
+        assert!(matches_with_tolerance(b"TTG", b"TTG"));
+        assert!(matches_with_tolerance(b"TT", b"TTGCA")); // partial overlap
+        assert!(matches_with_tolerance(b"ttgcaGGG", b"TTGCA")); // clip runs into reference
+        assert!(!matches_with_tolerance(b"TAG", b"TTG"));
+        assert!(matches_with_tolerance(b"TTGCATTGCA", b"TTGCATTGCC")); // 1 mismatch in 10
+        assert!(!matches_with_tolerance(b"", b"TTG"));
+    }
+
+    #[test]
+    fn test_insertion_tail_matches() {
+        // At least three inserted bases, or the whole of a shorter insertion
+        assert!(!insertion_tail_matches(b"TT", true, b"TTGCA", None));
+        assert!(insertion_tail_matches(b"TTG", true, b"TTGCA", None));
+        assert!(insertion_tail_matches(b"TT", false, b"TT", None));
+        assert!(!insertion_tail_matches(b"T", false, b"TT", None));
+        assert!(!insertion_tail_matches(b"TTGCAA", false, b"TTGCA", None));
+
+        // Clipped bases past the insertion must continue the reference
+        assert!(insertion_tail_matches(b"TTGCAGGAC", true, b"TTGCA", Some(b"GGACTT")));
+        assert!(!insertion_tail_matches(b"TTGCACCTG", true, b"TTGCA", Some(b"GGACTT")));
+        assert!(insertion_tail_matches(b"TTGCACCTG", true, b"TTGCA", None));
+    }
+
+    #[test]
+    fn test_unaligned_tail() {
+        use rust_htslib::bam::record::CigarString;
+
+        let mut record = Record::new();
+        let cigar = CigarString(vec![Cigar::Match(5), Cigar::SoftClip(3)]);
+        record.set(b"read1", Some(&cigar), b"ACGTATTG", &[30; 8]);
+        assert_eq!(unaligned_tail(&record, 5), Some((b"TTG".to_vec(), true)));
+        assert_eq!(unaligned_tail(&record, 4), None);
+
+        let cigar = CigarString(vec![Cigar::Match(5), Cigar::Ins(2), Cigar::HardClip(10)]);
+        record.set(b"read2", Some(&cigar), b"ACGTATT", &[30; 7]);
+        assert_eq!(unaligned_tail(&record, 5), Some((b"TT".to_vec(), false)));
+
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        record.set(b"read3", Some(&cigar), b"ACGTATTG", &[30; 8]);
+        assert_eq!(unaligned_tail(&record, 5), None);
+    }
+
+    #[test]
+    fn test_add_read_with_amplicon() {
+        let mut counts = AlleleCounts::new();
+        let amp1 = ReadContext { amplicon: Some("AMP_1"), ..ReadContext::default() };
+        let amp2 = ReadContext { amplicon: Some("AMP_2"), ..ReadContext::default() };
+
+        counts.add_read(ReadAllele::Ref, &amp1);
+        counts.add_read(ReadAllele::Alt("T".to_string()), &amp1);
+        counts.add_read(ReadAllele::SoftClippedAlt("T".to_string()), &amp2);
+        counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+
+        assert_eq!(counts.ref_count, 1);
+        assert_eq!(counts.get_alt_count("T"), 3);
+        assert_eq!(counts.get_alt_softclip_support("T"), 1);
+        assert_eq!(counts.total_count, 4);
+
+        assert_eq!(counts.amplicon_counts.len(), 2);
+        assert_eq!(counts.amplicon_counts["AMP_1"].ref_count, 1);
+        assert_eq!(counts.amplicon_counts["AMP_1"].alt_counts["T"], 1);
+        assert_eq!(counts.amplicon_counts["AMP_2"].ref_count, 0);
+        assert_eq!(counts.amplicon_counts["AMP_2"].alt_counts["T"], 1);
+    }
+
+    #[test]
+    fn test_add_read_orientation() {
+        let mut counts = AlleleCounts::new();
+        let f1r2 = ReadContext { orientation: Some(PairOrientation::F1R2), ..ReadContext::default() };
+        let f2r1 = ReadContext { orientation: Some(PairOrientation::F2R1), ..ReadContext::default() };
+
+        counts.add_read(ReadAllele::Ref, &f2r1);
+        counts.add_read(ReadAllele::Alt("T".to_string()), &f1r2);
+        counts.add_read(ReadAllele::Alt("T".to_string()), &f1r2);
+        counts.add_read(ReadAllele::SoftClippedAlt("T".to_string()), &f2r1);
+        counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+
+        let orientation = counts.get_alt_orientation("T");
+        assert_eq!(orientation, OrientationCounts { f1r2: 2, f2r1: 1 });
+        assert_eq!(orientation.bias(), Some(2.0 / 3.0));
+        assert_eq!(counts.get_alt_orientation("G").bias(), None);
+    }
+
+    #[test]
+    fn test_add_read_strand() {
+        let mut counts = AlleleCounts::new();
+        let reverse = ReadContext { reverse: true, ..ReadContext::default() };
+        for _ in 0..10 {
+            counts.add_read(ReadAllele::Ref, &ReadContext::default());
+            counts.add_read(ReadAllele::Ref, &reverse);
+            counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+        }
+        counts.add_read(ReadAllele::Other, &reverse);
+
+        let strand = counts.get_strand_counts("T");
+        assert_eq!(strand, StrandCounts { ref_forward: 10, ref_reverse: 10, alt_forward: 10, alt_reverse: 0 });
+        // ALT reads on one strand only: an artifact signal
+        assert!((strand.fisher_strand().unwrap() - 19.5885).abs() < 1e-3);
+        assert!(strand.strand_odds_ratio().unwrap() > 3.0);
+
+        let balanced = StrandCounts { ref_forward: 20, ref_reverse: 20, alt_forward: 5, alt_reverse: 5 };
+        assert_eq!(balanced.fisher_strand(), Some(0.0));
+        assert!(balanced.strand_odds_ratio().unwrap() < 1.0);
+        assert_eq!(counts.get_strand_counts("G").fisher_strand(), None);
+        assert_eq!(counts.get_strand_counts("G").strand_odds_ratio(), None);
+    }
+
+    #[test]
+    fn test_bisulfite_base_matching() {
+        assert!(BisulfiteStrand::Top.base_matches(b'T', b'C'));
+        assert!(!BisulfiteStrand::Top.base_matches(b'A', b'G'));
+        assert!(BisulfiteStrand::Bottom.base_matches(b'A', b'G'));
+        assert!(!BisulfiteStrand::Bottom.base_matches(b'T', b'C'));
+        assert!(BisulfiteStrand::Top.allele_matches(b"TTG", "CTG"));
+        assert!(!BisulfiteStrand::Top.allele_matches(b"TT", "CTG"));
+    }
+
+    #[test]
+    fn test_is_short_fragment() {
+        use rust_htslib::bam::record::CigarString;
+
+        let mut record = Record::new();
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        record.set(b"read1", Some(&cigar), b"ACGTACGT", &[30; 8]);
+        record.set_paired();
+        record.set_tid(0);
+        record.set_mtid(0);
+        record.set_insert_size(-6);
+        assert!(is_short_fragment(&record));
+
+        record.set_insert_size(300);
+        assert!(!is_short_fragment(&record));
+        record.set_insert_size(0);
+        assert!(!is_short_fragment(&record));
+
+        record.set_insert_size(6);
+        record.set_mtid(1);
+        assert!(!is_short_fragment(&record));
+        record.set_mtid(0);
+        record.set_mate_unmapped();
+        assert!(!is_short_fragment(&record));
+
+        let mut counts = AlleleCounts::new();
+        assert_eq!(counts.short_fragment_fraction(), 0.0);
+        counts.site_depth = 8;
+        counts.short_fragment_reads = 2;
+        assert_eq!(counts.short_fragment_fraction(), 0.25);
+        assert_eq!("Collapse".parse::<ShortFragmentPolicy>(), Ok(ShortFragmentPolicy::Collapse));
+    }
+
+    #[test]
+    fn test_bisulfite_strand_of_record() {
+        let mut record = Record::new();
+        assert_eq!(BisulfiteStrand::of_record(&record), BisulfiteStrand::Top);
+
+        record.set_reverse();
+        assert_eq!(BisulfiteStrand::of_record(&record), BisulfiteStrand::Bottom);
+
+        // A reverse-mapped second mate comes from the original top strand
+        record.set_paired();
+        record.set_last_in_template();
+        assert_eq!(BisulfiteStrand::of_record(&record), BisulfiteStrand::Top);
+    }
+
+    #[test]
+    fn test_classify_bisulfite() {
+        let variant = Variant::new("chr1".to_string(), 100, "C".to_string(), "T".to_string());
+        let alts = ["T"];
+
+        // A top-strand T at a C>T site may be a conversion: uninformative
+        assert_eq!(BamAnalyzer::classify_bisulfite(b"T", BisulfiteStrand::Top, &variant, &alts), None);
+        assert_eq!(
+            BamAnalyzer::classify_bisulfite(b"T", BisulfiteStrand::Bottom, &variant, &alts),
+            Some(ReadAllele::Alt("T".to_string()))
+        );
+        assert_eq!(
+            BamAnalyzer::classify_bisulfite(b"C", BisulfiteStrand::Top, &variant, &alts),
+            Some(ReadAllele::Ref)
+        );
+
+        // Converted REF base still supports REF when the ALT is unrelated
+        let variant = Variant::new("chr1".to_string(), 100, "C".to_string(), "G".to_string());
+        assert_eq!(
+            BamAnalyzer::classify_bisulfite(b"T", BisulfiteStrand::Top, &variant, &["G"]),
+            Some(ReadAllele::Ref)
+        );
+
+        // A base compatible with neither allele supports a third allele
+        assert_eq!(
+            BamAnalyzer::classify_bisulfite(b"A", BisulfiteStrand::Top, &variant, &["G"]),
+            Some(ReadAllele::Other)
+        );
+        assert_eq!(BamAnalyzer::classify_bisulfite(b"N", BisulfiteStrand::Top, &variant, &["G"]), None);
+    }
+
+    #[test]
+    fn test_other_allele_counts() {
+        let mut counts = AlleleCounts::new();
+        counts.add_read(ReadAllele::Ref, &ReadContext::default());
+        counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+        counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+        counts.add_read(ReadAllele::Other, &ReadContext::default());
+
+        assert_eq!(counts.other_count, 1);
+        assert_eq!(counts.total_count, 3);
+        assert_eq!(counts.get_vaf("T"), 2.0 / 3.0);
+        assert_eq!(counts.other_fraction(), 0.25);
+        assert_eq!(AlleleCounts::new().other_fraction(), 0.0);
+        assert!(is_called(b"ACgt"));
+        assert!(!is_called(b"AN"));
+    }
+
+    #[test]
+    fn test_deleted_reads() {
+        let mut counts = AlleleCounts::new();
+        counts.titration = vec![AlleleCounts::new()];
+        counts.add_read(ReadAllele::Ref, &ReadContext::default());
+        counts.add_read(ReadAllele::Alt("T".to_string()), &ReadContext::default());
+        counts.add_read(ReadAllele::Deleted, &ReadContext::default());
+        counts.add_read(ReadAllele::Deleted, &ReadContext::default());
+        counts.titration[0].add_read(ReadAllele::Deleted, &ReadContext::default());
+
+        assert_eq!((counts.deleted_at_site, counts.total_count), (2, 2));
+        assert_eq!(counts.get_vaf("T"), 0.5);
+        counts.count_deleted_reads();
+        assert_eq!(counts.total_count, 4);
+        assert_eq!(counts.get_vaf("T"), 0.25);
+        assert_eq!(counts.vaf("T", VafDefinition::RefAlt), 0.5);
+        assert_eq!(counts.titration[0].total_count, 1);
+        assert_eq!("Count".parse::<DeletedReadPolicy>(), Ok(DeletedReadPolicy::Count));
+        assert!("ref".parse::<DeletedReadPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_reader_limit() {
+        let limit = ReaderLimit::new(Some(2));
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    let _permit = limit.acquire();
+                    std::thread::sleep(Duration::from_millis(5));
+                });
+            }
+        });
+        assert!(limit.peak() >= 1 && limit.peak() <= 2);
+
+        // A limit of zero still lets one reader open
+        let _permit = ReaderLimit::new(Some(0)).acquire();
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        // Large retry numbers saturate rather than overflow
+        assert!(policy.backoff(40) >= policy.backoff(31));
+
+        assert!(RetryPolicy::is_retryable(&VlodError::Io(std::io::Error::other("stale file handle"))));
+        let error = VlodError::Io(std::io::Error::other("stale file handle")).context("reading chr1:100:A>T");
+        assert!(RetryPolicy::is_retryable(&error));
+        assert_eq!(error.to_string(), "reading chr1:100:A>T: IO error: stale file handle");
+        assert!(!RetryPolicy::is_retryable(&VlodError::InvalidVariant("Unknown chromosome: chrZ".to_string())));
+    }
+
     #[test]
     fn test_bam_analyzer_index_detection() {
         // Test with missing BAM file (should fail early)
@@ -306,6 +2184,7 @@ mod tests {
             assert!(msg.contains("BAM index file not found"));
             assert!(msg.contains(".bam.bai"));
             assert!(msg.contains(".bai"));
+            assert!(msg.contains(".bam.csi"));
         } else {
             panic!("Expected FileNotFound error");
         }
@@ -329,6 +2208,64 @@ mod tests {
         std::fs::remove_file(bai_path).ok();
     }
 
+    #[test]
+    fn test_bam_index_candidates() {
+        assert_eq!(
+            bam_index_candidates(Path::new("run.v2/sample.v2.bam")),
+            vec![
+                PathBuf::from("run.v2/sample.v2.bam.bai"),
+                PathBuf::from("run.v2/sample.v2.bai"),
+                PathBuf::from("run.v2/sample.v2.bam.csi"),
+                PathBuf::from("run.v2/sample.v2.csi"),
+            ]
+        );
+        // Without a .bam extension the name is never truncated at its last dot
+        assert_eq!(
+            bam_index_candidates(Path::new("sample.v2")),
+            vec![PathBuf::from("sample.v2.bai"), PathBuf::from("sample.v2.csi")]
+        );
+    }
+
+    #[test]
+    fn test_fetch_past_bai_limit_with_csi_index() {
+        use rust_htslib::bam::{
+            header::{Header, HeaderRecord},
+            record::CigarString,
+        };
+
+        // A read past 2^29, which only a CSI index can locate
+        let dir = tempfile::tempdir().unwrap();
+        let bam_path = dir.path().join("long_contig.bam");
+        let mut header = Header::new();
+        let mut sq = HeaderRecord::new(b"SQ");
+        sq.push_tag(b"SN", "chr1");
+        sq.push_tag(b"LN", 1u64 << 31);
+        header.push_record(&sq);
+        let start = (1u64 << 29) + 1000;
+        let format = rust_htslib::bam::Format::Bam;
+        let mut writer = rust_htslib::bam::Writer::from_path(&bam_path, &header, format).unwrap();
+        for read in 0..10 {
+            let bases = if read < 4 { b"AAAAATAAAA" } else { b"AAAAAAAAAA" };
+            let mut record = Record::new();
+            let qname = format!("read{}", read);
+            record.set(qname.as_bytes(), Some(&CigarString(vec![Cigar::Match(10)])), bases, &[30; 10]);
+            record.set_tid(0);
+            record.set_pos(start as i64);
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            record.set_mapq(60);
+            writer.write(&record).unwrap();
+        }
+        drop(writer);
+        rust_htslib::bam::index::build(&bam_path, None, rust_htslib::bam::index::Type::Csi(14), 1).unwrap();
+        assert!(append_extension(&bam_path, "csi").exists());
+
+        let variant = Variant::new("chr1".to_string(), start + 6, "A".to_string(), "T".to_string());
+        let counts = BamAnalyzer::new(&bam_path).unwrap().analyze_variant(&variant).unwrap();
+        assert_eq!(counts.ref_count, 6);
+        assert_eq!(counts.get_alt_count("T"), 4);
+    }
+
     #[test]
     fn test_bam_analyzer_with_bai_only_extension() {
         // Create a temporary BAM file
@@ -346,4 +2283,189 @@ mod tests {
         // Clean up
         std::fs::remove_file(bai_path).ok();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_missing_quality_policy() {
+        assert_eq!("exclude".parse(), Ok(MissingQualityPolicy::Exclude));
+        assert_eq!("default:20".parse(), Ok(MissingQualityPolicy::Default(20)));
+        assert_eq!(MissingQualityPolicy::default().to_string(), "default:30");
+        assert!("default".parse::<MissingQualityPolicy>().is_err());
+        assert!("default:255".parse::<MissingQualityPolicy>().is_err());
+
+        let mut record = Record::new();
+        record.set(b"read1", None, b"ACGT", &[10, 20, 30, 40]);
+        assert!(!has_missing_quality(&record));
+        assert_eq!(base_quality(&record, 1, 30), 20);
+
+        // QUAL `*` is stored as 0xff for every base
+        record.set(b"read2", None, b"ACGT", &[MISSING_BASE_QUALITY; 4]);
+        assert!(has_missing_quality(&record));
+        assert_eq!(base_quality(&record, 1, 25), 25);
+    }
+
+    #[test]
+    fn test_fetch_groups() {
+        let locus = |chrom: &str, pos: u64| Ok(Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string()));
+        let loci = vec![
+            locus("chr1", 100),
+            locus("chr1", 250),
+            locus("chr1", 500),
+            Err("contig not in BAM".to_string()),
+            locus("chr1", 520),
+            locus("chr2", 530),
+            locus("chr2", 520),
+        ];
+        assert_eq!(fetch_groups(&loci, 200), vec![0..2, 2..3, 3..4, 4..5, 5..6, 6..7]);
+        assert_eq!(fetch_groups(&loci, 1000), vec![0..3, 3..4, 4..5, 5..6, 6..7]);
+        assert_eq!(fetch_groups(&loci, 0).len(), loci.len());
+        assert!(fetch_groups(&[], 200).is_empty());
+    }
+
+    #[test]
+    fn test_base_counts_use_read_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let site = &data.expected[0].0;
+        let pos = site.pos - 1;
+
+        let counts = BamAnalyzer::new(&data.bam).unwrap().base_counts(&site.chrom, pos).unwrap();
+        assert_eq!(counts.iter().sum::<u32>(), 100);
+
+        // Background counts come from the reads and bases counted at variants
+        let configs = [
+            LodConfig { min_mapping_quality: 61, ..LodConfig::default() },
+            LodConfig { min_base_quality: 31, ..LodConfig::default() },
+        ];
+        for config in &configs {
+            let mut analyzer = BamAnalyzer::new(&data.bam).unwrap().with_config(config);
+            assert_eq!(analyzer.base_counts(&site.chrom, pos).unwrap(), [0; 4]);
+            assert_eq!(analyzer.analyze_variant(site).unwrap().site_depth, 0);
+        }
+    }
+
+    #[test]
+    fn test_analyze_variants_in_one_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let site = &data.expected[0].0;
+
+        // A hotspot panel: ten variants three bases apart around the first site,
+        // and the second site far downstream
+        let mut panel: Vec<Variant> = (0..10)
+            .map(|i| {
+                let pos = site.pos - 15 + 3 * i;
+                Variant::new(site.chrom.clone(), pos, site.ref_allele.clone(), site.alt_allele.clone())
+            })
+            .collect();
+        panel.push(data.expected[1].0.clone());
+
+        let mut single = BamAnalyzer::new(&data.bam).unwrap();
+        let expected: Vec<AlleleCounts> = panel.iter().map(|variant| single.analyze_variant(variant).unwrap()).collect();
+        assert_eq!(single.fetches(), panel.len() as u64);
+
+        let mut batched = BamAnalyzer::new(&data.bam).unwrap();
+        let counts = batched.analyze_variants(&panel).unwrap();
+        assert_eq!(batched.fetches(), 1);
+        assert_eq!(counts.len(), panel.len());
+        for (counts, expected) in counts.iter().zip(&expected) {
+            assert_eq!(counts.ref_count, expected.ref_count);
+            assert_eq!(counts.alt_counts, expected.alt_counts);
+            assert_eq!(counts.other_count, expected.other_count);
+            assert_eq!(counts.site_depth, expected.site_depth);
+        }
+        assert_eq!(counts[5].get_alt_count(&site.alt_allele), 20);
+        assert_eq!(counts[10].get_alt_count(&panel[10].alt_allele), 1);
+    }
+
+    #[test]
+    fn test_site_read_from_record() {
+        use rust_htslib::bam::record::CigarString;
+
+        let cigar = CigarString(vec![Cigar::Match(5), Cigar::Ins(2), Cigar::Match(3), Cigar::Del(2), Cigar::Match(4)]);
+        let mut record = Record::new();
+        record.set(b"read1", Some(&cigar), b"ACGTACCGTAACGT", &[30; 14]);
+        record.set_pos(100);
+        let at = |pos: i64| SiteRead::from_record(record.clone(), pos);
+
+        assert!(at(99).is_none());
+        assert!(at(114).is_none());
+        let read = at(100).unwrap();
+        assert_eq!((read.qpos, read.is_del, read.indel), (Some(0), false, Indel::None));
+        assert_eq!(at(104).unwrap().indel, Indel::Ins(2));
+        assert_eq!(at(105).unwrap().qpos, Some(7));
+        assert_eq!(at(107).unwrap().indel, Indel::Del(2));
+        let deleted = at(108).unwrap();
+        assert_eq!((deleted.qpos, deleted.is_del), (None, true));
+        assert_eq!(at(110).unwrap().qpos, Some(10));
+    }
+
+    #[test]
+    fn test_min_mapping_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let site = data.expected[0].0.clone();
+        let count = |min_mapping_quality: u8| {
+            let config = LodConfig { min_mapping_quality, ..LodConfig::default() };
+            BamAnalyzer::new(&data.bam).unwrap().with_config(&config).analyze_variant(&site).unwrap()
+        };
+
+        // The synthetic reads all have MAPQ 60
+        let full = count(60);
+        assert!(full.site_depth > 0);
+        assert_eq!(full.get_alt_count(&site.alt_allele), 20);
+        let filtered = count(61);
+        assert_eq!(filtered.site_depth, 0);
+        assert_eq!(filtered.total_count, 0);
+    }
+
+    #[test]
+    fn test_index_load_estimates() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let site = &data.expected[0].0;
+        let analyzer = BamAnalyzer::new(&data.bam).unwrap();
+        let loads = analyzer.index_load_estimates(&[(site.chrom.as_str(), site.pos), ("chrUnknown", 100)]).unwrap();
+        assert!(loads[0] >= 1);
+        assert_eq!(loads[1], 1);
+    }
+
+    #[test]
+    fn test_depth_cap_policy() {
+        assert_eq!("iterate".parse(), Ok(DepthCapPolicy::Iterate));
+        assert_eq!("Flag".parse(), Ok(DepthCapPolicy::Flag));
+        assert!("truncate".parse::<DepthCapPolicy>().is_err());
+        assert_eq!(DepthCapPolicy::default().to_string(), "iterate");
+
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let site = data.expected[0].0.clone();
+        let full = BamAnalyzer::new(&data.bam).unwrap().analyze_variant(&site).unwrap();
+        assert!(!full.depth_capped);
+
+        // Sites deeper than the cap are counted by read iteration...
+        let config = LodConfig { max_pileup_depth: 50, ..LodConfig::default() };
+        let mut analyzer = BamAnalyzer::new(&data.bam).unwrap().with_config(&config);
+        let iterated = analyzer.analyze_variant(&site).unwrap();
+        assert_eq!(analyzer.fetches(), 2);
+        assert!(!iterated.depth_capped);
+        assert_eq!(iterated.ref_count, full.ref_count);
+        assert_eq!(iterated.alt_counts, full.alt_counts);
+        assert_eq!(iterated.site_depth, full.site_depth);
+
+        // ...or keep the partial pileup counts, flagged
+        let config = LodConfig { depth_cap: DepthCapPolicy::Flag, ..config };
+        let capped = BamAnalyzer::new(&data.bam).unwrap().with_config(&config).analyze_variant(&site).unwrap();
+        assert!(capped.depth_capped);
+        assert!(capped.site_depth < full.site_depth);
+
+        // Region depths follow the same cap
+        let pos = site.pos - 1;
+        let depths = |config: &LodConfig| {
+            let mut analyzer = BamAnalyzer::new(&data.bam).unwrap().with_config(config);
+            analyzer.region_depths(&site.chrom, pos, pos + 1).unwrap()[0]
+        };
+        let iterate = LodConfig { depth_cap: DepthCapPolicy::Iterate, ..config.clone() };
+        assert_eq!(depths(&iterate), full.site_depth);
+        assert!(depths(&config) < full.site_depth);
+    }
+}
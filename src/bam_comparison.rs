@@ -0,0 +1,200 @@
+//! Comparison of one variant set scored against two BAMs (e.g. an old and a new
+//! library prep of the same sample): per-variant score and coverage deltas, and a
+//! summary of the variants whose classification changed
+
+use crate::{about::about_comment, utils::create_output_file, DetectabilityCondition, DetectabilityResult, VlodResult};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Score of a variant against one of the BAMs
+#[derive(Debug, Clone, PartialEq)]
+pub struct BamScore {
+    pub condition: DetectabilityCondition,
+    pub score: f64,
+    pub coverage: u32,
+}
+
+impl From<&DetectabilityResult> for BamScore {
+    fn from(result: &DetectabilityResult) -> Self {
+        BamScore {
+            condition: result.detectability_condition.clone(),
+            score: result.detectability_score,
+            coverage: result.coverage,
+        }
+    }
+}
+
+/// One variant scored against both BAMs
+#[derive(Debug, Clone, PartialEq)]
+pub struct BamDelta {
+    pub key: (String, u64, String, String),
+    pub old: BamScore,
+    pub new: BamScore,
+}
+
+impl BamDelta {
+    /// New score minus old score
+    pub fn score_delta(&self) -> f64 {
+        self.new.score - self.old.score
+    }
+
+    /// New coverage minus old coverage
+    pub fn coverage_delta(&self) -> i64 {
+        self.new.coverage as i64 - self.old.coverage as i64
+    }
+
+    /// Whether the classification differs between the BAMs
+    pub fn changed(&self) -> bool {
+        self.old.condition != self.new.condition
+    }
+}
+
+/// Pair the results of the two BAMs by variant, in the order of the old results;
+/// variants scored against only one BAM are left out
+pub fn compare_bam_results(old: &[DetectabilityResult], new: &[DetectabilityResult]) -> Vec<BamDelta> {
+    let key = |result: &DetectabilityResult| {
+        let variant = &result.variant;
+        (variant.chrom.clone(), variant.pos, variant.ref_allele.clone(), variant.alt_allele.clone())
+    };
+    let new: HashMap<_, _> = new.iter().map(|result| (key(result), result)).collect();
+    old.iter()
+        .filter_map(|old| {
+            let key = key(old);
+            let new = BamScore::from(*new.get(&key)?);
+            Some(BamDelta { key, old: BamScore::from(old), new })
+        })
+        .collect()
+}
+
+/// Classification changes between the BAMs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BamComparisonSummary {
+    pub variants: usize,
+    /// Variants whose classification changed
+    pub changed: usize,
+    /// Variants detectable only with the new BAM
+    pub gained: usize,
+    /// Variants detectable only with the old BAM
+    pub lost: usize,
+    pub mean_score_delta: f64,
+    pub mean_coverage_delta: f64,
+    /// Changed variants per (old, new) classification
+    pub transitions: BTreeMap<(String, String), usize>,
+}
+
+impl BamComparisonSummary {
+    pub fn new(deltas: &[BamDelta]) -> Self {
+        let mut summary = BamComparisonSummary { variants: deltas.len(), ..Self::default() };
+        let finite: Vec<f64> = deltas.iter().map(BamDelta::score_delta).filter(|delta| delta.is_finite()).collect();
+        if !finite.is_empty() {
+            summary.mean_score_delta = finite.iter().sum::<f64>() / finite.len() as f64;
+        }
+        if !deltas.is_empty() {
+            let total: i64 = deltas.iter().map(BamDelta::coverage_delta).sum();
+            summary.mean_coverage_delta = total as f64 / deltas.len() as f64;
+        }
+        for delta in deltas.iter().filter(|delta| delta.changed()) {
+            summary.changed += 1;
+            match (delta.old.condition.is_detectable(), delta.new.condition.is_detectable()) {
+                (false, true) => summary.gained += 1,
+                (true, false) => summary.lost += 1,
+                _ => {}
+            }
+            let transition = (delta.old.condition.to_string(), delta.new.condition.to_string());
+            *summary.transitions.entry(transition).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    pub fn log(&self) {
+        log::info!("BAM comparison over {} variants:", self.variants);
+        log::info!("  Mean score delta: {:.3}", self.mean_score_delta);
+        log::info!("  Mean coverage delta: {:.1}", self.mean_coverage_delta);
+        log::info!("  Classification changed: {}", self.changed);
+        log::info!("  Detectable only with the new BAM: {}", self.gained);
+        log::info!("  Detectable only with the old BAM: {}", self.lost);
+        for ((old, new), count) in &self.transitions {
+            log::info!("  {} -> {}: {}", old, new, count);
+        }
+    }
+}
+
+/// Write the per-variant deltas as TSV
+pub fn write_bam_comparison<P: AsRef<Path>>(deltas: &[BamDelta], path: P) -> VlodResult<()> {
+    write_bam_comparison_to_writer(deltas, BufWriter::new(create_output_file(path)?))
+}
+
+/// Write the per-variant deltas as TSV to any writer
+pub fn write_bam_comparison_to_writer<W: Write>(deltas: &[BamDelta], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "Chrom\tPos\tRef\tAlt\tOld_Condition\tOld_Score\tOld_Coverage\tNew_Condition\tNew_Score\tNew_Coverage\t\
+         Score_Delta\tCoverage_Delta\tChanged"
+    )?;
+    for delta in deltas {
+        let (chrom, pos, ref_allele, alt_allele) = &delta.key;
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            chrom,
+            pos,
+            ref_allele,
+            alt_allele,
+            delta.old.condition,
+            delta.old.score,
+            delta.old.coverage,
+            delta.new.condition,
+            delta.new.score,
+            delta.new.coverage,
+            delta.score_delta(),
+            delta.coverage_delta(),
+            if delta.changed() { "yes" } else { "no" }
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variant;
+
+    fn result(pos: u64, score: f64, condition: DetectabilityCondition, coverage: u32) -> DetectabilityResult {
+        let variant = Variant::new("chr1".to_string(), pos, "A".to_string(), "T".to_string());
+        DetectabilityResult::new(variant, score, condition, coverage, 0)
+    }
+
+    #[test]
+    fn test_compare_bam_results() {
+        let old = vec![
+            result(100, 2.0, DetectabilityCondition::Detectable, 100),
+            result(200, -1.0, DetectabilityCondition::NonDetectable, 20),
+            result(300, 1.0, DetectabilityCondition::Detectable, 50),
+            result(400, 1.0, DetectabilityCondition::Detectable, 50),
+        ];
+        let new = vec![
+            result(300, -0.5, DetectabilityCondition::NonDetectable, 30),
+            result(200, 1.5, DetectabilityCondition::Detectable, 80),
+            result(100, 2.5, DetectabilityCondition::Detectable, 120),
+        ];
+        let deltas = compare_bam_results(&old, &new);
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(deltas[1].key.1, 200);
+        assert_eq!((deltas[1].score_delta(), deltas[1].coverage_delta()), (2.5, 60));
+
+        let summary = BamComparisonSummary::new(&deltas);
+        assert_eq!((summary.variants, summary.changed, summary.gained, summary.lost), (3, 2, 1, 1));
+        assert!((summary.mean_score_delta - 0.5).abs() < 1e-9);
+        assert!((summary.mean_coverage_delta - 20.0).abs() < 1e-9);
+        assert_eq!(summary.transitions.values().sum::<usize>(), 2);
+
+        let mut report = Vec::new();
+        write_bam_comparison_to_writer(&deltas, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(report.lines().count(), 5);
+        assert!(report.lines().nth(2).unwrap().ends_with("\t0.5\t20\tno"));
+    }
+}
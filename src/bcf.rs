@@ -0,0 +1,90 @@
+//! BCF input and output. BCF records are not handled natively: a BCF input is
+//! decoded with `bcf::Reader` into a scratch VCF, which the line-based readers and
+//! the merge read (the input is read more than once, so it is decoded once to
+//! disk), and a BCF output is written as a scratch VCF then encoded with
+//! `bcf::Writer`. Both conversions go through htslib record by record, so the
+//! header and every field are carried over as htslib parses them; the cost is one
+//! extra pass over the records and a scratch file the size of the decoded VCF.
+
+use crate::{utils::is_gzipped, VlodResult};
+use flate2::read::MultiGzDecoder;
+use rust_htslib::bcf::{self, Read as BcfRead};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Magic bytes opening a BCF file once decompressed
+const BCF_MAGIC: &[u8; 3] = b"BCF";
+
+/// Whether a file is BCF, compressed or not, by its content
+pub fn is_bcf<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
+    let file = File::open(&path)?;
+    let mut magic = Vec::with_capacity(BCF_MAGIC.len());
+    if is_gzipped(&path)? {
+        MultiGzDecoder::new(file).take(BCF_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    } else {
+        file.take(BCF_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    }
+    Ok(magic == BCF_MAGIC)
+}
+
+/// Whether an output path asks for BCF (a `.bcf` extension)
+pub fn is_bcf_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("bcf"))
+}
+
+/// Decode a BCF (or any VCF htslib reads) to uncompressed VCF text
+pub fn bcf_to_vcf<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> VlodResult<usize> {
+    convert(input.as_ref(), output.as_ref(), bcf::Format::Vcf, true)
+}
+
+/// Encode a VCF as compressed BCF
+pub fn vcf_to_bcf<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> VlodResult<usize> {
+    convert(input.as_ref(), output.as_ref(), bcf::Format::Bcf, false)
+}
+
+/// Copy every record of `input` to `output` in another format; returns the
+/// number of records
+fn convert(input: &Path, output: &Path, format: bcf::Format, uncompressed: bool) -> VlodResult<usize> {
+    let mut reader = bcf::Reader::from_path(input)?;
+    let header = bcf::Header::from_template(reader.header());
+    let mut writer = bcf::Writer::from_path(output, &header, uncompressed, format)?;
+    let mut records = 0;
+    for record in reader.records() {
+        let mut record = record?;
+        writer.translate(&mut record);
+        writer.write(&record)?;
+        records += 1;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcf_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let vcf = dir.path().join("calls.vcf");
+        let text = "##fileformat=VCFv4.2\n##FILTER=<ID=PASS,Description=\"All filters passed\">\n\
+                    ##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                    ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n##contig=<ID=chr1,length=1000>\n\
+                    #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tTUMOR\n\
+                    chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\tGT\t0/1\nchr1\t200\t.\tC\tG\t.\tPASS\tDP=12\tGT\t1/1\n";
+        std::fs::write(&vcf, text).unwrap();
+
+        let bcf = dir.path().join("calls.bcf");
+        assert_eq!(vcf_to_bcf(&vcf, &bcf).unwrap(), 2);
+        assert!(is_bcf(&bcf).unwrap());
+        assert!(!is_bcf(&vcf).unwrap());
+        assert!(is_bcf_path(&bcf) && !is_bcf_path(&vcf));
+
+        let decoded = dir.path().join("decoded.vcf");
+        assert_eq!(bcf_to_vcf(&bcf, &decoded).unwrap(), 2);
+        let decoded = std::fs::read_to_string(decoded).unwrap();
+        for line in text.lines().filter(|line| !line.starts_with("##fileformat")) {
+            assert!(decoded.lines().any(|decoded| decoded == line), "{} was not preserved", line);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Concordance check of the Rust pipeline against results of the original Python
+//! vLoD (LOD_edit.py) on the same VCF and BAM
+
+use clap::Parser;
+use env_logger::Env;
+use std::path::PathBuf;
+use vlod_rs::{
+    compare::{
+        compare_results, read_compared_results, results_map, write_difference_report, DifferenceKind,
+        ScoreTolerance,
+    },
+    lod::{calculate_detectability_scores, validate_lod_config},
+    utils::{get_num_cpus, validate_file_readable, Timer},
+    vcf::{dedup_variants, read_vcf_variants, DuplicatePolicy},
+    LodConfig, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
+};
+
+#[derive(Parser)]
+#[command(name = "compat_test")]
+#[command(about = "Compare Rust vLoD results with the output of the Python vLoD")]
+#[command(long_about = "
+Runs the Rust detectability analysis on a VCF and BAM and compares it, variant
+by variant, with the TSV the Python LOD_edit.py produced for the same inputs.
+Give both runs the same TP, FP and SE.
+
+Every variant whose classification flips, whose score differs beyond the
+tolerance, or that only one implementation reported is written to the report
+TSV. Scores agree when they are within either the absolute or the relative
+tolerance, absorbing differences in float formatting.
+
+The exit status is 0 when the results are concordant and 2 when they are not.
+")]
+struct Args {
+    /// Path to the input VCF file
+    #[arg(long, value_name = "FILE")]
+    input_vcf: PathBuf,
+
+    /// Path to the input BAM file
+    #[arg(long, value_name = "FILE")]
+    input_bam: PathBuf,
+
+    /// Results TSV written by the Python vLoD for the same VCF and BAM
+    #[arg(long, value_name = "FILE")]
+    python_results: PathBuf,
+
+    /// Path to the difference report TSV
+    #[arg(long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Probability of true positive result
+    #[arg(long = "TP", default_value = "0.999")]
+    tp: f64,
+
+    /// Probability of false positive result
+    #[arg(long = "FP", default_value = "0.001")]
+    fp: f64,
+
+    /// Probability of sequencing error
+    #[arg(long = "SE", default_value = "0.0001")]
+    se: f64,
+
+    /// Score at or above which a variant is detectable (the Python vLoD's cutoff)
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Absolute score tolerance
+    #[arg(long, default_value = "1e-6")]
+    score_tolerance: f64,
+
+    /// Relative score tolerance
+    #[arg(long, default_value = "1e-6")]
+    relative_tolerance: f64,
+
+    /// Number of processes to use for parallel processing
+    #[arg(long, default_value_t = get_num_cpus())]
+    num_processes: usize,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+/// Returns whether the two implementations agree
+fn run() -> VlodResult<bool> {
+    let args = Args::parse();
+
+    let log_level = if args.debug {
+        "debug"
+    } else if args.verbose {
+        "info"
+    } else {
+        "warn"
+    };
+    env_logger::Builder::from_env(Env::default().default_filter_or(log_level))
+        .format_timestamp_secs()
+        .init();
+
+    validate_file_readable(&args.input_vcf)?;
+    validate_file_readable(&args.input_bam)?;
+    validate_file_readable(&args.python_results)?;
+
+    if args.output.exists() && !args.force {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", args.output),
+        )));
+    }
+
+    let config = LodConfig {
+        p_tp: args.tp,
+        p_fp: args.fp,
+        p_se: args.se,
+        score_threshold: args.threshold,
+        ..LodConfig::default()
+    };
+    validate_lod_config(&config)?;
+    let tolerance = ScoreTolerance {
+        absolute: args.score_tolerance,
+        relative: args.relative_tolerance,
+    };
+
+    let _timer = Timer::new("Running the Rust pipeline");
+    let (variants, _) = dedup_variants(read_vcf_variants(&args.input_vcf)?, DuplicatePolicy::First)?;
+    let rust_results = calculate_detectability_scores(variants, &args.input_bam, &config, args.num_processes)?;
+    let python_results = read_compared_results(&args.python_results)?;
+
+    let comparison = compare_results(&python_results, &results_map(&rust_results), &tolerance);
+    comparison.log_summary("Python", "Rust");
+    write_difference_report(&comparison, ("Python", "Rust"), &args.output)?;
+
+    println!(
+        "{} variants compared: {} classification flips, {} score deltas beyond tolerance \
+         (largest {:.6}), {} only in Python, {} only in Rust",
+        comparison.compared,
+        comparison.count(DifferenceKind::Flip),
+        comparison.count(DifferenceKind::Score),
+        comparison.max_score_delta(),
+        comparison.count(DifferenceKind::OnlyOld),
+        comparison.count(DifferenceKind::OnlyNew)
+    );
+    println!("Difference report written to: {:?}", args.output);
+
+    Ok(comparison.is_concordant())
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(2),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
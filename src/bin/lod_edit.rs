@@ -2,12 +2,51 @@
 
 use clap::Parser;
 use env_logger::Env;
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use vlod_rs::{
-    lod::{calculate_detectability_scores, validate_lod_config, write_detectability_results},
-    utils::{get_num_cpus, validate_file_readable, Timer},
-    vcf::read_vcf_variants,
-    LodConfig, VlodError, VlodResult,
+    annotation::{AnnotationTable, SignificanceSummary, CLINVAR_SIGNIFICANCE_FIELD},
+    claims::{log_claim_verdicts, ClaimSet, CLAIMS_FAILED_EXIT_CODE},
+    bam::{
+        bam_contig_order, sample_background_noise, DeletedReadPolicy,
+        DepthCapPolicy, MissingQualityPolicy, RetryPolicy, ShortFragmentPolicy, VafDefinition, DEFAULT_MAX_PILEUP_DEPTH,
+    },
+    calibration::{read_truth_set, Calibration},
+    confirmation::RefConfirmation,
+    contig::{AltContigMap, ContigPolicy},
+    gtf::{gene_regions, read_exons, read_transcripts},
+    hgvs::{hgvs_by_variant, read_hgvs_hotspots},
+    integrity::write_checksum_sidecar,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    lod::{
+        calculate_detectability_scores, io_retries, log_filter_stratified_summary, validate_lod_config,
+        with_site_aggregates, write_detectability_columns, ColumnSelection, ResultsLayout,
+    },
+    noise::{sample_positions, NOISE_SAMPLING_SEED},
+    pgcopy::{is_valid_table_name, pg_ddl_path, write_pgcopy_results, ResultsFormat, DEFAULT_PG_TABLE},
+    pool::PoolDesign,
+    presets::{resolve_preset, Preset, PresetChoice},
+    read_filter::ReadFilter,
+    reference::{log_ref_mismatches, ReferenceFasta},
+    regions::{read_regions, retain_in_regions, write_bed_regions, AmpliconSet},
+    review::{rank_for_review, write_review_list, ReviewOptions, ReviewPriorities, ReviewRanking},
+    rollup::{rollup_by_feature, write_rollup},
+    sample::{resolve_sample_name, SampleName},
+    summary::{multiqc_path, write_multiqc, RunSummary},
+    sweep::{sweep_thresholds, write_sweep_results, ThresholdSweep},
+    titration::{titration_fractions, write_titration_results},
+    uniformity::{measure_uniformity, DEFAULT_COVERAGE_THRESHOLDS},
+    utils::{append_extension, ensure_parent_dirs, get_num_cpus, validate_file_readable, Timer, DEFAULT_MAX_LINE_LENGTH},
+    vcf::{
+        apply_monomorphic_policy, check_sort_order, Caller, dedup_variants, read_vcf_input,
+        select_pass_variants, sort_variants, DuplicatePolicy, MonomorphicPolicy, VcfReadLimits,
+    },
+    verify::{validate_verify_fraction, verify_counts, VERIFY_SAMPLING_SEED},
+    warnings::WarningKind,
+    DetectabilityResult, LodConfig, Variant, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
+    DEFAULT_SEQUENCING_ERROR_RATE,
 };
 
 #[derive(Parser)]
@@ -20,11 +59,20 @@ for each variant based on variant allele frequency (VAF) and statistical
 parameters including true positive rate, false positive rate, and sequencing
 error rate.
 
-The BAM index file (.bai) must be present next to the BAM file. The tool will
-automatically look for files with .bam.bai or .bai extensions.
+The BAM index file (.bai, or .csi for contigs longer than 2^29 bases) must be
+present next to the BAM file. The tool will automatically look for files with
+.bam.bai, .bai, .bam.csi or .csi extensions.
 
 The output is a TSV file containing detectability scores and classifications
 for each variant, along with coverage and read count information.
+
+With --pool-size, the Pool_Alleles column estimates how many of the pool's 2N
+alleles carry each variant (e.g. 3/40), and Pool_Power gives the probability
+of detecting a single-copy allele at the site's coverage.
+
+VCF records may override the model parameters for themselves (e.g. at known
+noisy hotspots) with VLOD_SE, VLOD_TP and VLOD_THRESHOLD INFO tags; the applied
+values are echoed in the Overrides column.
 ")]
 struct Args {
     /// Path to the input VCF file
@@ -39,6 +87,32 @@ struct Args {
     #[arg(long, value_name = "FILE")]
     output: PathBuf,
 
+    /// Format of --output: the detectability TSV, or pgcopy for PostgreSQL bulk
+    /// loading (COPY text format with typed NULLs, plus the table DDL in <output>.sql)
+    #[arg(long, default_value = "tsv")]
+    output_format: ResultsFormat,
+
+    /// Table created by the pgcopy DDL (optionally schema-qualified)
+    #[arg(long, default_value = DEFAULT_PG_TABLE)]
+    pg_table: String,
+
+    /// Comma-separated TSV columns to write, in order (e.g.
+    /// chrom,pos,ref,alt,score,coverage,vaf,strand_bias); all columns by default
+    #[arg(long, value_name = "COLUMNS")]
+    columns: Option<ColumnSelection>,
+
+    /// Shape of the per-amplicon read support in the TSV: packed (one
+    /// Amplicon_Support column), long (one row per variant and amplicon) or wide
+    /// (read-count columns per amplicon)
+    #[arg(long, default_value = "packed")]
+    layout: ResultsLayout,
+
+    /// Add a site-level row after the ALT rows of each multi-allelic site, with
+    /// the ALTs joined by commas: Detectable if any ALT is, the highest score and
+    /// the ALT reads combined
+    #[arg(long)]
+    site_aggregates: bool,
+
     /// Probability of true positive result
     #[arg(long = "TP", default_value = "0.999")]
     tp: f64,
@@ -47,9 +121,360 @@ struct Args {
     #[arg(long = "FP", default_value = "0.001")]
     fp: f64,
 
-    /// Probability of sequencing error
-    #[arg(long = "SE", default_value = "0.0001")]
-    se: f64,
+    /// Probability of sequencing error [default: 0.0001, or the aligner preset's]
+    #[arg(long = "SE")]
+    se: Option<f64>,
+
+    /// Score at or above which a variant is detectable (calibrated class thresholds
+    /// and VLOD_THRESHOLD overrides still take precedence)
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Aligner preset setting the sequencing error rate and read filters: auto
+    /// (detected from the BAM @PG lines), none, bwa, dragen, minimap2-sr,
+    /// minimap2-hifi, minimap2-ont or star. --SE and --read-filter still apply.
+    #[arg(long, default_value = "auto")]
+    preset: PresetChoice,
+
+    /// VAF scored: total (ALT reads over all reads assigned to an allele, including
+    /// other ALTs) or ref-alt (ALT reads over REF plus ALT reads). Both are reported.
+    #[arg(long, default_value = "total")]
+    vaf_definition: VafDefinition,
+
+    /// Report sites whose reads support a third allele (neither REF nor ALT) above
+    /// this fraction as Not-assessable instead of Detectable
+    #[arg(long, value_name = "FRACTION")]
+    max_other_allele_fraction: Option<f64>,
+
+    /// Reads carrying a deletion over an SNV or MNV site: exclude them from the
+    /// depth, or count them toward it as reads not supporting the ALT. They are
+    /// reported either way.
+    #[arg(long, default_value = "exclude")]
+    deleted_reads: DeletedReadPolicy,
+
+    /// Reads from fragments shorter than the read, whose mates overlap completely
+    /// (adapter read-through in degraded cfDNA): keep both mates, collapse them to
+    /// one read, or exclude them. Their fraction is reported either way.
+    #[arg(long, default_value = "keep")]
+    short_fragments: ShortFragmentPolicy,
+
+    /// Minimum base quality of the read bases at an SNV or MNV: a read with any base
+    /// of the site below it is left out of the site's counts entirely. Indel sites
+    /// are not filtered. 0 counts every read.
+    #[arg(long, default_value_t = 0)]
+    min_base_quality: u8,
+
+    /// Minimum mapping quality (MAPQ) of a read; reads below it count towards
+    /// neither the depth nor any allele (0 counts every read)
+    #[arg(long, default_value_t = 0)]
+    min_mapping_quality: u8,
+
+    /// Reads without base qualities (QUAL `*`): count them, giving their bases
+    /// quality Q (`default:Q`), or exclude them. Their number is reported in the
+    /// run summary either way.
+    #[arg(long, default_value = "default:30")]
+    missing_quality: MissingQualityPolicy,
+
+    /// Measure only the depth of each site, without classifying reads into alleles,
+    /// and report the minimum VAF detectable at that depth (Coverage-only condition)
+    #[arg(long)]
+    coverage_only: bool,
+
+    /// Named amplicons (BED, Picard interval_list or `chr1:100-200 NAME` lines);
+    /// splits read support by amplicon of origin
+    #[arg(long, value_name = "FILE")]
+    amplicon_bed: Option<PathBuf>,
+
+    /// Bisulfite/EM-seq mode: ignore C>T (G>A on the reverse strand) conversions
+    #[arg(long)]
+    bisulfite: bool,
+
+    /// FFPE mode: raise the error prior of low-VAF C>T/G>A changes so deamination
+    /// artifacts do not count as detectable support
+    #[arg(long)]
+    ffpe: bool,
+
+    /// Read filter on a BAM aux tag, repeatable: no:TAG, has:TAG, min:TAG:VALUE or
+    /// max:TAG:VALUE (e.g. --read-filter no:SA --read-filter min:AS:50)
+    #[arg(long, value_name = "FILTER")]
+    read_filter: Vec<ReadFilter>,
+
+    /// Pooled design of N diploid samples: a single-copy allele (VAF 1/(2N)) counts
+    /// as detectable, and the power to detect one is reported per variant
+    #[arg(long, value_name = "N")]
+    pool_size: Option<u32>,
+
+    /// Write detectability at downsampled read fractions to this CSV file
+    #[arg(long, value_name = "FILE")]
+    titration_output: Option<PathBuf>,
+
+    /// Write a JSON summary of the run (provenance, result counts and warnings
+    /// such as skipped records, with examples) to this file
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+
+    /// Fraction step for the coverage titration (e.g. 0.1 for 10%, 20%, ..., 100%)
+    #[arg(long, default_value = "0.1")]
+    titration_step: f64,
+
+    /// GTF or GFF3 annotation; detectability is rolled up per exon and transcript
+    /// into --rollup-output, and the genes of --review-priority are located in it
+    #[arg(long, value_name = "FILE")]
+    gtf: Option<PathBuf>,
+
+    /// Write the per-exon and per-transcript rollup to this TSV file
+    #[arg(long, value_name = "FILE", requires = "gtf")]
+    rollup_output: Option<PathBuf>,
+
+    /// Target VAF at which rolled-up variant positions count as assessable
+    #[arg(long, value_name = "VAF", default_value = "0.05")]
+    rollup_vaf: f64,
+
+    /// Analyze only the variants in the exons of these genes (comma-separated
+    /// symbols, located through --gtf)
+    #[arg(long, value_name = "GENES", value_delimiter = ',', requires = "gtf")]
+    genes: Vec<String>,
+
+    /// Write the regions analyzed for --genes (merged exons, named by gene) to this
+    /// BED file [default: OUTPUT.genes.bed]
+    #[arg(long, value_name = "FILE", requires = "genes")]
+    genes_bed: Option<PathBuf>,
+
+    /// Reference FASTA, plain or bgzipped with .fai (and .gzi) indexes; VCF REF
+    /// alleles are checked against it, and so are the soft-clipped bases of reads
+    /// past an insertion at their end
+    #[arg(long, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Build missing --reference indexes instead of failing
+    #[arg(long, requires = "reference")]
+    auto_faidx: bool,
+
+    /// ClinVar VCF (optionally gzipped); each result's CLNSIG clinical significance
+    /// is joined on by contig, position and alleles, and the run summary counts
+    /// the pathogenic variants not Detectable
+    #[arg(long, value_name = "FILE")]
+    clinvar: Option<PathBuf>,
+
+    /// Hotspots given as HGVS, one per line (`NM_000546.6:c.524G>A`,
+    /// `NM_000546.6:p.Arg175His`), located through the --gtf transcripts and the
+    /// --reference and analysed along with the VCF variants; the HGVS column
+    /// records each result's description
+    #[arg(long, value_name = "FILE", requires_all = ["gtf", "reference"])]
+    hgvs: Option<PathBuf>,
+
+    /// Append the results to this SQLite database (created if missing), with one
+    /// run per sample; requires the `sqlite` feature
+    #[arg(long, value_name = "FILE")]
+    output_db: Option<PathBuf>,
+
+    /// Write the results and a run summary to this Excel workbook, with
+    /// Non-detectable variants highlighted; requires the `xlsx` feature
+    #[arg(long, value_name = "FILE")]
+    output_xlsx: Option<PathBuf>,
+
+    /// Write SVG plots of the results (score histogram, coverage against score,
+    /// Detectable fraction per chromosome) to this directory; requires the
+    /// `plots` feature
+    #[arg(long, value_name = "DIR")]
+    plot_dir: Option<PathBuf>,
+
+    /// Write the sample's metrics (% detectable, median score and depth) as
+    /// `<sample>_vlod_mqc.json` to this directory, for MultiQC to pick up
+    #[arg(long, value_name = "DIR")]
+    multiqc_dir: Option<PathBuf>,
+
+    /// Sample name recorded in the Sample column, the run summary, --output-db and
+    /// --multiqc-dir [default: the BAM read-group SM tag, the VCF's only sample or
+    /// the BAM file name]
+    #[arg(long, value_name = "NAME", alias = "db-sample")]
+    sample_name: Option<String>,
+
+    /// Write a SHA-256 checksum sidecar (`<output>.sha256`) next to each output;
+    /// check it later with `vlod verify-output`
+    #[arg(long)]
+    checksum_outputs: bool,
+
+    /// Reclassify the results at each threshold START:STOP:STEP (e.g. 1.0:4.0:0.1)
+    /// and write the counts of flipped variants to --sweep-output
+    #[arg(long, value_name = "START:STOP:STEP", requires = "sweep_output")]
+    threshold_sweep: Option<ThresholdSweep>,
+
+    /// Write the threshold sweep to this CSV file
+    #[arg(long, value_name = "FILE", requires = "threshold_sweep")]
+    sweep_output: Option<PathBuf>,
+
+    /// Truth set TSV (Chrom, Pos, Ref, Alt, Truth) adding sensitivity and
+    /// specificity to the threshold sweep
+    #[arg(long, value_name = "FILE", requires = "threshold_sweep")]
+    sweep_truth: Option<PathBuf>,
+
+    /// Write the variants ranked for manual review, most concerning first, with
+    /// a softmax weight per variant, to this TSV file
+    #[arg(long, value_name = "FILE")]
+    review_list: Option<PathBuf>,
+
+    /// Clinical priority for --review-list: a BED with the priority in column 5
+    /// (default 1), `chr1:100-200 PRIORITY` lines, or a gene list located through --gtf
+    #[arg(long, value_name = "FILE", requires = "review_list")]
+    review_priority: Option<PathBuf>,
+
+    /// Ranking for --review-list: priority (failing variants in priority regions,
+    /// then borderline scores), borderline or score
+    #[arg(long, default_value = "priority")]
+    review_ranking: ReviewRanking,
+
+    /// Softmax temperature of the --review-list weights; lower values concentrate
+    /// the weight on the top variants
+    #[arg(long, value_name = "T", default_value = "1.0")]
+    review_temperature: f64,
+
+    /// Assemble reads locally for variants with conflicting pileup evidence
+    /// (requires the `assembly` feature)
+    #[arg(long)]
+    local_assembly: bool,
+
+    /// Calibration file from `vlod calibrate` with per-variant-class thresholds
+    #[arg(long, value_name = "FILE")]
+    calibration: Option<PathBuf>,
+
+    /// Panel regions sampled for background noise (BED, Picard interval_list or
+    /// `chr1:100-200` lines); per-substitution-class noise percentiles are reported
+    /// in the summary
+    #[arg(long, value_name = "FILE")]
+    noise_bed: Option<PathBuf>,
+
+    /// Number of positions sampled from --noise-bed
+    #[arg(long, default_value = "1000")]
+    noise_samples: usize,
+
+    /// Use the sampled background noise as per-substitution-class sequencing error
+    /// rates instead of --SE
+    #[arg(long, requires = "noise_bed")]
+    noise_error_rates: bool,
+
+    /// Target regions (BED, Picard interval_list or `chr1:100-200` lines) whose
+    /// coverage uniformity is reported in the log and run summary: the fold-80 base
+    /// penalty, the bases at --coverage-thresholds depths and, with --reference,
+    /// the correlation of depth with GC content
+    #[arg(long, value_name = "FILE")]
+    target_bed: Option<PathBuf>,
+
+    /// Depths whose share of the --target-bed bases is reported
+    #[arg(long, value_name = "DEPTHS", value_delimiter = ',', default_values_t = DEFAULT_COVERAGE_THRESHOLDS)]
+    coverage_thresholds: Vec<u32>,
+
+    /// Reference-confirmation mode: report monomorphic sites (ALT '.') as
+    /// REF_CONFIRMED when their depth rules out a variant at this assay LoD VAF
+    #[arg(long, value_name = "VAF")]
+    ref_confirm_vaf: Option<f64>,
+
+    /// Probability of sampling a variant read at the assay LoD required to confirm
+    /// the reference
+    #[arg(long, default_value = "0.95")]
+    ref_confirm_confidence: f64,
+
+    /// Report in Required_Depth the depth at which a variant at this expected VAF
+    /// would reach the detection threshold with 95% power, for planning top-up
+    /// sequencing of failed sites
+    #[arg(long, value_name = "VAF")]
+    required_depth_vaf: Option<f64>,
+
+    /// Variant caller conventions of the input VCF: auto to detect the caller from
+    /// the header, generic, dragen to drop <NON_REF> alleles and gVCF reference
+    /// blocks, count LowGQ-only records as PASS and trim padded alleles of
+    /// multi-allelic records, or strelka2 or vardict to read their FORMAT depths
+    #[arg(long, default_value = "auto")]
+    caller: Caller,
+
+    /// How to resolve variants repeated in the input (first, max or error)
+    #[arg(long, default_value = "first")]
+    duplicate_policy: DuplicatePolicy,
+
+    /// Sort the input VCF into BAM header contig order instead of failing when it
+    /// is unsorted (held in memory; meant for modest-size VCFs)
+    #[arg(long)]
+    sort_input: bool,
+
+    /// Longest VCF/TSV line accepted, in bytes; guards against corrupted inputs
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// How to handle monomorphic reference sites with ALT "." (skip, or report the
+    /// depth available to confirm the reference)
+    #[arg(long, default_value = "skip")]
+    monomorphic_policy: MonomorphicPolicy,
+
+    /// Analyse only variants whose FILTER is PASS (non-PASS records are left unannotated)
+    #[arg(long)]
+    pass_only: bool,
+
+    /// Stop after this many invalid VCF records (by default they are all skipped)
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// Times a BAM read failing with a transient IO error (e.g. on NFS or S3) is
+    /// retried before the run aborts
+    #[arg(long, default_value = "3")]
+    io_retries: u32,
+
+    /// Wait before the first retry of a failed BAM read, doubling on each further
+    /// retry
+    #[arg(long, default_value = "200", value_name = "MS")]
+    io_retry_backoff_ms: u64,
+
+    /// Most BAM readers open at once, for systems with a low open-file limit;
+    /// variants are scheduled in per-chromosome chunks that wait for a free reader
+    #[arg(long, value_name = "N")]
+    max_open_bams: Option<usize>,
+
+    /// Balance the work between processes by the depth the BAM index suggests at
+    /// each variant, scoring deep hotspots in chunks of their own
+    #[arg(long)]
+    balance_depth: bool,
+
+    /// Variants at most this many bases apart on a contig (dense hotspots) are read
+    /// with a single BAM fetch and pileup traversal; 0 fetches each variant alone
+    #[arg(long, default_value = "200", value_name = "BP")]
+    fetch_merge_distance: u64,
+
+    /// Reads a pileup column holds; at deeper sites the pileup drops reads
+    #[arg(long, default_value_t = DEFAULT_MAX_PILEUP_DEPTH, value_name = "N")]
+    max_pileup_depth: u32,
+
+    /// Sites whose pileup reaches --max-pileup-depth: count them again by iterating
+    /// over their reads, or keep the partial counts and flag them (Depth_Capped
+    /// column, depth_capped warning)
+    #[arg(long, default_value = "iterate")]
+    depth_cap: DepthCapPolicy,
+
+    /// Count this fraction of the variants (e.g. 0.01) a second time by iterating
+    /// over their reads and report any disagreement with the pileup counts
+    /// (count_disagreement warning, spot_check in the run summary)
+    #[arg(long, value_name = "F")]
+    verify_fraction: Option<f64>,
+
+    /// Seed choosing the variants spot-checked by --verify-fraction
+    #[arg(long, default_value_t = VERIFY_SAMPLING_SEED, value_name = "N")]
+    verify_seed: u64,
+
+    /// Assay claims (JSON) evaluated against the run: each gets a pass/fail verdict
+    /// in the log and the run summary, and the run exits with status 3 when any
+    /// claim fails
+    #[arg(long, value_name = "FILE")]
+    claims: Option<PathBuf>,
+
+    /// Handling of variants on unplaced, random and alt contigs: analyze, skip
+    /// (reported Not-assessable) or map (scored at the primary locus given by
+    /// --alt-contig-alignment). Contigs missing from the BAM are always reported
+    /// Not-assessable rather than failing the run
+    #[arg(long, default_value = "analyze")]
+    contig_policy: ContigPolicy,
+
+    /// SAM alignments of alt contigs to the primary assembly (e.g. a bwa .alt
+    /// file), used by --contig-policy map
+    #[arg(long, value_name = "FILE")]
+    alt_contig_alignment: Option<PathBuf>,
 
     /// Number of processes to use for parallel processing
     #[arg(long, default_value_t = get_num_cpus())]
@@ -80,6 +505,9 @@ fn run() -> VlodResult<()> {
         .format_timestamp_secs()
         .init();
 
+    // Flush completed results instead of dying on Ctrl-C or a scheduler's SIGTERM
+    interrupt::install_signal_handlers();
+
     log::info!("Starting vLoD analysis");
     log::info!("VCF file: {:?}", args.input_vcf);
     log::info!("BAM file: {:?}", args.input_bam);
@@ -89,18 +517,171 @@ fn run() -> VlodResult<()> {
     // Validate input files
     validate_file_readable(&args.input_vcf)?;
     validate_file_readable(&args.input_bam)?;
+    let exons = match &args.gtf {
+        Some(gtf) => {
+            if !(args.rollup_vaf > 0.0 && args.rollup_vaf <= 1.0) {
+                return Err(VlodError::InvalidConfig("--rollup-vaf must be in (0, 1]".to_string()));
+            }
+            let exons = read_exons(gtf)?;
+            log::info!("Read {} exons from {:?}", exons.len(), gtf);
+            Some(exons)
+        }
+        None => None,
+    };
+    let gene_footprint = match &exons {
+        Some(exons) if !args.genes.is_empty() => {
+            let regions = gene_regions(exons, &args.genes)?;
+            let genes_bed = args.genes_bed.clone().unwrap_or_else(|| append_extension(&args.output, "genes.bed"));
+            ensure_parent_dirs(&genes_bed)?;
+            write_bed_regions(&regions, &genes_bed)?;
+            log::info!(
+                "Restricting the analysis to {} exonic regions of {} genes, written to {:?}",
+                regions.len(),
+                args.genes.len(),
+                genes_bed
+            );
+            Some(regions)
+        }
+        _ => None,
+    };
+
+    let review_priorities = args
+        .review_priority
+        .as_ref()
+        .map(|path| ReviewPriorities::read(path, exons.as_deref()))
+        .transpose()?;
+    let review_options = ReviewOptions {
+        ranking: args.review_ranking,
+        temperature: args.review_temperature,
+    };
+    let sweep_truth = args.sweep_truth.as_ref().map(read_truth_set).transpose()?;
+    let claims = args.claims.as_ref().map(ClaimSet::from_file).transpose()?;
+    let targets = args.target_bed.as_ref().map(read_regions).transpose()?;
+    let reference = args
+        .reference
+        .as_ref()
+        .map(|reference| ReferenceFasta::open(reference, args.auto_faidx))
+        .transpose()?;
+
+    // Aligner preset, unless overridden
+    let preset = resolve_preset(args.preset, &args.input_bam)?;
+    let mut read_filters = args.read_filter.clone();
+    if let Some(preset) = preset {
+        log::info!("Using the {} preset ({})", preset.name, preset.description);
+        read_filters.extend(preset.read_filters());
+    }
 
     // Create LOD configuration
-    let config = LodConfig {
+    let mut config = LodConfig {
         p_tp: args.tp,
         p_fp: args.fp,
-        p_se: args.se,
+        p_se: args.se.or(preset.map(|preset| preset.p_se)).unwrap_or(DEFAULT_SEQUENCING_ERROR_RATE),
+        score_threshold: args.threshold,
+        local_assembly: args.local_assembly,
+        amplicons: args
+            .amplicon_bed
+            .as_ref()
+            .map(AmpliconSet::from_bed)
+            .transpose()?
+            .map(Arc::new),
+        bisulfite: args.bisulfite,
+        ffpe: args.ffpe,
+        titration_fractions: if args.titration_output.is_some() {
+            titration_fractions(args.titration_step)?
+        } else {
+            Vec::new()
+        },
+        calibration: args
+            .calibration
+            .as_ref()
+            .map(Calibration::from_file)
+            .transpose()?
+            .map(Arc::new),
+        ref_confirmation: args.ref_confirm_vaf.map(|assay_lod_vaf| RefConfirmation {
+            assay_lod_vaf,
+            confidence: args.ref_confirm_confidence,
+        }),
+        required_depth_vaf: args.required_depth_vaf,
+        noise_profile: None,
+        read_filters,
+        pool: args.pool_size.map(|size| PoolDesign { size }),
+        io_retry: RetryPolicy {
+            max_retries: args.io_retries,
+            initial_backoff: Duration::from_millis(args.io_retry_backoff_ms),
+        },
+        max_open_bams: args.max_open_bams,
+        balance_depth: args.balance_depth,
+        contig_policy: args.contig_policy,
+        alt_contig_map: args
+            .alt_contig_alignment
+            .as_ref()
+            .map(AltContigMap::from_sam)
+            .transpose()?
+            .map(Arc::new),
+        variant_overrides: None,
+        vaf_definition: args.vaf_definition,
+        max_other_allele_fraction: args.max_other_allele_fraction,
+        deleted_reads: args.deleted_reads,
+        short_fragments: args.short_fragments,
+        min_base_quality: args.min_base_quality,
+        min_mapping_quality: args.min_mapping_quality,
+        missing_quality: args.missing_quality,
+        fetch_merge_distance: args.fetch_merge_distance,
+        coverage_only: args.coverage_only,
+        max_pileup_depth: args.max_pileup_depth,
+        depth_cap: args.depth_cap,
+        reference: args.reference.clone(),
     };
 
     // Validate configuration
     validate_lod_config(&config)?;
+    if let Some(fraction) = args.verify_fraction {
+        validate_verify_fraction(fraction)?;
+    }
+    if args.output_format == ResultsFormat::Pgcopy && !is_valid_table_name(&args.pg_table) {
+        return Err(VlodError::InvalidConfig(format!("invalid PostgreSQL table name '{}'", args.pg_table)));
+    }
+    if args.output_format == ResultsFormat::Pgcopy && (args.columns.is_some() || args.layout != ResultsLayout::Packed) {
+        return Err(VlodError::InvalidConfig(
+            "--columns and --layout apply to the TSV output only".to_string(),
+        ));
+    }
+    if args.output_db.is_some() && !cfg!(feature = "sqlite") {
+        return Err(VlodError::InvalidConfig(
+            "--output-db requires vlod-rs to be built with the `sqlite` feature".to_string(),
+        ));
+    }
+    if args.output_xlsx.is_some() && !cfg!(feature = "xlsx") {
+        return Err(VlodError::InvalidConfig(
+            "--output-xlsx requires vlod-rs to be built with the `xlsx` feature".to_string(),
+        ));
+    }
+    if args.plot_dir.is_some() && !cfg!(feature = "plots") {
+        return Err(VlodError::InvalidConfig(
+            "--plot-dir requires vlod-rs to be built with the `plots` feature".to_string(),
+        ));
+    }
 
     log::info!("Configuration: TP={}, FP={}, SE={}", config.p_tp, config.p_fp, config.p_se);
+    if let Some(pool) = &config.pool {
+        log::info!(
+            "Pooled design: {} samples, single-copy allele fraction {:.4}",
+            pool.size,
+            pool.expected_fraction()
+        );
+    }
+
+    // Sample background noise at random panel positions, from the reads the
+    // variants are counted from
+    if let Some(noise_bed) = &args.noise_bed {
+        let _timer = Timer::new("Sampling background noise");
+        let positions = sample_positions(&read_regions(noise_bed)?, args.noise_samples, NOISE_SAMPLING_SEED);
+        let profile = sample_background_noise(&args.input_bam, &positions, &config)?;
+        profile.log_summary();
+        if args.noise_error_rates {
+            config.noise_profile = Some(Arc::new(profile));
+        }
+    }
 
     // Create output directory if it doesn't exist
     if let Some(parent) = args.output.parent() {
@@ -109,29 +690,171 @@ fn run() -> VlodResult<()> {
 
     // Read VCF variants
     let _timer = Timer::new("Reading VCF variants");
-    let variants = read_vcf_variants(&args.input_vcf)?;
+    let limits = VcfReadLimits {
+        max_line_length: args.max_line_length,
+        max_errors: args.max_errors,
+        caller: args.caller,
+    };
+    let input = read_vcf_input(&args.input_vcf, &limits)?;
+    let mut warnings = input.warnings;
+    if !input.overrides.is_empty() {
+        log::info!("{} variants override model parameters with VLOD_* INFO tags", input.overrides.len());
+        config.variant_overrides = Some(Arc::new(input.overrides));
+    }
+    let (variants, non_pass) = select_pass_variants(input.variants, args.pass_only);
+    if args.pass_only {
+        warnings.add(WarningKind::NonPassSkipped, non_pass.len());
+    }
+    // Reference confirmation assesses monomorphic sites, so never skip them
+    let monomorphic_policy = if config.ref_confirmation.is_some() {
+        MonomorphicPolicy::Report
+    } else {
+        args.monomorphic_policy
+    };
+    let read = variants.len();
+    let mut variants = apply_monomorphic_policy(variants, monomorphic_policy);
+    warnings.add(WarningKind::MonomorphicSkipped, read - variants.len());
     log::info!("Read {} variants from VCF file", variants.len());
 
+    let sample =
+        resolve_sample_name(args.sample_name.as_deref(), &args.input_bam, &args.input_vcf, args.max_line_length)?;
+
+    // Variants must follow the BAM header contig order
+    let contig_order = bam_contig_order(&args.input_bam)?;
+    if args.sort_input {
+        sort_variants(&mut variants, &contig_order);
+    } else {
+        check_sort_order(&variants, &contig_order)?;
+    }
+    let (mut variants, duplicates) = dedup_variants(variants, args.duplicate_policy)?;
+    if duplicates > 0 {
+        log::warn!("Skipped {} duplicate variants in the input VCF", duplicates);
+    }
+    let hgvs = match (&args.hgvs, &args.gtf, &reference) {
+        (Some(hgvs), Some(gtf), Some(reference)) => {
+            let transcripts = read_transcripts(gtf)?;
+            let hotspots = read_hgvs_hotspots(hgvs, &transcripts, reference, args.max_line_length)?;
+            let by_variant = hgvs_by_variant(&hotspots);
+            let mut known: HashSet<Variant> = variants.iter().cloned().collect();
+            let in_vcf = variants.len();
+            for variant in hotspots.iter().flat_map(|hotspot| &hotspot.variants) {
+                if known.insert(variant.clone()) {
+                    variants.push(variant.clone());
+                }
+            }
+            log::info!(
+                "Located {} HGVS hotspots as {} variants, {} of them not in the VCF",
+                hotspots.len(),
+                by_variant.len(),
+                variants.len() - in_vcf
+            );
+            sort_variants(&mut variants, &contig_order);
+            Some(by_variant)
+        }
+        _ => None,
+    };
+    if let Some(regions) = &gene_footprint {
+        let outside = retain_in_regions(&mut variants, regions);
+        log::info!("{} variants outside the exons of --genes are not analysed", outside);
+    }
+    warnings.add(WarningKind::DuplicateVariant, duplicates);
+    if let Some(reference) = &reference {
+        log_ref_mismatches(&variants, reference)?;
+    }
+
     if variants.is_empty() {
         log::warn!("No variants found in the input VCF file");
         // Create empty output file with header
-        write_detectability_results(&[], &args.output)?;
+        write_results_output(&[], &args, &config, false)?;
+        if let Some(titration_output) = &args.titration_output {
+            write_titration_results(&[], titration_output)?;
+        }
+        if let (Some(exons), Some(rollup_output)) = (&exons, &args.rollup_output) {
+            write_rollup(&rollup_by_feature(exons, &[], &config, args.rollup_vaf), rollup_output)?;
+        }
+        if let (Some(sweep), Some(sweep_output)) = (&args.threshold_sweep, &args.sweep_output) {
+            write_sweep_results(&sweep_thresholds(&[], sweep, sweep_truth.as_ref()), sweep_output)?;
+        }
+        if let Some(review_list) = &args.review_list {
+            write_review_list(&[], review_list)?;
+        }
+        let mut summary = RunSummary::new(&[], warnings, false, &config);
+        summary.claims = claims.as_ref().map(|claims| claims.evaluate(&[], &config));
+        write_run_reports(&[], &mut summary, preset, &sample, &args)?;
+        if args.checksum_outputs {
+            write_output_checksums(&args, &sample)?;
+        }
+        exit_on_failed_claims(&summary);
         return Ok(());
     }
 
+    let clinvar = match &args.clinvar {
+        Some(clinvar) => {
+            let table = AnnotationTable::read_vcf(clinvar, CLINVAR_SIGNIFICANCE_FIELD, &variants, args.max_line_length)?;
+            log::info!("{} of {} variants have a ClinVar clinical significance", table.len(), variants.len());
+            Some(table)
+        }
+        None => None,
+    };
+
     // Calculate detectability scores
     let _timer = Timer::new("Calculating detectability scores");
-    let results = calculate_detectability_scores(
+    let variant_count = variants.len();
+    let mut results = calculate_detectability_scores(
         variants,
         &args.input_bam,
         &config,
         args.num_processes,
     )?;
+    for result in &mut results {
+        result.sample = Some(sample.name.clone());
+        result.hgvs = hgvs.as_ref().and_then(|hgvs| hgvs.get(&result.variant).cloned());
+        result.clinical_significance =
+            clinvar.as_ref().and_then(|clinvar| clinvar.get(&result.variant)).map(str::to_string);
+    }
+
+    if interrupt::is_interrupted() {
+        log::warn!(
+            "Interrupted: {} results scored for {} input variants; writing partial results",
+            results.len(),
+            variant_count
+        );
+        write_results_output(&results, &args, &config, true)?;
+        warnings.record_not_assessable(&results);
+        let mut summary = RunSummary::new(&results, warnings, true, &config);
+        write_run_reports(&results, &mut summary, preset, &sample, &args)?;
+        match args.output_format {
+            ResultsFormat::Tsv => log::warn!("Partial results written to: {:?} (marked #partial=true)", args.output),
+            ResultsFormat::Pgcopy => log::warn!("Partial results written to: {:?}", args.output),
+        }
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
 
     log::info!("Calculated detectability scores for {} variants", results.len());
+    let spot_check = match args.verify_fraction {
+        Some(fraction) => {
+            let _timer = Timer::new("Spot-checking counts");
+            let scored: Vec<Variant> = results.iter().map(|result| result.variant.clone()).collect();
+            let check = verify_counts(&scored, &args.input_bam, &config, fraction, args.verify_seed)?;
+            check.record_warnings(&mut warnings);
+            Some(check)
+        }
+        None => None,
+    };
+    let coverage_uniformity = match &targets {
+        Some(targets) => {
+            let _timer = Timer::new("Measuring target coverage");
+            let uniformity =
+                measure_uniformity(&args.input_bam, targets, reference.as_ref(), &config, &args.coverage_thresholds)?;
+            uniformity.log();
+            Some(uniformity)
+        }
+        _ => None,
+    };
+    warnings.record_not_assessable(&results);
 
     // Log statistics
-    let detectable_count = results.iter().filter(|r| r.detectability_condition == "Detectable").count();
+    let detectable_count = results.iter().filter(|r| r.detectability_condition.is_detectable()).count();
     let non_detectable_count = results.len() - detectable_count;
     
     log::info!("Results summary:");
@@ -147,17 +870,155 @@ fn run() -> VlodResult<()> {
         log::info!("  Score range: {:.3} to {:.3}", min_score, max_score);
         log::info!("  Average score: {:.3}", avg_score);
     }
+    log::info!("  BAM read retries: {}", io_retries(&results));
+    log_filter_stratified_summary(&results, &non_pass);
+    if args.clinvar.is_some() {
+        SignificanceSummary::new(&results).log();
+    }
+    let mut summary = RunSummary::new(&results, warnings, false, &config);
+    summary.spot_check = spot_check;
+    summary.coverage_uniformity = coverage_uniformity;
+    summary.claims = claims.as_ref().map(|claims| claims.evaluate(&results, &config));
+    if let Some(verdicts) = &summary.claims {
+        log_claim_verdicts(verdicts);
+    }
 
     // Write results
     let _timer = Timer::new("Writing results");
-    write_detectability_results(&results, &args.output)?;
+    write_results_output(&results, &args, &config, false)?;
 
     log::info!("Results written to: {:?}", args.output);
+
+    if let Some(titration_output) = &args.titration_output {
+        write_titration_results(&results, titration_output)?;
+        log::info!("Coverage titration written to: {:?}", titration_output);
+    }
+    if let (Some(exons), Some(rollup_output)) = (&exons, &args.rollup_output) {
+        write_rollup(&rollup_by_feature(exons, &results, &config, args.rollup_vaf), rollup_output)?;
+        log::info!("Exon and transcript rollup written to: {:?}", rollup_output);
+    }
+    if let (Some(sweep), Some(sweep_output)) = (&args.threshold_sweep, &args.sweep_output) {
+        write_sweep_results(&sweep_thresholds(&results, sweep, sweep_truth.as_ref()), sweep_output)?;
+        log::info!("Threshold sweep written to: {:?}", sweep_output);
+    }
+    if let Some(review_list) = &args.review_list {
+        let entries = rank_for_review(&results, review_priorities.as_ref(), &config, &review_options)?;
+        write_review_list(&entries, review_list)?;
+        log::info!("Review list written to: {:?}", review_list);
+    }
+    write_run_reports(&results, &mut summary, preset, &sample, &args)?;
+    #[cfg(feature = "sqlite")]
+    if let Some(output_db) = &args.output_db {
+        let run_id = vlod_rs::results_db::append_results_to_db(&results, output_db, &sample.name, &config)?;
+        log::info!("Results appended to {:?} as run {} of sample {}", output_db, run_id, sample.name);
+    }
+    if args.checksum_outputs {
+        write_output_checksums(&args, &sample)?;
+    }
+    exit_on_failed_claims(&summary);
     log::info!("Analysis completed successfully");
 
     Ok(())
 }
 
+/// Write the per-variant results in the requested format, marking a TSV partial
+/// for an interrupted run
+fn write_results_output(
+    results: &[DetectabilityResult],
+    args: &Args,
+    config: &LodConfig,
+    partial: bool,
+) -> VlodResult<()> {
+    let with_sites;
+    let results = if args.site_aggregates {
+        with_sites = with_site_aggregates(results);
+        &with_sites
+    } else {
+        results
+    };
+    match args.output_format {
+        ResultsFormat::Tsv => {
+            let columns = args.columns.clone().unwrap_or_default();
+            write_detectability_columns(results, &args.output, &columns, args.layout, partial, config)
+        }
+        ResultsFormat::Pgcopy => {
+            let ddl = write_pgcopy_results(results, &args.output, &args.pg_table, config)?;
+            log::info!("PostgreSQL table DDL written to: {:?}", ddl);
+            Ok(())
+        }
+    }
+}
+
+/// Exit with `CLAIMS_FAILED_EXIT_CODE` once every output is written if an assay
+/// claim failed
+fn exit_on_failed_claims(summary: &RunSummary) {
+    if summary.claims.iter().flatten().any(|verdict| !verdict.passed) {
+        log::error!("The run fails its assay claims");
+        std::process::exit(CLAIMS_FAILED_EXIT_CODE);
+    }
+}
+
+/// Write the JSON run summary, the Excel report, the plots and, for a complete
+/// run, the MultiQC metrics, if requested
+fn write_run_reports(
+    results: &[DetectabilityResult],
+    summary: &mut RunSummary,
+    preset: Option<&Preset>,
+    sample: &SampleName,
+    args: &Args,
+) -> VlodResult<()> {
+    let partial = summary.partial;
+    summary.clinical_significance = args.clinvar.is_some().then(|| SignificanceSummary::new(results));
+    summary.preset = preset.map(|preset| preset.name.to_string());
+    summary.sample = Some(sample.name.clone());
+    summary.sample_source = Some(sample.source.to_string());
+    if let Some(summary_json) = &args.summary_json {
+        summary.write(summary_json)?;
+        log::info!("Run summary written to: {:?}", summary_json);
+    }
+    #[cfg(feature = "xlsx")]
+    if let Some(output_xlsx) = &args.output_xlsx {
+        vlod_rs::xlsx::write_xlsx_report(results, summary, output_xlsx)?;
+        log::info!("Excel report written to: {:?}", output_xlsx);
+    }
+    #[cfg(feature = "plots")]
+    if let Some(plot_dir) = &args.plot_dir {
+        vlod_rs::plots::write_plots(results, plot_dir)?;
+        log::info!("Plots written to: {:?}", plot_dir);
+    }
+    if let (Some(multiqc_dir), false) = (&args.multiqc_dir, partial) {
+        let path = write_multiqc(results, &sample.name, multiqc_dir)?;
+        log::info!("MultiQC metrics written to: {:?}", path);
+    }
+    Ok(())
+}
+
+/// Write checksum sidecars for every output of a run
+fn write_output_checksums(args: &Args, sample: &SampleName) -> VlodResult<()> {
+    let ddl = (args.output_format == ResultsFormat::Pgcopy).then(|| pg_ddl_path(&args.output));
+    let multiqc = args.multiqc_dir.as_ref().map(|dir| multiqc_path(dir, &sample.name));
+    let optional = [
+        &args.titration_output,
+        &args.rollup_output,
+        &args.sweep_output,
+        &args.review_list,
+        &args.summary_json,
+        &args.output_xlsx,
+        &ddl,
+        &multiqc,
+    ];
+    for output in std::iter::once(&args.output).chain(optional.into_iter().flatten()) {
+        let sidecar = write_checksum_sidecar(output)?;
+        log::info!("Checksum written to: {:?}", sidecar);
+    }
+    #[cfg(feature = "plots")]
+    for plot in args.plot_dir.iter().flat_map(vlod_rs::plots::plot_paths) {
+        let sidecar = write_checksum_sidecar(&plot)?;
+        log::info!("Checksum written to: {:?}", sidecar);
+    }
+    Ok(())
+}
+
 /// Handle application errors and provide user-friendly messages
 fn handle_error(error: VlodError) -> ! {
     match error {
@@ -169,13 +1030,17 @@ fn handle_error(error: VlodError) -> ! {
             eprintln!("Error: Invalid variant data: {}", msg);
             eprintln!("Please check that your VCF file is properly formatted.");
         }
+        VlodError::Parse { .. } => {
+            eprintln!("Error: {}", error);
+            eprintln!("Please check that your VCF file is properly formatted.");
+        }
         VlodError::InvalidConfig(msg) => {
             eprintln!("Error: Invalid configuration: {}", msg);
             eprintln!("Please check your probability parameters (TP, FP, SE).");
         }
         VlodError::Htslib(ref e) => {
             eprintln!("Error: BAM/VCF processing error: {}", e);
-            eprintln!("Please check that your BAM file is valid and has an index (.bai) file.");
+            eprintln!("Please check that your BAM file is valid and has an index (.bai or .csi) file.");
         }
         VlodError::Io(ref e) => {
             eprintln!("Error: I/O error: {}", e);
@@ -185,6 +1050,14 @@ fn handle_error(error: VlodError) -> ! {
             eprintln!("Error: CSV processing error: {}", e);
             eprintln!("Please check the output file format.");
         }
+        VlodError::Database(ref msg) => {
+            eprintln!("Error: Results database error: {}", msg);
+            eprintln!("Please check that the --output-db file is a writable SQLite database.");
+        }
+        VlodError::Context { context, source } => {
+            eprintln!("Error while {}:", context);
+            handle_error(*source);
+        }
     }
     std::process::exit(1);
 }
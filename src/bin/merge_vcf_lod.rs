@@ -4,9 +4,12 @@ use clap::Parser;
 use env_logger::Env;
 use std::path::PathBuf;
 use vlod_rs::{
-    merge::merge_detectability_into_vcf,
-    utils::{validate_file_readable, Timer},
-    VlodError, VlodResult,
+    bam::bam_contigs,
+    integrity::write_checksum_sidecar,
+    merge::{annotate_in_place, merge_detectability_into_vcf, read_results_threshold, MergeOptions},
+    vcf::{Caller, DuplicatePolicy},
+    utils::{validate_file_readable, Timer, DEFAULT_MAX_LINE_LENGTH},
+    VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
 };
 
 #[derive(Parser)]
@@ -18,10 +21,23 @@ It reads detectability scores from a TSV file (produced by lod_edit) and adds
 them as INFO fields to the corresponding variants in the VCF file.
 
 Two new INFO fields are added:
-- DET: Detectability status (Yes/No)
+- DET: Detectability status (Yes/No/NoCoverage/Monomorphic/REF_CONFIRMED/REF_UNCONFIRMED/CoverageOnly)
 - DETS: Detectability score (float)
 
+If the TSV has a Detection_Probability column (lod_edit --calibration), the
+calibrated probability is added as DETP as well.
+
+With --bam, ##contig lines are synthesized from the BAM header when the input
+VCF has none, so that strict downstream validators accept the output.
+
 The tool supports both compressed and uncompressed VCF files.
+
+A results TSV indexed with `vlod index-results` (bgzip plus .tbi) is looked up
+record by record through its index rather than loaded into memory.
+
+With --in-place the VCF itself is annotated: the new file replaces it only once
+the merge succeeded, stays bgzipped if it was, and its .tbi or .csi index is
+rebuilt.
 ")]
 struct Args {
     /// Path to the input VCF file
@@ -33,8 +49,65 @@ struct Args {
     detectability_file: PathBuf,
 
     /// Path to the output VCF file
-    #[arg(value_name = "OUTPUT_FILE")]
-    output_file: PathBuf,
+    #[arg(value_name = "OUTPUT_FILE", required_unless_present = "in_place")]
+    output_file: Option<PathBuf>,
+
+    /// Annotate VCF_FILE itself instead of writing OUTPUT_FILE
+    #[arg(long, conflicts_with = "output_file")]
+    in_place: bool,
+
+    /// Variant caller conventions of the VCF: auto to detect the caller from the
+    /// header, generic, dragen to ignore <NON_REF> alleles when matching records to
+    /// results, or strelka2 or vardict to read their FORMAT depths
+    #[arg(long, default_value = "auto")]
+    caller: Caller,
+
+    /// How to resolve variants repeated in the results or the VCF (first, max or error)
+    #[arg(long, default_value = "first")]
+    duplicate_policy: DuplicatePolicy,
+
+    /// Match contig names exactly instead of aliasing chr-prefixed and bare names
+    #[arg(long)]
+    strict_contig_names: bool,
+
+    /// Add the fraction of ALT reads in F1R2 orientation as a DETOB INFO field
+    #[arg(long)]
+    orientation_info: bool,
+
+    /// Add the strand bias of the ALT reads as DETFS (phred-scaled Fisher's exact
+    /// test) and DETSOR (symmetric odds ratio) INFO fields
+    #[arg(long)]
+    strand_info: bool,
+
+    /// Flag records whose BAM coverage differs from the VCF depth (INFO DP or summed
+    /// AD, or the caller's FORMAT depths) by more than FOLD (default 2) with a
+    /// DETDPD INFO field, and log a per-contig discordance table; catches a BAM the
+    /// variants were not called from
+    #[arg(long, value_name = "FOLD", num_args = 0..=1, default_missing_value = "2")]
+    depth_discordance: Option<f64>,
+
+    /// Score threshold the results were called at, recorded in the output's
+    /// provenance when the results TSV does not record it
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Longest VCF/TSV line accepted, in bytes; guards against corrupted inputs
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// Skip invalid detectability rows, stopping after this many (by default the
+    /// first invalid row is fatal)
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// BAM file whose header supplies ##contig lines when the VCF has none
+    #[arg(long, value_name = "FILE")]
+    bam: Option<PathBuf>,
+
+    /// Write a SHA-256 checksum sidecar (`<output>.sha256`) next to each output;
+    /// check it later with `vlod verify-output`
+    #[arg(long)]
+    checksum_outputs: bool,
 
     /// Enable verbose logging
     #[arg(short, long)]
@@ -68,35 +141,68 @@ fn run() -> VlodResult<()> {
     log::info!("Starting VCF merge operation");
     log::info!("VCF file: {:?}", args.vcf_file);
     log::info!("Detectability file: {:?}", args.detectability_file);
-    log::info!("Output file: {:?}", args.output_file);
+    let output_file = args.output_file.clone().unwrap_or_else(|| args.vcf_file.clone());
+    log::info!("Output file: {:?}", output_file);
 
     // Validate input files
     validate_file_readable(&args.vcf_file)?;
     validate_file_readable(&args.detectability_file)?;
+    if let Some(bam) = &args.bam {
+        validate_file_readable(bam)?;
+    }
 
     // Check if output file exists and handle accordingly
-    if args.output_file.exists() && !args.force {
+    if !args.in_place && output_file.exists() && !args.force {
         return Err(VlodError::Io(std::io::Error::new(
             std::io::ErrorKind::AlreadyExists,
-            format!("Output file {:?} already exists. Use --force to overwrite.", args.output_file),
+            format!("Output file {:?} already exists. Use --force to overwrite.", output_file),
         )));
     }
 
     // Create output directory if it doesn't exist
-    if let Some(parent) = args.output_file.parent() {
+    if let Some(parent) = output_file.parent() {
         std::fs::create_dir_all(parent)?;
     }
+    let input_size = std::fs::metadata(&args.vcf_file).map(|m| m.len());
 
     // Perform the merge operation
     let _timer = Timer::new("Merging detectability results into VCF");
-    merge_detectability_into_vcf(&args.vcf_file, &args.detectability_file, &args.output_file)?;
+    let options = MergeOptions {
+        duplicate_policy: args.duplicate_policy,
+        strict_contig_names: args.strict_contig_names,
+        contigs: args.bam.as_ref().map(bam_contigs).transpose()?.unwrap_or_default(),
+        max_line_length: args.max_line_length,
+        max_errors: args.max_errors,
+        orientation_info: args.orientation_info,
+        strand_info: args.strand_info,
+        depth_discordance_fold: args.depth_discordance,
+        caller: args.caller,
+        header_lines: Vec::new(),
+        keep_annotated: false,
+        info_fields: true,
+        // The header records the threshold the results were scored at
+        score_threshold: read_results_threshold(&args.detectability_file, args.max_line_length)?
+            .unwrap_or(args.threshold),
+    };
+    let stats = if args.in_place {
+        annotate_in_place(&args.vcf_file, |output| {
+            merge_detectability_into_vcf(args.vcf_file.as_path(), args.detectability_file.as_path(), output, &options)
+        })?
+    } else {
+        merge_detectability_into_vcf(&args.vcf_file, &args.detectability_file, &output_file, &options)?
+    };
 
     log::info!("Merge operation completed successfully");
-    log::info!("Output written to: {:?}", args.output_file);
+    stats.log_summary();
+    log::info!("Output written to: {:?}", output_file);
+    if args.checksum_outputs {
+        let sidecar = write_checksum_sidecar(&output_file)?;
+        log::info!("Checksum written to: {:?}", sidecar);
+    }
 
     // Log file sizes for reference
-    if let Ok(input_size) = std::fs::metadata(&args.vcf_file).map(|m| m.len()) {
-        if let Ok(output_size) = std::fs::metadata(&args.output_file).map(|m| m.len()) {
+    if let Ok(input_size) = input_size {
+        if let Ok(output_size) = std::fs::metadata(&output_file).map(|m| m.len()) {
             log::info!("Input VCF size: {} bytes", input_size);
             log::info!("Output VCF size: {} bytes", output_size);
             
@@ -123,6 +229,10 @@ fn handle_error(error: VlodError) -> ! {
             eprintln!("Error: Invalid variant data: {}", msg);
             eprintln!("Please check that your VCF or detectability file is properly formatted.");
         }
+        VlodError::Parse { .. } => {
+            eprintln!("Error: {}", error);
+            eprintln!("Please check that your VCF or detectability file is properly formatted.");
+        }
         VlodError::InvalidConfig(msg) => {
             eprintln!("Error: Invalid configuration: {}", msg);
         }
@@ -138,6 +248,13 @@ fn handle_error(error: VlodError) -> ! {
             eprintln!("Error: CSV processing error: {}", e);
             eprintln!("Please check the detectability file format.");
         }
+        VlodError::Database(ref msg) => {
+            eprintln!("Error: Results database error: {}", msg);
+        }
+        VlodError::Context { context, source } => {
+            eprintln!("Error while {}:", context);
+            handle_error(*source);
+        }
     }
     std::process::exit(1);
 }
@@ -175,6 +292,7 @@ mod tests {
             vcf_file.path(),
             detectability_file.path(),
             output_file.path(),
+            &MergeOptions::default(),
         );
         
         assert!(result.is_ok());
@@ -0,0 +1,123 @@
+//! `vlod calibrate`: fits per-variant-class score thresholds and probability models
+//! from results with known truth
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    calibration::{label_results, read_truth_set, Calibration, ProbabilityMethod},
+    merge::read_detectability_results,
+    VlodError, VlodResult,
+};
+
+#[derive(Args)]
+#[command(about = "Fit per-variant-class detectability thresholds from results with known truth")]
+#[command(long_about = "
+Fits the score threshold maximising Youden's J (sensitivity + specificity - 1)
+separately for SNVs, MNVs, insertions, deletions and complex variants, using
+detectability results (lod_edit TSV output) on samples with known truth.
+
+Truth sets are TSV files with the columns Chrom, Pos, Ref, Alt and Truth
+(detected/undetected). Give one truth set per results file, or a single truth
+set shared by all of them. Classes without both detected and undetected truth
+variants are left uncalibrated and keep the scoring run's --threshold (2.5 by default).
+
+Each class also gets Platt and isotonic score-to-probability models; --method
+selects which one runs when the file is passed back via --calibration, which
+adds the calibrated detection probability as the DETP INFO field.
+")]
+pub struct CalibrateArgs {
+    /// Detectability results TSV, or a VCF annotated by vlod; repeat for several samples
+    #[arg(long, value_name = "FILE", required = true)]
+    results: Vec<PathBuf>,
+
+    /// Truth set TSV matching each results file (or one shared by all)
+    #[arg(long, value_name = "FILE", required = true)]
+    truth: Vec<PathBuf>,
+
+    /// Path to the output calibration file (JSON)
+    #[arg(long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Score-to-probability model used at runtime (platt or isotonic)
+    #[arg(long, default_value = "platt")]
+    method: ProbabilityMethod,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: CalibrateArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    if args.truth.len() != 1 && args.truth.len() != args.results.len() {
+        return Err(VlodError::InvalidConfig(format!(
+            "expected 1 or {} truth sets, got {}",
+            args.results.len(),
+            args.truth.len()
+        )));
+    }
+
+    if args.output.exists() && !args.force {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", args.output),
+        )));
+    }
+
+    let mut observations = Vec::new();
+    for (i, results_path) in args.results.iter().enumerate() {
+        let truth_path = &args.truth[i.min(args.truth.len() - 1)];
+        let results = read_detectability_results(results_path)?;
+        let truth = read_truth_set(truth_path)?;
+        let labelled = label_results(&results, &truth);
+        log::info!(
+            "{:?}: {} of {} results have a truth label in {:?}",
+            results_path,
+            labelled.len(),
+            results.len(),
+            truth_path
+        );
+        observations.extend(labelled);
+    }
+
+    if observations.is_empty() {
+        return Err(VlodError::InvalidConfig(
+            "no results matched a truth set variant".to_string(),
+        ));
+    }
+
+    let calibration = Calibration::fit(&observations, args.method);
+    if calibration.thresholds.is_empty() {
+        log::warn!("No variant class had both detected and undetected truth variants");
+    }
+    for (class, fit) in &calibration.thresholds {
+        log::info!(
+            "  {:?}: threshold {:.3} (J={:.3}, sensitivity {:.3}, specificity {:.3}, n={}/{})",
+            class,
+            fit.threshold,
+            fit.youden_j,
+            fit.sensitivity,
+            fit.specificity,
+            fit.positives,
+            fit.negatives
+        );
+    }
+
+    if let Some(parent) = args.output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    calibration.write(&args.output)?;
+    log::info!("Calibration written to: {:?}", args.output);
+
+    Ok(())
+}
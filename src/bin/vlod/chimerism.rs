@@ -0,0 +1,140 @@
+//! `vlod chimerism`: estimates the donor fraction and chimerism LoD from informative SNPs
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    chimerism::{estimate_chimerism, write_chimerism_report, ChimerismOptions},
+    lod::{calculate_detectability_scores, validate_lod_config},
+    utils::{get_num_cpus, validate_file_readable, Timer},
+    vcf::{dedup_variants, read_vcf_variants, DuplicatePolicy},
+    LodConfig, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
+};
+
+#[derive(Args)]
+#[command(about = "Estimate the donor fraction and chimerism LoD from informative SNPs")]
+#[command(long_about = "
+For transplant chimerism monitoring: reads donor support at informative SNPs
+(a VCF whose ALT alleles are carried by the donor and absent from the recipient)
+and reports the pooled donor mixture fraction with its confidence bounds, and
+the chimerism LoD - the smallest donor fraction that would reach the detection
+threshold with the requested power at the observed depth.
+
+The report TSV starts with a # line holding the aggregate estimate, followed by
+the depth, donor reads and implied mixture fraction of each marker.
+")]
+pub struct ChimerismArgs {
+    /// VCF of informative SNPs; ALT is the donor-specific allele
+    #[arg(long, value_name = "FILE")]
+    markers: PathBuf,
+
+    /// Path to the input BAM file
+    #[arg(long, value_name = "FILE")]
+    input_bam: PathBuf,
+
+    /// Path to the output chimerism report (TSV)
+    #[arg(long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Probability of true positive result
+    #[arg(long = "TP", default_value = "0.999")]
+    tp: f64,
+
+    /// Probability of false positive result
+    #[arg(long = "FP", default_value = "0.001")]
+    fp: f64,
+
+    /// Probability of sequencing error
+    #[arg(long = "SE", default_value = "0.0001")]
+    se: f64,
+
+    /// Score at or above which a variant is detectable
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// ALT allele fraction of the markers in pure donor DNA (1.0 for homozygous,
+    /// 0.5 for heterozygous donor markers)
+    #[arg(long, default_value = "1.0")]
+    donor_allele_fraction: f64,
+
+    /// Confidence of the mixture fraction bounds and power of the LoD
+    #[arg(long, default_value = "0.95")]
+    confidence: f64,
+
+    /// Number of processes to use for parallel processing
+    #[arg(long, default_value_t = get_num_cpus())]
+    num_processes: usize,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: ChimerismArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    validate_file_readable(&args.markers)?;
+    validate_file_readable(&args.input_bam)?;
+    if args.output.exists() && !args.force {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", args.output),
+        )));
+    }
+
+    let config = LodConfig {
+        p_tp: args.tp,
+        p_fp: args.fp,
+        p_se: args.se,
+        score_threshold: args.threshold,
+        ..LodConfig::default()
+    };
+    validate_lod_config(&config)?;
+    let options = ChimerismOptions {
+        donor_allele_fraction: args.donor_allele_fraction,
+        confidence: args.confidence,
+    };
+    options.validate()?;
+
+    let (markers, duplicates) = dedup_variants(read_vcf_variants(&args.markers)?, DuplicatePolicy::First)?;
+    if duplicates > 0 {
+        log::warn!("Skipped {} duplicate markers", duplicates);
+    }
+    log::info!("Read {} informative markers", markers.len());
+
+    let _timer = Timer::new("Counting donor support");
+    let results = calculate_detectability_scores(markers, &args.input_bam, &config, args.num_processes)?;
+    let estimate = estimate_chimerism(&results, &config, &options);
+
+    if let Some(parent) = args.output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_chimerism_report(&estimate, &args.output)?;
+
+    let percent = |value: Option<f64>| {
+        value
+            .map(|v| format!("{:.3}%", v * 100.0))
+            .unwrap_or_else(|| "n/a".to_string())
+    };
+    println!(
+        "Donor fraction {} ({} CI {} to {}), chimerism LoD {} over {} markers ({} reads)",
+        percent(estimate.mixture_fraction),
+        options.confidence,
+        percent(estimate.lower_bound),
+        percent(estimate.upper_bound),
+        percent(estimate.lod),
+        estimate.markers.len(),
+        estimate.depth
+    );
+    log::info!("Chimerism report written to: {:?}", args.output);
+
+    Ok(())
+}
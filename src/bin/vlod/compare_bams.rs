@@ -0,0 +1,143 @@
+//! `vlod compare-bams`: compares the detectability of one variant set in two BAMs
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    bam_comparison::{compare_bam_results, write_bam_comparison, write_bam_comparison_to_writer, BamComparisonSummary},
+    lod::{calculate_detectability_scores, validate_lod_config},
+    utils::{get_num_cpus, validate_file_readable, Timer},
+    vcf::{dedup_variants, read_vcf_variants, DuplicatePolicy},
+    LodConfig, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
+};
+
+#[derive(Args)]
+#[command(about = "Compare the detectability of one variant set in two BAMs")]
+#[command(long_about = "
+Scores the variants of a VCF against two BAMs of the same sample, e.g. an old and
+a new library prep, with the same configuration, and reports for every variant
+the condition, score and coverage with each BAM, the score and coverage deltas
+and whether the classification changed. The log summarizes the variants that
+became detectable or stopped being detectable, by classification change.
+
+The report TSV is written to --output, or to standard output.
+")]
+pub struct CompareBamsArgs {
+    /// BAM the comparison starts from
+    #[arg(value_name = "OLD_BAM")]
+    old: PathBuf,
+
+    /// BAM compared with it
+    #[arg(value_name = "NEW_BAM")]
+    new: PathBuf,
+
+    /// VCF of the variants to score
+    #[arg(long, value_name = "FILE")]
+    input_vcf: PathBuf,
+
+    /// Path to the comparison report TSV (standard output when omitted)
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Probability of true positive result
+    #[arg(long = "TP", default_value = "0.999")]
+    tp: f64,
+
+    /// Probability of false positive result
+    #[arg(long = "FP", default_value = "0.001")]
+    fp: f64,
+
+    /// Probability of sequencing error
+    #[arg(long = "SE", default_value = "0.0001")]
+    se: f64,
+
+    /// Score at or above which a variant is detectable
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Minimum base quality of the read bases at an SNV or MNV (0 counts every read)
+    #[arg(long, default_value_t = 0)]
+    min_base_quality: u8,
+
+    /// Minimum mapping quality (MAPQ) of a read (0 counts every read)
+    #[arg(long, default_value_t = 0)]
+    min_mapping_quality: u8,
+
+    /// Number of processes to use for parallel processing
+    #[arg(long, default_value_t = get_num_cpus())]
+    num_processes: usize,
+
+    /// Exit with status 2 when a variant changed classification
+    #[arg(long)]
+    exit_code: bool,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: CompareBamsArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    validate_file_readable(&args.input_vcf)?;
+    validate_file_readable(&args.old)?;
+    validate_file_readable(&args.new)?;
+    if let Some(output) = args.output.as_ref().filter(|output| output.exists() && !args.force) {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", output),
+        )));
+    }
+
+    let config = LodConfig {
+        p_tp: args.tp,
+        p_fp: args.fp,
+        p_se: args.se,
+        score_threshold: args.threshold,
+        min_base_quality: args.min_base_quality,
+        min_mapping_quality: args.min_mapping_quality,
+        ..LodConfig::default()
+    };
+    validate_lod_config(&config)?;
+
+    let (variants, duplicates) = dedup_variants(read_vcf_variants(&args.input_vcf)?, DuplicatePolicy::First)?;
+    if duplicates > 0 {
+        log::warn!("Skipped {} duplicate variants", duplicates);
+    }
+    log::info!("Read {} variants from {:?}", variants.len(), args.input_vcf);
+
+    let old = {
+        let _timer = Timer::new("Scoring against the old BAM");
+        calculate_detectability_scores(variants.clone(), &args.old, &config, args.num_processes)?
+    };
+    let new = {
+        let _timer = Timer::new("Scoring against the new BAM");
+        calculate_detectability_scores(variants, &args.new, &config, args.num_processes)?
+    };
+    let deltas = compare_bam_results(&old, &new);
+    let summary = BamComparisonSummary::new(&deltas);
+    summary.log();
+
+    match &args.output {
+        Some(output) => write_bam_comparison(&deltas, output)?,
+        None => write_bam_comparison_to_writer(&deltas, std::io::stdout().lock())?,
+    }
+
+    eprintln!(
+        "{} variants compared, {} changed classification: {} became detectable, {} no longer detectable",
+        summary.variants, summary.changed, summary.gained, summary.lost
+    );
+
+    if args.exit_code && summary.changed > 0 {
+        std::process::exit(2);
+    }
+    Ok(())
+}
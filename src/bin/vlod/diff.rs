@@ -0,0 +1,113 @@
+//! `vlod diff`: reports variants whose detectability changed between two runs
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    compare::{
+        compare_results, read_compared_results, write_difference_report, write_difference_report_to_writer,
+        ChangeCause, DifferenceKind, ScoreTolerance,
+    },
+    utils::validate_file_readable,
+    VlodError, VlodResult,
+};
+
+#[derive(Args)]
+#[command(about = "Report variants whose detectability changed between two runs")]
+#[command(long_about = "
+Compares two detectability result sets, each a results TSV (lod_edit, or the
+Python vLoD) or a VCF annotated by vlod or merge_vcf_lod, and reports every
+variant whose classification flipped, whose score moved beyond the tolerance,
+or that only one run reported.
+
+Changed variants are grouped by likely cause:
+- coverage_change: the site coverage differs (results TSVs only)
+- threshold_change: same score, different classification
+- score_change: the score moved at unchanged coverage, e.g. after a prior or
+  pipeline change
+
+The report TSV is written to --output, or to standard output.
+")]
+pub struct DiffArgs {
+    /// Old results (TSV or annotated VCF)
+    #[arg(value_name = "OLD")]
+    old: PathBuf,
+
+    /// New results (TSV or annotated VCF)
+    #[arg(value_name = "NEW")]
+    new: PathBuf,
+
+    /// Path to the difference report TSV (standard output when omitted)
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Absolute score tolerance
+    #[arg(long, default_value = "1e-6")]
+    score_tolerance: f64,
+
+    /// Relative score tolerance
+    #[arg(long, default_value = "1e-6")]
+    relative_tolerance: f64,
+
+    /// Exit with status 2 when the results differ
+    #[arg(long)]
+    exit_code: bool,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: DiffArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    validate_file_readable(&args.old)?;
+    validate_file_readable(&args.new)?;
+    if let Some(output) = args.output.as_ref().filter(|output| output.exists() && !args.force) {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", output),
+        )));
+    }
+
+    let tolerance = ScoreTolerance {
+        absolute: args.score_tolerance,
+        relative: args.relative_tolerance,
+    };
+    let old = read_compared_results(&args.old)?;
+    let new = read_compared_results(&args.new)?;
+    let mut comparison = compare_results(&old, &new, &tolerance);
+    comparison.log_summary("old", "new");
+
+    // Group the report by cause, variants only in one run last
+    comparison.differences.sort_by_key(|difference| (difference.cause.is_none(), difference.cause));
+    match &args.output {
+        Some(output) => write_difference_report(&comparison, ("Old", "New"), output)?,
+        None => write_difference_report_to_writer(&comparison, ("Old", "New"), std::io::stdout().lock())?,
+    }
+
+    eprintln!(
+        "{} variants in both runs, {} changed: {} coverage_change, {} threshold_change, {} score_change; \
+         {} only in old, {} only in new",
+        comparison.compared,
+        comparison.count(DifferenceKind::Flip) + comparison.count(DifferenceKind::Score),
+        comparison.count_cause(ChangeCause::Coverage),
+        comparison.count_cause(ChangeCause::Threshold),
+        comparison.count_cause(ChangeCause::Score),
+        comparison.count(DifferenceKind::OnlyOld),
+        comparison.count(DifferenceKind::OnlyNew)
+    );
+
+    if args.exit_code && !comparison.is_concordant() {
+        std::process::exit(2);
+    }
+    Ok(())
+}
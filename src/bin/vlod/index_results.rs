@@ -0,0 +1,60 @@
+//! `vlod index-results`: writes a bgzip-compressed, tabix-indexed copy of a
+//! detectability TSV
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    results_index::{index_results, results_index_path},
+    utils::append_extension,
+    VlodError, VlodResult,
+};
+
+#[derive(Args)]
+#[command(about = "Write a bgzip-compressed, tabix-indexed copy of a detectability TSV")]
+#[command(long_about = "
+Sorts a detectability TSV (from lod_edit or --tsv-output) by contig and
+position, writes it bgzip-compressed and builds its tabix index (.tbi). Merging
+an indexed TSV with merge_vcf_lod looks each VCF record up through the index
+instead of loading every result into memory, for result sets too large to hold.
+The indexed copy remains a results TSV that every vlod command reads.
+")]
+pub struct IndexResultsArgs {
+    /// Detectability TSV to index (plain or gzipped)
+    #[arg(value_name = "FILE")]
+    results: PathBuf,
+
+    /// Path of the indexed copy [default: FILE.gz]
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of the output files if they exist
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: IndexResultsArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    let output = args.output.clone().unwrap_or_else(|| append_extension(&args.results, "gz"));
+    if !args.force {
+        if let Some(existing) = [output.clone(), results_index_path(&output)].into_iter().find(|path| path.exists()) {
+            return Err(VlodError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Output file {:?} already exists. Use --force to overwrite.", existing),
+            )));
+        }
+    }
+
+    let index = index_results(&args.results, &output)?;
+    println!("Indexed results written to {:?} (index {:?})", output, index);
+    Ok(())
+}
@@ -0,0 +1,1626 @@
+//! Combined CLI binary for vLoD - performs detectability analysis and VCF annotation in one step
+
+mod calibrate;
+mod chimerism;
+mod compare_bams;
+mod diff;
+mod index_results;
+mod make_test_data;
+mod plan_topup;
+mod serve;
+mod serve_grpc;
+mod verify_output;
+mod watch;
+
+use clap::{Parser, Subcommand};
+use env_logger::Env;
+use std::collections::{HashMap, HashSet};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use vlod_rs::{
+    annotation::{AnnotationTable, SignificanceSummary, CLINVAR_SIGNIFICANCE_FIELD},
+    audit::{new_request_id, AuditEntry, AuditEvent, AuditLog},
+    claims::{log_claim_verdicts, ClaimSet, CLAIMS_FAILED_EXIT_CODE},
+    bam::{
+        bam_contigs, sample_background_noise, DeletedReadPolicy,
+        DepthCapPolicy, MissingQualityPolicy, RetryPolicy, ShortFragmentPolicy, VafDefinition, DEFAULT_MAX_PILEUP_DEPTH,
+    },
+    bcf::{bcf_to_vcf, is_bcf, is_bcf_path, vcf_to_bcf},
+    calibration::{read_truth_set, Calibration},
+    confirmation::RefConfirmation,
+    contamination::{screen_contamination, DEFAULT_CONTAMINATION_THRESHOLD},
+    contig::{AltContigMap, ContigPolicy},
+    gtf::{gene_regions, read_exons},
+    incremental::{config_hash, config_header_line, read_prior_annotations},
+    integrity::write_checksum_sidecar,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    lod::{
+        calculate_detectability_scores, io_retries, log_filter_stratified_summary, validate_lod_config,
+        with_site_aggregates,
+    },
+    manifest::RunManifest,
+    merge::{merge_detectability_results_into_vcf, merge_sample_results_into_vcf, MergeOptions},
+    noise::{sample_positions, NOISE_SAMPLING_SEED},
+    pool::PoolDesign,
+    presets::{resolve_preset, PresetChoice},
+    read_filter::ReadFilter,
+    reference::{log_ref_mismatches, ReferenceFasta},
+    regions::{read_regions, retain_in_regions, write_bed_regions, AmpliconSet},
+    streaming::{stream_annotate_vcf, StreamingConfig, DEFAULT_STREAM_BATCH_SIZE},
+    rollup::{rollup_by_feature, write_rollup},
+    sample::{resolve_sample_name, SampleBam},
+    summary::{multiqc_path, write_multiqc, RunSummary},
+    sweep::{sweep_thresholds, write_sweep_results, ThresholdSweep},
+    titration::{titration_fractions, write_titration_results},
+    uniformity::{measure_uniformity, DEFAULT_COVERAGE_THRESHOLDS},
+    utils::{
+        append_extension, create_output_file, ensure_parent_dirs, get_num_cpus, validate_file_readable, ResourceSampler,
+        ScratchFile, Timer, DEFAULT_MAX_LINE_LENGTH, RESOURCE_SAMPLE_INTERVAL,
+    },
+    vcf::{
+        apply_monomorphic_policy, check_sort_order, Caller, dedup_variants, normalize_overrides, normalize_variants,
+        read_vcf_input, read_vcf_variants, restore_original_variants, select_pass_variants, sort_vcf_file,
+        union_variants, DuplicatePolicy, MonomorphicPolicy, VariantOrigins, VariantOverrideMap, VcfReadLimits,
+    },
+    verify::{validate_verify_fraction, verify_counts, VERIFY_SAMPLING_SEED},
+    warnings::{WarningKind, Warnings},
+    LodConfig, Variant, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD, DEFAULT_SEQUENCING_ERROR_RATE,
+};
+
+use calibrate::CalibrateArgs;
+use chimerism::ChimerismArgs;
+use compare_bams::CompareBamsArgs;
+use diff::DiffArgs;
+use index_results::IndexResultsArgs;
+use make_test_data::MakeTestDataArgs;
+use plan_topup::PlanTopupArgs;
+use serve::ServeArgs;
+use serve_grpc::ServeGrpcArgs;
+use verify_output::VerifyOutputArgs;
+use watch::WatchArgs;
+
+#[derive(Parser)]
+#[command(name = "vlod")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(about = "vLoD - Variant Limit of Detection analysis and VCF annotation tool")]
+#[command(long_about = "
+vLoD (Variant Limit of Detection) analyzes the detectability of variants in a VCF file
+by examining corresponding BAM alignment data and directly annotates the VCF with results.
+
+This tool combines detectability analysis and VCF annotation in a single step:
+1. Reads variants from the input VCF file
+2. Analyzes BAM alignment data to calculate detectability scores
+3. Annotates the VCF with detectability information and writes the output
+
+The BAM index file (.bai, or .csi for contigs longer than 2^29 bases) must be
+present next to the BAM file. The tool will automatically look for files with
+.bam.bai, .bai, .bam.csi or .csi extensions.
+
+##contig lines are taken from the BAM header when the input VCF has none.
+
+Two new INFO fields are added to the output VCF:
+- DET: Detectability status (Yes, No, NoCoverage for sites without reads, or
+  Monomorphic for ALT '.' sites with --monomorphic-policy report)
+- DETS: Detectability score (float)
+
+With --calibration, DETP (calibrated detection probability) is added as well.
+
+With --coverage-only, reads are not classified into alleles: every covered site
+is reported CoverageOnly with the minimum VAF detectable at its depth, for
+coverage certification at a fraction of the run time.
+
+With --ref-confirm-vaf, monomorphic sites are instead reported as REF_CONFIRMED
+when enough reads support the reference to rule out a variant at that VAF, and
+REF_UNCONFIRMED otherwise.
+
+Several VCFs for the same sample (e.g. from different callers) can be annotated
+in one run by repeating --input-vcf; --output is then a directory receiving one
+annotated VCF per input, named after it. Variants present in more than one input
+are analysed once.
+
+Input VCFs may be BCF; an output path ending in .bcf is written as BCF. BCF is
+converted through a scratch VCF next to the output, needing that much free space.
+
+With --streaming, a VCF too large to hold in memory (e.g. a whole-genome VCF) is
+scored and annotated in batches of --stream-batch-size records as it is read.
+
+For a multi-sample VCF with one BAM per sample, give each BAM as
+--input-bam SAMPLE=FILE: every sample is scored against its own BAM and DET/DETS
+are added as FORMAT fields of its column. The first BAM supplies the INFO fields
+and every other output; --format-only leaves INFO unchanged.
+
+For advanced use cases requiring separate analysis and annotation steps,
+use the individual tools: lod_edit and merge_vcf_lod.
+
+Run `vlod COMMAND --help` for the commands listed below, e.g. `vlod calibrate` to
+fit per-variant-class score thresholds to pass back via --calibration.
+")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Calibrate(CalibrateArgs),
+    Chimerism(ChimerismArgs),
+    CompareBams(CompareBamsArgs),
+    Diff(DiffArgs),
+    PlanTopup(PlanTopupArgs),
+    IndexResults(IndexResultsArgs),
+    Serve(ServeArgs),
+    ServeGrpc(ServeGrpcArgs),
+    Watch(WatchArgs),
+    MakeTestData(MakeTestDataArgs),
+    VerifyOutput(VerifyOutputArgs),
+}
+
+// Options of the combined analysis, run when no command is given
+#[derive(clap::Args)]
+struct Args {
+    /// Path to the input VCF file; repeat to annotate several VCFs (e.g. from
+    /// different callers) against the same BAM in one run
+    #[arg(long, value_name = "FILE", required = true)]
+    input_vcf: Vec<PathBuf>,
+
+    /// Path to the input BAM file; for a multi-sample VCF, repeat as SAMPLE=FILE
+    /// to score each sample column against its own BAM
+    #[arg(long, value_name = "[SAMPLE=]FILE", required = true)]
+    input_bam: Vec<SampleBam>,
+
+    /// With SAMPLE=FILE BAMs, write DET/DETS only as FORMAT fields, leaving the
+    /// INFO column unchanged
+    #[arg(long)]
+    format_only: bool,
+
+    /// Path to the output annotated VCF file (BCF when it ends in .bcf), or the
+    /// output directory when several input VCFs are given
+    #[arg(long, value_name = "PATH")]
+    output: PathBuf,
+
+    /// Probability of true positive result
+    #[arg(long = "TP", default_value = "0.999")]
+    tp: f64,
+
+    /// Probability of false positive result
+    #[arg(long = "FP", default_value = "0.001")]
+    fp: f64,
+
+    /// Probability of sequencing error [default: 0.0001, or the aligner preset's]
+    #[arg(long = "SE")]
+    se: Option<f64>,
+
+    /// Score at or above which a variant is detectable (calibrated class thresholds
+    /// and VLOD_THRESHOLD overrides still take precedence)
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Aligner preset setting the sequencing error rate and read filters: auto
+    /// (detected from the BAM @PG lines), none, bwa, dragen, minimap2-sr,
+    /// minimap2-hifi, minimap2-ont or star. --SE and --read-filter still apply.
+    #[arg(long, default_value = "auto")]
+    preset: PresetChoice,
+
+    /// VAF scored: total (ALT reads over all reads assigned to an allele, including
+    /// other ALTs) or ref-alt (ALT reads over REF plus ALT reads). Both are reported.
+    #[arg(long, default_value = "total")]
+    vaf_definition: VafDefinition,
+
+    /// Report sites whose reads support a third allele (neither REF nor ALT) above
+    /// this fraction as Not-assessable instead of Detectable
+    #[arg(long, value_name = "FRACTION")]
+    max_other_allele_fraction: Option<f64>,
+
+    /// Reads carrying a deletion over an SNV or MNV site: exclude them from the
+    /// depth, or count them toward it as reads not supporting the ALT. They are
+    /// reported either way.
+    #[arg(long, default_value = "exclude")]
+    deleted_reads: DeletedReadPolicy,
+
+    /// Reads from fragments shorter than the read, whose mates overlap completely
+    /// (adapter read-through in degraded cfDNA): keep both mates, collapse them to
+    /// one read, or exclude them. Their fraction is reported either way.
+    #[arg(long, default_value = "keep")]
+    short_fragments: ShortFragmentPolicy,
+
+    /// Minimum base quality of the read bases at an SNV or MNV: a read with any base
+    /// of the site below it is left out of the site's counts entirely. Indel sites
+    /// are not filtered. 0 counts every read.
+    #[arg(long, default_value_t = 0)]
+    min_base_quality: u8,
+
+    /// Minimum mapping quality (MAPQ) of a read; reads below it count towards
+    /// neither the depth nor any allele (0 counts every read)
+    #[arg(long, default_value_t = 0)]
+    min_mapping_quality: u8,
+
+    /// Reads without base qualities (QUAL `*`): count them, giving their bases
+    /// quality Q (`default:Q`), or exclude them. Their number is reported in the
+    /// run summary either way.
+    #[arg(long, default_value = "default:30")]
+    missing_quality: MissingQualityPolicy,
+
+    /// Measure only the depth of each site, without classifying reads into alleles,
+    /// and report the minimum VAF detectable at that depth (Coverage-only condition)
+    #[arg(long)]
+    coverage_only: bool,
+
+    /// Named amplicons (BED, Picard interval_list or `chr1:100-200 NAME` lines);
+    /// splits read support by amplicon of origin
+    #[arg(long, value_name = "FILE")]
+    amplicon_bed: Option<PathBuf>,
+
+    /// Bisulfite/EM-seq mode: ignore C>T (G>A on the reverse strand) conversions
+    #[arg(long)]
+    bisulfite: bool,
+
+    /// FFPE mode: raise the error prior of low-VAF C>T/G>A changes so deamination
+    /// artifacts do not count as detectable support
+    #[arg(long)]
+    ffpe: bool,
+
+    /// Read filter on a BAM aux tag, repeatable: no:TAG, has:TAG, min:TAG:VALUE or
+    /// max:TAG:VALUE (e.g. --read-filter no:SA --read-filter min:AS:50)
+    #[arg(long, value_name = "FILTER")]
+    read_filter: Vec<ReadFilter>,
+
+    /// Pooled design of N diploid samples: a single-copy allele (VAF 1/(2N)) counts
+    /// as detectable, and the power to detect one is reported per variant
+    #[arg(long, value_name = "N")]
+    pool_size: Option<u32>,
+
+    /// Write detectability at downsampled read fractions to this CSV file
+    #[arg(long, value_name = "FILE")]
+    titration_output: Option<PathBuf>,
+
+    /// Write a JSON summary of the run (provenance, result counts, warnings such as
+    /// skipped records, with examples, and the wall time, CPU time and peak memory
+    /// of each stage) to this file
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+
+    /// Fraction step for the coverage titration (e.g. 0.1 for 10%, 20%, ..., 100%)
+    #[arg(long, default_value = "0.1")]
+    titration_step: f64,
+
+    /// ClinVar VCF (optionally gzipped); each result's CLNSIG clinical significance
+    /// is joined on by contig, position and alleles, and the run summary counts
+    /// the pathogenic variants not Detectable
+    #[arg(long, value_name = "FILE")]
+    clinvar: Option<PathBuf>,
+
+    /// GTF or GFF3 annotation; detectability is rolled up per exon and transcript
+    /// into --rollup-output, and the genes of --genes are located in it
+    #[arg(long, value_name = "FILE")]
+    gtf: Option<PathBuf>,
+
+    /// Write the per-exon and per-transcript rollup to this TSV file
+    #[arg(long, value_name = "FILE", requires = "gtf")]
+    rollup_output: Option<PathBuf>,
+
+    /// Target VAF at which rolled-up variant positions count as assessable
+    #[arg(long, value_name = "VAF", default_value = "0.05")]
+    rollup_vaf: f64,
+
+    /// Analyze only the variants in the exons of these genes (comma-separated
+    /// symbols, located through --gtf)
+    #[arg(long, value_name = "GENES", value_delimiter = ',', requires = "gtf")]
+    genes: Vec<String>,
+
+    /// Write the regions analyzed for --genes (merged exons, named by gene) to this
+    /// BED file [default: OUTPUT.genes.bed, or genes.bed in the
+    /// output directory when several input VCFs are given]
+    #[arg(long, value_name = "FILE", requires = "genes")]
+    genes_bed: Option<PathBuf>,
+
+    /// Reference FASTA, plain or bgzipped with .fai (and .gzi) indexes; VCF REF
+    /// alleles are checked against it, and so are the soft-clipped bases of reads
+    /// past an insertion at their end
+    #[arg(long, value_name = "FILE")]
+    reference: Option<PathBuf>,
+
+    /// Left-align and trim the alleles of indels and complex variants before they
+    /// are looked up in the BAM (callers may not left-align them), using
+    /// --reference; without it alleles are only trimmed. Results still annotate the
+    /// records as they are written in the input VCF.
+    #[arg(long)]
+    normalize: bool,
+
+    /// Build missing --reference indexes instead of failing
+    #[arg(long, requires = "reference")]
+    auto_faidx: bool,
+
+    /// Append the results to this SQLite database (created if missing), with one
+    /// run per sample; requires the `sqlite` feature
+    #[arg(long, value_name = "FILE")]
+    output_db: Option<PathBuf>,
+
+    /// Write the sample's metrics (% detectable, median score and depth) as
+    /// `<sample>_vlod_mqc.json` to this directory, for MultiQC to pick up
+    #[arg(long, value_name = "DIR")]
+    multiqc_dir: Option<PathBuf>,
+
+    /// Sample name recorded in the VCF header, the run summary, --output-db and
+    /// --multiqc-dir [default: the BAM read-group SM tag, the first VCF's only
+    /// sample or the BAM file name]
+    #[arg(long, value_name = "NAME", alias = "db-sample")]
+    sample_name: Option<String>,
+
+    /// Write a SHA-256 checksum sidecar (`<output>.sha256`) next to each output;
+    /// check it later with `vlod verify-output`
+    #[arg(long)]
+    checksum_outputs: bool,
+
+    /// Append audit entries for this run (request id, user, samples, configuration
+    /// hash and output checksums) to this JSON-lines file
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Write a JSON manifest with the MD5 checksums of all inputs and the
+    /// arguments of this run; the checksums are also added to the VCF header
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Fail unless the inputs have the checksums recorded in this manifest (e.g.
+    /// from the validation run)
+    #[arg(long, value_name = "FILE")]
+    verify_checksums: Option<PathBuf>,
+
+    /// Reclassify the results at each threshold START:STOP:STEP (e.g. 1.0:4.0:0.1)
+    /// and write the counts of flipped variants to --sweep-output
+    #[arg(long, value_name = "START:STOP:STEP", requires = "sweep_output")]
+    threshold_sweep: Option<ThresholdSweep>,
+
+    /// Write the threshold sweep to this CSV file
+    #[arg(long, value_name = "FILE", requires = "threshold_sweep")]
+    sweep_output: Option<PathBuf>,
+
+    /// Truth set TSV (Chrom, Pos, Ref, Alt, Truth) adding sensitivity and
+    /// specificity to the threshold sweep
+    #[arg(long, value_name = "FILE", requires = "threshold_sweep")]
+    sweep_truth: Option<PathBuf>,
+
+    /// Assemble reads locally for variants with conflicting pileup evidence
+    /// (requires the `assembly` feature)
+    #[arg(long)]
+    local_assembly: bool,
+
+    /// Calibration file from `vlod calibrate` with per-variant-class thresholds
+    #[arg(long, value_name = "FILE")]
+    calibration: Option<PathBuf>,
+
+    /// Panel regions sampled for background noise (BED, Picard interval_list or
+    /// `chr1:100-200` lines); per-substitution-class noise percentiles are reported
+    /// in the summary
+    #[arg(long, value_name = "FILE")]
+    noise_bed: Option<PathBuf>,
+
+    /// Number of positions sampled from --noise-bed
+    #[arg(long, default_value = "1000")]
+    noise_samples: usize,
+
+    /// Use the sampled background noise as per-substitution-class sequencing error
+    /// rates instead of --SE
+    #[arg(long, requires = "noise_bed")]
+    noise_error_rates: bool,
+
+    /// Target regions (BED, Picard interval_list or `chr1:100-200` lines) whose
+    /// coverage uniformity is reported in the log and run summary: the fold-80 base
+    /// penalty, the bases at --coverage-thresholds depths and, with --reference,
+    /// the correlation of depth with GC content
+    #[arg(long, value_name = "FILE")]
+    target_bed: Option<PathBuf>,
+
+    /// Depths whose share of the --target-bed bases is reported
+    #[arg(long, value_name = "DEPTHS", value_delimiter = ',', default_values_t = DEFAULT_COVERAGE_THRESHOLDS)]
+    coverage_thresholds: Vec<u32>,
+
+    /// VCF of common SNP sites (e.g. gnomAD SNPs within the panel) screened for
+    /// contamination before scoring: reads carrying the SNP allele absent from a
+    /// homozygous sample are reported in the log and run summary, and the sample is
+    /// flagged when their excess reaches --contamination-threshold
+    #[arg(long, value_name = "FILE")]
+    contamination_sites: Option<PathBuf>,
+
+    /// Excess fraction of unexpected-allele reads at homozygous SNPs from which the
+    /// sample is flagged as contaminated
+    #[arg(long, value_name = "F", default_value_t = DEFAULT_CONTAMINATION_THRESHOLD, requires = "contamination_sites")]
+    contamination_threshold: f64,
+
+    /// Reference-confirmation mode: report monomorphic sites (ALT '.') as
+    /// REF_CONFIRMED when their depth rules out a variant at this assay LoD VAF
+    #[arg(long, value_name = "VAF")]
+    ref_confirm_vaf: Option<f64>,
+
+    /// Probability of sampling a variant read at the assay LoD required to confirm
+    /// the reference
+    #[arg(long, default_value = "0.95")]
+    ref_confirm_confidence: f64,
+
+    /// Report in Required_Depth the depth at which a variant at this expected VAF
+    /// would reach the detection threshold with 95% power, for planning top-up
+    /// sequencing of failed sites
+    #[arg(long, value_name = "VAF")]
+    required_depth_vaf: Option<f64>,
+
+    /// Variant caller conventions of the input VCF: auto to detect the caller from
+    /// the header, generic, dragen to drop <NON_REF> alleles and gVCF reference
+    /// blocks, count LowGQ-only records as PASS and trim padded alleles of
+    /// multi-allelic records, or strelka2 or vardict to read their FORMAT depths
+    #[arg(long, default_value = "auto")]
+    caller: Caller,
+
+    /// How to resolve variants repeated in the input (first, max or error)
+    #[arg(long, default_value = "first")]
+    duplicate_policy: DuplicatePolicy,
+
+    /// Match contig names exactly instead of aliasing chr-prefixed and bare names
+    #[arg(long)]
+    strict_contig_names: bool,
+
+    /// Add the fraction of ALT reads in F1R2 orientation as a DETOB INFO field
+    #[arg(long)]
+    orientation_info: bool,
+
+    /// Add the strand bias of the ALT reads as DETFS (phred-scaled Fisher's exact
+    /// test) and DETSOR (symmetric odds ratio) INFO fields
+    #[arg(long)]
+    strand_info: bool,
+
+    /// Annotate multi-allelic records with a site-level DET/DETS: Detectable if
+    /// any ALT is, with the highest score
+    #[arg(long)]
+    site_aggregates: bool,
+
+    /// Re-annotate a VCF annotated by an earlier run: records carrying DET/DETS from
+    /// a run with the same configuration (its `##vlodConfig` hash) are kept as they
+    /// are, and only the other variants are analysed
+    #[arg(long)]
+    incremental: bool,
+
+    /// Flag records whose BAM coverage differs from the VCF depth (INFO DP or summed
+    /// AD, or the caller's FORMAT depths) by more than FOLD (default 2) with a
+    /// DETDPD INFO field, and log a per-contig discordance table; catches a BAM the
+    /// variants were not called from
+    #[arg(long, value_name = "FOLD", num_args = 0..=1, default_missing_value = "2")]
+    depth_discordance: Option<f64>,
+
+    /// Sort the input VCF into BAM header contig order instead of failing when it
+    /// is unsorted (held in memory; meant for modest-size VCFs)
+    #[arg(long)]
+    sort_input: bool,
+
+    /// Score and annotate the VCF a batch of --stream-batch-size records at a time
+    /// as it is read, holding one batch in memory rather than every variant (for
+    /// whole-genome VCFs). Needs a single input VCF and BAM; outputs built from all
+    /// results at once are not available.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "sort_input", "incremental", "gtf", "clinvar", "reference", "titration_output", "summary_json",
+            "threshold_sweep", "output_db", "multiqc_dir", "verify_fraction", "claims", "target_bed",
+            "site_aggregates", "normalize", "contamination_sites",
+        ]
+    )]
+    streaming: bool,
+
+    /// VCF records scored and annotated at a time with --streaming
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_STREAM_BATCH_SIZE, requires = "streaming")]
+    stream_batch_size: usize,
+
+    /// Longest VCF/TSV line accepted, in bytes; guards against corrupted inputs
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// How to handle monomorphic reference sites with ALT "." (skip, or report the
+    /// depth available to confirm the reference)
+    #[arg(long, default_value = "skip")]
+    monomorphic_policy: MonomorphicPolicy,
+
+    /// Analyse only variants whose FILTER is PASS (non-PASS records are left unannotated)
+    #[arg(long)]
+    pass_only: bool,
+
+    /// Stop after this many invalid VCF records (by default they are all skipped)
+    #[arg(long, value_name = "N")]
+    max_errors: Option<usize>,
+
+    /// Times a BAM read failing with a transient IO error (e.g. on NFS or S3) is
+    /// retried before the run aborts
+    #[arg(long, default_value = "3")]
+    io_retries: u32,
+
+    /// Wait before the first retry of a failed BAM read, doubling on each further
+    /// retry
+    #[arg(long, default_value = "200", value_name = "MS")]
+    io_retry_backoff_ms: u64,
+
+    /// Most BAM readers open at once, for systems with a low open-file limit;
+    /// variants are scheduled in per-chromosome chunks that wait for a free reader
+    #[arg(long, value_name = "N")]
+    max_open_bams: Option<usize>,
+
+    /// Balance the work between processes by the depth the BAM index suggests at
+    /// each variant, scoring deep hotspots in chunks of their own
+    #[arg(long)]
+    balance_depth: bool,
+
+    /// Variants at most this many bases apart on a contig (dense hotspots) are read
+    /// with a single BAM fetch and pileup traversal; 0 fetches each variant alone
+    #[arg(long, default_value = "200", value_name = "BP")]
+    fetch_merge_distance: u64,
+
+    /// Reads a pileup column holds; at deeper sites the pileup drops reads
+    #[arg(long, default_value_t = DEFAULT_MAX_PILEUP_DEPTH, value_name = "N")]
+    max_pileup_depth: u32,
+
+    /// Sites whose pileup reaches --max-pileup-depth: count them again by iterating
+    /// over their reads, or keep the partial counts and flag them (Depth_Capped
+    /// column, depth_capped warning)
+    #[arg(long, default_value = "iterate")]
+    depth_cap: DepthCapPolicy,
+
+    /// Count this fraction of the variants (e.g. 0.01) a second time by iterating
+    /// over their reads and report any disagreement with the pileup counts
+    /// (count_disagreement warning, spot_check in the run summary)
+    #[arg(long, value_name = "F")]
+    verify_fraction: Option<f64>,
+
+    /// Seed choosing the variants spot-checked by --verify-fraction
+    #[arg(long, default_value_t = VERIFY_SAMPLING_SEED, value_name = "N")]
+    verify_seed: u64,
+
+    /// Assay claims (JSON) evaluated against the run: each gets a pass/fail verdict
+    /// in the log and the run summary, and the run exits with status 3 when any
+    /// claim fails
+    #[arg(long, value_name = "FILE")]
+    claims: Option<PathBuf>,
+
+    /// Handling of variants on unplaced, random and alt contigs: analyze, skip
+    /// (reported Not-assessable) or map (scored at the primary locus given by
+    /// --alt-contig-alignment). Contigs missing from the BAM are always reported
+    /// Not-assessable rather than failing the run
+    #[arg(long, default_value = "analyze")]
+    contig_policy: ContigPolicy,
+
+    /// SAM alignments of alt contigs to the primary assembly (e.g. a bwa .alt
+    /// file), used by --contig-policy map
+    #[arg(long, value_name = "FILE")]
+    alt_contig_alignment: Option<PathBuf>,
+
+    /// Number of processes to use for parallel processing
+    #[arg(long, default_value_t = get_num_cpus())]
+    num_processes: usize,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+fn init_logging(verbose: bool, debug: bool) {
+    let log_level = if debug {
+        "debug"
+    } else if verbose {
+        "info"
+    } else {
+        "warn"
+    };
+
+    env_logger::Builder::from_env(Env::default().default_filter_or(log_level))
+        .format_timestamp_secs()
+        .init();
+}
+
+/// Checksums of every input file of a run, when --manifest or --verify-checksums
+/// asks for them
+fn run_manifest(args: &Args) -> VlodResult<Option<RunManifest>> {
+    if args.manifest.is_none() && args.verify_checksums.is_none() {
+        return Ok(None);
+    }
+
+    let _timer = Timer::new("Computing input checksums");
+    let mut inputs: Vec<(&str, &Path)> = args.input_vcf.iter().map(|vcf| ("vcf", vcf.as_path())).collect();
+    inputs.extend(args.input_bam.iter().map(|bam| ("bam", bam.path.as_path())));
+    let optional = [
+        ("amplicon_bed", &args.amplicon_bed),
+        ("noise_bed", &args.noise_bed),
+        ("calibration", &args.calibration),
+        ("reference", &args.reference),
+        ("gtf", &args.gtf),
+        ("sweep_truth", &args.sweep_truth),
+    ];
+    inputs.extend(optional.iter().filter_map(|(role, path)| path.as_deref().map(|path| (*role, path))));
+
+    RunManifest::compute(&inputs, std::env::args().skip(1).collect()).map(Some)
+}
+
+/// Sample names and BAMs of the --input-bam SAMPLE=FILE pairs, in order (none for
+/// a single unnamed BAM)
+fn sample_bams(args: &Args) -> VlodResult<Vec<(String, PathBuf)>> {
+    if args.input_bam.len() == 1 && args.input_bam[0].sample.is_none() {
+        if args.format_only {
+            return Err(VlodError::InvalidConfig("--format-only needs --input-bam SAMPLE=FILE".to_string()));
+        }
+        return Ok(Vec::new());
+    }
+    let mut pairs: Vec<(String, PathBuf)> = Vec::new();
+    for input_bam in &args.input_bam {
+        let Some(sample) = &input_bam.sample else {
+            return Err(VlodError::InvalidConfig(format!(
+                "give every BAM of a multi-sample run as SAMPLE=FILE, not {}",
+                input_bam.path.display()
+            )));
+        };
+        if pairs.iter().any(|(known, _)| known == sample) {
+            return Err(VlodError::InvalidConfig(format!("sample '{}' is given more than one BAM", sample)));
+        }
+        pairs.push((sample.clone(), input_bam.path.clone()));
+    }
+    Ok(pairs)
+}
+
+/// Merge options of a run, before the sample and configuration header lines
+fn merge_options(args: &Args, contigs: Vec<(String, u64)>, manifest: Option<&RunManifest>) -> MergeOptions {
+    MergeOptions {
+        duplicate_policy: args.duplicate_policy,
+        strict_contig_names: args.strict_contig_names,
+        contigs,
+        max_line_length: args.max_line_length,
+        max_errors: args.max_errors,
+        orientation_info: args.orientation_info,
+        strand_info: args.strand_info,
+        depth_discordance_fold: args.depth_discordance,
+        caller: args.caller,
+        header_lines: manifest.map(RunManifest::vcf_header_lines).unwrap_or_default(),
+        keep_annotated: false,
+        info_fields: !args.format_only,
+        score_threshold: args.threshold,
+    }
+}
+
+/// One input VCF of a run and where its annotated copy is written
+struct BatchInput {
+    input_vcf: PathBuf,
+    output: PathBuf,
+    /// VCF text decoded from a BCF input
+    decoded_vcf: Option<ScratchFile>,
+    /// Copy of the input sorted into BAM contig order (--sort-input)
+    sorted_vcf: Option<ScratchFile>,
+    /// Keep the DET/DETS records of an earlier run with this configuration (--incremental)
+    keep_annotated: bool,
+}
+
+impl BatchInput {
+    /// The input as VCF text: the decoded copy of a BCF
+    fn source(&self) -> &Path {
+        self.decoded_vcf.as_ref().map(|f| f.path()).unwrap_or(&self.input_vcf)
+    }
+
+    /// The VCF to read: the sorted copy when there is one
+    fn vcf(&self) -> &Path {
+        self.sorted_vcf.as_ref().map(|f| f.path()).unwrap_or(self.source())
+    }
+}
+
+/// Pair each input VCF with its output path. A single input is written to
+/// --output; several are written into the --output directory under their own names.
+fn batch_inputs(args: &Args) -> VlodResult<Vec<BatchInput>> {
+    if let [input_vcf] = args.input_vcf.as_slice() {
+        return Ok(vec![BatchInput {
+            input_vcf: input_vcf.clone(),
+            output: args.output.clone(),
+            decoded_vcf: None,
+            sorted_vcf: None,
+            keep_annotated: false,
+        }]);
+    }
+
+    let mut outputs = HashSet::new();
+    args.input_vcf
+        .iter()
+        .map(|input_vcf| {
+            let file_name = input_vcf
+                .file_name()
+                .ok_or_else(|| VlodError::InvalidConfig(format!("{:?} is not a VCF file path", input_vcf)))?;
+            let output = args.output.join(file_name);
+            if !outputs.insert(output.clone()) {
+                return Err(VlodError::InvalidConfig(format!(
+                    "several input VCFs are named {:?}; their outputs would collide in {:?}",
+                    file_name, args.output
+                )));
+            }
+            Ok(BatchInput {
+                input_vcf: input_vcf.clone(),
+                output,
+                decoded_vcf: None,
+                sorted_vcf: None,
+                keep_annotated: false,
+            })
+        })
+        .collect()
+}
+
+/// How a combined analysis that did not fail ended. The process exits with its
+/// status from `main`, once the run's scratch files were dropped and removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunOutcome {
+    Completed,
+    /// Stopped by SIGINT/SIGTERM after writing partial outputs
+    Interrupted,
+    /// Completed, but failed its --claims
+    ClaimsFailed,
+}
+
+/// Run the combined analysis, recording a Failed entry in the --audit-log if it
+/// fails after its Started entry
+fn run(args: Args) -> VlodResult<RunOutcome> {
+    let mut audit = None;
+    let result = run_audited(args, &mut audit);
+    if let (Err(e), Some((audit_log, mut entry))) = (&result, audit) {
+        entry.detail = Some(e.to_string());
+        if let Err(record_error) = audit_log.record(&entry, AuditEvent::Failed) {
+            log::warn!("Could not record the failed run in the audit log: {}", record_error);
+        }
+    }
+    result
+}
+
+/// The combined analysis; `audit` holds the audit log and entry of the run from
+/// its Started entry until the entry that ends it is recorded
+fn run_audited(args: Args, audit: &mut Option<(AuditLog, AuditEntry)>) -> VlodResult<RunOutcome> {
+    // Initialize logging
+    init_logging(args.verbose, args.debug);
+
+    // Flush completed results instead of dying on Ctrl-C or a scheduler's SIGTERM
+    interrupt::install_signal_handlers();
+
+    log::info!("Starting vLoD combined analysis");
+    for input_vcf in &args.input_vcf {
+        log::info!("Input VCF: {:?}", input_vcf);
+    }
+    for input_bam in &args.input_bam {
+        log::info!("Input BAM: {}", input_bam);
+    }
+    log::info!("Output: {:?}", args.output);
+    log::info!("Number of processes: {}", args.num_processes);
+
+    // Validate input files
+    for input_vcf in &args.input_vcf {
+        validate_file_readable(input_vcf)?;
+    }
+    for input_bam in &args.input_bam {
+        validate_file_readable(&input_bam.path)?;
+    }
+    let sample_bams = sample_bams(&args)?;
+    let input_bam = args.input_bam[0].path.as_path();
+    let exons = match &args.gtf {
+        Some(gtf) => {
+            if !(args.rollup_vaf > 0.0 && args.rollup_vaf <= 1.0) {
+                return Err(VlodError::InvalidConfig("--rollup-vaf must be in (0, 1]".to_string()));
+            }
+            let exons = read_exons(gtf)?;
+            log::info!("Read {} exons from {:?}", exons.len(), gtf);
+            Some(exons)
+        }
+        None => None,
+    };
+    let gene_footprint = match &exons {
+        Some(exons) if !args.genes.is_empty() => {
+            let regions = gene_regions(exons, &args.genes)?;
+            let genes_bed = args.genes_bed.clone().unwrap_or_else(|| {
+                if args.input_vcf.len() > 1 {
+                    args.output.join("genes.bed")
+                } else {
+                    append_extension(&args.output, "genes.bed")
+                }
+            });
+            ensure_parent_dirs(&genes_bed)?;
+            write_bed_regions(&regions, &genes_bed)?;
+            log::info!(
+                "Restricting the analysis to {} exonic regions of {} genes, written to {:?}",
+                regions.len(),
+                args.genes.len(),
+                genes_bed
+            );
+            Some(regions)
+        }
+        _ => None,
+    };
+
+    let sweep_truth = args.sweep_truth.as_ref().map(read_truth_set).transpose()?;
+    let claims = args.claims.as_ref().map(ClaimSet::from_file).transpose()?;
+    let targets = args.target_bed.as_ref().map(read_regions).transpose()?;
+    let reference = args
+        .reference
+        .as_ref()
+        .map(|reference| ReferenceFasta::open(reference, args.auto_faidx))
+        .transpose()?;
+
+    let manifest = run_manifest(&args)?;
+    if let (Some(manifest), Some(recorded)) = (&manifest, &args.verify_checksums) {
+        manifest.verify(&RunManifest::from_file(recorded)?)?;
+        log::info!("Input checksums match manifest {:?}", recorded);
+    }
+
+    let mut inputs = batch_inputs(&args)?;
+
+    // Check if output files exist and handle accordingly
+    for input in &inputs {
+        if input.output.exists() && !args.force {
+            return Err(VlodError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Output file {:?} already exists. Use --force to overwrite.", input.output),
+            )));
+        }
+
+        // Create output directory if it doesn't exist
+        if let Some(parent) = input.output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    // Aligner preset, unless overridden
+    let preset = resolve_preset(args.preset, input_bam)?;
+    let mut read_filters = args.read_filter.clone();
+    if let Some(preset) = preset {
+        log::info!("Using the {} preset ({})", preset.name, preset.description);
+        read_filters.extend(preset.read_filters());
+    }
+
+    // Create LOD configuration
+    let mut config = LodConfig {
+        p_tp: args.tp,
+        p_fp: args.fp,
+        p_se: args.se.or(preset.map(|preset| preset.p_se)).unwrap_or(DEFAULT_SEQUENCING_ERROR_RATE),
+        score_threshold: args.threshold,
+        local_assembly: args.local_assembly,
+        amplicons: args
+            .amplicon_bed
+            .as_ref()
+            .map(AmpliconSet::from_bed)
+            .transpose()?
+            .map(Arc::new),
+        bisulfite: args.bisulfite,
+        ffpe: args.ffpe,
+        titration_fractions: if args.titration_output.is_some() {
+            titration_fractions(args.titration_step)?
+        } else {
+            Vec::new()
+        },
+        calibration: args
+            .calibration
+            .as_ref()
+            .map(Calibration::from_file)
+            .transpose()?
+            .map(Arc::new),
+        ref_confirmation: args.ref_confirm_vaf.map(|assay_lod_vaf| RefConfirmation {
+            assay_lod_vaf,
+            confidence: args.ref_confirm_confidence,
+        }),
+        required_depth_vaf: args.required_depth_vaf,
+        noise_profile: None,
+        read_filters,
+        pool: args.pool_size.map(|size| PoolDesign { size }),
+        io_retry: RetryPolicy {
+            max_retries: args.io_retries,
+            initial_backoff: Duration::from_millis(args.io_retry_backoff_ms),
+        },
+        max_open_bams: args.max_open_bams,
+        balance_depth: args.balance_depth,
+        contig_policy: args.contig_policy,
+        alt_contig_map: args
+            .alt_contig_alignment
+            .as_ref()
+            .map(AltContigMap::from_sam)
+            .transpose()?
+            .map(Arc::new),
+        variant_overrides: None,
+        vaf_definition: args.vaf_definition,
+        max_other_allele_fraction: args.max_other_allele_fraction,
+        deleted_reads: args.deleted_reads,
+        short_fragments: args.short_fragments,
+        min_base_quality: args.min_base_quality,
+        min_mapping_quality: args.min_mapping_quality,
+        missing_quality: args.missing_quality,
+        fetch_merge_distance: args.fetch_merge_distance,
+        coverage_only: args.coverage_only,
+        max_pileup_depth: args.max_pileup_depth,
+        depth_cap: args.depth_cap,
+        reference: args.reference.clone(),
+    };
+
+    // Validate configuration
+    validate_lod_config(&config)?;
+    if let Some(fraction) = args.verify_fraction {
+        validate_verify_fraction(fraction)?;
+    }
+    if args.output_db.is_some() && !cfg!(feature = "sqlite") {
+        return Err(VlodError::InvalidConfig(
+            "--output-db requires vlod-rs to be built with the `sqlite` feature".to_string(),
+        ));
+    }
+    log::info!("Configuration: TP={}, FP={}, SE={}", config.p_tp, config.p_fp, config.p_se);
+    if let Some(pool) = &config.pool {
+        log::info!(
+            "Pooled design: {} samples, single-copy allele fraction {:.4}",
+            pool.size,
+            pool.expected_fraction()
+        );
+    }
+
+    // Sample background noise at random panel positions, from the reads the
+    // variants are counted from
+    if let Some(noise_bed) = &args.noise_bed {
+        let _timer = Timer::new("Sampling background noise");
+        let positions = sample_positions(&read_regions(noise_bed)?, args.noise_samples, NOISE_SAMPLING_SEED);
+        let profile = sample_background_noise(input_bam, &positions, &config)?;
+        profile.log_summary();
+        if args.noise_error_rates {
+            config.noise_profile = Some(Arc::new(profile));
+        }
+    }
+
+    // Screen for contamination before the results are trusted
+    let contamination = match &args.contamination_sites {
+        Some(sites) => {
+            let _timer = Timer::new("Screening for contamination");
+            let sites = read_vcf_variants(sites)?;
+            let screen = screen_contamination(input_bam, &sites, &config, args.contamination_threshold)?;
+            screen.log();
+            Some(screen)
+        }
+        None => None,
+    };
+
+    // The input VCFs must follow the BAM header contig order
+    let contigs = bam_contigs(input_bam)?;
+    let contig_order: Vec<String> = contigs.iter().map(|(name, _)| name.clone()).collect();
+
+    // Records annotated by a run with this hash are kept in incremental mode
+    let config_hash = config_hash(&config, input_bam)?;
+
+    // Audit entries of this run share a request id
+    *audit = match &args.audit_log {
+        Some(audit_log) => {
+            let audit_log = AuditLog::open(audit_log)?;
+            let request_id = new_request_id();
+            log::info!("Request id: {}", request_id);
+            let mut entry = AuditEntry::new("run", "analyze", &request_id);
+            entry.config_hash = Some(config_hash.clone());
+            let input_paths = args.input_vcf.iter().chain(args.input_bam.iter().map(|bam| &bam.path));
+            entry.inputs = input_paths.map(|path| path.to_string_lossy().to_string()).collect();
+            audit_log.record(&entry, AuditEvent::Started)?;
+            Some((audit_log, entry))
+        }
+        None => None,
+    };
+
+    // Reference confirmation assesses monomorphic sites, so never skip them
+    let monomorphic_policy = if config.ref_confirmation.is_some() {
+        MonomorphicPolicy::Report
+    } else {
+        args.monomorphic_policy
+    };
+    if args.streaming {
+        let merge_options = merge_options(&args, contigs, manifest.as_ref());
+        let partial: Vec<PathBuf> = inputs.iter().map(|input| input.output.clone()).collect();
+        let outputs: Vec<PathBuf> = partial.iter().cloned().chain(args.manifest.clone()).collect();
+        let (sample, interrupted) =
+            run_streaming(&args, config, merge_options, &config_hash, monomorphic_policy, manifest.as_ref(), inputs)?;
+        if interrupted {
+            finish_audit(audit.take(), AuditEvent::Interrupted, &sample, &partial)?;
+            return Ok(RunOutcome::Interrupted);
+        }
+        finish_audit(audit.take(), AuditEvent::Finished, &sample, &outputs)?;
+        return Ok(RunOutcome::Completed);
+    }
+
+    // Peak memory and CPU use of each stage, for the run summary
+    let mut resources = ResourceSampler::start(RESOURCE_SAMPLE_INTERVAL);
+
+    // Step 1: Read VCF variants
+    resources.stage("reading_variants");
+    let _timer = Timer::new("Reading VCF variants");
+    let limits = VcfReadLimits {
+        max_line_length: args.max_line_length,
+        max_errors: args.max_errors,
+        caller: args.caller,
+    };
+    let mut variant_sets = Vec::with_capacity(inputs.len());
+    let mut non_pass = HashSet::new();
+    let mut warnings = Warnings::new();
+    let mut overrides = VariantOverrideMap::new();
+    let mut origins = VariantOrigins::new();
+    for input in &mut inputs {
+        // The readers and the merge work on VCF text
+        if is_bcf(&input.input_vcf)? {
+            let _timer = Timer::new("Decoding input BCF");
+            let decoded_vcf = ScratchFile::new(input.output.with_extension("decoded.vcf.tmp"));
+            bcf_to_vcf(&input.input_vcf, decoded_vcf.path())?;
+            input.decoded_vcf = Some(decoded_vcf);
+        }
+
+        // Optionally sort a copy of the input into BAM contig order
+        if args.sort_input {
+            let _timer = Timer::new("Sorting input VCF");
+            let sorted_vcf = ScratchFile::new(input.output.with_extension("sorted.vcf.tmp"));
+            sort_vcf_file(input.source(), sorted_vcf.path(), &contig_order, args.max_line_length)?;
+            input.sorted_vcf = Some(sorted_vcf);
+        }
+
+        let vcf_input = read_vcf_input(input.vcf(), &limits)?;
+        warnings.extend(vcf_input.warnings);
+        overrides.extend(vcf_input.overrides);
+        let (variants, input_non_pass) = select_pass_variants(vcf_input.variants, args.pass_only);
+        if args.pass_only {
+            warnings.add(WarningKind::NonPassSkipped, input_non_pass.len());
+        }
+        let read = variants.len();
+        let mut variants = apply_monomorphic_policy(variants, monomorphic_policy);
+        warnings.add(WarningKind::MonomorphicSkipped, read - variants.len());
+        if args.incremental {
+            let prior = read_prior_annotations(input.vcf(), args.caller, args.max_line_length)?;
+            if prior.matches(&config_hash) {
+                let unannotated = variants.len();
+                variants.retain(|variant| !prior.contains(variant));
+                log::info!(
+                    "{} variants of {:?} keep their earlier annotations",
+                    unannotated - variants.len(),
+                    input.input_vcf
+                );
+                input.keep_annotated = true;
+            } else if !prior.annotated.is_empty() {
+                log::warn!(
+                    "{:?} was annotated with a different configuration; analysing all its variants again",
+                    input.input_vcf
+                );
+            }
+        }
+        log::info!("Read {} variants from {:?}", variants.len(), input.input_vcf);
+        check_sort_order(&variants, &contig_order)?;
+        let (variants, duplicates) = dedup_variants(variants, args.duplicate_policy)?;
+        if duplicates > 0 {
+            log::warn!("Skipped {} duplicate variants in {:?}", duplicates, input.input_vcf);
+        }
+        warnings.add(WarningKind::DuplicateVariant, duplicates);
+        // Distinct records that normalize to one variant are scored once and each
+        // gets its result back, so collapsing them is not a duplicate
+        let variants = if args.normalize {
+            let (variants, input_origins) = normalize_variants(variants, reference.as_ref())?;
+            for (normal, records) in input_origins {
+                let known = origins.entry(normal).or_default();
+                for record in records {
+                    if !known.contains(&record) {
+                        known.push(record);
+                    }
+                }
+            }
+            dedup_variants(variants, DuplicatePolicy::First)?.0
+        } else {
+            variants
+        };
+        non_pass.extend(input_non_pass);
+        variant_sets.push(variants);
+    }
+
+    normalize_overrides(&mut overrides, &origins);
+    if !overrides.is_empty() {
+        log::info!("{} variants override model parameters with VLOD_* INFO tags", overrides.len());
+        config.variant_overrides = Some(Arc::new(overrides));
+    }
+
+    // Variants shared between input VCFs are analysed once
+    let mut variants = union_variants(variant_sets, &contig_order);
+    if inputs.len() > 1 {
+        log::info!("{} distinct variants across {} input VCFs", variants.len(), inputs.len());
+    }
+    if let Some(regions) = &gene_footprint {
+        let outside = retain_in_regions(&mut variants, regions);
+        log::info!("{} variants outside the exons of --genes are not analysed", outside);
+    }
+    if let Some(reference) = &reference {
+        log_ref_mismatches(&variants, reference)?;
+    }
+
+    let explicit_sample = args.sample_name.as_deref().or(args.input_bam[0].sample.as_deref());
+    let sample = resolve_sample_name(explicit_sample, input_bam, inputs[0].source(), args.max_line_length)?;
+    let mut merge_options = merge_options(&args, contigs, manifest.as_ref());
+    merge_options.header_lines.push(sample.vcf_header_line());
+    merge_options.header_lines.push(config_header_line(&config_hash));
+
+    let clinvar = match &args.clinvar {
+        Some(clinvar) => {
+            let table = AnnotationTable::read_vcf(clinvar, CLINVAR_SIGNIFICANCE_FIELD, &variants, args.max_line_length)?;
+            log::info!("{} of {} variants have a ClinVar clinical significance", table.len(), variants.len());
+            Some(table)
+        }
+        None => None,
+    };
+
+    // Step 2: Calculate detectability scores
+    resources.stage("scoring");
+    let sample_variants = if sample_bams.len() > 1 { variants.clone() } else { Vec::new() };
+    let mut results = if variants.is_empty() {
+        log::warn!("No variants found in the input VCF files");
+        Vec::new()
+    } else {
+        let _timer = Timer::new("Calculating detectability scores");
+        let results = calculate_detectability_scores(variants, input_bam, &config, args.num_processes)?;
+        restore_original_variants(results, &origins)
+    };
+    for result in &mut results {
+        result.sample = Some(sample.name.clone());
+        result.clinical_significance =
+            clinvar.as_ref().and_then(|clinvar| clinvar.get(&result.variant)).map(str::to_string);
+    }
+
+    // Annotate what was scored before an interruption, marking the output partial
+    let interrupted = interrupt::is_interrupted();
+    if interrupted {
+        log::warn!("Interrupted after scoring {} results; writing partial annotations", results.len());
+        merge_options.header_lines.push(
+            "##vlodPartial=<Description=\"Run interrupted; records without DET were not analysed\">".to_string(),
+        );
+    }
+
+    if !results.is_empty() {
+        log::info!("Calculated detectability scores for {} variants", results.len());
+
+        // Log statistics
+        let detectable_count = results.iter().filter(|r| r.detectability_condition.is_detectable()).count();
+        let non_detectable_count = results.len() - detectable_count;
+
+        log::info!("Detectability summary:");
+        log::info!("  Detectable: {} ({:.1}%)", detectable_count, (detectable_count as f64 / results.len() as f64) * 100.0);
+        log::info!("  Non-detectable: {} ({:.1}%)", non_detectable_count, (non_detectable_count as f64 / results.len() as f64) * 100.0);
+
+        let scores: Vec<f64> = results.iter().map(|r| r.detectability_score).collect();
+        let min_score = scores.iter().copied().fold(f64::INFINITY, f64::min);
+        let max_score = scores.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let avg_score = scores.iter().sum::<f64>() / scores.len() as f64;
+
+        log::info!("  Score range: {:.3} to {:.3}", min_score, max_score);
+        log::info!("  Average score: {:.3}", avg_score);
+        log::info!("  BAM read retries: {}", io_retries(&results));
+        log_filter_stratified_summary(&results, &non_pass);
+        if args.clinvar.is_some() {
+            SignificanceSummary::new(&results).log();
+        }
+    }
+    let verdicts = claims.as_ref().filter(|_| !interrupted).map(|claims| claims.evaluate(&results, &config));
+    if let Some(verdicts) = &verdicts {
+        log_claim_verdicts(verdicts);
+    }
+
+    // Every other sample is scored against its own BAM for its FORMAT fields
+    let mut sample_results = Vec::with_capacity(sample_bams.len());
+    for (index, (name, bam)) in sample_bams.iter().enumerate() {
+        let scored = if index == 0 {
+            results.clone()
+        } else if sample_variants.is_empty() || interrupted {
+            Vec::new()
+        } else {
+            let _timer = Timer::new(&format!("Calculating detectability scores for {}", name));
+            let scored = calculate_detectability_scores(sample_variants.clone(), bam, &config, args.num_processes)?;
+            restore_original_variants(scored, &origins)
+        };
+        sample_results.push((name.clone(), scored));
+    }
+
+    // Step 3: Merge results directly into each VCF
+    resources.stage("merging");
+    let _timer = Timer::new("Merging results into VCF");
+    let with_sites;
+    let merged_results = if args.site_aggregates {
+        with_sites = with_site_aggregates(&results);
+        &with_sites
+    } else {
+        &results
+    };
+    for input in &inputs {
+        let options = MergeOptions { keep_annotated: input.keep_annotated, ..merge_options.clone() };
+        // A BCF output is encoded from the annotated VCF text
+        let annotated_vcf =
+            is_bcf_path(&input.output).then(|| ScratchFile::new(input.output.with_extension("annotated.vcf.tmp")));
+        let merge_output = annotated_vcf.as_ref().map_or(input.output.clone(), |f| f.path().to_path_buf());
+        let merge_stats = if sample_results.is_empty() {
+            merge_detectability_results_into_vcf(input.vcf(), merged_results, &merge_output, &options)?
+        } else {
+            merge_sample_results_into_vcf(input.vcf(), merged_results, &sample_results, &merge_output, &options)?
+        };
+        if let Some(annotated_vcf) = &annotated_vcf {
+            let _timer = Timer::new("Encoding output BCF");
+            vcf_to_bcf(annotated_vcf.path(), &input.output)?;
+        }
+        merge_stats.log_summary();
+        log::info!("Annotated VCF written to: {:?}", input.output);
+
+        // Log file sizes for reference
+        if let Ok(input_size) = std::fs::metadata(&input.input_vcf).map(|m| m.len()) {
+            if let Ok(output_size) = std::fs::metadata(&input.output).map(|m| m.len()) {
+                log::info!("Input VCF size: {} bytes", input_size);
+                log::info!("Output VCF size: {} bytes", output_size);
+
+                if output_size > input_size {
+                    let size_increase = output_size - input_size;
+                    log::info!("Size increase: {} bytes ({:.1}%)",
+                              size_increase,
+                              (size_increase as f64 / input_size as f64) * 100.0);
+                }
+            }
+        }
+    }
+
+    resources.stage("reporting");
+    let spot_check = match args.verify_fraction {
+        Some(fraction) if !interrupted => {
+            let _timer = Timer::new("Spot-checking counts");
+            // Variants are counted again as they were scored, normalized
+            let normalized: HashMap<&Variant, &Variant> =
+                origins.iter().flat_map(|(normal, records)| records.iter().map(move |record| (record, normal))).collect();
+            let mut seen = HashSet::new();
+            let scored: Vec<Variant> = results
+                .iter()
+                .map(|result| *normalized.get(&result.variant).unwrap_or(&&result.variant))
+                .filter(|variant| seen.insert(*variant))
+                .cloned()
+                .collect();
+            let check = verify_counts(&scored, input_bam, &config, fraction, args.verify_seed)?;
+            check.record_warnings(&mut warnings);
+            Some(check)
+        }
+        _ => None,
+    };
+    let coverage_uniformity = match &targets {
+        Some(targets) if !interrupted => {
+            let _timer = Timer::new("Measuring target coverage");
+            let uniformity =
+                measure_uniformity(input_bam, targets, reference.as_ref(), &config, &args.coverage_thresholds)?;
+            uniformity.log();
+            Some(uniformity)
+        }
+        _ => None,
+    };
+    warnings.record_not_assessable(&results);
+    if let Some(summary_json) = &args.summary_json {
+        let mut summary = RunSummary::new(&results, warnings, interrupted, &config);
+        summary.spot_check = spot_check;
+        summary.coverage_uniformity = coverage_uniformity;
+        summary.clinical_significance = args.clinvar.is_some().then(|| SignificanceSummary::new(&results));
+        summary.claims = verdicts.clone();
+        summary.contamination = contamination;
+        summary.preset = preset.map(|preset| preset.name.to_string());
+        summary.sample = Some(sample.name.clone());
+        summary.sample_source = Some(sample.source.to_string());
+        summary.resources = resources.usage();
+        summary.write(summary_json)?;
+        log::info!("Run summary written to: {:?}", summary_json);
+    }
+
+    if interrupted {
+        log::warn!("Partial annotated VCFs and run summary written (marked partial); other outputs were skipped");
+        let partial: Vec<&PathBuf> = inputs.iter().map(|input| &input.output).chain(&args.summary_json).collect();
+        finish_audit(audit.take(), AuditEvent::Interrupted, &sample.name, &partial)?;
+        return Ok(RunOutcome::Interrupted);
+    }
+
+    if let Some(titration_output) = &args.titration_output {
+        write_titration_results(&results, titration_output)?;
+        log::info!("Coverage titration written to: {:?}", titration_output);
+    }
+    if let (Some(exons), Some(rollup_output)) = (&exons, &args.rollup_output) {
+        write_rollup(&rollup_by_feature(exons, &results, &config, args.rollup_vaf), rollup_output)?;
+        log::info!("Exon and transcript rollup written to: {:?}", rollup_output);
+    }
+    if let (Some(sweep), Some(sweep_output)) = (&args.threshold_sweep, &args.sweep_output) {
+        write_sweep_results(&sweep_thresholds(&results, sweep, sweep_truth.as_ref()), sweep_output)?;
+        log::info!("Threshold sweep written to: {:?}", sweep_output);
+    }
+    if let (Some(manifest), Some(manifest_output)) = (&manifest, &args.manifest) {
+        manifest.write(manifest_output)?;
+        log::info!("Checksum manifest written to: {:?}", manifest_output);
+    }
+    if let Some(multiqc_dir) = &args.multiqc_dir {
+        let path = write_multiqc(&results, &sample.name, multiqc_dir)?;
+        log::info!("MultiQC metrics written to: {:?}", path);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(output_db) = &args.output_db {
+        let run_id = vlod_rs::results_db::append_results_to_db(&results, output_db, &sample.name, &config)?;
+        log::info!("Results appended to {:?} as run {} of sample {}", output_db, run_id, sample.name);
+    }
+    let multiqc = args.multiqc_dir.as_ref().map(|dir| multiqc_path(dir, &sample.name));
+    let optional = [
+        &args.titration_output,
+        &args.rollup_output,
+        &args.sweep_output,
+        &args.manifest,
+        &args.summary_json,
+        &multiqc,
+    ];
+    let outputs: Vec<&PathBuf> = inputs.iter().map(|input| &input.output).chain(optional.into_iter().flatten()).collect();
+    if args.checksum_outputs {
+        for output in &outputs {
+            let sidecar = write_checksum_sidecar(output)?;
+            log::info!("Checksum written to: {:?}", sidecar);
+        }
+    }
+    finish_audit(audit.take(), AuditEvent::Finished, &sample.name, &outputs)?;
+
+    if verdicts.iter().flatten().any(|verdict| !verdict.passed) {
+        log::error!("The run fails its assay claims");
+        return Ok(RunOutcome::ClaimsFailed);
+    }
+    log::info!("Analysis completed successfully");
+
+    Ok(RunOutcome::Completed)
+}
+
+/// Record the end of a run in the --audit-log, with the checksums of its outputs
+fn finish_audit<P: AsRef<Path>>(
+    audit: Option<(AuditLog, AuditEntry)>,
+    event: AuditEvent,
+    sample: &str,
+    outputs: &[P],
+) -> VlodResult<()> {
+    if let Some((audit_log, mut entry)) = audit {
+        entry.samples = vec![sample.to_string()];
+        entry.add_outputs(outputs)?;
+        audit_log.record(&entry, event)?;
+    }
+    Ok(())
+}
+
+/// Score and annotate the input VCF a batch at a time as it is read (--streaming);
+/// returns the sample name and whether the run was interrupted
+fn run_streaming(
+    args: &Args,
+    config: LodConfig,
+    mut merge_options: MergeOptions,
+    config_hash: &str,
+    monomorphic_policy: MonomorphicPolicy,
+    manifest: Option<&RunManifest>,
+    inputs: Vec<BatchInput>,
+) -> VlodResult<(String, bool)> {
+    let (Ok([mut input]), [input_bam]) = (<[BatchInput; 1]>::try_from(inputs), args.input_bam.as_slice()) else {
+        return Err(VlodError::InvalidConfig(
+            "--streaming annotates a single input VCF against a single BAM".to_string(),
+        ));
+    };
+    if is_bcf(&input.input_vcf)? {
+        let _timer = Timer::new("Decoding input BCF");
+        let decoded_vcf = ScratchFile::new(input.output.with_extension("decoded.vcf.tmp"));
+        bcf_to_vcf(&input.input_vcf, decoded_vcf.path())?;
+        input.decoded_vcf = Some(decoded_vcf);
+    }
+
+    let explicit_sample = args.sample_name.as_deref().or(input_bam.sample.as_deref());
+    let sample = resolve_sample_name(explicit_sample, &input_bam.path, input.source(), args.max_line_length)?;
+    merge_options.header_lines.push(sample.vcf_header_line());
+    merge_options.header_lines.push(config_header_line(config_hash));
+    let streaming = StreamingConfig {
+        lod: config,
+        merge: merge_options,
+        pass_only: args.pass_only,
+        monomorphic_policy,
+        num_processes: args.num_processes,
+        batch_size: args.stream_batch_size,
+    };
+
+    let annotated_vcf =
+        is_bcf_path(&input.output).then(|| ScratchFile::new(input.output.with_extension("annotated.vcf.tmp")));
+    let merge_output = annotated_vcf.as_ref().map_or(input.output.clone(), |f| f.path().to_path_buf());
+    let output = {
+        let _timer = Timer::new("Streaming detectability scores into VCF");
+        let writer = BufWriter::new(create_output_file(&merge_output)?);
+        stream_annotate_vcf(input.vcf(), &input_bam.path, writer, &streaming)?
+    };
+    if let Some(annotated_vcf) = &annotated_vcf {
+        let _timer = Timer::new("Encoding output BCF");
+        vcf_to_bcf(annotated_vcf.path(), &input.output)?;
+    }
+    log::info!(
+        "Calculated detectability scores for {} variants in {} batches ({} detectable)",
+        output.scored,
+        output.batches,
+        output.detectable
+    );
+    output.stats.log_summary();
+    log::info!("Annotated VCF written to: {:?}", input.output);
+
+    if interrupt::is_interrupted() {
+        log::warn!("Interrupted; records after the last scored batch were left unannotated");
+        return Ok((sample.name, true));
+    }
+    if let (Some(manifest), Some(manifest_output)) = (manifest, &args.manifest) {
+        manifest.write(manifest_output)?;
+        log::info!("Checksum manifest written to: {:?}", manifest_output);
+    }
+    if args.checksum_outputs {
+        for output in std::iter::once(&input.output).chain(&args.manifest) {
+            let sidecar = write_checksum_sidecar(output)?;
+            log::info!("Checksum written to: {:?}", sidecar);
+        }
+    }
+    log::info!("Analysis completed successfully");
+    Ok((sample.name, false))
+}
+
+/// Handle application errors and provide user-friendly messages
+fn handle_error(error: VlodError) -> ! {
+    match error {
+        VlodError::FileNotFound(path) => {
+            eprintln!("Error: File not found: {}", path);
+            eprintln!("Please check that the file exists and is readable.");
+            eprintln!("For BAM files, ensure the index file (.bai or .csi) is present.");
+        }
+        VlodError::InvalidVariant(msg) => {
+            eprintln!("Error: Invalid variant data: {}", msg);
+            eprintln!("Please check that your VCF file is properly formatted.");
+        }
+        VlodError::Parse { .. } => {
+            eprintln!("Error: {}", error);
+            eprintln!("Please check that your VCF file is properly formatted.");
+        }
+        VlodError::InvalidConfig(msg) => {
+            eprintln!("Error: Invalid configuration: {}", msg);
+            eprintln!("Please check your probability parameters (TP, FP, SE).");
+        }
+        VlodError::Htslib(ref e) => {
+            eprintln!("Error: BAM/VCF processing error: {}", e);
+            eprintln!("Please check that your BAM file is valid and has an index (.bai or .csi) file.");
+            eprintln!("Also verify that your VCF file is properly formatted.");
+        }
+        VlodError::Io(ref e) => {
+            eprintln!("Error: I/O error: {}", e);
+            eprintln!("Please check file permissions and disk space.");
+        }
+        VlodError::Csv(ref e) => {
+            eprintln!("Error: Data processing error: {}", e);
+            eprintln!("This is unexpected in the combined workflow. Please report this issue.");
+        }
+        VlodError::Database(ref msg) => {
+            eprintln!("Error: Results database error: {}", msg);
+            eprintln!("Please check that the --output-db file is a writable SQLite database.");
+        }
+        VlodError::Context { context, source } => {
+            eprintln!("Error while {}:", context);
+            handle_error(*source);
+        }
+    }
+    std::process::exit(1);
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Calibrate(args)) => calibrate::run(args),
+        Some(Command::Chimerism(args)) => chimerism::run(args),
+        Some(Command::CompareBams(args)) => compare_bams::run(args),
+        Some(Command::Diff(args)) => diff::run(args),
+        Some(Command::PlanTopup(args)) => plan_topup::run(args),
+        Some(Command::IndexResults(args)) => index_results::run(args),
+        Some(Command::Serve(args)) => serve::run(args),
+        Some(Command::ServeGrpc(args)) => serve_grpc::run(args),
+        Some(Command::Watch(args)) => watch::run(args),
+        Some(Command::MakeTestData(args)) => make_test_data::run(args),
+        Some(Command::VerifyOutput(args)) => verify_output::run(args),
+        None => match run(cli.args) {
+            Ok(RunOutcome::Interrupted) => std::process::exit(INTERRUPTED_EXIT_CODE),
+            Ok(RunOutcome::ClaimsFailed) => std::process::exit(CLAIMS_FAILED_EXIT_CODE),
+            result => result.map(|_| ()),
+        },
+    };
+    if let Err(e) = result {
+        handle_error(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_combined_workflow_empty_vcf() {
+        // Create empty VCF file
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        
+        // Create empty BAM file (will fail but that's expected)
+        let _bam_file = NamedTempFile::new().unwrap();
+        
+        let _output_file = NamedTempFile::new().unwrap();
+        
+        // This should handle empty VCF gracefully
+        let _config = LodConfig::default();
+        let variants = read_vcf_variants(vcf_file.path()).unwrap();
+        assert!(variants.is_empty());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let config = LodConfig::default();
+        assert!(validate_lod_config(&config).is_ok());
+        
+        let invalid_config = LodConfig {
+            p_tp: 0.0,
+            p_fp: 0.001,
+            p_se: 0.0001,
+            ..LodConfig::default()
+        };
+        assert!(validate_lod_config(&invalid_config).is_err());
+    }
+
+    #[test]
+    fn test_combined_workflow_integration() {
+        use vlod_rs::merge::{merge_detectability_results_into_vcf, MergeOptions};
+        use vlod_rs::{DetectabilityCondition, DetectabilityResult};
+        use vlod_rs::Variant;
+
+        // Create test VCF with variants
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth\">").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(vcf_file, "chr2\t200\t.\tG\tC\t.\tPASS\tDP=40").unwrap();
+        
+        // Read variants from VCF
+        let variants = read_vcf_variants(vcf_file.path()).unwrap();
+        assert_eq!(variants.len(), 2);
+        
+        // Create mock detectability results
+        let results = vec![
+            DetectabilityResult::new(
+                Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+                3.5,
+                DetectabilityCondition::Detectable,
+                30,
+                15,
+            ),
+            DetectabilityResult::new(
+                Variant::new("chr2".to_string(), 200, "G".to_string(), "C".to_string()),
+                1.2,
+                DetectabilityCondition::NonDetectable,
+                40,
+                8,
+            ),
+        ];
+        
+        // Test the merge functionality (core of the combined workflow)
+        let output_file = NamedTempFile::new().unwrap();
+        let merge_result = merge_detectability_results_into_vcf(
+            vcf_file.path(),
+            &results,
+            output_file.path(),
+            &MergeOptions::default(),
+        );
+        
+        assert!(merge_result.is_ok());
+        
+        // Verify the output contains the expected annotations
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(output_content.contains("DET=Yes"));
+        assert!(output_content.contains("DETS=3.5"));
+        assert!(output_content.contains("DET=No"));
+        assert!(output_content.contains("DETS=1.2"));
+        assert!(output_content.contains("##INFO=<ID=DET,Number=1,Type=String"));
+        assert!(output_content.contains("##INFO=<ID=DETS,Number=1,Type=Float"));
+    }
+}
\ No newline at end of file
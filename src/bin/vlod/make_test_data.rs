@@ -0,0 +1,70 @@
+//! `vlod make-test-data`: writes a tiny synthetic data set with known detectability
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    testdata::{write_test_data, TestData, TEST_DATA_PREFIX},
+    VlodError, VlodResult,
+};
+
+#[derive(Args)]
+#[command(about = "Write a tiny synthetic reference, BAM and VCF with known detectability")]
+#[command(long_about = "
+Writes vlod_test.fa (with .fai), vlod_test.bam (with .bai) and vlod_test.vcf into
+the output directory. The VCF holds one detectable, one non-detectable and one
+uncovered SNV; the EXPECTED INFO field gives the DET status vLoD should report
+for each with the default settings. Use it to check an installation, or that a
+cluster node can read and write the file systems involved.
+")]
+pub struct MakeTestDataArgs {
+    /// Directory to write the test data into
+    #[arg(long, value_name = "DIR")]
+    output_dir: PathBuf,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output files if they exist
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: MakeTestDataArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    if !args.force {
+        if let Some(existing) = TestData::paths(&args.output_dir).into_iter().find(|path| path.exists()) {
+            return Err(VlodError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Output file {:?} already exists. Use --force to overwrite.", existing),
+            )));
+        }
+    }
+
+    let data = write_test_data(&args.output_dir)?;
+    println!("Test data written to {:?}:", args.output_dir);
+    for (variant, expected) in &data.expected {
+        println!(
+            "  {}:{} {}>{} expected DET={}",
+            variant.chrom,
+            variant.pos,
+            variant.ref_allele,
+            variant.alt_allele,
+            expected.vcf_status()
+        );
+    }
+    println!(
+        "Check it with: vlod --input-vcf {} --input-bam {} --output {}",
+        data.vcf.display(),
+        data.bam.display(),
+        args.output_dir.join(format!("{}.annotated.vcf", TEST_DATA_PREFIX)).display()
+    );
+
+    Ok(())
+}
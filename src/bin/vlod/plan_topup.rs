@@ -0,0 +1,128 @@
+//! `vlod plan-topup`: plans the top-up sequencing a run needs to meet its assay claims
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    claims::ClaimSet,
+    lod::validate_lod_config,
+    topup::{plan_topup, read_topup_results, write_topup_plan, write_topup_plan_to_writer, TopupOptions},
+    utils::validate_file_readable,
+    LodConfig, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
+};
+
+#[derive(Args)]
+#[command(about = "Plan the top-up sequencing a run needs to meet its assay claims")]
+#[command(long_about = "
+Reads a results TSV (from lod_edit or --tsv-output) and the assay claims file
+given to --claims, and computes for every variant in a claim's scope the depth
+at which a variant at the claimed VAF would be detected with 95% power (or the
+claimed depth, if higher). Per region of each claim (per variant site for claims
+without regions) the plan lists:
+
+- Short: variants below their required depth
+- Unreachable: short variants without coverage, or whose VAF no depth detects
+- Required_Depth and Additional_Coverage: the deepest requirement and the most
+  depth missing at a short variant top-up can rescue
+- Additional_Reads: on-target reads adding that coverage over the region
+- Extra_Lanes: lanes to add, scaling --current-lanes by the largest relative
+  depth increase needed, as a top-up raises every region together
+
+The plan TSV is written to --output, or to standard output.
+")]
+pub struct PlanTopupArgs {
+    /// Results TSV with a Coverage column
+    #[arg(value_name = "RESULTS")]
+    results: PathBuf,
+
+    /// Assay claims file (JSON, as for --claims)
+    #[arg(long, value_name = "FILE")]
+    claims: PathBuf,
+
+    /// Path to the plan TSV (standard output when omitted)
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Read length, converting coverage into reads
+    #[arg(long, default_value = "150")]
+    read_length: u32,
+
+    /// Lanes the current run was sequenced on
+    #[arg(long, default_value = "1")]
+    current_lanes: f64,
+
+    /// Probability of true positive result
+    #[arg(long = "TP", default_value = "0.999")]
+    tp: f64,
+
+    /// Probability of false positive result
+    #[arg(long = "FP", default_value = "0.001")]
+    fp: f64,
+
+    /// Probability of sequencing error
+    #[arg(long = "SE", default_value = "0.0001")]
+    se: f64,
+
+    /// Score at or above which a variant is detectable
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Force overwrite of output file if it exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+pub fn run(args: PlanTopupArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    validate_file_readable(&args.results)?;
+    validate_file_readable(&args.claims)?;
+    if let Some(output) = args.output.as_ref().filter(|output| output.exists() && !args.force) {
+        return Err(VlodError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("Output file {:?} already exists. Use --force to overwrite.", output),
+        )));
+    }
+
+    let config = LodConfig {
+        p_tp: args.tp,
+        p_fp: args.fp,
+        p_se: args.se,
+        score_threshold: args.threshold,
+        ..LodConfig::default()
+    };
+    validate_lod_config(&config)?;
+    let options = TopupOptions {
+        read_length: args.read_length,
+        current_lanes: args.current_lanes,
+    };
+    options.validate()?;
+
+    let claims = ClaimSet::from_file(&args.claims)?;
+    let results = read_topup_results(&args.results)?;
+    let rows = plan_topup(&claims, &results, &config, &options);
+    match &args.output {
+        Some(output) => write_topup_plan(&rows, output)?,
+        None => write_topup_plan_to_writer(&rows, std::io::stdout().lock())?,
+    }
+
+    let short: usize = rows.iter().map(|row| row.short).sum();
+    let unreachable: usize = rows.iter().map(|row| row.unreachable).sum();
+    let extra_lanes = rows.iter().map(|row| row.extra_lanes).fold(0.0, f64::max);
+    eprintln!(
+        "{} region rows, {} variants short of their claim ({} not rescued by top-up); {:.2} extra lanes",
+        rows.len(),
+        short,
+        unreachable,
+        extra_lanes
+    );
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! `vlod serve`: serves region queries over indexed results to genome browsers
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    audit::AuditLog,
+    merge::MergeOptions,
+    results_index::IndexedResults,
+    server::{serve, DEFAULT_SERVE_ADDRESS},
+    VlodResult, DEFAULT_DETECTION_THRESHOLD,
+};
+
+#[derive(Args)]
+#[command(about = "Serve region queries over indexed results for genome browsers")]
+#[command(long_about = "
+Serves a results TSV indexed with `vlod index-results` over HTTP, so that IGV or
+JBrowse can display detectability as a live track during a review session:
+
+  GET /results?region=chr1:1-1000000             JSON array of results
+  GET /results?region=chr1:1-1000000&format=bed  BED9, coloured by status
+
+Regions are 1-based and inclusive; CHROM alone returns a whole contig. Contig
+names are aliased (chr1 vs 1) unless --strict-contig-names is given. Responses
+allow cross-origin requests so browser-based viewers can load them. By default
+only the local machine can connect.
+")]
+pub struct ServeArgs {
+    /// bgzip-compressed results TSV with a tabix index
+    #[arg(value_name = "FILE")]
+    results: PathBuf,
+
+    /// Address and port to listen on
+    #[arg(long, default_value = DEFAULT_SERVE_ADDRESS)]
+    bind: String,
+
+    /// Match contig names exactly instead of aliasing chr-prefixed and bare names
+    #[arg(long)]
+    strict_contig_names: bool,
+
+    /// Score threshold the results were called at; BED scores saturate at twice it
+    #[arg(long, default_value_t = DEFAULT_DETECTION_THRESHOLD)]
+    threshold: f64,
+
+    /// Append an audit entry (request id, client and query) for every request to
+    /// this JSON-lines file
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+}
+
+pub fn run(args: ServeArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    let options = MergeOptions {
+        strict_contig_names: args.strict_contig_names,
+        ..MergeOptions::default()
+    };
+    let results = IndexedResults::open(&args.results, &options)?;
+    let audit = args.audit_log.as_ref().map(AuditLog::open).transpose()?;
+    serve(results, &args.bind, args.threshold, audit.as_ref())
+}
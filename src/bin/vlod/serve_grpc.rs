@@ -0,0 +1,60 @@
+//! `vlod serve-grpc`: serves analyses, merges and status over gRPC
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+#[cfg(feature = "grpc")]
+use vlod_rs::audit::AuditLog;
+#[cfg(not(feature = "grpc"))]
+use vlod_rs::VlodError;
+use vlod_rs::VlodResult;
+
+#[derive(Args)]
+#[command(about = "Serve analyses, merges and status over gRPC")]
+#[command(long_about = "
+Serves the vlod gRPC service defined in proto/vlod.proto (shipped with the
+crate) for enterprise pipeline platforms:
+
+  Analyze  score a VCF against a BAM, streaming progress, then the result of
+           every variant, then a summary; closing the stream cancels it
+  Merge    annotate a VCF with a detectability TSV
+  Status   build provenance and the analyses served so far
+
+Paths in requests are paths on the server. By default only the local machine
+can connect. Requires vlod-rs to be built with the `grpc` feature.
+")]
+pub struct ServeGrpcArgs {
+    /// Address and port to listen on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    bind: String,
+
+    /// Append audit entries for every Analyze and Merge (request id, client,
+    /// samples, configuration hash and output checksums) to this JSON-lines file
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+}
+
+pub fn run(args: ServeGrpcArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    #[cfg(feature = "grpc")]
+    {
+        let audit = args.audit_log.as_ref().map(AuditLog::open).transpose()?;
+        vlod_rs::grpc::serve_grpc(&args.bind, audit)
+    }
+    #[cfg(not(feature = "grpc"))]
+    {
+        Err(VlodError::InvalidConfig(format!(
+            "vlod serve-grpc requires vlod-rs to be built with the `grpc` feature (cannot serve {})",
+            args.bind
+        )))
+    }
+}
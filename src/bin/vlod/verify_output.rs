@@ -0,0 +1,48 @@
+//! `vlod verify-output`: checks outputs against their SHA-256 checksum sidecars
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{integrity::verify_checksum_sidecar, VlodResult};
+
+#[derive(Args)]
+#[command(about = "Check outputs against their SHA-256 checksum sidecars")]
+#[command(long_about = "
+Checks each file against the <file>.sha256 sidecar written by --checksum-outputs
+and prints OK or FAILED per file. Exits with status 1 if any file no longer
+matches its checksum. The sidecars use the sha256sum format, so `sha256sum -c`
+run from the output directory checks them as well.
+")]
+pub struct VerifyOutputArgs {
+    /// Output files to verify
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+}
+
+pub fn run(args: VerifyOutputArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+
+    let mut failed = 0;
+    for file in &args.files {
+        if verify_checksum_sidecar(file)? {
+            println!("{}: OK", file.display());
+        } else {
+            println!("{}: FAILED", file.display());
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} of {} outputs do not match their checksum", failed, args.files.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! `vlod watch`: runs every VCF+BAM pair arriving in an inbox directory
+
+use crate::init_logging;
+use clap::Args;
+use std::path::PathBuf;
+use vlod_rs::{
+    audit::AuditLog,
+    interrupt,
+    watch::{Watcher, WATCH_EVENT_LOG},
+    VlodResult,
+};
+
+#[derive(Args)]
+#[command(about = "Run every VCF+BAM pair arriving in an inbox directory")]
+#[command(long_about = "
+Polls an inbox directory for a VCF and a BAM (with its index) of the same
+sample, matched by the file name patterns of the rules file, and runs each pair
+through the pipeline once its files have stopped changing. The results TSV,
+annotated VCF and run summary of a sample are written to the outbox as
+SAMPLE.vlod.tsv, SAMPLE.vlod.vcf and SAMPLE.vlod.summary.json; samples whose
+results TSV is already there are not run again. A sample that fails is retried
+once any of its files changes.
+
+The rules file is TOML, and is re-read whenever it changes (a file that no
+longer parses is rejected and the previous rules kept):
+
+  vcf_pattern = \"{sample}.vcf.gz\"     # {sample} stands for the sample id
+  bam_pattern = \"{sample}.bam\"
+  poll_seconds = 10                   # between inbox scans
+  settle_seconds = 30                 # files unchanged this long are complete
+  pass_only = false
+  processes = 0                       # 0 uses every CPU
+  p_tp = 0.999                        # model parameters, as --TP, --FP, --SE
+  p_fp = 0.001                        # and --threshold
+  p_se = 0.0001
+  score_threshold = 2.5
+
+Every detection, run, failure and rules reload is appended as a JSON line to
+vlod_events.jsonl in the outbox. The watch runs until SIGINT/SIGTERM.
+")]
+pub struct WatchArgs {
+    /// Directory the VCFs and BAMs arrive in
+    #[arg(long, value_name = "DIR")]
+    inbox: PathBuf,
+
+    /// Directory to write the outputs and the event log into
+    #[arg(long, value_name = "DIR")]
+    outbox: PathBuf,
+
+    /// TOML rules file
+    #[arg(long, value_name = "FILE")]
+    rules: PathBuf,
+
+    /// Scan the inbox once, running the samples whose files have settled, and exit
+    #[arg(long)]
+    once: bool,
+
+    /// Append audit entries for every sample run (request id, user, sample,
+    /// configuration hash and output checksums) to this JSON-lines file
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Enable verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    debug: bool,
+}
+
+pub fn run(args: WatchArgs) -> VlodResult<()> {
+    init_logging(args.verbose, args.debug);
+    interrupt::install_signal_handlers();
+
+    let mut watcher = Watcher::new(&args.inbox, &args.outbox, &args.rules)?;
+    if let Some(audit_log) = &args.audit_log {
+        watcher = watcher.with_audit_log(AuditLog::open(audit_log)?);
+    }
+    if args.once {
+        let finished = watcher.poll()?;
+        println!("{} samples finished; events in {:?}", finished, args.outbox.join(WATCH_EVENT_LOG));
+        Ok(())
+    } else {
+        watcher.run()
+    }
+}
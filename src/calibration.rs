@@ -0,0 +1,552 @@
+//! Per-variant-class score threshold calibration against truth sets
+
+use crate::{about, utils::create_output_file, About, DetectabilityCondition, Variant, VlodError, VlodResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Variant classes calibrated independently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantClass {
+    Snv,
+    Mnv,
+    Insertion,
+    Deletion,
+    Complex,
+}
+
+impl VariantClass {
+    pub fn of(variant: &Variant) -> Self {
+        let ref_len = variant.ref_allele.len();
+        let alt_len = variant.alt_allele.len();
+        let same_anchor = variant.ref_allele.get(..1).map(|b| b.to_ascii_uppercase())
+            == variant.alt_allele.get(..1).map(|b| b.to_ascii_uppercase());
+
+        if ref_len == 1 && alt_len == 1 {
+            VariantClass::Snv
+        } else if ref_len == alt_len {
+            VariantClass::Mnv
+        } else if ref_len == 1 && same_anchor {
+            VariantClass::Insertion
+        } else if alt_len == 1 && same_anchor {
+            VariantClass::Deletion
+        } else {
+            VariantClass::Complex
+        }
+    }
+}
+
+/// How raw scores are mapped to detection probabilities
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbabilityMethod {
+    /// Logistic fit of truth against score
+    #[default]
+    Platt,
+    /// Monotone step function fitted by pool-adjacent-violators
+    Isotonic,
+}
+
+impl FromStr for ProbabilityMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "platt" => Ok(ProbabilityMethod::Platt),
+            "isotonic" => Ok(ProbabilityMethod::Isotonic),
+            _ => Err(format!("unknown calibration method '{}' (expected platt or isotonic)", s)),
+        }
+    }
+}
+
+/// Platt scaling parameters: `P(detected | score) = 1 / (1 + exp(a * score + b))`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlattScaling {
+    pub a: f64,
+    pub b: f64,
+}
+
+impl PlattScaling {
+    pub fn probability(&self, score: f64) -> f64 {
+        let f = self.a * score + self.b;
+        // Evaluate the logistic without overflowing exp() for large |f|
+        if f >= 0.0 {
+            (-f).exp() / (1.0 + (-f).exp())
+        } else {
+            1.0 / (1.0 + f.exp())
+        }
+    }
+}
+
+/// One step of an isotonic calibration: scores from `score` up to the next step map to `probability`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IsotonicStep {
+    pub score: f64,
+    pub probability: f64,
+}
+
+/// Fitted threshold and probability models for one variant class
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassCalibration {
+    pub threshold: f64,
+    pub youden_j: f64,
+    pub sensitivity: f64,
+    pub specificity: f64,
+    pub positives: usize,
+    pub negatives: usize,
+    #[serde(default)]
+    pub platt: Option<PlattScaling>,
+    #[serde(default)]
+    pub isotonic: Vec<IsotonicStep>,
+}
+
+/// Calibration file produced by `vlod calibrate` and consumed via `--calibration`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Probability model applied at runtime
+    #[serde(default)]
+    pub method: ProbabilityMethod,
+    pub thresholds: BTreeMap<VariantClass, ClassCalibration>,
+    /// Provenance of the vlod build that fitted the calibration
+    #[serde(default)]
+    pub about: Option<About>,
+}
+
+impl Calibration {
+    /// Fit a threshold per variant class from `(variant, score, truly detected)` observations
+    pub fn fit(observations: &[(Variant, f64, bool)], method: ProbabilityMethod) -> Self {
+        let mut by_class: BTreeMap<VariantClass, Vec<(f64, bool)>> = BTreeMap::new();
+        for (variant, score, truth) in observations {
+            by_class
+                .entry(VariantClass::of(variant))
+                .or_default()
+                .push((*score, *truth));
+        }
+
+        let thresholds = by_class
+            .into_iter()
+            .filter_map(|(class, points)| {
+                let mut fit = fit_youden_threshold(&points)?;
+                fit.platt = fit_platt(&points);
+                fit.isotonic = fit_isotonic(&points);
+                Some((class, fit))
+            })
+            .collect();
+
+        Calibration {
+            method,
+            thresholds,
+            about: Some(about()),
+        }
+    }
+
+    /// Calibrated threshold for a variant, if its class was calibrated
+    pub fn threshold_for(&self, variant: &Variant) -> Option<f64> {
+        self.thresholds
+            .get(&VariantClass::of(variant))
+            .map(|t| t.threshold)
+    }
+
+    /// Calibrated probability that a variant with this score is detected,
+    /// if its class was calibrated
+    pub fn probability(&self, variant: &Variant, score: f64) -> Option<f64> {
+        let fit = self.thresholds.get(&VariantClass::of(variant))?;
+        match self.method {
+            ProbabilityMethod::Platt => fit.platt.map(|platt| platt.probability(score)),
+            ProbabilityMethod::Isotonic => isotonic_probability(&fit.isotonic, score),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        let file = File::open(&path)
+            .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| VlodError::InvalidConfig(format!("Invalid calibration file: {}", e)))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> VlodResult<()> {
+        let mut writer = BufWriter::new(create_output_file(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| VlodError::InvalidConfig(format!("Cannot serialize calibration: {}", e)))?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Pick the score threshold maximising Youden's J (sensitivity + specificity - 1),
+/// calling a variant detectable when its score is at or above the threshold.
+/// Needs at least one truly detected and one undetected observation.
+pub fn fit_youden_threshold(points: &[(f64, bool)]) -> Option<ClassCalibration> {
+    let positives = points.iter().filter(|(_, truth)| *truth).count();
+    let negatives = points.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return None;
+    }
+
+    let mut candidates: Vec<f64> = points.iter().map(|(score, _)| *score).filter(|s| s.is_finite()).collect();
+    candidates.sort_by(|a, b| a.total_cmp(b));
+    candidates.dedup();
+
+    let mut best: Option<ClassCalibration> = None;
+    for threshold in candidates {
+        let true_positives = points.iter().filter(|(s, truth)| *truth && *s >= threshold).count();
+        let true_negatives = points.iter().filter(|(s, truth)| !*truth && *s < threshold).count();
+        let sensitivity = true_positives as f64 / positives as f64;
+        let specificity = true_negatives as f64 / negatives as f64;
+        let youden_j = sensitivity + specificity - 1.0;
+
+        // Ties keep the lowest threshold, favouring sensitivity
+        if best.as_ref().is_none_or(|b| youden_j > b.youden_j) {
+            best = Some(ClassCalibration {
+                threshold,
+                youden_j,
+                sensitivity,
+                specificity,
+                positives,
+                negatives,
+                platt: None,
+                isotonic: Vec::new(),
+            });
+        }
+    }
+
+    best
+}
+
+/// Fit Platt scaling by Newton's method with backtracking, using Platt's smoothed
+/// targets so that perfectly separated classes do not diverge
+pub fn fit_platt(points: &[(f64, bool)]) -> Option<PlattScaling> {
+    let points: Vec<(f64, bool)> = points.iter().copied().filter(|(s, _)| s.is_finite()).collect();
+    let positives = points.iter().filter(|(_, truth)| *truth).count() as f64;
+    let negatives = points.len() as f64 - positives;
+    if positives == 0.0 || negatives == 0.0 {
+        return None;
+    }
+
+    let hi_target = (positives + 1.0) / (positives + 2.0);
+    let lo_target = 1.0 / (negatives + 2.0);
+    let targets: Vec<f64> = points
+        .iter()
+        .map(|(_, truth)| if *truth { hi_target } else { lo_target })
+        .collect();
+
+    let objective = |a: f64, b: f64| -> f64 {
+        points
+            .iter()
+            .zip(&targets)
+            .map(|((score, _), t)| {
+                let f = a * score + b;
+                if f >= 0.0 {
+                    t * f + (1.0 + (-f).exp()).ln()
+                } else {
+                    (t - 1.0) * f + (1.0 + f.exp()).ln()
+                }
+            })
+            .sum()
+    };
+
+    let mut a = 0.0;
+    let mut b = ((negatives + 1.0) / (positives + 1.0)).ln();
+    let mut value = objective(a, b);
+
+    for _ in 0..100 {
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (1e-12, 1e-12, 0.0, 0.0, 0.0);
+        for ((score, _), t) in points.iter().zip(&targets) {
+            let p = PlattScaling { a, b }.probability(*score);
+            let d2 = p * (1.0 - p);
+            h11 += score * score * d2;
+            h22 += d2;
+            h21 += score * d2;
+            let d1 = t - p;
+            g1 += score * d1;
+            g2 += d1;
+        }
+        if g1.abs() < 1e-5 && g2.abs() < 1e-5 {
+            break;
+        }
+
+        let det = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / det;
+        let db = -(-h21 * g1 + h11 * g2) / det;
+        let gd = g1 * da + g2 * db;
+
+        let mut step = 1.0;
+        while step >= 1e-10 {
+            let (new_a, new_b) = (a + step * da, b + step * db);
+            let new_value = objective(new_a, new_b);
+            if new_value < value + 1e-4 * step * gd {
+                a = new_a;
+                b = new_b;
+                value = new_value;
+                break;
+            }
+            step /= 2.0;
+        }
+        if step < 1e-10 {
+            break;
+        }
+    }
+
+    Some(PlattScaling { a, b })
+}
+
+/// Fit a non-decreasing step function of P(detected) against score (pool-adjacent-violators)
+pub fn fit_isotonic(points: &[(f64, bool)]) -> Vec<IsotonicStep> {
+    let mut sorted: Vec<(f64, bool)> = points.iter().copied().filter(|(s, _)| !s.is_nan()).collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // (lowest score, detected count, total count); tied scores always share a block
+    let mut blocks: Vec<(f64, f64, f64)> = Vec::new();
+    for (score, truth) in sorted {
+        let detected = if truth { 1.0 } else { 0.0 };
+        match blocks.last_mut() {
+            Some(last) if last.0 == score => {
+                last.1 += detected;
+                last.2 += 1.0;
+            }
+            _ => blocks.push((score, detected, 1.0)),
+        }
+        while blocks.len() >= 2 {
+            let (_, cur_detected, cur_total) = blocks[blocks.len() - 1];
+            let prev = blocks[blocks.len() - 2];
+            if prev.1 / prev.2 < cur_detected / cur_total {
+                break;
+            }
+            blocks.pop();
+            let last = blocks.last_mut().unwrap();
+            last.1 += cur_detected;
+            last.2 += cur_total;
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|(score, detected, total)| IsotonicStep {
+            score,
+            probability: detected / total,
+        })
+        .collect()
+}
+
+/// Evaluate an isotonic step function; scores below the first step take its probability
+pub fn isotonic_probability(steps: &[IsotonicStep], score: f64) -> Option<f64> {
+    let first = steps.first()?;
+    Some(
+        steps
+            .iter()
+            .take_while(|step| step.score <= score)
+            .last()
+            .unwrap_or(first)
+            .probability,
+    )
+}
+
+/// Pair scored results (as read by `read_detectability_results`) with truth labels;
+/// results without a truth label are skipped
+pub fn label_results(
+    results: &HashMap<(String, u64, String, String), (DetectabilityCondition, f64)>,
+    truth: &HashMap<Variant, bool>,
+) -> Vec<(Variant, f64, bool)> {
+    let mut labelled: Vec<(Variant, f64, bool)> = results
+        .iter()
+        .filter_map(|((chrom, pos, ref_allele, alt_allele), (_, score))| {
+            let variant = Variant::new(chrom.clone(), *pos, ref_allele.clone(), alt_allele.clone());
+            truth.get(&variant).map(|&label| (variant, *score, label))
+        })
+        .collect();
+    labelled.sort_by(|a, b| (&a.0.chrom, a.0.pos).cmp(&(&b.0.chrom, b.0.pos)));
+    labelled
+}
+
+/// Parse a truth label (detected/undetected, yes/no, true/false, 1/0)
+fn parse_truth_label(label: &str) -> Option<bool> {
+    match label.trim().to_ascii_lowercase().as_str() {
+        "detected" | "yes" | "true" | "1" => Some(true),
+        "undetected" | "not_detected" | "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Read a truth set TSV (`Chrom Pos Ref Alt Truth`, header optional)
+pub fn read_truth_set<P: AsRef<Path>>(path: P) -> VlodResult<HashMap<Variant, bool>> {
+    let file = File::open(&path)
+        .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+
+    let mut truth = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            return Err(VlodError::InvalidVariant(format!(
+                "Invalid truth set line - expected 5 columns: {}",
+                line
+            )));
+        }
+
+        let Ok(pos) = fields[1].parse::<u64>() else {
+            if fields[1].eq_ignore_ascii_case("pos") {
+                continue;
+            }
+            return Err(VlodError::InvalidVariant(format!("Invalid position: {}", fields[1])));
+        };
+        let label = parse_truth_label(fields[4])
+            .ok_or_else(|| VlodError::InvalidVariant(format!("Invalid truth label: {}", fields[4])))?;
+
+        truth.insert(
+            Variant::new(fields[0].to_string(), pos, fields[2].to_string(), fields[3].to_string()),
+            label,
+        );
+    }
+
+    Ok(truth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn variant(ref_allele: &str, alt_allele: &str) -> Variant {
+        Variant::new("chr1".to_string(), 100, ref_allele.to_string(), alt_allele.to_string())
+    }
+
+    #[test]
+    fn test_variant_class() {
+        assert_eq!(VariantClass::of(&variant("A", "T")), VariantClass::Snv);
+        assert_eq!(VariantClass::of(&variant("AC", "TG")), VariantClass::Mnv);
+        assert_eq!(VariantClass::of(&variant("A", "ATT")), VariantClass::Insertion);
+        assert_eq!(VariantClass::of(&variant("ATT", "A")), VariantClass::Deletion);
+        assert_eq!(VariantClass::of(&variant("ATT", "GC")), VariantClass::Complex);
+        assert_eq!(VariantClass::of(&variant("A", "TCC")), VariantClass::Complex);
+    }
+
+    #[test]
+    fn test_fit_youden_threshold() {
+        let points = vec![(1.0, false), (2.0, false), (3.0, true), (4.0, true)];
+        let fit = fit_youden_threshold(&points).unwrap();
+        assert_eq!(fit.threshold, 3.0);
+        assert_eq!(fit.youden_j, 1.0);
+        assert_eq!((fit.positives, fit.negatives), (2, 2));
+
+        // Overlapping scores: 1.8 keeps every positive and rejects half the negatives
+        let points = vec![(0.5, false), (2.5, false), (1.8, true), (2.0, true), (3.0, true)];
+        let fit = fit_youden_threshold(&points).unwrap();
+        assert_eq!(fit.threshold, 1.8);
+        assert_eq!(fit.sensitivity, 1.0);
+        assert_eq!(fit.specificity, 0.5);
+
+        assert!(fit_youden_threshold(&[(1.0, true), (2.0, true)]).is_none());
+        assert!(fit_youden_threshold(&[]).is_none());
+    }
+
+    #[test]
+    fn test_calibration_fit_and_roundtrip() {
+        let observations = vec![
+            (variant("A", "T"), 1.0, false),
+            (variant("A", "T"), 3.2, true),
+            (variant("A", "ATT"), 0.5, false),
+            (variant("A", "ATT"), 1.9, true),
+            (variant("ATT", "A"), 2.0, true),
+        ];
+        let calibration = Calibration::fit(&observations, ProbabilityMethod::Platt);
+
+        assert_eq!(calibration.threshold_for(&variant("C", "G")), Some(3.2));
+        assert_eq!(calibration.threshold_for(&variant("C", "CA")), Some(1.9));
+        // Deletions had no negatives, so they fall back to the default threshold
+        assert_eq!(calibration.threshold_for(&variant("CA", "C")), None);
+
+        let output = NamedTempFile::new().unwrap();
+        calibration.write(output.path()).unwrap();
+        let content = std::fs::read_to_string(output.path()).unwrap();
+        assert!(content.contains("\"snv\""));
+        assert_eq!(Calibration::from_file(output.path()).unwrap(), calibration);
+    }
+
+    #[test]
+    fn test_fit_platt() {
+        let points = vec![(0.5, false), (1.0, false), (1.5, false), (2.0, true), (2.5, false), (3.0, true), (3.5, true), (4.0, true)];
+        let platt = fit_platt(&points).unwrap();
+
+        assert!(platt.a < 0.0);
+        assert!(platt.probability(0.5) < 0.2);
+        assert!(platt.probability(4.0) > 0.8);
+        assert!(platt.probability(1.0) < platt.probability(3.0));
+        // Extreme scores stay finite
+        assert!(platt.probability(1e6).is_finite());
+        assert!(platt.probability(-1e6).is_finite());
+
+        assert!(fit_platt(&[(1.0, true)]).is_none());
+    }
+
+    #[test]
+    fn test_fit_isotonic() {
+        let points = vec![(1.0, false), (2.0, true), (3.0, false), (4.0, true), (5.0, true)];
+        let steps = fit_isotonic(&points);
+
+        // The 2.0/3.0 violation is pooled into one block at 0.5
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1], IsotonicStep { score: 2.0, probability: 0.5 });
+        assert_eq!(isotonic_probability(&steps, 0.0), Some(0.0));
+        assert_eq!(isotonic_probability(&steps, 2.5), Some(0.5));
+        assert_eq!(isotonic_probability(&steps, 9.0), Some(1.0));
+        assert_eq!(isotonic_probability(&[], 1.0), None);
+
+        let probabilities: Vec<f64> = steps.iter().map(|s| s.probability).collect();
+        assert!(probabilities.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_calibration_probability() {
+        let observations = vec![
+            (variant("A", "T"), 0.5, false),
+            (variant("A", "T"), 1.0, false),
+            (variant("A", "T"), 3.0, true),
+            (variant("A", "T"), 3.5, true),
+        ];
+
+        let calibration = Calibration::fit(&observations, ProbabilityMethod::Isotonic);
+        assert_eq!(calibration.probability(&variant("C", "G"), 3.2), Some(1.0));
+        assert_eq!(calibration.probability(&variant("C", "G"), 0.7), Some(0.0));
+        assert_eq!(calibration.probability(&variant("C", "CA"), 3.2), None);
+
+        let calibration = Calibration::fit(&observations, ProbabilityMethod::Platt);
+        let low = calibration.probability(&variant("C", "G"), 0.7).unwrap();
+        let high = calibration.probability(&variant("C", "G"), 3.2).unwrap();
+        assert!(low < 0.5 && high > 0.5);
+
+        assert_eq!("isotonic".parse::<ProbabilityMethod>(), Ok(ProbabilityMethod::Isotonic));
+        assert!("spline".parse::<ProbabilityMethod>().is_err());
+    }
+
+    #[test]
+    fn test_read_truth_set() {
+        let mut truth_file = NamedTempFile::new().unwrap();
+        writeln!(truth_file, "Chrom\tPos\tRef\tAlt\tTruth").unwrap();
+        writeln!(truth_file, "chr1\t100\tA\tT\tdetected").unwrap();
+        writeln!(truth_file, "chr1\t200\tG\tC\tundetected").unwrap();
+
+        let truth = read_truth_set(truth_file.path()).unwrap();
+        assert_eq!(truth.len(), 2);
+        assert_eq!(truth.get(&variant("A", "T")), Some(&true));
+
+        let mut results = HashMap::new();
+        results.insert(("chr1".to_string(), 100, "A".to_string(), "T".to_string()), (DetectabilityCondition::Detectable, 3.1));
+        results.insert(("chr1".to_string(), 300, "A".to_string(), "T".to_string()), (DetectabilityCondition::NonDetectable, 0.4));
+        let labelled = label_results(&results, &truth);
+        assert_eq!(labelled, vec![(variant("A", "T"), 3.1, true)]);
+
+        let mut bad_file = NamedTempFile::new().unwrap();
+        writeln!(bad_file, "chr1\t100\tA\tT\tmaybe").unwrap();
+        assert!(read_truth_set(bad_file.path()).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Chimerism monitoring: donor mixture fraction and its limit of detection from
+//! informative donor/recipient SNPs
+
+use crate::{
+    about::about_comment,
+    lod::{calculate_lod_score, min_alt_reads_reaching},
+    pool::binomial_upper_tail,
+    utils::create_output_file,
+    DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Bisection steps when solving for a fraction in [0, 1]
+const FRACTION_SEARCH_STEPS: usize = 60;
+
+/// Settings for chimerism estimation. Markers are SNPs where the recipient is
+/// homozygous reference and the donor carries the ALT allele.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChimerismOptions {
+    /// ALT allele fraction in pure donor DNA (1.0 homozygous, 0.5 heterozygous)
+    pub donor_allele_fraction: f64,
+    /// Confidence of the mixture fraction interval and power of the LoD
+    pub confidence: f64,
+}
+
+impl Default for ChimerismOptions {
+    fn default() -> Self {
+        Self {
+            donor_allele_fraction: 1.0,
+            confidence: 0.95,
+        }
+    }
+}
+
+impl ChimerismOptions {
+    pub fn validate(&self) -> VlodResult<()> {
+        if !(self.donor_allele_fraction > 0.0 && self.donor_allele_fraction <= 1.0) {
+            return Err(VlodError::InvalidConfig(
+                "donor allele fraction must be in (0, 1]".to_string(),
+            ));
+        }
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(VlodError::InvalidConfig(
+                "chimerism confidence must be between 0 and 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Donor support at one informative marker
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkerEstimate {
+    pub variant: Variant,
+    pub depth: u32,
+    pub donor_reads: u32,
+    /// Donor fraction implied by this marker alone (None without coverage)
+    pub mixture_fraction: Option<f64>,
+}
+
+/// Aggregate donor fraction over all covered markers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChimerismEstimate {
+    pub markers: Vec<MarkerEstimate>,
+    /// Reads over all covered markers
+    pub depth: u32,
+    pub donor_reads: u32,
+    /// Pooled donor fraction (None without coverage)
+    pub mixture_fraction: Option<f64>,
+    /// Clopper-Pearson bounds of the donor fraction at the configured confidence
+    pub lower_bound: Option<f64>,
+    pub upper_bound: Option<f64>,
+    /// Smallest donor fraction detected with the configured power (None when no
+    /// fraction reaches it at this depth)
+    pub lod: Option<f64>,
+}
+
+/// Estimate the donor fraction and the chimerism LoD from the detectability
+/// results of the informative markers. The pooled donor reads are detected when
+/// their VAF reaches the detection threshold; the LoD is the smallest donor
+/// fraction at which that happens with probability `confidence`.
+pub fn estimate_chimerism(
+    results: &[DetectabilityResult],
+    config: &LodConfig,
+    options: &ChimerismOptions,
+) -> ChimerismEstimate {
+    let donor_af = options.donor_allele_fraction;
+    let markers: Vec<MarkerEstimate> = results
+        .iter()
+        .map(|result| MarkerEstimate {
+            variant: result.variant.clone(),
+            depth: result.coverage,
+            donor_reads: result.variant_reads,
+            mixture_fraction: (result.coverage > 0)
+                .then(|| (result.variant_reads as f64 / result.coverage as f64 / donor_af).min(1.0)),
+        })
+        .collect();
+
+    let depth: u32 = markers.iter().map(|m| m.depth).sum();
+    let donor_reads: u32 = markers.iter().map(|m| m.donor_reads).sum();
+    if depth == 0 {
+        return ChimerismEstimate {
+            markers,
+            depth,
+            donor_reads,
+            mixture_fraction: None,
+            lower_bound: None,
+            upper_bound: None,
+            lod: None,
+        };
+    }
+
+    let alpha = 1.0 - options.confidence;
+    // Clopper-Pearson bounds of the donor allele fraction in the reads
+    let lower = if donor_reads == 0 {
+        0.0
+    } else {
+        solve_increasing(|p| binomial_upper_tail(depth, donor_reads, p), alpha / 2.0)
+    };
+    let upper = if donor_reads == depth {
+        1.0
+    } else {
+        solve_increasing(|p| binomial_upper_tail(depth, donor_reads + 1, p), 1.0 - alpha / 2.0)
+    };
+
+    let lod = min_alt_reads_reaching(depth, config.score_threshold, |vaf| calculate_lod_score(vaf, config))
+        .and_then(|min_reads| {
+            let power = |fraction: f64| binomial_upper_tail(depth, min_reads, fraction * donor_af);
+            (power(1.0) >= options.confidence).then(|| solve_increasing(power, options.confidence))
+        });
+
+    ChimerismEstimate {
+        markers,
+        depth,
+        donor_reads,
+        mixture_fraction: Some((donor_reads as f64 / depth as f64 / donor_af).min(1.0)),
+        lower_bound: Some((lower / donor_af).min(1.0)),
+        upper_bound: Some((upper / donor_af).min(1.0)),
+        lod,
+    }
+}
+
+/// Smallest x in [0, 1] with `f(x) >= target`, for `f` increasing
+fn solve_increasing(f: impl Fn(f64) -> f64, target: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..FRACTION_SEARCH_STEPS {
+        let mid = (low + high) / 2.0;
+        if f(mid) >= target {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    high
+}
+
+/// Write per-marker donor support as TSV, preceded by a `#` line with the aggregate
+/// estimate
+pub fn write_chimerism_report<P: AsRef<Path>>(estimate: &ChimerismEstimate, path: P) -> VlodResult<()> {
+    let writer = BufWriter::new(create_output_file(path)?);
+    write_chimerism_report_to_writer(estimate, writer)
+}
+
+/// Write the chimerism report to any writer
+pub fn write_chimerism_report_to_writer<W: Write>(estimate: &ChimerismEstimate, mut writer: W) -> VlodResult<()> {
+    let format = |value: Option<f64>| value.map(|v| format!("{:.6}", v)).unwrap_or_else(|| ".".to_string());
+    writeln!(
+        writer,
+        "#markers={} #depth={} #donor_reads={} #mixture_fraction={} #lower_bound={} #upper_bound={} #lod={} {}",
+        estimate.markers.len(),
+        estimate.depth,
+        estimate.donor_reads,
+        format(estimate.mixture_fraction),
+        format(estimate.lower_bound),
+        format(estimate.upper_bound),
+        format(estimate.lod),
+        about_comment()
+    )?;
+    writeln!(writer, "Chrom\tPos\tRef\tAlt\tDepth\tDonor_Reads\tMixture_Fraction")?;
+    for marker in &estimate.markers {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            marker.variant.chrom,
+            marker.variant.pos,
+            marker.variant.ref_allele,
+            marker.variant.alt_allele,
+            marker.depth,
+            marker.donor_reads,
+            format(marker.mixture_fraction)
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DetectabilityCondition;
+
+    fn marker(pos: u64, depth: u32, donor_reads: u32) -> DetectabilityResult {
+        DetectabilityResult::new(
+            Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()),
+            0.0,
+            DetectabilityCondition::NonDetectable,
+            depth,
+            donor_reads,
+        )
+    }
+
+    #[test]
+    fn test_estimate_chimerism() {
+        let results = vec![marker(100, 1000, 10), marker(200, 1000, 12), marker(300, 1000, 8)];
+        let estimate = estimate_chimerism(&results, &LodConfig::default(), &ChimerismOptions::default());
+
+        assert_eq!(estimate.depth, 3000);
+        assert_eq!(estimate.donor_reads, 30);
+        assert!((estimate.mixture_fraction.unwrap() - 0.01).abs() < 1e-12);
+        assert!(estimate.lower_bound.unwrap() < 0.01 && estimate.upper_bound.unwrap() > 0.01);
+        // The default threshold needs a VAF of about 4.4%
+        let lod = estimate.lod.unwrap();
+        assert!(lod > 0.044 && lod < 0.06, "{}", lod);
+
+        // Heterozygous donor markers carry half the donor fraction
+        let options = ChimerismOptions {
+            donor_allele_fraction: 0.5,
+            ..ChimerismOptions::default()
+        };
+        let estimate = estimate_chimerism(&results, &LodConfig::default(), &options);
+        assert!((estimate.mixture_fraction.unwrap() - 0.02).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_estimate_chimerism_without_coverage() {
+        let estimate = estimate_chimerism(&[marker(100, 0, 0)], &LodConfig::default(), &ChimerismOptions::default());
+        assert_eq!(estimate.mixture_fraction, None);
+        assert_eq!(estimate.lod, None);
+        assert!(ChimerismOptions { confidence: 1.0, ..ChimerismOptions::default() }.validate().is_err());
+
+        let mut report = Vec::new();
+        write_chimerism_report_to_writer(&estimate, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("#markers=1 #depth=0"));
+        assert!(report.contains("chr1\t100\tA\tG\t0\t0\t."));
+    }
+}
@@ -0,0 +1,225 @@
+//! Assay claims ("SNVs at >= 5% VAF are detected at >= 250x in the panel regions")
+//! read from a JSON specification and evaluated against a run, giving a pass/fail
+//! verdict per claim so that a run can be accepted or rejected automatically
+
+use crate::{
+    calibration::VariantClass,
+    regions::{read_regions, BedRegion},
+    rollup::{is_assessable, required_depth_for_vaf},
+    DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Exit status of a run that fails one of its claims (`compare --exit-code` uses 2)
+pub const CLAIMS_FAILED_EXIT_CODE: i32 = 3;
+
+/// One performance claim of an assay. A variant position in its scope meets the
+/// claim when it has the claimed depth and a variant at the claimed VAF would be
+/// detected there with `ROLLUP_MIN_POWER`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claim {
+    pub name: String,
+    /// Variant classes the claim covers; all when empty
+    #[serde(default)]
+    pub classes: Vec<VariantClass>,
+    /// Lowest VAF claimed to be detected
+    pub vaf: f64,
+    /// Depth every position in scope must reach
+    #[serde(default)]
+    pub min_depth: Option<u32>,
+    /// Regions file (BED, interval_list or `chr1:100-200` lines) the claim covers,
+    /// relative to the claims file; the whole run when absent
+    #[serde(default)]
+    pub regions: Option<PathBuf>,
+    /// Fraction of the positions in scope that must meet the claim
+    #[serde(default = "default_min_fraction")]
+    pub min_fraction: f64,
+    #[serde(skip)]
+    region_list: Option<Vec<BedRegion>>,
+}
+
+fn default_min_fraction() -> f64 {
+    1.0
+}
+
+impl Claim {
+    fn validate(&self) -> VlodResult<()> {
+        if self.name.trim().is_empty() {
+            return Err(VlodError::InvalidConfig("every claim needs a name".to_string()));
+        }
+        if !(self.vaf > 0.0 && self.vaf <= 1.0) {
+            return Err(VlodError::InvalidConfig(format!(
+                "claim '{}': the VAF must be in (0, 1], got {}",
+                self.name, self.vaf
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.min_fraction) {
+            return Err(VlodError::InvalidConfig(format!(
+                "claim '{}': min_fraction must be in [0, 1], got {}",
+                self.name, self.min_fraction
+            )));
+        }
+        Ok(())
+    }
+
+    /// Regions read from the claim's regions file, if it names one
+    pub fn region_list(&self) -> Option<&[BedRegion]> {
+        self.region_list.as_deref()
+    }
+
+    /// Whether the claim covers the class of a variant
+    pub fn covers_class(&self, variant: &Variant) -> bool {
+        self.classes.is_empty() || self.classes.contains(&VariantClass::of(variant))
+    }
+
+    /// Depth at which a variant meets the claim: the claimed depth, or more when
+    /// detecting the claimed VAF needs it (None when no depth up to
+    /// `MAX_REQUIRED_DEPTH` would)
+    pub fn required_depth(&self, variant: &Variant, config: &LodConfig) -> Option<u32> {
+        let depth = required_depth_for_vaf(variant, config, self.vaf)?;
+        Some(self.min_depth.map_or(depth, |min_depth| depth.max(min_depth)))
+    }
+
+    /// Whether a result is in the scope of the claim
+    fn covers(&self, result: &DetectabilityResult, regions: &HashMap<&str, Vec<&BedRegion>>) -> bool {
+        let variant = &result.variant;
+        self.covers_class(variant)
+            && (self.region_list.is_none()
+                || regions.get(variant.chrom.as_str()).is_some_and(|regions| {
+                    regions.iter().any(|region| region.contains(&variant.chrom, variant.pos.saturating_sub(1)))
+                }))
+    }
+
+    /// Whether a position in scope meets the claim
+    fn is_met(&self, result: &DetectabilityResult, config: &LodConfig) -> bool {
+        self.min_depth.is_none_or(|depth| result.coverage >= depth) && is_assessable(result, config, self.vaf)
+    }
+
+    /// Evaluate the claim against the results of a run. A claim with no position in
+    /// scope fails, as the run cannot support it.
+    pub fn evaluate(&self, results: &[DetectabilityResult], config: &LodConfig) -> ClaimVerdict {
+        let mut by_chrom: HashMap<&str, Vec<&BedRegion>> = HashMap::new();
+        for region in self.region_list.iter().flatten() {
+            by_chrom.entry(region.chrom.as_str()).or_default().push(region);
+        }
+        let in_scope: Vec<&DetectabilityResult> =
+            results.iter().filter(|result| self.covers(result, &by_chrom)).collect();
+        let met = in_scope.iter().filter(|result| self.is_met(result, config)).count();
+        let fraction_met = (!in_scope.is_empty()).then(|| met as f64 / in_scope.len() as f64);
+        ClaimVerdict {
+            name: self.name.clone(),
+            variants: in_scope.len(),
+            met,
+            fraction_met,
+            passed: fraction_met.is_some_and(|fraction| fraction >= self.min_fraction),
+        }
+    }
+}
+
+/// Outcome of one claim for a run, recorded in the run summary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimVerdict {
+    pub name: String,
+    /// Variant positions in the scope of the claim
+    pub variants: usize,
+    /// Positions meeting the claim
+    pub met: usize,
+    /// Fraction of the positions meeting the claim (None without any)
+    pub fraction_met: Option<f64>,
+    pub passed: bool,
+}
+
+/// Claims of an assay, as given with `--claims`:
+/// `{"claims": [{"name": "SNV 5%", "classes": ["snv"], "vaf": 0.05, "min_depth": 250,
+/// "regions": "panel.bed", "min_fraction": 0.95}]}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimSet {
+    pub claims: Vec<Claim>,
+}
+
+impl ClaimSet {
+    /// Read a claims file and the regions files it names
+    pub fn from_file<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        let file = File::open(&path)
+            .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+        let base = path.as_ref().parent().unwrap_or(Path::new(""));
+        Self::from_reader(BufReader::new(file), base)
+    }
+
+    /// Read claims from JSON, resolving relative regions files against `base`
+    pub fn from_reader<R: Read>(reader: R, base: &Path) -> VlodResult<Self> {
+        let mut set: ClaimSet = serde_json::from_reader(reader)
+            .map_err(|e| VlodError::InvalidConfig(format!("invalid claims file: {}", e)))?;
+        for claim in &mut set.claims {
+            claim.validate()?;
+            if let Some(regions) = &claim.regions {
+                claim.region_list = Some(read_regions(base.join(regions))?);
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn evaluate(&self, results: &[DetectabilityResult], config: &LodConfig) -> Vec<ClaimVerdict> {
+        self.claims.iter().map(|claim| claim.evaluate(results, config)).collect()
+    }
+}
+
+/// Log the verdict of each claim; returns whether all of them passed
+pub fn log_claim_verdicts(verdicts: &[ClaimVerdict]) -> bool {
+    log::info!("Assay claims:");
+    for verdict in verdicts {
+        let fraction = verdict.fraction_met.map_or("-".to_string(), |fraction| format!("{:.1}%", 100.0 * fraction));
+        let status = if verdict.passed { "PASS" } else { "FAIL" };
+        log::info!(
+            "  {}: {} ({} of {} positions met, {})",
+            verdict.name,
+            status,
+            verdict.met,
+            verdict.variants,
+            fraction
+        );
+    }
+    verdicts.iter().all(|verdict| verdict.passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DetectabilityCondition;
+
+    fn result(pos: u64, alt: &str, coverage: u32) -> DetectabilityResult {
+        let variant = Variant::new("chr1".to_string(), pos, "A".to_string(), alt.to_string());
+        DetectabilityResult::new(variant, 1.0, DetectabilityCondition::NonDetectable, coverage, 0)
+    }
+
+    #[test]
+    fn test_evaluate_claims() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hotspots.bed"), "chr1\t99\t300\n").unwrap();
+        let json = r#"{"claims": [
+            {"name": "SNV 20% at 250x", "classes": ["snv"], "vaf": 0.2, "min_depth": 250},
+            {"name": "SNV 20% in hotspots", "classes": ["snv"], "vaf": 0.2, "regions": "hotspots.bed"},
+            {"name": "SNV 0.1%", "vaf": 0.001, "min_fraction": 0.5},
+            {"name": "Insertions", "classes": ["insertion"], "vaf": 0.2}
+        ]}"#;
+        let claims = ClaimSet::from_reader(json.as_bytes(), dir.path()).unwrap();
+        let results = vec![result(100, "G", 300), result(200, "G", 200), result(400, "G", 0)];
+        let verdicts = claims.evaluate(&results, &LodConfig::default());
+
+        assert_eq!((verdicts[0].variants, verdicts[0].met, verdicts[0].passed), (3, 1, false));
+        assert_eq!((verdicts[1].variants, verdicts[1].met, verdicts[1].passed), (2, 2, true));
+        assert_eq!((verdicts[2].variants, verdicts[2].met, verdicts[2].passed), (3, 0, false));
+        assert_eq!((verdicts[3].variants, verdicts[3].fraction_met, verdicts[3].passed), (0, None, false));
+        assert!(!log_claim_verdicts(&verdicts));
+        assert!(log_claim_verdicts(&verdicts[1..2]));
+
+        let invalid = r#"{"claims": [{"name": "SNV", "vaf": 5}]}"#;
+        assert!(ClaimSet::from_reader(invalid.as_bytes(), dir.path()).is_err());
+        let missing_regions = r#"{"claims": [{"name": "SNV", "vaf": 0.05, "regions": "missing.bed"}]}"#;
+        assert!(ClaimSet::from_reader(missing_regions.as_bytes(), dir.path()).is_err());
+    }
+}
@@ -0,0 +1,430 @@
+//! Comparison of two sets of detectability results (e.g. Python vs Rust vLoD, or
+//! runs before and after a parameter change): per-variant score deltas and
+//! classification flips, grouped by cause
+
+use crate::{
+    about::about_comment,
+    merge::{is_annotated_vcf, read_annotated_vcf, read_detectability_results, read_result_coverage},
+    utils::create_output_file,
+    vcf::DuplicatePolicy,
+    DetectabilityCondition, DetectabilityResult, VlodResult,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+type ResultMap = HashMap<(String, u64, String, String), ComparedResult>;
+
+/// The parts of a detectability result that are compared
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparedResult {
+    pub condition: DetectabilityCondition,
+    pub score: f64,
+    /// Site coverage, when the source records it (results TSVs do, VCFs do not)
+    pub coverage: Option<u32>,
+}
+
+impl From<(DetectabilityCondition, f64)> for ComparedResult {
+    fn from((condition, score): (DetectabilityCondition, f64)) -> Self {
+        ComparedResult {
+            condition,
+            score,
+            coverage: None,
+        }
+    }
+}
+
+/// Tolerance for scores that differ only through float formatting or summation order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreTolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Default for ScoreTolerance {
+    fn default() -> Self {
+        Self {
+            absolute: 1e-6,
+            relative: 1e-6,
+        }
+    }
+}
+
+impl ScoreTolerance {
+    /// Whether two scores agree within either tolerance (NaNs agree with each other)
+    pub fn equal(&self, a: f64, b: f64) -> bool {
+        if a == b || (a.is_nan() && b.is_nan()) {
+            return true;
+        }
+        let delta = (a - b).abs();
+        delta <= self.absolute || delta <= self.relative * a.abs().max(b.abs())
+    }
+}
+
+/// How a variant differs between the two result sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DifferenceKind {
+    /// The detectability condition changed
+    Flip,
+    /// Same condition, score outside the tolerance
+    Score,
+    /// Only the old (reference) results have the variant
+    OnlyOld,
+    /// Only the new results have the variant
+    OnlyNew,
+}
+
+impl fmt::Display for DifferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DifferenceKind::Flip => "classification_flip",
+            DifferenceKind::Score => "score_delta",
+            DifferenceKind::OnlyOld => "only_in_old",
+            DifferenceKind::OnlyNew => "only_in_new",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Likely reason a variant present in both sets changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeCause {
+    /// The site coverage differs
+    Coverage,
+    /// Same score, different classification: the detection threshold changed
+    Threshold,
+    /// The score moved at unchanged (or unknown) coverage, e.g. a prior changed
+    Score,
+}
+
+impl fmt::Display for ChangeCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChangeCause::Coverage => "coverage_change",
+            ChangeCause::Threshold => "threshold_change",
+            ChangeCause::Score => "score_change",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One variant whose results differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultDifference {
+    pub key: (String, u64, String, String),
+    pub old: Option<ComparedResult>,
+    pub new: Option<ComparedResult>,
+    pub kind: DifferenceKind,
+    /// Set for variants present in both sets
+    pub cause: Option<ChangeCause>,
+}
+
+impl ResultDifference {
+    /// New score minus old score, when the variant is in both
+    pub fn score_delta(&self) -> Option<f64> {
+        Some(self.new.as_ref()?.score - self.old.as_ref()?.score)
+    }
+}
+
+/// Outcome of comparing two result sets
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    /// Variants present in both sets
+    pub compared: usize,
+    /// Differing variants, sorted by chromosome, position and alleles
+    pub differences: Vec<ResultDifference>,
+}
+
+impl Comparison {
+    pub fn count(&self, kind: DifferenceKind) -> usize {
+        self.differences.iter().filter(|d| d.kind == kind).count()
+    }
+
+    /// Number of changed variants attributed to a cause
+    pub fn count_cause(&self, cause: ChangeCause) -> usize {
+        self.differences.iter().filter(|d| d.cause == Some(cause)).count()
+    }
+
+    /// Largest absolute score delta among variants in both sets
+    pub fn max_score_delta(&self) -> f64 {
+        self.differences
+            .iter()
+            .filter_map(|d| d.score_delta())
+            .filter(|delta| delta.is_finite())
+            .fold(0.0, |max, delta| max.max(delta.abs()))
+    }
+
+    /// Whether both sets classify every variant identically
+    pub fn is_concordant(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub fn log_summary(&self, old_label: &str, new_label: &str) {
+        log::info!("Comparison of {} and {} results:", old_label, new_label);
+        log::info!("  Variants in both: {}", self.compared);
+        log::info!("  Classification flips: {}", self.count(DifferenceKind::Flip));
+        log::info!("  Score deltas beyond tolerance: {}", self.count(DifferenceKind::Score));
+        log::info!("  Largest score delta: {:.6}", self.max_score_delta());
+        log::info!("  Only in {}: {}", old_label, self.count(DifferenceKind::OnlyOld));
+        log::info!("  Only in {}: {}", new_label, self.count(DifferenceKind::OnlyNew));
+        for cause in [ChangeCause::Coverage, ChangeCause::Threshold, ChangeCause::Score] {
+            log::info!("  Changes by {}: {}", cause, self.count_cause(cause));
+        }
+    }
+}
+
+/// Key results by variant, in the form read back from a results TSV
+pub fn results_map(results: &[DetectabilityResult]) -> ResultMap {
+    results
+        .iter()
+        .map(|result| {
+            let variant = &result.variant;
+            (
+                (
+                    variant.chrom.clone(),
+                    variant.pos,
+                    variant.ref_allele.clone(),
+                    variant.alt_allele.clone(),
+                ),
+                ComparedResult {
+                    condition: result.detectability_condition.clone(),
+                    score: result.detectability_score,
+                    coverage: Some(result.coverage),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Compare old (reference) results with new ones
+pub fn compare_results(old: &ResultMap, new: &ResultMap, tolerance: &ScoreTolerance) -> Comparison {
+    let keys: BTreeSet<&(String, u64, String, String)> = old.keys().chain(new.keys()).collect();
+    let mut comparison = Comparison::default();
+
+    for key in keys {
+        let old_result = old.get(key);
+        let new_result = new.get(key);
+        let (kind, cause) = match (old_result, new_result) {
+            (Some(old_result), Some(new_result)) => {
+                comparison.compared += 1;
+                let same_score = tolerance.equal(old_result.score, new_result.score);
+                let kind = if old_result.condition != new_result.condition {
+                    DifferenceKind::Flip
+                } else if !same_score {
+                    DifferenceKind::Score
+                } else {
+                    continue;
+                };
+                let cause = match (old_result.coverage, new_result.coverage) {
+                    (Some(old_coverage), Some(new_coverage)) if old_coverage != new_coverage => ChangeCause::Coverage,
+                    _ if same_score => ChangeCause::Threshold,
+                    _ => ChangeCause::Score,
+                };
+                (kind, Some(cause))
+            }
+            (Some(_), None) => (DifferenceKind::OnlyOld, None),
+            (None, _) => (DifferenceKind::OnlyNew, None),
+        };
+        comparison.differences.push(ResultDifference {
+            key: key.clone(),
+            old: old_result.cloned(),
+            new: new_result.cloned(),
+            kind,
+            cause,
+        });
+    }
+
+    comparison
+}
+
+/// Write the differing variants as TSV, labelling the two sides (e.g. Python, Rust)
+pub fn write_difference_report<P: AsRef<Path>>(
+    comparison: &Comparison,
+    labels: (&str, &str),
+    path: P,
+) -> VlodResult<()> {
+    let writer = BufWriter::new(create_output_file(path)?);
+    write_difference_report_to_writer(comparison, labels, writer)
+}
+
+/// Write the differing variants as TSV to any writer
+pub fn write_difference_report_to_writer<W: Write>(
+    comparison: &Comparison,
+    (old_label, new_label): (&str, &str),
+    mut writer: W,
+) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "Chrom\tPos\tRef\tAlt\tDifference\tCause\t{old}_Condition\t{old}_Score\t{old}_Coverage\t\
+         {new}_Condition\t{new}_Score\t{new}_Coverage\tScore_Delta",
+        old = old_label,
+        new = new_label
+    )?;
+
+    // Condition, score and coverage columns of one side ("." where absent)
+    let columns = |result: &Option<ComparedResult>| match result {
+        Some(result) => format!(
+            "{}\t{}\t{}",
+            result.condition,
+            result.score,
+            result.coverage.map(|coverage| coverage.to_string()).unwrap_or_else(|| ".".to_string())
+        ),
+        None => ".\t.\t.".to_string(),
+    };
+
+    for difference in &comparison.differences {
+        let (chrom, pos, ref_allele, alt_allele) = &difference.key;
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            chrom,
+            pos,
+            ref_allele,
+            alt_allele,
+            difference.kind,
+            difference.cause.map(|cause| cause.to_string()).unwrap_or_else(|| ".".to_string()),
+            columns(&difference.old),
+            columns(&difference.new),
+            difference
+                .score_delta()
+                .map(|delta| delta.to_string())
+                .unwrap_or_else(|| ".".to_string()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read results to compare from a results TSV (lod_edit or the Python vLoD) or from
+/// a VCF annotated with DET/DETS, detected from the first line
+pub fn read_compared_results<P: AsRef<Path>>(path: P) -> VlodResult<ResultMap> {
+    let path = path.as_ref();
+    if is_annotated_vcf(path)? {
+        return read_annotated_vcf_results(path);
+    }
+
+    let coverage = read_result_coverage(path, DuplicatePolicy::First)?;
+    Ok(read_detectability_results(path)?
+        .into_iter()
+        .map(|(key, result)| {
+            let mut result = ComparedResult::from(result);
+            result.coverage = coverage.get(&key).copied();
+            (key, result)
+        })
+        .collect())
+}
+
+/// Read DET/DETS annotations back from a VCF written by vlod or merge_vcf_lod;
+/// records without a DET field are ignored
+fn read_annotated_vcf_results(path: &Path) -> VlodResult<ResultMap> {
+    let mut results = HashMap::new();
+    for result in read_annotated_vcf(path)? {
+        let variant = result.variant;
+        results
+            .entry((variant.chrom, variant.pos, variant.ref_allele, variant.alt_allele))
+            .or_insert(ComparedResult {
+                condition: result.detectability_condition,
+                score: result.detectability_score,
+                coverage: None,
+            });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn key(pos: u64) -> (String, u64, String, String) {
+        ("chr1".to_string(), pos, "A".to_string(), "T".to_string())
+    }
+
+    #[test]
+    fn test_score_tolerance() {
+        let tolerance = ScoreTolerance::default();
+        assert!(tolerance.equal(2.5, 2.5000000001));
+        assert!(tolerance.equal(f64::NAN, f64::NAN));
+        assert!(tolerance.equal(f64::NEG_INFINITY, f64::NEG_INFINITY));
+        assert!(!tolerance.equal(2.5, 2.51));
+        // Relative tolerance covers large scores printed with fewer digits
+        assert!(tolerance.equal(1234567.0, 1234567.5));
+    }
+
+    fn result(condition: DetectabilityCondition, score: f64, coverage: u32) -> ComparedResult {
+        ComparedResult {
+            condition,
+            score,
+            coverage: Some(coverage),
+        }
+    }
+
+    #[test]
+    fn test_compare_results() {
+        let old: ResultMap = [
+            (key(100), result(DetectabilityCondition::Detectable, 3.0, 50)),
+            (key(200), result(DetectabilityCondition::Detectable, 2.6, 50)),
+            (key(300), result(DetectabilityCondition::NonDetectable, 1.0, 50)),
+            (key(400), result(DetectabilityCondition::NonDetectable, 1.0, 50)),
+        ]
+        .into_iter()
+        .collect();
+        let new: ResultMap = [
+            (key(100), result(DetectabilityCondition::Detectable, 3.0, 50)),
+            (key(200), result(DetectabilityCondition::NonDetectable, 2.4, 40)),
+            (key(300), result(DetectabilityCondition::NonDetectable, 1.5, 50)),
+            (key(500), result(DetectabilityCondition::NoCoverage, 0.0, 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        let comparison = compare_results(&old, &new, &ScoreTolerance::default());
+        assert_eq!(comparison.compared, 3);
+        let kinds: Vec<DifferenceKind> = comparison.differences.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![DifferenceKind::Flip, DifferenceKind::Score, DifferenceKind::OnlyOld, DifferenceKind::OnlyNew]
+        );
+        assert_eq!(comparison.count_cause(ChangeCause::Coverage), 1);
+        assert_eq!(comparison.count_cause(ChangeCause::Score), 1);
+        assert!((comparison.max_score_delta() - 0.5).abs() < 1e-12);
+
+        let mut report = Vec::new();
+        write_difference_report_to_writer(&comparison, ("Python", "Rust"), &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.lines().nth(1).unwrap().starts_with("Chrom\tPos\tRef\tAlt\tDifference\tCause\tPython_Condition"));
+        assert!(report.contains(
+            "chr1\t200\tA\tT\tclassification_flip\tcoverage_change\tDetectable\t2.6\t50\tNon-detectable\t2.4\t40\t"
+        ));
+        assert!(report.contains("chr1\t500\tA\tT\tonly_in_new\t.\t.\t.\t.\tNo-coverage\t0\t0\t."));
+    }
+
+    #[test]
+    fn test_threshold_change() {
+        // Same score, new classification: the threshold moved
+        let old: ResultMap = [(key(100), result(DetectabilityCondition::Detectable, 2.6, 50))].into_iter().collect();
+        let new: ResultMap = [(key(100), result(DetectabilityCondition::NonDetectable, 2.6, 50))].into_iter().collect();
+        let comparison = compare_results(&old, &new, &ScoreTolerance::default());
+        assert_eq!(comparison.differences[0].cause, Some(ChangeCause::Threshold));
+    }
+
+    #[test]
+    fn test_read_compared_results() {
+        let mut tsv = NamedTempFile::new().unwrap();
+        writeln!(tsv, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\tVariant_Reads").unwrap();
+        writeln!(tsv, "chr1\t100\tA\tT\t3.5\tDetectable\t30\t15").unwrap();
+        let results = read_compared_results(tsv.path()).unwrap();
+        assert_eq!(results[&key(100)], result(DetectabilityCondition::Detectable, 3.5, 30));
+
+        let mut vcf = NamedTempFile::new().unwrap();
+        writeln!(vcf, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;DET=No;DETS=1.2").unwrap();
+        writeln!(vcf, "chr1\t200\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        let results = read_compared_results(vcf.path()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[&key(100)], ComparedResult::from((DetectabilityCondition::NonDetectable, 1.2)));
+    }
+}
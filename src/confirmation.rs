@@ -0,0 +1,89 @@
+//! Reference confirmation: whether the depth at a site rules out a variant at the assay LoD
+
+use crate::{DetectabilityCondition, VlodError, VlodResult};
+
+/// Settings for confirming the reference allele at monomorphic sites
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RefConfirmation {
+    /// Lowest VAF the assay claims to detect
+    pub assay_lod_vaf: f64,
+    /// Required probability of sampling at least one variant read at that VAF
+    pub confidence: f64,
+}
+
+impl Default for RefConfirmation {
+    fn default() -> Self {
+        Self {
+            assay_lod_vaf: 0.05,
+            confidence: 0.95,
+        }
+    }
+}
+
+impl RefConfirmation {
+    pub fn validate(&self) -> VlodResult<()> {
+        if !(self.assay_lod_vaf > 0.0 && self.assay_lod_vaf < 1.0) {
+            return Err(VlodError::InvalidConfig(
+                "reference confirmation VAF must be between 0 and 1".to_string(),
+            ));
+        }
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(VlodError::InvalidConfig(
+                "reference confirmation confidence must be between 0 and 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Smallest depth `n` for which a variant at the assay LoD would show no reads
+    /// with probability at most `1 - confidence`, i.e. `(1 - vaf)^n <= 1 - confidence`
+    pub fn required_depth(&self) -> u32 {
+        ((1.0 - self.confidence).ln() / (1.0 - self.assay_lod_vaf).ln()).ceil() as u32
+    }
+
+    /// Classify a site from its reference-supporting reads and total depth. The
+    /// reference is confirmed when enough reads support it and the reads that do
+    /// not stay below the assay LoD.
+    pub fn classify(&self, ref_reads: u32, site_depth: u32) -> DetectabilityCondition {
+        let non_ref_reads = site_depth.saturating_sub(ref_reads);
+        let non_ref_fraction = if site_depth == 0 {
+            0.0
+        } else {
+            non_ref_reads as f64 / site_depth as f64
+        };
+
+        if ref_reads >= self.required_depth() && non_ref_fraction < self.assay_lod_vaf {
+            DetectabilityCondition::RefConfirmed
+        } else {
+            DetectabilityCondition::RefUnconfirmed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_depth() {
+        let confirmation = RefConfirmation::default();
+        // ln(0.05) / ln(0.95) = 58.4
+        assert_eq!(confirmation.required_depth(), 59);
+
+        let confirmation = RefConfirmation {
+            assay_lod_vaf: 0.01,
+            confidence: 0.99,
+        };
+        assert_eq!(confirmation.required_depth(), 459);
+    }
+
+    #[test]
+    fn test_classify() {
+        let confirmation = RefConfirmation::default();
+        assert_eq!(confirmation.classify(60, 60), DetectabilityCondition::RefConfirmed);
+        assert_eq!(confirmation.classify(58, 58), DetectabilityCondition::RefUnconfirmed);
+        // Non-reference reads at or above the LoD leave the reference unconfirmed
+        assert_eq!(confirmation.classify(90, 100), DetectabilityCondition::RefUnconfirmed);
+        assert!(RefConfirmation { assay_lod_vaf: 0.0, ..confirmation }.validate().is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Sample contamination screen at common SNP sites: where the sample is
+//! homozygous, reads carrying the other SNP allele come from another sample
+//! (or from errors, measured by the bases that are neither allele). A sample
+//! whose excess of such reads passes a threshold is flagged before its
+//! detectability results are trusted.
+
+use crate::{
+    bam::BamAnalyzer,
+    noise::{base_index, BaseCounts},
+    LodConfig, Variant, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Excess unexpected allele fraction from which a sample is flagged unless
+/// another threshold is given
+pub const DEFAULT_CONTAMINATION_THRESHOLD: f64 = 0.02;
+
+/// Reads a site needs to be screened
+pub const CONTAMINATION_MIN_DEPTH: u32 = 20;
+
+/// Minor SNP allele fraction up to which a site counts as homozygous
+pub const HOMOZYGOUS_MAX_MINOR_FRACTION: f64 = 0.2;
+
+/// Homozygous sites needed before a sample is judged
+pub const CONTAMINATION_MIN_SITES: usize = 10;
+
+/// Outcome of the contamination screen, recorded in the run summary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContaminationScreen {
+    /// SNV sites screened
+    pub sites: usize,
+    /// Sites with at least `CONTAMINATION_MIN_DEPTH` reads
+    pub covered_sites: usize,
+    /// Covered sites where the sample is homozygous for either SNP allele
+    pub homozygous_sites: usize,
+    /// Mean fraction of REF and ALT reads carrying the allele the sample lacks, over
+    /// the homozygous sites
+    pub unexpected_allele_fraction: Option<f64>,
+    /// Mean fraction of reads carrying neither SNP allele (two bases of errors)
+    pub third_allele_fraction: Option<f64>,
+    /// Unexpected allele fraction above the error rate of a single base
+    pub excess_fraction: Option<f64>,
+    pub threshold: f64,
+    /// The excess reaches the threshold over at least `CONTAMINATION_MIN_SITES`
+    /// homozygous sites
+    pub contaminated: bool,
+}
+
+impl ContaminationScreen {
+    pub fn log(&self) {
+        log::info!(
+            "Contamination screen: {} of {} SNP sites covered, {} homozygous",
+            self.covered_sites,
+            self.sites,
+            self.homozygous_sites
+        );
+        if let (Some(unexpected), Some(third), Some(excess)) =
+            (self.unexpected_allele_fraction, self.third_allele_fraction, self.excess_fraction)
+        {
+            log::info!(
+                "  Unexpected allele fraction: {:.4} (third allele {:.4}, excess {:.4})",
+                unexpected,
+                third,
+                excess
+            );
+        }
+        if self.contaminated {
+            log::warn!(
+                "The sample looks contaminated: {:.1}% of reads at its homozygous SNPs carry the other allele \
+                 (threshold {:.1}%); check its detectability results against another sample",
+                100.0 * self.excess_fraction.unwrap_or(0.0),
+                100.0 * self.threshold
+            );
+        } else if self.homozygous_sites < CONTAMINATION_MIN_SITES {
+            log::warn!(
+                "Only {} homozygous SNP sites are covered; at least {} are needed to judge contamination",
+                self.homozygous_sites,
+                CONTAMINATION_MIN_SITES
+            );
+        }
+    }
+}
+
+/// Allele fractions of the screened sites, summarized by `finish`
+#[derive(Debug, Clone, Default)]
+pub struct ContaminationAccumulator {
+    sites: usize,
+    covered_sites: usize,
+    unexpected_fractions: Vec<f64>,
+    third_fractions: Vec<f64>,
+}
+
+impl ContaminationAccumulator {
+    /// Add a site from its REF, ALT and other-base read counts
+    pub fn add_site(&mut self, ref_reads: u32, alt_reads: u32, other_reads: u32) {
+        self.sites += 1;
+        let depth = ref_reads + alt_reads + other_reads;
+        if depth < CONTAMINATION_MIN_DEPTH {
+            return;
+        }
+        self.covered_sites += 1;
+        let allele_reads = ref_reads + alt_reads;
+        if allele_reads == 0 {
+            return;
+        }
+        let minor = ref_reads.min(alt_reads) as f64 / allele_reads as f64;
+        if minor <= HOMOZYGOUS_MAX_MINOR_FRACTION {
+            self.unexpected_fractions.push(minor);
+            self.third_fractions.push(other_reads as f64 / depth as f64);
+        }
+    }
+
+    pub fn finish(&self, threshold: f64) -> ContaminationScreen {
+        let mean = |values: &[f64]| (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64);
+        let unexpected_allele_fraction = mean(&self.unexpected_fractions);
+        let third_allele_fraction = mean(&self.third_fractions);
+        let excess_fraction = unexpected_allele_fraction
+            .zip(third_allele_fraction)
+            .map(|(unexpected, third)| (unexpected - third / 2.0).max(0.0));
+        let homozygous_sites = self.unexpected_fractions.len();
+        ContaminationScreen {
+            sites: self.sites,
+            covered_sites: self.covered_sites,
+            homozygous_sites,
+            unexpected_allele_fraction,
+            third_allele_fraction,
+            excess_fraction,
+            threshold,
+            contaminated: homozygous_sites >= CONTAMINATION_MIN_SITES
+                && excess_fraction.is_some_and(|excess| excess >= threshold),
+        }
+    }
+}
+
+/// Screen a BAM for contamination at SNP sites (e.g. common gnomAD SNPs within
+/// the panel), counting the reads and bases `config` counts at variants; sites
+/// that are not SNVs are left out
+pub fn screen_contamination(
+    bam_path: &Path,
+    sites: &[Variant],
+    config: &LodConfig,
+    threshold: f64,
+) -> VlodResult<ContaminationScreen> {
+    if !(threshold > 0.0 && threshold < 1.0) {
+        return Err(VlodError::InvalidConfig(format!(
+            "contamination threshold must be between 0 and 1, got {}",
+            threshold
+        )));
+    }
+    let mut analyzer = BamAnalyzer::new(bam_path)?.with_config(config);
+    let mut accumulator = ContaminationAccumulator::default();
+    for site in sites {
+        let (Some(ref_index), Some(alt_index)) = (snv_base_index(&site.ref_allele), snv_base_index(&site.alt_allele))
+        else {
+            continue;
+        };
+        if ref_index == alt_index {
+            continue;
+        }
+        let counts: BaseCounts = analyzer.base_counts(&site.chrom, site.pos.saturating_sub(1))?;
+        let (ref_reads, alt_reads) = (counts[ref_index], counts[alt_index]);
+        let other_reads = counts.iter().sum::<u32>() - ref_reads - alt_reads;
+        accumulator.add_site(ref_reads, alt_reads, other_reads);
+    }
+    Ok(accumulator.finish(threshold))
+}
+
+/// Base index of a single-base allele
+fn snv_base_index(allele: &str) -> Option<usize> {
+    match allele.as_bytes() {
+        [base] => base_index(*base),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contamination_accumulator() {
+        let mut clean = ContaminationAccumulator::default();
+        let mut mixed = ContaminationAccumulator::default();
+        for _ in 0..CONTAMINATION_MIN_SITES {
+            // Homozygous REF and ALT sites, with one read of errors
+            clean.add_site(99, 0, 1);
+            clean.add_site(0, 99, 1);
+            mixed.add_site(94, 5, 1);
+            mixed.add_site(5, 94, 1);
+        }
+        // Heterozygous and shallow sites are not judged
+        clean.add_site(50, 50, 0);
+        clean.add_site(5, 5, 0);
+
+        let screen = clean.finish(DEFAULT_CONTAMINATION_THRESHOLD);
+        assert_eq!((screen.sites, screen.covered_sites, screen.homozygous_sites), (22, 21, 20));
+        assert_eq!(screen.excess_fraction, Some(0.0));
+        assert!(!screen.contaminated);
+
+        let screen = mixed.finish(DEFAULT_CONTAMINATION_THRESHOLD);
+        assert!((screen.unexpected_allele_fraction.unwrap() - 5.0 / 99.0).abs() < 1e-9);
+        assert!((screen.excess_fraction.unwrap() - (5.0 / 99.0 - 0.005)).abs() < 1e-9);
+        assert!(screen.contaminated);
+
+        // Too few homozygous sites to judge
+        let mut sparse = ContaminationAccumulator::default();
+        sparse.add_site(90, 10, 0);
+        assert!(!sparse.finish(DEFAULT_CONTAMINATION_THRESHOLD).contaminated);
+    }
+
+    #[test]
+    fn test_screen_contamination() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let sites: Vec<Variant> = data.expected.iter().map(|(variant, _)| variant.clone()).collect();
+        let screen = screen_contamination(&data.bam, &sites, &LodConfig::default(), 0.05).unwrap();
+        assert!(screen.sites <= sites.len());
+        assert!(screen.covered_sites <= screen.sites);
+
+        let result = screen_contamination(&data.bam, &sites, &LodConfig::default(), 1.5);
+        assert!(matches!(result, Err(VlodError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_screen_contamination_filters_reads() {
+        use rust_htslib::bam::{
+            self,
+            header::{Header, HeaderRecord},
+            record::{Cigar, CigarString, Record},
+        };
+
+        // Homozygous REF sites where only poorly mapped reads carry the ALT base
+        let dir = tempfile::tempdir().unwrap();
+        let bam_path = dir.path().join("screen.bam");
+        let mut header = Header::new();
+        let mut sq = HeaderRecord::new(b"SQ");
+        sq.push_tag(b"SN", "chr1");
+        sq.push_tag(b"LN", 10_000);
+        header.push_record(&sq);
+        let mut writer = bam::Writer::from_path(&bam_path, &header, bam::Format::Bam).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        let mut sites = Vec::new();
+        for site in 0..CONTAMINATION_MIN_SITES as u64 {
+            let start = 100 + 100 * site;
+            for read in 0..100 {
+                let (bases, mapq) = if read < 10 { (b"AAAAAGAAAA", 5) } else { (b"AAAAAAAAAA", 60) };
+                let mut record = Record::new();
+                let qname = format!("site{}_read{}", site, read);
+                record.set(qname.as_bytes(), Some(&cigar), bases, &[30; 10]);
+                record.set_tid(0);
+                record.set_pos(start as i64);
+                record.set_mtid(-1);
+                record.set_mpos(-1);
+                record.set_mapq(mapq);
+                writer.write(&record).unwrap();
+            }
+            sites.push(Variant::new("chr1".to_string(), start + 6, "A".to_string(), "G".to_string()));
+        }
+        drop(writer);
+        bam::index::build(&bam_path, None, bam::index::Type::Bai, 1).unwrap();
+
+        let screen = screen_contamination(&bam_path, &sites, &LodConfig::default(), 0.05).unwrap();
+        assert!(screen.contaminated);
+        let config = LodConfig { min_mapping_quality: 20, ..LodConfig::default() };
+        let screen = screen_contamination(&bam_path, &sites, &config, 0.05).unwrap();
+        assert_eq!(screen.homozygous_sites, CONTAMINATION_MIN_SITES);
+        assert_eq!(screen.unexpected_allele_fraction, Some(0.0));
+        assert!(!screen.contaminated);
+    }
+}
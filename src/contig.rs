@@ -0,0 +1,329 @@
+//! Contig name aliasing between naming conventions (e.g. `chr1` vs `1`, `chrM` vs `MT`),
+//! and the handling of variants on unplaced, random and alt contigs
+
+use crate::{
+    utils::{open_text_input, DEFAULT_MAX_LINE_LENGTH},
+    Variant, VlodError, VlodResult,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Canonical form of a contig name: without a `chr` prefix, with the mitochondrial
+/// contig spelled `MT`
+pub fn canonical_contig_name(name: &str) -> String {
+    let stripped = if name.len() > 3 && name.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("chr")) {
+        &name[3..]
+    } else {
+        name
+    };
+
+    if stripped.eq_ignore_ascii_case("M") || stripped.eq_ignore_ascii_case("MT") {
+        "MT".to_string()
+    } else {
+        stripped.to_string()
+    }
+}
+
+/// Whether two contig names refer to the same sequence under aliasing
+pub fn contigs_match(a: &str, b: &str) -> bool {
+    a == b || canonical_contig_name(a) == canonical_contig_name(b)
+}
+
+/// Lookup of keyed entries by canonical contig name, for keys whose exact contig
+/// name does not match
+#[derive(Debug, Clone, Default)]
+pub struct ContigAliasIndex {
+    canonical: HashMap<(String, u64, String, String), (String, u64, String, String)>,
+}
+
+impl ContigAliasIndex {
+    pub fn new<'a, I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = &'a (String, u64, String, String)>,
+    {
+        let canonical = keys
+            .into_iter()
+            .map(|key| {
+                let (chrom, pos, ref_allele, alt_allele) = key;
+                (
+                    (canonical_contig_name(chrom), *pos, ref_allele.clone(), alt_allele.clone()),
+                    key.clone(),
+                )
+            })
+            .collect();
+        ContigAliasIndex { canonical }
+    }
+
+    /// Original key matching `key` once contig names are canonicalized
+    pub fn resolve(&self, key: &(String, u64, String, String)) -> Option<&(String, u64, String, String)> {
+        let (chrom, pos, ref_allele, alt_allele) = key;
+        self.canonical
+            .get(&(canonical_contig_name(chrom), *pos, ref_allele.clone(), alt_allele.clone()))
+    }
+}
+
+/// Whether a contig is outside the primary assembly: unlocalized (`_random`),
+/// unplaced (`chrUn_*`, `GL000192.1`, `KI270302.1`), alt haplotypes and fix patches
+/// (`_alt`, `_fix`), decoys and HLA sequences
+pub fn is_non_primary_contig(name: &str) -> bool {
+    const SUFFIXES: [&str; 5] = ["_random", "_alt", "_fix", "_decoy", "_hap"];
+    const PREFIXES: [&str; 6] = ["Un", "GL", "KI", "JH", "NT_", "HLA-"];
+    let canonical = canonical_contig_name(name);
+    SUFFIXES.iter().any(|suffix| canonical.ends_with(suffix))
+        || PREFIXES.iter().any(|prefix| canonical.starts_with(prefix))
+        || canonical.eq_ignore_ascii_case("hs37d5")
+}
+
+/// How variants on contigs outside the primary assembly, or missing from the BAM,
+/// are handled. Variants on contigs missing from the BAM are reported
+/// `Not-assessable` under every policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContigPolicy {
+    /// Score variants on every contig present in the BAM
+    #[default]
+    Analyze,
+    /// Report variants on unplaced, random and alt contigs (and zero-length BAM
+    /// contigs) `Not-assessable` without reading them
+    Skip,
+    /// Score variants on alt contigs at their primary locus from an alt-to-primary
+    /// alignment; other contigs are analysed
+    Map,
+}
+
+impl FromStr for ContigPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "analyze" => Ok(ContigPolicy::Analyze),
+            "skip" => Ok(ContigPolicy::Skip),
+            "map" => Ok(ContigPolicy::Map),
+            _ => Err(format!("unknown contig policy '{}' (expected analyze, skip or map)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ContigPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ContigPolicy::Analyze => "analyze",
+            ContigPolicy::Skip => "skip",
+            ContigPolicy::Map => "map",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Alignment of one alt contig to the primary assembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AltAlignment {
+    primary: String,
+    /// 1-based primary position of the first aligned contig base
+    pos: u64,
+    reverse: bool,
+    cigar: Vec<(u32, u8)>,
+}
+
+/// Alt-to-primary contig alignments, read from SAM records of the alt contigs
+/// aligned to the primary assembly (e.g. a bwa `.alt` file)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AltContigMap {
+    alignments: HashMap<String, AltAlignment>,
+}
+
+impl AltContigMap {
+    /// Read alignments from a SAM file (`@` header lines optional, gzip accepted).
+    /// Records without an alignment are skipped; the first alignment of a contig wins.
+    pub fn from_sam<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        let path = path.as_ref();
+        let reader = open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?;
+        let source = path.display().to_string();
+        let mut alignments = HashMap::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.starts_with('@') || line.trim().is_empty() {
+                continue;
+            }
+            let alignment = parse_alt_alignment(&line).map_err(|e| e.at_line(&source, index as u64 + 1))?;
+            if let Some((contig, alignment)) = alignment {
+                alignments.entry(contig).or_insert(alignment);
+            }
+        }
+        Ok(AltContigMap { alignments })
+    }
+
+    pub fn len(&self) -> usize {
+        self.alignments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alignments.is_empty()
+    }
+
+    /// Whether an alignment is known for a contig
+    pub fn contains(&self, contig: &str) -> bool {
+        self.alignments.contains_key(contig)
+    }
+
+    /// The variant at its primary locus, with the same alleles. Fails for contigs
+    /// without an alignment, reverse-strand alignments (the alleles would need
+    /// complementing) and positions in insertions or clipped sequence.
+    pub fn map_variant(&self, variant: &Variant) -> Result<Variant, String> {
+        let alignment = self
+            .alignments
+            .get(&variant.chrom)
+            .ok_or_else(|| "no primary alignment".to_string())?;
+        if alignment.reverse {
+            return Err("reverse-strand primary alignment".to_string());
+        }
+
+        // Walk the CIGAR from the first contig base; hard clips count as contig bases
+        let offset = variant.pos.saturating_sub(1);
+        let (mut contig_pos, mut primary_pos) = (0u64, alignment.pos - 1);
+        for &(length, op) in &alignment.cigar {
+            let length = u64::from(length);
+            let within = (contig_pos..contig_pos.saturating_add(length)).contains(&offset);
+            match op {
+                b'M' | b'=' | b'X' => {
+                    if within {
+                        let mut mapped = variant.clone();
+                        mapped.chrom = alignment.primary.clone();
+                        mapped.pos = primary_pos + (offset - contig_pos) + 1;
+                        return Ok(mapped);
+                    }
+                    contig_pos += length;
+                    primary_pos += length;
+                }
+                b'I' | b'S' | b'H' => {
+                    if within {
+                        return Err("not aligned to the primary assembly".to_string());
+                    }
+                    contig_pos += length;
+                }
+                b'D' | b'N' => primary_pos += length,
+                _ => {}
+            }
+        }
+        Err("beyond the primary alignment".to_string())
+    }
+}
+
+/// Contig and alignment of one SAM record (None when unmapped)
+fn parse_alt_alignment(line: &str) -> VlodResult<Option<(String, AltAlignment)>> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 6 {
+        return Err(VlodError::InvalidVariant(format!(
+            "expected at least 6 SAM columns, found {}",
+            fields.len()
+        )));
+    }
+    let flag: u16 = fields[1]
+        .parse()
+        .map_err(|_| VlodError::InvalidVariant(format!("invalid SAM flag '{}'", fields[1])))?;
+    let pos: u64 = fields[3]
+        .parse()
+        .map_err(|_| VlodError::InvalidVariant(format!("invalid SAM position '{}'", fields[3])))?;
+    if flag & 0x4 != 0 || fields[2] == "*" || pos == 0 || fields[5] == "*" {
+        return Ok(None);
+    }
+
+    let mut cigar = Vec::new();
+    let mut length = String::new();
+    for c in fields[5].chars() {
+        if c.is_ascii_digit() {
+            length.push(c);
+        } else {
+            let op_length = length
+                .parse()
+                .map_err(|_| VlodError::InvalidVariant(format!("invalid CIGAR '{}'", fields[5])))?;
+            cigar.push((op_length, c as u8));
+            length.clear();
+        }
+    }
+
+    Ok(Some((
+        fields[0].to_string(),
+        AltAlignment {
+            primary: fields[2].to_string(),
+            pos,
+            reverse: flag & 0x10 != 0,
+            cigar,
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_contig_name() {
+        assert_eq!(canonical_contig_name("chr1"), "1");
+        assert_eq!(canonical_contig_name("Chr1"), "1");
+        assert_eq!(canonical_contig_name("1"), "1");
+        assert_eq!(canonical_contig_name("chrX"), "X");
+        assert_eq!(canonical_contig_name("chrM"), "MT");
+        assert_eq!(canonical_contig_name("MT"), "MT");
+        assert_eq!(canonical_contig_name("chr"), "chr");
+        assert_eq!(canonical_contig_name("ab€1"), "ab€1");
+        assert!(contigs_match("chr7", "7"));
+        assert!(!contigs_match("chr7", "17"));
+    }
+
+    #[test]
+    fn test_contig_alias_index() {
+        let keys = vec![("1".to_string(), 100, "A".to_string(), "T".to_string())];
+        let index = ContigAliasIndex::new(&keys);
+
+        let vcf_key = ("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        assert_eq!(index.resolve(&vcf_key), Some(&keys[0]));
+
+        let other = ("chr2".to_string(), 100, "A".to_string(), "T".to_string());
+        assert_eq!(index.resolve(&other), None);
+    }
+
+    #[test]
+    fn test_is_non_primary_contig() {
+        assert!(is_non_primary_contig("chr1_KI270706v1_random"));
+        assert!(is_non_primary_contig("chrUn_GL000220v1"));
+        assert!(is_non_primary_contig("GL000192.1"));
+        assert!(is_non_primary_contig("chr6_GL000250v2_alt"));
+        assert!(is_non_primary_contig("HLA-A*01:01:01:01"));
+        assert!(!is_non_primary_contig("chr1"));
+        assert!(!is_non_primary_contig("MT"));
+        assert_eq!("SKIP".parse::<ContigPolicy>(), Ok(ContigPolicy::Skip));
+        assert!("drop".parse::<ContigPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_alt_contig_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let sam = dir.path().join("hs38.alt");
+        std::fs::write(
+            &sam,
+            "@SQ\tSN:chr6\tLN:170805979\n\
+             chr6_GL000250v2_alt\t0\tchr6\t1000\t60\t5H10M2I10M5D10M\t*\t0\t0\t*\t*\n\
+             chr6_GL000251v2_alt\t16\tchr6\t5000\t60\t40M\t*\t0\t0\t*\t*\n",
+        )
+        .unwrap();
+        let map = AltContigMap::from_sam(&sam).unwrap();
+        assert_eq!(map.len(), 2);
+
+        let variant = |chrom: &str, pos| Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        // Contig base 6 is the first aligned base
+        assert_eq!(map.map_variant(&variant("chr6_GL000250v2_alt", 6)).unwrap(), variant("chr6", 1000));
+        // After the 2-base insertion
+        assert_eq!(map.map_variant(&variant("chr6_GL000250v2_alt", 18)).unwrap(), variant("chr6", 1010));
+        // After the 5-base deletion
+        assert_eq!(map.map_variant(&variant("chr6_GL000250v2_alt", 28)).unwrap(), variant("chr6", 1025));
+        assert!(map.map_variant(&variant("chr6_GL000250v2_alt", 2)).is_err());
+        assert!(map.map_variant(&variant("chr6_GL000250v2_alt", 16)).is_err());
+        assert!(map.map_variant(&variant("chr6_GL000250v2_alt", 100)).is_err());
+        assert!(map.map_variant(&variant("chr6_GL000251v2_alt", 10)).is_err());
+        assert!(map.map_variant(&variant("chr7_alt", 10)).is_err());
+    }
+}
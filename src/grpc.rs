@@ -0,0 +1,409 @@
+//! gRPC service for enterprise pipeline platforms, defined in `proto/vlod.proto`:
+//! Analyze scores a VCF against a BAM and streams the result of every variant,
+//! Merge annotates a VCF with a results TSV, and Status reports the build and the
+//! analyses served. Paths in requests are paths on the server. Each Analyze and
+//! Merge is answered under a request id (the client's `x-request-id` metadata, or a
+//! new one), returned in the response metadata and used in the audit log.
+
+pub mod proto {
+    tonic::include_proto!("vlod.v1");
+}
+
+use crate::{
+    about::about,
+    audit::{new_request_id, AuditEntry, AuditEvent, AuditLog},
+    bam::AlleleCounts,
+    incremental::config_hash,
+    integrity::Sha256,
+    lod::{validate_lod_config, write_detectability_results_to_writer},
+    merge::{merge_detectability_into_vcf, MergeOptions},
+    observer::Observer,
+    pipeline::{run_pipeline, PipelineConfig},
+    sample::resolve_sample_name,
+    utils::{create_output_file, get_num_cpus, open_text_input, validate_file_readable},
+    DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+};
+use proto::{
+    analyze_event::Event,
+    vlod_server::{Vlod, VlodServer},
+    AnalyzeEvent, AnalyzeRequest, AnalyzeSummary, MergeReply, MergeRequest, ModelSettings, StatusReply,
+    StatusRequest, VariantProgress, VariantResult,
+};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// Events buffered per Analyze stream
+const ANALYZE_STREAM_BUFFER: usize = 1024;
+
+type EventSender = mpsc::Sender<Result<AnalyzeEvent, Status>>;
+
+/// gRPC status of an error, by the kind of its root cause
+fn error_status(error: VlodError) -> Status {
+    match error.root() {
+        VlodError::InvalidConfig(_) | VlodError::InvalidVariant(_) | VlodError::Parse { .. } => {
+            Status::invalid_argument(error.to_string())
+        }
+        VlodError::FileNotFound(_) => Status::not_found(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+/// Request id and client of a request, from its `x-request-id` and `x-vlod-user`
+/// metadata, falling back to a new id and the client's address
+fn request_identity<T>(request: &Request<T>) -> (String, String) {
+    let metadata = |key: &str| {
+        request.metadata().get(key).and_then(|value| value.to_str().ok()).filter(|value| !value.is_empty())
+    };
+    let request_id = metadata("x-request-id").map_or_else(new_request_id, str::to_string);
+    let actor = metadata("x-vlod-user")
+        .map(str::to_string)
+        .or_else(|| request.remote_addr().map(|address| address.to_string()))
+        .unwrap_or_else(|| "unknown".to_string());
+    (request_id, actor)
+}
+
+/// A response carrying its request id in the metadata
+fn with_request_id<T>(message: T, request_id: &str) -> Response<T> {
+    let mut response = Response::new(message);
+    if let Ok(value) = request_id.parse() {
+        response.metadata_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// Model configuration of a request; unset parameters keep their defaults
+fn lod_config(settings: Option<&ModelSettings>) -> LodConfig {
+    let default = LodConfig::default();
+    let Some(settings) = settings else {
+        return default;
+    };
+    LodConfig {
+        p_tp: settings.p_tp.unwrap_or(default.p_tp),
+        p_fp: settings.p_fp.unwrap_or(default.p_fp),
+        p_se: settings.p_se.unwrap_or(default.p_se),
+        score_threshold: settings.score_threshold.unwrap_or(default.score_threshold),
+        ..default
+    }
+}
+
+impl From<&Variant> for proto::Variant {
+    fn from(variant: &Variant) -> Self {
+        proto::Variant {
+            chrom: variant.chrom.clone(),
+            pos: variant.pos,
+            ref_allele: variant.ref_allele.clone(),
+            alt_allele: variant.alt_allele.clone(),
+        }
+    }
+}
+
+impl From<&DetectabilityResult> for VariantResult {
+    fn from(result: &DetectabilityResult) -> Self {
+        VariantResult {
+            variant: Some((&result.variant).into()),
+            score: result.detectability_score,
+            condition: result.detectability_condition.to_string(),
+            det: result.detectability_condition.vcf_status().to_string(),
+            coverage: result.coverage,
+            variant_reads: result.variant_reads,
+        }
+    }
+}
+
+fn event(event: Event) -> Result<AnalyzeEvent, Status> {
+    Ok(AnalyzeEvent { event: Some(event) })
+}
+
+/// Streams a progress event for every variant read and the result of every
+/// variant as it is scored, and cancels scoring once the client has closed the
+/// stream
+struct StreamObserver {
+    events: EventSender,
+    done: AtomicU64,
+}
+
+impl Observer for StreamObserver {
+    fn on_variant_done(&self, variant: &Variant, counts: &AlleleCounts) {
+        let variants_done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let progress = VariantProgress { variant: Some(variant.into()), depth: counts.total_count, variants_done };
+        // Progress is dropped while the buffer is full rather than stall the workers
+        let _ = self.events.try_send(event(Event::Progress(progress)));
+    }
+
+    fn on_result(&self, result: &DetectabilityResult) {
+        // Results wait for room in the buffer; a send fails only once the client
+        // has gone, which cancels the run
+        let _ = self.events.blocking_send(event(Event::Result(result.into())));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.events.is_closed()
+    }
+}
+
+/// Run an analysis, its results streamed as they are scored, then stream its
+/// summary; returns the SHA-256 of the results as a detectability TSV, or None
+/// once the client has gone
+fn run_analysis(request: &AnalyzeRequest, config: &PipelineConfig, events: &EventSender) -> VlodResult<Option<String>> {
+    let vcf = open_text_input(&request.vcf_path, config.merge.max_line_length)?;
+    let output = run_pipeline(vcf, Path::new(&request.bam_path), config)?;
+    if events.is_closed() {
+        return Ok(None);
+    }
+    if !request.annotated_vcf_path.is_empty() {
+        let mut file = create_output_file(&request.annotated_vcf_path)?;
+        file.write_all(&output.annotated_vcf)?;
+    }
+    let mut tsv = Vec::new();
+    write_detectability_results_to_writer(&output.results, &mut tsv, &config.lod)?;
+    let mut results_sha256 = Sha256::new();
+    results_sha256.update(&tsv);
+
+    let mut conditions = HashMap::new();
+    for result in &output.results {
+        *conditions.entry(result.detectability_condition.vcf_status().to_string()).or_insert(0) += 1;
+    }
+    let summary = AnalyzeSummary {
+        results: output.results.len() as u64,
+        detectable: output.results.iter().filter(|result| result.detectability_condition.is_detectable()).count()
+            as u64,
+        conditions,
+        warnings_json: serde_json::to_string(&output.warnings)
+            .map_err(|e| VlodError::InvalidConfig(format!("cannot write warnings: {}", e)))?,
+    };
+    let _ = events.blocking_send(event(Event::Summary(summary)));
+    Ok(Some(results_sha256.hex_digest()))
+}
+
+/// The vlod gRPC service; analyses run on blocking threads of the runtime
+#[derive(Debug, Default)]
+pub struct VlodService {
+    active: Arc<AtomicU64>,
+    completed: Arc<AtomicU64>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl VlodService {
+    /// Record every Analyze and Merge request in an audit log
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+}
+
+/// Append an entry to the audit log, if there is one
+fn audit(log: Option<&AuditLog>, entry: &AuditEntry, event: AuditEvent) -> Result<(), Status> {
+    match log {
+        Some(log) => log.record(entry, event).map_err(error_status),
+        None => Ok(()),
+    }
+}
+
+#[tonic::async_trait]
+impl Vlod for VlodService {
+    type AnalyzeStream = ReceiverStream<Result<AnalyzeEvent, Status>>;
+
+    async fn analyze(&self, request: Request<AnalyzeRequest>) -> Result<Response<Self::AnalyzeStream>, Status> {
+        let (request_id, actor) = request_identity(&request);
+        let request = request.into_inner();
+        let lod = lod_config(request.settings.as_ref());
+        validate_lod_config(&lod).map_err(error_status)?;
+        validate_file_readable(&request.vcf_path).map_err(error_status)?;
+        validate_file_readable(&request.bam_path).map_err(error_status)?;
+
+        let mut entry = AuditEntry::new("grpc", "analyze", &request_id);
+        entry.actor = actor;
+        entry.config_hash = Some(config_hash(&lod, &request.bam_path).map_err(error_status)?);
+        entry.inputs = vec![request.vcf_path.clone(), request.bam_path.clone()];
+        audit(self.audit.as_deref(), &entry, AuditEvent::Started)?;
+
+        let (events, receiver) = mpsc::channel(ANALYZE_STREAM_BUFFER);
+        let config = PipelineConfig {
+            lod,
+            pass_only: request.pass_only,
+            num_processes: if request.processes == 0 { get_num_cpus() } else { request.processes as usize },
+            observer: Some(Arc::new(StreamObserver { events: events.clone(), done: AtomicU64::new(0) })),
+            ..PipelineConfig::default()
+        };
+        let (active, completed) = (Arc::clone(&self.active), Arc::clone(&self.completed));
+        let audit_log = self.audit.clone();
+        active.fetch_add(1, Ordering::SeqCst);
+        tokio::task::spawn_blocking(move || {
+            let max_line_length = config.merge.max_line_length;
+            if let Ok(sample) = resolve_sample_name(None, &request.bam_path, &request.vcf_path, max_line_length) {
+                entry.samples = vec![sample.name];
+            }
+            let event = match run_analysis(&request, &config, &events) {
+                Ok(Some(results_sha256)) => {
+                    entry.outputs.insert("results".to_string(), results_sha256);
+                    let annotated = Some(&request.annotated_vcf_path).filter(|path| !path.is_empty());
+                    match entry.add_outputs(annotated) {
+                        Ok(()) => AuditEvent::Finished,
+                        Err(e) => {
+                            entry.detail = Some(e.to_string());
+                            AuditEvent::Failed
+                        }
+                    }
+                }
+                Ok(None) => AuditEvent::Interrupted,
+                Err(e) => {
+                    log::warn!("Analysis {} of {} failed: {}", entry.request_id, request.vcf_path, e);
+                    entry.detail = Some(e.to_string());
+                    let _ = events.blocking_send(Err(error_status(e)));
+                    AuditEvent::Failed
+                }
+            };
+            if let Err(e) = audit(audit_log.as_deref(), &entry, event) {
+                log::error!("Analysis {} was not recorded in the audit log: {}", entry.request_id, e.message());
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+            completed.fetch_add(1, Ordering::SeqCst);
+        });
+        Ok(with_request_id(ReceiverStream::new(receiver), &request_id))
+    }
+
+    async fn merge(&self, request: Request<MergeRequest>) -> Result<Response<MergeReply>, Status> {
+        let (request_id, actor) = request_identity(&request);
+        let request = request.into_inner();
+        let mut entry = AuditEntry::new("grpc", "merge", &request_id);
+        entry.actor = actor;
+        entry.inputs = vec![request.vcf_path.clone(), request.results_path.clone()];
+        audit(self.audit.as_deref(), &entry, AuditEvent::Started)?;
+
+        let options = MergeOptions { strict_contig_names: request.strict_contig_names, ..MergeOptions::default() };
+        let output_path = request.output_path.clone();
+        let merged = tokio::task::spawn_blocking(move || {
+            merge_detectability_into_vcf(
+                Path::new(&request.vcf_path),
+                Path::new(&request.results_path),
+                Path::new(&request.output_path),
+                &options,
+            )
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .and_then(|stats| entry.add_outputs([&output_path]).map(|()| stats));
+        let stats = match merged {
+            Ok(stats) => {
+                audit(self.audit.as_deref(), &entry, AuditEvent::Finished)?;
+                stats
+            }
+            Err(e) => {
+                entry.detail = Some(e.to_string());
+                audit(self.audit.as_deref(), &entry, AuditEvent::Failed)?;
+                return Err(error_status(e));
+            }
+        };
+        Ok(with_request_id(MergeReply {
+            annotated: stats.annotated as u64,
+            unmatched: stats.unmatched as u64,
+            malformed: stats.malformed as u64,
+            passed_through: stats.passed_through as u64,
+        }, &request_id))
+    }
+
+    async fn status(&self, _request: Request<StatusRequest>) -> Result<Response<StatusReply>, Status> {
+        let about = about();
+        Ok(Response::new(StatusReply {
+            vlod_version: about.vlod_version.clone(),
+            htslib_version: about.htslib_version.clone(),
+            tsv_schema: about.tsv_schema,
+            detection_threshold: about.detection_threshold,
+            active_analyses: self.active.load(Ordering::SeqCst),
+            completed_analyses: self.completed.load(Ordering::SeqCst),
+            about_json: about.to_json(),
+        }))
+    }
+}
+
+/// Serve the gRPC service at `address` until the process is stopped, recording
+/// requests in `audit` if given
+pub fn serve_grpc(address: &str, audit: Option<AuditLog>) -> VlodResult<()> {
+    let socket: SocketAddr = address
+        .parse()
+        .map_err(|e| VlodError::InvalidConfig(format!("invalid gRPC address '{}': {}", address, e)))?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let service = match audit {
+        Some(audit) => VlodService::default().with_audit_log(audit),
+        None => VlodService::default(),
+    };
+    log::info!("Serving the vlod gRPC service at {}", socket);
+    runtime
+        .block_on(tonic::transport::Server::builder().add_service(VlodServer::new(service)).serve(socket))
+        .map_err(|e| VlodError::InvalidConfig(format!("cannot serve gRPC at {}: {}", socket, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn test_analyze_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let annotated = dir.path().join("annotated.vcf");
+        let audit_path = dir.path().join("audit.jsonl");
+        let service = VlodService::default().with_audit_log(AuditLog::open(&audit_path).unwrap());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let request = AnalyzeRequest {
+            vcf_path: data.vcf.to_string_lossy().to_string(),
+            bam_path: data.bam.to_string_lossy().to_string(),
+            annotated_vcf_path: annotated.to_string_lossy().to_string(),
+            processes: 1,
+            ..AnalyzeRequest::default()
+        };
+        let mut analyze = Request::new(request.clone());
+        analyze.metadata_mut().insert("x-request-id", "req-1".parse().unwrap());
+        let events: Vec<Event> = runtime.block_on(async {
+            let response = service.analyze(analyze).await.unwrap();
+            assert_eq!(response.metadata().get("x-request-id").unwrap(), "req-1");
+            response.into_inner().map(|event| event.unwrap().event.unwrap()).collect().await
+        });
+        let results: Vec<&VariantResult> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Result(result) => Some(result),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(results.len(), data.expected.len());
+        for (result, (variant, condition)) in results.iter().zip(&data.expected) {
+            assert_eq!(result.variant, Some(variant.into()));
+            assert_eq!(result.condition, condition.to_string());
+        }
+        let Some(Event::Summary(summary)) = events.last() else {
+            panic!("the stream ends with a summary");
+        };
+        assert_eq!(summary.results, data.expected.len() as u64);
+        assert!(annotated.exists());
+
+        let status = runtime.block_on(service.status(Request::new(StatusRequest {}))).unwrap().into_inner();
+        assert_eq!((status.active_analyses, status.completed_analyses), (0, 1));
+
+        let entries = crate::audit::read_audit_log(&audit_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.request_id == "req-1"));
+        assert_eq!(entries[1].event, AuditEvent::Finished);
+        assert_eq!(entries[1].outputs.len(), 2);
+
+        // Invalid settings and missing files are refused before the stream starts
+        let invalid = AnalyzeRequest {
+            settings: Some(ModelSettings { p_tp: Some(0.0), ..ModelSettings::default() }),
+            ..request.clone()
+        };
+        let status = runtime.block_on(service.analyze(Request::new(invalid))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let missing = AnalyzeRequest { bam_path: "missing.bam".to_string(), ..request };
+        let status = runtime.block_on(service.analyze(Request::new(missing))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,372 @@
+//! GTF/GFF3 annotation parsing: exons and the transcripts they belong to
+
+use crate::{
+    regions::BedRegion,
+    utils::{open_text_input, DEFAULT_MAX_LINE_LENGTH},
+    VlodError, VlodResult,
+};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+/// An exon of a transcript, as a 0-based half-open interval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exon {
+    pub region: BedRegion,
+    pub transcript_id: String,
+    pub gene: Option<String>,
+    /// Exon number within the transcript (numbered by position when the
+    /// annotation has none)
+    pub number: u32,
+}
+
+/// Attributes of a GTF (`key "value";`) or GFF3 (`key=value;`) attribute column
+fn parse_attributes(column: &str) -> HashMap<&str, &str> {
+    column
+        .split(';')
+        .filter_map(|attribute| {
+            let attribute = attribute.trim();
+            // Quoted values are GTF, whose values may themselves contain '='
+            let (key, value) = if attribute.contains('"') {
+                attribute.split_once(' ')?
+            } else {
+                attribute.split_once('=').or_else(|| attribute.split_once(' '))?
+            };
+            Some((key.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Transcript of a feature: the GTF `transcript_id` or the GFF3 `Parent`
+fn transcript_id(attributes: &HashMap<&str, &str>) -> Option<String> {
+    attributes.get("transcript_id").or_else(|| attributes.get("Parent")).map(|id| {
+        // GFF3 parents may list several transcripts and carry a type prefix
+        let id = id.split(',').next().unwrap_or(id);
+        id.strip_prefix("transcript:").unwrap_or(id).to_string()
+    })
+}
+
+/// Gene of a feature: its `gene_name`, else its `gene_id`
+fn gene_symbol(attributes: &HashMap<&str, &str>) -> Option<String> {
+    ["gene_name", "gene_id"].iter().find_map(|key| attributes.get(key)).map(|gene| gene.to_string())
+}
+
+/// Parse one annotation line, returning the exon it describes (None for other
+/// features)
+fn parse_exon_line(line: &str) -> VlodResult<Option<(Exon, bool)>> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 9 {
+        return Err(VlodError::InvalidVariant(format!(
+            "Invalid GTF/GFF3 line - expected 9 columns, found {}",
+            fields.len()
+        )));
+    }
+    if fields[2] != "exon" {
+        return Ok(None);
+    }
+
+    let start = fields[3]
+        .parse::<u64>()
+        .ok()
+        .filter(|&start| start > 0)
+        .ok_or_else(|| VlodError::in_column("start", format!("Invalid start: {}", fields[3])))?;
+    let end = fields[4]
+        .parse::<u64>()
+        .ok()
+        .filter(|&end| end >= start)
+        .ok_or_else(|| VlodError::in_column("end", format!("Invalid end: {}", fields[4])))?;
+
+    let attributes = parse_attributes(fields[8]);
+    let transcript_id = transcript_id(&attributes)
+        .ok_or_else(|| VlodError::in_column("attributes", "Exon without transcript_id or Parent".to_string()))?;
+    let gene = gene_symbol(&attributes);
+    let number = ["exon_number", "rank"]
+        .iter()
+        .find_map(|key| attributes.get(key))
+        .and_then(|number| number.parse::<u32>().ok());
+
+    let exon = Exon {
+        region: BedRegion {
+            chrom: fields[0].to_string(),
+            start: start - 1,
+            end,
+            name: None,
+        },
+        transcript_id,
+        gene,
+        number: number.unwrap_or(0),
+    };
+    Ok(Some((exon, number.is_some())))
+}
+
+/// Read the exons of a GTF or GFF3 file (optionally gzipped)
+pub fn read_exons<P: AsRef<Path>>(path: P) -> VlodResult<Vec<Exon>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    read_exons_from_reader(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?, &source)
+}
+
+/// Read exons from uncompressed GTF/GFF3 text
+pub fn read_exons_from_reader<R: BufRead>(reader: R, source: &str) -> VlodResult<Vec<Exon>> {
+    let mut exons = Vec::new();
+    let mut unnumbered = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((exon, numbered)) = parse_exon_line(line).map_err(|e| e.at_line(source, index as u64 + 1))? {
+            unnumbered |= !numbered;
+            exons.push(exon);
+        }
+    }
+
+    if unnumbered {
+        number_exons_by_position(&mut exons);
+    }
+    Ok(exons)
+}
+
+/// A transcript's exons and coding span, for locating HGVS positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub id: String,
+    pub gene: Option<String>,
+    pub chrom: String,
+    /// On the reverse strand
+    pub reverse: bool,
+    /// Exons as 0-based half-open intervals, in genomic order
+    pub exons: Vec<(u64, u64)>,
+    /// Coding span (CDS and stop codon features), 0-based half-open; None for
+    /// non-coding transcripts
+    pub cds: Option<(u64, u64)>,
+}
+
+impl Transcript {
+    /// Length of the spliced transcript
+    pub fn len(&self) -> u64 {
+        self.exons.iter().map(|(start, end)| end - start).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exons.is_empty()
+    }
+
+    /// 0-based genomic position of a 0-based position along the spliced
+    /// transcript, counted from its 5' end
+    pub fn genomic_position(&self, index: u64) -> Option<u64> {
+        let mut remaining = index;
+        let mut exons: Vec<(u64, u64)> = self.exons.clone();
+        if self.reverse {
+            exons.reverse();
+        }
+        for (start, end) in exons {
+            let length = end - start;
+            if remaining < length {
+                return Some(if self.reverse { end - 1 - remaining } else { start + remaining });
+            }
+            remaining -= length;
+        }
+        None
+    }
+
+    /// Position along the spliced transcript of a 0-based genomic position, if
+    /// exonic
+    pub fn transcript_position(&self, pos: u64) -> Option<u64> {
+        let mut exons: Vec<(u64, u64)> = self.exons.clone();
+        if self.reverse {
+            exons.reverse();
+        }
+        let mut offset = 0;
+        for (start, end) in exons {
+            if start <= pos && pos < end {
+                return Some(offset + if self.reverse { end - 1 - pos } else { pos - start });
+            }
+            offset += end - start;
+        }
+        None
+    }
+}
+
+/// Read the transcripts of a GTF or GFF3 file (optionally gzipped), keyed by
+/// transcript ID
+pub fn read_transcripts<P: AsRef<Path>>(path: P) -> VlodResult<HashMap<String, Transcript>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    read_transcripts_from_reader(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?, &source)
+}
+
+/// Read transcripts from uncompressed GTF/GFF3 text
+pub fn read_transcripts_from_reader<R: BufRead>(reader: R, source: &str) -> VlodResult<HashMap<String, Transcript>> {
+    let mut transcripts: HashMap<String, Transcript> = HashMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 || !matches!(fields[2], "exon" | "CDS" | "stop_codon") {
+            continue;
+        }
+        let invalid = |message: String| VlodError::InvalidVariant(message).at_line(source, index as u64 + 1);
+        let start = fields[3].parse::<u64>().ok().filter(|&start| start > 0);
+        let end = fields[4].parse::<u64>().ok();
+        let (Some(start), Some(end)) = (start, end) else {
+            return Err(invalid(format!("Invalid feature interval: {}-{}", fields[3], fields[4])));
+        };
+        if end < start {
+            return Err(invalid(format!("Invalid feature interval: {}-{}", fields[3], fields[4])));
+        }
+        let attributes = parse_attributes(fields[8]);
+        let Some(id) = transcript_id(&attributes) else {
+            continue;
+        };
+        let transcript = transcripts.entry(id.clone()).or_insert_with(|| Transcript {
+            id,
+            gene: gene_symbol(&attributes),
+            chrom: fields[0].to_string(),
+            reverse: fields[6] == "-",
+            exons: Vec::new(),
+            cds: None,
+        });
+        let (start, end) = (start - 1, end);
+        if fields[2] == "exon" {
+            transcript.exons.push((start, end));
+        } else {
+            transcript.cds = Some(match transcript.cds {
+                Some((cds_start, cds_end)) => (cds_start.min(start), cds_end.max(end)),
+                None => (start, end),
+            });
+        }
+    }
+    for transcript in transcripts.values_mut() {
+        transcript.exons.sort_unstable();
+    }
+    Ok(transcripts)
+}
+
+/// Exonic footprint of the named genes: the exons of each gene, merged into
+/// sorted non-overlapping regions named by the gene. Genes are matched on
+/// `gene_name` (or `gene_id` when the annotation has no names); a symbol missing
+/// from the annotation is an error.
+pub fn gene_regions(exons: &[Exon], genes: &[String]) -> VlodResult<Vec<BedRegion>> {
+    let mut regions = Vec::new();
+    for gene in genes {
+        let mut footprint: Vec<BedRegion> = exons
+            .iter()
+            .filter(|exon| exon.gene.as_deref() == Some(gene.as_str()))
+            .map(|exon| exon.region.clone())
+            .collect();
+        if footprint.is_empty() {
+            return Err(VlodError::InvalidConfig(format!("gene {} has no exons in the GTF", gene)));
+        }
+        footprint.sort_by(|a, b| (&a.chrom, a.start).cmp(&(&b.chrom, b.start)));
+        let mut merged: Vec<BedRegion> = Vec::new();
+        for region in footprint {
+            match merged.last_mut() {
+                Some(last) if last.chrom == region.chrom && region.start <= last.end => {
+                    last.end = last.end.max(region.end);
+                }
+                _ => merged.push(BedRegion { name: Some(gene.clone()), ..region }),
+            }
+        }
+        regions.extend(merged);
+    }
+    Ok(regions)
+}
+
+/// Number unnumbered exons by their order along each transcript
+fn number_exons_by_position(exons: &mut [Exon]) {
+    let mut by_transcript: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, exon) in exons.iter().enumerate() {
+        if exon.number == 0 {
+            by_transcript.entry(exon.transcript_id.clone()).or_default().push(index);
+        }
+    }
+    for mut indices in by_transcript.into_values() {
+        indices.sort_by_key(|&index| exons[index].region.start);
+        for (rank, index) in indices.into_iter().enumerate() {
+            exons[index].number = rank as u32 + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_gtf_exons() {
+        let gtf = "#!genome-build GRCh38\n\
+            chr1\tHAVANA\tgene\t100\t500\t.\t+\t.\tgene_id \"G1\"; gene_name \"ABC\";\n\
+            chr1\tHAVANA\texon\t100\t200\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1\"; exon_number \"1\"; gene_name \"ABC\";\n\
+            chr1\tHAVANA\texon\t300\t500\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1\"; exon_number \"2\"; gene_name \"ABC\";\n";
+        let exons = read_exons_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+        assert_eq!(exons.len(), 2);
+        assert_eq!(exons[0].region, BedRegion::from_line("chr1\t99\t200").unwrap());
+        assert_eq!(exons[1].transcript_id, "T1");
+        assert_eq!(exons[1].gene.as_deref(), Some("ABC"));
+        assert_eq!(exons[1].number, 2);
+    }
+
+    #[test]
+    fn test_read_gff3_exons() {
+        let gff = "##gff-version 3\n\
+            chr2\t.\texon\t500\t600\t.\t-\t.\tParent=transcript:T2;Name=E2\n\
+            chr2\t.\texon\t100\t200\t.\t-\t.\tParent=transcript:T2;Name=E1\n";
+        let exons = read_exons_from_reader(gff.as_bytes(), "test.gff3").unwrap();
+        assert_eq!(exons[0].transcript_id, "T2");
+        // Numbered by position when the annotation has no exon numbers
+        assert_eq!(exons[0].number, 2);
+        assert_eq!(exons[1].number, 1);
+    }
+
+    #[test]
+    fn test_read_transcripts() {
+        let gtf = "chr1\tsrc\texon\t301\t400\t.\t-\t.\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr1\tsrc\texon\t101\t200\t.\t-\t.\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr1\tsrc\tCDS\t151\t200\t.\t-\t0\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr1\tsrc\tCDS\t301\t350\t.\t-\t0\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr1\tsrc\tstop_codon\t148\t150\t.\t-\t0\ttranscript_id \"T1\"; gene_name \"ABC\";\n";
+        let transcripts = read_transcripts_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+        let transcript = &transcripts["T1"];
+        assert!(transcript.reverse);
+        assert_eq!(transcript.exons, vec![(100, 200), (300, 400)]);
+        assert_eq!(transcript.cds, Some((147, 350)));
+        assert_eq!(transcript.len(), 200);
+
+        // The 5' end of a reverse-strand transcript is its last genomic base
+        assert_eq!(transcript.genomic_position(0), Some(399));
+        assert_eq!(transcript.genomic_position(100), Some(199));
+        assert_eq!(transcript.genomic_position(200), None);
+        assert_eq!(transcript.transcript_position(199), Some(100));
+        assert_eq!(transcript.transcript_position(250), None);
+    }
+
+    #[test]
+    fn test_gene_regions() {
+        let gtf = "chr1\tsrc\texon\t100\t200\t.\t+\t.\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr1\tsrc\texon\t150\t300\t.\t+\t.\ttranscript_id \"T2\"; gene_name \"ABC\";\n\
+            chr1\tsrc\texon\t500\t600\t.\t+\t.\ttranscript_id \"T1\"; gene_name \"ABC\";\n\
+            chr2\tsrc\texon\t10\t20\t.\t-\t.\ttranscript_id \"T3\"; gene_name \"XYZ\";\n";
+        let exons = read_exons_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+
+        let regions = gene_regions(&exons, &["ABC".to_string(), "XYZ".to_string()]).unwrap();
+        let spans: Vec<(&str, u64, u64)> =
+            regions.iter().map(|region| (region.chrom.as_str(), region.start, region.end)).collect();
+        assert_eq!(spans, vec![("chr1", 99, 300), ("chr1", 499, 600), ("chr2", 9, 20)]);
+        assert_eq!(regions[2].name.as_deref(), Some("XYZ"));
+
+        let error = gene_regions(&exons, &["BRCA1".to_string()]).unwrap_err();
+        assert!(matches!(error, VlodError::InvalidConfig(_)));
+    }
+
+    #[test]
+    fn test_invalid_gtf_line() {
+        let gtf = "chr1\tsrc\texon\t0\t200\t.\t+\t.\ttranscript_id \"T1\";\n";
+        let error = read_exons_from_reader(gtf.as_bytes(), "test.gtf").unwrap_err();
+        assert!(matches!(error, VlodError::Parse { line: 1, .. }));
+        assert!(read_exons_from_reader("chr1\tsrc\texon\n".as_bytes(), "test.gtf").is_err());
+    }
+}
@@ -0,0 +1,552 @@
+//! HGVS hotspots (`NM_000546.6:c.524G>A`, `NM_000546.6:p.Arg175His`) located on
+//! the genome through the GTF transcripts and the reference FASTA, so that hotspot
+//! lists need not be converted to genomic coordinates by hand
+
+use crate::{
+    gtf::Transcript, reference::ReferenceFasta, utils::open_text_input, Variant, VlodError, VlodResult,
+};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Standard genetic code, codons ordered TTT, TTC, TTA, TTG, TCT, ... GGG
+const GENETIC_CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+/// Three-letter amino acid codes and their one-letter equivalents
+const AMINO_ACIDS: [(&str, char); 21] = [
+    ("Ala", 'A'),
+    ("Arg", 'R'),
+    ("Asn", 'N'),
+    ("Asp", 'D'),
+    ("Cys", 'C'),
+    ("Gln", 'Q'),
+    ("Glu", 'E'),
+    ("Gly", 'G'),
+    ("His", 'H'),
+    ("Ile", 'I'),
+    ("Leu", 'L'),
+    ("Lys", 'K'),
+    ("Met", 'M'),
+    ("Phe", 'F'),
+    ("Pro", 'P'),
+    ("Ser", 'S'),
+    ("Thr", 'T'),
+    ("Trp", 'W'),
+    ("Tyr", 'Y'),
+    ("Val", 'V'),
+    ("Ter", '*'),
+];
+
+/// A position in coding DNA numbering: `c.76`, `c.-14` (5' UTR), `c.*32` (3' UTR),
+/// with an optional intronic offset (`c.88+1`, `c.89-2`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodingPosition {
+    /// Bases from the first coding base (negative in the 5' UTR), or after the
+    /// stop codon in the 3' UTR
+    pub base: i64,
+    pub utr3: bool,
+    /// Bases into the intron from the exonic `base`
+    pub offset: i64,
+}
+
+impl FromStr for CodingPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid coding position '{}'", s);
+        let (utr3, sign, rest) = match s.as_bytes().first() {
+            Some(b'*') => (true, 1, &s[1..]),
+            Some(b'-') => (false, -1, &s[1..]),
+            _ => (false, 1, s),
+        };
+        let digits = rest.find(['+', '-']).unwrap_or(rest.len());
+        let base = rest[..digits].parse::<i64>().ok().filter(|&base| base > 0).ok_or_else(invalid)?;
+        let offset = match &rest[digits..] {
+            "" => 0,
+            offset => {
+                let value = offset[1..].parse::<i64>().ok().filter(|&value| value > 0).ok_or_else(invalid)?;
+                if offset.starts_with('-') {
+                    -value
+                } else {
+                    value
+                }
+            }
+        };
+        Ok(CodingPosition { base: sign * base, utr3, offset })
+    }
+}
+
+/// The edit of a `c.` description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodingEdit {
+    /// `524G>A`
+    Substitution { reference: String, alternate: String },
+    /// `del`, optionally followed by the deleted bases
+    Deletion,
+    Duplication,
+    Insertion(String),
+    DelIns(String),
+}
+
+/// The change an HGVS description makes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HgvsChange {
+    Coding { start: CodingPosition, end: CodingPosition, edit: CodingEdit },
+    /// A missense or nonsense change of one codon (`p.Arg175His`, `p.R175H`,
+    /// `p.Arg213Ter`), as one-letter amino acids
+    Protein { reference: char, codon: u64, alternate: char },
+}
+
+/// An HGVS description on a transcript
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hgvs {
+    pub transcript: String,
+    pub change: HgvsChange,
+}
+
+/// One-letter code of a three- or one-letter amino acid at the start of `s`, and
+/// the rest of `s`
+fn parse_amino_acid(s: &str) -> Option<(char, &str)> {
+    if let Some((code, letter)) = AMINO_ACIDS.iter().find(|(code, _)| s.starts_with(code)) {
+        return Some((*letter, &s[code.len()..]));
+    }
+    let letter = s.chars().next().filter(|letter| letter.is_ascii_uppercase() || *letter == '*')?;
+    let letter = if letter == 'X' { '*' } else { letter };
+    GENETIC_CODE.contains(&(letter as u8)).then(|| (letter, &s[1..]))
+}
+
+fn is_bases(sequence: &str) -> bool {
+    !sequence.is_empty() && sequence.bytes().all(|base| matches!(base, b'A' | b'C' | b'G' | b'T'))
+}
+
+impl FromStr for Hgvs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid HGVS '{}' (expected TRANSCRIPT:c.CHANGE or TRANSCRIPT:p.CHANGE)", s);
+        let (transcript, description) = s.trim().split_once(':').ok_or_else(invalid)?;
+        // `NM_000546.6(TP53):c.524G>A` names the gene as well
+        let transcript = transcript.split('(').next().unwrap_or(transcript);
+        if transcript.is_empty() {
+            return Err(invalid());
+        }
+
+        let change = if let Some(change) = description.strip_prefix("c.") {
+            let edit_start = change.find(|c: char| c.is_ascii_alphabetic()).ok_or_else(invalid)?;
+            let (positions, edit) = change.split_at(edit_start);
+            let (start, end) = match positions.split_once('_') {
+                Some((start, end)) => (start.parse::<CodingPosition>()?, end.parse::<CodingPosition>()?),
+                None => {
+                    let position = positions.parse::<CodingPosition>()?;
+                    (position, position)
+                }
+            };
+            let edit = if let Some((reference, alternate)) = edit.split_once('>') {
+                if start != end || reference.len() != 1 || !is_bases(reference) || !is_bases(alternate) {
+                    return Err(invalid());
+                }
+                CodingEdit::Substitution { reference: reference.to_string(), alternate: alternate.to_string() }
+            } else if let Some(inserted) = edit.strip_prefix("delins") {
+                CodingEdit::DelIns(inserted.to_string())
+            } else if let Some(deleted) = edit.strip_prefix("del") {
+                if !deleted.is_empty() && !is_bases(deleted) {
+                    return Err(invalid());
+                }
+                CodingEdit::Deletion
+            } else if let Some(duplicated) = edit.strip_prefix("dup") {
+                if !duplicated.is_empty() && !is_bases(duplicated) {
+                    return Err(invalid());
+                }
+                CodingEdit::Duplication
+            } else if let Some(inserted) = edit.strip_prefix("ins") {
+                CodingEdit::Insertion(inserted.to_string())
+            } else {
+                return Err(invalid());
+            };
+            if let CodingEdit::Insertion(sequence) | CodingEdit::DelIns(sequence) = &edit {
+                if !is_bases(sequence) {
+                    return Err(invalid());
+                }
+            }
+            HgvsChange::Coding { start, end, edit }
+        } else if let Some(change) = description.strip_prefix("p.") {
+            // Predicted changes are parenthesised: p.(Arg175His)
+            let change = change.trim_start_matches('(').trim_end_matches(')');
+            let (reference, rest) = parse_amino_acid(change).ok_or_else(invalid)?;
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let codon = rest[..digits].parse::<u64>().ok().filter(|&codon| codon > 0).ok_or_else(invalid)?;
+            let (alternate, rest) = parse_amino_acid(&rest[digits..]).ok_or_else(|| {
+                format!("unsupported HGVS '{}' (only missense and nonsense protein changes are located)", s)
+            })?;
+            if !rest.is_empty() {
+                return Err(invalid());
+            }
+            HgvsChange::Protein { reference, codon, alternate }
+        } else {
+            return Err(invalid());
+        };
+        Ok(Hgvs { transcript: transcript.to_string(), change })
+    }
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        other => other,
+    }
+}
+
+fn reverse_complement(sequence: &str) -> String {
+    sequence.bytes().rev().map(|base| complement(base) as char).collect()
+}
+
+/// Amino acid a codon codes for (None for codons with other than ACGT)
+pub fn translate(codon: &[u8]) -> Option<char> {
+    let index = |base: u8| match base {
+        b'T' => Some(0),
+        b'C' => Some(1),
+        b'A' => Some(2),
+        b'G' => Some(3),
+        _ => None,
+    };
+    match codon {
+        [first, second, third] => {
+            Some(GENETIC_CODE[16 * index(*first)? + 4 * index(*second)? + index(*third)?] as char)
+        }
+        _ => None,
+    }
+}
+
+/// A transcript by ID, ignoring the version when the annotation has another one
+/// (`NM_000546.6` matches `NM_000546` and `NM_000546.5`)
+pub fn find_transcript<'a>(transcripts: &'a HashMap<String, Transcript>, id: &str) -> Option<&'a Transcript> {
+    let unversioned = |id: &str| id.split('.').next().unwrap_or(id).to_string();
+    transcripts
+        .get(id)
+        .or_else(|| transcripts.values().find(|transcript| unversioned(&transcript.id) == unversioned(id)))
+}
+
+/// 0-based genomic position of a coding position on a transcript
+fn coding_to_genomic(transcript: &Transcript, position: CodingPosition) -> Result<u64, String> {
+    let (cds_start, cds_end) = transcript.cds.ok_or_else(|| format!("transcript {} is non-coding", transcript.id))?;
+    let (first, last) = if transcript.reverse { (cds_end - 1, cds_start) } else { (cds_start, cds_end - 1) };
+    let outside = || format!("position is outside transcript {}", transcript.id);
+    let first = transcript.transcript_position(first).ok_or_else(outside)? as i64;
+    let last = transcript.transcript_position(last).ok_or_else(outside)? as i64;
+    let index = if position.utr3 {
+        last + position.base
+    } else if position.base > 0 {
+        first + position.base - 1
+    } else {
+        first + position.base
+    };
+    let exonic = u64::try_from(index).ok().and_then(|index| transcript.genomic_position(index)).ok_or_else(outside)?;
+    let offset = if transcript.reverse { -position.offset } else { position.offset };
+    u64::try_from(exonic as i64 + offset).map_err(|_| outside())
+}
+
+/// Locate an HGVS description on the genome: one variant for a `c.` description,
+/// every single-base change giving the amino acid change for a `p.` description.
+/// Indels are written VCF-style with the preceding base, and are not left-aligned.
+pub fn hgvs_to_variants(
+    hgvs: &Hgvs,
+    transcripts: &HashMap<String, Transcript>,
+    reference: &ReferenceFasta,
+) -> VlodResult<Vec<Variant>> {
+    let invalid = |message: String| VlodError::InvalidVariant(format!("{}: {}", hgvs.transcript, message));
+    let transcript = find_transcript(transcripts, &hgvs.transcript)
+        .ok_or_else(|| invalid("transcript is not in the GTF".to_string()))?;
+    let chrom = &transcript.chrom;
+    // Bases of the 0-based inclusive interval [start, end]
+    let bases = |start: u64, end: u64| -> VlodResult<String> {
+        let sequence = reference.fetch(chrom, start + 1, end + 1)?;
+        if sequence.len() as u64 != end - start + 1 {
+            return Err(invalid(format!("{}:{}-{} is past the end of the contig", chrom, start + 1, end + 1)));
+        }
+        Ok(sequence)
+    };
+    let stranded = |sequence: &str| if transcript.reverse { reverse_complement(sequence) } else { sequence.to_string() };
+    let variant = |pos: u64, ref_allele: String, alt_allele: String| {
+        Variant::new(chrom.clone(), pos, ref_allele, alt_allele)
+    };
+
+    match &hgvs.change {
+        HgvsChange::Coding { start, end, edit } => {
+            let start = coding_to_genomic(transcript, *start).map_err(invalid)?;
+            let end = coding_to_genomic(transcript, *end).map_err(invalid)?;
+            let (low, high) = (start.min(end), start.max(end));
+            let located = match edit {
+                CodingEdit::Substitution { reference: ref_base, alternate } => {
+                    let ref_base = stranded(ref_base);
+                    let actual = bases(low, low)?;
+                    if actual != ref_base {
+                        return Err(invalid(format!(
+                            "the reference base at {}:{} is {}, not {}",
+                            chrom,
+                            low + 1,
+                            actual,
+                            ref_base
+                        )));
+                    }
+                    variant(low + 1, ref_base, stranded(alternate))
+                }
+                CodingEdit::Deletion => {
+                    if low == 0 {
+                        return Err(invalid("a deletion at the contig start cannot be padded".to_string()));
+                    }
+                    let padded = bases(low - 1, high)?;
+                    variant(low, padded.clone(), padded[..1].to_string())
+                }
+                CodingEdit::Duplication => {
+                    let duplicated = bases(low, high)?;
+                    let anchor = duplicated[duplicated.len() - 1..].to_string();
+                    variant(high + 1, anchor.clone(), anchor + &duplicated)
+                }
+                CodingEdit::Insertion(inserted) => {
+                    if high != low + 1 {
+                        return Err(invalid("an insertion must be between adjacent positions".to_string()));
+                    }
+                    let anchor = bases(low, low)?;
+                    variant(low + 1, anchor.clone(), anchor + &stranded(inserted))
+                }
+                CodingEdit::DelIns(inserted) => variant(low + 1, bases(low, high)?, stranded(inserted)),
+            };
+            Ok(vec![located])
+        }
+        HgvsChange::Protein { reference: ref_amino_acid, codon, alternate } => {
+            let positions = (0..3)
+                .map(|i| {
+                    let base = (3 * codon - 2 + i) as i64;
+                    coding_to_genomic(transcript, CodingPosition { base, utr3: false, offset: 0 })
+                })
+                .collect::<Result<Vec<u64>, String>>()
+                .map_err(invalid)?;
+            let mut genomic = Vec::with_capacity(3);
+            for &pos in &positions {
+                genomic.push(bases(pos, pos)?.as_bytes()[0]);
+            }
+            let strand = |base: u8| if transcript.reverse { complement(base) } else { base };
+            let triplet: Vec<u8> = genomic.iter().map(|&base| strand(base)).collect();
+            let actual = translate(&triplet);
+            if actual != Some(*ref_amino_acid) {
+                return Err(invalid(format!(
+                    "codon {} is {} ({}), not {}",
+                    codon,
+                    String::from_utf8_lossy(&triplet),
+                    actual.unwrap_or('?'),
+                    ref_amino_acid
+                )));
+            }
+
+            let mut variants = Vec::new();
+            for (i, &pos) in positions.iter().enumerate() {
+                for base in [b'A', b'C', b'G', b'T'] {
+                    if base == triplet[i] {
+                        continue;
+                    }
+                    let mut changed = triplet.clone();
+                    changed[i] = base;
+                    if translate(&changed) == Some(*alternate) {
+                        variants.push(variant(
+                            pos + 1,
+                            (genomic[i] as char).to_string(),
+                            (strand(base) as char).to_string(),
+                        ));
+                    }
+                }
+            }
+            if variants.is_empty() {
+                return Err(invalid(format!("no single-base change turns {} into {}", ref_amino_acid, alternate)));
+            }
+            variants.sort_by_key(|variant| variant.pos);
+            Ok(variants)
+        }
+    }
+}
+
+/// An HGVS hotspot and the variants it was located as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HgvsHotspot {
+    /// The HGVS description as given
+    pub hgvs: String,
+    pub variants: Vec<Variant>,
+}
+
+/// Read a hotspot list, one HGVS description per line (in the first column;
+/// blank and `#` lines are skipped), and locate each on the genome
+pub fn read_hgvs_hotspots<P: AsRef<Path>>(
+    path: P,
+    transcripts: &HashMap<String, Transcript>,
+    reference: &ReferenceFasta,
+    max_line_length: usize,
+) -> VlodResult<Vec<HgvsHotspot>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    read_hgvs_hotspots_from_reader(open_text_input(path, max_line_length)?, &source, transcripts, reference)
+}
+
+/// Read and locate a hotspot list from any reader
+pub fn read_hgvs_hotspots_from_reader<R: BufRead>(
+    reader: R,
+    source: &str,
+    transcripts: &HashMap<String, Transcript>,
+    reference: &ReferenceFasta,
+) -> VlodResult<Vec<HgvsHotspot>> {
+    let mut hotspots = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let Some(description) = line.split_whitespace().next().filter(|field| !field.starts_with('#')) else {
+            continue;
+        };
+        let located = description
+            .parse::<Hgvs>()
+            .map_err(VlodError::InvalidVariant)
+            .and_then(|hgvs| hgvs_to_variants(&hgvs, transcripts, reference))
+            .map_err(|e| e.at_line(source, index as u64 + 1))?;
+        hotspots.push(HgvsHotspot { hgvs: description.to_string(), variants: located });
+    }
+    Ok(hotspots)
+}
+
+/// HGVS descriptions of each located variant (comma-separated when several
+/// hotspots locate the same variant)
+pub fn hgvs_by_variant(hotspots: &[HgvsHotspot]) -> HashMap<Variant, String> {
+    let mut by_variant: HashMap<Variant, String> = HashMap::new();
+    for hotspot in hotspots {
+        for variant in &hotspot.variants {
+            by_variant
+                .entry(variant.clone())
+                .and_modify(|hgvs| {
+                    hgvs.push(',');
+                    hgvs.push_str(&hotspot.hgvs);
+                })
+                .or_insert_with(|| hotspot.hgvs.clone());
+        }
+    }
+    by_variant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtf::read_transcripts_from_reader;
+
+    /// A forward gene with exons 11-30 and 41-60 (coding 16-30 and 41-45) and its
+    /// reverse-strand mirror on chr2
+    fn test_annotation(dir: &Path) -> (HashMap<String, Transcript>, ReferenceFasta) {
+        // Codon 3 (c.7-9, chr1 22-24) is CGT, Arg; chr2 is the reverse complement
+        let chr1 = format!("{}CCCCCATGGAACGTAAAAAA{}TTTAA{}", "G".repeat(10), "G".repeat(10), "C".repeat(20));
+        let chr2 = reverse_complement(&chr1);
+        let fasta = dir.join("ref.fa");
+        std::fs::write(&fasta, format!(">chr1\n{}\n>chr2\n{}\n", chr1, chr2)).unwrap();
+        let length = chr1.len() as u64;
+        let mirror = |pos: u64| length + 1 - pos;
+
+        let mut gtf = String::new();
+        let mut feature = |chrom: &str, kind: &str, start: u64, end: u64, strand: char, id: &str| {
+            gtf.push_str(&format!(
+                "{}\tsrc\t{}\t{}\t{}\t.\t{}\t.\ttranscript_id \"{}\"; gene_name \"G\";\n",
+                chrom, kind, start, end, strand, id
+            ));
+        };
+        for (kind, start, end) in [("exon", 11, 30), ("exon", 41, 60), ("CDS", 16, 30), ("CDS", 41, 45)] {
+            feature("chr1", kind, start, end, '+', "NM_1.2");
+            feature("chr2", kind, mirror(end), mirror(start), '-', "NM_2.1");
+        }
+        let transcripts = read_transcripts_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+        (transcripts, ReferenceFasta::open(&fasta, true).unwrap())
+    }
+
+    fn locate(hgvs: &str, transcripts: &HashMap<String, Transcript>, reference: &ReferenceFasta) -> Vec<Variant> {
+        hgvs_to_variants(&hgvs.parse().unwrap(), transcripts, reference).unwrap()
+    }
+
+    #[test]
+    fn test_parse_hgvs() {
+        let hgvs: Hgvs = "NM_000546.6(TP53):c.524G>A".parse().unwrap();
+        assert_eq!(hgvs.transcript, "NM_000546.6");
+        let position = |base, utr3, offset| CodingPosition { base, utr3, offset };
+        assert_eq!(
+            hgvs.change,
+            HgvsChange::Coding {
+                start: position(524, false, 0),
+                end: position(524, false, 0),
+                edit: CodingEdit::Substitution { reference: "G".to_string(), alternate: "A".to_string() }
+            }
+        );
+        assert_eq!("c.88+1".trim_start_matches("c.").parse(), Ok(position(88, false, 1)));
+        assert_eq!("-14".parse(), Ok(position(-14, false, 0)));
+        assert_eq!("*32-2".parse(), Ok(position(32, true, -2)));
+        assert!("0".parse::<CodingPosition>().is_err());
+
+        let protein: Hgvs = "NM_000546.6:p.(Arg175His)".parse().unwrap();
+        assert_eq!(protein.change, HgvsChange::Protein { reference: 'R', codon: 175, alternate: 'H' });
+        let nonsense: Hgvs = "NM_1:p.R213*".parse().unwrap();
+        assert_eq!(nonsense.change, HgvsChange::Protein { reference: 'R', codon: 213, alternate: '*' });
+        assert!("NM_1:c.10_12delinsAT".parse::<Hgvs>().is_ok());
+        assert!("NM_1:p.Arg175fs".parse::<Hgvs>().is_err());
+        assert!("NM_1:c.10G>AT".parse::<Hgvs>().is_err());
+        assert!("NM_1:g.100A>G".parse::<Hgvs>().is_err());
+        assert!("c.100A>G".parse::<Hgvs>().is_err());
+    }
+
+    #[test]
+    fn test_coding_hgvs_to_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let (transcripts, reference) = test_annotation(dir.path());
+        let variant = |chrom: &str, pos: u64, ref_allele: &str, alt: &str| {
+            Variant::new(chrom.to_string(), pos, ref_allele.to_string(), alt.to_string())
+        };
+
+        // c.1 is the A of the ATG at chr1:16; c.16 is the first base of the second exon
+        assert_eq!(locate("NM_1.2:c.1A>G", &transcripts, &reference), vec![variant("chr1", 16, "A", "G")]);
+        assert_eq!(locate("NM_1:c.16T>C", &transcripts, &reference), vec![variant("chr1", 41, "T", "C")]);
+        assert_eq!(locate("NM_1.2:c.15+1G>A", &transcripts, &reference), vec![variant("chr1", 31, "G", "A")]);
+        assert_eq!(locate("NM_1.2:c.-1C>T", &transcripts, &reference), vec![variant("chr1", 15, "C", "T")]);
+        assert_eq!(locate("NM_1.2:c.*1C>T", &transcripts, &reference), vec![variant("chr1", 46, "C", "T")]);
+        assert_eq!(locate("NM_1.2:c.4_5del", &transcripts, &reference), vec![variant("chr1", 18, "GGA", "G")]);
+        assert_eq!(locate("NM_1.2:c.4dup", &transcripts, &reference), vec![variant("chr1", 19, "G", "GG")]);
+        assert_eq!(locate("NM_1.2:c.3_4insTT", &transcripts, &reference), vec![variant("chr1", 18, "G", "GTT")]);
+
+        // The reverse-strand mirror gives the reverse-complemented alleles
+        assert_eq!(locate("NM_2.1:c.1A>G", &transcripts, &reference), vec![variant("chr2", 50, "T", "C")]);
+        assert_eq!(locate("NM_2.1:c.3_4insTT", &transcripts, &reference), vec![variant("chr2", 47, "C", "CAA")]);
+
+        let wrong_base = hgvs_to_variants(&"NM_1.2:c.1C>G".parse().unwrap(), &transcripts, &reference);
+        assert!(matches!(wrong_base, Err(VlodError::InvalidVariant(_))));
+        assert!(hgvs_to_variants(&"NM_9:c.1A>G".parse().unwrap(), &transcripts, &reference).is_err());
+        assert!(hgvs_to_variants(&"NM_1.2:c.1_3insA".parse().unwrap(), &transcripts, &reference).is_err());
+    }
+
+    #[test]
+    fn test_protein_hgvs_to_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let (transcripts, reference) = test_annotation(dir.path());
+
+        // CAT is the only His codon one base from CGT
+        let variants = locate("NM_1.2:p.Arg3His", &transcripts, &reference);
+        assert_eq!(variants, vec![Variant::new("chr1".to_string(), 23, "G".to_string(), "A".to_string())]);
+        let mirrored = locate("NM_2.1:p.R3H", &transcripts, &reference);
+        assert_eq!(mirrored, vec![Variant::new("chr2".to_string(), 43, "C".to_string(), "T".to_string())]);
+
+        // Every single-base change to a Ser codon: only AGT
+        let serine = locate("NM_1.2:p.Arg3Ser", &transcripts, &reference);
+        assert_eq!(serine, vec![Variant::new("chr1".to_string(), 22, "C".to_string(), "A".to_string())]);
+
+        assert!(hgvs_to_variants(&"NM_1.2:p.Gly3His".parse().unwrap(), &transcripts, &reference).is_err());
+
+        let list = "# hotspots\nNM_1.2:c.1A>G\tstart\nNM_1.2:p.Arg3His\n\n";
+        let hotspots = read_hgvs_hotspots_from_reader(list.as_bytes(), "hotspots.txt", &transcripts, &reference).unwrap();
+        assert_eq!(hotspots.len(), 2);
+        assert_eq!(hotspots[1].hgvs, "NM_1.2:p.Arg3His");
+        let by_variant = hgvs_by_variant(&hotspots);
+        assert_eq!(by_variant[&hotspots[0].variants[0]], "NM_1.2:c.1A>G");
+
+        let error = read_hgvs_hotspots_from_reader("NM_1.2:c.1C>G\n".as_bytes(), "hotspots.txt", &transcripts, &reference)
+            .unwrap_err();
+        assert!(matches!(error, VlodError::Parse { line: 1, .. }));
+    }
+}
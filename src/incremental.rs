@@ -0,0 +1,181 @@
+//! Incremental annotation: a VCF annotated by an earlier run with the same model
+//! configuration keeps its DET/DETS records, and only variants without them are
+//! analysed again
+
+use crate::{integrity::Sha256, utils::open_text_input, vcf::Caller, LodConfig, Variant, VlodResult};
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Prefix of the VCF header line recording the configuration hash of a run
+const CONFIG_HEADER_PREFIX: &str = "##vlodConfig=<Hash=";
+
+/// Hash of the settings that decide DET/DETS: the vlod version, the BAM (file name
+/// and size) and the model parameters. Settings that only affect performance or
+/// extra outputs (retries, open readers, titration) are left out, as are the
+/// per-variant `VLOD_*` overrides, which travel with the VCF records.
+pub fn config_hash<P: AsRef<Path>>(config: &LodConfig, bam_path: P) -> VlodResult<String> {
+    let bam_path = bam_path.as_ref();
+    let bam_size = std::fs::metadata(bam_path)?.len();
+    let bam_name = bam_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let settings = [
+        format!("version={}", env!("CARGO_PKG_VERSION")),
+        format!("bam={}:{}", bam_name, bam_size),
+        format!("p_tp={:?}", config.p_tp),
+        format!("p_fp={:?}", config.p_fp),
+        format!("p_se={:?}", config.p_se),
+        format!("score_threshold={:?}", config.score_threshold),
+        format!("local_assembly={}", config.local_assembly),
+        format!("amplicons={}", config.amplicons.is_some()),
+        format!("bisulfite={}", config.bisulfite),
+        format!("ffpe={}", config.ffpe),
+        format!("calibration={:?}", config.calibration),
+        format!("ref_confirmation={:?}", config.ref_confirmation),
+        format!("required_depth_vaf={:?}", config.required_depth_vaf),
+        format!("noise_profile={:?}", config.noise_profile),
+        format!("read_filters={:?}", config.read_filters),
+        format!("pool={:?}", config.pool),
+        format!("contig_policy={:?}", config.contig_policy),
+        format!("alt_contig_map={}", config.alt_contig_map.is_some()),
+        format!("vaf_definition={:?}", config.vaf_definition),
+        format!("max_other_allele_fraction={:?}", config.max_other_allele_fraction),
+        format!("deleted_reads={:?}", config.deleted_reads),
+        format!("short_fragments={:?}", config.short_fragments),
+        format!("min_base_quality={}", config.min_base_quality),
+        format!("min_mapping_quality={}", config.min_mapping_quality),
+        format!("missing_quality={}", config.missing_quality),
+        format!("coverage_only={}", config.coverage_only),
+        format!("max_pileup_depth={}", config.max_pileup_depth),
+        format!("depth_cap={}", config.depth_cap),
+        format!("reference={}", config.reference.is_some()),
+    ];
+    let mut hasher = Sha256::new();
+    for setting in &settings {
+        hasher.update(setting.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hasher.hex_digest())
+}
+
+/// `##vlodConfig` VCF header line recording a configuration hash
+pub fn config_header_line(hash: &str) -> String {
+    format!("{}{}>", CONFIG_HEADER_PREFIX, hash)
+}
+
+/// Annotations already present in a VCF
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriorAnnotations {
+    /// Configuration hash of the run that annotated the VCF, if recorded
+    pub config_hash: Option<String>,
+    /// `(chrom, pos, ref, alt)` of every ALT allele of the records carrying DET and DETS
+    pub annotated: HashSet<(String, u64, String, String)>,
+}
+
+impl PriorAnnotations {
+    /// Whether the annotations were made with the configuration hashed to `hash`
+    pub fn matches(&self, hash: &str) -> bool {
+        self.config_hash.as_deref() == Some(hash)
+    }
+
+    /// Whether a variant's record already carries DET and DETS
+    pub fn contains(&self, variant: &Variant) -> bool {
+        self.annotated.contains(&(
+            variant.chrom.clone(),
+            variant.pos,
+            variant.ref_allele.clone(),
+            variant.alt_allele.clone(),
+        ))
+    }
+}
+
+/// Whether an INFO column carries both DET and DETS
+pub fn has_detectability_info(info: &str) -> bool {
+    let keys: Vec<&str> = info.split(';').map(|field| field.split('=').next().unwrap_or(field)).collect();
+    keys.contains(&"DET") && keys.contains(&"DETS")
+}
+
+/// Read the configuration hash and the annotated records of a VCF
+pub fn read_prior_annotations<P: AsRef<Path>>(
+    vcf_path: P,
+    caller: Caller,
+    max_line_length: usize,
+) -> VlodResult<PriorAnnotations> {
+    read_prior_annotations_from_reader(open_text_input(vcf_path, max_line_length)?, caller)
+}
+
+/// Read the configuration hash and the annotated records of VCF text
+pub fn read_prior_annotations_from_reader<R: BufRead>(reader: R, caller: Caller) -> VlodResult<PriorAnnotations> {
+    let mut prior = PriorAnnotations::default();
+    let mut caller = caller;
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(hash) = line.strip_prefix(CONFIG_HEADER_PREFIX) {
+            prior.config_hash = Some(hash.trim_end_matches('>').to_string());
+            continue;
+        }
+        if line.starts_with('#') {
+            caller = caller.resolve(&line);
+            continue;
+        }
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 8 || !has_detectability_info(columns[7]) {
+            continue;
+        }
+        let Ok(pos) = columns[1].parse::<u64>() else {
+            continue;
+        };
+        for alt in caller.alt_alleles(columns[4]) {
+            prior.annotated.insert((columns[0].to_string(), pos, columns[3].to_string(), alt.to_string()));
+        }
+    }
+    Ok(prior)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_prior_annotations() {
+        let vcf = format!(
+            "##fileformat=VCFv4.2\n{}\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+             chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;DET=Yes;DETS=3.5\n\
+             chr1\t200\t.\tC\tG,T\t.\tPASS\tDET=No;DETS=1.2\n\
+             chr1\t300\t.\tG\tA\t.\tPASS\tDP=30\n\
+             chr1\t400\t.\tG\tA\t.\tPASS\tDETS=2\n",
+            config_header_line("abc123")
+        );
+        let prior = read_prior_annotations_from_reader(Cursor::new(vcf), Caller::Generic).unwrap();
+        assert!(prior.matches("abc123"));
+        assert!(!prior.matches("def456"));
+        assert_eq!(prior.annotated.len(), 3);
+        let variant = |pos: u64, ref_allele: &str, alt: &str| {
+            Variant::new("chr1".to_string(), pos, ref_allele.to_string(), alt.to_string())
+        };
+        assert!(prior.contains(&variant(100, "A", "T")));
+        assert!(prior.contains(&variant(200, "C", "T")));
+        assert!(!prior.contains(&variant(300, "G", "A")));
+        assert!(!prior.contains(&variant(400, "G", "A")));
+
+        let unhashed = read_prior_annotations_from_reader(Cursor::new("#CHROM\n"), Caller::Generic).unwrap();
+        assert_eq!(unhashed.config_hash, None);
+        assert!(!unhashed.matches("abc123"));
+    }
+
+    #[test]
+    fn test_config_hash() {
+        let bam = tempfile::NamedTempFile::new().unwrap();
+        let config = LodConfig::default();
+        let hash = config_hash(&config, bam.path()).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(config_hash(&config, bam.path()).unwrap(), hash);
+
+        // Performance settings do not change the hash; model parameters do
+        let tuned = LodConfig { max_open_bams: Some(4), balance_depth: true, ..config.clone() };
+        assert_eq!(config_hash(&tuned, bam.path()).unwrap(), hash);
+        let changed = LodConfig { p_se: 0.001, ..config };
+        assert_ne!(config_hash(&changed, bam.path()).unwrap(), hash);
+        assert_eq!(config_header_line("abc"), "##vlodConfig=<Hash=abc>");
+    }
+}
@@ -0,0 +1,120 @@
+//! Output integrity: SHA-256 checksum sidecars (`sha256sum` format) written next to
+//! result files, so that their contents can be verified during an audit
+
+use crate::{
+    manifest::read_chunks,
+    utils::{append_extension, create_output_file},
+    VlodError, VlodResult,
+};
+use sha2::Digest;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Extension appended to an output's file name for its checksum sidecar
+pub const CHECKSUM_SIDECAR_EXTENSION: &str = "sha256";
+
+/// Streaming SHA-256, matching `sha256sum`
+#[derive(Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256(sha2::Sha256::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Lower-case hex digest
+    pub fn hex_digest(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+/// SHA-256 hex digest of a file
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> VlodResult<String> {
+    let mut sha256 = Sha256::new();
+    read_chunks(path, |chunk| sha256.update(chunk))?;
+    Ok(sha256.hex_digest())
+}
+
+/// Path of the checksum sidecar of an output (`out.vcf` -> `out.vcf.sha256`)
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    append_extension(path, CHECKSUM_SIDECAR_EXTENSION)
+}
+
+/// Write the checksum sidecar of a finished output. The sidecar names the file
+/// without its directory, so `sha256sum -c` works from the output directory.
+pub fn write_checksum_sidecar<P: AsRef<Path>>(path: P) -> VlodResult<PathBuf> {
+    let path = path.as_ref();
+    let digest = sha256_file(path)?;
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let sidecar = sidecar_path(path);
+    let mut file = create_output_file(&sidecar)?;
+    writeln!(file, "{}  {}", digest, name)?;
+    Ok(sidecar)
+}
+
+/// Whether an output still matches its checksum sidecar
+pub fn verify_checksum_sidecar<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
+    let path = path.as_ref();
+    let sidecar = sidecar_path(path);
+    let content = std::fs::read_to_string(&sidecar)
+        .map_err(|_| VlodError::FileNotFound(sidecar.to_string_lossy().to_string()))?;
+    let expected = content
+        .split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| {
+            VlodError::InvalidConfig(format!("{} is not a SHA-256 checksum file", sidecar.display()))
+        })?;
+    Ok(sha256_file(path)?.eq_ignore_ascii_case(expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut sha256 = Sha256::new();
+        sha256.update(data);
+        sha256.hex_digest()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // Chunked updates across block boundaries give the same digest
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut sha256 = Sha256::new();
+        for chunk in data.chunks(37) {
+            sha256.update(chunk);
+        }
+        assert_eq!(sha256.hex_digest(), "96ad0ddabe9c733d4550fde750255a94806811029be67504bd9bd68e556686b9");
+    }
+
+    #[test]
+    fn test_checksum_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("sample.vcf");
+        std::fs::write(&output, "abc").unwrap();
+
+        let sidecar = write_checksum_sidecar(&output).unwrap();
+        assert_eq!(sidecar, dir.path().join("sample.vcf.sha256"));
+        assert_eq!(
+            std::fs::read_to_string(&sidecar).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  sample.vcf\n"
+        );
+        assert!(verify_checksum_sidecar(&output).unwrap());
+
+        std::fs::write(&output, "abd").unwrap();
+        assert!(!verify_checksum_sidecar(&output).unwrap());
+        assert!(matches!(
+            verify_checksum_sidecar(dir.path().join("other.vcf")),
+            Err(VlodError::FileNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+//! SIGINT/SIGTERM handling: the first signal asks long-running scoring to stop so
+//! that completed results can still be written; a second one exits at once.
+//! Embedders stop a run the same way with a `CancellationToken`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Exit status of a run stopped by a signal (128 + SIGINT, as shells report it)
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Set by the signal handlers
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The flag the signal handlers set, for `calculate_detectability_scores_until`
+pub fn flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
+
+/// Cloneable handle through which another thread (e.g. a GUI or a server request
+/// handler) stops an analysis; scoring checks it between variants and returns the
+/// results completed so far
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the analysis to stop before its next variant
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// The flag to pass as `stop` to `calculate_detectability_scores_until`
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+/// Whether SIGINT or SIGTERM has been received
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    // Only async-signal-safe calls here: an atomic swap and _exit
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(INTERRUPTED_EXIT_CODE) };
+    }
+}
+
+/// Catch SIGINT and SIGTERM so that the run can flush partial results
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic and calls _exit
+        unsafe {
+            libc::signal(signal, handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+/// Signals keep their default behaviour on platforms without POSIX signals
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        assert!(!token.is_cancelled());
+
+        std::thread::spawn(move || handle.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        assert!(token.flag().load(Ordering::SeqCst));
+    }
+}
@@ -3,26 +3,98 @@
 //! A Rust implementation of the vLoD tool for assessing the detectability status
 //! of alleles from variant call files (VCF) using matched sequencing data.
 
+pub mod about;
+pub mod annotation;
+pub mod audit;
+#[cfg(feature = "assembly")]
+pub mod assembly;
 pub mod bam;
+pub mod bam_comparison;
+pub mod bcf;
+pub mod calibration;
+pub mod chimerism;
+pub mod claims;
+pub mod compare;
+pub mod confirmation;
+pub mod contamination;
+pub mod contig;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod gtf;
+pub mod hgvs;
+pub mod incremental;
+pub mod integrity;
+pub mod interrupt;
 pub mod lod;
+pub mod manifest;
 pub mod merge;
+pub mod noise;
+pub mod observer;
+pub mod pgcopy;
+pub mod pipeline;
+#[cfg(feature = "plots")]
+pub mod plots;
+pub mod pool;
+pub mod presets;
+pub mod read_filter;
+pub mod reference;
+pub mod regions;
+#[cfg(feature = "sqlite")]
+pub mod results_db;
+pub mod results_index;
+pub mod review;
+pub mod rollup;
+pub mod sample;
+pub mod server;
+pub mod streaming;
+pub mod summary;
+pub mod sweep;
+pub mod testdata;
+pub mod titration;
+pub mod topup;
+pub mod uniformity;
 pub mod utils;
 pub mod vcf;
+pub mod verify;
+pub mod warnings;
+pub mod watch;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+pub use about::{about, run_about, About};
 
 use anyhow::Result;
+use bam::{
+    DeletedReadPolicy, DepthCapPolicy, MissingQualityPolicy, RetryPolicy, ShortFragmentPolicy, VafDefinition,
+    DEFAULT_FETCH_MERGE_DISTANCE, DEFAULT_MAX_PILEUP_DEPTH,
+};
+use calibration::Calibration;
+use confirmation::RefConfirmation;
+use contig::{AltContigMap, ContigPolicy};
+use lod::calculate_variant_lod_score;
+use noise::{NoiseProfile, SubstitutionClass};
+use pool::PoolDesign;
+use read_filter::ReadFilter;
+use regions::AmpliconSet;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use titration::TitrationPoint;
+use vcf::{VariantOverrideMap, VariantOverrides};
 
 /// Represents a genomic variant with its position and alleles
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Variant {
     pub chrom: String,
-    pub pos: u32,
+    pub pos: u64,
     pub ref_allele: String,
     pub alt_allele: String,
 }
 
 impl Variant {
-    pub fn new(chrom: String, pos: u32, ref_allele: String, alt_allele: String) -> Self {
+    pub fn new(chrom: String, pos: u64, ref_allele: String, alt_allele: String) -> Self {
         Self {
             chrom,
             pos,
@@ -30,6 +102,239 @@ impl Variant {
             alt_allele,
         }
     }
+
+    /// Whether the record is a monomorphic reference site (ALT `.` or missing)
+    pub fn is_monomorphic(&self) -> bool {
+        self.alt_allele == "." || self.alt_allele.is_empty()
+    }
+}
+
+/// REF/ALT read support attributed to a single amplicon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmpliconSupport {
+    pub amplicon: String,
+    pub ref_reads: u32,
+    pub variant_reads: u32,
+}
+
+/// Read-pair orientation of a read: F1R2 for a forward first mate or a reverse
+/// second mate, F2R1 otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairOrientation {
+    F1R2,
+    F2R1,
+}
+
+/// ALT-supporting reads split by read-pair orientation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrientationCounts {
+    pub f1r2: u32,
+    pub f2r1: u32,
+}
+
+impl OrientationCounts {
+    pub fn add(&mut self, orientation: PairOrientation) {
+        match orientation {
+            PairOrientation::F1R2 => self.f1r2 += 1,
+            PairOrientation::F2R1 => self.f2r1 += 1,
+        }
+    }
+
+    /// Fraction of reads in F1R2 orientation; values near 0 or 1 point to
+    /// orientation-specific artifacts such as OxoG or FFPE damage
+    pub fn bias(&self) -> Option<f64> {
+        let total = self.f1r2 + self.f2r1;
+        (total > 0).then(|| self.f1r2 as f64 / total as f64)
+    }
+}
+
+/// REF and ALT reads split by the strand they map to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StrandCounts {
+    pub ref_forward: u32,
+    pub ref_reverse: u32,
+    pub alt_forward: u32,
+    pub alt_reverse: u32,
+}
+
+impl StrandCounts {
+    /// Phred-scaled p-value of Fisher's exact test (two-sided) for REF and ALT
+    /// reads being spread differently over the strands, as in GATK's FS; None
+    /// without ALT reads
+    pub fn fisher_strand(&self) -> Option<f64> {
+        let ref_reads = self.ref_forward + self.ref_reverse;
+        let alt_reads = self.alt_forward + self.alt_reverse;
+        if alt_reads == 0 {
+            return None;
+        }
+        let forward = self.ref_forward + self.alt_forward;
+        // Tables with the observed margins, indexed by their REF forward reads;
+        // log-probabilities relative to the first, by the hypergeometric recurrence
+        let low = forward.saturating_sub(alt_reads);
+        let high = ref_reads.min(forward);
+        let mut log_probabilities = Vec::with_capacity((high - low + 1) as usize);
+        let mut log_probability = 0.0;
+        for ref_forward in low..=high {
+            log_probabilities.push(log_probability);
+            let k = ref_forward as f64;
+            log_probability += ((ref_reads as f64 - k) * (forward as f64 - k)).ln()
+                - ((k + 1.0) * (alt_reads as f64 - forward as f64 + k + 1.0)).ln();
+        }
+        let observed = log_probabilities[(self.ref_forward - low) as usize];
+        let max = log_probabilities.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let total: f64 = log_probabilities.iter().map(|p| (p - max).exp()).sum();
+        // Tables as or less likely than the observed one, with slack for rounding
+        let extreme: f64 = log_probabilities
+            .iter()
+            .filter(|&&p| p <= observed + 1e-7)
+            .map(|p| (p - max).exp())
+            .sum();
+        let p_value = (extreme / total).min(1.0);
+        Some((-10.0 * p_value.log10()).abs())
+    }
+
+    /// Symmetric odds ratio of the strand counts, as in GATK's SOR: unlike
+    /// `fisher_strand`, it does not grow with depth at balanced sites. Values
+    /// above 3 usually point to a strand-specific artifact; None without ALT reads.
+    pub fn strand_odds_ratio(&self) -> Option<f64> {
+        if self.alt_forward + self.alt_reverse == 0 {
+            return None;
+        }
+        // A pseudocount keeps empty cells finite
+        let [ref_forward, ref_reverse, alt_forward, alt_reverse] =
+            [self.ref_forward, self.ref_reverse, self.alt_forward, self.alt_reverse].map(|reads| reads as f64 + 1.0);
+        let ratio = (ref_forward * alt_reverse) / (ref_reverse * alt_forward);
+        let ref_ratio = ref_forward.min(ref_reverse) / ref_forward.max(ref_reverse);
+        let alt_ratio = alt_forward.min(alt_reverse) / alt_forward.max(alt_reverse);
+        Some((ratio + 1.0 / ratio).ln() + ref_ratio.ln() - alt_ratio.ln())
+    }
+}
+
+/// Detectability classification of a variant, written as `Detectable`,
+/// `Non-detectable`, `No-coverage`, `Monomorphic`, `Ref-confirmed`,
+/// `Ref-unconfirmed`, `Not-assessable:<reason>` or `Failed:<reason>` in TSV and
+/// JSON output
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum DetectabilityCondition {
+    Detectable,
+    NonDetectable,
+    /// No reads cover the site
+    NoCoverage,
+    /// Monomorphic reference site (ALT `.`); the coverage is the depth available
+    /// to confirm the reference allele
+    Monomorphic,
+    /// Reference confirmation: the depth rules out a variant at the assay LoD
+    RefConfirmed,
+    /// Reference confirmation: too few reference reads, or non-reference reads at
+    /// or above the assay LoD
+    RefUnconfirmed,
+    /// Coverage-only run: the depth was measured but reads were not classified, so
+    /// only the minimum detectable VAF is reported
+    CoverageOnly,
+    /// The site cannot be scored (e.g. a symbolic allele)
+    NotAssessable(String),
+    /// Analysis of the site failed
+    Failed(String),
+}
+
+impl DetectabilityCondition {
+    /// Classify a score against a detection threshold
+    pub fn from_score(score: f64, threshold: f64) -> Self {
+        if score >= threshold {
+            DetectabilityCondition::Detectable
+        } else {
+            DetectabilityCondition::NonDetectable
+        }
+    }
+
+    pub fn is_detectable(&self) -> bool {
+        matches!(self, DetectabilityCondition::Detectable)
+    }
+
+    /// Value of the VCF `DET` INFO field
+    pub fn vcf_status(&self) -> &'static str {
+        match self {
+            DetectabilityCondition::Detectable => "Yes",
+            DetectabilityCondition::NonDetectable => "No",
+            DetectabilityCondition::NoCoverage => "NoCoverage",
+            DetectabilityCondition::Monomorphic => "Monomorphic",
+            DetectabilityCondition::RefConfirmed => "REF_CONFIRMED",
+            DetectabilityCondition::RefUnconfirmed => "REF_UNCONFIRMED",
+            DetectabilityCondition::CoverageOnly => "CoverageOnly",
+            DetectabilityCondition::NotAssessable(_) => "NotAssessable",
+            DetectabilityCondition::Failed(_) => "Failed",
+        }
+    }
+
+    /// Parse a VCF `DET` INFO value back into a condition (reasons are not kept)
+    pub fn from_vcf_status(status: &str) -> Option<Self> {
+        match status {
+            "Yes" => Some(DetectabilityCondition::Detectable),
+            "No" => Some(DetectabilityCondition::NonDetectable),
+            "NoCoverage" => Some(DetectabilityCondition::NoCoverage),
+            "Monomorphic" => Some(DetectabilityCondition::Monomorphic),
+            "REF_CONFIRMED" => Some(DetectabilityCondition::RefConfirmed),
+            "REF_UNCONFIRMED" => Some(DetectabilityCondition::RefUnconfirmed),
+            "CoverageOnly" => Some(DetectabilityCondition::CoverageOnly),
+            "NotAssessable" => Some(DetectabilityCondition::NotAssessable(String::new())),
+            "Failed" => Some(DetectabilityCondition::Failed(String::new())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DetectabilityCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetectabilityCondition::Detectable => write!(f, "Detectable"),
+            DetectabilityCondition::NonDetectable => write!(f, "Non-detectable"),
+            DetectabilityCondition::NoCoverage => write!(f, "No-coverage"),
+            DetectabilityCondition::Monomorphic => write!(f, "Monomorphic"),
+            DetectabilityCondition::RefConfirmed => write!(f, "Ref-confirmed"),
+            DetectabilityCondition::RefUnconfirmed => write!(f, "Ref-unconfirmed"),
+            DetectabilityCondition::CoverageOnly => write!(f, "Coverage-only"),
+            DetectabilityCondition::NotAssessable(reason) => write!(f, "Not-assessable:{}", reason),
+            DetectabilityCondition::Failed(reason) => write!(f, "Failed:{}", reason),
+        }
+    }
+}
+
+impl FromStr for DetectabilityCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, reason) = match s.split_once(':') {
+            Some((name, reason)) => (name, reason.to_string()),
+            None => (s, String::new()),
+        };
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "detectable" => Ok(DetectabilityCondition::Detectable),
+            "non-detectable" | "nondetectable" => Ok(DetectabilityCondition::NonDetectable),
+            "no-coverage" | "nocoverage" => Ok(DetectabilityCondition::NoCoverage),
+            "monomorphic" => Ok(DetectabilityCondition::Monomorphic),
+            "ref-confirmed" | "refconfirmed" => Ok(DetectabilityCondition::RefConfirmed),
+            "ref-unconfirmed" | "refunconfirmed" => Ok(DetectabilityCondition::RefUnconfirmed),
+            "coverage-only" | "coverageonly" => Ok(DetectabilityCondition::CoverageOnly),
+            "not-assessable" | "notassessable" => Ok(DetectabilityCondition::NotAssessable(reason)),
+            "failed" => Ok(DetectabilityCondition::Failed(reason)),
+            _ => Err(format!("unknown detectability condition '{}'", s)),
+        }
+    }
+}
+
+impl From<DetectabilityCondition> for String {
+    fn from(condition: DetectabilityCondition) -> Self {
+        condition.to_string()
+    }
+}
+
+impl TryFrom<String> for DetectabilityCondition {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
 }
 
 /// Represents the detectability analysis result for a variant
@@ -37,16 +342,71 @@ impl Variant {
 pub struct DetectabilityResult {
     pub variant: Variant,
     pub detectability_score: f64,
-    pub detectability_condition: String,
+    pub detectability_condition: DetectabilityCondition,
     pub coverage: u32,
     pub variant_reads: u32,
+    /// Reads supporting the reference allele (part of `coverage`)
+    pub ref_reads: u32,
+    /// Reads supporting a third allele, neither REF nor an ALT (not part of `coverage`)
+    pub other_reads: u32,
+    /// Reads carrying a deletion over the site, for SNVs and MNVs (part of `coverage`
+    /// only under `DeletedReadPolicy::Count`)
+    pub deleted_reads: u32,
+    /// Fraction of the reads overlapping the site from fragments shorter than the
+    /// read, whose mates overlap completely (adapter read-through)
+    pub short_fragment_fraction: f64,
+    /// Variant reads recovered from soft-clipped read tails
+    pub alt_softclip_support: u32,
+    /// Variant reads supported by local assembly, when the locus was assembled
+    pub assembly_support: Option<u32>,
+    /// Read support split by amplicon, when an amplicon BED was supplied
+    pub amplicon_support: Vec<AmpliconSupport>,
+    /// Scores at downsampled read fractions, when titration is enabled
+    pub titration: Vec<TitrationPoint>,
+    /// Calibrated probability of detection, when a calibration file was supplied
+    pub detection_probability: Option<f64>,
+    /// Reference reads needed to confirm the reference, for reference-confirmation
+    /// sites; for variants, the depth needed to detect the required-depth VAF
+    pub required_depth: Option<u32>,
+    /// ALT reads by read-pair orientation (unpaired reads are not counted)
+    pub alt_orientation: OrientationCounts,
+    /// REF and ALT reads by mapped strand, for strand bias
+    #[serde(default)]
+    pub strand: StrandCounts,
+    /// Estimated carrier alleles out of the pool's (e.g. `3/40`), for pooled designs
+    pub pool_alleles: Option<String>,
+    /// Power to detect a single-copy allele at this coverage, for pooled designs
+    pub pool_power: Option<f64>,
+    /// Model parameters overridden by the record's `VLOD_*` INFO tags
+    pub overrides: Option<VariantOverrides>,
+    /// Sample the BAM was sequenced from (see `sample::resolve_sample_name`)
+    pub sample: Option<String>,
+    /// Lowest VAF scoring at the detection threshold at this coverage (None when
+    /// no VAF would, e.g. with one read or fewer)
+    pub min_detectable_vaf: Option<f64>,
+    /// The pileup reached the maximum depth at the site and the counts cover only
+    /// part of its reads
+    #[serde(default)]
+    pub depth_capped: bool,
+    /// Reads without base qualities (QUAL `*`) met at the site; on the first ALT of
+    /// a record only, so that sums over results count each read once
+    #[serde(default)]
+    pub missing_quality_reads: u32,
+    /// BAM operations retried after a transient error while reading the variant
+    /// (see `lod::io_retries`)
+    #[serde(default)]
+    pub io_retries: u32,
+    /// HGVS hotspot descriptions the variant was located from (see `hgvs`)
+    pub hgvs: Option<String>,
+    /// ClinVar clinical significance of the allele (see `annotation`)
+    pub clinical_significance: Option<String>,
 }
 
 impl DetectabilityResult {
     pub fn new(
         variant: Variant,
         detectability_score: f64,
-        detectability_condition: String,
+        detectability_condition: DetectabilityCondition,
         coverage: u32,
         variant_reads: u32,
     ) -> Self {
@@ -56,16 +416,57 @@ impl DetectabilityResult {
             detectability_condition,
             coverage,
             variant_reads,
+            ref_reads: 0,
+            other_reads: 0,
+            deleted_reads: 0,
+            short_fragment_fraction: 0.0,
+            alt_softclip_support: 0,
+            assembly_support: None,
+            amplicon_support: Vec::new(),
+            titration: Vec::new(),
+            detection_probability: None,
+            required_depth: None,
+            alt_orientation: OrientationCounts::default(),
+            strand: StrandCounts::default(),
+            pool_alleles: None,
+            pool_power: None,
+            overrides: None,
+            sample: None,
+            min_detectable_vaf: None,
+            depth_capped: false,
+            missing_quality_reads: 0,
+            io_retries: 0,
+            hgvs: None,
+            clinical_significance: None,
         }
     }
 
-    /// Determine detectability condition based on score
-    pub fn condition_from_score(score: f64) -> String {
-        if score >= 2.50 {
-            "Detectable".to_string()
-        } else {
-            "Non-detectable".to_string()
+    /// Pileup VAF of the variant under a VAF definition
+    pub fn vaf(&self, definition: VafDefinition) -> f64 {
+        definition.vaf(self.variant_reads, self.ref_reads, self.coverage)
+    }
+
+    /// Fraction of reads with called bases at the site that support a third allele
+    pub fn other_allele_fraction(&self) -> f64 {
+        bam::other_allele_fraction(self.other_reads, self.coverage)
+    }
+
+    /// Whether all variant reads come from a single amplicon (None without amplicon data)
+    pub fn single_amplicon_support(&self) -> Option<bool> {
+        if self.amplicon_support.is_empty() {
+            return None;
         }
+        let supporting = self
+            .amplicon_support
+            .iter()
+            .filter(|a| a.variant_reads > 0)
+            .count();
+        Some(supporting == 1)
+    }
+
+    /// Determine detectability condition based on score, at the configured threshold
+    pub fn condition_from_score(score: f64, config: &LodConfig) -> DetectabilityCondition {
+        DetectabilityCondition::from_score(score, config.score_threshold)
     }
 }
 
@@ -75,6 +476,133 @@ pub struct LodConfig {
     pub p_tp: f64,  // Probability of true positive
     pub p_fp: f64,  // Probability of false positive
     pub p_se: f64,  // Probability of sequencing error
+    /// Score at or above which a variant is detectable, unless a calibration or a
+    /// `VLOD_THRESHOLD` override gives its own
+    pub score_threshold: f64,
+    /// Assemble reads locally for variants with conflicting pileup evidence
+    pub local_assembly: bool,
+    /// Named amplicons used to split read support by amplicon of origin
+    pub amplicons: Option<Arc<AmpliconSet>>,
+    /// Mask bisulfite conversions (C>T, G>A on the bottom strand) when matching alleles
+    pub bisulfite: bool,
+    /// FFPE mode: raise the error prior of low-VAF C>T/G>A changes (deamination artifacts)
+    pub ffpe: bool,
+    /// Read fractions at which downsampled scores are computed (empty disables titration)
+    pub titration_fractions: Vec<f64>,
+    /// Per-variant-class detection thresholds fitted by `vlod calibrate`
+    pub calibration: Option<Arc<Calibration>>,
+    /// Classify monomorphic sites (ALT `.`) by reference confirmation
+    pub ref_confirmation: Option<RefConfirmation>,
+    /// Expected VAF at which each variant's required depth is reported
+    pub required_depth_vaf: Option<f64>,
+    /// Background noise used as per-substitution-class sequencing error rates
+    pub noise_profile: Option<Arc<NoiseProfile>>,
+    /// Auxiliary-tag conditions every counted read must pass
+    pub read_filters: Vec<ReadFilter>,
+    /// Pooled-sample design; a single-copy allele in the pool counts as detectable
+    pub pool: Option<PoolDesign>,
+    /// Retries of BAM reads failing with transient IO errors
+    pub io_retry: RetryPolicy,
+    /// Most BAM readers open at once; variants are then scheduled in per-chromosome
+    /// chunks that wait for a free reader (None opens one reader per worker)
+    pub max_open_bams: Option<usize>,
+    /// Schedule variants by their read load estimated from the BAM index, spreading
+    /// deep hotspots over their own chunks (see `lod::chunk_by_load`)
+    pub balance_depth: bool,
+    /// Handling of variants on unplaced, random and alt contigs
+    pub contig_policy: ContigPolicy,
+    /// Alt-to-primary contig alignments used by `ContigPolicy::Map`
+    pub alt_contig_map: Option<Arc<AltContigMap>>,
+    /// Parameters overridden per variant by `VLOD_SE`, `VLOD_TP` and
+    /// `VLOD_THRESHOLD` INFO tags of the input VCF
+    pub variant_overrides: Option<Arc<VariantOverrideMap>>,
+    /// Whether the scored VAF divides ALT reads by all allele-assigned reads or by
+    /// REF plus ALT reads
+    pub vaf_definition: VafDefinition,
+    /// Sites whose third-allele read fraction exceeds this are reported
+    /// Not-assessable instead of Detectable (None disables the cap)
+    pub max_other_allele_fraction: Option<f64>,
+    /// Whether reads deleted at an SNV or MNV site count toward its depth
+    pub deleted_reads: DeletedReadPolicy,
+    /// Whether reads from fragments shorter than the read are counted once per
+    /// fragment, excluded or kept
+    pub short_fragments: ShortFragmentPolicy,
+    /// Reads with any SNV or MNV base below this base quality are left out of the
+    /// site's counts; indel sites are not filtered (0 counts every read)
+    pub min_base_quality: u8,
+    /// Reads below this mapping quality are not counted (0 counts every read)
+    pub min_mapping_quality: u8,
+    /// Handling of reads without base qualities (QUAL `*`)
+    pub missing_quality: MissingQualityPolicy,
+    /// Variants at most this many bases apart on a contig are read with a single
+    /// fetch and pileup traversal (0 fetches every variant on its own)
+    pub fetch_merge_distance: u64,
+    /// Measure depth only, without classifying reads into alleles; results carry
+    /// the minimum detectable VAF of each site instead of a score
+    pub coverage_only: bool,
+    /// Reads a pileup column holds; deeper sites are handled by `depth_cap`
+    pub max_pileup_depth: u32,
+    /// Whether sites reaching `max_pileup_depth` are counted by read iteration or
+    /// flagged with partial counts
+    pub depth_cap: DepthCapPolicy,
+    /// Indexed reference FASTA; soft-clipped reads carrying an insertion at their
+    /// end only support it when their bases past the insertion match the
+    /// reference (without it, only the inserted bases are compared)
+    pub reference: Option<PathBuf>,
+}
+
+/// Score at or above which a variant is called detectable unless `--threshold` or a
+/// calibration sets another
+pub const DEFAULT_DETECTION_THRESHOLD: f64 = 2.50;
+
+/// Sequencing error rate used unless `--SE` or an aligner preset sets one
+pub const DEFAULT_SEQUENCING_ERROR_RATE: f64 = 0.0001;
+
+impl LodConfig {
+    /// Detection threshold for a variant: its `VLOD_THRESHOLD` override, else its
+    /// calibrated class threshold when available, else `score_threshold`. In a
+    /// pooled design a threshold that is not overridden is lowered, if needed, to
+    /// the score of a single-copy allele at the expected fraction 1/(2N).
+    pub fn detection_threshold(&self, variant: &Variant) -> f64 {
+        if let Some(threshold) = self.overrides(variant).and_then(|overrides| overrides.threshold) {
+            return threshold;
+        }
+        let threshold = self
+            .calibration
+            .as_ref()
+            .and_then(|calibration| calibration.threshold_for(variant))
+            .unwrap_or(self.score_threshold);
+        match &self.pool {
+            Some(pool) => threshold.min(calculate_variant_lod_score(pool.expected_fraction(), variant, self)),
+            None => threshold,
+        }
+    }
+
+    /// Sequencing error rate for a variant: its `VLOD_SE` override, else its
+    /// substitution class's background noise when a noise profile is configured,
+    /// `p_se` otherwise
+    pub fn error_rate(&self, variant: &Variant) -> f64 {
+        if let Some(p_se) = self.overrides(variant).and_then(|overrides| overrides.p_se) {
+            return p_se;
+        }
+        self.noise_profile
+            .as_ref()
+            .zip(SubstitutionClass::of(variant))
+            .and_then(|(profile, class)| profile.error_rate(class))
+            .unwrap_or(self.p_se)
+    }
+
+    /// True positive rate for a variant: its `VLOD_TP` override, `p_tp` otherwise
+    pub fn true_positive_rate(&self, variant: &Variant) -> f64 {
+        self.overrides(variant)
+            .and_then(|overrides| overrides.p_tp)
+            .unwrap_or(self.p_tp)
+    }
+
+    /// Parameters the input VCF overrides for a variant
+    pub fn overrides(&self, variant: &Variant) -> Option<&VariantOverrides> {
+        self.variant_overrides.as_ref().and_then(|overrides| overrides.get(variant))
+    }
 }
 
 impl Default for LodConfig {
@@ -82,7 +610,37 @@ impl Default for LodConfig {
         Self {
             p_tp: 0.999,
             p_fp: 0.001,
-            p_se: 0.0001,
+            p_se: DEFAULT_SEQUENCING_ERROR_RATE,
+            score_threshold: DEFAULT_DETECTION_THRESHOLD,
+            local_assembly: false,
+            amplicons: None,
+            bisulfite: false,
+            ffpe: false,
+            titration_fractions: Vec::new(),
+            calibration: None,
+            ref_confirmation: None,
+            required_depth_vaf: None,
+            noise_profile: None,
+            read_filters: Vec::new(),
+            pool: None,
+            io_retry: RetryPolicy::default(),
+            max_open_bams: None,
+            balance_depth: false,
+            contig_policy: ContigPolicy::default(),
+            alt_contig_map: None,
+            variant_overrides: None,
+            vaf_definition: VafDefinition::default(),
+            max_other_allele_fraction: None,
+            deleted_reads: DeletedReadPolicy::default(),
+            short_fragments: ShortFragmentPolicy::default(),
+            min_base_quality: 0,
+            min_mapping_quality: 0,
+            missing_quality: MissingQualityPolicy::default(),
+            fetch_merge_distance: DEFAULT_FETCH_MERGE_DISTANCE,
+            coverage_only: false,
+            max_pileup_depth: DEFAULT_MAX_PILEUP_DEPTH,
+            depth_cap: DepthCapPolicy::default(),
+            reference: None,
         }
     }
 }
@@ -98,15 +656,87 @@ pub enum VlodError {
     
     #[error("CSV error: {0}")]
     Csv(#[from] csv::Error),
+
+    #[error("Database error: {0}")]
+    Database(String),
     
     #[error("Invalid variant format: {0}")]
     InvalidVariant(String),
+
+    #[error("Parse error in {file}, line {line}{}: {message}", format_column(.column))]
+    Parse {
+        file: String,
+        line: u64,
+        column: Option<String>,
+        message: String,
+    },
     
     #[error("File not found: {0}")]
     FileNotFound(String),
     
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+
+    /// An error with where it happened (e.g. the variant and BAM being read)
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        source: Box<VlodError>,
+    },
+}
+
+impl VlodError {
+    /// Locate a parse error at a line of a file (variant errors become parse errors)
+    pub fn at_line(self, file: &str, line: u64) -> Self {
+        match self {
+            VlodError::Parse { column, message, .. } => VlodError::Parse {
+                file: file.to_string(),
+                line,
+                column,
+                message,
+            },
+            VlodError::InvalidVariant(message) => VlodError::Parse {
+                file: file.to_string(),
+                line,
+                column: None,
+                message,
+            },
+            other => other,
+        }
+    }
+
+    /// Attach where an error happened, such as the variant and file being read
+    pub fn context(self, context: impl Into<String>) -> Self {
+        VlodError::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The underlying error, beneath any context
+    pub fn root(&self) -> &VlodError {
+        match self {
+            VlodError::Context { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Parse error for a named column, located later with `at_line`
+    pub fn in_column(column: &str, message: String) -> Self {
+        VlodError::Parse {
+            file: String::new(),
+            line: 0,
+            column: Some(column.to_string()),
+            message,
+        }
+    }
+}
+
+fn format_column(column: &Option<String>) -> String {
+    column
+        .as_ref()
+        .map(|column| format!(", column {}", column))
+        .unwrap_or_default()
 }
 
 pub type VlodResult<T> = Result<T, VlodError>;
\ No newline at end of file
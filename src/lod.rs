@@ -1,10 +1,27 @@
 //! LOD (Limit of Detection) calculation and detectability scoring
 
 use crate::{
-    bam::process_variant_chunk, DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+    about::run_about_comment,
+    bam::{process_variant_chunk, AlleleCounts, BamAnalyzer, ReaderLimit, VafDefinition},
+    contig::ContigPolicy,
+    interrupt,
+    observer::{ChunkProgress, NoopObserver, Observer},
+    noise::SubstitutionClass,
+    rollup::required_depth_for_vaf,
+    titration::TitrationPoint,
+    utils::{create_output_file, log_file_descriptor_usage},
+    AmpliconSupport, DetectabilityCondition, DetectabilityResult, LodConfig, Variant, VlodError,
+    VlodResult,
 };
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::io::{BufWriter, Write};
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 
 /// Chunk variants for parallel processing
 pub fn chunkify<T: Clone>(items: Vec<T>, num_chunks: usize) -> Vec<Vec<T>> {
@@ -34,78 +51,371 @@ pub fn chunkify<T: Clone>(items: Vec<T>, num_chunks: usize) -> Vec<Vec<T>> {
     chunks
 }
 
-/// Calculate detectability scores for a list of variants
+/// Chunk variants so that no chunk spans two chromosomes, splitting chromosomes
+/// with more than their share of variants so that up to `num_chunks` workers stay
+/// busy. Input order is kept.
+pub fn chunk_by_chromosome(variants: Vec<Variant>, num_chunks: usize) -> Vec<Vec<Variant>> {
+    let chunk_size = variants.len().div_ceil(num_chunks.max(1)).max(1);
+    let mut chunks: Vec<Vec<Variant>> = Vec::new();
+
+    for variant in variants {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() < chunk_size && chunk[0].chrom == variant.chrom => chunk.push(variant),
+            _ => chunks.push(vec![variant]),
+        }
+    }
+    chunks
+}
+
+/// Variants whose estimated load is more than this multiple of the median load are
+/// hotspots, scheduled apart from the other variants by `chunk_by_load`
+pub const HOTSPOT_LOAD_FOLD: u64 = 20;
+
+/// Chunk variants by their estimated load (`BamAnalyzer::index_load_estimates`) so
+/// that deep hotspots do not leave one worker running long after the others:
+/// hotspots are spread over up to `num_chunks` chunks, heaviest first onto the
+/// lightest chunk, and the other variants are cut into up to `num_chunks`
+/// contiguous chunks of equal load, none spanning two chromosomes. Each chunk comes
+/// with the input indices of its variants, in input order; hotspot chunks come first.
+pub fn chunk_by_load(variants: Vec<Variant>, loads: &[u64], num_chunks: usize) -> Vec<(Vec<usize>, Vec<Variant>)> {
+    let num_chunks = num_chunks.max(1);
+    let mut sorted_loads = loads.to_vec();
+    sorted_loads.sort_unstable();
+    let median = sorted_loads.get(sorted_loads.len() / 2).copied().unwrap_or(0).max(1);
+    let is_hotspot = |index: usize| loads[index] > median.saturating_mul(HOTSPOT_LOAD_FOLD);
+
+    // Hotspots, heaviest first, each onto the chunk with the least load so far
+    let mut hotspots: Vec<usize> = (0..variants.len()).filter(|&index| is_hotspot(index)).collect();
+    hotspots.sort_by_key(|&index| std::cmp::Reverse(loads[index]));
+    let mut hotspot_chunks: Vec<(u64, Vec<usize>)> = vec![(0, Vec::new()); num_chunks.min(hotspots.len())];
+    for index in hotspots {
+        if let Some(chunk) = hotspot_chunks.iter_mut().min_by_key(|(load, _)| *load) {
+            chunk.0 += loads[index];
+            chunk.1.push(index);
+        }
+    }
+
+    // The other variants in contiguous chunks of about equal load
+    let regular: Vec<usize> = (0..variants.len()).filter(|&index| !is_hotspot(index)).collect();
+    let total: u64 = regular.iter().map(|&index| loads[index]).sum();
+    let target = total.div_ceil(num_chunks as u64).max(1);
+    let mut regular_chunks: Vec<Vec<usize>> = Vec::new();
+    let mut chunk_load = 0;
+    for index in regular {
+        let split = match regular_chunks.last() {
+            Some(chunk) => chunk_load >= target || variants[chunk[0]].chrom != variants[index].chrom,
+            None => true,
+        };
+        if split {
+            regular_chunks.push(Vec::new());
+            chunk_load = 0;
+        }
+        chunk_load += loads[index];
+        if let Some(chunk) = regular_chunks.last_mut() {
+            chunk.push(index);
+        }
+    }
+
+    let mut variants: Vec<Option<Variant>> = variants.into_iter().map(Some).collect();
+    hotspot_chunks
+        .into_iter()
+        .map(|(_, mut indices)| {
+            indices.sort_unstable();
+            indices
+        })
+        .chain(regular_chunks)
+        .map(|indices| {
+            let chunk = indices.iter().filter_map(|&index| variants[index].take()).collect();
+            (indices, chunk)
+        })
+        .collect()
+}
+
+/// Calculate detectability scores for a list of variants. Once SIGINT/SIGTERM is
+/// received (with `interrupt::install_signal_handlers`) scoring stops early and
+/// the results completed so far are returned.
 pub fn calculate_detectability_scores(
     variants: Vec<Variant>,
     bam_path: &Path,
     config: &LodConfig,
     num_processes: usize,
+) -> VlodResult<Vec<DetectabilityResult>> {
+    calculate_detectability_scores_until(variants, bam_path, config, num_processes, interrupt::flag())
+}
+
+/// Calculate detectability scores, stopping early once `stop` is set
+pub fn calculate_detectability_scores_until(
+    variants: Vec<Variant>,
+    bam_path: &Path,
+    config: &LodConfig,
+    num_processes: usize,
+    stop: &AtomicBool,
+) -> VlodResult<Vec<DetectabilityResult>> {
+    calculate_detectability_scores_observed(variants, bam_path, config, num_processes, stop, &NoopObserver)
+}
+
+/// Calculate detectability scores, reporting progress to `observer` and stopping
+/// early once `stop` is set or the observer cancels
+pub fn calculate_detectability_scores_observed(
+    variants: Vec<Variant>,
+    bam_path: &Path,
+    config: &LodConfig,
+    num_processes: usize,
+    stop: &AtomicBool,
+    observer: &dyn Observer,
 ) -> VlodResult<Vec<DetectabilityResult>> {
     if variants.is_empty() {
         return Ok(Vec::new());
     }
 
     let num_processes = std::cmp::min(num_processes, variants.len());
-    let chunks = chunkify(variants, num_processes);
+    // Input indices of the variants of each chunk, when chunks are balanced by load
+    let (chunks, chunk_indices) = if config.balance_depth {
+        let positions: Vec<(&str, u64)> =
+            variants.iter().map(|variant| (variant.chrom.as_str(), variant.pos)).collect();
+        let loads = BamAnalyzer::new(bam_path)?.index_load_estimates(&positions)?;
+        let balanced = chunk_by_load(variants, &loads, num_processes);
+        let (indices, chunks): (Vec<_>, Vec<_>) = balanced.into_iter().unzip();
+        log::info!("Scheduling {} variants in {} load-balanced chunks", loads.len(), chunks.len());
+        (chunks, Some(indices))
+    } else {
+        let chunks = match config.max_open_bams {
+            Some(_) => chunk_by_chromosome(variants, num_processes),
+            None => chunkify(variants, num_processes),
+        };
+        (chunks, None)
+    };
 
-    // Process chunks in parallel
+    // Process chunks in parallel, each holding one of the allowed open readers
+    let reader_limit = ReaderLimit::new(config.max_open_bams);
+    let chunks_total = chunks.len();
+    let chunks_done = AtomicUsize::new(0);
     let chunk_results: Result<Vec<Vec<_>>, VlodError> = chunks
         .into_par_iter()
-        .map(|chunk| process_variant_chunk(&chunk, bam_path, config))
+        .map(|chunk| {
+            let _permit = reader_limit.acquire();
+            let results = process_variant_chunk(&chunk, bam_path, config, stop, observer)?;
+            observer.on_chunk_done(ChunkProgress {
+                variants: chunk.len(),
+                chunks_done: chunks_done.fetch_add(1, Ordering::Relaxed) + 1,
+                chunks_total,
+            });
+            Ok(results)
+        })
         .collect();
 
+    log::info!(
+        "Peak concurrently open BAM readers: {}{}",
+        reader_limit.peak(),
+        config.max_open_bams.map(|max| format!(" (limit {})", max)).unwrap_or_default()
+    );
+    log_file_descriptor_usage("after scoring");
+
     let chunk_results = chunk_results?;
     
-    // Flatten results
-    let mut results: Vec<(Variant, f64, u32, u32)> = Vec::new();
-    for chunk_result in chunk_results {
-        results.extend(chunk_result);
-    }
+    // Flatten results, back in input order when chunks were balanced by load
+    let detectability_results: Vec<DetectabilityResult> = match chunk_indices {
+        Some(chunk_indices) => {
+            let mut indexed: Vec<(usize, DetectabilityResult)> = chunk_indices
+                .into_iter()
+                .zip(chunk_results)
+                .flat_map(|(indices, chunk_result)| indices.into_iter().zip(chunk_result))
+                .collect();
+            indexed.sort_by_key(|(index, _)| *index);
+            indexed.into_iter().map(|(_, result)| result).collect()
+        }
+        None => chunk_results.into_iter().flatten().collect(),
+    };
 
-    if results.is_empty() {
-        return Ok(Vec::new());
+    let not_assessable = detectability_results
+        .iter()
+        .filter(|result| matches!(result.detectability_condition, DetectabilityCondition::NotAssessable(_)))
+        .count();
+    if not_assessable > 0 {
+        log::warn!(
+            "{} variants were not assessable (contig policy '{}')",
+            not_assessable,
+            config.contig_policy
+        );
     }
 
-    // Calculate normalization factors (currently unused but kept for potential future use)
-    let _max_coverage = results.iter().map(|(_, _, coverage, _)| *coverage).max().unwrap_or(1);
-    let _max_variant_reads = results.iter().map(|(_, _, _, reads)| *reads).max().unwrap_or(1);
-
-    // Convert to DetectabilityResult
-    let detectability_results: Vec<DetectabilityResult> = results
-        .into_iter()
-        .map(|(variant, lod, coverage, variant_reads)| {
-            let detectability_score = if lod == f64::NEG_INFINITY || coverage <= 1 {
-                0.0
-            } else {
-                lod
-            };
+    Ok(detectability_results)
+}
 
-            let detectability_condition = if detectability_score >= 2.50 {
-                "Detectable".to_string()
-            } else {
-                "Non-detectable".to_string()
-            };
+/// Detectability result of one ALT allele of a variant from its LOD and allele
+/// counts
+pub(crate) fn detectability_result(
+    variant: Variant,
+    lod: f64,
+    counts: AlleleCounts,
+    config: &LodConfig,
+) -> DetectabilityResult {
+    let coverage = counts.total_count;
+    let variant_reads = counts.get_alt_count(&variant.alt_allele);
+    let alt_softclip_support = counts.get_alt_softclip_support(&variant.alt_allele);
+    let assembly_support = counts.assembly_support.get(&variant.alt_allele).copied();
+    let alt_orientation = counts.get_alt_orientation(&variant.alt_allele);
+    let strand = counts.get_strand_counts(&variant.alt_allele);
+    let amplicon_support = counts
+        .amplicon_counts
+        .iter()
+        .map(|(amplicon, amplicon_counts)| AmpliconSupport {
+            amplicon: amplicon.clone(),
+            ref_reads: amplicon_counts.ref_count,
+            variant_reads: amplicon_counts
+                .alt_counts
+                .get(&variant.alt_allele)
+                .copied()
+                .unwrap_or(0),
+        })
+        .collect();
 
-            DetectabilityResult::new(
-                variant,
-                detectability_score,
-                detectability_condition,
+    let detectability_score = score_from_lod(lod, coverage);
+    let titration = counts
+        .titration
+        .iter()
+        .zip(&config.titration_fractions)
+        .map(|(subsample, &fraction)| {
+            let coverage = subsample.total_count;
+            let vaf = subsample.vaf(&variant.alt_allele, config.vaf_definition);
+            let lod = calculate_variant_lod_score(vaf, &variant, config);
+            TitrationPoint {
+                fraction,
                 coverage,
-                variant_reads,
-            )
+                variant_reads: subsample.get_alt_count(&variant.alt_allele),
+                detectability_score: score_from_lod(lod, coverage),
+            }
         })
         .collect();
 
-    Ok(detectability_results)
+    let detectability_condition = if let Some(reason) = &counts.not_assessable {
+        DetectabilityCondition::NotAssessable(reason.clone())
+    } else if coverage == 0 {
+        DetectabilityCondition::NoCoverage
+    } else if config.coverage_only {
+        DetectabilityCondition::CoverageOnly
+    } else if variant.is_monomorphic() {
+        match &config.ref_confirmation {
+            Some(confirmation) => confirmation.classify(counts.ref_count, counts.site_depth),
+            None => DetectabilityCondition::Monomorphic,
+        }
+    } else {
+        match DetectabilityCondition::from_score(detectability_score, config.detection_threshold(&variant)) {
+            DetectabilityCondition::Detectable => third_allele_noise(&counts, config)
+                .map_or(DetectabilityCondition::Detectable, DetectabilityCondition::NotAssessable),
+            condition => condition,
+        }
+    };
+
+    let required_depth = if variant.is_monomorphic() {
+        config.ref_confirmation.map(|confirmation| confirmation.required_depth())
+    } else {
+        config.required_depth_vaf.and_then(|vaf| required_depth_for_vaf(&variant, config, vaf))
+    };
+
+    // Fewest ALT reads the coverage needs to reach the detection threshold
+    let min_alt_reads = if variant.is_monomorphic() {
+        None
+    } else {
+        min_detectable_alt_reads(coverage, &variant, config, config.detection_threshold(&variant))
+    };
+    let min_detectable_vaf = min_alt_reads.map(|alt_reads| alt_reads as f64 / coverage as f64);
+
+    let (pool_alleles, pool_power) = match &config.pool {
+        Some(pool) if coverage > 0 && !variant.is_monomorphic() => {
+            let power = min_alt_reads.map_or(0.0, |alt_reads| pool.power(coverage, alt_reads));
+            let vaf = counts.get_scoring_vaf(&variant.alt_allele, config.vaf_definition);
+            ((!config.coverage_only).then(|| pool.label(vaf)), Some(power))
+        }
+        _ => (None, None),
+    };
+
+    let detection_probability = config
+        .calibration
+        .as_ref()
+        .filter(|_| !config.coverage_only)
+        .and_then(|calibration| calibration.probability(&variant, detectability_score));
+
+    let mut result = DetectabilityResult::new(
+        variant,
+        detectability_score,
+        detectability_condition,
+        coverage,
+        variant_reads,
+    );
+    result.ref_reads = counts.ref_count;
+    result.other_reads = counts.other_count;
+    result.deleted_reads = counts.deleted_at_site;
+    result.short_fragment_fraction = counts.short_fragment_fraction();
+    result.alt_softclip_support = alt_softclip_support;
+    result.assembly_support = assembly_support;
+    result.amplicon_support = amplicon_support;
+    result.titration = titration;
+    result.detection_probability = detection_probability;
+    result.required_depth = required_depth;
+    result.alt_orientation = alt_orientation;
+    result.strand = strand;
+    result.pool_alleles = pool_alleles;
+    result.pool_power = pool_power;
+    result.overrides = config.overrides(&result.variant).copied();
+    result.min_detectable_vaf = min_detectable_vaf;
+    result.depth_capped = counts.depth_capped;
+    result.missing_quality_reads = counts.missing_quality_reads;
+    result.io_retries = counts.io_retries;
+    result
+}
+
+/// Why a site with more third-allele reads than `max_other_allele_fraction` allows
+/// cannot be called detectable, if it has
+fn third_allele_noise(counts: &AlleleCounts, config: &LodConfig) -> Option<String> {
+    let cap = config.max_other_allele_fraction?;
+    let fraction = counts.other_fraction();
+    (fraction > cap).then(|| format!("third-allele fraction {:.3} above {}", fraction, cap))
+}
+
+/// Detectability score reported for sites without evidence
+pub const NO_EVIDENCE_SCORE: f64 = 0.0;
+
+/// Map a raw LOD to the reported detectability score
+fn score_from_lod(lod: f64, coverage: u32) -> f64 {
+    if lod == f64::NEG_INFINITY || coverage <= 1 {
+        NO_EVIDENCE_SCORE
+    } else {
+        lod
+    }
 }
 
 /// Calculate LOD score for a given VAF and configuration
 pub fn calculate_lod_score(vaf: f64, config: &LodConfig) -> f64 {
+    lod_score(vaf, config.p_tp, config.p_fp, config.p_se)
+}
+
+/// Error prior for C>T/G>A changes at low VAF in FFPE mode
+pub const FFPE_DEAMINATION_ERROR_RATE: f64 = 0.01;
+
+/// VAF below which FFPE mode treats C>T/G>A support as possible deamination
+pub const FFPE_MAX_ARTIFACT_VAF: f64 = 0.1;
+
+/// Calculate the LOD score of a variant, using its overridden parameters, its
+/// class-specific sequencing error rate when a noise profile is configured and,
+/// in FFPE mode, raising the error prior of low-VAF deamination changes
+pub fn calculate_variant_lod_score(vaf: f64, variant: &Variant, config: &LodConfig) -> f64 {
+    let mut p_se = config.error_rate(variant);
+    if config.ffpe
+        && vaf < FFPE_MAX_ARTIFACT_VAF
+        && SubstitutionClass::of(variant).is_some_and(|class| class.is_deamination())
+    {
+        p_se = p_se.max(FFPE_DEAMINATION_ERROR_RATE);
+    }
+    lod_score(vaf, config.true_positive_rate(variant), config.p_fp, p_se)
+}
+
+fn lod_score(vaf: f64, p_tp: f64, p_fp: f64, p_se: f64) -> f64 {
     if vaf <= 0.0 {
         return f64::NEG_INFINITY;
     }
 
-    let lod_value = (config.p_tp * vaf) / ((1.0 - vaf) * config.p_se + vaf * config.p_fp);
+    let lod_value = (p_tp * vaf) / ((1.0 - vaf) * p_se + vaf * p_fp);
     
     if lod_value > 0.0 {
         lod_value.log10()
@@ -114,13 +424,37 @@ pub fn calculate_lod_score(vaf: f64, config: &LodConfig) -> f64 {
     }
 }
 
-/// Calculate detectability condition based on score
-pub fn calculate_detectability_condition(score: f64) -> String {
-    if score >= 2.50 {
-        "Detectable".to_string()
-    } else {
-        "Non-detectable".to_string()
+/// Fewest ALT reads out of `depth` whose VAF scores at or above `threshold` (None
+/// when even an all-ALT site would not)
+pub fn min_detectable_alt_reads(depth: u32, variant: &Variant, config: &LodConfig, threshold: f64) -> Option<u32> {
+    min_alt_reads_reaching(depth, threshold, |vaf| calculate_variant_lod_score(vaf, variant, config))
+}
+
+/// Fewest ALT reads out of `depth` for which `lod(vaf)` reaches `threshold`, for a
+/// LOD increasing with the VAF
+pub fn min_alt_reads_reaching(depth: u32, threshold: f64, lod: impl Fn(f64) -> f64) -> Option<u32> {
+    let reaches = |alt_reads: u32| lod(alt_reads as f64 / depth as f64) >= threshold;
+    // Scores at depth 1 count as no evidence
+    if depth <= 1 || !reaches(depth) {
+        return None;
+    }
+
+    // `low` never reaches the threshold, `high` always does
+    let (mut low, mut high) = (0, depth);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if reaches(mid) {
+            high = mid;
+        } else {
+            low = mid;
+        }
     }
+    Some(high)
+}
+
+/// Calculate detectability condition based on score, at the configured threshold
+pub fn calculate_detectability_condition(score: f64, config: &LodConfig) -> DetectabilityCondition {
+    DetectabilityCondition::from_score(score, config.score_threshold)
 }
 
 /// Validate LOD configuration parameters
@@ -143,60 +477,706 @@ pub fn validate_lod_config(config: &LodConfig) -> VlodResult<()> {
         ));
     }
 
+    if !config.score_threshold.is_finite() {
+        return Err(VlodError::InvalidConfig(format!(
+            "score threshold must be a finite number, got {}",
+            config.score_threshold
+        )));
+    }
+
     if config.p_tp <= config.p_fp {
         return Err(VlodError::InvalidConfig(
             "p_tp must be greater than p_fp".to_string(),
         ));
     }
 
+    if let Some(confirmation) = &config.ref_confirmation {
+        confirmation.validate()?;
+    }
+
+    if let Some(pool) = &config.pool {
+        pool.validate()?;
+    }
+
+    if let Some(vaf) = config.required_depth_vaf {
+        if !(vaf > 0.0 && vaf <= 1.0) {
+            return Err(VlodError::InvalidConfig(
+                "the required-depth VAF must be between 0 and 1".to_string(),
+            ));
+        }
+    }
+
+    if let Some(cap) = config.max_other_allele_fraction {
+        if !(0.0..=1.0).contains(&cap) {
+            return Err(VlodError::InvalidConfig(
+                "the third-allele fraction cap must be between 0 and 1".to_string(),
+            ));
+        }
+    }
+
+    if config.max_pileup_depth == 0 {
+        return Err(VlodError::InvalidConfig(
+            "the maximum pileup depth must be at least 1".to_string(),
+        ));
+    }
+
+    if config.max_open_bams == Some(0) {
+        return Err(VlodError::InvalidConfig(
+            "at least one BAM reader must be allowed to open".to_string(),
+        ));
+    }
+
+    if config.contig_policy == ContigPolicy::Map && config.alt_contig_map.is_none() {
+        return Err(VlodError::InvalidConfig(
+            "the map contig policy needs an alt-to-primary contig alignment".to_string(),
+        ));
+    }
+
+    if config.local_assembly && !cfg!(feature = "assembly") {
+        return Err(VlodError::InvalidConfig(
+            "local assembly requires vlod-rs to be built with the `assembly` feature".to_string(),
+        ));
+    }
+
     Ok(())
 }
 
-/// Write detectability results to a TSV file
+/// Detectable counts for PASS and non-PASS input variants, as `(detectable, total)`
+pub fn filter_stratified_counts(
+    results: &[DetectabilityResult],
+    non_pass: &HashSet<Variant>,
+) -> ((usize, usize), (usize, usize)) {
+    let mut pass = (0, 0);
+    let mut failed = (0, 0);
+    for result in results {
+        let stratum = if non_pass.contains(&result.variant) { &mut failed } else { &mut pass };
+        stratum.1 += 1;
+        if result.detectability_condition.is_detectable() {
+            stratum.0 += 1;
+        }
+    }
+    (pass, failed)
+}
+
+/// Log detectability separately for PASS and non-PASS input variants, since
+/// mixing them skews assay QC metrics
+pub fn log_filter_stratified_summary(results: &[DetectabilityResult], non_pass: &HashSet<Variant>) {
+    let (pass, failed) = filter_stratified_counts(results, non_pass);
+    for (label, (detectable, total)) in [("PASS", pass), ("Non-PASS", failed)] {
+        if total > 0 {
+            log::info!(
+                "  {} variants detectable: {} of {} ({:.1}%)",
+                label,
+                detectable,
+                total,
+                (detectable as f64 / total as f64) * 100.0
+            );
+        }
+    }
+}
+
+/// Format per-amplicon support as `name:ref/alt` pairs separated by semicolons
+pub fn format_amplicon_support(support: &[AmpliconSupport]) -> String {
+    if support.is_empty() {
+        return ".".to_string();
+    }
+    support
+        .iter()
+        .map(|a| format!("{}:{}/{}", a.amplicon, a.ref_reads, a.variant_reads))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// BAM operations retried after a transient error while scoring `results`. Each
+/// fetch group's retries are carried by its first variant, so the sum covers the
+/// run without counting the retries of other runs in the process.
+pub fn io_retries(results: &[DetectabilityResult]) -> u64 {
+    results.iter().map(|result| u64::from(result.io_retries)).sum()
+}
+
+/// Site-level result of the ALT alleles of one multi-allelic site: the ALTs joined
+/// with commas (as in the VCF record), Detectable if any ALT is, with the highest
+/// score and the ALT reads of all alleles combined
+pub fn site_aggregate(alleles: &[&DetectabilityResult]) -> DetectabilityResult {
+    let first = alleles[0];
+    let best = alleles
+        .iter()
+        .copied()
+        .filter(|result| !result.detectability_score.is_nan())
+        .max_by(|a, b| a.detectability_score.total_cmp(&b.detectability_score))
+        .unwrap_or(first);
+    let condition = if alleles.iter().any(|result| result.detectability_condition == DetectabilityCondition::Detectable) {
+        DetectabilityCondition::Detectable
+    } else {
+        best.detectability_condition.clone()
+    };
+    let variant = Variant::new(
+        first.variant.chrom.clone(),
+        first.variant.pos,
+        first.variant.ref_allele.clone(),
+        alleles.iter().map(|result| result.variant.alt_allele.as_str()).collect::<Vec<_>>().join(","),
+    );
+    let variant_reads: u32 = alleles.iter().map(|result| result.variant_reads).sum();
+    let mut site = DetectabilityResult::new(
+        variant,
+        best.detectability_score,
+        condition,
+        alleles.iter().map(|result| result.coverage).max().unwrap_or(0),
+        variant_reads,
+    );
+    site.ref_reads = first.ref_reads;
+    // An allele's other reads include the reads of the remaining ALTs
+    site.other_reads = first.other_reads.saturating_sub(variant_reads - first.variant_reads);
+    site.deleted_reads = alleles.iter().map(|result| result.deleted_reads).max().unwrap_or(0);
+    site.short_fragment_fraction = first.short_fragment_fraction;
+    site.alt_softclip_support = alleles.iter().map(|result| result.alt_softclip_support).sum();
+    site.detection_probability = alleles.iter().filter_map(|result| result.detection_probability).reduce(f64::max);
+    site.min_detectable_vaf = alleles.iter().filter_map(|result| result.min_detectable_vaf).reduce(f64::min);
+    site.depth_capped = alleles.iter().any(|result| result.depth_capped);
+    for result in alleles {
+        site.alt_orientation.f1r2 += result.alt_orientation.f1r2;
+        site.alt_orientation.f2r1 += result.alt_orientation.f2r1;
+        site.strand.alt_forward += result.strand.alt_forward;
+        site.strand.alt_reverse += result.strand.alt_reverse;
+    }
+    site.strand.ref_forward = first.strand.ref_forward;
+    site.strand.ref_reverse = first.strand.ref_reverse;
+    site.sample = first.sample.clone();
+    let hgvs: Vec<&str> = alleles.iter().filter_map(|result| result.hgvs.as_deref()).collect();
+    site.hgvs = (!hgvs.is_empty()).then(|| hgvs.join(","));
+    let significance: Vec<&str> =
+        alleles.iter().filter_map(|result| result.clinical_significance.as_deref()).collect();
+    site.clinical_significance = (!significance.is_empty()).then(|| significance.join(","));
+    site
+}
+
+/// Results with a site-level row (see `site_aggregate`) after the last ALT of each
+/// multi-allelic site, i.e. of alleles sharing contig, position and REF
+pub fn with_site_aggregates(results: &[DetectabilityResult]) -> Vec<DetectabilityResult> {
+    let mut sites: HashMap<(&str, u64, &str), Vec<usize>> = HashMap::new();
+    for (index, result) in results.iter().enumerate() {
+        let variant = &result.variant;
+        sites
+            .entry((variant.chrom.as_str(), variant.pos, variant.ref_allele.as_str()))
+            .or_default()
+            .push(index);
+    }
+    let multi_allelic: HashMap<usize, Vec<usize>> = sites
+        .into_values()
+        .filter(|alleles| alleles.len() > 1)
+        .map(|alleles| (alleles[alleles.len() - 1], alleles))
+        .collect();
+
+    let mut with_sites = Vec::with_capacity(results.len() + multi_allelic.len());
+    for (index, result) in results.iter().enumerate() {
+        with_sites.push(result.clone());
+        if let Some(alleles) = multi_allelic.get(&index) {
+            let alleles: Vec<&DetectabilityResult> = alleles.iter().map(|&allele| &results[allele]).collect();
+            with_sites.push(site_aggregate(&alleles));
+        }
+    }
+    with_sites
+}
+
+/// Version of the detectability TSV layout, recorded in its metadata line. Schema 2
+/// added the metadata line; readers locate columns by header name from schema 2 on.
+pub const TSV_SCHEMA_VERSION: u32 = 2;
+
+/// A column of the detectability TSV
+#[derive(Debug)]
+pub struct ResultColumn {
+    /// Name selecting the column with `--columns`
+    pub name: &'static str,
+    pub header: &'static str,
+    format: fn(&DetectabilityResult) -> String,
+}
+
+impl ResultColumn {
+    pub fn format(&self, result: &DetectabilityResult) -> String {
+        (self.format)(result)
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_else(|| ".".to_string())
+}
+
+/// Columns of the detectability TSV, in their default order
+pub static RESULT_COLUMNS: [ResultColumn; 34] = [
+    ResultColumn {
+        name: "chrom",
+        header: "Chrom",
+        format: |result| result.variant.chrom.clone(),
+    },
+    ResultColumn {
+        name: "pos",
+        header: "Pos",
+        format: |result| result.variant.pos.to_string(),
+    },
+    ResultColumn {
+        name: "ref",
+        header: "Ref",
+        format: |result| result.variant.ref_allele.clone(),
+    },
+    ResultColumn {
+        name: "alt",
+        header: "Alt",
+        format: |result| result.variant.alt_allele.clone(),
+    },
+    ResultColumn {
+        name: "detectability_score",
+        header: "Detectability_Score",
+        format: |result| result.detectability_score.to_string(),
+    },
+    ResultColumn {
+        name: "detectability_condition",
+        header: "Detectability_Condition",
+        format: |result| result.detectability_condition.to_string(),
+    },
+    ResultColumn {
+        name: "coverage",
+        header: "Coverage",
+        format: |result| result.coverage.to_string(),
+    },
+    ResultColumn {
+        name: "variant_reads",
+        header: "Variant_Reads",
+        format: |result| result.variant_reads.to_string(),
+    },
+    ResultColumn {
+        name: "softclip_support",
+        header: "Softclip_Support",
+        format: |result| result.alt_softclip_support.to_string(),
+    },
+    ResultColumn {
+        name: "assembly_support",
+        header: "Assembly_Support",
+        format: |result| optional(result.assembly_support),
+    },
+    ResultColumn {
+        name: "amplicon_support",
+        header: "Amplicon_Support",
+        format: |result| format_amplicon_support(&result.amplicon_support),
+    },
+    ResultColumn {
+        name: "single_amplicon_support",
+        header: "Single_Amplicon_Support",
+        format: |result| {
+            match result.single_amplicon_support() {
+                Some(true) => "Yes",
+                Some(false) => "No",
+                None => ".",
+            }
+            .to_string()
+        },
+    },
+    ResultColumn {
+        name: "detection_probability",
+        header: "Detection_Probability",
+        format: |result| optional(result.detection_probability),
+    },
+    ResultColumn {
+        name: "required_depth",
+        header: "Required_Depth",
+        format: |result| optional(result.required_depth),
+    },
+    ResultColumn {
+        name: "alt_f1r2",
+        header: "Alt_F1R2",
+        format: |result| result.alt_orientation.f1r2.to_string(),
+    },
+    ResultColumn {
+        name: "alt_f2r1",
+        header: "Alt_F2R1",
+        format: |result| result.alt_orientation.f2r1.to_string(),
+    },
+    ResultColumn {
+        name: "orientation_bias",
+        header: "Orientation_Bias",
+        format: |result| optional(result.alt_orientation.bias().map(|bias| format!("{:.3}", bias))),
+    },
+    ResultColumn {
+        name: "pool_alleles",
+        header: "Pool_Alleles",
+        format: |result| optional(result.pool_alleles.as_deref()),
+    },
+    ResultColumn {
+        name: "pool_power",
+        header: "Pool_Power",
+        format: |result| optional(result.pool_power.map(|power| format!("{:.4}", power))),
+    },
+    ResultColumn {
+        name: "overrides",
+        header: "Overrides",
+        format: |result| optional(result.overrides),
+    },
+    ResultColumn {
+        name: "vaf_total",
+        header: "VAF_Total",
+        format: |result| format!("{:.4}", result.vaf(VafDefinition::Total)),
+    },
+    ResultColumn {
+        name: "vaf_ref_alt",
+        header: "VAF_Ref_Alt",
+        format: |result| format!("{:.4}", result.vaf(VafDefinition::RefAlt)),
+    },
+    ResultColumn {
+        name: "other_allele_fraction",
+        header: "Other_Allele_Fraction",
+        format: |result| format!("{:.4}", result.other_allele_fraction()),
+    },
+    ResultColumn {
+        name: "deleted_reads",
+        header: "Deleted_Reads",
+        format: |result| result.deleted_reads.to_string(),
+    },
+    ResultColumn {
+        name: "short_fragment_fraction",
+        header: "Short_Fragment_Fraction",
+        format: |result| format!("{:.4}", result.short_fragment_fraction),
+    },
+    ResultColumn {
+        name: "sample",
+        header: "Sample",
+        format: |result| optional(result.sample.as_deref()),
+    },
+    ResultColumn {
+        name: "min_detectable_vaf",
+        header: "Min_Detectable_VAF",
+        format: |result| optional(result.min_detectable_vaf.map(|vaf| format!("{:.4}", vaf))),
+    },
+    ResultColumn {
+        name: "depth_capped",
+        header: "Depth_Capped",
+        format: |result| result.depth_capped.to_string(),
+    },
+    ResultColumn {
+        name: "hgvs",
+        header: "HGVS",
+        format: |result| optional(result.hgvs.as_deref()),
+    },
+    ResultColumn {
+        name: "clinical_significance",
+        header: "Clinical_Significance",
+        format: |result| optional(result.clinical_significance.as_deref()),
+    },
+    ResultColumn {
+        name: "alt_forward",
+        header: "Alt_Forward",
+        format: |result| result.strand.alt_forward.to_string(),
+    },
+    ResultColumn {
+        name: "alt_reverse",
+        header: "Alt_Reverse",
+        format: |result| result.strand.alt_reverse.to_string(),
+    },
+    ResultColumn {
+        name: "fisher_strand",
+        header: "Fisher_Strand",
+        format: |result| optional(result.strand.fisher_strand().map(|fs| format!("{:.3}", fs))),
+    },
+    ResultColumn {
+        name: "strand_odds_ratio",
+        header: "Strand_Odds_Ratio",
+        format: |result| optional(result.strand.strand_odds_ratio().map(|sor| format!("{:.3}", sor))),
+    },
+];
+
+/// Short names accepted by `--columns` for the columns LIMS schemas usually want;
+/// `strand_bias` is the ALT F1R2 fraction (read-pair orientation), not the mapped
+/// strand bias of `fisher_strand` and `strand_odds_ratio`
+const RESULT_COLUMN_ALIASES: [(&str, &str); 4] = [
+    ("score", "detectability_score"),
+    ("condition", "detectability_condition"),
+    ("vaf", "vaf_total"),
+    ("strand_bias", "orientation_bias"),
+];
+
+/// Look up a TSV column by name or alias (case-insensitive)
+pub fn result_column(name: &str) -> Option<&'static ResultColumn> {
+    let name = name.to_ascii_lowercase();
+    let name = RESULT_COLUMN_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name.as_str(), |(_, column)| *column);
+    RESULT_COLUMNS.iter().find(|column| column.name == name)
+}
+
+/// Columns written to the detectability TSV, in order (all of them by default)
+#[derive(Debug, Clone)]
+pub struct ColumnSelection(Vec<&'static ResultColumn>);
+
+impl ColumnSelection {
+    pub fn columns(&self) -> &[&'static ResultColumn] {
+        &self.0
+    }
+}
+
+impl Default for ColumnSelection {
+    fn default() -> Self {
+        ColumnSelection(RESULT_COLUMNS.iter().collect())
+    }
+}
+
+impl FromStr for ColumnSelection {
+    type Err = String;
+
+    /// Parse a comma-separated list of column names, e.g. `chrom,pos,ref,alt,score`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns: Vec<&'static ResultColumn> = Vec::new();
+        for name in s.split(',').map(str::trim) {
+            let column = result_column(name).ok_or_else(|| {
+                let names: Vec<&str> = RESULT_COLUMNS.iter().map(|column| column.name).collect();
+                format!("unknown column '{}' (expected {})", name, names.join(", "))
+            })?;
+            if columns.iter().any(|selected| selected.name == column.name) {
+                return Err(format!("column '{}' is selected twice", name));
+            }
+            columns.push(column);
+        }
+        Ok(ColumnSelection(columns))
+    }
+}
+
+/// Write detectability results of a run under `config` to a TSV file (gzipped for
+/// a `.gz` extension)
 pub fn write_detectability_results(
     results: &[DetectabilityResult],
     output_path: &Path,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    write_results_file(results, output_path, &ColumnSelection::default(), ResultsLayout::default(), false, config)
+}
+
+/// Write the results of an interrupted run, marked `#partial=true` in the metadata
+/// line; variants that were not reached are missing
+pub fn write_partial_detectability_results(
+    results: &[DetectabilityResult],
+    output_path: &Path,
+    config: &LodConfig,
 ) -> VlodResult<()> {
-    use flate2::write::GzEncoder;
-    use flate2::Compression;
-    use std::fs::File;
-    use std::io::Write;
-
-    let file = File::create(output_path)?;
-    let mut writer: Box<dyn Write> = if output_path.extension().and_then(|s| s.to_str()) == Some("gz") {
-        Box::new(GzEncoder::new(file, Compression::default()))
+    write_results_file(results, output_path, &ColumnSelection::default(), ResultsLayout::default(), true, config)
+}
+
+/// Shape of the per-amplicon read support in the detectability TSV
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultsLayout {
+    /// One row per variant, support packed into `Amplicon_Support` as `name:ref/alt`
+    #[default]
+    Packed,
+    /// One row per variant and amplicon, with `Amplicon`, `Amplicon_Ref_Reads` and
+    /// `Amplicon_Variant_Reads` columns
+    Long,
+    /// One row per variant, with `Amplicon_Ref_Reads.<amplicon>` and
+    /// `Amplicon_Variant_Reads.<amplicon>` columns for every amplicon
+    Wide,
+}
+
+impl FromStr for ResultsLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "packed" => Ok(ResultsLayout::Packed),
+            "long" => Ok(ResultsLayout::Long),
+            "wide" => Ok(ResultsLayout::Wide),
+            _ => Err(format!("unknown layout '{}' (expected packed, long or wide)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ResultsLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResultsLayout::Packed => "packed",
+            ResultsLayout::Long => "long",
+            ResultsLayout::Wide => "wide",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Column expanded by the long and wide layouts
+const AMPLICON_SUPPORT_COLUMN: &str = "amplicon_support";
+
+/// Per-amplicon column headers of the long layout; the wide layout suffixes the
+/// read counts with `.<amplicon>`
+const AMPLICON_HEADER: &str = "Amplicon";
+const AMPLICON_REF_READS_HEADER: &str = "Amplicon_Ref_Reads";
+const AMPLICON_VARIANT_READS_HEADER: &str = "Amplicon_Variant_Reads";
+
+/// Write the selected columns of detectability results to a TSV file in a layout,
+/// marked `#partial=true` for an interrupted run
+pub fn write_detectability_columns(
+    results: &[DetectabilityResult],
+    output_path: &Path,
+    columns: &ColumnSelection,
+    layout: ResultsLayout,
+    partial: bool,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    write_results_file(results, output_path, columns, layout, partial, config)
+}
+
+fn write_results_file(
+    results: &[DetectabilityResult],
+    output_path: &Path,
+    columns: &ColumnSelection,
+    layout: ResultsLayout,
+    partial: bool,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    let file = BufWriter::new(create_output_file(output_path)?);
+    if output_path.extension().and_then(|s| s.to_str()) == Some("gz") {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_results(results, &mut encoder, columns, layout, partial, config)?;
+        encoder.finish()?.flush()?;
+        Ok(())
     } else {
-        Box::new(file)
+        write_results(results, file, columns, layout, partial, config)
+    }
+}
+
+/// Write detectability results of a run under `config` as uncompressed TSV to `writer`
+pub fn write_detectability_results_to_writer<W: Write>(
+    results: &[DetectabilityResult],
+    writer: W,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    write_results(results, writer, &ColumnSelection::default(), ResultsLayout::default(), false, config)
+}
+
+fn write_results<W: Write>(
+    results: &[DetectabilityResult],
+    mut writer: W,
+    columns: &ColumnSelection,
+    layout: ResultsLayout,
+    partial: bool,
+    config: &LodConfig,
+) -> VlodResult<()> {
+    let expanded = |column: &ResultColumn| layout != ResultsLayout::Packed && column.name == AMPLICON_SUPPORT_COLUMN;
+    let expand = columns.columns().iter().any(|column| expanded(column));
+    let wide_amplicons: Vec<&str> = match layout {
+        ResultsLayout::Wide => results
+            .iter()
+            .flat_map(|result| result.amplicon_support.iter().map(|support| support.amplicon.as_str()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
     };
 
-    // Write header
+    // Write metadata and header
     writeln!(
         writer,
-        "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\tVariant_Reads"
+        "#vlod_version={} #schema={}{} {}",
+        env!("CARGO_PKG_VERSION"),
+        TSV_SCHEMA_VERSION,
+        if partial { " #partial=true" } else { "" },
+        run_about_comment(config)
     )?;
+    let mut headers = Vec::new();
+    for column in columns.columns() {
+        if !expanded(column) {
+            headers.push(column.header.to_string());
+        } else if layout == ResultsLayout::Long {
+            headers.extend(
+                [AMPLICON_HEADER, AMPLICON_REF_READS_HEADER, AMPLICON_VARIANT_READS_HEADER].map(String::from),
+            );
+        } else {
+            for amplicon in &wide_amplicons {
+                headers.push(format!("{}.{}", AMPLICON_REF_READS_HEADER, amplicon));
+                headers.push(format!("{}.{}", AMPLICON_VARIANT_READS_HEADER, amplicon));
+            }
+        }
+    }
+    writeln!(writer, "{}", headers.join("\t"))?;
 
-    // Write results
+    // Write results, one row per amplicon in the long layout
+    let missing = || ".".to_string();
     for result in results {
-        writeln!(
-            writer,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            result.variant.chrom,
-            result.variant.pos,
-            result.variant.ref_allele,
-            result.variant.alt_allele,
-            result.detectability_score,
-            result.detectability_condition,
-            result.coverage,
-            result.variant_reads,
-        )?;
+        let rows: Vec<Option<&AmpliconSupport>> =
+            if expand && layout == ResultsLayout::Long && !result.amplicon_support.is_empty() {
+                result.amplicon_support.iter().map(Some).collect()
+            } else {
+                vec![None]
+            };
+        for row in rows {
+            let mut fields = Vec::new();
+            for column in columns.columns() {
+                if !expanded(column) {
+                    fields.push(column.format(result));
+                } else if layout == ResultsLayout::Long {
+                    match row {
+                        Some(support) => fields.extend([
+                            support.amplicon.clone(),
+                            support.ref_reads.to_string(),
+                            support.variant_reads.to_string(),
+                        ]),
+                        None => fields.extend([missing(), missing(), missing()]),
+                    }
+                } else {
+                    for amplicon in &wide_amplicons {
+                        match result.amplicon_support.iter().find(|support| support.amplicon == *amplicon) {
+                            Some(support) => {
+                                fields.extend([support.ref_reads.to_string(), support.variant_reads.to_string()])
+                            }
+                            None => fields.extend([missing(), missing()]),
+                        }
+                    }
+                }
+            }
+            writeln!(writer, "{}", fields.join("\t"))?;
+        }
     }
 
+    writer.flush()?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pool::PoolDesign;
+    use crate::vcf::VariantOverrides;
+    use crate::StrandCounts;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_with_site_aggregates() {
+        let result = |pos: u64, alt: &str, score: f64, condition: DetectabilityCondition, variant_reads: u32| {
+            let variant = Variant::new("chr1".to_string(), pos, "A".to_string(), alt.to_string());
+            let mut result = DetectabilityResult::new(variant, score, condition, 100, variant_reads);
+            result.ref_reads = 80;
+            result.other_reads = 20 - variant_reads + 1;
+            result.alt_orientation.f1r2 = variant_reads;
+            result.strand =
+                StrandCounts { ref_forward: 40, ref_reverse: 40, alt_forward: variant_reads, alt_reverse: 0 };
+            result
+        };
+        let results = vec![
+            result(100, "G", 1.5, DetectabilityCondition::NonDetectable, 4),
+            result(100, "T", 4.0, DetectabilityCondition::Detectable, 15),
+            result(200, "C", 0.5, DetectabilityCondition::NonDetectable, 1),
+        ];
+
+        let with_sites = with_site_aggregates(&results);
+        assert_eq!(with_sites.len(), 4);
+        let site = &with_sites[2];
+        assert_eq!(site.variant.alt_allele, "G,T");
+        assert_eq!(site.detectability_condition, DetectabilityCondition::Detectable);
+        assert_eq!(site.detectability_score, 4.0);
+        assert_eq!((site.coverage, site.variant_reads, site.ref_reads), (100, 19, 80));
+        assert_eq!(site.other_reads, 2);
+        assert_eq!(site.alt_orientation.f1r2, 19);
+        assert_eq!(site.strand, StrandCounts { ref_forward: 40, ref_reverse: 40, alt_forward: 19, alt_reverse: 0 });
+        assert_eq!(with_sites[3].variant.pos, 200);
+
+        // Without a Detectable ALT the site takes the condition of its best allele
+        let site = site_aggregate(&[&results[0], &results[2]]);
+        assert_eq!((site.detectability_condition, site.detectability_score), (DetectabilityCondition::NonDetectable, 1.5));
+        assert_eq!(with_site_aggregates(&results[2..]).len(), 1);
+    }
 
     #[test]
     fn test_chunkify() {
@@ -221,6 +1201,49 @@ mod tests {
         assert!(chunks[0].is_empty());
     }
 
+    #[test]
+    fn test_chunk_by_chromosome() {
+        let variant = |chrom: &str, pos| Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        let variants = vec![
+            variant("chr1", 100),
+            variant("chr1", 200),
+            variant("chr1", 300),
+            variant("chr2", 100),
+            variant("chr3", 100),
+            variant("chr3", 200),
+        ];
+        let chunks = chunk_by_chromosome(variants.clone(), 2);
+
+        // chr1 is split at the per-worker share of 3 variants; no chunk spans contigs
+        let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(sizes, vec![3, 1, 2]);
+        assert!(chunks.iter().all(|chunk| chunk.iter().all(|v| v.chrom == chunk[0].chrom)));
+        assert_eq!(chunks.concat(), variants);
+        assert_eq!(chunk_by_chromosome(variants, 6).len(), 6);
+        assert!(chunk_by_chromosome(Vec::new(), 4).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_by_load() {
+        let variant = |chrom: &str, pos| Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        let variants: Vec<Variant> = (1..=8).map(|pos| variant("chr1", pos)).chain([variant("chr2", 1)]).collect();
+        // Two hotspots far above the median load of 10
+        let loads = [10, 10, 5000, 10, 10, 9000, 10, 10, 10];
+        let chunks = chunk_by_load(variants.clone(), &loads, 2);
+
+        let indices: Vec<Vec<usize>> = chunks.iter().map(|(indices, _)| indices.clone()).collect();
+        assert_eq!(indices, vec![vec![5], vec![2], vec![0, 1, 3, 4], vec![6, 7], vec![8]]);
+        for (indices, chunk) in &chunks {
+            let expected: Vec<Variant> = indices.iter().map(|&index| variants[index].clone()).collect();
+            assert_eq!(chunk, &expected);
+        }
+
+        // Uniform loads leave no hotspots
+        let chunks = chunk_by_load(variants.clone(), &[1; 9], 3);
+        assert_eq!(chunks.iter().map(|(indices, _)| indices.len()).sum::<usize>(), 9);
+        assert!(chunks.iter().all(|(_, chunk)| chunk.iter().all(|v| v.chrom == chunk[0].chrom)));
+    }
+
     #[test]
     fn test_calculate_lod_score() {
         let config = LodConfig::default();
@@ -239,13 +1262,249 @@ mod tests {
         assert_eq!(score, f64::NEG_INFINITY);
     }
 
+    #[test]
+    fn test_ffpe_deamination_prior() {
+        let config = LodConfig {
+            ffpe: true,
+            ..LodConfig::default()
+        };
+        let c_to_t = Variant::new("chr1".to_string(), 100, "C".to_string(), "T".to_string());
+        let a_to_g = Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string());
+
+        // Low-VAF C>T support is discounted; other changes and high VAFs are not
+        assert!(calculate_variant_lod_score(0.02, &c_to_t, &config) < calculate_lod_score(0.02, &config));
+        assert_eq!(calculate_variant_lod_score(0.02, &a_to_g, &config), calculate_lod_score(0.02, &config));
+        assert_eq!(calculate_variant_lod_score(0.3, &c_to_t, &config), calculate_lod_score(0.3, &config));
+    }
+
+    #[test]
+    fn test_pool_detection_threshold() {
+        let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string());
+        let config = LodConfig::default();
+        assert_eq!(min_detectable_alt_reads(1000, &variant, &config, 2.5), Some(45));
+        assert_eq!(min_detectable_alt_reads(1, &variant, &config, 2.5), None);
+
+        // One carrier in 50 samples is at VAF 0.01, below the default threshold's
+        let pooled = LodConfig {
+            pool: Some(PoolDesign { size: 50 }),
+            ..LodConfig::default()
+        };
+        let threshold = pooled.detection_threshold(&variant);
+        assert!((threshold - calculate_lod_score(0.01, &pooled)).abs() < 1e-12);
+        assert_eq!(min_detectable_alt_reads(1000, &variant, &pooled, threshold), Some(10));
+
+        // A single sample keeps the default threshold
+        let single = LodConfig {
+            pool: Some(PoolDesign { size: 1 }),
+            ..LodConfig::default()
+        };
+        assert_eq!(single.detection_threshold(&variant), crate::DEFAULT_DETECTION_THRESHOLD);
+    }
+
+    #[test]
+    fn test_variant_overrides() {
+        let hotspot = Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string());
+        let other = Variant::new("chr1".to_string(), 200, "A".to_string(), "G".to_string());
+        let overrides = VariantOverrides {
+            p_se: Some(0.01),
+            p_tp: None,
+            threshold: Some(4.0),
+        };
+        let config = LodConfig {
+            pool: Some(PoolDesign { size: 50 }),
+            variant_overrides: Some(Arc::new([(hotspot.clone(), overrides)].into_iter().collect())),
+            ..LodConfig::default()
+        };
+
+        // A noisy hotspot needs more support, and its threshold is not pooled
+        assert!(calculate_variant_lod_score(0.05, &hotspot, &config) < calculate_variant_lod_score(0.05, &other, &config));
+        assert_eq!(config.error_rate(&hotspot), 0.01);
+        assert_eq!(config.true_positive_rate(&hotspot), config.p_tp);
+        assert_eq!(config.detection_threshold(&hotspot), 4.0);
+        assert!(config.detection_threshold(&other) < crate::DEFAULT_DETECTION_THRESHOLD);
+        assert_eq!(config.overrides(&other), None);
+    }
+
     #[test]
     fn test_calculate_detectability_condition() {
-        assert_eq!(calculate_detectability_condition(3.0), "Detectable");
-        assert_eq!(calculate_detectability_condition(2.5), "Detectable");
-        assert_eq!(calculate_detectability_condition(2.49), "Non-detectable");
-        assert_eq!(calculate_detectability_condition(0.0), "Non-detectable");
-        assert_eq!(calculate_detectability_condition(-1.0), "Non-detectable");
+        let config = LodConfig::default();
+        assert_eq!(calculate_detectability_condition(3.0, &config), DetectabilityCondition::Detectable);
+        assert_eq!(calculate_detectability_condition(2.5, &config), DetectabilityCondition::Detectable);
+        assert_eq!(calculate_detectability_condition(2.49, &config), DetectabilityCondition::NonDetectable);
+        assert_eq!(calculate_detectability_condition(0.0, &config), DetectabilityCondition::NonDetectable);
+        assert_eq!(calculate_detectability_condition(-1.0, &config), DetectabilityCondition::NonDetectable);
+
+        let strict = LodConfig { score_threshold: 3.5, ..LodConfig::default() };
+        assert_eq!(calculate_detectability_condition(3.0, &strict), DetectabilityCondition::NonDetectable);
+        let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        assert_eq!(strict.detection_threshold(&variant), 3.5);
+
+        let invalid = LodConfig { score_threshold: f64::NAN, ..LodConfig::default() };
+        assert!(validate_lod_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_filter_stratified_counts() {
+        let variant = |pos| Variant::new("chr1".to_string(), pos, "A".to_string(), "T".to_string());
+        let results = vec![
+            DetectabilityResult::new(variant(100), 3.5, DetectabilityCondition::Detectable, 30, 15),
+            DetectabilityResult::new(variant(200), 1.0, DetectabilityCondition::NonDetectable, 30, 1),
+            DetectabilityResult::new(variant(300), 3.5, DetectabilityCondition::Detectable, 30, 15),
+        ];
+        let non_pass: HashSet<Variant> = [variant(300)].into_iter().collect();
+
+        assert_eq!(filter_stratified_counts(&results, &non_pass), ((1, 2), (1, 1)));
+    }
+
+    #[test]
+    fn test_detectability_condition_round_trip() {
+        let conditions = [
+            DetectabilityCondition::Detectable,
+            DetectabilityCondition::NonDetectable,
+            DetectabilityCondition::NoCoverage,
+            DetectabilityCondition::Monomorphic,
+            DetectabilityCondition::RefConfirmed,
+            DetectabilityCondition::RefUnconfirmed,
+            DetectabilityCondition::CoverageOnly,
+            DetectabilityCondition::NotAssessable("symbolic allele".to_string()),
+            DetectabilityCondition::Failed("unknown contig".to_string()),
+        ];
+        for condition in conditions {
+            assert_eq!(condition.to_string().parse::<DetectabilityCondition>(), Ok(condition.clone()));
+            let json = serde_json::to_string(&condition).unwrap();
+            assert_eq!(serde_json::from_str::<DetectabilityCondition>(&json).unwrap(), condition);
+        }
+
+        assert_eq!(DetectabilityCondition::NonDetectable.to_string(), "Non-detectable");
+        assert_eq!(DetectabilityCondition::NoCoverage.vcf_status(), "NoCoverage");
+        assert_eq!(
+            DetectabilityCondition::from_vcf_status("REF_CONFIRMED"),
+            Some(DetectabilityCondition::RefConfirmed)
+        );
+        assert_eq!(DetectabilityCondition::from_vcf_status("Maybe"), None);
+        assert!("Maybe".parse::<DetectabilityCondition>().is_err());
+    }
+
+    #[test]
+    fn test_format_amplicon_support() {
+        assert_eq!(format_amplicon_support(&[]), ".");
+
+        let support = vec![
+            AmpliconSupport { amplicon: "AMP_1".to_string(), ref_reads: 20, variant_reads: 5 },
+            AmpliconSupport { amplicon: "AMP_2".to_string(), ref_reads: 18, variant_reads: 0 },
+        ];
+        assert_eq!(format_amplicon_support(&support), "AMP_1:20/5;AMP_2:18/0");
+    }
+
+    #[test]
+    fn test_write_detectability_results_to_writer() {
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        );
+        result.ref_reads = 12;
+
+        let mut output = Vec::new();
+        write_detectability_results_to_writer(&[result], &mut output, &LodConfig::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(&format!("#vlod_version={} #schema=2 #about={{", env!("CARGO_PKG_VERSION"))));
+        assert!(lines[1].starts_with("Chrom\tPos\tRef\tAlt\tDetectability_Score"));
+        assert_eq!(lines[2], "chr1\t100\tA\tT\t3.5\tDetectable\t30\t15\t0\t.\t.\t.\t.\t.\t0\t0\t.\t.\t.\t.\t0.5000\t0.5556\t0.0000\t0\t0.0000\t.\t.\tfalse\t.\t.\t0\t0\t.\t.");
+    }
+
+    #[test]
+    fn test_write_partial_detectability_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("partial.tsv");
+        write_partial_detectability_results(&[], &output, &LodConfig::default()).unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(content.lines().next().unwrap().contains(" #schema=2 #partial=true #about="));
+    }
+
+    #[test]
+    fn test_write_detectability_columns() {
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            40,
+            10,
+        );
+        result.alt_orientation.f1r2 = 6;
+        result.alt_orientation.f2r1 = 4;
+
+        let columns: ColumnSelection = "chrom,pos,ref,alt,score,coverage,VAF,strand_bias".parse().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("lims.tsv");
+        let config = LodConfig::default();
+        write_detectability_columns(&[result], &output, &columns, ResultsLayout::Packed, false, &config).unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[1], "Chrom\tPos\tRef\tAlt\tDetectability_Score\tCoverage\tVAF_Total\tOrientation_Bias");
+        assert_eq!(lines[2], "chr1\t100\tA\tT\t3.5\t40\t0.2500\t0.600");
+
+        assert_eq!(ColumnSelection::default().columns().len(), RESULT_COLUMNS.len());
+        assert!("chrom,depth".parse::<ColumnSelection>().unwrap_err().contains("unknown column 'depth'"));
+        assert!("score,detectability_score".parse::<ColumnSelection>().is_err());
+    }
+
+    #[test]
+    fn test_write_results_layouts() {
+        let result = |pos: u64, support: &[(&str, u32, u32)]| {
+            let mut result = DetectabilityResult::new(
+                Variant::new("chr1".to_string(), pos, "A".to_string(), "T".to_string()),
+                3.5,
+                DetectabilityCondition::Detectable,
+                40,
+                10,
+            );
+            result.amplicon_support = support
+                .iter()
+                .map(|&(amplicon, ref_reads, variant_reads)| AmpliconSupport {
+                    amplicon: amplicon.to_string(),
+                    ref_reads,
+                    variant_reads,
+                })
+                .collect();
+            result
+        };
+        let results = vec![result(100, &[("amp2", 20, 5), ("amp1", 10, 5)]), result(200, &[])];
+        let columns: ColumnSelection = "pos,amplicon_support,coverage".parse().unwrap();
+        let lines = |layout: ResultsLayout| {
+            let dir = tempfile::tempdir().unwrap();
+            let output = dir.path().join("results.tsv");
+            write_detectability_columns(&results, &output, &columns, layout, false, &LodConfig::default()).unwrap();
+            let content = std::fs::read_to_string(&output).unwrap();
+            content.lines().skip(1).map(String::from).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lines(ResultsLayout::Packed),
+            vec!["Pos\tAmplicon_Support\tCoverage", "100\tamp2:20/5;amp1:10/5\t40", "200\t.\t40"]
+        );
+        assert_eq!(
+            lines(ResultsLayout::Long),
+            vec![
+                "Pos\tAmplicon\tAmplicon_Ref_Reads\tAmplicon_Variant_Reads\tCoverage",
+                "100\tamp2\t20\t5\t40",
+                "100\tamp1\t10\t5\t40",
+                "200\t.\t.\t.\t40",
+            ]
+        );
+        assert_eq!(
+            lines(ResultsLayout::Wide),
+            vec![
+                "Pos\tAmplicon_Ref_Reads.amp1\tAmplicon_Variant_Reads.amp1\tAmplicon_Ref_Reads.amp2\tAmplicon_Variant_Reads.amp2\tCoverage",
+                "100\t10\t5\t20\t5\t40",
+                "200\t.\t.\t.\t.\t40",
+            ]
+        );
+        assert_eq!("Wide".parse::<ResultsLayout>(), Ok(ResultsLayout::Wide));
     }
 
     #[test]
@@ -257,6 +1516,7 @@ mod tests {
             p_tp: 0.0,
             p_fp: 0.001,
             p_se: 0.0001,
+            ..LodConfig::default()
         };
         assert!(validate_lod_config(&invalid_config).is_err());
         
@@ -264,7 +1524,42 @@ mod tests {
             p_tp: 0.5,
             p_fp: 0.6,
             p_se: 0.0001,
+            ..LodConfig::default()
         };
         assert!(validate_lod_config(&invalid_config).is_err());
+
+        let invalid_config = LodConfig {
+            contig_policy: ContigPolicy::Map,
+            ..LodConfig::default()
+        };
+        assert!(validate_lod_config(&invalid_config).is_err());
+
+        let invalid_config = LodConfig {
+            max_other_allele_fraction: Some(1.5),
+            ..LodConfig::default()
+        };
+        assert!(validate_lod_config(&invalid_config).is_err());
+    }
+
+    #[test]
+    fn test_third_allele_noise() {
+        let mut counts = AlleleCounts::new();
+        for _ in 0..18 {
+            counts.add_ref();
+        }
+        counts.add_alt("T".to_string());
+        counts.other_count = 1;
+
+        assert_eq!(third_allele_noise(&counts, &LodConfig::default()), None);
+        let config = LodConfig {
+            max_other_allele_fraction: Some(0.1),
+            ..LodConfig::default()
+        };
+        assert_eq!(third_allele_noise(&counts, &config), None);
+        counts.other_count = 5;
+        assert_eq!(
+            third_allele_noise(&counts, &config).as_deref(),
+            Some("third-allele fraction 0.208 above 0.1")
+        );
     }
 }
\ No newline at end of file
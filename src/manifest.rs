@@ -0,0 +1,232 @@
+//! Reproducibility manifest: checksums of the inputs of a run, so that a later run
+//! can assert it reads the same files
+
+use crate::{
+    about, About,
+    utils::{create_output_file, platform_path},
+    VlodError, VlodResult,
+};
+use md5::Digest;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+/// Bytes read per chunk when hashing a file
+const HASH_BUFFER_SIZE: usize = 1 << 20;
+
+/// Streaming MD5 (RFC 1321), matching `md5sum`
+#[derive(Default)]
+pub struct Md5(md5::Md5);
+
+impl Md5 {
+    pub fn new() -> Self {
+        Md5(md5::Md5::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Lower-case hex digest
+    pub fn hex_digest(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+/// Feed a file to `consume` in chunks, returning its size in bytes
+pub(crate) fn read_chunks<P: AsRef<Path>>(path: P, mut consume: impl FnMut(&[u8])) -> VlodResult<u64> {
+    let file = File::open(platform_path(&path)).map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+    let mut reader = BufReader::with_capacity(HASH_BUFFER_SIZE, file);
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(size);
+        }
+        consume(&buffer[..read]);
+        size += read as u64;
+    }
+}
+
+/// MD5 hex digest and byte size of a file
+pub fn md5_file<P: AsRef<Path>>(path: P) -> VlodResult<(String, u64)> {
+    let mut md5 = Md5::new();
+    let size = read_chunks(path, |chunk| md5.update(chunk))?;
+    Ok((md5.hex_digest(), size))
+}
+
+/// Checksum of one input file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputChecksum {
+    /// What the file is used as (vcf, bam, amplicon_bed, calibration, ...)
+    pub role: String,
+    pub path: String,
+    pub size: u64,
+    pub md5: String,
+}
+
+/// Inputs and settings of a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub vlod_version: String,
+    /// Command-line arguments of the run, excluding the program name
+    pub arguments: Vec<String>,
+    pub inputs: Vec<InputChecksum>,
+    /// Provenance of the vlod build that wrote the manifest
+    #[serde(default)]
+    pub about: Option<About>,
+}
+
+impl RunManifest {
+    /// Checksum the `(role, path)` inputs of a run
+    pub fn compute(inputs: &[(&str, &Path)], arguments: Vec<String>) -> VlodResult<Self> {
+        let inputs = inputs
+            .iter()
+            .map(|(role, path)| {
+                let (md5, size) = md5_file(path)?;
+                log::debug!("{} {:?}: MD5 {} ({} bytes)", role, path, md5, size);
+                Ok(InputChecksum {
+                    role: role.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    size,
+                    md5,
+                })
+            })
+            .collect::<VlodResult<_>>()?;
+        Ok(RunManifest {
+            vlod_version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments,
+            inputs,
+            about: Some(about()),
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        let file = File::open(&path)
+            .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| VlodError::InvalidConfig(format!("invalid manifest file: {}", e)))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> VlodResult<()> {
+        let mut writer = BufWriter::new(create_output_file(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| VlodError::InvalidConfig(format!("cannot write manifest: {}", e)))?;
+        Ok(())
+    }
+
+    /// `##vlodInput` VCF header lines recording each input's checksum
+    pub fn vcf_header_lines(&self) -> Vec<String> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                format!(
+                    "##vlodInput=<Role={},File=\"{}\",Size={},MD5={}>",
+                    input.role, input.path, input.size, input.md5
+                )
+            })
+            .collect()
+    }
+
+    /// Check that this run's inputs match a recorded manifest. Inputs are paired
+    /// by role and order, not path, so the files may have moved in between.
+    pub fn verify(&self, recorded: &RunManifest) -> VlodResult<()> {
+        let by_role = |manifest: &RunManifest| {
+            let mut roles: BTreeMap<String, Vec<InputChecksum>> = BTreeMap::new();
+            for input in &manifest.inputs {
+                roles.entry(input.role.clone()).or_default().push(input.clone());
+            }
+            roles
+        };
+        let current = by_role(self);
+        let expected = by_role(recorded);
+        let roles: BTreeSet<&String> = current.keys().chain(expected.keys()).collect();
+
+        let mut differences = Vec::new();
+        for role in roles {
+            let actual = current.get(role).map(Vec::as_slice).unwrap_or(&[]);
+            let wanted = expected.get(role).map(Vec::as_slice).unwrap_or(&[]);
+            for index in 0..actual.len().max(wanted.len()) {
+                match (actual.get(index), wanted.get(index)) {
+                    (Some(actual), Some(wanted)) if actual.md5 != wanted.md5 => differences.push(format!(
+                        "{} {} has MD5 {}, manifest has {} for {}",
+                        role, actual.path, actual.md5, wanted.md5, wanted.path
+                    )),
+                    (None, Some(wanted)) => {
+                        differences.push(format!("{} {} is not an input of this run", role, wanted.path))
+                    }
+                    (Some(actual), None) => differences.push(format!("{} {} is not in the manifest", role, actual.path)),
+                    _ => {}
+                }
+            }
+        }
+
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(VlodError::InvalidConfig(format!(
+                "inputs differ from the checksum manifest: {}",
+                differences.join("; ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn md5_hex(data: &[u8]) -> String {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        md5.hex_digest()
+    }
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5_hex(b"The quick brown fox jumps over the lazy dog"),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+
+        // Chunked updates across block boundaries give the same digest
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut md5 = Md5::new();
+        for chunk in data.chunks(37) {
+            md5.update(chunk);
+        }
+        assert_eq!(md5.hex_digest(), "c260642229888763c0fa2a4843f50a36");
+    }
+
+    #[test]
+    fn test_manifest_verify() {
+        let mut vcf = NamedTempFile::new().unwrap();
+        writeln!(vcf, "##fileformat=VCFv4.2").unwrap();
+        let mut bam = NamedTempFile::new().unwrap();
+        bam.write_all(b"BAM\x01").unwrap();
+
+        let inputs = [("vcf", vcf.path()), ("bam", bam.path())];
+        let manifest = RunManifest::compute(&inputs, vec!["--input-vcf".to_string()]).unwrap();
+        assert_eq!(manifest.inputs[1].size, 4);
+        assert!(manifest.vcf_header_lines()[0].starts_with("##vlodInput=<Role=vcf,File="));
+
+        let saved = NamedTempFile::new().unwrap();
+        manifest.write(saved.path()).unwrap();
+        let recorded = RunManifest::from_file(saved.path()).unwrap();
+        assert_eq!(recorded, manifest);
+        assert!(RunManifest::compute(&inputs, Vec::new()).unwrap().verify(&recorded).is_ok());
+
+        // A changed input, or a missing one, fails verification
+        writeln!(vcf, "#CHROM").unwrap();
+        assert!(RunManifest::compute(&inputs, Vec::new()).unwrap().verify(&recorded).is_err());
+        let fewer = RunManifest::compute(&inputs[1..], Vec::new()).unwrap();
+        assert!(fewer.verify(&recorded).is_err());
+    }
+}
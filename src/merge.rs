@@ -1,110 +1,1096 @@
 //! VCF integration functionality for merging detectability results
 
 use crate::{
-    vcf::is_gzipped,
-    DetectabilityResult, VlodError, VlodResult,
+    about,
+    contig::ContigAliasIndex,
+    incremental::has_detectability_info,
+    lod::{NO_EVIDENCE_SCORE, TSV_SCHEMA_VERSION},
+    results_index::{is_indexed_results, IndexedResults},
+    utils::{
+        append_extension, build_tabix_index, create_output_file, is_gzipped, open_text_input, ParseErrorBudget,
+        ScratchFile, DEFAULT_MAX_LINE_LENGTH,
+    },
+    vcf::{Caller, DuplicatePolicy, VcfReader},
+    About, DetectabilityCondition, DetectabilityResult, VlodError, VlodResult, DEFAULT_DETECTION_THRESHOLD,
 };
-use flate2::read::MultiGzDecoder;
-use std::collections::HashMap;
+use rust_htslib::htslib;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufWriter, Write};
 use std::path::Path;
 
-/// Read detectability results from a TSV file
+/// Options controlling how detectability results are merged into a VCF
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    /// Resolution for variants repeated in the results or in the VCF
+    pub duplicate_policy: DuplicatePolicy,
+    /// Reference contigs (name, length), written as ##contig lines when the input has none
+    pub contigs: Vec<(String, u64)>,
+    /// Match contig names exactly, without aliasing `chr1` to `1` (or `chrM` to `MT`)
+    pub strict_contig_names: bool,
+    /// Longest line accepted when reading the VCF and results files
+    pub max_line_length: usize,
+    /// Skip invalid results rows, failing after this many (None fails on the first)
+    pub max_errors: Option<usize>,
+    /// Add the ALT F1R2 fraction as a DETOB INFO field
+    pub orientation_info: bool,
+    /// Add the strand bias of the ALT reads as DETFS (Fisher's exact test) and
+    /// DETSOR (symmetric odds ratio) INFO fields
+    pub strand_info: bool,
+    /// Flag records whose BAM coverage differs from the VCF depth (see
+    /// `vcf_record_depth`) by more than this fold with a DETDPD INFO field
+    pub depth_discordance_fold: Option<f64>,
+    /// Extra `##` header lines (e.g. input checksums) written before `#CHROM`
+    pub header_lines: Vec<String>,
+    /// Caller conventions of the VCF; for DRAGEN, `<NON_REF>` is ignored when
+    /// matching records to results, and depths are read from the FORMAT fields
+    /// the caller writes them to
+    pub caller: Caller,
+    /// Leave records already carrying DET/DETS from an earlier run with the same
+    /// configuration as they are (incremental mode)
+    pub keep_annotated: bool,
+    /// Write the DET* INFO fields; without them, only the per-sample DET/DETS
+    /// FORMAT fields of a multi-sample merge are written
+    pub info_fields: bool,
+    /// Score threshold of the run, recorded in the `##vlodAbout` header
+    pub score_threshold: f64,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            contigs: Vec::new(),
+            strict_contig_names: false,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            max_errors: None,
+            orientation_info: false,
+            strand_info: false,
+            depth_discordance_fold: None,
+            header_lines: Vec::new(),
+            caller: Caller::default(),
+            keep_annotated: false,
+            info_fields: true,
+            score_threshold: DEFAULT_DETECTION_THRESHOLD,
+        }
+    }
+}
+
+/// Fold difference between BAM coverage and VCF depth flagged by `--depth-discordance`
+pub const DEFAULT_DEPTH_DISCORDANCE_FOLD: f64 = 2.0;
+
+/// INFO fields written by a merge
+const DETECTABILITY_INFO_IDS: [&str; 7] = ["DET", "DETS", "DETP", "DETOB", "DETFS", "DETSOR", "DETDPD"];
+
+/// FORMAT fields written per sample by a multi-sample merge
+const DETECTABILITY_FORMAT_IDS: [&str; 2] = ["DET", "DETS"];
+
+/// Data lines logged between merge progress messages
+const MERGE_PROGRESS_INTERVAL: usize = 1_000_000;
+
+/// Counts of VCF data lines seen during a merge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// Records annotated with DET/DETS
+    pub annotated: usize,
+    /// Well-formed records with no detectability result
+    pub unmatched: usize,
+    /// Records with too few columns or an invalid POS
+    pub malformed: usize,
+    /// Records written unchanged (unmatched, malformed or lacking an INFO column)
+    pub passed_through: usize,
+    /// Annotated records whose BAM coverage was compared with the VCF depth
+    pub depth_compared: usize,
+    /// Compared records flagged with DETDPD
+    pub depth_discordant: usize,
+    /// Records passed through with the DET/DETS of an earlier run (incremental mode)
+    pub kept: usize,
+}
+
+impl MergeStats {
+    /// Total number of VCF data lines
+    pub fn records(&self) -> usize {
+        self.annotated + self.passed_through
+    }
+
+    /// Log the counts as part of a run summary
+    pub fn log_summary(&self) {
+        log::info!("Merge summary:");
+        log::info!("  Annotated: {}", self.annotated);
+        log::info!("  Unmatched: {}", self.unmatched);
+        log::info!("  Malformed: {}", self.malformed);
+        log::info!("  Passed through unchanged: {}", self.passed_through);
+        if self.kept > 0 {
+            log::info!("  Kept from an earlier run: {}", self.kept);
+        }
+        if self.depth_compared > 0 {
+            log::info!("  Depth discordant: {} of {}", self.depth_discordant, self.depth_compared);
+        }
+    }
+}
+
+/// Write `##contig` header lines for reference contigs
+fn write_contig_headers<W: Write>(writer: &mut W, contigs: &[(String, u64)]) -> VlodResult<()> {
+    for (name, length) in contigs {
+        writeln!(writer, "##contig=<ID={},length={}>", name, length)?;
+    }
+    Ok(())
+}
+
+/// Insert the fields of a result row, resolving an existing row for the same
+/// variant by policy: the winning row supplies every field. Returns whether the
+/// variant was already present.
+fn insert_resolved(
+    rows: &mut HashMap<(String, u64, String, String), MergedFields>,
+    key: (String, u64, String, String),
+    fields: MergedFields,
+    policy: DuplicatePolicy,
+) -> VlodResult<bool> {
+    match rows.entry(key) {
+        Entry::Vacant(entry) => {
+            entry.insert(fields);
+            Ok(false)
+        }
+        Entry::Occupied(mut entry) => match policy {
+            DuplicatePolicy::First => Ok(true),
+            DuplicatePolicy::Max => {
+                if fields.score > entry.get().score {
+                    entry.insert(fields);
+                }
+                Ok(true)
+            }
+            DuplicatePolicy::Error => {
+                let (chrom, pos, ref_allele, alt_allele) = entry.key();
+                Err(VlodError::InvalidVariant(format!(
+                    "Duplicate detectability result: {}:{} {}>{}",
+                    chrom, pos, ref_allele, alt_allele
+                )))
+            }
+        },
+    }
+}
+
+/// Read detectability results from a results TSV, or from a VCF annotated with
+/// DET/DETS (see `read_annotated_vcf`)
 pub fn read_detectability_results<P: AsRef<Path>>(
     path: P,
-) -> VlodResult<HashMap<(String, u32, String, String), (String, f64)>> {
-    let file = File::open(&path)
-        .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+) -> VlodResult<HashMap<(String, u64, String, String), (DetectabilityCondition, f64)>> {
+    if is_annotated_vcf(&path)? {
+        return Ok(create_detectability_map(&read_annotated_vcf(path)?));
+    }
+    read_detectability_results_with_policy(path, DuplicatePolicy::First).map(|(data, _)| data)
+}
 
-    let reader: Box<dyn BufRead> = if is_gzipped(&path)? {
-        let gz_decoder = MultiGzDecoder::new(file);
-        Box::new(BufReader::new(gz_decoder))
-    } else {
-        Box::new(BufReader::new(file))
-    };
+/// Whether a results file is a VCF (annotated by vlod or merge_vcf_lod) rather than
+/// a results TSV, detected from the first line
+pub fn is_annotated_vcf<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
+    Ok(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?.fill_buf()?.starts_with(b"##fileformat=VCF"))
+}
+
+/// Read detectability results back from a VCF annotated by vlod or merge_vcf_lod:
+/// the condition from DET, the score from DETS and the detection probability from
+/// DETP. The VCF does not record read counts, which are left at 0; records without
+/// a DET field are skipped.
+pub fn read_annotated_vcf<P: AsRef<Path>>(path: P) -> VlodResult<Vec<DetectabilityResult>> {
+    let mut results = Vec::new();
+    let mut reader = VcfReader::new(path)?;
+    for record in reader.records() {
+        let record = record?;
+        let mut condition = None;
+        let mut score = f64::NAN;
+        let mut probability = None;
+        for field in record.info.split(';') {
+            match field.split_once('=') {
+                Some(("DET", status)) => condition = DetectabilityCondition::from_vcf_status(status),
+                Some(("DETS", value)) => score = value.parse().unwrap_or(f64::NAN),
+                Some(("DETP", value)) => probability = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if let Some(condition) = condition {
+            let mut result = DetectabilityResult::new(record.variant, score, condition, 0, 0);
+            result.detection_probability = probability;
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+/// Read detectability results from a TSV file, resolving repeated variants (e.g. from
+/// concatenated result files) by policy; also returns the number of duplicates
+pub fn read_detectability_results_with_policy<P: AsRef<Path>>(
+    path: P,
+    policy: DuplicatePolicy,
+) -> VlodResult<(HashMap<(String, u64, String, String), (DetectabilityCondition, f64)>, usize)> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    let table = parse_results_table(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?, &source, policy, None)?;
+    Ok((table.scores(), table.duplicates))
+}
+
+/// Read detectability results from uncompressed TSV text, resolving repeated
+/// variants by policy; also returns the number of duplicates
+pub fn read_detectability_results_from_reader<R: BufRead>(
+    reader: R,
+    policy: DuplicatePolicy,
+) -> VlodResult<(HashMap<(String, u64, String, String), (DetectabilityCondition, f64)>, usize)> {
+    let table = parse_results_table(reader, "detectability results", policy, None)?;
+    Ok((table.scores(), table.duplicates))
+}
+
+/// Read calibrated detection probabilities from the `Detection_Probability` column
+/// of a results TSV (empty when the column is absent or uncalibrated)
+pub fn read_detection_probabilities<P: AsRef<Path>>(
+    path: P,
+    policy: DuplicatePolicy,
+) -> VlodResult<HashMap<(String, u64, String, String), f64>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    let table = parse_results_table(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?, &source, policy, None)?;
+    Ok(table.probabilities())
+}
+
+/// Read calibrated detection probabilities from uncompressed results TSV text
+pub fn read_detection_probabilities_from_reader<R: BufRead>(
+    reader: R,
+    policy: DuplicatePolicy,
+) -> VlodResult<HashMap<(String, u64, String, String), f64>> {
+    Ok(parse_results_table(reader, "detectability results", policy, None)?.probabilities())
+}
+
+/// Read per-variant coverage from the `Coverage` column of a results TSV (empty
+/// when the column is absent)
+pub fn read_result_coverage<P: AsRef<Path>>(
+    path: P,
+    policy: DuplicatePolicy,
+) -> VlodResult<HashMap<(String, u64, String, String), u32>> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    let table = parse_results_table(open_text_input(path, DEFAULT_MAX_LINE_LENGTH)?, &source, policy, None)?;
+    Ok(table.coverage())
+}
+
+/// Read per-variant coverage from uncompressed results TSV text
+pub fn read_result_coverage_from_reader<R: BufRead>(
+    reader: R,
+    policy: DuplicatePolicy,
+) -> VlodResult<HashMap<(String, u64, String, String), u32>> {
+    Ok(parse_results_table(reader, "detectability results", policy, None)?.coverage())
+}
+
+/// Merged fields of the results parsed from one results TSV, one row per variant
+pub(crate) struct ResultsTable {
+    rows: HashMap<(String, u64, String, String), MergedFields>,
+    duplicates: usize,
+    has_probabilities: bool,
+}
+
+impl ResultsTable {
+    /// Table of resolved rows; rows with a detection probability add DETP
+    fn new(rows: HashMap<(String, u64, String, String), MergedFields>, duplicates: usize) -> Self {
+        let has_probabilities = rows.values().any(|fields| fields.probability.is_some());
+        ResultsTable { rows, duplicates, has_probabilities }
+    }
+
+    /// Merged fields of the result with exactly this key
+    pub(crate) fn fields(&self, key: &(String, u64, String, String)) -> Option<MergedFields> {
+        self.rows.get(key).cloned()
+    }
+
+    /// Condition and score of every variant
+    fn scores(self) -> HashMap<(String, u64, String, String), (DetectabilityCondition, f64)> {
+        self.rows.into_iter().map(|(key, fields)| (key, (fields.condition, fields.score))).collect()
+    }
+
+    /// Detection probability of every variant that has one
+    fn probabilities(self) -> HashMap<(String, u64, String, String), f64> {
+        self.rows.into_iter().filter_map(|(key, fields)| Some((key, fields.probability?))).collect()
+    }
+
+    /// Coverage of every variant that has one
+    fn coverage(self) -> HashMap<(String, u64, String, String), u32> {
+        self.rows.into_iter().filter_map(|(key, fields)| Some((key, fields.coverage?))).collect()
+    }
+
+    /// Duplicate results resolved by policy
+    pub(crate) fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+}
+
+/// Fields merged into a VCF record from one detectability result
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MergedFields {
+    pub condition: DetectabilityCondition,
+    pub score: f64,
+    pub probability: Option<f64>,
+    pub orientation_bias: Option<f64>,
+    /// Fisher strand and strand odds ratio of the ALT reads
+    pub strand_bias: Option<(f64, f64)>,
+    pub coverage: Option<u32>,
+}
+
+impl From<&DetectabilityResult> for MergedFields {
+    fn from(result: &DetectabilityResult) -> Self {
+        MergedFields {
+            condition: result.detectability_condition.clone(),
+            score: result.detectability_score,
+            probability: result.detection_probability,
+            orientation_bias: result.alt_orientation.bias(),
+            strand_bias: result.strand.fisher_strand().zip(result.strand.strand_odds_ratio()),
+            coverage: Some(result.coverage),
+        }
+    }
+}
+
+/// Detectability results looked up record by record while annotating a VCF
+pub(crate) trait ResultsLookup {
+    /// Result for a VCF record key, and whether it matched only after contig aliasing
+    fn lookup(&mut self, key: &(String, u64, String, String)) -> VlodResult<Option<(MergedFields, bool)>>;
+    /// Whether results carry detection probabilities (adds the DETP header)
+    fn has_probabilities(&self) -> bool;
+    /// Duplicate results resolved by policy so far
+    fn duplicates(&self) -> usize;
+    /// Number of results, when known without reading them all
+    fn result_count(&self) -> Option<usize>;
+    /// Whether results are scored while the VCF is read, so that memory must not
+    /// grow with the number of records; duplicate VCF records are then only found
+    /// next to each other (at the same position)
+    fn streams(&self) -> bool {
+        false
+    }
+}
+
+/// Lookup in a results table held in memory
+struct TableLookup<'a> {
+    table: &'a ResultsTable,
+    alias_index: Option<ContigAliasIndex>,
+}
+
+impl<'a> TableLookup<'a> {
+    fn new(table: &'a ResultsTable, options: &MergeOptions) -> Self {
+        TableLookup {
+            table,
+            alias_index: (!options.strict_contig_names).then(|| ContigAliasIndex::new(table.rows.keys())),
+        }
+    }
+}
+
+impl ResultsLookup for TableLookup<'_> {
+    fn lookup(&mut self, key: &(String, u64, String, String)) -> VlodResult<Option<(MergedFields, bool)>> {
+        // Fall back to contig aliasing (chr1 vs 1) when the exact key has no result
+        let (result_key, aliased) = if self.table.rows.contains_key(key) {
+            (Some(key), false)
+        } else {
+            (self.alias_index.as_ref().and_then(|index| index.resolve(key)), true)
+        };
+        Ok(result_key.and_then(|key| self.table.fields(key)).map(|fields| (fields, aliased)))
+    }
+
+    fn has_probabilities(&self) -> bool {
+        self.table.has_probabilities
+    }
+
+    fn duplicates(&self) -> usize {
+        self.table.duplicates
+    }
+
+    fn result_count(&self) -> Option<usize> {
+        Some(self.table.rows.len())
+    }
+}
+
+/// Column positions of the merged fields in a results TSV
+pub(crate) struct ResultsColumns {
+    pub chrom: usize,
+    pub pos: usize,
+    ref_allele: usize,
+    alt_allele: usize,
+    score: usize,
+    condition: usize,
+    pub probability: Option<usize>,
+    pub orientation_bias: Option<usize>,
+    pub fisher_strand: Option<usize>,
+    pub strand_odds_ratio: Option<usize>,
+    pub coverage: Option<usize>,
+}
+
+impl ResultsColumns {
+    /// Columns of a results TSV with the given schema version and header row
+    pub(crate) fn for_schema(schema: u32, headers: &csv::StringRecord) -> VlodResult<Self> {
+        match schema {
+            1 => Ok(ResultsColumns::positional(headers)),
+            _ => ResultsColumns::from_headers(headers),
+        }
+    }
+
+    /// Schema 1 (no metadata line): the six leading columns, in order
+    fn positional(headers: &csv::StringRecord) -> Self {
+        ResultsColumns {
+            chrom: 0,
+            pos: 1,
+            ref_allele: 2,
+            alt_allele: 3,
+            score: 4,
+            condition: 5,
+            probability: headers.iter().position(|h| h == "Detection_Probability"),
+            orientation_bias: headers.iter().position(|h| h == "Orientation_Bias"),
+            fisher_strand: headers.iter().position(|h| h == "Fisher_Strand"),
+            strand_odds_ratio: headers.iter().position(|h| h == "Strand_Odds_Ratio"),
+            coverage: headers.iter().position(|h| h == "Coverage"),
+        }
+    }
+
+    /// Schema 2: columns located by header name
+    fn from_headers(headers: &csv::StringRecord) -> VlodResult<Self> {
+        let column = |name: &str| {
+            headers.iter().position(|h| h == name).ok_or_else(|| {
+                VlodError::InvalidVariant(format!("{} column not found in detectability TSV header", name))
+            })
+        };
+        Ok(ResultsColumns {
+            chrom: column("Chrom")?,
+            pos: column("Pos")?,
+            ref_allele: column("Ref")?,
+            alt_allele: column("Alt")?,
+            score: column("Detectability_Score")?,
+            condition: column("Detectability_Condition")?,
+            probability: headers.iter().position(|h| h == "Detection_Probability"),
+            orientation_bias: headers.iter().position(|h| h == "Orientation_Bias"),
+            fisher_strand: headers.iter().position(|h| h == "Fisher_Strand"),
+            strand_odds_ratio: headers.iter().position(|h| h == "Strand_Odds_Ratio"),
+            coverage: headers.iter().position(|h| h == "Coverage"),
+        })
+    }
+
+    /// Strand bias (Fisher strand, strand odds ratio) of a row, when it has both
+    pub(crate) fn strand_bias(&self, record: &csv::StringRecord) -> Option<(f64, f64)> {
+        let number = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+        number(self.fisher_strand).zip(number(self.strand_odds_ratio))
+    }
+
+    pub(crate) fn required_len(&self) -> usize {
+        [self.chrom, self.pos, self.ref_allele, self.alt_allele, self.score, self.condition]
+            .into_iter()
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+}
+
+/// Consume the `#vlod_version=... #schema=N` metadata line if present, returning
+/// the schema version (1 for files written before the line was introduced)
+pub(crate) fn read_schema_version<R: BufRead>(reader: &mut R) -> VlodResult<u32> {
+    if !reader.fill_buf()?.starts_with(b"#vlod_version=") {
+        return Ok(1);
+    }
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let schema = line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("#schema="))
+        .ok_or_else(|| VlodError::InvalidVariant(format!("Missing schema in TSV metadata line: {}", line.trim_end())))?;
+    let schema = schema
+        .parse::<u32>()
+        .map_err(|_| VlodError::InvalidVariant(format!("Invalid TSV schema version: {}", schema)))?;
+
+    if schema > TSV_SCHEMA_VERSION {
+        return Err(VlodError::InvalidVariant(format!(
+            "Detectability TSV uses schema {}, but this vlod reads up to schema {}; please upgrade vlod",
+            schema, TSV_SCHEMA_VERSION
+        )));
+    }
+    Ok(schema)
+}
+
+/// Detection threshold recorded in the `#about=` metadata of a results TSV (None
+/// for results written without it, e.g. by other tools or older vlod versions)
+pub fn read_results_threshold<P: AsRef<Path>>(path: P, max_line_length: usize) -> VlodResult<Option<f64>> {
+    let mut reader = open_text_input(path, max_line_length)?;
+    if !reader.fill_buf()?.starts_with(b"#vlod_version=") {
+        return Ok(None);
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    // The JSON is last on the line and may contain spaces
+    Ok(line
+        .split_once("#about=")
+        .and_then(|(_, about)| serde_json::from_str::<serde_json::Value>(about.trim_end()).ok())
+        .and_then(|about| about.get("detection_threshold")?.as_f64()))
+}
+
+/// Parse a score written by vlod or another tool. Accepts scientific notation,
+/// `inf`/`nan` and a decimal comma; missing values (`.`, `NA`, empty) parse as NaN.
+/// Returns None for text that is not a number.
+fn parse_score(field: &str) -> Option<f64> {
+    let field = field.trim();
+    if field.is_empty() || field == "." || field.eq_ignore_ascii_case("na") {
+        return Some(f64::NAN);
+    }
+    if let Ok(score) = field.parse::<f64>() {
+        return Some(score);
+    }
+    // Locale-formatted decimals such as 3,5
+    if !field.contains('.') && field.matches(',').count() == 1 {
+        return field.replace(',', ".").parse::<f64>().ok();
+    }
+    None
+}
+
+/// Parse one results row into its key, condition and (possibly non-finite) score
+pub(crate) fn parse_results_row(
+    record: &csv::StringRecord,
+    columns: &ResultsColumns,
+) -> VlodResult<((String, u64, String, String), DetectabilityCondition, f64)> {
+    let chrom = record[columns.chrom].to_string();
+    let pos = record[columns.pos]
+        .parse::<u64>()
+        .map_err(|_| VlodError::in_column("Pos", format!("Invalid position: {}", &record[columns.pos])))?;
+    let ref_allele = record[columns.ref_allele].to_string();
+    let alt_allele = record[columns.alt_allele].to_string();
+    let score = parse_score(&record[columns.score]).ok_or_else(|| {
+        VlodError::in_column("Detectability_Score", format!("Invalid score: {}", &record[columns.score]))
+    })?;
+    let condition = record[columns.condition]
+        .parse::<DetectabilityCondition>()
+        .map_err(|e| VlodError::in_column("Detectability_Condition", format!("Invalid condition: {}", e)))?;
+    Ok(((chrom, pos, ref_allele, alt_allele), condition, score))
+}
 
+/// Parse a results TSV in a single pass. Invalid rows are fatal unless
+/// `max_errors` is set, in which case up to that many are skipped.
+fn parse_results_table<R: BufRead>(
+    mut reader: R,
+    source: &str,
+    policy: DuplicatePolicy,
+    max_errors: Option<usize>,
+) -> VlodResult<ResultsTable> {
+    // csv counts lines from its own start, after any metadata line
+    let line_offset = u64::from(reader.fill_buf()?.starts_with(b"#vlod_version="));
+    let schema = read_schema_version(&mut reader)?;
     let mut csv_reader = csv::ReaderBuilder::new()
         .delimiter(b'\t')
         .from_reader(reader);
 
-    let mut detectability_data = HashMap::new();
+    let columns = ResultsColumns::for_schema(schema, csv_reader.headers()?)?;
+    let required_len = columns.required_len();
+
+    let mut rows = HashMap::new();
+    let mut duplicates = 0;
+    let mut no_evidence_scores = 0;
+    let mut errors = ParseErrorBudget::new(source, max_errors);
 
     for result in csv_reader.records() {
         let record = result?;
         
-        if record.len() < 6 {
+        if record.len() < required_len {
             continue;
         }
 
-        let chrom = record[0].to_string();
-        let pos = record[1].parse::<u32>()
-            .map_err(|_| VlodError::InvalidVariant(format!("Invalid position: {}", &record[1])))?;
-        let ref_allele = record[2].to_string();
-        let alt_allele = record[3].to_string();
-        let detectability_score = record[4].parse::<f64>()
-            .map_err(|_| VlodError::InvalidVariant(format!("Invalid score: {}", &record[4])))?;
-        let detectability_condition = record[5].to_string();
-
-        let condition = if detectability_condition == "Detectable" {
-            "Yes".to_string()
+        let (key, condition, score) = match parse_results_row(&record, &columns) {
+            Ok(row) => row,
+            Err(e) => {
+                let line = record.position().map(|p| p.line()).unwrap_or(0) + line_offset;
+                let e = e.at_line(source, line);
+                if max_errors.is_none() {
+                    return Err(e);
+                }
+                errors.record(e)?;
+                continue;
+            }
+        };
+        let detectability_score = if score.is_finite() {
+            score
         } else {
-            "No".to_string()
+            no_evidence_scores += 1;
+            NO_EVIDENCE_SCORE
+        };
+
+        let fields = MergedFields {
+            condition,
+            score: detectability_score,
+            probability: columns
+                .probability
+                .and_then(|column| record.get(column))
+                .and_then(|probability| probability.parse::<f64>().ok()),
+            orientation_bias: columns
+                .orientation_bias
+                .and_then(|column| record.get(column))
+                .and_then(|bias| bias.parse::<f64>().ok()),
+            strand_bias: columns.strand_bias(&record),
+            coverage: columns
+                .coverage
+                .and_then(|column| record.get(column))
+                .and_then(|depth| depth.parse::<u32>().ok()),
         };
+        if insert_resolved(&mut rows, key, fields, policy)? {
+            duplicates += 1;
+        }
+    }
 
-        detectability_data.insert(
-            (chrom, pos, ref_allele, alt_allele),
-            (condition, detectability_score),
+    errors.log_summary();
+    if no_evidence_scores > 0 {
+        log::warn!(
+            "{} missing or non-finite detectability scores were read as {} (no evidence)",
+            no_evidence_scores,
+            NO_EVIDENCE_SCORE
         );
     }
 
-    Ok(detectability_data)
+    Ok(ResultsTable::new(rows, duplicates))
 }
 
-/// Merge detectability results into a VCF file
+/// Merge detectability results into a VCF file. A bgzip-compressed results TSV
+/// with a tabix index (see `results_index::index_results`) is looked up record by
+/// record instead of being loaded into memory.
 pub fn merge_detectability_into_vcf<P: AsRef<Path>>(
     vcf_path: P,
     detectability_path: P,
     output_path: P,
-) -> VlodResult<()> {
-    let detectability_data = read_detectability_results(detectability_path)?;
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let reader = open_text_input(&vcf_path, options.max_line_length)?;
+    if is_indexed_results(&detectability_path) {
+        log::info!("Looking up results through the tabix index of {:?}", detectability_path.as_ref());
+        let mut results = IndexedResults::open(&detectability_path, options)?;
+        let output_file = BufWriter::new(create_output_file(output_path)?);
+        return annotate_vcf(reader, output_file, &mut results, &mut [], options);
+    }
+
+    let source = detectability_path.as_ref().to_string_lossy().to_string();
+    let detectability = open_text_input(&detectability_path, options.max_line_length)?;
+    let table = parse_results_table(detectability, &source, options.duplicate_policy, options.max_errors)?;
+    let output_file = BufWriter::new(create_output_file(output_path)?);
+    annotate_vcf(reader, output_file, &mut TableLookup::new(&table, options), &mut [], options)
+}
 
-    let file = File::open(&vcf_path)
-        .map_err(|_| VlodError::FileNotFound(vcf_path.as_ref().to_string_lossy().to_string()))?;
+/// Annotate a VCF in place. `annotate` writes the annotated VCF to a scratch file
+/// next to the original; it is bgzipped when the original is compressed and only
+/// renamed over the original once annotation succeeded, so a failed run leaves
+/// the original untouched. A `.tbi` or `.csi` index of the original is rebuilt.
+pub fn annotate_in_place<P, F>(vcf_path: P, annotate: F) -> VlodResult<MergeStats>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&Path) -> VlodResult<MergeStats>,
+{
+    let vcf_path = vcf_path.as_ref();
+    let compressed = is_gzipped(vcf_path)?;
+    // (extension, min_shift): htslib builds a .csi for a non-zero min_shift
+    let indexes: Vec<(&str, i32)> = [("tbi", 0), ("csi", 14)]
+        .into_iter()
+        .filter(|(extension, _)| append_extension(vcf_path, extension).exists())
+        .collect();
 
-    let reader: Box<dyn BufRead> = if is_gzipped(&vcf_path)? {
-        let gz_decoder = MultiGzDecoder::new(file);
-        Box::new(BufReader::new(gz_decoder))
+    let annotated = ScratchFile::new(append_extension(vcf_path, "vlod-tmp"));
+    let stats = annotate(annotated.path())?;
+    let bgzipped = ScratchFile::new(append_extension(vcf_path, "vlod-tmp.gz"));
+    let replacement = if compressed {
+        let mut writer = rust_htslib::bgzf::Writer::from_path(bgzipped.path())?;
+        std::io::copy(&mut File::open(annotated.path())?, &mut writer)?;
+        // Dropping the writer flushes the last block and writes the BGZF end marker
+        drop(writer);
+        bgzipped.path()
     } else {
-        Box::new(BufReader::new(file))
+        annotated.path()
+    };
+    let new_indexes: Vec<ScratchFile> = indexes
+        .iter()
+        .map(|(extension, min_shift)| {
+            let index = ScratchFile::new(append_extension(replacement, extension));
+            build_vcf_index(replacement, *min_shift).map(|_| index)
+        })
+        .collect::<VlodResult<_>>()?;
+
+    std::fs::rename(replacement, vcf_path)?;
+    for ((extension, _), index) in indexes.iter().zip(&new_indexes) {
+        std::fs::rename(index.path(), append_extension(vcf_path, extension))?;
+        log::info!("Rebuilt the .{} index of {:?}", extension, vcf_path);
+    }
+    Ok(stats)
+}
+
+fn build_vcf_index(path: &Path, min_shift: i32) -> VlodResult<()> {
+    let conf = htslib::tbx_conf_t {
+        preset: htslib::TBX_VCF as i32,
+        sc: 1,
+        bc: 2,
+        ec: 0,
+        meta_char: '#' as i32,
+        line_skip: 0,
+    };
+    build_tabix_index(path, &conf, min_shift)
+}
+
+/// Merge a results TSV read from `detectability` into VCF text read from `reader`,
+/// writing the annotated VCF to `writer`
+pub fn merge_detectability_into_writer<R: BufRead, D: BufRead, W: Write>(
+    reader: R,
+    detectability: D,
+    writer: W,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let table = parse_results_table(detectability, "detectability results", options.duplicate_policy, options.max_errors)?;
+    annotate_vcf(reader, writer, &mut TableLookup::new(&table, options), &mut [], options)
+}
+
+/// Create detectability results from a vector of DetectabilityResult
+pub fn create_detectability_map(
+    results: &[DetectabilityResult],
+) -> HashMap<(String, u64, String, String), (DetectabilityCondition, f64)> {
+    create_detectability_map_with_policy(results, DuplicatePolicy::First)
+        .map(|(map, _)| map)
+        .unwrap_or_default()
+}
+
+/// Create the merge map from results, resolving repeated variants by policy;
+/// also returns the number of duplicates
+pub fn create_detectability_map_with_policy(
+    results: &[DetectabilityResult],
+    policy: DuplicatePolicy,
+) -> VlodResult<(HashMap<(String, u64, String, String), (DetectabilityCondition, f64)>, usize)> {
+    let table = results_table(results, policy)?;
+    let duplicates = table.duplicates;
+    Ok((table.scores(), duplicates))
+}
+
+/// Merge detectability results directly into VCF without intermediate file
+pub fn merge_detectability_results_into_vcf<P: AsRef<Path>>(
+    vcf_path: P,
+    results: &[DetectabilityResult],
+    output_path: P,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let reader = open_text_input(&vcf_path, options.max_line_length)?;
+    let output_file = BufWriter::new(create_output_file(output_path)?);
+    merge_detectability_results_into_writer(reader, results, output_file, options)
+}
+
+/// Merge detectability results into VCF text read from `reader`, writing the
+/// annotated VCF to `writer`
+pub fn merge_detectability_results_into_writer<R: BufRead, W: Write>(
+    reader: R,
+    results: &[DetectabilityResult],
+    writer: W,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    merge_sample_results_into_writer(reader, results, &[], writer, options)
+}
+
+/// Merge detectability results into a multi-sample VCF: `results` as INFO fields
+/// and the results of each named sample as DET/DETS FORMAT fields of its column
+pub fn merge_sample_results_into_vcf<P: AsRef<Path>>(
+    vcf_path: P,
+    results: &[DetectabilityResult],
+    samples: &[(String, Vec<DetectabilityResult>)],
+    output_path: P,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let reader = open_text_input(&vcf_path, options.max_line_length)?;
+    let output_file = BufWriter::new(create_output_file(output_path)?);
+    merge_sample_results_into_writer(reader, results, samples, output_file, options)
+}
+
+/// Multi-sample merge of VCF text read from `reader`, writing the annotated VCF to
+/// `writer`; see `merge_sample_results_into_vcf`
+pub fn merge_sample_results_into_writer<R: BufRead, W: Write>(
+    reader: R,
+    results: &[DetectabilityResult],
+    samples: &[(String, Vec<DetectabilityResult>)],
+    writer: W,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let table = results_table(results, options.duplicate_policy)?;
+    let sample_tables = samples
+        .iter()
+        .map(|(sample, results)| Ok((sample.clone(), results_table(results, options.duplicate_policy)?)))
+        .collect::<VlodResult<Vec<_>>>()?;
+    let mut sample_lookups: Vec<(String, TableLookup<'_>)> = sample_tables
+        .iter()
+        .map(|(sample, table)| (sample.clone(), TableLookup::new(table, options)))
+        .collect();
+    annotate_vcf(reader, writer, &mut TableLookup::new(&table, options), &mut sample_lookups, options)
+}
+
+/// Annotate VCF text read from `reader` with results looked up record by record,
+/// writing the annotated VCF to `writer`
+pub(crate) fn merge_lookup_into_writer<R: BufRead, W: Write, L: ResultsLookup>(
+    reader: R,
+    writer: W,
+    results: &mut L,
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    annotate_vcf(reader, writer, results, &mut [], options)
+}
+
+/// Results table of scored results, repeated variants resolved by policy
+pub(crate) fn results_table(results: &[DetectabilityResult], policy: DuplicatePolicy) -> VlodResult<ResultsTable> {
+    let mut rows = HashMap::new();
+    let mut duplicates = 0;
+    for result in results {
+        let key = (
+            result.variant.chrom.clone(),
+            result.variant.pos,
+            result.variant.ref_allele.clone(),
+            result.variant.alt_allele.clone(),
+        );
+        if insert_resolved(&mut rows, key, result.into(), policy)? {
+            duplicates += 1;
+        }
+    }
+    Ok(ResultsTable::new(rows, duplicates))
+}
+
+/// Depth a VCF record reports: the sample depth from the caller's FORMAT fields
+/// for Strelka2 and VarDict, whose INFO has no (or a pooled) depth, otherwise
+/// INFO DP or summed AD, falling back to FORMAT
+fn vcf_record_depth(caller: Caller, line: &str, info: &str) -> Option<u32> {
+    let format_depth = || caller.record_depths(line).map(|depths| depths.depth);
+    match caller {
+        Caller::Strelka2 | Caller::VarDict => format_depth().or_else(|| vcf_info_depth(info)),
+        _ => vcf_info_depth(info).or_else(format_depth),
+    }
+}
+
+/// Depth a VCF record reports in INFO: DP, or the sum of AD when DP is absent
+fn vcf_info_depth(info: &str) -> Option<u32> {
+    let value = |key: &str| {
+        info.split(';')
+            .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
     };
+    if let Some(depth) = value("DP").and_then(|depth| depth.parse().ok()) {
+        return Some(depth);
+    }
+    value("AD").and_then(|counts| counts.split(',').map(|count| count.parse::<u32>().ok()).sum())
+}
+
+/// BAM coverage to VCF depth ratios of one contig
+struct ContigDepthRatios {
+    contig: String,
+    ratios: Vec<f64>,
+    discordant: usize,
+}
+
+/// Summary table of depth discordance per contig, in VCF order
+#[derive(Default)]
+struct DepthDiscordance {
+    contigs: Vec<ContigDepthRatios>,
+}
+
+impl DepthDiscordance {
+    fn record(&mut self, contig: &str, ratio: f64, discordant: bool) {
+        let index = match self.contigs.iter().rposition(|entry| entry.contig == contig) {
+            Some(index) => index,
+            None => {
+                self.contigs.push(ContigDepthRatios {
+                    contig: contig.to_string(),
+                    ratios: Vec::new(),
+                    discordant: 0,
+                });
+                self.contigs.len() - 1
+            }
+        };
+        let entry = &mut self.contigs[index];
+        entry.ratios.push(ratio);
+        entry.discordant += discordant as usize;
+    }
+
+    /// Log compared and discordant records with the median ratio per contig, and
+    /// warn when most records disagree
+    fn log_table(&mut self, fold: f64) {
+        if self.contigs.is_empty() {
+            return;
+        }
+        log::info!(
+            "Depth discordance (BAM coverage / VCF depth, flagged outside {:.2}-{:.2}):",
+            1.0 / fold,
+            fold
+        );
+        log::info!("  {:<16} {:>10} {:>11} {:>13}", "Contig", "Compared", "Discordant", "Median_Ratio");
+        let (mut compared, mut discordant) = (0, 0);
+        for entry in &mut self.contigs {
+            entry.ratios.sort_by(f64::total_cmp);
+            let median = entry.ratios[entry.ratios.len() / 2];
+            log::info!(
+                "  {:<16} {:>10} {:>11} {:>13.3}",
+                entry.contig,
+                entry.ratios.len(),
+                entry.discordant,
+                median
+            );
+            compared += entry.ratios.len();
+            discordant += entry.discordant;
+        }
+        if discordant * 2 > compared {
+            log::warn!(
+                "BAM coverage disagrees with the VCF depth for {} of {} records; \
+                 check that the BAM is the one the variants were called from",
+                discordant,
+                compared
+            );
+        }
+    }
+}
 
-    let mut output_file = File::create(output_path)?;
+/// Copy a VCF from `reader` to `writer`, adding the DET/DETS(/DETP/DETOB/DETFS/DETSOR/DETDPD) header
+/// lines and annotating each record that has a detectability result, and with
+/// `samples`, the DET/DETS FORMAT fields of their sample columns
+fn annotate_vcf<R: BufRead, W: Write, L: ResultsLookup>(
+    reader: R,
+    mut output_file: W,
+    results: &mut L,
+    samples: &mut [(String, TableLookup<'_>)],
+    options: &MergeOptions,
+) -> VlodResult<MergeStats> {
+    let policy = options.duplicate_policy;
     let mut info_added = false;
     let mut info_column_index = None;
+    // FORMAT column, and the position of each sample in `samples` among the sample columns
+    let mut format_column_index = None;
+    let mut sample_columns: Vec<Option<usize>> = Vec::new();
+    let mut seen_records = HashSet::new();
+    let mut seen_position: Option<(String, u64)> = None;
+    let mut duplicate_records = 0;
+    let mut has_contig_headers = false;
+    let mut aliased_records = 0;
+    let mut stats = MergeStats::default();
+    let mut depth_discordance = DepthDiscordance::default();
+    let mut caller = options.caller;
+    // INFO fields this merge writes; earlier definitions of them are replaced
+    let mut info_ids = vec!["DET", "DETS"];
+    if results.has_probabilities() {
+        info_ids.push("DETP");
+    }
+    if options.orientation_info {
+        info_ids.push("DETOB");
+    }
+    if options.strand_info {
+        info_ids.extend(["DETFS", "DETSOR"]);
+    }
+    if options.depth_discordance_fold.is_some() {
+        info_ids.push("DETDPD");
+    }
+    if let Some(fold) = options.depth_discordance_fold.filter(|fold| fold.is_nan() || *fold <= 1.0) {
+        return Err(VlodError::InvalidConfig(format!(
+            "depth discordance fold must be greater than 1, got {}",
+            fold
+        )));
+    }
 
     for line in reader.lines() {
         let line = line?;
         
+        if line.starts_with("##contig=") {
+            has_contig_headers = true;
+        }
+        if line.starts_with("##") {
+            caller = caller.resolve(&line);
+        }
+
+        // Run metadata of an earlier annotation is replaced by this run's
+        if line.starts_with("##vlod") {
+            continue;
+        }
+
         if line.starts_with("#CHROM") {
+            let about = About { detection_threshold: options.score_threshold, ..about() };
+            writeln!(output_file, "##vlodAbout={}", about.to_json())?;
+            if !has_contig_headers {
+                write_contig_headers(&mut output_file, &options.contigs)?;
+            }
+            if !samples.is_empty() {
+                writeln!(
+                    output_file,
+                    "##FORMAT=<ID=DET,Number=1,Type=String,Description=\"Detectability status of the sample\">"
+                )?;
+                writeln!(
+                    output_file,
+                    "##FORMAT=<ID=DETS,Number=1,Type=Float,Description=\"Detectability Score of the sample\">"
+                )?;
+            }
+            for header_line in &options.header_lines {
+                writeln!(output_file, "{}", header_line)?;
+            }
             // Find the INFO column index
             let header: Vec<&str> = line.split('\t').collect();
             info_column_index = header.iter().position(|&col| col == "INFO");
+            if !samples.is_empty() {
+                format_column_index = header.iter().position(|&col| col == "FORMAT");
+                let Some(format_idx) = format_column_index else {
+                    return Err(VlodError::InvalidConfig(
+                        "per-sample detectability needs a VCF with sample columns".to_string(),
+                    ));
+                };
+                let columns = &header[format_idx + 1..];
+                sample_columns = vec![None; columns.len()];
+                for (index, (sample, _)) in samples.iter().enumerate() {
+                    let column = columns.iter().position(|column| column == sample).ok_or_else(|| {
+                        VlodError::InvalidConfig(format!("sample '{}' is not a column of the VCF", sample))
+                    })?;
+                    sample_columns[column] = Some(index);
+                }
+            }
             writeln!(output_file, "{}", line)?;
             continue;
         }
 
-        if line.starts_with("##INFO") {
-            writeln!(output_file, "{}", line)?;
+        // Earlier DET/DETS FORMAT definitions are replaced by the ones written above
+        if !samples.is_empty()
+            && line
+                .strip_prefix("##FORMAT=<ID=")
+                .and_then(|rest| rest.split(',').next())
+                .is_some_and(|id| DETECTABILITY_FORMAT_IDS.contains(&id))
+        {
+            continue;
+        }
+
+        if line.starts_with("##INFO") && options.info_fields {
+            let replaced = line
+                .strip_prefix("##INFO=<ID=")
+                .and_then(|rest| rest.split(',').next())
+                .is_some_and(|id| info_ids.contains(&id));
+            if !replaced {
+                writeln!(output_file, "{}", line)?;
+            }
             if !info_added {
                 writeln!(
                     output_file,
-                    "##INFO=<ID=DET,Number=1,Type=String,Description=\"Detectability status (Yes if detectable, No if non-detectable)\">"
+                    "##INFO=<ID=DET,Number=1,Type=String,Description=\"Detectability status (Yes, No, NoCoverage, Monomorphic, REF_CONFIRMED, REF_UNCONFIRMED, CoverageOnly, NotAssessable or Failed)\">"
                 )?;
                 writeln!(
                     output_file,
                     "##INFO=<ID=DETS,Number=1,Type=Float,Description=\"Detectability Score\">"
                 )?;
+                if results.has_probabilities() {
+                    writeln!(
+                        output_file,
+                        "##INFO=<ID=DETP,Number=1,Type=Float,Description=\"Calibrated probability of detection\">"
+                    )?;
+                }
+                if options.orientation_info {
+                    writeln!(
+                        output_file,
+                        "##INFO=<ID=DETOB,Number=1,Type=Float,Description=\"Fraction of ALT reads in F1R2 orientation\">"
+                    )?;
+                }
+                if options.strand_info {
+                    writeln!(
+                        output_file,
+                        "##INFO=<ID=DETFS,Number=1,Type=Float,Description=\"Phred-scaled p-value of Fisher's exact test for strand bias of the ALT reads\">"
+                    )?;
+                    writeln!(
+                        output_file,
+                        "##INFO=<ID=DETSOR,Number=1,Type=Float,Description=\"Symmetric odds ratio of REF and ALT reads by strand (strand bias)\">"
+                    )?;
+                }
+                if options.depth_discordance_fold.is_some() {
+                    writeln!(
+                        output_file,
+                        "##INFO=<ID=DETDPD,Number=1,Type=Float,Description=\"BAM coverage divided by the VCF depth (INFO DP or summed AD, or the caller's FORMAT depth), when they differ by more than the discordance fold\">"
+                    )?;
+                }
                 info_added = true;
             }
             continue;
@@ -115,150 +1101,213 @@ pub fn merge_detectability_into_vcf<P: AsRef<Path>>(
             continue;
         }
 
+        if stats.records() > 0 && stats.records() % MERGE_PROGRESS_INTERVAL == 0 {
+            log::info!("Merged {} VCF records ({} annotated)", stats.records(), stats.annotated);
+        }
+
         // Process data lines
         let mut columns: Vec<String> = line.split('\t').map(|s| s.to_string()).collect();
         
-        if columns.len() < 8 {
-            writeln!(output_file, "{}", line)?;
-            continue;
-        }
+        let pos = columns.get(1).and_then(|pos| pos.parse::<u64>().ok());
+        let pos = match pos {
+            Some(pos) if columns.len() >= 8 => pos,
+            _ => {
+                stats.malformed += 1;
+                stats.passed_through += 1;
+                writeln!(output_file, "{}", line)?;
+                continue;
+            }
+        };
 
         let chrom = columns[0].clone();
-        let pos = columns[1].parse::<u32>().unwrap_or(0);
         let ref_allele = columns[3].clone();
-        let alt_allele = columns[4].clone();
+        let alt_allele = caller.record_alt(&columns[4]);
 
         let vcf_id = (chrom, pos, ref_allele, alt_allele);
 
-        if let Some((condition, score)) = detectability_data.get(&vcf_id) {
-            let info_idx = info_column_index.unwrap_or(7);
-            
-            if info_idx < columns.len() {
-                let new_info = format!("{};DET={};DETS={}", columns[info_idx], condition, score);
+        let new_position = seen_position.as_ref().is_none_or(|(chrom, seen)| *seen != pos || *chrom != vcf_id.0);
+        if results.streams() && new_position {
+            seen_records.clear();
+            seen_position = Some((vcf_id.0.clone(), pos));
+        }
+        if !seen_records.insert(vcf_id.clone()) {
+            if policy == DuplicatePolicy::Error {
+                return Err(VlodError::InvalidVariant(format!(
+                    "Duplicate VCF record: {}:{} {}>{}",
+                    vcf_id.0, vcf_id.1, vcf_id.2, vcf_id.3
+                )));
+            }
+            duplicate_records += 1;
+        }
+
+        let info_idx = info_column_index.unwrap_or(7);
+        if options.keep_annotated && columns.get(info_idx).is_some_and(|info| has_detectability_info(info)) {
+            stats.kept += 1;
+            stats.passed_through += 1;
+            writeln!(output_file, "{}", line)?;
+            continue;
+        }
+
+        if let Some((fields, aliased)) = results.lookup(&vcf_id)? {
+            if aliased {
+                aliased_records += 1;
+            }
+
+            if !options.info_fields {
+                stats.annotated += 1;
+            } else if info_idx < columns.len() {
+                let mut new_info = format!(
+                    "{};DET={};DETS={}",
+                    strip_detectability_info(&columns[info_idx]),
+                    fields.condition.vcf_status(),
+                    fields.score
+                );
+                if let Some(probability) = fields.probability {
+                    new_info.push_str(&format!(";DETP={}", probability));
+                }
+                if let Some(bias) = fields.orientation_bias.filter(|_| options.orientation_info) {
+                    new_info.push_str(&format!(";DETOB={:.3}", bias));
+                }
+                if let Some((fs, sor)) = fields.strand_bias.filter(|_| options.strand_info) {
+                    new_info.push_str(&format!(";DETFS={:.3};DETSOR={:.3}", fs, sor));
+                }
+                if let Some(fold) = options.depth_discordance_fold {
+                    let vcf_depth = vcf_record_depth(caller, &line, &columns[info_idx]).filter(|&depth| depth > 0);
+                    if let (Some(vcf_depth), Some(coverage)) = (vcf_depth, fields.coverage) {
+                        let ratio = coverage as f64 / vcf_depth as f64;
+                        let discordant = ratio > fold || ratio < 1.0 / fold;
+                        if discordant {
+                            new_info.push_str(&format!(";DETDPD={:.3}", ratio));
+                            stats.depth_discordant += 1;
+                        }
+                        stats.depth_compared += 1;
+                        depth_discordance.record(&columns[0], ratio, discordant);
+                    }
+                }
                 columns[info_idx] = new_info;
+                stats.annotated += 1;
+            } else {
+                stats.passed_through += 1;
+            }
+        } else {
+            stats.unmatched += 1;
+            stats.passed_through += 1;
+        }
+
+        if let Some(format_idx) = format_column_index.filter(|&index| index < columns.len()) {
+            let mut values = Vec::with_capacity(sample_columns.len());
+            for sample in &sample_columns {
+                let fields = match sample {
+                    Some(index) => samples[*index].1.lookup(&vcf_id)?.map(|(fields, _)| fields),
+                    None => None,
+                };
+                values.push(fields.map(|fields| (fields.condition.vcf_status().to_string(), fields.score.to_string())));
             }
+            set_detectability_format(&mut columns, format_idx, &values);
         }
 
         writeln!(output_file, "{}", columns.join("\t"))?;
     }
+    output_file.flush()?;
 
-    Ok(())
-}
-
-/// Create detectability results from a vector of DetectabilityResult
-pub fn create_detectability_map(
-    results: &[DetectabilityResult],
-) -> HashMap<(String, u32, String, String), (String, f64)> {
-    let mut map = HashMap::new();
-    
-    for result in results {
-        let key = (
-            result.variant.chrom.clone(),
-            result.variant.pos,
-            result.variant.ref_allele.clone(),
-            result.variant.alt_allele.clone(),
-        );
-        
-        let condition = if result.detectability_condition == "Detectable" {
-            "Yes".to_string()
-        } else {
-            "No".to_string()
-        };
-        
-        map.insert(key, (condition, result.detectability_score));
+    log_merge_report(results.duplicates(), duplicate_records, aliased_records, policy);
+    if let Some(fold) = options.depth_discordance_fold {
+        depth_discordance.log_table(fold);
     }
-    
-    map
-}
-
-/// Merge detectability results directly into VCF without intermediate file
-pub fn merge_detectability_results_into_vcf<P: AsRef<Path>>(
-    vcf_path: P,
-    results: &[DetectabilityResult],
-    output_path: P,
-) -> VlodResult<()> {
-    let detectability_data = create_detectability_map(results);
+    warn_suspicious_merge(&stats, results.result_count());
 
-    let file = File::open(&vcf_path)
-        .map_err(|_| VlodError::FileNotFound(vcf_path.as_ref().to_string_lossy().to_string()))?;
+    Ok(stats)
+}
 
-    let reader: Box<dyn BufRead> = if is_gzipped(&vcf_path)? {
-        let gz_decoder = MultiGzDecoder::new(file);
-        Box::new(BufReader::new(gz_decoder))
+/// INFO column without the DET* fields of an earlier annotation
+fn strip_detectability_info(info: &str) -> String {
+    let kept: Vec<&str> = info
+        .split(';')
+        .filter(|field| !DETECTABILITY_INFO_IDS.contains(&field.split('=').next().unwrap_or(field)))
+        .collect();
+    if kept.is_empty() {
+        ".".to_string()
     } else {
-        Box::new(BufReader::new(file))
-    };
-
-    let mut output_file = File::create(output_path)?;
-    let mut info_added = false;
-    let mut info_column_index = None;
-
-    for line in reader.lines() {
-        let line = line?;
-        
-        if line.starts_with("#CHROM") {
-            // Find the INFO column index
-            let header: Vec<&str> = line.split('\t').collect();
-            info_column_index = header.iter().position(|&col| col == "INFO");
-            writeln!(output_file, "{}", line)?;
-            continue;
-        }
-
-        if line.starts_with("##INFO") {
-            writeln!(output_file, "{}", line)?;
-            if !info_added {
-                writeln!(
-                    output_file,
-                    "##INFO=<ID=DET,Number=1,Type=String,Description=\"Detectability status (Yes if detectable, No if non-detectable)\">"
-                )?;
-                writeln!(
-                    output_file,
-                    "##INFO=<ID=DETS,Number=1,Type=Float,Description=\"Detectability Score\">"
-                )?;
-                info_added = true;
-            }
-            continue;
-        }
-
-        if line.starts_with("##") || line.starts_with("#") {
-            writeln!(output_file, "{}", line)?;
-            continue;
-        }
-
-        // Process data lines
-        let mut columns: Vec<String> = line.split('\t').map(|s| s.to_string()).collect();
-        
-        if columns.len() < 8 {
-            writeln!(output_file, "{}", line)?;
-            continue;
-        }
-
-        let chrom = columns[0].clone();
-        let pos = columns[1].parse::<u32>().unwrap_or(0);
-        let ref_allele = columns[3].clone();
-        let alt_allele = columns[4].clone();
+        kept.join(";")
+    }
+}
 
-        let vcf_id = (chrom, pos, ref_allele, alt_allele);
+/// Replace the DET/DETS FORMAT fields of a record with the (status, score) of each
+/// sample column, "." for samples without a result
+fn set_detectability_format(columns: &mut [String], format_idx: usize, values: &[Option<(String, String)>]) {
+    let format_column = columns[format_idx].clone();
+    let keys: Vec<&str> = format_column.split(':').collect();
+    let kept: Vec<usize> = (0..keys.len())
+        .filter(|&index| keys[index] != "." && !DETECTABILITY_FORMAT_IDS.contains(&keys[index]))
+        .collect();
+    let mut format: Vec<&str> = kept.iter().map(|&index| keys[index]).collect();
+    format.extend(DETECTABILITY_FORMAT_IDS);
+    let format = format.join(":");
 
-        if let Some((condition, score)) = detectability_data.get(&vcf_id) {
-            let info_idx = info_column_index.unwrap_or(7);
-            
-            if info_idx < columns.len() {
-                let new_info = format!("{};DET={};DETS={}", columns[info_idx], condition, score);
-                columns[info_idx] = new_info;
-            }
+    for (offset, value) in values.iter().enumerate() {
+        let Some(column) = columns.get_mut(format_idx + 1 + offset) else {
+            break;
+        };
+        // Trailing sample fields may be omitted; they are filled in before DET/DETS
+        let fields: Vec<&str> = column.split(':').collect();
+        let mut sample: Vec<&str> = kept.iter().map(|&index| fields.get(index).copied().unwrap_or(".")).collect();
+        match value {
+            Some((status, score)) => sample.extend([status.as_str(), score.as_str()]),
+            None => sample.extend([".", "."]),
         }
+        let annotated = sample.join(":");
+        *column = annotated;
+    }
+    columns[format_idx] = format;
+}
 
-        writeln!(output_file, "{}", columns.join("\t"))?;
+/// Report duplicates resolved and contig aliases applied during a merge
+/// Warn when the counts suggest the results do not belong to this VCF
+fn warn_suspicious_merge(stats: &MergeStats, results: Option<usize>) {
+    if stats.malformed > 0 {
+        log::warn!("{} malformed VCF records were passed through unchanged", stats.malformed);
+    }
+    if results != Some(0) && stats.records() > 0 && stats.annotated == 0 {
+        log::warn!(
+            "None of the {} VCF records matched the {} detectability results; \
+             check that the results were computed for this VCF",
+            stats.records(),
+            results.map(|count| count.to_string()).unwrap_or_else(|| "indexed".to_string())
+        );
     }
+}
 
-    Ok(())
+fn log_merge_report(
+    duplicate_results: usize,
+    duplicate_records: usize,
+    aliased_records: usize,
+    policy: DuplicatePolicy,
+) {
+    if duplicate_results > 0 {
+        log::warn!(
+            "{} duplicate detectability results resolved with the '{}' policy",
+            duplicate_results,
+            policy
+        );
+    }
+    if duplicate_records > 0 {
+        log::warn!(
+            "{} duplicate VCF records were annotated identically",
+            duplicate_records
+        );
+    }
+    if aliased_records > 0 {
+        log::warn!(
+            "{} VCF records matched results only after contig name aliasing (e.g. chr1 vs 1)",
+            aliased_records
+        );
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Variant;
+    use crate::{DetectabilityCondition, OrientationCounts, StrandCounts, Variant};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -272,8 +1321,33 @@ mod tests {
         let results = read_detectability_results(temp_file.path()).unwrap();
         
         assert_eq!(results.len(), 2);
-        assert_eq!(results.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())), Some(&("Yes".to_string(), 3.5)));
-        assert_eq!(results.get(&("chr2".to_string(), 200, "G".to_string(), "C".to_string())), Some(&("No".to_string(), 1.2)));
+        assert_eq!(results.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())), Some(&(DetectabilityCondition::Detectable, 3.5)));
+        assert_eq!(results.get(&("chr2".to_string(), 200, "G".to_string(), "C".to_string())), Some(&(DetectabilityCondition::NonDetectable, 1.2)));
+    }
+
+    #[test]
+    fn test_read_annotated_vcf() {
+        let vcf = "##fileformat=VCFv4.2\n##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\nchr1\t200\t.\tC\tG\t.\tPASS\tDP=8\n";
+        let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        let mut result = DetectabilityResult::new(variant, 3.5, DetectabilityCondition::Detectable, 30, 4);
+        result.detection_probability = Some(0.9);
+
+        let mut annotated = NamedTempFile::new().unwrap();
+        let options = MergeOptions::default();
+        merge_detectability_results_into_writer(vcf.as_bytes(), &[result.clone()], &mut annotated, &options).unwrap();
+        assert!(is_annotated_vcf(annotated.path()).unwrap());
+
+        // The record without DET is skipped
+        let results = read_annotated_vcf(annotated.path()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].variant, result.variant);
+        assert_eq!(results[0].detectability_condition, DetectabilityCondition::Detectable);
+        assert_eq!((results[0].detectability_score, results[0].detection_probability), (3.5, Some(0.9)));
+
+        let map = read_detectability_results(annotated.path()).unwrap();
+        assert_eq!(map.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())), Some(&(DetectabilityCondition::Detectable, 3.5)));
     }
 
     #[test]
@@ -282,7 +1356,7 @@ mod tests {
         let result = DetectabilityResult::new(
             variant,
             3.5,
-            "Detectable".to_string(),
+            DetectabilityCondition::Detectable,
             30,
             15,
         );
@@ -290,7 +1364,7 @@ mod tests {
         let map = create_detectability_map(&[result]);
         
         assert_eq!(map.len(), 1);
-        assert_eq!(map.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())), Some(&("Yes".to_string(), 3.5)));
+        assert_eq!(map.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())), Some(&(DetectabilityCondition::Detectable, 3.5)));
     }
 
     #[test]
@@ -313,6 +1387,7 @@ mod tests {
             vcf_file.path(),
             detectability_file.path(),
             output_file.path(),
+            &MergeOptions::default(),
         ).unwrap();
         
         // Read the output and verify
@@ -321,5 +1396,623 @@ mod tests {
         assert!(output_content.contains("DETS=3.5"));
         assert!(output_content.contains("##INFO=<ID=DET,Number=1,Type=String"));
         assert!(output_content.contains("##INFO=<ID=DETS,Number=1,Type=Float"));
+        assert!(!output_content.contains("DETP"));
+    }
+
+    #[test]
+    fn test_annotate_in_place() {
+        let mut detectability_file = NamedTempFile::new().unwrap();
+        writeln!(detectability_file, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\tVariant_Reads").unwrap();
+        writeln!(detectability_file, "chr1\t100\tA\tT\t3.5\tDetectable\t30\t15").unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let vcf = dir.path().join("calls.vcf.gz");
+        let mut writer = rust_htslib::bgzf::Writer::from_path(&vcf).unwrap();
+        writeln!(writer, "##fileformat=VCFv4.2").unwrap();
+        writeln!(writer, "##contig=<ID=chr1,length=1000>").unwrap();
+        writeln!(writer, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(writer, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        drop(writer);
+        build_vcf_index(&vcf, 0).unwrap();
+        let index = append_extension(&vcf, "tbi");
+        let original_index = std::fs::read(&index).unwrap();
+
+        let stats = annotate_in_place(&vcf, |output| {
+            merge_detectability_into_vcf(vcf.as_path(), detectability_file.path(), output, &MergeOptions::default())
+        })
+        .unwrap();
+        assert_eq!(stats.annotated, 1);
+        assert!(crate::utils::is_bgzipped(&vcf).unwrap());
+        let mut annotated = String::new();
+        use std::io::Read;
+        open_text_input(&vcf, usize::MAX).unwrap().read_to_string(&mut annotated).unwrap();
+        assert!(annotated.contains("DP=30;DET=Yes;DETS=3.5"));
+        assert_ne!(std::fs::read(&index).unwrap(), original_index);
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(leftovers.len(), 2, "{:?}", leftovers);
+
+        // A failed annotation leaves the original as it was
+        let before = std::fs::read(&vcf).unwrap();
+        assert!(annotate_in_place(&vcf, |_| Err(VlodError::InvalidConfig("failed".to_string()))).is_err());
+        assert_eq!(std::fs::read(&vcf).unwrap(), before);
+    }
+
+    #[test]
+    fn test_merge_detection_probability() {
+        let mut detectability_file = NamedTempFile::new().unwrap();
+        writeln!(detectability_file, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\tVariant_Reads\tDetection_Probability").unwrap();
+        writeln!(detectability_file, "chr1\t100\tA\tT\t3.5\tDetectable\t30\t15\t0.97").unwrap();
+        writeln!(detectability_file, "chr1\t200\tG\tC\t1.5\tNon-detectable\t30\t2\t.").unwrap();
+
+        let probabilities = read_detection_probabilities(detectability_file.path(), DuplicatePolicy::First).unwrap();
+        assert_eq!(probabilities.len(), 1);
+
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth\">").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(vcf_file, "chr1\t200\t.\tG\tC\t.\tPASS\tDP=30").unwrap();
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_into_vcf(
+            vcf_file.path(),
+            detectability_file.path(),
+            output_file.path(),
+            &MergeOptions::default(),
+        ).unwrap();
+
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(output_content.contains("##INFO=<ID=DETP,Number=1,Type=Float"));
+        assert!(output_content.contains("DP=30;DET=Yes;DETS=3.5;DETP=0.97"));
+        assert!(output_content.contains("DP=30;DET=No;DETS=1.5\n"));
+
+        // The direct path takes probabilities from the results themselves
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        );
+        result.detection_probability = Some(0.9);
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &[result], output_file.path(), &MergeOptions::default()).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(output_content.contains("DET=Yes;DETS=3.5;DETP=0.9"));
+    }
+
+    #[test]
+    fn test_duplicate_results_policy() {
+        let mut detectability_file = NamedTempFile::new().unwrap();
+        writeln!(detectability_file, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition").unwrap();
+        writeln!(detectability_file, "chr1\t100\tA\tT\t1.2\tNon-detectable").unwrap();
+        writeln!(detectability_file, "chr1\t100\tA\tT\t3.5\tDetectable").unwrap();
+        let key = ("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+
+        let (data, duplicates) = read_detectability_results_with_policy(detectability_file.path(), DuplicatePolicy::First).unwrap();
+        assert_eq!(duplicates, 1);
+        assert_eq!(data.get(&key), Some(&(DetectabilityCondition::NonDetectable, 1.2)));
+
+        let (data, _) = read_detectability_results_with_policy(detectability_file.path(), DuplicatePolicy::Max).unwrap();
+        assert_eq!(data.get(&key), Some(&(DetectabilityCondition::Detectable, 3.5)));
+
+        let result = read_detectability_results_with_policy(detectability_file.path(), DuplicatePolicy::Error);
+        assert!(matches!(result, Err(VlodError::InvalidVariant(_))));
+
+        // Every merged field comes from the winning row
+        let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        let mut low = DetectabilityResult::new(variant.clone(), 1.2, DetectabilityCondition::NonDetectable, 40, 2);
+        low.detection_probability = Some(0.99);
+        let mut high = DetectabilityResult::new(variant, 3.5, DetectabilityCondition::Detectable, 30, 15);
+        high.detection_probability = Some(0.9);
+        let fields = results_table(&[low.clone(), high.clone()], DuplicatePolicy::Max).unwrap().fields(&key).unwrap();
+        assert_eq!((fields.score, fields.probability, fields.coverage), (3.5, Some(0.9), Some(30)));
+        let fields = results_table(&[low, high], DuplicatePolicy::First).unwrap().fields(&key).unwrap();
+        assert_eq!((fields.score, fields.probability, fields.coverage), (1.2, Some(0.99), Some(40)));
+    }
+
+    #[test]
+    fn test_duplicate_vcf_records_policy() {
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=31").unwrap();
+
+        let results = vec![DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        )];
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &results, output_file.path(), &MergeOptions::default()).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert_eq!(output_content.matches("DET=Yes;DETS=3.5").count(), 2);
+
+        let options = MergeOptions {
+            duplicate_policy: DuplicatePolicy::Error,
+            ..MergeOptions::default()
+        };
+        let output_file = NamedTempFile::new().unwrap();
+        let result = merge_detectability_results_into_vcf(vcf_file.path(), &results, output_file.path(), &options);
+        assert!(matches!(result, Err(VlodError::InvalidVariant(_))));
+    }
+
+    #[test]
+    fn test_merge_dragen_non_ref() {
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t90\t.\tG\t<NON_REF>\t.\tPASS\tEND=99").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT,<NON_REF>\t.\tPASS\tDP=30").unwrap();
+        let results = vec![DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        )];
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &results, output_file.path(), &MergeOptions::default()).unwrap();
+        assert!(!std::fs::read_to_string(output_file.path()).unwrap().contains("DET=Yes"));
+
+        let options = MergeOptions {
+            caller: Caller::Dragen,
+            ..MergeOptions::default()
+        };
+        merge_detectability_results_into_vcf(vcf_file.path(), &results, output_file.path(), &options).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(output_content.contains("chr1\t100\t.\tA\tT,<NON_REF>\t.\tPASS\tDP=30;DET=Yes;DETS=3.5"));
+        assert!(output_content.contains("chr1\t90\t.\tG\t<NON_REF>\t.\tPASS\tEND=99\n"));
+    }
+
+    #[test]
+    fn test_contig_headers_synthesized() {
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth\">").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+
+        let options = MergeOptions {
+            contigs: vec![("chr1".to_string(), 248956422), ("chr2".to_string(), 242193529)],
+            ..MergeOptions::default()
+        };
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &[], output_file.path(), &options).unwrap();
+
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        let lines: Vec<&str> = output_content.lines().collect();
+        let chrom_line = lines.iter().position(|l| l.starts_with("#CHROM")).unwrap();
+        assert_eq!(lines[chrom_line - 2], "##contig=<ID=chr1,length=248956422>");
+        assert_eq!(lines[chrom_line - 1], "##contig=<ID=chr2,length=242193529>");
+        assert!(lines[chrom_line - 3].starts_with("##vlodAbout={\"vlod_version\":"));
+
+        // Existing contig lines are propagated and not duplicated
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "##contig=<ID=chr1,length=1000>").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &[], output_file.path(), &options).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert_eq!(output_content.matches("##contig=").count(), 1);
+        assert!(output_content.contains("##contig=<ID=chr1,length=1000>"));
+    }
+
+    #[test]
+    fn test_extra_header_lines() {
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+
+        let options = MergeOptions {
+            header_lines: vec!["##vlodInput=<Role=bam,File=\"x.bam\",Size=4,MD5=abc>".to_string()],
+            ..MergeOptions::default()
+        };
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &[], output_file.path(), &options).unwrap();
+
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        let lines: Vec<&str> = output_content.lines().collect();
+        assert_eq!(lines[lines.len() - 2], options.header_lines[0]);
+        assert!(lines[lines.len() - 1].starts_with("#CHROM"));
+    }
+
+    #[test]
+    fn test_merge_contig_aliases() {
+        let mut detectability_file = NamedTempFile::new().unwrap();
+        writeln!(detectability_file, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition").unwrap();
+        writeln!(detectability_file, "1\t100\tA\tT\t3.5\tDetectable").unwrap();
+        writeln!(detectability_file, "MT\t50\tG\tA\t1.0\tNon-detectable").unwrap();
+
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(vcf_file, "chrM\t50\t.\tG\tA\t.\tPASS\tDP=30").unwrap();
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_into_vcf(
+            vcf_file.path(),
+            detectability_file.path(),
+            output_file.path(),
+            &MergeOptions::default(),
+        ).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(output_content.contains("chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;DET=Yes;DETS=3.5"));
+        assert!(output_content.contains("chrM\t50\t.\tG\tA\t.\tPASS\tDP=30;DET=No;DETS=1"));
+
+        let options = MergeOptions {
+            strict_contig_names: true,
+            ..MergeOptions::default()
+        };
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_into_vcf(
+            vcf_file.path(),
+            detectability_file.path(),
+            output_file.path(),
+            &options,
+        ).unwrap();
+        let output_content = std::fs::read_to_string(output_file.path()).unwrap();
+        assert!(!output_content.contains("DET="));
+    }
+
+    #[test]
+    fn test_merge_into_writer() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\nchr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n";
+        let results = vec![DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        )];
+
+        let mut output = Vec::new();
+        merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut output, &MergeOptions::default()).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.ends_with("chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;DET=Yes;DETS=3.5\n"));
+
+        // The TSV path can be driven entirely from memory as well
+        let tsv = "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tDetection_Probability\nchr1\t100\tA\tT\t1.5\tNon-detectable\t0.2\n";
+        let mut output = Vec::new();
+        merge_detectability_into_writer(vcf.as_bytes(), tsv.as_bytes(), &mut output, &MergeOptions::default()).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.ends_with("DP=30;DET=No;DETS=1.5;DETP=0.2\n"));
+    }
+
+    #[test]
+    fn test_reannotate_and_keep_annotated() {
+        let vcf = "##fileformat=VCFv4.2\n##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                   ##INFO=<ID=DET,Number=1,Type=String,Description=\"Old\">\n##INFO=<ID=DETS,Number=1,Type=Float,Description=\"Old\">\n\
+                   ##vlodAbout={}\n##vlodConfig=<Hash=abc>\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;DET=No;DETS=1\nchr1\t200\t.\tC\tG\t.\tPASS\tDP=30\n";
+        let result = |pos: u64, score: f64, condition: DetectabilityCondition| {
+            let ref_allele = if pos == 100 { "A" } else { "C" };
+            let alt_allele = if pos == 100 { "T" } else { "G" };
+            let variant = Variant::new("chr1".to_string(), pos, ref_allele.to_string(), alt_allele.to_string());
+            DetectabilityResult::new(variant, score, condition, 30, 10)
+        };
+        let results = vec![
+            result(100, 3.5, DetectabilityCondition::Detectable),
+            result(200, 1.5, DetectabilityCondition::NonDetectable),
+        ];
+
+        // Annotating again replaces the earlier fields and header lines
+        let mut output = Vec::new();
+        let stats =
+            merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut output, &MergeOptions::default())
+                .unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(stats.annotated, 2);
+        assert_eq!(output_content.matches("##INFO=<ID=DET,").count(), 1);
+        assert_eq!(output_content.matches("##vlodAbout=").count(), 1);
+        assert!(!output_content.contains("Description=\"Old\"") && !output_content.contains("##vlodConfig"));
+        assert!(output_content.contains("\tDP=30;DET=Yes;DETS=3.5\n"));
+        assert!(output_content.contains("\tDP=30;DET=No;DETS=1.5\n"));
+
+        // Incremental mode keeps the annotated record as it is
+        let options = MergeOptions { keep_annotated: true, ..MergeOptions::default() };
+        let mut output = Vec::new();
+        let stats = merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut output, &options).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!((stats.annotated, stats.kept, stats.passed_through), (1, 1, 1));
+        assert!(output_content.contains("\tDP=30;DET=No;DETS=1\n"));
+        assert!(output_content.contains("\tDP=30;DET=No;DETS=1.5\n"));
+        assert_eq!(strip_detectability_info("DET=Yes;DETS=3.5;DETOB=0.5"), ".");
+    }
+
+    #[test]
+    fn test_results_schema_versions() {
+        let key = ("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+
+        // Schema 2 locates columns by name
+        let tsv = "#vlod_version=0.1.0 #schema=2\nPos\tChrom\tRef\tAlt\tDetectability_Condition\tDetectability_Score\n100\tchr1\tA\tT\tDetectable\t3.5\n";
+        let (data, _) = read_detectability_results_from_reader(tsv.as_bytes(), DuplicatePolicy::First).unwrap();
+        assert_eq!(data.get(&key), Some(&(DetectabilityCondition::Detectable, 3.5)));
+
+        // Files without the metadata line are read as schema 1
+        let tsv = "Chrom\tPos\tRef\tAlt\tScore\tCondition\nchr1\t100\tA\tT\t1.5\tNon-detectable\n";
+        let (data, _) = read_detectability_results_from_reader(tsv.as_bytes(), DuplicatePolicy::First).unwrap();
+        assert_eq!(data.get(&key), Some(&(DetectabilityCondition::NonDetectable, 1.5)));
+
+        let tsv = "#vlod_version=9.0.0 #schema=99\nChrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\n";
+        let result = read_detectability_results_from_reader(tsv.as_bytes(), DuplicatePolicy::First);
+        assert!(matches!(result, Err(VlodError::InvalidVariant(_))));
+    }
+
+    #[test]
+    fn test_read_results_threshold() {
+        let mut tsv = NamedTempFile::new().unwrap();
+        writeln!(tsv, "#vlod_version=0.1.0 #schema=2 #about={{\"detection_threshold\":3.0,\"note\":\"a b\"}}").unwrap();
+        writeln!(tsv, "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition").unwrap();
+        assert_eq!(read_results_threshold(tsv.path(), DEFAULT_MAX_LINE_LENGTH).unwrap(), Some(3.0));
+
+        let mut tsv = NamedTempFile::new().unwrap();
+        writeln!(tsv, "#vlod_version=0.1.0 #schema=2").unwrap();
+        assert_eq!(read_results_threshold(tsv.path(), DEFAULT_MAX_LINE_LENGTH).unwrap(), None);
+        let mut tsv = NamedTempFile::new().unwrap();
+        writeln!(tsv, "Chrom\tPos\tRef\tAlt\tScore\tCondition").unwrap();
+        assert_eq!(read_results_threshold(tsv.path(), DEFAULT_MAX_LINE_LENGTH).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("3.5"), Some(3.5));
+        assert_eq!(parse_score("1.2e-3"), Some(0.0012));
+        assert_eq!(parse_score("2.5E+00"), Some(2.5));
+        assert_eq!(parse_score("3,5"), Some(3.5));
+        assert_eq!(parse_score("inf"), Some(f64::INFINITY));
+        assert!(parse_score("NaN").unwrap().is_nan());
+        assert!(parse_score("NA").unwrap().is_nan());
+        assert!(parse_score(".").unwrap().is_nan());
+        assert_eq!(parse_score("high"), None);
+        assert_eq!(parse_score("1,000,000"), None);
+
+        let tsv = "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\nchr1\t100\tA\tT\t-inf\tNon-detectable\nchr1\t200\tA\tT\t3.1e0\tDetectable\n";
+        let (data, _) = read_detectability_results_from_reader(tsv.as_bytes(), DuplicatePolicy::First).unwrap();
+        assert_eq!(
+            data.get(&("chr1".to_string(), 100, "A".to_string(), "T".to_string())),
+            Some(&(DetectabilityCondition::NonDetectable, NO_EVIDENCE_SCORE))
+        );
+        assert_eq!(
+            data.get(&("chr1".to_string(), 200, "A".to_string(), "T".to_string())),
+            Some(&(DetectabilityCondition::Detectable, 3.1))
+        );
+    }
+
+    #[test]
+    fn test_merge_max_line_length() {
+        let mut vcf_file = NamedTempFile::new().unwrap();
+        writeln!(vcf_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(vcf_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(vcf_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30;{}", "X".repeat(200)).unwrap();
+
+        let options = MergeOptions {
+            max_line_length: 100,
+            ..MergeOptions::default()
+        };
+        let output_file = NamedTempFile::new().unwrap();
+        let result = merge_detectability_results_into_vcf(vcf_file.path(), &[], output_file.path(), &options);
+        match result {
+            Err(VlodError::Io(e)) => assert!(e.to_string().contains("line 3 of")),
+            other => panic!("expected a line length error, got {:?}", other.map(|_| ())),
+        }
+
+        let output_file = NamedTempFile::new().unwrap();
+        merge_detectability_results_into_vcf(vcf_file.path(), &[], output_file.path(), &MergeOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_results_row_location() {
+        let tsv = "#vlod_version=0.1.0 #schema=2\n\
+                   Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\n\
+                   chr1\t100\tA\tT\t3.5\tDetectable\n\
+                   chr1\t200\tG\tC\tabc\tDetectable\n";
+        match read_detectability_results_from_reader(tsv.as_bytes(), DuplicatePolicy::First) {
+            Err(VlodError::Parse { line, column, .. }) => {
+                assert_eq!(line, 4);
+                assert_eq!(column.as_deref(), Some("Detectability_Score"));
+            }
+            other => panic!("expected a located parse error, got {:?}", other.map(|_| ())),
+        }
+
+        let options = MergeOptions {
+            max_errors: Some(5),
+            ..MergeOptions::default()
+        };
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\nchr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n";
+        let mut output = Vec::new();
+        merge_detectability_into_writer(vcf.as_bytes(), tsv.as_bytes(), &mut output, &options).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("DET=Yes"));
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let vcf = "##fileformat=VCFv4.2\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n\
+                   chr1\t200\t.\tG\tC\t.\tPASS\tDP=30\n\
+                   chr1\tabc\t.\tG\tC\t.\tPASS\tDP=30\n\
+                   chr1\t300\n";
+        let results = vec![DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            15,
+        )];
+
+        let mut output = Vec::new();
+        let stats =
+            merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut output, &MergeOptions::default())
+                .unwrap();
+        assert_eq!(
+            stats,
+            MergeStats {
+                annotated: 1,
+                unmatched: 1,
+                malformed: 2,
+                passed_through: 3,
+                ..MergeStats::default()
+            }
+        );
+        assert_eq!(stats.records(), 4);
+        assert!(String::from_utf8(output).unwrap().contains("chr1\tabc\t.\tG\tC\t.\tPASS\tDP=30\n"));
+    }
+
+    #[test]
+    fn test_merge_orientation_info() {
+        let vcf = "##fileformat=VCFv4.2\n##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\nchr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n";
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            4,
+        );
+        result.alt_orientation = OrientationCounts { f1r2: 3, f2r1: 1 };
+        let options = MergeOptions {
+            orientation_info: true,
+            ..MergeOptions::default()
+        };
+
+        let mut output = Vec::new();
+        merge_detectability_results_into_writer(vcf.as_bytes(), &[result], &mut output, &options).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("##INFO=<ID=DETOB,"));
+        assert!(output_content.ends_with("DP=30;DET=Yes;DETS=3.5;DETOB=0.750\n"));
+    }
+
+    #[test]
+    fn test_merge_strand_info() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\nchr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n";
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            3.5,
+            DetectabilityCondition::Detectable,
+            30,
+            10,
+        );
+        result.strand = StrandCounts { ref_forward: 10, ref_reverse: 10, alt_forward: 10, alt_reverse: 0 };
+        let options = MergeOptions {
+            strand_info: true,
+            ..MergeOptions::default()
+        };
+
+        let mut output = Vec::new();
+        merge_detectability_results_into_writer(vcf.as_bytes(), &[result.clone()], &mut output, &options).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("##INFO=<ID=DETFS,"));
+        assert!(output_content.contains("##INFO=<ID=DETSOR,"));
+        assert!(output_content.ends_with("DP=30;DET=Yes;DETS=3.5;DETFS=19.589;DETSOR=4.804\n"));
+
+        // Read back from the TSV columns
+        let mut tsv = Vec::new();
+        let config = crate::LodConfig::default();
+        crate::lod::write_detectability_results_to_writer(&[result], &mut tsv, &config).unwrap();
+        let table = parse_results_table(tsv.as_slice(), "results.tsv", DuplicatePolicy::default(), None).unwrap();
+        let key = ("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        let (fs, sor) = table.fields(&key).unwrap().strand_bias.unwrap();
+        assert!((fs - 19.589).abs() < 1e-3 && (sor - 4.804).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_merge_sample_format_fields() {
+        let vcf = "##fileformat=VCFv4.2\n##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                   ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+                   ##FORMAT=<ID=DET,Number=1,Type=String,Description=\"Old\">\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tTUMOR\tNORMAL\tOTHER\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\tGT:DET\t0/1:No\t0/0\t./.\n\
+                   chr1\t200\t.\tC\tG\t.\tPASS\tDP=30\tGT\t0/1\t0/0\t./.\n";
+        let result = |pos, score, condition| {
+            let variant = Variant::new("chr1".to_string(), pos, "A".to_string(), "T".to_string());
+            DetectabilityResult::new(variant, score, condition, 30, 4)
+        };
+        let tumor = vec![result(100, 3.5, DetectabilityCondition::Detectable)];
+        let normal = vec![result(100, -1.0, DetectabilityCondition::NonDetectable)];
+        let samples = vec![("TUMOR".to_string(), tumor.clone()), ("NORMAL".to_string(), normal)];
+
+        let mut output = Vec::new();
+        let stats =
+            merge_sample_results_into_writer(vcf.as_bytes(), &tumor, &samples, &mut output, &MergeOptions::default())
+                .unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(stats.annotated, 1);
+        assert_eq!(output_content.matches("##FORMAT=<ID=DET,").count(), 1);
+        assert!(output_content.contains("##FORMAT=<ID=DETS,"));
+        assert!(output_content.contains("DP=30;DET=Yes;DETS=3.5\tGT:DET:DETS\t0/1:Yes:3.5\t0/0:No:-1\t./.:.:.\n"));
+        assert!(output_content.ends_with("DP=30\tGT:DET:DETS\t0/1:.:.\t0/0:.:.\t./.:.:.\n"));
+
+        // FORMAT fields only, leaving INFO as it was
+        let options = MergeOptions { info_fields: false, ..MergeOptions::default() };
+        let mut output = Vec::new();
+        merge_sample_results_into_writer(vcf.as_bytes(), &tumor, &samples, &mut output, &options).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(!output_content.contains("##INFO=<ID=DET,"));
+        assert!(output_content.contains("\tDP=30\tGT:DET:DETS\t0/1:Yes:3.5\t"));
+
+        let missing = vec![("BLOOD".to_string(), Vec::new())];
+        assert!(merge_sample_results_into_writer(vcf.as_bytes(), &tumor, &missing, Vec::new(), &options).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_merge_depth_discordance() {
+        let vcf = "##fileformat=VCFv4.2\n##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n\
+                   chr1\t200\t.\tG\tC\t.\tPASS\tAD=100,20\n\
+                   chr1\t300\t.\tT\tA\t.\tPASS\t.\n";
+        let result = |pos: u64, coverage: u32| {
+            DetectabilityResult::new(
+                Variant::new("chr1".to_string(), pos, "A".to_string(), "T".to_string()),
+                3.5,
+                DetectabilityCondition::Detectable,
+                coverage,
+                4,
+            )
+        };
+        let mut results = vec![result(100, 35), result(200, 30), result(300, 50)];
+        results[1].variant.ref_allele = "G".to_string();
+        results[1].variant.alt_allele = "C".to_string();
+        results[2].variant.ref_allele = "T".to_string();
+        results[2].variant.alt_allele = "A".to_string();
+        let options = MergeOptions {
+            depth_discordance_fold: Some(DEFAULT_DEPTH_DISCORDANCE_FOLD),
+            ..MergeOptions::default()
+        };
+
+        let mut output = Vec::new();
+        let stats = merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut output, &options).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("##INFO=<ID=DETDPD,"));
+        assert!(output_content.contains("\tDP=30;DET=Yes;DETS=3.5\n"));
+        assert!(output_content.contains("\tAD=100,20;DET=Yes;DETS=3.5;DETDPD=0.250\n"));
+        assert_eq!((stats.depth_compared, stats.depth_discordant), (2, 1));
+
+        assert_eq!(vcf_info_depth("DPX=3;AD=4,5"), Some(9));
+        assert_eq!(vcf_info_depth("AD=4,."), None);
+
+        // Strelka2 somatic VCFs carry depths only in the TUMOR sample's tier-1 counts
+        let strelka = "##fileformat=VCFv4.1\n##source=strelka\n\
+                       #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tNORMAL\tTUMOR\n\
+                       chr1\t100\t.\tA\tT\t.\tPASS\tSOMATIC\tDP:AU:CU:GU:TU\t60:60,60:0,0:0,0:0,0\t10:8,8:0,0:0,0:2,2\n";
+        let mut output = Vec::new();
+        let stats = merge_detectability_results_into_writer(strelka.as_bytes(), &results[..1], &mut output, &options).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\tSOMATIC;DET=Yes;DETS=3.5;DETDPD=3.500\t"));
+        assert_eq!((stats.depth_compared, stats.depth_discordant), (1, 1));
+        let options = MergeOptions {
+            depth_discordance_fold: Some(1.0),
+            ..MergeOptions::default()
+        };
+        assert!(merge_detectability_results_into_writer(vcf.as_bytes(), &results, &mut Vec::new(), &options).is_err());
+    }
+}
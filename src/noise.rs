@@ -0,0 +1,212 @@
+//! Background noise spectrum: ALT fractions at non-variant panel positions, per
+//! substitution class
+
+use crate::{regions::BedRegion, Variant};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Percentiles reported for each substitution class
+pub const NOISE_PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// Fixed seed so repeated runs sample the same positions
+pub const NOISE_SAMPLING_SEED: u64 = 0x766c6f64;
+
+/// Sites with fewer reads are not sampled
+const NOISE_MIN_DEPTH: u32 = 20;
+
+/// Sites where a non-majority base reaches this fraction likely carry a real variant
+const NOISE_MAX_ALT_FRACTION: f64 = 0.1;
+
+/// Lower bound for class error rates, keeping the LOD finite at noise-free classes
+const MIN_ERROR_RATE: f64 = 1e-6;
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Base counts (A, C, G, T) at one reference position
+pub type BaseCounts = [u32; 4];
+
+/// Index of a base in `BaseCounts`
+pub fn base_index(base: u8) -> Option<usize> {
+    BASES.iter().position(|&b| b == base.to_ascii_uppercase())
+}
+
+/// Single-base substitution, e.g. `C>T`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubstitutionClass {
+    pub ref_base: u8,
+    pub alt_base: u8,
+}
+
+impl SubstitutionClass {
+    /// Substitution class of an SNV (None for other variant types)
+    pub fn of(variant: &Variant) -> Option<Self> {
+        match (variant.ref_allele.as_bytes(), variant.alt_allele.as_bytes()) {
+            ([ref_base], [alt_base]) => {
+                let ref_base = BASES[base_index(*ref_base)?];
+                let alt_base = BASES[base_index(*alt_base)?];
+                (ref_base != alt_base).then_some(SubstitutionClass { ref_base, alt_base })
+            }
+            _ => None,
+        }
+    }
+
+    /// C>T or G>A, the changes produced by cytosine deamination (e.g. in FFPE samples)
+    pub fn is_deamination(&self) -> bool {
+        matches!((self.ref_base, self.alt_base), (b'C', b'T') | (b'G', b'A'))
+    }
+}
+
+impl fmt::Display for SubstitutionClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}>{}", self.ref_base as char, self.alt_base as char)
+    }
+}
+
+/// Background ALT fractions collected at sampled non-variant positions
+#[derive(Debug, Clone, Default)]
+pub struct NoiseProfile {
+    fractions: BTreeMap<SubstitutionClass, Vec<f64>>,
+    sites: usize,
+}
+
+impl NoiseProfile {
+    /// Add a sampled position, taking the majority base as the reference. Returns
+    /// false when the site is too shallow or looks like a real variant.
+    pub fn add_site(&mut self, counts: &BaseCounts) -> bool {
+        let depth: u32 = counts.iter().sum();
+        if depth < NOISE_MIN_DEPTH {
+            return false;
+        }
+
+        let ref_index = (0..4).max_by_key(|&i| counts[i]).unwrap_or(0);
+        let fractions: Vec<(usize, f64)> = (0..4)
+            .filter(|&i| i != ref_index)
+            .map(|i| (i, counts[i] as f64 / depth as f64))
+            .collect();
+        if fractions.iter().any(|&(_, fraction)| fraction >= NOISE_MAX_ALT_FRACTION) {
+            return false;
+        }
+
+        for (alt_index, fraction) in fractions {
+            let class = SubstitutionClass {
+                ref_base: BASES[ref_index],
+                alt_base: BASES[alt_index],
+            };
+            self.fractions.entry(class).or_default().push(fraction);
+        }
+        self.sites += 1;
+        true
+    }
+
+    /// Number of positions that contributed to the profile
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+
+    /// Nearest-rank percentile of the background ALT fraction for a class
+    pub fn percentile(&self, class: SubstitutionClass, percentile: f64) -> Option<f64> {
+        let mut fractions = self.fractions.get(&class)?.clone();
+        fractions.sort_by(|a, b| a.total_cmp(b));
+        let rank = ((percentile / 100.0) * fractions.len() as f64).ceil() as usize;
+        fractions.get(rank.clamp(1, fractions.len()) - 1).copied()
+    }
+
+    /// Mean background ALT fraction of a class, used as its sequencing error rate
+    pub fn error_rate(&self, class: SubstitutionClass) -> Option<f64> {
+        let fractions = self.fractions.get(&class)?;
+        let mean = fractions.iter().sum::<f64>() / fractions.len() as f64;
+        Some(mean.max(MIN_ERROR_RATE))
+    }
+
+    /// Log the noise percentiles of every substitution class
+    pub fn log_summary(&self) {
+        log::info!("Background noise ({} sites):", self.sites);
+        for &class in self.fractions.keys() {
+            let percentiles: Vec<String> = NOISE_PERCENTILES
+                .iter()
+                .filter_map(|&p| self.percentile(class, p).map(|value| format!("p{}={:.2e}", p, value)))
+                .collect();
+            log::info!("  {}: {}", class, percentiles.join(" "));
+        }
+    }
+}
+
+/// Pick up to `count` distinct 0-based positions uniformly over the regions,
+/// deterministically from `seed`, sorted by region order and position
+pub fn sample_positions(regions: &[BedRegion], count: usize, seed: u64) -> Vec<(String, u64)> {
+    let total: u64 = regions.iter().map(|r| r.end - r.start).sum();
+    if total == 0 {
+        return Vec::new();
+    }
+
+    let mut state = seed;
+    let mut offsets: Vec<u64> = (0..count.min(total as usize))
+        .map(|_| splitmix64(&mut state) % total)
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut positions = Vec::with_capacity(offsets.len());
+    let mut region_start = 0;
+    let mut offsets = offsets.into_iter().peekable();
+    for region in regions {
+        let length = region.end - region.start;
+        while let Some(&offset) = offsets.peek() {
+            if offset >= region_start + length {
+                break;
+            }
+            positions.push((region.chrom.clone(), region.start + offset - region_start));
+            offsets.next();
+        }
+        region_start += length;
+    }
+    positions
+}
+
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_profile() {
+        let mut profile = NoiseProfile::default();
+        // 100 reads of C with one T
+        assert!(profile.add_site(&[0, 99, 0, 1]));
+        assert!(profile.add_site(&[0, 100, 0, 0]));
+        // Too shallow, and a likely heterozygous site
+        assert!(!profile.add_site(&[0, 10, 0, 0]));
+        assert!(!profile.add_site(&[0, 50, 0, 50]));
+        assert_eq!(profile.sites(), 2);
+
+        let c_to_t = SubstitutionClass { ref_base: b'C', alt_base: b'T' };
+        assert_eq!(c_to_t.to_string(), "C>T");
+        assert_eq!(profile.percentile(c_to_t, 50.0), Some(0.0));
+        assert_eq!(profile.percentile(c_to_t, 99.0), Some(0.01));
+        assert_eq!(profile.error_rate(c_to_t), Some(0.005));
+
+        let variant = Variant::new("chr1".to_string(), 100, "c".to_string(), "T".to_string());
+        assert_eq!(SubstitutionClass::of(&variant), Some(c_to_t));
+    }
+
+    #[test]
+    fn test_sample_positions() {
+        let regions = vec![
+            BedRegion::from_line("chr1\t100\t110").unwrap(),
+            BedRegion::from_line("chr2\t0\t5").unwrap(),
+        ];
+        let positions = sample_positions(&regions, 100, NOISE_SAMPLING_SEED);
+        assert!(positions.len() <= 15);
+        assert!(positions
+            .iter()
+            .all(|(chrom, pos)| regions.iter().any(|r| r.contains(chrom, *pos))));
+        assert_eq!(positions, sample_positions(&regions, 100, NOISE_SAMPLING_SEED));
+    }
+}
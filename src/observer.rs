@@ -0,0 +1,67 @@
+//! Hooks through which an embedding application follows a pipeline run, for
+//! progress displays, live dashboards and cancellation
+
+use crate::{bam::AlleleCounts, DetectabilityResult, Variant};
+use std::fmt;
+
+/// Stage of a pipeline run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    ReadingVariants,
+    Scoring,
+    Merging,
+    Finished,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::ReadingVariants => "reading variants",
+            Stage::Scoring => "scoring",
+            Stage::Merging => "merging",
+            Stage::Finished => "finished",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Progress after a chunk of variants was scored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkProgress {
+    /// VCF records in this chunk
+    pub variants: usize,
+    pub chunks_done: usize,
+    pub chunks_total: usize,
+}
+
+/// Callbacks from a pipeline run. Variants are scored in parallel, so the variant
+/// and chunk callbacks are called from worker threads, in no particular order.
+/// Every method has a no-op default.
+pub trait Observer: Send + Sync {
+    /// A stage begins
+    fn on_stage(&self, _stage: Stage) {}
+
+    /// A VCF record is about to be read from the BAM
+    fn on_variant_start(&self, _variant: &Variant) {}
+
+    /// A VCF record was read; `counts` are empty when it was not assessable
+    fn on_variant_done(&self, _variant: &Variant, _counts: &AlleleCounts) {}
+
+    /// An ALT allele of a VCF record was scored, as it will be in the run's results
+    fn on_result(&self, _result: &DetectabilityResult) {}
+
+    /// A chunk of variants was scored
+    fn on_chunk_done(&self, _progress: ChunkProgress) {}
+
+    /// Polled before each variant; once true, scoring stops and the results
+    /// completed so far are returned, as after an interrupt
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Observer that ignores every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
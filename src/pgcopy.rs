@@ -0,0 +1,262 @@
+//! PostgreSQL bulk-load export: results in COPY text format with typed NULLs,
+//! plus the DDL of the table they load into
+
+use crate::{
+    about::run_about,
+    bam::VafDefinition,
+    lod::format_amplicon_support,
+    utils::{append_extension, create_output_file},
+    DetectabilityResult, LodConfig, VlodError, VlodResult,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Table the DDL creates unless another name is given
+pub const DEFAULT_PG_TABLE: &str = "vlod_results";
+
+/// Columns of the exported table with their PostgreSQL types, in COPY order
+const PG_COLUMNS: [(&str, &str); 25] = [
+    ("chrom", "text NOT NULL"),
+    ("pos", "bigint NOT NULL"),
+    ("ref", "text NOT NULL"),
+    ("alt", "text NOT NULL"),
+    ("detectability_score", "double precision NOT NULL"),
+    ("detectability_condition", "text NOT NULL"),
+    ("coverage", "integer NOT NULL"),
+    ("variant_reads", "integer NOT NULL"),
+    ("softclip_support", "integer NOT NULL"),
+    ("assembly_support", "integer"),
+    ("amplicon_support", "text"),
+    ("single_amplicon_support", "boolean"),
+    ("detection_probability", "double precision"),
+    ("required_depth", "integer"),
+    ("alt_f1r2", "integer NOT NULL"),
+    ("alt_f2r1", "integer NOT NULL"),
+    ("orientation_bias", "double precision"),
+    ("pool_alleles", "text"),
+    ("pool_power", "double precision"),
+    ("overrides", "text"),
+    ("vaf_total", "double precision NOT NULL"),
+    ("vaf_ref_alt", "double precision NOT NULL"),
+    ("other_allele_fraction", "double precision NOT NULL"),
+    ("deleted_reads", "integer NOT NULL"),
+    ("short_fragment_fraction", "double precision NOT NULL"),
+];
+
+/// Format of the per-variant results written by lod_edit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultsFormat {
+    /// The detectability TSV read by merge_vcf_lod and the other vlod commands
+    #[default]
+    Tsv,
+    /// PostgreSQL COPY text format, with a `<output>.sql` DDL file next to it
+    Pgcopy,
+}
+
+impl FromStr for ResultsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tsv" => Ok(ResultsFormat::Tsv),
+            "pgcopy" => Ok(ResultsFormat::Pgcopy),
+            _ => Err(format!("unknown output format '{}' (expected tsv or pgcopy)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ResultsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResultsFormat::Tsv => "tsv",
+            ResultsFormat::Pgcopy => "pgcopy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether a name can be used unquoted as a (schema-qualified) PostgreSQL table name
+pub fn is_valid_table_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').count() <= 2
+        && name.split('.').all(|part| {
+            part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// DDL path written next to a COPY file
+pub fn pg_ddl_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    append_extension(path, "sql")
+}
+
+/// `CREATE TABLE` statement for the exported columns, commented with the provenance
+/// of a run under `config`
+pub fn pg_ddl(table: &str, config: &LodConfig) -> String {
+    let columns: Vec<String> = PG_COLUMNS
+        .iter()
+        .map(|(name, sql_type)| format!("    {} {}", name, sql_type))
+        .collect();
+    format!(
+        "-- Written by vlod {}; load the data with:\n\
+         --   \\copy {} FROM 'results.pgcopy'\n\
+         -- (or FROM PROGRAM 'zcat results.pgcopy.gz' for a gzipped file)\n\
+         -- vlod about: {}\n\
+         CREATE TABLE IF NOT EXISTS {} (\n{}\n);\n\
+         -- Tables created by earlier versions stored pos as integer\n\
+         ALTER TABLE {} ALTER COLUMN pos TYPE bigint;\n\
+         CREATE INDEX IF NOT EXISTS {}_position ON {} (chrom, pos);\n",
+        env!("CARGO_PKG_VERSION"),
+        table,
+        run_about(config).to_json(),
+        table,
+        columns.join(",\n"),
+        table,
+        table.replace('.', "_"),
+        table
+    )
+}
+
+/// Escape a text value for COPY text format
+fn copy_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A double precision value, spelled the way PostgreSQL parses non-finite values
+fn copy_float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// A nullable value, `\N` when missing
+fn copy_optional<T>(value: Option<T>, format: impl Fn(T) -> String) -> String {
+    value.map(format).unwrap_or_else(|| "\\N".to_string())
+}
+
+/// Write results of a run under `config` in COPY text format (gzipped for a `.gz`
+/// extension) and the DDL of their table to `<output>.sql`. Returns the DDL path.
+pub fn write_pgcopy_results(
+    results: &[DetectabilityResult],
+    output_path: &Path,
+    table: &str,
+    config: &LodConfig,
+) -> VlodResult<PathBuf> {
+    if !is_valid_table_name(table) {
+        return Err(VlodError::InvalidConfig(format!("invalid PostgreSQL table name '{}'", table)));
+    }
+
+    let file = BufWriter::new(create_output_file(output_path)?);
+    if output_path.extension().and_then(|s| s.to_str()) == Some("gz") {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_pgcopy_results_to_writer(results, &mut encoder)?;
+        encoder.finish()?.flush()?;
+    } else {
+        write_pgcopy_results_to_writer(results, file)?;
+    }
+
+    let ddl_path = pg_ddl_path(output_path);
+    let mut ddl = create_output_file(&ddl_path)?;
+    ddl.write_all(pg_ddl(table, config).as_bytes())?;
+    Ok(ddl_path)
+}
+
+/// Write results in COPY text format to `writer`; there is no header row
+pub fn write_pgcopy_results_to_writer<W: Write>(results: &[DetectabilityResult], mut writer: W) -> VlodResult<()> {
+    for result in results {
+        let amplicon_support =
+            (!result.amplicon_support.is_empty()).then(|| format_amplicon_support(&result.amplicon_support));
+        let fields = [
+            copy_text(&result.variant.chrom),
+            result.variant.pos.to_string(),
+            copy_text(&result.variant.ref_allele),
+            copy_text(&result.variant.alt_allele),
+            copy_float(result.detectability_score),
+            copy_text(&result.detectability_condition.to_string()),
+            result.coverage.to_string(),
+            result.variant_reads.to_string(),
+            result.alt_softclip_support.to_string(),
+            copy_optional(result.assembly_support, |support| support.to_string()),
+            copy_optional(amplicon_support, |support| copy_text(&support)),
+            copy_optional(result.single_amplicon_support(), |single| single.to_string()),
+            copy_optional(result.detection_probability, copy_float),
+            copy_optional(result.required_depth, |depth| depth.to_string()),
+            result.alt_orientation.f1r2.to_string(),
+            result.alt_orientation.f2r1.to_string(),
+            copy_optional(result.alt_orientation.bias(), copy_float),
+            copy_optional(result.pool_alleles.as_deref(), copy_text),
+            copy_optional(result.pool_power, copy_float),
+            copy_optional(result.overrides, |overrides| copy_text(&overrides.to_string())),
+            copy_float(result.vaf(VafDefinition::Total)),
+            copy_float(result.vaf(VafDefinition::RefAlt)),
+            copy_float(result.other_allele_fraction()),
+            result.deleted_reads.to_string(),
+            copy_float(result.short_fragment_fraction),
+        ];
+        writeln!(writer, "{}", fields.join("\t"))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DetectabilityCondition, Variant};
+
+    #[test]
+    fn test_write_pgcopy_results() {
+        let mut result = DetectabilityResult::new(
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string()),
+            f64::NEG_INFINITY,
+            DetectabilityCondition::NotAssessable("low\tmapq".to_string()),
+            0,
+            0,
+        );
+        result.detection_probability = Some(0.25);
+
+        let mut output = Vec::new();
+        write_pgcopy_results_to_writer(&[result], &mut output).unwrap();
+        let line = String::from_utf8(output).unwrap();
+        let fields: Vec<&str> = line.trim_end().split('\t').collect();
+
+        assert_eq!(fields.len(), PG_COLUMNS.len());
+        assert_eq!(fields[4], "-Infinity");
+        assert_eq!(fields[5], "Not-assessable:low\\tmapq");
+        assert_eq!(fields[9], "\\N");
+        assert_eq!(fields[12], "0.25");
+    }
+
+    #[test]
+    fn test_pg_ddl() {
+        let ddl = pg_ddl("lab.vlod_results", &LodConfig::default());
+        assert!(ddl.contains("CREATE TABLE IF NOT EXISTS lab.vlod_results (\n    chrom text NOT NULL,"));
+        assert!(ddl.contains("    pos bigint NOT NULL,"));
+        assert!(ddl.contains("    short_fragment_fraction double precision NOT NULL\n);"));
+        assert!(ddl.contains("ALTER TABLE lab.vlod_results ALTER COLUMN pos TYPE bigint;"));
+        assert!(ddl.contains("lab_vlod_results_position ON lab.vlod_results"));
+
+        assert!(is_valid_table_name("vlod_results"));
+        assert!(!is_valid_table_name("results; DROP TABLE x"));
+        assert!(!is_valid_table_name("1results"));
+        assert_eq!("PGCOPY".parse::<ResultsFormat>(), Ok(ResultsFormat::Pgcopy));
+    }
+}
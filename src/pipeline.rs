@@ -0,0 +1,156 @@
+//! End-to-end pipeline (VCF + BAM → results and annotated VCF) without intermediate files
+
+use crate::{
+    bam::bam_contigs,
+    interrupt::{self, CancellationToken},
+    lod::{calculate_detectability_scores_observed, validate_lod_config},
+    merge::{merge_detectability_results_into_writer, MergeOptions},
+    observer::{NoopObserver, Observer, Stage},
+    utils::{get_num_cpus, LineLengthGuard},
+    vcf::{
+        apply_monomorphic_policy, check_sort_order, dedup_variants, read_vcf_input_from_reader,
+        select_pass_variants, MonomorphicPolicy,
+    },
+    warnings::{WarningKind, Warnings},
+    DetectabilityResult, LodConfig, VlodResult,
+};
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Configuration for a complete pipeline run
+#[derive(Clone)]
+pub struct PipelineConfig {
+    pub lod: LodConfig,
+    /// Merge options; `contigs` is filled from the BAM header when empty. Its
+    /// `caller` also applies when reading the variants.
+    pub merge: MergeOptions,
+    /// Analyse only variants whose FILTER is PASS
+    pub pass_only: bool,
+    pub monomorphic_policy: MonomorphicPolicy,
+    pub num_processes: usize,
+    /// Receives stage, variant and chunk events, and may cancel the run
+    pub observer: Option<Arc<dyn Observer>>,
+    /// Stops scoring from another thread; without one, SIGINT/SIGTERM does (once
+    /// `interrupt::install_signal_handlers` was called)
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl fmt::Debug for PipelineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineConfig")
+            .field("lod", &self.lod)
+            .field("merge", &self.merge)
+            .field("pass_only", &self.pass_only)
+            .field("monomorphic_policy", &self.monomorphic_policy)
+            .field("num_processes", &self.num_processes)
+            .field("observer", &self.observer.is_some())
+            .field("cancellation", &self.cancellation)
+            .finish()
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            lod: LodConfig::default(),
+            merge: MergeOptions::default(),
+            pass_only: false,
+            monomorphic_policy: MonomorphicPolicy::default(),
+            num_processes: get_num_cpus(),
+            observer: None,
+            cancellation: None,
+        }
+    }
+}
+
+/// Outputs of a pipeline run
+#[derive(Debug, Clone)]
+pub struct PipelineOutput {
+    pub results: Vec<DetectabilityResult>,
+    pub annotated_vcf: Vec<u8>,
+    /// Records skipped or not assessable, with examples
+    pub warnings: Warnings,
+}
+
+/// Run the whole analysis on uncompressed VCF text, returning the detectability
+/// results, the annotated VCF and the warnings raised. Only the BAM (and its
+/// index) is read from disk. A run cancelled by the token or the observer (or
+/// interrupted) returns the results completed so far, with only their records
+/// annotated.
+pub fn run_pipeline<R: BufRead>(vcf: R, bam_path: &Path, config: &PipelineConfig) -> VlodResult<PipelineOutput> {
+    validate_lod_config(&config.lod)?;
+    let observer: &dyn Observer = config.observer.as_deref().unwrap_or(&NoopObserver);
+    observer.on_stage(Stage::ReadingVariants);
+
+    // The VCF is read twice (variants, then annotation), so buffer it once
+    let mut vcf_bytes = Vec::new();
+    LineLengthGuard::new(vcf, config.merge.max_line_length).read_to_end(&mut vcf_bytes)?;
+
+    let contigs = bam_contigs(bam_path)?;
+    let contig_order: Vec<String> = contigs.iter().map(|(name, _)| name.clone()).collect();
+
+    let input = read_vcf_input_from_reader(vcf_bytes.as_slice(), config.merge.caller)?;
+    let mut warnings = input.warnings;
+    let mut lod = config.lod.clone();
+    if !input.overrides.is_empty() {
+        lod.variant_overrides = Some(Arc::new(input.overrides));
+    }
+    let (variants, non_pass) = select_pass_variants(input.variants, config.pass_only);
+    if config.pass_only {
+        warnings.add(WarningKind::NonPassSkipped, non_pass.len());
+    }
+    let read = variants.len();
+    let variants = apply_monomorphic_policy(variants, config.monomorphic_policy);
+    warnings.add(WarningKind::MonomorphicSkipped, read - variants.len());
+    check_sort_order(&variants, &contig_order)?;
+    let (variants, duplicates) = dedup_variants(variants, config.merge.duplicate_policy)?;
+    if duplicates > 0 {
+        log::warn!("Skipped {} duplicate variants in the input VCF", duplicates);
+    }
+    warnings.add(WarningKind::DuplicateVariant, duplicates);
+
+    observer.on_stage(Stage::Scoring);
+    let stop = config.cancellation.as_ref().map_or(interrupt::flag(), CancellationToken::flag);
+    let results =
+        calculate_detectability_scores_observed(variants, bam_path, &lod, config.num_processes, stop, observer)?;
+    warnings.record_not_assessable(&results);
+
+    observer.on_stage(Stage::Merging);
+
+    let mut merge_options = config.merge.clone();
+    if merge_options.contigs.is_empty() {
+        merge_options.contigs = contigs;
+    }
+    merge_options.score_threshold = lod.score_threshold;
+    let mut annotated_vcf = Vec::new();
+    merge_detectability_results_into_writer(vcf_bytes.as_slice(), &results, &mut annotated_vcf, &merge_options)?;
+    observer.on_stage(Stage::Finished);
+
+    Ok(PipelineOutput {
+        results,
+        annotated_vcf,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VlodError;
+
+    #[test]
+    fn test_run_pipeline_validates_config() {
+        let config = PipelineConfig {
+            lod: LodConfig {
+                p_tp: 0.0,
+                ..LodConfig::default()
+            },
+            ..PipelineConfig::default()
+        };
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n";
+        let result = run_pipeline(vcf.as_bytes(), Path::new("missing.bam"), &config);
+        assert!(matches!(result, Err(VlodError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,216 @@
+//! Standalone SVG plots of a run, reviewable in a browser without a Python
+//! environment: the score histogram, coverage against score as a hexbin, and the
+//! Detectable fraction per chromosome
+
+use crate::{DetectabilityCondition, DetectabilityResult, VlodError, VlodResult};
+use plotters::prelude::*;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+type PlotResult = Result<(), Box<dyn Error>>;
+
+/// Plots written to the plot directory
+pub const PLOT_FILES: [&str; 3] = ["score_histogram.svg", "coverage_vs_score.svg", "detectable_by_chrom.svg"];
+
+const SIZE: (u32, u32) = (900, 560);
+const HISTOGRAM_BINS: usize = 40;
+/// Hexagons across the coverage axis
+const HEXBIN_COLUMNS: f64 = 30.0;
+/// Hexbin colour ramp, from one variant to the most populated hexagon
+const LIGHT: (u8, u8, u8) = (222, 235, 247);
+const DARK: (u8, u8, u8) = (8, 48, 107);
+
+/// Paths of the plots written to `dir`
+pub fn plot_paths<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    PLOT_FILES.iter().map(|file| dir.as_ref().join(file)).collect()
+}
+
+/// Write the SVG plots of `results` to `dir` (created if missing); returns the
+/// files written
+pub fn write_plots<P: AsRef<Path>>(results: &[DetectabilityResult], dir: P) -> VlodResult<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir.as_ref())?;
+    let paths = plot_paths(dir);
+    let plots: [fn(&[DetectabilityResult], &Path) -> PlotResult; 3] =
+        [score_histogram, coverage_vs_score, detectable_by_chrom];
+    for (plot, path) in plots.iter().zip(&paths) {
+        plot(results, path).map_err(|e| {
+            VlodError::Io(std::io::Error::other(format!("cannot draw {}: {}", path.display(), e)))
+        })?;
+    }
+    Ok(paths)
+}
+
+/// Finite detectability scores
+fn scores(results: &[DetectabilityResult]) -> impl Iterator<Item = f64> + '_ {
+    results.iter().map(|result| result.detectability_score).filter(|score| score.is_finite())
+}
+
+/// Range spanning `values`, widened when empty or a single value
+fn span(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
+    }
+}
+
+fn score_histogram(results: &[DetectabilityResult], path: &Path) -> PlotResult {
+    let (min, max) = span(scores(results));
+    let width = (max - min) / HISTOGRAM_BINS as f64;
+    let mut counts = vec![0u32; HISTOGRAM_BINS];
+    for score in scores(results) {
+        let bin = (((score - min) / width) as usize).min(HISTOGRAM_BINS - 1);
+        counts[bin] += 1;
+    }
+    let top = counts.iter().copied().max().unwrap_or(0).max(1) as f64 * 1.05;
+
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Detectability score", ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(55)
+        .build_cartesian_2d(min..max, 0.0..top)?;
+    chart.configure_mesh().disable_x_mesh().x_desc("Score").y_desc("Variants").draw()?;
+    chart.draw_series(counts.iter().enumerate().map(|(bin, &count)| {
+        let left = min + bin as f64 * width;
+        Rectangle::new([(left, 0.0), (left + width, count as f64)], BLUE.mix(0.7).filled())
+    }))?;
+    root.present()?;
+    Ok(())
+}
+
+/// Hexbin of coverage against score: hexagon centres lie on two offset
+/// rectangular lattices and each point goes to the nearer centre
+fn coverage_vs_score(results: &[DetectabilityResult], path: &Path) -> PlotResult {
+    let points: Vec<(f64, f64)> = results
+        .iter()
+        .filter(|result| result.detectability_score.is_finite())
+        .map(|result| (result.coverage as f64, result.detectability_score))
+        .collect();
+    let (x_min, x_max) = span(points.iter().map(|point| point.0));
+    let (y_min, y_max) = span(points.iter().map(|point| point.1));
+    let sx = (x_max - x_min) / HEXBIN_COLUMNS;
+    let sy = (y_max - y_min) / (HEXBIN_COLUMNS / 3f64.sqrt());
+
+    let mut bins: std::collections::HashMap<(i64, i64, bool), u32> = std::collections::HashMap::new();
+    for &(x, y) in &points {
+        let (ix, iy) = ((x - x_min) / sx, (y - y_min) / sy);
+        let (ix1, iy1) = (ix.round(), iy.round());
+        let (ix2, iy2) = (ix.floor(), iy.floor());
+        let d1 = (ix - ix1).powi(2) + 3.0 * (iy - iy1).powi(2);
+        let d2 = (ix - ix2 - 0.5).powi(2) + 3.0 * (iy - iy2 - 0.5).powi(2);
+        let key = if d1 <= d2 { (ix1 as i64, iy1 as i64, false) } else { (ix2 as i64, iy2 as i64, true) };
+        *bins.entry(key).or_insert(0) += 1;
+    }
+    let most = bins.values().copied().max().unwrap_or(1) as f64;
+
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Coverage and detectability score (darker: more variants)", ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(55)
+        .build_cartesian_2d(x_min - sx..x_max + sx, y_min - sy..y_max + sy)?;
+    chart.configure_mesh().x_desc("Coverage").y_desc("Score").draw()?;
+    chart.draw_series(bins.iter().map(|(&(i, j, offset), &count)| {
+        let shift = if offset { 0.5 } else { 0.0 };
+        let (cx, cy) = (x_min + (i as f64 + shift) * sx, y_min + (j as f64 + shift) * sy);
+        let hexagon: Vec<(f64, f64)> = [(0.5, -0.5), (0.5, 0.5), (0.0, 1.0), (-0.5, 0.5), (-0.5, -0.5), (0.0, -1.0)]
+            .iter()
+            .map(|(dx, dy)| (cx + dx * sx, cy + dy * sy / 3.0))
+            .collect();
+        let t = (1.0 + count as f64).ln() / (1.0 + most).ln();
+        let channel = |light: u8, dark: u8| (light as f64 + (dark as f64 - light as f64) * t).round() as u8;
+        let color = RGBColor(channel(LIGHT.0, DARK.0), channel(LIGHT.1, DARK.1), channel(LIGHT.2, DARK.2));
+        Polygon::new(hexagon, color.filled())
+    }))?;
+    root.present()?;
+    Ok(())
+}
+
+fn detectable_by_chrom(results: &[DetectabilityResult], path: &Path) -> PlotResult {
+    // Chromosomes in input order, with (Detectable, total) variants
+    let mut chroms: Vec<(&str, u32, u32)> = Vec::new();
+    for result in results {
+        let detectable = (result.detectability_condition == DetectabilityCondition::Detectable) as u32;
+        match chroms.iter_mut().find(|(chrom, _, _)| *chrom == result.variant.chrom) {
+            Some(entry) => {
+                entry.1 += detectable;
+                entry.2 += 1;
+            }
+            None => chroms.push((&result.variant.chrom, detectable, 1)),
+        }
+    }
+    let columns = chroms.len().max(1) as u32;
+    let label = |value: &SegmentValue<u32>| match value {
+        SegmentValue::CenterOf(i) => chroms.get(*i as usize).map(|entry| entry.0.to_string()).unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Detectable fraction per chromosome", ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(55)
+        .build_cartesian_2d((0..columns).into_segmented(), 0.0..1.0)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(columns as usize)
+        .x_label_formatter(&label)
+        .y_desc("Detectable fraction")
+        .draw()?;
+    chart.draw_series(chroms.iter().enumerate().map(|(i, &(_, detectable, total))| {
+        let i = i as u32;
+        let fraction = detectable as f64 / total as f64;
+        let mut bar = Rectangle::new(
+            [(SegmentValue::Exact(i), 0.0), (SegmentValue::Exact(i + 1), fraction)],
+            GREEN.mix(0.7).filled(),
+        );
+        bar.set_margin(0, 0, 6, 6);
+        bar
+    }))?;
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variant;
+
+    #[test]
+    fn test_write_plots() {
+        let result = |chrom: &str, coverage: u32, score: f64, condition: DetectabilityCondition| {
+            let variant = Variant::new(chrom.to_string(), 100, "A".to_string(), "G".to_string());
+            DetectabilityResult::new(variant, score, condition, coverage, 0)
+        };
+        let results = vec![
+            result("chr1", 100, 4.0, DetectabilityCondition::Detectable),
+            result("chr1", 60, 2.0, DetectabilityCondition::NonDetectable),
+            result("chr2", 250, 6.5, DetectabilityCondition::Detectable),
+            result("chr2", 0, f64::NAN, DetectabilityCondition::NoCoverage),
+        ];
+
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_plots(&results, dir.path().join("plots")).unwrap();
+        assert_eq!(paths, plot_paths(dir.path().join("plots")));
+        for path in &paths {
+            let svg = std::fs::read_to_string(path).unwrap();
+            assert!(svg.starts_with("<svg"), "{}", path.display());
+        }
+        let by_chrom = std::fs::read_to_string(&paths[2]).unwrap();
+        assert!(by_chrom.contains("chr1") && by_chrom.contains("chr2"));
+
+        // Without results the plots are drawn empty
+        write_plots(&[], dir.path()).unwrap();
+    }
+}
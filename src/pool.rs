@@ -0,0 +1,95 @@
+//! Pooled-sample designs: the allele fraction of a single carrier in a pool of
+//! diploid samples, and the binomial power to detect it
+
+use crate::{VlodError, VlodResult};
+
+/// A pool of `size` diploid samples sequenced together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolDesign {
+    pub size: u32,
+}
+
+impl PoolDesign {
+    pub fn validate(&self) -> VlodResult<()> {
+        if self.size == 0 {
+            return Err(VlodError::InvalidConfig("pool size must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Number of alleles in the pool
+    pub fn alleles(&self) -> u32 {
+        2 * self.size
+    }
+
+    /// Expected fraction of an allele carried once in the pool, 1/(2N)
+    pub fn expected_fraction(&self) -> f64 {
+        1.0 / self.alleles() as f64
+    }
+
+    /// Estimated number of pool alleles carrying a variant observed at `vaf`
+    pub fn allele_copies(&self, vaf: f64) -> u32 {
+        (vaf * self.alleles() as f64).round() as u32
+    }
+
+    /// Pool-aware label for a VAF, e.g. `3/40` for three of 40 alleles
+    pub fn label(&self, vaf: f64) -> String {
+        format!("{}/{}", self.allele_copies(vaf), self.alleles())
+    }
+
+    /// Probability that a single-copy allele shows at least `min_alt_reads` of
+    /// `depth` reads
+    pub fn power(&self, depth: u32, min_alt_reads: u32) -> f64 {
+        binomial_upper_tail(depth, min_alt_reads, self.expected_fraction())
+    }
+}
+
+/// P(X >= k) for X ~ Binomial(n, p), summing the lower tail in log space so that
+/// deep sites do not underflow
+pub fn binomial_upper_tail(n: u32, k: u32, p: f64) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    if k > n || p <= 0.0 {
+        return 0.0;
+    }
+    if p >= 1.0 {
+        return 1.0;
+    }
+
+    let log_odds = (p / (1.0 - p)).ln();
+    let mut log_pmf = n as f64 * (1.0 - p).ln();
+    let mut lower_tail = 0.0;
+    for i in 0..k {
+        lower_tail += log_pmf.exp();
+        log_pmf += ((n - i) as f64 / (i + 1) as f64).ln() + log_odds;
+    }
+    (1.0 - lower_tail).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_design() {
+        let pool = PoolDesign { size: 20 };
+        assert_eq!(pool.alleles(), 40);
+        assert_eq!(pool.expected_fraction(), 0.025);
+        assert_eq!(pool.label(0.074), "3/40");
+        assert!(PoolDesign { size: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_binomial_upper_tail() {
+        assert_eq!(binomial_upper_tail(10, 0, 0.1), 1.0);
+        assert_eq!(binomial_upper_tail(10, 11, 0.1), 0.0);
+        // P(X >= 1) = 1 - 0.9^10
+        assert!((binomial_upper_tail(10, 1, 0.1) - (1.0 - 0.9f64.powi(10))).abs() < 1e-12);
+        // P(X >= 2) for Bin(2, 0.5)
+        assert!((binomial_upper_tail(2, 2, 0.5) - 0.25).abs() < 1e-12);
+        // Deep sites stay finite
+        let tail = binomial_upper_tail(100_000, 50_000, 0.5);
+        assert!(tail > 0.4 && tail < 0.6);
+    }
+}
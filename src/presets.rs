@@ -0,0 +1,212 @@
+//! Registry of aligner presets: sequencing error rates and read filters suited
+//! to the aligner (and read technology) that produced a BAM, detected from the
+//! `@PG` lines of its header
+
+use crate::{read_filter::ReadFilter, VlodResult};
+use rust_htslib::bam::{Read, Reader};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Error rate and read filters applied for BAMs from one aligner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Sequencing error rate, used unless `--SE` is given
+    pub p_se: f64,
+    /// Read filters, added to those given with `--read-filter`
+    pub read_filters: &'static [&'static str],
+}
+
+impl Preset {
+    pub fn read_filters(&self) -> Vec<ReadFilter> {
+        self.read_filters
+            .iter()
+            .map(|filter| filter.parse().expect("preset read filters are valid"))
+            .collect()
+    }
+}
+
+/// Presets in the registry
+pub static PRESETS: [Preset; 6] = [
+    Preset {
+        name: "bwa",
+        description: "short reads aligned with BWA or BWA-MEM2",
+        p_se: 0.0001,
+        read_filters: &[],
+    },
+    Preset {
+        name: "dragen",
+        description: "short reads aligned with DRAGEN",
+        p_se: 0.0001,
+        read_filters: &[],
+    },
+    Preset {
+        name: "minimap2-sr",
+        description: "short reads aligned with minimap2 -x sr",
+        p_se: 0.0001,
+        read_filters: &[],
+    },
+    Preset {
+        name: "minimap2-hifi",
+        description: "PacBio HiFi reads aligned with minimap2",
+        p_se: 0.001,
+        read_filters: &[],
+    },
+    Preset {
+        name: "minimap2-ont",
+        description: "Nanopore reads aligned with minimap2; reads over 10% divergent are dropped",
+        p_se: 0.02,
+        read_filters: &["max:de:0.1"],
+    },
+    Preset {
+        name: "star",
+        description: "RNA-seq reads aligned with STAR; only uniquely mapped reads are counted",
+        p_se: 0.0001,
+        read_filters: &["max:NH:1"],
+    },
+];
+
+/// Look up a preset by name
+pub fn preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+/// Preset selection on the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PresetChoice {
+    /// Detect the aligner from the BAM header
+    #[default]
+    Auto,
+    /// Apply no preset
+    None,
+    Named(&'static Preset),
+}
+
+impl FromStr for PresetChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(PresetChoice::Auto),
+            "none" => Ok(PresetChoice::None),
+            _ => preset(s).map(PresetChoice::Named).ok_or_else(|| {
+                let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+                format!("unknown preset '{}' (expected auto, none or {})", s, names.join(", "))
+            }),
+        }
+    }
+}
+
+impl fmt::Display for PresetChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetChoice::Auto => write!(f, "auto"),
+            PresetChoice::None => write!(f, "none"),
+            PresetChoice::Named(preset) => write!(f, "{}", preset.name),
+        }
+    }
+}
+
+/// Preset for the first aligner recognised in the `@PG` lines of SAM header text
+pub fn detect_preset(header: &str) -> Option<&'static Preset> {
+    header
+        .lines()
+        .filter(|line| line.starts_with("@PG"))
+        .find_map(|line| {
+            let field = |tag: &str| {
+                line.split('\t')
+                    .find_map(|field| field.strip_prefix(tag))
+                    .unwrap_or("")
+                    .to_ascii_lowercase()
+            };
+            let program = match field("PN:") {
+                program if program.is_empty() => field("ID:"),
+                program => program,
+            };
+            let name = if program.starts_with("bwa") {
+                "bwa"
+            } else if program.starts_with("dragen") {
+                "dragen"
+            } else if program == "star" {
+                "star"
+            } else if program.starts_with("minimap2") {
+                // minimap2 aligns long reads with map-ont settings unless given -x
+                let command_line = field("CL:");
+                let words: Vec<&str> = command_line.split_whitespace().collect();
+                if words.contains(&"sr") {
+                    "minimap2-sr"
+                } else if ["map-hifi", "map-pb", "lr:hq"].iter().any(|mode| words.contains(mode)) {
+                    "minimap2-hifi"
+                } else {
+                    "minimap2-ont"
+                }
+            } else {
+                return None;
+            };
+            preset(name)
+        })
+}
+
+/// Resolve a preset choice, detecting the aligner from the BAM header for `auto`
+pub fn resolve_preset<P: AsRef<Path>>(choice: PresetChoice, bam_path: P) -> VlodResult<Option<&'static Preset>> {
+    match choice {
+        PresetChoice::None => Ok(None),
+        PresetChoice::Named(preset) => Ok(Some(preset)),
+        PresetChoice::Auto => {
+            let reader = Reader::from_path(bam_path.as_ref())?;
+            let header = String::from_utf8_lossy(reader.header().as_bytes()).to_string();
+            let detected = detect_preset(&header);
+            if detected.is_none() {
+                log::info!("No known aligner in the BAM @PG lines; no preset applied");
+            }
+            Ok(detected)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for preset in &PRESETS {
+            assert_eq!(preset.read_filters().len(), preset.read_filters.len());
+        }
+    }
+
+    #[test]
+    fn test_detect_preset() {
+        let header = |programs: &[&str]| {
+            let mut header = String::from("@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n");
+            for program in programs {
+                header.push_str(program);
+                header.push('\n');
+            }
+            header
+        };
+
+        let bwa = header(&["@PG\tID:bwa\tPN:bwa\tVN:0.7.17\tCL:bwa mem ref.fa r1.fq r2.fq", "@PG\tID:samtools\tPN:samtools\tPP:bwa"]);
+        assert_eq!(detect_preset(&bwa).unwrap().name, "bwa");
+        let ont = header(&["@PG\tID:minimap2\tPN:minimap2\tCL:minimap2 -ax map-ont ref.fa reads.fq"]);
+        assert_eq!(detect_preset(&ont).unwrap().name, "minimap2-ont");
+        let hifi = header(&["@PG\tID:minimap2\tPN:minimap2\tCL:minimap2 -a -x map-hifi ref.fa reads.fq"]);
+        assert_eq!(detect_preset(&hifi).unwrap().name, "minimap2-hifi");
+        let short = header(&["@PG\tID:minimap2\tPN:minimap2\tCL:minimap2 -ax sr ref.fa r1.fq r2.fq"]);
+        assert_eq!(detect_preset(&short).unwrap().name, "minimap2-sr");
+        assert_eq!(detect_preset(&header(&["@PG\tID:STAR\tPN:STAR\tVN:2.7.10a"])).unwrap().name, "star");
+        assert_eq!(detect_preset(&header(&["@PG\tID:DRAGEN SW build\tVN:4.2"])).unwrap().name, "dragen");
+        assert_eq!(detect_preset(&header(&["@PG\tID:samtools\tPN:samtools"])), None);
+        assert_eq!(detect_preset(&header(&[])), None);
+    }
+
+    #[test]
+    fn test_parse_preset_choice() {
+        assert_eq!("auto".parse::<PresetChoice>(), Ok(PresetChoice::Auto));
+        assert_eq!("None".parse::<PresetChoice>(), Ok(PresetChoice::None));
+        assert_eq!("STAR".parse::<PresetChoice>().unwrap().to_string(), "star");
+        assert!("hisat2".parse::<PresetChoice>().unwrap_err().contains("expected auto, none or bwa, dragen"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Read filters on BAM auxiliary tags, e.g. `no:SA` or `min:AS:50`
+
+use rust_htslib::bam::record::{Aux, Record};
+use std::fmt;
+use std::str::FromStr;
+
+/// A per-read condition on an auxiliary tag; reads failing any filter are ignored
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadFilter {
+    /// `no:TAG` - drop reads carrying the tag
+    Without(String),
+    /// `has:TAG` - keep only reads carrying the tag
+    With(String),
+    /// `min:TAG:VALUE` - keep reads whose numeric tag is at least the value
+    Min(String, f64),
+    /// `max:TAG:VALUE` - keep reads whose numeric tag is at most the value
+    Max(String, f64),
+}
+
+impl ReadFilter {
+    /// Whether a read passes the filter. Reads lacking the tag fail `min` and `max`.
+    pub fn passes(&self, record: &Record) -> bool {
+        match self {
+            ReadFilter::Without(tag) => record.aux(tag.as_bytes()).is_err(),
+            ReadFilter::With(tag) => record.aux(tag.as_bytes()).is_ok(),
+            ReadFilter::Min(tag, min) => aux_number(record, tag).is_some_and(|value| value >= *min),
+            ReadFilter::Max(tag, max) => aux_number(record, tag).is_some_and(|value| value <= *max),
+        }
+    }
+}
+
+/// Whether a read passes every filter
+pub fn passes_all(filters: &[ReadFilter], record: &Record) -> bool {
+    filters.iter().all(|filter| filter.passes(record))
+}
+
+fn aux_number(record: &Record, tag: &str) -> Option<f64> {
+    match record.aux(tag.as_bytes()).ok()? {
+        Aux::I8(value) => Some(value as f64),
+        Aux::U8(value) => Some(value as f64),
+        Aux::I16(value) => Some(value as f64),
+        Aux::U16(value) => Some(value as f64),
+        Aux::I32(value) => Some(value as f64),
+        Aux::U32(value) => Some(value as f64),
+        Aux::Float(value) => Some(value as f64),
+        Aux::Double(value) => Some(value),
+        _ => None,
+    }
+}
+
+impl FromStr for ReadFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid read filter '{}' (expected no:TAG, has:TAG, min:TAG:VALUE or max:TAG:VALUE)",
+                s
+            )
+        };
+        let fields: Vec<&str> = s.split(':').collect();
+        let tag = match fields.get(1) {
+            Some(tag) if tag.len() == 2 && tag.is_ascii() => tag.to_string(),
+            _ => return Err(invalid()),
+        };
+        let value = || -> Result<f64, String> {
+            fields
+                .get(2)
+                .and_then(|value| value.parse::<f64>().ok())
+                .ok_or_else(invalid)
+        };
+
+        match (fields[0].to_ascii_lowercase().as_str(), fields.len()) {
+            ("no", 2) => Ok(ReadFilter::Without(tag)),
+            ("has", 2) => Ok(ReadFilter::With(tag)),
+            ("min", 3) => Ok(ReadFilter::Min(tag, value()?)),
+            ("max", 3) => Ok(ReadFilter::Max(tag, value()?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ReadFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadFilter::Without(tag) => write!(f, "no:{}", tag),
+            ReadFilter::With(tag) => write!(f, "has:{}", tag),
+            ReadFilter::Min(tag, value) => write!(f, "min:{}:{}", tag, value),
+            ReadFilter::Max(tag, value) => write!(f, "max:{}:{}", tag, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_read_filter() {
+        assert_eq!("no:SA".parse(), Ok(ReadFilter::Without("SA".to_string())));
+        assert_eq!("has:MC".parse(), Ok(ReadFilter::With("MC".to_string())));
+        assert_eq!("min:AS:50".parse(), Ok(ReadFilter::Min("AS".to_string(), 50.0)));
+        assert_eq!("max:NM:4".parse::<ReadFilter>().unwrap().to_string(), "max:NM:4");
+        assert!("min:AS".parse::<ReadFilter>().is_err());
+        assert!("no:LONG".parse::<ReadFilter>().is_err());
+        assert!("keep:SA".parse::<ReadFilter>().is_err());
+    }
+
+    #[test]
+    fn test_read_filter_passes() {
+        let mut record = Record::new();
+        record.push_aux(b"AS", Aux::I32(60)).unwrap();
+
+        assert!(ReadFilter::Min("AS".to_string(), 50.0).passes(&record));
+        assert!(!ReadFilter::Max("AS".to_string(), 50.0).passes(&record));
+        assert!(ReadFilter::Without("SA".to_string()).passes(&record));
+        assert!(!ReadFilter::With("MC".to_string()).passes(&record));
+        // A missing tag fails numeric filters
+        assert!(!ReadFilter::Min("NM".to_string(), 0.0).passes(&record));
+    }
+}
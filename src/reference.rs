@@ -0,0 +1,224 @@
+//! Indexed reference FASTA access, plain or bgzip-compressed (.fai, plus .gzi for
+//! bgzip)
+
+use crate::{
+    utils::{append_extension, is_bgzipped, is_gzipped, validate_file_readable},
+    Variant, VlodError, VlodResult,
+};
+use rust_htslib::faidx;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Compression of a FASTA file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastaCompression {
+    None,
+    /// Blocked gzip, which htslib can index and seek into
+    Bgzip,
+    /// Ordinary gzip, which cannot be indexed
+    Gzip,
+}
+
+/// Detect the compression of a FASTA from its first gzip header
+pub fn fasta_compression<P: AsRef<Path>>(path: P) -> VlodResult<FastaCompression> {
+    let path = path.as_ref();
+    if !is_gzipped(path)? {
+        Ok(FastaCompression::None)
+    } else if is_bgzipped(path)? {
+        Ok(FastaCompression::Bgzip)
+    } else {
+        Ok(FastaCompression::Gzip)
+    }
+}
+
+/// Index files a FASTA needs: .fai, plus .gzi when bgzipped
+pub fn fasta_index_paths<P: AsRef<Path>>(path: P) -> VlodResult<Vec<PathBuf>> {
+    let path = path.as_ref();
+    match fasta_compression(path)? {
+        FastaCompression::None => Ok(vec![append_extension(path, "fai")]),
+        FastaCompression::Bgzip => Ok(vec![append_extension(path, "fai"), append_extension(path, "gzi")]),
+        FastaCompression::Gzip => Err(VlodError::InvalidConfig(format!(
+            "reference {} is gzip- but not bgzip-compressed and cannot be indexed; \
+             recompress it with `bgzip` or decompress it",
+            path.display()
+        ))),
+    }
+}
+
+/// An indexed reference FASTA
+pub struct ReferenceFasta {
+    path: PathBuf,
+    reader: faidx::Reader,
+    contigs: HashSet<String>,
+}
+
+impl ReferenceFasta {
+    /// Open an indexed FASTA. Missing indexes are an error unless `auto_faidx` is
+    /// set, in which case they are built next to the FASTA.
+    pub fn open<P: AsRef<Path>>(path: P, auto_faidx: bool) -> VlodResult<Self> {
+        let path = path.as_ref();
+        validate_file_readable(path)?;
+
+        let missing: Vec<PathBuf> = fasta_index_paths(path)?.into_iter().filter(|index| !index.exists()).collect();
+        if !missing.is_empty() {
+            if !auto_faidx {
+                let missing: Vec<String> = missing.iter().map(|index| index.display().to_string()).collect();
+                return Err(VlodError::FileNotFound(format!(
+                    "reference index {} (run `samtools faidx {}` or pass --auto-faidx)",
+                    missing.join(" and "),
+                    path.display()
+                )));
+            }
+            log::info!("Indexing reference {:?}", path);
+            faidx::build(path).map_err(|e| {
+                VlodError::InvalidConfig(format!("cannot index reference {}: {}", path.display(), e))
+            })?;
+        }
+
+        let reader = faidx::Reader::from_path(path)?;
+        let contigs = (0..reader.n_seqs())
+            .map(|i| reader.seq_name(i as i32))
+            .collect::<Result<_, _>>()?;
+        Ok(ReferenceFasta {
+            path: path.to_path_buf(),
+            reader,
+            contigs,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn has_contig(&self, chrom: &str) -> bool {
+        self.contigs.contains(chrom)
+    }
+
+    /// Bases of the 1-based inclusive interval [start, end], upper-cased
+    pub fn fetch(&self, chrom: &str, start: u64, end: u64) -> VlodResult<String> {
+        if !self.has_contig(chrom) {
+            return Err(VlodError::InvalidVariant(format!(
+                "contig {} is not in reference {}",
+                chrom,
+                self.path.display()
+            )));
+        }
+        let bases = self
+            .reader
+            .fetch_seq_string(chrom, start.saturating_sub(1) as usize, end.saturating_sub(1) as usize)?;
+        Ok(bases.to_ascii_uppercase())
+    }
+
+    /// Whether a variant's REF allele matches the reference. Monomorphic sites are
+    /// checked on their REF base as well.
+    pub fn ref_matches(&self, variant: &Variant) -> VlodResult<bool> {
+        if !self.has_contig(&variant.chrom) || variant.ref_allele.is_empty() {
+            return Ok(false);
+        }
+        let end = variant.pos + variant.ref_allele.len() as u64 - 1;
+        Ok(self.fetch(&variant.chrom, variant.pos, end)? == variant.ref_allele.to_ascii_uppercase())
+    }
+}
+
+/// Variants whose REF allele disagrees with the reference (or whose contig is
+/// missing from it)
+pub fn check_ref_alleles<'a>(variants: &'a [Variant], reference: &ReferenceFasta) -> VlodResult<Vec<&'a Variant>> {
+    let mut mismatches = Vec::new();
+    for variant in variants {
+        if !reference.ref_matches(variant)? {
+            mismatches.push(variant);
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Check REF alleles against the reference, warning about the first few mismatches
+pub fn log_ref_mismatches(variants: &[Variant], reference: &ReferenceFasta) -> VlodResult<()> {
+    let mismatches = check_ref_alleles(variants, reference)?;
+    if mismatches.is_empty() {
+        log::info!("All {} REF alleles match reference {:?}", variants.len(), reference.path());
+        return Ok(());
+    }
+    log::warn!(
+        "{} of {} REF alleles do not match reference {:?}; check that the VCF and BAM use this build",
+        mismatches.len(),
+        variants.len(),
+        reference.path()
+    );
+    for variant in mismatches.iter().take(5) {
+        log::warn!(
+            "  {}:{} {}>{}",
+            variant.chrom,
+            variant.pos,
+            variant.ref_allele,
+            variant.alt_allele
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::{write_test_data, TEST_DATA_CONTIG};
+    use flate2::{write::GzEncoder, Compression};
+    use std::fs::File;
+    use std::io::Write;
+
+    #[test]
+    fn test_reference_ref_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        let reference = ReferenceFasta::open(&data.reference, false).unwrap();
+        assert!(reference.has_contig(TEST_DATA_CONTIG));
+
+        let variants: Vec<Variant> = data.expected.iter().map(|(variant, _)| variant.clone()).collect();
+        assert!(check_ref_alleles(&variants, &reference).unwrap().is_empty());
+
+        let base = reference.fetch(TEST_DATA_CONTIG, 201, 201).unwrap();
+        let wrong = if base == "A" { "C" } else { "A" };
+        let mismatched = Variant::new(TEST_DATA_CONTIG.to_string(), 201, wrong.to_string(), base.clone());
+        let other_contig = Variant::new("chrZ".to_string(), 1, "A".to_string(), "C".to_string());
+        assert_eq!(check_ref_alleles(&[mismatched, other_contig], &reference).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_missing_reference_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        std::fs::remove_file(append_extension(&data.reference, "fai")).unwrap();
+        assert!(matches!(
+            ReferenceFasta::open(&data.reference, false),
+            Err(VlodError::FileNotFound(_))
+        ));
+
+        let reference = ReferenceFasta::open(&data.reference, true).unwrap();
+        assert!(reference.has_contig(TEST_DATA_CONTIG));
+        assert!(append_extension(&data.reference, "fai").exists());
+    }
+
+    #[test]
+    fn test_fasta_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let gzipped = dir.path().join("ref.fa.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), Compression::default());
+        encoder.write_all(b">chr1\nACGT\n").unwrap();
+        encoder.finish().unwrap();
+
+        assert_eq!(fasta_compression(&gzipped).unwrap(), FastaCompression::Gzip);
+        assert!(matches!(fasta_index_paths(&gzipped), Err(VlodError::InvalidConfig(_))));
+        assert!(fasta_index_paths(dir.path().join("missing.fa")).is_err());
+
+        let bgzipped = dir.path().join("ref.fa.bgz");
+        let mut writer = rust_htslib::bgzf::Writer::from_path(&bgzipped).unwrap();
+        writer.write_all(b">chr1\nACGT\n").unwrap();
+        drop(writer);
+
+        assert_eq!(fasta_compression(&bgzipped).unwrap(), FastaCompression::Bgzip);
+        assert_eq!(
+            fasta_index_paths(&bgzipped).unwrap(),
+            vec![append_extension(&bgzipped, "fai"), append_extension(&bgzipped, "gzi")]
+        );
+        assert!(matches!(ReferenceFasta::open(&bgzipped, false), Err(VlodError::FileNotFound(_))));
+    }
+}
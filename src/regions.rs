@@ -0,0 +1,416 @@
+//! Genomic interval handling (BED files, Picard interval lists, `chr1:100-200`
+//! region syntax, amplicon definitions)
+
+use crate::{about::about_comment, utils::create_output_file, Variant, VlodError, VlodResult};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A BED interval (0-based, half-open) with an optional name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BedRegion {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub name: Option<String>,
+}
+
+impl BedRegion {
+    pub fn from_line(line: &str) -> VlodResult<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            return Err(VlodError::InvalidVariant(format!(
+                "Invalid BED line - expected at least 3 columns: {}",
+                line
+            )));
+        }
+
+        let start = fields[1].parse::<u64>()
+            .map_err(|_| VlodError::InvalidVariant(format!("Invalid BED start: {}", fields[1])))?;
+        let end = fields[2].parse::<u64>()
+            .map_err(|_| VlodError::InvalidVariant(format!("Invalid BED end: {}", fields[2])))?;
+        if end < start {
+            return Err(VlodError::InvalidVariant(format!(
+                "Invalid BED interval - end before start: {}",
+                line
+            )));
+        }
+
+        let name = fields
+            .get(3)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && *s != ".")
+            .map(|s| s.to_string());
+
+        Ok(BedRegion {
+            chrom: fields[0].to_string(),
+            start,
+            end,
+            name,
+        })
+    }
+
+    /// Parse 1-based, inclusive region syntax: `chr1:100-200` or `chr1:100`, with
+    /// optional thousands separators (`chr1:1,000-2,000`)
+    pub fn from_region_str(s: &str) -> VlodResult<Self> {
+        let invalid = || VlodError::InvalidVariant(format!("Invalid region - expected CHROM:START-END: {}", s));
+        let position = |value: &str| value.replace(',', "").parse::<u64>().ok().filter(|&pos| pos > 0);
+
+        let (chrom, range) = s.rsplit_once(':').ok_or_else(invalid)?;
+        if chrom.is_empty() {
+            return Err(invalid());
+        }
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (position(start).ok_or_else(invalid)?, position(end).ok_or_else(invalid)?),
+            None => {
+                let pos = position(range).ok_or_else(invalid)?;
+                (pos, pos)
+            }
+        };
+        if end < start {
+            return Err(VlodError::InvalidVariant(format!("Invalid region - end before start: {}", s)));
+        }
+
+        Ok(BedRegion {
+            chrom: chrom.to_string(),
+            start: start - 1,
+            end,
+            name: None,
+        })
+    }
+
+    /// Parse a Picard interval_list line: `chrom start end strand name`, 1-based and
+    /// inclusive
+    pub fn from_interval_list_line(line: &str) -> VlodResult<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            return Err(VlodError::InvalidVariant(format!(
+                "Invalid interval_list line - expected at least 3 columns: {}",
+                line
+            )));
+        }
+
+        let start = fields[1]
+            .parse::<u64>()
+            .ok()
+            .filter(|&start| start > 0)
+            .ok_or_else(|| VlodError::InvalidVariant(format!("Invalid interval_list start: {}", fields[1])))?;
+        let end = fields[2]
+            .parse::<u64>()
+            .map_err(|_| VlodError::InvalidVariant(format!("Invalid interval_list end: {}", fields[2])))?;
+        if end < start {
+            return Err(VlodError::InvalidVariant(format!(
+                "Invalid interval_list interval - end before start: {}",
+                line
+            )));
+        }
+
+        let name = fields
+            .get(4)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && *s != ".")
+            .map(|s| s.to_string());
+
+        Ok(BedRegion {
+            chrom: fields[0].to_string(),
+            start: start - 1,
+            end,
+            name,
+        })
+    }
+
+    /// Check whether a 0-based position lies inside the interval
+    pub fn contains(&self, chrom: &str, pos: u64) -> bool {
+        self.chrom == chrom && self.start <= pos && pos < self.end
+    }
+}
+
+/// Whether a line uses `chr1:100-200` region syntax rather than tab-separated columns
+pub fn is_region_syntax(line: &str) -> bool {
+    !line.contains('\t') && line.contains(':')
+}
+
+/// Read intervals from a BED file, a Picard interval_list (recognised by its `@HD`
+/// or `@SQ` header) or a list of `chr1:100-200` regions, each optionally followed by
+/// whitespace and a name. Comment, track and browser lines are skipped.
+pub fn read_regions<P: AsRef<Path>>(path: P) -> VlodResult<Vec<BedRegion>> {
+    let file = File::open(&path)
+        .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+    read_regions_from_reader(BufReader::new(file))
+}
+
+/// Read intervals in any of the formats of [`read_regions`] from a reader
+pub fn read_regions_from_reader<R: BufRead>(reader: R) -> VlodResult<Vec<BedRegion>> {
+    let mut regions = Vec::new();
+    let mut interval_list = false;
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+
+        if line.starts_with('@') {
+            interval_list = true;
+            continue;
+        }
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let region = if is_region_syntax(line) {
+            let (region, name) = match line.split_once(char::is_whitespace) {
+                Some((region, name)) => (region, Some(name.trim().to_string())),
+                None => (line, None),
+            };
+            BedRegion { name: name.filter(|name| !name.is_empty()), ..BedRegion::from_region_str(region)? }
+        } else if interval_list {
+            BedRegion::from_interval_list_line(line)?
+        } else {
+            BedRegion::from_line(line)?
+        };
+        regions.push(region);
+    }
+
+    Ok(regions)
+}
+
+/// Keep the variants whose position lies in one of the regions, returning how
+/// many were dropped
+pub fn retain_in_regions(variants: &mut Vec<Variant>, regions: &[BedRegion]) -> usize {
+    let mut by_chrom: HashMap<&str, Vec<&BedRegion>> = HashMap::new();
+    for region in regions {
+        by_chrom.entry(region.chrom.as_str()).or_default().push(region);
+    }
+    let before = variants.len();
+    variants.retain(|variant| {
+        by_chrom.get(variant.chrom.as_str()).is_some_and(|regions| {
+            regions.iter().any(|region| region.contains(&variant.chrom, variant.pos.saturating_sub(1)))
+        })
+    });
+    before - variants.len()
+}
+
+/// Write regions as a BED file (0-based, half-open), headed by the vlod version
+pub fn write_bed_regions<P: AsRef<Path>>(regions: &[BedRegion], path: P) -> VlodResult<()> {
+    write_bed_regions_to_writer(regions, BufWriter::new(create_output_file(path)?))
+}
+
+/// Write regions as BED to any writer
+pub fn write_bed_regions_to_writer<W: Write>(regions: &[BedRegion], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    for region in regions {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            region.chrom,
+            region.start,
+            region.end,
+            region.name.as_deref().unwrap_or(".")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Named amplicon intervals used to attribute reads to the amplicon they came from
+#[derive(Debug, Clone, Default)]
+pub struct AmpliconSet {
+    by_chrom: HashMap<String, Vec<BedRegion>>,
+}
+
+impl AmpliconSet {
+    pub fn from_regions(regions: Vec<BedRegion>) -> VlodResult<Self> {
+        let mut by_chrom: HashMap<String, Vec<BedRegion>> = HashMap::new();
+        for region in regions {
+            if region.name.is_none() {
+                return Err(VlodError::InvalidConfig(format!(
+                    "Amplicon BED entries must be named (4th column): {}:{}-{}",
+                    region.chrom, region.start, region.end
+                )));
+            }
+            by_chrom.entry(region.chrom.clone()).or_default().push(region);
+        }
+
+        Ok(AmpliconSet { by_chrom })
+    }
+
+    /// Read amplicons from a BED file, interval_list or region list (see [`read_regions`])
+    pub fn from_bed<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
+        Self::from_regions(read_regions(path)?)
+    }
+
+    /// Amplicons covering a 0-based position
+    pub fn covering(&self, chrom: &str, pos: u64) -> Vec<&BedRegion> {
+        self.by_chrom
+            .get(chrom)
+            .map(|regions| regions.iter().filter(|r| r.contains(chrom, pos)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Pick the amplicon a read most likely originates from: forward reads start at
+    /// the amplicon start, reverse reads end at the amplicon end
+    pub fn assign<'a>(
+        candidates: &[&'a BedRegion],
+        read_start: i64,
+        read_end: i64,
+        is_reverse: bool,
+    ) -> Option<&'a str> {
+        candidates
+            .iter()
+            .min_by_key(|amplicon| {
+                if is_reverse {
+                    (read_end - amplicon.end as i64).abs()
+                } else {
+                    (read_start - amplicon.start as i64).abs()
+                }
+            })
+            .and_then(|amplicon| amplicon.name.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_bed_region_from_line() {
+        let region = BedRegion::from_line("chr1\t100\t200\tAMP_1").unwrap();
+        assert_eq!(region.chrom, "chr1");
+        assert_eq!(region.start, 100);
+        assert_eq!(region.end, 200);
+        assert_eq!(region.name.as_deref(), Some("AMP_1"));
+
+        let region = BedRegion::from_line("chr1\t100\t200").unwrap();
+        assert_eq!(region.name, None);
+
+        assert!(BedRegion::from_line("chr1\t100").is_err());
+        assert!(BedRegion::from_line("chr1\t200\t100").is_err());
+        assert!(BedRegion::from_line("chr1\tabc\t100").is_err());
+    }
+
+    #[test]
+    fn test_bed_region_contains() {
+        let region = BedRegion::from_line("chr1\t100\t200").unwrap();
+        assert!(region.contains("chr1", 100));
+        assert!(region.contains("chr1", 199));
+        assert!(!region.contains("chr1", 200));
+        assert!(!region.contains("chr2", 150));
+    }
+
+    #[test]
+    fn test_read_regions() {
+        let mut bed = NamedTempFile::new().unwrap();
+        writeln!(bed, "track name=panel").unwrap();
+        writeln!(bed, "# comment").unwrap();
+        writeln!(bed, "chr1\t100\t200\tAMP_1").unwrap();
+        writeln!(bed).unwrap();
+        writeln!(bed, "chr1\t150\t250\tAMP_2").unwrap();
+
+        let regions = read_regions(bed.path()).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[1].name.as_deref(), Some("AMP_2"));
+    }
+
+    #[test]
+    fn test_region_syntax() {
+        // 1-based inclusive chr1:101-200 is BED chr1 100 200
+        let region = BedRegion::from_region_str("chr1:101-200").unwrap();
+        assert_eq!(region, BedRegion::from_line("chr1\t100\t200").unwrap());
+        assert!(region.contains("chr1", 100));
+        assert!(!region.contains("chr1", 99));
+
+        let single = BedRegion::from_region_str("chr2:1,000").unwrap();
+        assert_eq!((single.start, single.end), (999, 1000));
+        assert_eq!(BedRegion::from_region_str("HLA-A*01:01:1-10").unwrap().chrom, "HLA-A*01:01");
+
+        assert!(BedRegion::from_region_str("chr1").is_err());
+        assert!(BedRegion::from_region_str(":1-10").is_err());
+        assert!(BedRegion::from_region_str("chr1:0-10").is_err());
+        assert!(BedRegion::from_region_str("chr1:200-100").is_err());
+        assert!(is_region_syntax("chr1:100-200 AMP_1"));
+        assert!(!is_region_syntax("chr1\t100\t200"));
+    }
+
+    #[test]
+    fn test_interval_list_line() {
+        let region = BedRegion::from_interval_list_line("chr1\t101\t200\t+\tAMP_1").unwrap();
+        assert_eq!((region.start, region.end), (100, 200));
+        assert_eq!(region.name.as_deref(), Some("AMP_1"));
+        assert_eq!(BedRegion::from_interval_list_line("chr1\t1\t1\t-\t.").unwrap().name, None);
+
+        assert!(BedRegion::from_interval_list_line("chr1\t0\t10").is_err());
+        assert!(BedRegion::from_interval_list_line("chr1\t20\t10").is_err());
+        assert!(BedRegion::from_interval_list_line("chr1\t10").is_err());
+    }
+
+    #[test]
+    fn test_read_regions_formats() {
+        let bed = "chr1\t100\t200\tAMP_1\n";
+        let interval_list = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\nchr1\t101\t200\t+\tAMP_1\n";
+        let region_list = "# panel\nchr1:101-200 AMP_1\n\n";
+        for text in [bed, interval_list, region_list] {
+            let regions = read_regions_from_reader(Cursor::new(text)).unwrap();
+            assert_eq!(regions, vec![BedRegion::from_line("chr1\t100\t200\tAMP_1").unwrap()], "{}", text);
+        }
+
+        // Region lines may be mixed into a BED; names are optional
+        let regions = read_regions_from_reader(Cursor::new("chr1\t0\t10\nchr2:5-6\n")).unwrap();
+        assert_eq!((regions[1].chrom.as_str(), regions[1].start, regions[1].end), ("chr2", 4, 6));
+        assert_eq!(regions[1].name, None);
+        assert!(read_regions_from_reader(Cursor::new("chr1:10-5\n")).is_err());
+    }
+
+    #[test]
+    fn test_retain_in_regions() {
+        let regions = vec![
+            BedRegion::from_region_str("chr1:101-200").unwrap(),
+            BedRegion::from_region_str("chr2:5-5").unwrap(),
+        ];
+        let variant = |chrom: &str, pos: u64| Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        let mut variants = vec![
+            variant("chr1", 100),
+            variant("chr1", 101),
+            variant("chr1", 200),
+            variant("chr2", 5),
+            variant("chr3", 150),
+        ];
+        assert_eq!(retain_in_regions(&mut variants, &regions), 2);
+        let kept: Vec<(&str, u64)> = variants.iter().map(|variant| (variant.chrom.as_str(), variant.pos)).collect();
+        assert_eq!(kept, vec![("chr1", 101), ("chr1", 200), ("chr2", 5)]);
+
+        let mut bed = Vec::new();
+        write_bed_regions_to_writer(&regions, &mut bed).unwrap();
+        let text = String::from_utf8(bed).unwrap();
+        assert!(text.starts_with("#about="));
+        assert_eq!(read_regions_from_reader(Cursor::new(text)).unwrap(), regions);
+    }
+
+    #[test]
+    fn test_amplicon_assignment() {
+        let amplicons = AmpliconSet::from_regions(vec![
+            BedRegion::from_line("chr1\t100\t200\tAMP_1").unwrap(),
+            BedRegion::from_line("chr1\t150\t250\tAMP_2").unwrap(),
+        ])
+        .unwrap();
+
+        let candidates = amplicons.covering("chr1", 175);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(AmpliconSet::assign(&candidates, 101, 190, false), Some("AMP_1"));
+        assert_eq!(AmpliconSet::assign(&candidates, 160, 249, true), Some("AMP_2"));
+
+        assert!(amplicons.covering("chr1", 120).len() == 1);
+        assert!(amplicons.covering("chr2", 175).is_empty());
+        assert_eq!(AmpliconSet::assign(&[], 160, 249, true), None);
+    }
+
+    #[test]
+    fn test_unnamed_amplicons_rejected() {
+        let result = AmpliconSet::from_regions(vec![BedRegion::from_line("chr1\t100\t200").unwrap()]);
+        assert!(matches!(result, Err(VlodError::InvalidConfig(_))));
+    }
+}
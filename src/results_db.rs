@@ -0,0 +1,132 @@
+//! SQLite results store: detectability results of one or more runs (e.g. one per
+//! sample) appended to a single database for review with SQL
+
+use crate::{about::run_about, DetectabilityResult, LodConfig, VlodError, VlodResult};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    run_id INTEGER PRIMARY KEY,
+    sample TEXT NOT NULL,
+    vlod_version TEXT NOT NULL,
+    about TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE IF NOT EXISTS results (
+    run_id INTEGER NOT NULL REFERENCES runs(run_id),
+    sample TEXT NOT NULL,
+    chrom TEXT NOT NULL,
+    pos INTEGER NOT NULL,
+    ref TEXT NOT NULL,
+    alt TEXT NOT NULL,
+    score REAL,
+    condition TEXT NOT NULL,
+    det TEXT NOT NULL,
+    coverage INTEGER NOT NULL,
+    variant_reads INTEGER NOT NULL,
+    detection_probability REAL,
+    orientation_bias REAL,
+    pool_alleles TEXT,
+    pool_power REAL
+);
+CREATE INDEX IF NOT EXISTS results_position ON results(chrom, pos);
+CREATE INDEX IF NOT EXISTS results_condition ON results(condition);
+CREATE INDEX IF NOT EXISTS results_sample ON results(sample);
+";
+
+impl From<rusqlite::Error> for VlodError {
+    fn from(error: rusqlite::Error) -> Self {
+        VlodError::Database(error.to_string())
+    }
+}
+
+/// Append the results of a run to a SQLite database, creating it (and its tables
+/// and indices) if needed. All rows of the run, scored under `config`, are written
+/// in one transaction. Returns the run's `run_id`.
+pub fn append_results_to_db<P: AsRef<Path>>(
+    results: &[DetectabilityResult],
+    path: P,
+    sample: &str,
+    config: &LodConfig,
+) -> VlodResult<i64> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO runs (sample, vlod_version, about) VALUES (?1, ?2, ?3)",
+        params![sample, env!("CARGO_PKG_VERSION"), run_about(config).to_json()],
+    )?;
+    let run_id = transaction.last_insert_rowid();
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO results (run_id, sample, chrom, pos, ref, alt, score, condition, det, coverage, \
+             variant_reads, detection_probability, orientation_bias, pool_alleles, pool_power) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        )?;
+        for result in results {
+            insert.execute(params![
+                run_id,
+                sample,
+                result.variant.chrom,
+                result.variant.pos,
+                result.variant.ref_allele,
+                result.variant.alt_allele,
+                Some(result.detectability_score).filter(|score| score.is_finite()),
+                result.detectability_condition.to_string(),
+                result.detectability_condition.vcf_status(),
+                result.coverage,
+                result.variant_reads,
+                result.detection_probability,
+                result.alt_orientation.bias(),
+                result.pool_alleles,
+                result.pool_power,
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(run_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DetectabilityCondition, Variant};
+
+    fn result(pos: u64, condition: DetectabilityCondition) -> DetectabilityResult {
+        DetectabilityResult::new(
+            Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()),
+            3.0,
+            condition,
+            100,
+            5,
+        )
+    }
+
+    #[test]
+    fn test_append_results_to_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("results.sqlite");
+        let results = vec![
+            result(100, DetectabilityCondition::Detectable),
+            result(200, DetectabilityCondition::NonDetectable),
+        ];
+
+        let config = LodConfig::default();
+        assert_eq!(append_results_to_db(&results, &db, "sample1", &config).unwrap(), 1);
+        assert_eq!(append_results_to_db(&results[..1], &db, "sample2", &config).unwrap(), 2);
+
+        let connection = Connection::open(&db).unwrap();
+        let count: i64 = connection
+            .query_row("SELECT COUNT(*) FROM results WHERE det = 'Yes'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let (sample, pos): (String, u64) = connection
+            .query_row("SELECT sample, pos FROM results WHERE condition = 'Non-detectable'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((sample.as_str(), pos), ("sample1", 200));
+    }
+}
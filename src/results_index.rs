@@ -0,0 +1,412 @@
+//! bgzip-compressed, tabix-indexed detectability TSVs, so that a merge can look up
+//! results record by record instead of loading them all into memory
+
+use crate::{
+    contig::canonical_contig_name,
+    lod::NO_EVIDENCE_SCORE,
+    merge::{parse_results_row, read_schema_version, MergeOptions, MergedFields, ResultsColumns, ResultsLookup},
+    utils::{append_extension, build_tabix_index, is_bgzipped, open_text_input, ParseErrorBudget},
+    vcf::DuplicatePolicy,
+    DetectabilityCondition, Variant, VlodError, VlodResult,
+};
+use rust_htslib::{htslib, tbx};
+use rust_htslib::tbx::Read as TabixRead;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Largest end coordinate passed to a tabix query (htslib's `HTS_POS_MAX`)
+const MAX_FETCH_END: u64 = ((i32::MAX as u64) << 32) | i32::MAX as u64;
+
+/// Positions from which a tabix index must be CSI: a `.tbi` holds positions below 2^29
+const MAX_TBI_POSITION: u64 = 1 << 29;
+
+/// Tabix index of a results TSV: its `.tbi`, or its `.csi` when only that exists
+/// (results with positions beyond the range of a `.tbi`)
+pub fn results_index_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let (tbi, csi) = (append_extension(path.as_ref(), "tbi"), append_extension(path.as_ref(), "csi"));
+    if csi.exists() && !tbi.exists() {
+        csi
+    } else {
+        tbi
+    }
+}
+
+/// Whether a results TSV is bgzip-compressed and has a tabix index next to it
+pub fn is_indexed_results<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    results_index_path(path).exists() && is_bgzipped(path).unwrap_or(false)
+}
+
+/// Leading lines of a results TSV: the optional metadata line and the header row
+struct ResultsHeader {
+    lines: Vec<String>,
+    columns: ResultsColumns,
+}
+
+fn read_results_header<R: BufRead>(mut reader: R) -> VlodResult<(ResultsHeader, R)> {
+    let mut lines = Vec::new();
+    if reader.fill_buf()?.starts_with(b"#vlod_version=") {
+        let mut metadata = String::new();
+        reader.read_line(&mut metadata)?;
+        lines.push(metadata.trim_end().to_string());
+    }
+    let schema = match lines.first() {
+        Some(metadata) => read_schema_version(&mut metadata.as_bytes())?,
+        None => 1,
+    };
+
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+        return Err(VlodError::InvalidVariant("Detectability TSV has no header row".to_string()));
+    }
+    let header = header.trim_end().to_string();
+    let columns = ResultsColumns::for_schema(schema, &csv::StringRecord::from(header.split('\t').collect::<Vec<_>>()))?;
+    lines.push(header);
+    Ok((ResultsHeader { lines, columns }, reader))
+}
+
+/// Write a results TSV as a bgzip-compressed copy sorted by contig (in order of
+/// first appearance) and position, and build its tabix index. Returns the index
+/// path. The copy is still a results TSV that every vlod command reads.
+pub fn index_results<P: AsRef<Path>, Q: AsRef<Path>>(results_path: P, output_path: Q) -> VlodResult<PathBuf> {
+    let (results_path, output_path) = (results_path.as_ref(), output_path.as_ref());
+    if results_path == output_path {
+        return Err(VlodError::InvalidConfig(format!(
+            "the indexed copy of {} must be written to another path",
+            results_path.display()
+        )));
+    }
+
+    let (header, reader) = read_results_header(open_text_input(results_path, usize::MAX)?)?;
+    let mut contig_order: HashMap<String, usize> = HashMap::new();
+    let mut rows: Vec<(usize, u64, String)> = Vec::new();
+    let source = results_path.to_string_lossy();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let line_number = (index + header.lines.len() + 1) as u64;
+        let chrom = fields
+            .get(header.columns.chrom)
+            .ok_or_else(|| VlodError::in_column("Chrom", "Missing column".to_string()).at_line(&source, line_number))?;
+        let pos = fields
+            .get(header.columns.pos)
+            .and_then(|pos| pos.parse::<u64>().ok())
+            .ok_or_else(|| VlodError::in_column("Pos", "Invalid position".to_string()).at_line(&source, line_number))?;
+        let next_contig = contig_order.len();
+        let contig = *contig_order.entry(chrom.to_string()).or_insert(next_contig);
+        rows.push((contig, pos, line));
+    }
+    rows.sort_by_key(|(contig, pos, _)| (*contig, *pos));
+
+    let mut writer = rust_htslib::bgzf::Writer::from_path(output_path)?;
+    for line in header.lines.iter().chain(rows.iter().map(|(_, _, line)| line)) {
+        writeln!(writer, "{}", line)?;
+    }
+    // Dropping the writer flushes the last block and writes the BGZF end marker
+    drop(writer);
+
+    // A .tbi cannot hold the positions of ultra-long contigs; build a .csi for them
+    let csi = rows.iter().any(|(_, pos, _)| *pos >= MAX_TBI_POSITION);
+    let (extension, stale, min_shift) = if csi { ("csi", "tbi", 14) } else { ("tbi", "csi", 0) };
+    let stale = append_extension(output_path, stale);
+    if stale.exists() {
+        std::fs::remove_file(stale)?;
+    }
+    let conf = htslib::tbx_conf_t {
+        preset: htslib::TBX_GENERIC as i32,
+        sc: header.columns.chrom as i32 + 1,
+        bc: header.columns.pos as i32 + 1,
+        ec: header.columns.pos as i32 + 1,
+        meta_char: '#' as i32,
+        line_skip: header.lines.len() as i32,
+    };
+    build_tabix_index(output_path, &conf, min_shift)?;
+    log::info!("Indexed {} results from {:?} into {:?}", rows.len(), results_path, output_path);
+    Ok(append_extension(output_path, extension))
+}
+
+/// A result row read through the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedResult {
+    pub variant: Variant,
+    pub condition: DetectabilityCondition,
+    pub score: f64,
+    pub coverage: Option<u32>,
+    pub probability: Option<f64>,
+}
+
+/// Results read through a tabix index, one region fetch per VCF record
+pub struct IndexedResults {
+    reader: tbx::Reader,
+    columns: ResultsColumns,
+    source: String,
+    contigs: HashSet<String>,
+    /// Indexed contig names by canonical name, for contig aliasing
+    canonical_contigs: Option<HashMap<String, String>>,
+    policy: DuplicatePolicy,
+    max_errors: Option<usize>,
+    errors: ParseErrorBudget,
+    duplicates: usize,
+}
+
+impl IndexedResults {
+    /// Open a bgzip-compressed results TSV with its tabix index
+    pub fn open<P: AsRef<Path>>(path: P, options: &MergeOptions) -> VlodResult<Self> {
+        let path = path.as_ref();
+        let index = results_index_path(path);
+        if !index.exists() {
+            return Err(VlodError::FileNotFound(format!(
+                "Tabix index {} not found; create it with `vlod index-results`",
+                index.display()
+            )));
+        }
+
+        let (header, _) = read_results_header(open_text_input(path, options.max_line_length)?)?;
+        let reader = tbx::Reader::from_path(path)?;
+        let contigs: HashSet<String> = reader.seqnames().into_iter().collect();
+        let canonical_contigs = (!options.strict_contig_names).then(|| {
+            contigs
+                .iter()
+                .map(|contig| (canonical_contig_name(contig), contig.clone()))
+                .collect()
+        });
+        let source = path.to_string_lossy().to_string();
+
+        Ok(IndexedResults {
+            reader,
+            columns: header.columns,
+            errors: ParseErrorBudget::new(&source, options.max_errors),
+            source,
+            contigs,
+            canonical_contigs,
+            policy: options.duplicate_policy,
+            max_errors: options.max_errors,
+            duplicates: 0,
+        })
+    }
+
+    /// Indexed contig for a VCF contig, and whether it was found only by aliasing
+    fn resolve_contig(&self, chrom: &str) -> Option<(String, bool)> {
+        if self.contigs.contains(chrom) {
+            return Some((chrom.to_string(), false));
+        }
+        self.canonical_contigs
+            .as_ref()
+            .and_then(|canonical| canonical.get(&canonical_contig_name(chrom)))
+            .map(|contig| (contig.clone(), true))
+    }
+
+    /// Parsed rows of an indexed contig overlapping the 0-based half-open interval
+    /// `[start, end)`. Invalid rows are fatal unless `max_errors` is set.
+    fn fetch_rows(&mut self, contig: &str, start: u64, end: u64) -> VlodResult<Vec<(Variant, MergedFields)>> {
+        let tid = self.reader.tid(contig)?;
+        self.reader.fetch(tid, start, end.min(MAX_FETCH_END))?;
+
+        let mut records = Vec::new();
+        for line in self.reader.records() {
+            let line = String::from_utf8_lossy(&line?).into_owned();
+            records.push(csv::StringRecord::from(line.split('\t').collect::<Vec<_>>()));
+        }
+
+        let mut rows = Vec::new();
+        for record in records {
+            if record.len() < self.columns.required_len() {
+                continue;
+            }
+            let ((chrom, pos, ref_allele, alt_allele), condition, score) = match parse_results_row(&record, &self.columns) {
+                Ok(row) => row,
+                Err(e) => {
+                    let e = VlodError::InvalidVariant(format!("{} ({}:{}-{}): {}", self.source, contig, start + 1, end, e));
+                    if self.max_errors.is_none() {
+                        return Err(e);
+                    }
+                    self.errors.record(e)?;
+                    continue;
+                }
+            };
+            let number = |column: Option<usize>| {
+                column
+                    .and_then(|column| record.get(column))
+                    .and_then(|value| value.parse::<f64>().ok())
+            };
+            let fields = MergedFields {
+                condition,
+                score: if score.is_finite() { score } else { NO_EVIDENCE_SCORE },
+                probability: number(self.columns.probability),
+                orientation_bias: number(self.columns.orientation_bias),
+                strand_bias: self.columns.strand_bias(&record),
+                coverage: self
+                    .columns
+                    .coverage
+                    .and_then(|column| record.get(column))
+                    .and_then(|depth| depth.parse::<u32>().ok()),
+            };
+            rows.push((Variant::new(chrom, pos, ref_allele, alt_allele), fields));
+        }
+        Ok(rows)
+    }
+
+    /// Results at 1-based positions `start..=end` of a contig (aliased unless
+    /// strict contig names were requested); empty for contigs without results
+    pub fn fetch_region(&mut self, chrom: &str, start: u64, end: u64) -> VlodResult<Vec<IndexedResult>> {
+        let Some((contig, _)) = self.resolve_contig(chrom) else {
+            return Ok(Vec::new());
+        };
+        Ok(self
+            .fetch_rows(&contig, start.saturating_sub(1), end)?
+            .into_iter()
+            .map(|(variant, fields)| IndexedResult {
+                variant,
+                condition: fields.condition,
+                score: fields.score,
+                coverage: fields.coverage,
+                probability: fields.probability,
+            })
+            .collect())
+    }
+}
+
+impl ResultsLookup for IndexedResults {
+    fn lookup(&mut self, key: &(String, u64, String, String)) -> VlodResult<Option<(MergedFields, bool)>> {
+        let (chrom, pos, ref_allele, alt_allele) = key;
+        let Some((contig, aliased)) = self.resolve_contig(chrom) else {
+            return Ok(None);
+        };
+
+        let mut found: Option<MergedFields> = None;
+        for (variant, fields) in self.fetch_rows(&contig, pos.saturating_sub(1), *pos)? {
+            if variant.pos != *pos || variant.ref_allele != *ref_allele || variant.alt_allele != *alt_allele {
+                continue;
+            }
+
+            match &mut found {
+                None => found = Some(fields),
+                Some(existing) => {
+                    self.duplicates += 1;
+                    match self.policy {
+                        DuplicatePolicy::First => {}
+                        DuplicatePolicy::Max => {
+                            if fields.score > existing.score {
+                                *existing = fields;
+                            }
+                        }
+                        DuplicatePolicy::Error => {
+                            return Err(VlodError::InvalidVariant(format!(
+                                "Duplicate detectability result: {}:{} {}>{}",
+                                contig, pos, ref_allele, alt_allele
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(found.map(|fields| (fields, aliased)))
+    }
+
+    fn has_probabilities(&self) -> bool {
+        self.columns.probability.is_some()
+    }
+
+    fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    fn result_count(&self) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::merge_detectability_into_vcf;
+    use std::io::Read;
+
+    const RESULTS: &str = "#vlod_version=0.1.0 #schema=2\n\
+        Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\n\
+        chr2\t500\tC\tT\t1.2\tNonDetectable\t40\n\
+        chr1\t300\tG\tA\t4.0\tDetectable\t90\n\
+        chr1\t100\tA\tG\t3.5\tDetectable\t80\n";
+
+    const VCF: &str = "##fileformat=VCFv4.2\n\
+        ##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        1\t100\t.\tA\tG\t.\tPASS\tDP=80\n\
+        chr1\t300\t.\tG\tC\t.\tPASS\tDP=90\n\
+        chr2\t500\t.\tC\tT\t.\tPASS\tDP=40\n";
+
+    #[test]
+    fn test_index_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = dir.path().join("results.tsv");
+        std::fs::write(&results, RESULTS).unwrap();
+        let indexed = dir.path().join("results.tsv.gz");
+
+        assert!(index_results(&results, &results).is_err());
+        let index = index_results(&results, &indexed).unwrap();
+        assert_eq!(index, dir.path().join("results.tsv.gz.tbi"));
+        assert!(is_indexed_results(&indexed));
+        assert!(!is_indexed_results(&results));
+
+        // The indexed copy is sorted and still reads as a results TSV
+        let mut text = String::new();
+        open_text_input(&indexed, usize::MAX).unwrap().read_to_string(&mut text).unwrap();
+        let positions: Vec<&str> = text.lines().skip(2).map(|line| line.split('\t').nth(1).unwrap()).collect();
+        assert_eq!(positions, vec!["500", "100", "300"]);
+    }
+
+    #[test]
+    fn test_index_ultra_long_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = dir.path().join("results.tsv");
+        std::fs::write(
+            &results,
+            "#vlod_version=0.1.0 #schema=2\n\
+             Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\n\
+             chr1\t100\tA\tG\t3.5\tDetectable\t80\n\
+             chr1\t3000000000\tC\tT\t1.2\tNonDetectable\t40\n",
+        )
+        .unwrap();
+        let indexed = dir.path().join("results.tsv.gz");
+
+        // Positions beyond 2^29 need a .csi index
+        let index = index_results(&results, &indexed).unwrap();
+        assert_eq!(index, dir.path().join("results.tsv.gz.csi"));
+        assert_eq!(results_index_path(&indexed), index);
+        assert!(is_indexed_results(&indexed));
+
+        let mut lookup = IndexedResults::open(&indexed, &MergeOptions::default()).unwrap();
+        let rows = lookup.fetch_region("chr1", 2_999_999_990, 3_000_000_010).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].variant.pos, 3_000_000_000);
+    }
+
+    #[test]
+    fn test_indexed_merge_matches_in_memory_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = dir.path().join("results.tsv");
+        std::fs::write(&results, RESULTS).unwrap();
+        let indexed = dir.path().join("results.tsv.gz");
+        index_results(&results, &indexed).unwrap();
+        let vcf = dir.path().join("input.vcf");
+        std::fs::write(&vcf, VCF).unwrap();
+
+        let options = MergeOptions::default();
+        let in_memory = dir.path().join("in_memory.vcf");
+        let expected = merge_detectability_into_vcf(&vcf, &results, &in_memory, &options).unwrap();
+        let from_index = dir.path().join("from_index.vcf");
+        let stats = merge_detectability_into_vcf(&vcf, &indexed, &from_index, &options).unwrap();
+
+        assert_eq!(stats, expected);
+        assert_eq!(stats.annotated, 2);
+        assert_eq!(
+            std::fs::read_to_string(&from_index).unwrap(),
+            std::fs::read_to_string(&in_memory).unwrap()
+        );
+        assert!(std::fs::read_to_string(&from_index).unwrap().contains("1\t100\t.\tA\tG\t.\tPASS\tDP=80;DET=Yes;DETS=3.5"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Ranked list of variants for manual review: the most concerning first, with a
+//! softmax weight giving each variant's share of the review effort
+
+use crate::{
+    about::about_comment,
+    gtf::Exon,
+    regions::{is_region_syntax, BedRegion},
+    utils::create_output_file,
+    DetectabilityCondition, DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Priority of a region listed without one
+const DEFAULT_PRIORITY: f64 = 1.0;
+/// Bound on `threshold - score` under `ReviewRanking::Score`
+const SCORE_CONCERN_CAP: f64 = 20.0;
+
+/// How variants are ordered for review
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReviewRanking {
+    /// Failing variants in high-priority regions first, then borderline scores:
+    /// `(1 + priority) * (failing + borderline)`
+    #[default]
+    Priority,
+    /// Scores closest to the detection threshold first: `exp(-|score - threshold|)`
+    Borderline,
+    /// Lowest scores relative to the threshold first: `threshold - score`
+    Score,
+}
+
+impl FromStr for ReviewRanking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "priority" => Ok(ReviewRanking::Priority),
+            "borderline" => Ok(ReviewRanking::Borderline),
+            "score" => Ok(ReviewRanking::Score),
+            _ => Err(format!("unknown review ranking '{}' (expected priority, borderline or score)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ReviewRanking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReviewRanking::Priority => write!(f, "priority"),
+            ReviewRanking::Borderline => write!(f, "borderline"),
+            ReviewRanking::Score => write!(f, "score"),
+        }
+    }
+}
+
+/// Options for the review list
+#[derive(Debug, Clone)]
+pub struct ReviewOptions {
+    pub ranking: ReviewRanking,
+    /// Softmax temperature: lower values concentrate the weight on the top variants
+    pub temperature: f64,
+}
+
+impl Default for ReviewOptions {
+    fn default() -> Self {
+        ReviewOptions {
+            ranking: ReviewRanking::default(),
+            temperature: 1.0,
+        }
+    }
+}
+
+/// Clinical priority of regions, from a BED (priority in column 5, default 1),
+/// `chr1:100-200` regions (optionally followed by a priority) or a gene list (one
+/// gene per line, located through the GTF exons)
+#[derive(Debug, Clone, Default)]
+pub struct ReviewPriorities {
+    regions: Vec<(BedRegion, f64)>,
+}
+
+impl ReviewPriorities {
+    pub fn read<P: AsRef<Path>>(path: P, exons: Option<&[Exon]>) -> VlodResult<Self> {
+        let file = File::open(&path).map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+        Self::from_reader(BufReader::new(file), exons)
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R, exons: Option<&[Exon]>) -> VlodResult<Self> {
+        let mut regions = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim_end();
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let priority = |field: Option<&str>| match field.map(|field| field.trim()) {
+                Some(priority) if !priority.is_empty() && priority != "." => priority
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|priority| priority.is_finite() && *priority >= 0.0)
+                    .ok_or_else(|| VlodError::InvalidConfig(format!("invalid review priority '{}'", priority))),
+                _ => Ok(DEFAULT_PRIORITY),
+            };
+            if is_region_syntax(line) {
+                let mut fields = line.split_whitespace();
+                let region = BedRegion::from_region_str(fields.next().unwrap_or_default())?;
+                regions.push((region, priority(fields.next())?));
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() >= 3 {
+                regions.push((BedRegion::from_line(line)?, priority(fields.get(4).copied())?));
+                continue;
+            }
+
+            let gene = line.trim();
+            let exons = exons.ok_or_else(|| {
+                VlodError::InvalidConfig(format!("review priority gene {} needs --gtf to locate it", gene))
+            })?;
+            let gene_exons: Vec<&Exon> = exons.iter().filter(|exon| exon.gene.as_deref() == Some(gene)).collect();
+            if gene_exons.is_empty() {
+                log::warn!("Review priority gene {} has no exons in the GTF", gene);
+            }
+            regions.extend(gene_exons.into_iter().map(|exon| (exon.region.clone(), DEFAULT_PRIORITY)));
+        }
+        Ok(ReviewPriorities { regions })
+    }
+
+    /// Highest priority of the regions covering a variant (0 outside them)
+    pub fn priority(&self, variant: &Variant) -> f64 {
+        self.regions
+            .iter()
+            .filter(|(region, _)| region.contains(&variant.chrom, variant.pos.saturating_sub(1)))
+            .map(|(_, priority)| *priority)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// A variant in the review list
+#[derive(Debug, Clone)]
+pub struct ReviewEntry<'a> {
+    pub result: &'a DetectabilityResult,
+    pub threshold: f64,
+    pub priority: f64,
+    pub concern: f64,
+    /// Softmax of the concern over the list, summing to 1
+    pub weight: f64,
+}
+
+/// Whether a result calls for review whatever its score
+fn is_failing(condition: &DetectabilityCondition) -> bool {
+    !matches!(
+        condition,
+        DetectabilityCondition::Detectable | DetectabilityCondition::Monomorphic | DetectabilityCondition::RefConfirmed
+    )
+}
+
+fn concern(result: &DetectabilityResult, threshold: f64, priority: f64, ranking: ReviewRanking) -> f64 {
+    let score = result.detectability_score;
+    let borderline = if score.is_finite() { (-(score - threshold).abs()).exp() } else { 0.0 };
+    match ranking {
+        ReviewRanking::Priority => {
+            let failing = if is_failing(&result.detectability_condition) { 1.0 } else { 0.0 };
+            (1.0 + priority) * (failing + borderline)
+        }
+        ReviewRanking::Borderline => borderline,
+        ReviewRanking::Score if score.is_nan() => SCORE_CONCERN_CAP,
+        ReviewRanking::Score => (threshold - score).clamp(-SCORE_CONCERN_CAP, SCORE_CONCERN_CAP),
+    }
+}
+
+/// Rank results for review, most concerning first (ties in input order)
+pub fn rank_for_review<'a>(
+    results: &'a [DetectabilityResult],
+    priorities: Option<&ReviewPriorities>,
+    config: &LodConfig,
+    options: &ReviewOptions,
+) -> VlodResult<Vec<ReviewEntry<'a>>> {
+    if !(options.temperature > 0.0 && options.temperature.is_finite()) {
+        return Err(VlodError::InvalidConfig(format!(
+            "review temperature must be positive, got {}",
+            options.temperature
+        )));
+    }
+    let mut entries: Vec<ReviewEntry> = results
+        .iter()
+        .map(|result| {
+            let threshold = config.detection_threshold(&result.variant);
+            let priority = priorities.map_or(0.0, |priorities| priorities.priority(&result.variant));
+            ReviewEntry {
+                result,
+                threshold,
+                priority,
+                concern: concern(result, threshold, priority, options.ranking),
+                weight: 0.0,
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| b.concern.total_cmp(&a.concern));
+
+    if let Some(top) = entries.first().map(|entry| entry.concern) {
+        let exponentials: Vec<f64> =
+            entries.iter().map(|entry| ((entry.concern - top) / options.temperature).exp()).collect();
+        let total: f64 = exponentials.iter().sum();
+        for (entry, exponential) in entries.iter_mut().zip(exponentials) {
+            entry.weight = exponential / total;
+        }
+    }
+    Ok(entries)
+}
+
+/// Write a review list as TSV
+pub fn write_review_list<P: AsRef<Path>>(entries: &[ReviewEntry], path: P) -> VlodResult<()> {
+    let writer = BufWriter::new(create_output_file(path)?);
+    write_review_list_to_writer(entries, writer)
+}
+
+/// Write a review list to any writer
+pub fn write_review_list_to_writer<W: Write>(entries: &[ReviewEntry], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "Rank\tChrom\tPos\tRef\tAlt\tDetectability_Condition\tDetectability_Score\tThreshold\tCoverage\tVariant_Reads\tPriority\tConcern\tReview_Weight"
+    )?;
+    for (rank, entry) in entries.iter().enumerate() {
+        let result = entry.result;
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}\t{}\t{:.4}\t{:.6}",
+            rank + 1,
+            result.variant.chrom,
+            result.variant.pos,
+            result.variant.ref_allele,
+            result.variant.alt_allele,
+            result.detectability_condition,
+            result.detectability_score,
+            entry.threshold,
+            result.coverage,
+            result.variant_reads,
+            entry.priority,
+            entry.concern,
+            entry.weight
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtf::read_exons_from_reader;
+
+    fn result(chrom: &str, pos: u64, score: f64, condition: DetectabilityCondition) -> DetectabilityResult {
+        let variant = Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        DetectabilityResult::new(variant, score, condition, 100, 5)
+    }
+
+    #[test]
+    fn test_review_priorities() {
+        let gtf = "chr2\ttest\texon\t1001\t1100\t.\t+\t.\tgene_id \"G1\"; transcript_id \"T1\"; gene_name \"TP53\"; exon_number \"1\";\n";
+        let exons = read_exons_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+        let list = "# priorities\nchr1\t99\t100\thotspot\t5\nchr1\t0\t1000\tpanel\nTP53\nchr4:10-20 3\n";
+        let priorities = ReviewPriorities::from_reader(list.as_bytes(), Some(&exons)).unwrap();
+
+        let variant = |chrom: &str, pos: u64| Variant::new(chrom.to_string(), pos, "A".to_string(), "G".to_string());
+        assert_eq!(priorities.priority(&variant("chr1", 100)), 5.0);
+        assert_eq!(priorities.priority(&variant("chr1", 500)), 1.0);
+        assert_eq!(priorities.priority(&variant("chr2", 1050)), 1.0);
+        assert_eq!(priorities.priority(&variant("chr3", 10)), 0.0);
+        assert_eq!(priorities.priority(&variant("chr4", 10)), 3.0);
+        assert_eq!(priorities.priority(&variant("chr4", 21)), 0.0);
+
+        assert!(ReviewPriorities::from_reader("TP53\n".as_bytes(), None).is_err());
+        assert!(ReviewPriorities::from_reader("chr1\t0\t10\tx\thigh\n".as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn test_rank_for_review() {
+        let results = vec![
+            result("chr1", 100, 8.0, DetectabilityCondition::Detectable),
+            result("chr1", 200, 2.4, DetectabilityCondition::NonDetectable),
+            result("chr1", 300, 2.8, DetectabilityCondition::Detectable),
+            result("chr1", 400, 0.5, DetectabilityCondition::NonDetectable),
+        ];
+        let priorities = ReviewPriorities::from_reader("chr1\t399\t400\thotspot\t3\n".as_bytes(), None).unwrap();
+        let config = LodConfig::default();
+
+        let entries = rank_for_review(&results, Some(&priorities), &config, &ReviewOptions::default()).unwrap();
+        let order: Vec<u64> = entries.iter().map(|entry| entry.result.variant.pos).collect();
+        assert_eq!(order, vec![400, 200, 300, 100]);
+        assert!((entries.iter().map(|entry| entry.weight).sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(entries[0].weight > entries[1].weight);
+
+        let borderline = ReviewOptions { ranking: ReviewRanking::Borderline, ..ReviewOptions::default() };
+        let entries = rank_for_review(&results, None, &config, &borderline).unwrap();
+        assert_eq!(entries[0].result.variant.pos, 200);
+        assert_eq!(entries[3].result.variant.pos, 100);
+
+        let by_score = ReviewOptions { ranking: ReviewRanking::Score, ..ReviewOptions::default() };
+        let entries = rank_for_review(&results, None, &config, &by_score).unwrap();
+        assert_eq!(entries[0].result.variant.pos, 400);
+
+        let cold = ReviewOptions { temperature: 0.0, ..ReviewOptions::default() };
+        assert!(rank_for_review(&results, None, &config, &cold).is_err());
+
+        let mut tsv = Vec::new();
+        write_review_list_to_writer(&rank_for_review(&results, None, &config, &ReviewOptions::default()).unwrap(), &mut tsv)
+            .unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.lines().nth(1).unwrap().starts_with("Rank\tChrom\tPos"));
+        assert!(tsv.lines().nth(2).unwrap().starts_with("1\tchr1\t200\t"));
+    }
+
+    #[test]
+    fn test_parse_review_ranking() {
+        assert_eq!("Borderline".parse::<ReviewRanking>(), Ok(ReviewRanking::Borderline));
+        assert_eq!(ReviewRanking::Score.to_string(), "score");
+        assert!("softmax".parse::<ReviewRanking>().unwrap_err().contains("expected priority, borderline or score"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Per-exon and per-transcript rollup of detectability results over a GTF/GFF3
+//! annotation
+
+use crate::{
+    about::about_comment,
+    gtf::Exon,
+    lod::min_detectable_alt_reads,
+    pool::binomial_upper_tail,
+    utils::create_output_file,
+    DetectabilityResult, LodConfig, Variant, VlodResult,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Power at the target VAF for a variant position to count as assessable
+pub const ROLLUP_MIN_POWER: f64 = 0.95;
+
+/// Deepest depth `required_depth_for_vaf` searches
+pub const MAX_REQUIRED_DEPTH: u32 = 1 << 24;
+
+/// Annotation level a rollup row summarizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureLevel {
+    Transcript,
+    Exon,
+}
+
+impl fmt::Display for FeatureLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureLevel::Transcript => write!(f, "transcript"),
+            FeatureLevel::Exon => write!(f, "exon"),
+        }
+    }
+}
+
+/// Detectability of the variant positions within one exon or transcript
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureRollup {
+    pub level: FeatureLevel,
+    /// Transcript ID, or `transcript:exon_number` for exons
+    pub id: String,
+    pub gene: Option<String>,
+    pub chrom: String,
+    /// 0-based half-open span of the feature
+    pub start: u64,
+    pub end: u64,
+    pub variants: usize,
+    /// Positions where a variant at the target VAF is detected with
+    /// `ROLLUP_MIN_POWER`
+    pub assessable: usize,
+    /// Positions whose observed score reaches the detection threshold
+    pub detectable: usize,
+}
+
+impl FeatureRollup {
+    pub fn assessable_fraction(&self) -> f64 {
+        if self.variants == 0 {
+            0.0
+        } else {
+            self.assessable as f64 / self.variants as f64
+        }
+    }
+}
+
+/// Whether a variant at `target_vaf` would be detected at this site's coverage
+/// with at least `ROLLUP_MIN_POWER`
+pub fn is_assessable(result: &DetectabilityResult, config: &LodConfig, target_vaf: f64) -> bool {
+    detection_power(result.coverage, &result.variant, config, target_vaf) >= ROLLUP_MIN_POWER
+}
+
+/// Probability that a variant at `vaf` reaches the detection threshold at `depth`
+pub fn detection_power(depth: u32, variant: &Variant, config: &LodConfig, vaf: f64) -> f64 {
+    let threshold = config.detection_threshold(variant);
+    min_detectable_alt_reads(depth, variant, config, threshold)
+        .map_or(0.0, |min_reads| binomial_upper_tail(depth, min_reads, vaf))
+}
+
+/// Depth at which a variant at `vaf` would be detected with `ROLLUP_MIN_POWER`,
+/// the inverse of `is_assessable` (None beyond `MAX_REQUIRED_DEPTH`). Power is not
+/// strictly monotone in depth, so this is the first depth found by bisection
+/// between powers of two rather than the global minimum.
+pub fn required_depth_for_vaf(variant: &Variant, config: &LodConfig, vaf: f64) -> Option<u32> {
+    let powered = |depth: u32| detection_power(depth, variant, config, vaf) >= ROLLUP_MIN_POWER;
+    let mut high = 2;
+    while !powered(high) {
+        if high >= MAX_REQUIRED_DEPTH {
+            return None;
+        }
+        high *= 2;
+    }
+    // `low` is underpowered, `high` powered
+    let mut low = high / 2;
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if powered(mid) {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    Some(high)
+}
+
+/// Roll results up per exon and per transcript. Only features overlapping at least
+/// one variant are reported, transcripts before their exons.
+pub fn rollup_by_feature(
+    exons: &[Exon],
+    results: &[DetectabilityResult],
+    config: &LodConfig,
+    target_vaf: f64,
+) -> Vec<FeatureRollup> {
+    // Per chromosome, (0-based position, assessable, detectable) sorted by position
+    let mut sites: HashMap<&str, Vec<(u64, bool, bool)>> = HashMap::new();
+    for result in results {
+        sites.entry(result.variant.chrom.as_str()).or_default().push((
+            result.variant.pos.saturating_sub(1),
+            is_assessable(result, config, target_vaf),
+            result.detectability_condition.is_detectable(),
+        ));
+    }
+    for positions in sites.values_mut() {
+        positions.sort_by_key(|&(pos, _, _)| pos);
+    }
+
+    // Each transcript with its exon rows, in transcript ID order
+    let mut transcripts: BTreeMap<&str, (FeatureRollup, Vec<FeatureRollup>)> = BTreeMap::new();
+    for exon in exons {
+        let within = sites
+            .get(exon.region.chrom.as_str())
+            .map(|positions| {
+                let first = positions.partition_point(|&(pos, _, _)| pos < exon.region.start);
+                let last = positions.partition_point(|&(pos, _, _)| pos < exon.region.end);
+                &positions[first..last]
+            })
+            .unwrap_or(&[]);
+        let assessable = within.iter().filter(|&&(_, assessable, _)| assessable).count();
+        let detectable = within.iter().filter(|&&(_, _, detectable)| detectable).count();
+
+        let (transcript, exon_rows) = transcripts.entry(exon.transcript_id.as_str()).or_insert_with(|| {
+            let transcript = FeatureRollup {
+                level: FeatureLevel::Transcript,
+                id: exon.transcript_id.clone(),
+                gene: exon.gene.clone(),
+                chrom: exon.region.chrom.clone(),
+                start: exon.region.start,
+                end: exon.region.end,
+                variants: 0,
+                assessable: 0,
+                detectable: 0,
+            };
+            (transcript, Vec::new())
+        });
+        transcript.start = transcript.start.min(exon.region.start);
+        transcript.end = transcript.end.max(exon.region.end);
+        transcript.variants += within.len();
+        transcript.assessable += assessable;
+        transcript.detectable += detectable;
+
+        if !within.is_empty() {
+            exon_rows.push(FeatureRollup {
+                level: FeatureLevel::Exon,
+                id: format!("{}:{}", exon.transcript_id, exon.number),
+                gene: exon.gene.clone(),
+                chrom: exon.region.chrom.clone(),
+                start: exon.region.start,
+                end: exon.region.end,
+                variants: within.len(),
+                assessable,
+                detectable,
+            });
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (transcript, mut exon_rows) in transcripts.into_values() {
+        if transcript.variants > 0 {
+            exon_rows.sort_by_key(|row| row.start);
+            rows.push(transcript);
+            rows.append(&mut exon_rows);
+        }
+    }
+    rows
+}
+
+/// Write a rollup as TSV
+pub fn write_rollup<P: AsRef<Path>>(rows: &[FeatureRollup], path: P) -> VlodResult<()> {
+    let writer = BufWriter::new(create_output_file(path)?);
+    write_rollup_to_writer(rows, writer)
+}
+
+/// Write a rollup to any writer
+pub fn write_rollup_to_writer<W: Write>(rows: &[FeatureRollup], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "Feature\tID\tGene\tChrom\tStart\tEnd\tVariants\tAssessable\tAssessable_Fraction\tDetectable"
+    )?;
+    for row in rows {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+            row.level,
+            row.id,
+            row.gene.as_deref().unwrap_or("."),
+            row.chrom,
+            row.start,
+            row.end,
+            row.variants,
+            row.assessable,
+            row.assessable_fraction(),
+            row.detectable
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gtf::read_exons_from_reader, DetectabilityCondition};
+
+    fn result(pos: u64, coverage: u32, condition: DetectabilityCondition) -> DetectabilityResult {
+        DetectabilityResult::new(
+            Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()),
+            0.0,
+            condition,
+            coverage,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_rollup_by_feature() {
+        let gtf = "chr1\tsrc\texon\t100\t200\t.\t+\t.\ttranscript_id \"T1\"; gene_name \"ABC\"; exon_number \"1\";\n\
+            chr1\tsrc\texon\t300\t400\t.\t+\t.\ttranscript_id \"T1\"; gene_name \"ABC\"; exon_number \"2\";\n\
+            chr1\tsrc\texon\t600\t700\t.\t+\t.\ttranscript_id \"T2\"; gene_name \"XYZ\"; exon_number \"1\";\n";
+        let exons = read_exons_from_reader(gtf.as_bytes(), "test.gtf").unwrap();
+        let results = vec![
+            result(100, 1000, DetectabilityCondition::Detectable),
+            result(150, 5, DetectabilityCondition::NonDetectable),
+            result(350, 1000, DetectabilityCondition::NonDetectable),
+            result(500, 1000, DetectabilityCondition::Detectable),
+        ];
+
+        let rows = rollup_by_feature(&exons, &results, &LodConfig::default(), 0.1);
+        // T2 has no variants, the intronic site counts for no feature
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].level, FeatureLevel::Transcript);
+        assert_eq!((rows[0].start, rows[0].end), (99, 400));
+        assert_eq!((rows[0].variants, rows[0].assessable, rows[0].detectable), (3, 2, 1));
+        assert_eq!(rows[1].id, "T1:1");
+        assert_eq!((rows[1].variants, rows[1].assessable), (2, 1));
+        assert_eq!(rows[2].id, "T1:2");
+
+        let mut report = Vec::new();
+        write_rollup_to_writer(&rows, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("transcript\tT1\tABC\tchr1\t99\t400\t3\t2\t0.6667\t1\n"));
+        assert!(report.contains("exon\tT1:1\tABC\tchr1\t99\t200\t2\t1\t0.5000\t1\n"));
+    }
+
+    #[test]
+    fn test_required_depth_for_vaf() {
+        let config = LodConfig::default();
+        let variant = result(100, 0, DetectabilityCondition::NoCoverage).variant;
+        let depth = required_depth_for_vaf(&variant, &config, 0.05).unwrap();
+        assert!(detection_power(depth, &variant, &config, 0.05) >= ROLLUP_MIN_POWER);
+        assert!(detection_power(depth - 1, &variant, &config, 0.05) < ROLLUP_MIN_POWER);
+        // Lower VAFs need more depth
+        assert!(required_depth_for_vaf(&variant, &config, 0.01).unwrap() > depth);
+        assert!(required_depth_for_vaf(&variant, &config, 0.2).unwrap() < depth);
+        assert_eq!(required_depth_for_vaf(&variant, &config, 1e-9), None);
+    }
+
+    #[test]
+    fn test_is_assessable_depends_on_target_vaf() {
+        let site = result(100, 200, DetectabilityCondition::NonDetectable);
+        assert!(is_assessable(&site, &LodConfig::default(), 0.2));
+        assert!(!is_assessable(&site, &LodConfig::default(), 0.01));
+        assert!(!is_assessable(&result(100, 0, DetectabilityCondition::NoCoverage), &LodConfig::default(), 0.2));
+    }
+}
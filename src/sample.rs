@@ -0,0 +1,213 @@
+//! Sample name of a run, given on the command line or detected from the `SM` tags
+//! of the BAM read groups or the VCF sample column, and recorded in every output
+//! so that results from many samples can be concatenated
+
+use crate::{utils::open_text_input, VlodResult};
+use rust_htslib::bam::{Read, Reader};
+use std::fmt;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where a sample name came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleSource {
+    /// `--sample-name`
+    Option,
+    /// The `SM` tag of the BAM `@RG` lines
+    ReadGroup,
+    /// The single sample column of the VCF
+    Vcf,
+    /// The BAM file name
+    FileName,
+}
+
+impl fmt::Display for SampleSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleSource::Option => write!(f, "option"),
+            SampleSource::ReadGroup => write!(f, "bam-rg"),
+            SampleSource::Vcf => write!(f, "vcf"),
+            SampleSource::FileName => write!(f, "file-name"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleName {
+    pub name: String,
+    pub source: SampleSource,
+}
+
+impl SampleName {
+    /// `##vlodSample` VCF header line recording the name and its source
+    pub fn vcf_header_line(&self) -> String {
+        format!("##vlodSample=<ID={},Source={}>", self.name, self.source)
+    }
+}
+
+/// BAM given as `FILE`, or as `SAMPLE=FILE` naming the VCF sample column it was
+/// sequenced for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleBam {
+    pub sample: Option<String>,
+    pub path: PathBuf,
+}
+
+impl FromStr for SampleBam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A '=' inside a directory name is part of the path, not a sample name
+        match s.split_once('=') {
+            Some((sample, path)) if !sample.is_empty() && !sample.contains('/') => {
+                if path.is_empty() {
+                    return Err(format!("no BAM given for sample '{}'", sample));
+                }
+                Ok(SampleBam { sample: Some(sample.to_string()), path: PathBuf::from(path) })
+            }
+            _ => Ok(SampleBam { sample: None, path: PathBuf::from(s) }),
+        }
+    }
+}
+
+impl fmt::Display for SampleBam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.sample {
+            Some(sample) => write!(f, "{}={}", sample, self.path.display()),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+/// Distinct `SM` values of the `@RG` lines of SAM header text, in order
+pub fn read_group_samples(header: &str) -> Vec<String> {
+    let mut samples: Vec<String> = Vec::new();
+    let tags = header
+        .lines()
+        .filter(|line| line.starts_with("@RG"))
+        .filter_map(|line| line.split('\t').find_map(|field| field.strip_prefix("SM:")));
+    for sample in tags {
+        if !samples.iter().any(|known| known == sample) {
+            samples.push(sample.to_string());
+        }
+    }
+    samples
+}
+
+/// Sample columns of a VCF `#CHROM` header line
+pub fn vcf_header_samples(chrom_line: &str) -> Vec<String> {
+    chrom_line.trim_end().split('\t').skip(9).map(str::to_string).collect()
+}
+
+/// Sample columns of a VCF (none for a sites-only VCF)
+pub fn vcf_samples<P: AsRef<Path>>(vcf_path: P, max_line_length: usize) -> VlodResult<Vec<String>> {
+    for line in open_text_input(vcf_path, max_line_length)?.lines() {
+        let line = line?;
+        if line.starts_with("#CHROM") {
+            return Ok(vcf_header_samples(&line));
+        }
+        if !line.starts_with('#') {
+            break;
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Choose the sample name: `explicit` if given, else the BAM's only read-group
+/// sample, else the VCF's only sample column, else the BAM file name
+pub fn choose_sample_name(
+    explicit: Option<&str>,
+    bam_samples: &[String],
+    vcf_samples: &[String],
+    bam_path: &Path,
+) -> SampleName {
+    if let Some(name) = explicit {
+        return SampleName { name: name.to_string(), source: SampleSource::Option };
+    }
+    if bam_samples.len() > 1 {
+        log::warn!(
+            "The BAM has read groups from {} samples ({}); give --sample-name to name the results",
+            bam_samples.len(),
+            bam_samples.join(", ")
+        );
+    }
+    match (bam_samples, vcf_samples) {
+        ([bam], [vcf]) if bam != vcf => {
+            log::warn!("The BAM read groups are from sample {} but the VCF is from sample {}", bam, vcf);
+            SampleName { name: bam.clone(), source: SampleSource::ReadGroup }
+        }
+        ([bam], _) => SampleName { name: bam.clone(), source: SampleSource::ReadGroup },
+        (_, [vcf]) => SampleName { name: vcf.clone(), source: SampleSource::Vcf },
+        _ => SampleName {
+            name: bam_path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+            source: SampleSource::FileName,
+        },
+    }
+}
+
+/// Resolve the sample name of a run from the BAM header and the VCF
+pub fn resolve_sample_name<P: AsRef<Path>, Q: AsRef<Path>>(
+    explicit: Option<&str>,
+    bam_path: P,
+    vcf_path: Q,
+    max_line_length: usize,
+) -> VlodResult<SampleName> {
+    if let Some(name) = explicit {
+        return Ok(SampleName { name: name.to_string(), source: SampleSource::Option });
+    }
+    let reader = Reader::from_path(bam_path.as_ref())?;
+    let bam_samples = read_group_samples(&String::from_utf8_lossy(reader.header().as_bytes()));
+    let vcf_samples = vcf_samples(vcf_path, max_line_length)?;
+    let sample = choose_sample_name(None, &bam_samples, &vcf_samples, bam_path.as_ref());
+    log::info!("Sample name: {} (from {})", sample.name, sample.source);
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_group_samples() {
+        let header = "@HD\tVN:1.6\n@RG\tID:lane1\tSM:NA12878\tPL:ILLUMINA\n@RG\tID:lane2\tSM:NA12878\n\
+                      @RG\tID:lane3\tSM:NA24385\n@RG\tID:nosm\n@PG\tID:bwa\n";
+        assert_eq!(read_group_samples(header), vec!["NA12878", "NA24385"]);
+        assert!(read_group_samples("@HD\tVN:1.6\n").is_empty());
+        assert_eq!(
+            vcf_header_samples("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tTUMOR\tNORMAL\n"),
+            vec!["TUMOR", "NORMAL"]
+        );
+        assert!(vcf_header_samples("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").is_empty());
+    }
+
+    #[test]
+    fn test_choose_sample_name() {
+        let bam = Path::new("/data/run1.sorted.bam");
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let chosen = |explicit, bam_samples: &[&str], vcf_samples: &[&str]| {
+            let sample = choose_sample_name(explicit, &names(bam_samples), &names(vcf_samples), bam);
+            (sample.name, sample.source)
+        };
+
+        assert_eq!(chosen(Some("S1"), &["A"], &["B"]), ("S1".to_string(), SampleSource::Option));
+        assert_eq!(chosen(None, &["A"], &["B"]), ("A".to_string(), SampleSource::ReadGroup));
+        assert_eq!(chosen(None, &["A", "C"], &["B"]), ("B".to_string(), SampleSource::Vcf));
+        assert_eq!(chosen(None, &[], &["T", "N"]), ("run1.sorted".to_string(), SampleSource::FileName));
+
+        let sample = SampleName { name: "A".to_string(), source: SampleSource::ReadGroup };
+        assert_eq!(sample.vcf_header_line(), "##vlodSample=<ID=A,Source=bam-rg>");
+    }
+
+    #[test]
+    fn test_parse_sample_bam() {
+        let tumor: SampleBam = "TUMOR=/data/tumor.bam".parse().unwrap();
+        assert_eq!(tumor.sample.as_deref(), Some("TUMOR"));
+        assert_eq!(tumor.path, PathBuf::from("/data/tumor.bam"));
+        assert_eq!(tumor.to_string(), "TUMOR=/data/tumor.bam");
+
+        let plain: SampleBam = "/data/run=1/sample.bam".parse().unwrap();
+        assert_eq!((plain.sample, plain.path), (None, PathBuf::from("/data/run=1/sample.bam")));
+        assert!("TUMOR=".parse::<SampleBam>().is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Small HTTP endpoint answering region queries over an indexed results TSV, so
+//! that genome browsers (IGV, JBrowse) can show detectability as a live track.
+//! Every response carries a request id (the client's `X-Request-Id`, or a new
+//! one) under which the query is recorded in the audit log, if there is one.
+
+use crate::{
+    audit::{new_request_id, AuditEntry, AuditEvent, AuditLog},
+    regions::BedRegion,
+    results_index::{IndexedResult, IndexedResults},
+    utils::LineLengthGuard,
+    DetectabilityCondition, VlodError, VlodResult,
+};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Address served by default; only the local machine can connect
+pub const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:8080";
+
+/// Time a connection is given for each read of its request and write of the
+/// response, so that a stalled client cannot hold the server
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request or header line read, in bytes
+const MAX_REQUEST_LINE_LENGTH: usize = 8 * 1024;
+
+/// Most header lines read from one request
+const MAX_REQUEST_HEADERS: usize = 100;
+
+/// Longest client `X-Request-Id` kept; longer ids are replaced by a new one
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// A region query, `chr1:1-1000000` (1-based, inclusive), `chr1:1000` or `chr1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionQuery {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl FromStr for RegionQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid region '{}' (expected CHROM, CHROM:POS or CHROM:START-END)", s);
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        if !s.contains(':') {
+            return Ok(RegionQuery { chrom: s.to_string(), start: 1, end: u64::MAX });
+        }
+        // Browsers copy positions with thousands separators (chr1:1,000-2,000)
+        let region = BedRegion::from_region_str(s).map_err(|_| invalid())?;
+        Ok(RegionQuery { chrom: region.chrom, start: region.start + 1, end: region.end })
+    }
+}
+
+impl fmt::Display for RegionQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.chrom, self.start, self.end)
+    }
+}
+
+/// Response format of a region query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    Json,
+    /// BED9 with `itemRgb` colours: green detectable, red non-detectable, grey otherwise
+    Bed,
+}
+
+impl FromStr for TrackFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(TrackFormat::Json),
+            "bed" => Ok(TrackFormat::Bed),
+            _ => Err(format!("invalid format '{}' (expected json or bed)", s)),
+        }
+    }
+}
+
+/// An HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", message),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Decode `%XX` escapes and `+` in a query string value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let escape = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match escape.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Answer a request target such as `/results?region=chr1:1-1000000&format=bed`;
+/// BED scores are scaled to the score threshold the results were called at
+pub fn handle_request(results: &mut IndexedResults, target: &str, score_threshold: f64) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/results" {
+        return Response::error(404, "Not found; query /results?region=CHROM:START-END[&format=json|bed]");
+    }
+
+    let mut region = None;
+    let mut format = TrackFormat::Json;
+    for (key, value) in query.split('&').filter_map(|parameter| parameter.split_once('=')) {
+        let value = percent_decode(value);
+        match key {
+            "region" => match value.parse::<RegionQuery>() {
+                Ok(query) => region = Some(query),
+                Err(e) => return Response::error(400, &e),
+            },
+            "format" => match value.parse::<TrackFormat>() {
+                Ok(value) => format = value,
+                Err(e) => return Response::error(400, &e),
+            },
+            _ => {}
+        }
+    }
+    let Some(region) = region else {
+        return Response::error(400, "Missing region parameter");
+    };
+
+    match results.fetch_region(&region.chrom, region.start, region.end) {
+        Ok(rows) => match format {
+            TrackFormat::Json => Response {
+                status: 200,
+                content_type: "application/json",
+                body: results_json(&rows),
+            },
+            TrackFormat::Bed => Response {
+                status: 200,
+                content_type: "text/plain",
+                body: results_bed(&rows, score_threshold),
+            },
+        },
+        Err(e) => {
+            log::warn!("Query {} failed: {}", region, e);
+            Response::error(500, &e.to_string())
+        }
+    }
+}
+
+fn results_json(rows: &[IndexedResult]) -> String {
+    let rows: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            serde_json::json!({
+                "chrom": row.variant.chrom,
+                "pos": row.variant.pos,
+                "ref": row.variant.ref_allele,
+                "alt": row.variant.alt_allele,
+                "det": row.condition.vcf_status(),
+                "condition": row.condition.to_string(),
+                "score": row.score,
+                "coverage": row.coverage,
+                "probability": row.probability,
+            })
+        })
+        .collect();
+    serde_json::Value::Array(rows).to_string()
+}
+
+fn results_bed(rows: &[IndexedResult], score_threshold: f64) -> String {
+    let mut bed = String::from("track name=vLoD description=\"Variant detectability\" itemRgb=On\n");
+    for row in rows {
+        let start = row.variant.pos.saturating_sub(1);
+        let end = start + row.variant.ref_allele.len().max(1) as u64;
+        // BED scores run 0-1000; twice the threshold saturates
+        let score = (row.score.max(0.0) / (2.0 * score_threshold)).min(1.0) * 1000.0;
+        let colour = match row.condition {
+            DetectabilityCondition::Detectable => "0,128,0",
+            DetectabilityCondition::NonDetectable => "200,0,0",
+            _ => "128,128,128",
+        };
+        bed.push_str(&format!(
+            "{}\t{}\t{}\t{}>{}:{}\t{:.0}\t.\t{}\t{}\t{}\n",
+            row.variant.chrom,
+            start,
+            end,
+            row.variant.ref_allele,
+            row.variant.alt_allele,
+            row.condition.vcf_status(),
+            score,
+            start,
+            end,
+            colour
+        ));
+    }
+    bed
+}
+
+/// Read one request from a connection and write the response. Reads and writes
+/// time out, and overlong lines or too many headers fail the request.
+fn handle_connection(
+    stream: TcpStream,
+    results: &mut IndexedResults,
+    score_threshold: f64,
+    audit: Option<&AuditLog>,
+) -> VlodResult<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let guard = LineLengthGuard::new(stream.try_clone()?, MAX_REQUEST_LINE_LENGTH).named("the request");
+    let mut reader = BufReader::new(guard);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers must be read before the response is written; only X-Request-Id is used
+    let mut request_id = None;
+    let mut header = String::new();
+    let mut headers = 0;
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        headers += 1;
+        if headers > MAX_REQUEST_HEADERS {
+            let message = format!("request has more than {} headers", MAX_REQUEST_HEADERS);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message).into());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("x-request-id") {
+                request_id = client_request_id(value);
+            }
+        }
+        header.clear();
+    }
+    let request_id = request_id.unwrap_or_else(new_request_id);
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => handle_request(results, target, score_threshold),
+        (Some(_), Some(_)) => Response::error(405, "Only GET is supported"),
+        _ => Response::error(400, "Malformed request"),
+    };
+    let detail = format!("{} -> {}", request_line.trim_end(), response.status);
+    log::debug!("{} [{}]", detail, request_id);
+    if let Some(audit) = audit {
+        let mut entry = AuditEntry::new("serve", "query", &request_id);
+        entry.actor = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+        entry.detail = Some(detail);
+        let event = if response.status == 200 { AuditEvent::Finished } else { AuditEvent::Failed };
+        audit.record(&entry, event)?;
+    }
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nX-Request-Id: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        request_id,
+        response.body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// The client's `X-Request-Id` header value, if it is safe to echo in the response
+/// and record in the audit log: printable ASCII without spaces, at most
+/// MAX_REQUEST_ID_LENGTH bytes
+fn client_request_id(value: &str) -> Option<String> {
+    let value = value.trim();
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LENGTH
+        && value.bytes().all(|byte| byte.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+/// Serve region queries over indexed results called at `score_threshold` at
+/// `address` until the process is stopped, recording each in `audit` if given.
+/// Connections are answered one at a time.
+pub fn serve(
+    mut results: IndexedResults,
+    address: &str,
+    score_threshold: f64,
+    audit: Option<&AuditLog>,
+) -> VlodResult<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| VlodError::InvalidConfig(format!("cannot listen on {}: {}", address, e)))?;
+    log::info!("Serving detectability results at http://{}/results", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(VlodError::from)
+            .and_then(|stream| handle_connection(stream, &mut results, score_threshold, audit));
+        if let Err(e) = result {
+            log::warn!("Request failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeOptions;
+    use crate::results_index::index_results;
+    use crate::DEFAULT_DETECTION_THRESHOLD;
+
+    #[test]
+    fn test_parse_region_query() {
+        let region: RegionQuery = "chr1:1,000-2,000".parse().unwrap();
+        assert_eq!(region, RegionQuery { chrom: "chr1".to_string(), start: 1000, end: 2000 });
+        assert_eq!("chr2:500".parse::<RegionQuery>().unwrap().end, 500);
+        assert_eq!("chrX".parse::<RegionQuery>().unwrap().end, u64::MAX);
+        assert!("chr1:2000-1000".parse::<RegionQuery>().is_err());
+        assert!("chr1:0-10".parse::<RegionQuery>().is_err());
+        assert!(":1-10".parse::<RegionQuery>().is_err());
+        assert_eq!(percent_decode("chr1%3A1-100"), "chr1:1-100");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let tsv = dir.path().join("results.tsv");
+        std::fs::write(
+            &tsv,
+            "#vlod_version=0.1.0 #schema=2\n\
+             Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\tCoverage\n\
+             chr1\t100\tA\tG\t3.5\tDetectable\t80\n\
+             chr1\t5000\tC\tT\t1.0\tNonDetectable\t20\n",
+        )
+        .unwrap();
+        let indexed = dir.path().join("results.tsv.gz");
+        index_results(&tsv, &indexed).unwrap();
+        let mut results = IndexedResults::open(&indexed, &MergeOptions::default()).unwrap();
+
+        let threshold = DEFAULT_DETECTION_THRESHOLD;
+        let response = handle_request(&mut results, "/results?region=chr1%3A1-1000", threshold);
+        assert_eq!(response.status, 200);
+        let rows: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 1);
+        assert_eq!(rows[0]["det"], "Yes");
+        assert_eq!(rows[0]["coverage"], 80);
+
+        // Contig names are aliased, as in a merge
+        let response = handle_request(&mut results, "/results?region=1:1-10000&format=bed", threshold);
+        let lines: Vec<&str> = response.body.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "chr1\t99\t100\tA>G:Yes\t700\t.\t99\t100\t0,128,0");
+        assert!(lines[2].ends_with("200,0,0"));
+        // A stricter threshold scales the BED scores down
+        let response = handle_request(&mut results, "/results?region=chr1&format=bed", 5.0);
+        assert!(response.body.lines().nth(1).unwrap().contains("\tA>G:Yes\t350\t"));
+
+        assert_eq!(handle_request(&mut results, "/results?region=chr9", threshold).body, "[]");
+        assert_eq!(handle_request(&mut results, "/results", threshold).status, 400);
+        assert_eq!(handle_request(&mut results, "/results?region=chr1&format=vcf", threshold).status, 400);
+        assert_eq!(handle_request(&mut results, "/tracks", threshold).status, 404);
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let tsv = dir.path().join("results.tsv");
+        std::fs::write(
+            &tsv,
+            "Chrom\tPos\tRef\tAlt\tDetectability_Score\tDetectability_Condition\nchr1\t100\tA\tG\t3.5\tDetectable\n",
+        )
+        .unwrap();
+        let indexed = dir.path().join("results.tsv.gz");
+        index_results(&tsv, &indexed).unwrap();
+        let mut results = IndexedResults::open(&indexed, &MergeOptions::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut request = |request: String| {
+            let mut client = TcpStream::connect(address).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &mut results, DEFAULT_DETECTION_THRESHOLD, None)
+        };
+
+        assert!(request("GET /results?region=chr1 HTTP/1.1\r\nX-Request-Id: q1\r\n\r\n".to_string()).is_ok());
+
+        assert_eq!(client_request_id(" q1\r\n").as_deref(), Some("q1"));
+        assert_eq!(client_request_id("q1\rSet-Cookie: x"), None);
+        assert_eq!(client_request_id("q\t1"), None);
+        assert_eq!(client_request_id(""), None);
+        assert_eq!(client_request_id(&"q".repeat(MAX_REQUEST_ID_LENGTH + 1)), None);
+        let long_line = format!("GET /results?region={} HTTP/1.1\r\n\r\n", "1".repeat(MAX_REQUEST_LINE_LENGTH));
+        assert!(request(long_line).is_err());
+        let headers = "X-A: b\r\n".repeat(MAX_REQUEST_HEADERS + 1);
+        let many_headers = format!("GET /results?region=chr1 HTTP/1.1\r\n{}\r\n", headers);
+        assert!(request(many_headers).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+//! Streaming annotation: the VCF is read in bounded batches of records, each
+//! scored against the BAM and annotated as the annotated VCF is written, so that
+//! memory does not grow with the number of variants (e.g. whole-genome VCFs)
+
+use crate::{
+    interrupt,
+    lod::{calculate_detectability_scores_until, validate_lod_config},
+    merge::{merge_lookup_into_writer, results_table, MergeOptions, MergeStats, MergedFields, ResultsLookup, ResultsTable},
+    utils::{get_num_cpus, open_text_input, ParseErrorBudget},
+    vcf::{
+        apply_monomorphic_policy, dedup_variants, select_pass_variants, Caller, MonomorphicPolicy, VariantOverrideMap,
+        VariantOverrides, VcfColumnIndices, VcfRecord,
+    },
+    warnings::{WarningKind, Warnings},
+    LodConfig, VlodError, VlodResult,
+};
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufRead, Lines, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// VCF records read, scored and annotated at a time unless another batch size is given
+pub const DEFAULT_STREAM_BATCH_SIZE: usize = 10_000;
+
+/// Configuration for a streaming annotation run
+#[derive(Debug, Clone)]
+pub struct StreamingConfig {
+    pub lod: LodConfig,
+    /// Merge options; `keep_annotated` records are still scored
+    pub merge: MergeOptions,
+    /// Analyse only variants whose FILTER is PASS
+    pub pass_only: bool,
+    pub monomorphic_policy: MonomorphicPolicy,
+    pub num_processes: usize,
+    /// VCF records per batch; a batch's results are all that is held in memory
+    pub batch_size: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            lod: LodConfig::default(),
+            merge: MergeOptions::default(),
+            pass_only: false,
+            monomorphic_policy: MonomorphicPolicy::default(),
+            num_processes: get_num_cpus(),
+            batch_size: DEFAULT_STREAM_BATCH_SIZE,
+        }
+    }
+}
+
+/// Outcome of a streaming annotation run
+#[derive(Debug, Clone)]
+pub struct StreamingOutput {
+    pub stats: MergeStats,
+    /// Records skipped or not assessable, with examples
+    pub warnings: Warnings,
+    /// Batches scored
+    pub batches: usize,
+    /// Results scored over all batches
+    pub scored: usize,
+    /// Results Detectable
+    pub detectable: usize,
+}
+
+/// Annotate the VCF at `vcf_path` with detectability against `bam_path`, writing
+/// the annotated VCF to `writer` batch by batch. The VCF is read once: the
+/// scoring runs a batch of records ahead of the annotation, and the lines in
+/// between are buffered until annotated. Duplicate results are only resolved
+/// within a batch, and duplicate records only found next to each other; variants
+/// need not follow the BAM contig order. A run cancelled by SIGINT/SIGTERM (once
+/// `interrupt::install_signal_handlers` was called) leaves the records after the
+/// interruption unannotated.
+pub fn stream_annotate_vcf<P: AsRef<Path>, W: Write>(
+    vcf_path: P,
+    bam_path: &Path,
+    writer: W,
+    config: &StreamingConfig,
+) -> VlodResult<StreamingOutput> {
+    validate_lod_config(&config.lod)?;
+    if config.batch_size == 0 {
+        return Err(VlodError::InvalidConfig("streaming batch size must be at least 1".to_string()));
+    }
+    let source = vcf_path.as_ref().to_string_lossy().to_string();
+    let lines = Rc::new(RefCell::new(SharedLines::new(open_text_input(&vcf_path, config.merge.max_line_length)?)));
+
+    let mut lookup = ScoringLookup::new(Rc::clone(&lines), &source, bam_path, config);
+    let reader = AnnotationReader { lines, line: Vec::new(), consumed: 0 };
+    let options = MergeOptions { score_threshold: config.lod.score_threshold, ..config.merge.clone() };
+    let stats = merge_lookup_into_writer(reader, writer, &mut lookup, &options)?;
+    lookup.errors.log_summary();
+    Ok(StreamingOutput {
+        stats,
+        warnings: lookup.warnings,
+        batches: lookup.batches,
+        scored: lookup.scored,
+        detectable: lookup.detectable,
+    })
+}
+
+/// Side of a streaming run reading the VCF lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Scoring,
+    Annotation,
+}
+
+/// VCF lines read once for both sides of a streaming run: lines one side read
+/// that the other has yet to are buffered (at most a batch of records, or the
+/// header)
+struct SharedLines<R: BufRead> {
+    lines: Lines<R>,
+    buffered: VecDeque<String>,
+    /// Side that read the buffered lines
+    leader: Side,
+}
+
+impl<R: BufRead> SharedLines<R> {
+    fn new(reader: R) -> Self {
+        SharedLines { lines: reader.lines(), buffered: VecDeque::new(), leader: Side::Scoring }
+    }
+
+    fn next_line(&mut self, side: Side) -> Option<io::Result<String>> {
+        if self.leader != side {
+            if let Some(line) = self.buffered.pop_front() {
+                return Some(Ok(line));
+            }
+            self.leader = side;
+        }
+        let line = self.lines.next()?;
+        if let Ok(line) = &line {
+            self.buffered.push_back(line.clone());
+        }
+        Some(line)
+    }
+}
+
+/// The annotation's reader of the shared VCF lines
+struct AnnotationReader<R: BufRead> {
+    lines: Rc<RefCell<SharedLines<R>>>,
+    /// Current line with its newline, and the bytes of it already read
+    line: Vec<u8>,
+    consumed: usize,
+}
+
+impl<R: BufRead> Read for AnnotationReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for AnnotationReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            if let Some(line) = self.lines.borrow_mut().next_line(Side::Annotation) {
+                self.line.extend_from_slice(line?.as_bytes());
+                self.line.push(b'\n');
+            }
+        }
+        Ok(&self.line[self.consumed..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed = (self.consumed + amt).min(self.line.len());
+    }
+}
+
+/// Results scored a batch at a time from lines read ahead of the annotation
+struct ScoringLookup<'a, R: BufRead> {
+    lines: Rc<RefCell<SharedLines<R>>>,
+    source: String,
+    line_number: u64,
+    column_indices: Option<VcfColumnIndices>,
+    caller: Caller,
+    bam_path: &'a Path,
+    config: &'a StreamingConfig,
+    /// Results of the current batch, and the keys of all its records
+    table: Option<ResultsTable>,
+    keys: HashSet<(String, u64, String, String)>,
+    exhausted: bool,
+    errors: ParseErrorBudget,
+    warnings: Warnings,
+    duplicates: usize,
+    batches: usize,
+    scored: usize,
+    detectable: usize,
+}
+
+impl<'a, R: BufRead> ScoringLookup<'a, R> {
+    fn new(lines: Rc<RefCell<SharedLines<R>>>, source: &str, bam_path: &'a Path, config: &'a StreamingConfig) -> Self {
+        ScoringLookup {
+            lines,
+            source: source.to_string(),
+            line_number: 0,
+            column_indices: None,
+            caller: config.merge.caller,
+            bam_path,
+            config,
+            table: None,
+            keys: HashSet::new(),
+            exhausted: false,
+            errors: ParseErrorBudget::new(source, config.merge.max_errors),
+            warnings: Warnings::new(),
+            duplicates: 0,
+            batches: 0,
+            scored: 0,
+            detectable: 0,
+        }
+    }
+
+    /// Read and score the next batch of records in place of the current one;
+    /// false once the VCF is exhausted (or the run interrupted)
+    fn next_batch(&mut self) -> VlodResult<bool> {
+        if self.exhausted || interrupt::is_interrupted() {
+            return Ok(false);
+        }
+        self.keys.clear();
+        let mut variants = Vec::new();
+        let mut overrides = VariantOverrideMap::new();
+        let mut records = 0;
+        while records < self.config.batch_size {
+            let Some(line) = self.lines.borrow_mut().next_line(Side::Scoring) else {
+                self.exhausted = true;
+                break;
+            };
+            self.line_number += 1;
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with("##") {
+                self.caller = self.caller.resolve(line);
+                continue;
+            }
+            if line.starts_with('#') {
+                self.column_indices = Some(
+                    VcfColumnIndices::from_header(line).map_err(|e| e.at_line(&self.source, self.line_number))?,
+                );
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            // Every record the annotation may look up belongs to the batch, scored or not
+            records += 1;
+            self.warnings.records += 1;
+            let columns: Vec<&str> = line.split('\t').collect();
+            if let Some(pos) = columns.get(1).and_then(|pos| pos.parse::<u64>().ok()).filter(|_| columns.len() >= 8) {
+                let alt_allele = self.caller.record_alt(columns[4]);
+                self.keys.insert((columns[0].to_string(), pos, columns[3].to_string(), alt_allele));
+            }
+
+            let record = match &self.column_indices {
+                Some(indices) => VcfRecord::from_line_with_indices(line, indices),
+                None => VcfRecord::from_line(line),
+            };
+            match record.and_then(|record| Ok((VariantOverrides::from_info(&record.info)?, record))) {
+                Ok((record_overrides, record)) => {
+                    let pass = self.caller.is_pass(&record.filter);
+                    for variant in self.caller.record_variants(&record.variant) {
+                        if let Some(record_overrides) = record_overrides {
+                            overrides.insert(variant.clone(), record_overrides);
+                        }
+                        variants.push((variant, pass));
+                    }
+                }
+                Err(e) => {
+                    let e = e.at_line(&self.source, self.line_number);
+                    self.warnings.record(WarningKind::InvalidRecord, e.to_string());
+                    self.errors.record(e)?
+                }
+            }
+        }
+        if records == 0 {
+            self.table = None;
+            return Ok(false);
+        }
+
+        let (variants, non_pass) = select_pass_variants(variants, self.config.pass_only);
+        if self.config.pass_only {
+            self.warnings.add(WarningKind::NonPassSkipped, non_pass.len());
+        }
+        let read = variants.len();
+        let variants = apply_monomorphic_policy(variants, self.config.monomorphic_policy);
+        self.warnings.add(WarningKind::MonomorphicSkipped, read - variants.len());
+        let (variants, duplicates) = dedup_variants(variants, self.config.merge.duplicate_policy)?;
+        self.warnings.add(WarningKind::DuplicateVariant, duplicates);
+
+        let mut lod = self.config.lod.clone();
+        if !overrides.is_empty() {
+            lod.variant_overrides = Some(Arc::new(overrides));
+        }
+        let results = calculate_detectability_scores_until(
+            variants,
+            self.bam_path,
+            &lod,
+            self.config.num_processes,
+            interrupt::flag(),
+        )?;
+        self.warnings.record_not_assessable(&results);
+        self.batches += 1;
+        self.scored += results.len();
+        self.detectable += results.iter().filter(|result| result.detectability_condition.is_detectable()).count();
+        log::info!("Scored batch {} ({} records, {} results so far)", self.batches, records, self.scored);
+
+        let table = results_table(&results, self.config.merge.duplicate_policy)?;
+        self.duplicates += table.duplicates();
+        self.table = Some(table);
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> ResultsLookup for ScoringLookup<'_, R> {
+    fn lookup(&mut self, key: &(String, u64, String, String)) -> VlodResult<Option<(MergedFields, bool)>> {
+        // The annotation reads the same records in the same order, so a record
+        // outside the current batch is in a later one
+        while !self.keys.contains(key) {
+            if !self.next_batch()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.table.as_ref().and_then(|table| table.fields(key)).map(|fields| (fields, false)))
+    }
+
+    fn has_probabilities(&self) -> bool {
+        self.config.lod.calibration.is_some() && !self.config.lod.coverage_only
+    }
+
+    fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    fn result_count(&self) -> Option<usize> {
+        None
+    }
+
+    fn streams(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_annotate_vcf() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let config = StreamingConfig { batch_size: 1, num_processes: 1, ..StreamingConfig::default() };
+
+        let mut streamed = Vec::new();
+        let output = stream_annotate_vcf(&data.vcf, &data.bam, &mut streamed, &config).unwrap();
+        assert_eq!(output.batches, data.expected.len());
+        assert_eq!(output.scored, data.expected.len());
+        assert_eq!(output.stats.annotated, data.expected.len());
+
+        // The same annotations as scoring every variant first
+        let batched = StreamingConfig { batch_size: DEFAULT_STREAM_BATCH_SIZE, ..config.clone() };
+        let mut whole = Vec::new();
+        let output = stream_annotate_vcf(&data.vcf, &data.bam, &mut whole, &batched).unwrap();
+        assert_eq!(output.batches, 1);
+        assert_eq!(streamed, whole);
+
+        // A record repeated next to itself is annotated twice, as with scoring every variant first
+        let vcf = std::fs::read_to_string(&data.vcf).unwrap();
+        let record = vcf.lines().find(|line| !line.starts_with('#')).unwrap();
+        let repeated = dir.path().join("repeated.vcf");
+        std::fs::write(&repeated, vcf.replacen(record, &format!("{}\n{}", record, record), 1)).unwrap();
+        let mut annotated = Vec::new();
+        let output = stream_annotate_vcf(&repeated, &data.bam, &mut annotated, &config).unwrap();
+        assert_eq!(output.stats.annotated, data.expected.len() + 1);
+        let mut strict = config.clone();
+        strict.merge.duplicate_policy = crate::vcf::DuplicatePolicy::Error;
+        assert!(stream_annotate_vcf(&repeated, &data.bam, Vec::new(), &strict).is_err());
+
+        let invalid = StreamingConfig { batch_size: 0, ..StreamingConfig::default() };
+        let result = stream_annotate_vcf(&data.vcf, &data.bam, Vec::new(), &invalid);
+        assert!(matches!(result, Err(VlodError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_shared_lines() {
+        let lines = Rc::new(RefCell::new(SharedLines::new("a\nb\nc\n".as_bytes())));
+        let mut reader = AnnotationReader { lines: Rc::clone(&lines), line: Vec::new(), consumed: 0 };
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "a\n");
+
+        // Each side reads every line once, in order, whichever runs ahead
+        let mut shared = lines.borrow_mut();
+        let scored: Vec<String> = std::iter::from_fn(|| shared.next_line(Side::Scoring)).map(Result::unwrap).collect();
+        assert_eq!(scored, ["a", "b", "c"]);
+        assert_eq!(shared.buffered.len(), 2);
+        drop(shared);
+        let rest: Vec<String> = reader.lines().map(Result::unwrap).collect();
+        assert_eq!(rest, ["b", "c"]);
+        assert!(lines.borrow().buffered.is_empty());
+    }
+}
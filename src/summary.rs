@@ -0,0 +1,263 @@
+//! JSON summary of a run: provenance, result counts and the warnings raised, for
+//! pipelines that alert on a run without parsing its log; and the per-sample
+//! metrics picked up by MultiQC
+
+use crate::{
+    about::run_about, annotation::SignificanceSummary, claims::ClaimVerdict, contamination::ContaminationScreen,
+    uniformity::CoverageUniformity,
+    utils::{create_output_file, StageUsage}, verify::SpotCheck, warnings::Warnings, About, DetectabilityCondition,
+    DetectabilityResult, LodConfig, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Summary of a run, written by `--summary-json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub about: About,
+    /// Aligner preset applied, if any
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Sample name and where it came from (`option`, `bam-rg`, `vcf` or `file-name`)
+    #[serde(default)]
+    pub sample: Option<String>,
+    #[serde(default)]
+    pub sample_source: Option<String>,
+    /// The run was interrupted and the results are partial
+    pub partial: bool,
+    /// Results scored
+    pub results: usize,
+    /// Results per VCF `DET` status (`Yes`, `No`, `NoCoverage`, ...)
+    pub conditions: BTreeMap<String, usize>,
+    pub warnings: Warnings,
+    /// Spot-check verification against the read-iteration engine, if run
+    #[serde(default)]
+    pub spot_check: Option<SpotCheck>,
+    /// Results by ClinVar clinical significance, with `--clinvar`
+    #[serde(default)]
+    pub clinical_significance: Option<SignificanceSummary>,
+    /// Coverage uniformity over the target regions, with `--target-bed`
+    #[serde(default)]
+    pub coverage_uniformity: Option<CoverageUniformity>,
+    /// Verdict of each assay claim, with `--claims`
+    #[serde(default)]
+    pub claims: Option<Vec<ClaimVerdict>>,
+    /// Contamination screen at common SNP sites, with `--contamination-sites`
+    #[serde(default)]
+    pub contamination: Option<ContaminationScreen>,
+    /// Wall time, CPU time and peak resident memory of each stage of the run
+    #[serde(default)]
+    pub resources: Vec<StageUsage>,
+}
+
+impl RunSummary {
+    pub fn new(results: &[DetectabilityResult], warnings: Warnings, partial: bool, config: &LodConfig) -> Self {
+        let mut conditions = BTreeMap::new();
+        for result in results {
+            *conditions
+                .entry(result.detectability_condition.vcf_status().to_string())
+                .or_insert(0) += 1;
+        }
+        RunSummary {
+            about: run_about(config),
+            preset: None,
+            sample: None,
+            sample_source: None,
+            partial,
+            results: results.len(),
+            conditions,
+            warnings,
+            spot_check: None,
+            clinical_significance: None,
+            coverage_uniformity: None,
+            claims: None,
+            contamination: None,
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> VlodResult<()> {
+        let mut writer = BufWriter::new(create_output_file(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| VlodError::InvalidConfig(format!("cannot write run summary: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Per-sample metrics for the MultiQC general statistics table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultiqcMetrics {
+    /// Percentage of variants Detectable
+    pub pct_detectable: f64,
+    /// Median detectability score over variants with a finite score
+    pub median_score: Option<f64>,
+    pub median_coverage: Option<f64>,
+    pub mean_coverage: Option<f64>,
+    /// Percentage of variants without coverage
+    pub pct_no_coverage: f64,
+    pub variants: usize,
+}
+
+impl MultiqcMetrics {
+    pub fn new(results: &[DetectabilityResult]) -> Self {
+        let percent = |condition: DetectabilityCondition| {
+            let count = results.iter().filter(|result| result.detectability_condition == condition).count();
+            if results.is_empty() {
+                0.0
+            } else {
+                100.0 * count as f64 / results.len() as f64
+            }
+        };
+        let coverage: Vec<f64> = results.iter().map(|result| result.coverage as f64).collect();
+        let scores: Vec<f64> =
+            results.iter().map(|result| result.detectability_score).filter(|score| score.is_finite()).collect();
+        MultiqcMetrics {
+            pct_detectable: percent(DetectabilityCondition::Detectable),
+            median_score: median(scores),
+            median_coverage: median(coverage.clone()),
+            mean_coverage: (!coverage.is_empty()).then(|| coverage.iter().sum::<f64>() / coverage.len() as f64),
+            pct_no_coverage: percent(DetectabilityCondition::NoCoverage),
+            variants: results.len(),
+        }
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len() % 2 == 0 { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+/// MultiQC custom-content file for a sample: `<sample>_vlod_mqc.json` in `dir`
+pub fn multiqc_path<P: AsRef<Path>>(dir: P, sample: &str) -> PathBuf {
+    dir.as_ref().join(format!("{}_vlod_mqc.json", sample))
+}
+
+/// Write the metrics of one sample as MultiQC custom content, shown as vLoD
+/// columns of the general statistics table when MultiQC is run over `dir`
+/// (created if missing); returns the file written
+pub fn write_multiqc<P: AsRef<Path>>(results: &[DetectabilityResult], sample: &str, dir: P) -> VlodResult<PathBuf> {
+    std::fs::create_dir_all(dir.as_ref())?;
+    let path = multiqc_path(dir, sample);
+    let content = serde_json::json!({
+        "id": "vlod",
+        "section_name": "vLoD",
+        "description": "Detectability of the variants of interest, from vLoD",
+        "plot_type": "generalstats",
+        "headers": {
+            "pct_detectable": {
+                "title": "% Detectable",
+                "description": "Percentage of variants Detectable",
+                "min": 0, "max": 100, "suffix": "%", "scale": "RdYlGn",
+            },
+            "median_score": {
+                "title": "Median score",
+                "description": "Median detectability score",
+                "scale": "Blues",
+            },
+            "median_coverage": {
+                "title": "Median depth",
+                "description": "Median read depth at the variant positions",
+                "suffix": "X", "scale": "Greens",
+            },
+            "mean_coverage": {
+                "title": "Mean depth",
+                "description": "Mean read depth at the variant positions",
+                "suffix": "X", "scale": "Greens", "hidden": true,
+            },
+            "pct_no_coverage": {
+                "title": "% No coverage",
+                "description": "Percentage of variants without coverage",
+                "min": 0, "max": 100, "suffix": "%", "scale": "OrRd",
+            },
+            "variants": {
+                "title": "Variants",
+                "description": "Variants assessed",
+                "format": "{:,.0f}", "hidden": true,
+            },
+        },
+        "data": { sample: MultiqcMetrics::new(results) },
+    });
+    let mut writer = BufWriter::new(create_output_file(&path)?);
+    serde_json::to_writer_pretty(&mut writer, &content)
+        .map_err(|e| VlodError::InvalidConfig(format!("cannot write MultiQC metrics: {}", e)))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{warnings::WarningKind, DetectabilityCondition, Variant};
+
+    #[test]
+    fn test_write_run_summary() {
+        let result = |pos: u64, condition: DetectabilityCondition| {
+            DetectabilityResult::new(
+                Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()),
+                3.0,
+                condition,
+                100,
+                5,
+            )
+        };
+        let results = vec![
+            result(100, DetectabilityCondition::Detectable),
+            result(200, DetectabilityCondition::Detectable),
+            result(300, DetectabilityCondition::NotAssessable("alt contig".to_string())),
+        ];
+        let mut warnings = Warnings::new();
+        warnings.records = 10;
+        warnings.record(WarningKind::InvalidRecord, "line 4");
+        warnings.record_not_assessable(&results);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("summary.json");
+        let mut summary = RunSummary::new(&results, warnings, false, &LodConfig::default());
+        summary.preset = Some("bwa".to_string());
+        summary.write(&path).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["results"], 3);
+        assert_eq!(json["preset"], "bwa");
+        assert_eq!(json["conditions"]["Yes"], 2);
+        assert_eq!(json["conditions"]["NotAssessable"], 1);
+        assert_eq!(json["warnings"]["records"], 10);
+        assert_eq!(json["warnings"]["warnings"][1]["kind"], "not_assessable");
+        assert_eq!(json["warnings"]["warnings"][1]["examples"][0], "chr1:300 A>G: alt contig");
+        assert_eq!(json["about"]["vlod_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_write_multiqc() {
+        let result = |coverage: u32, score: f64, condition: DetectabilityCondition| {
+            let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string());
+            DetectabilityResult::new(variant, score, condition, coverage, 0)
+        };
+        let results = vec![
+            result(100, 4.0, DetectabilityCondition::Detectable),
+            result(60, 2.0, DetectabilityCondition::Detectable),
+            result(20, 1.0, DetectabilityCondition::NonDetectable),
+            result(0, f64::NAN, DetectabilityCondition::NoCoverage),
+        ];
+        let metrics = MultiqcMetrics::new(&results);
+        assert_eq!(metrics.pct_detectable, 50.0);
+        assert_eq!(metrics.median_score, Some(2.0));
+        assert_eq!(metrics.median_coverage, Some(40.0));
+        assert_eq!(metrics.mean_coverage, Some(45.0));
+        assert_eq!(metrics.pct_no_coverage, 25.0);
+        assert_eq!(MultiqcMetrics::new(&[]).median_score, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_multiqc(&results, "S1", dir.path().join("multiqc")).unwrap();
+        assert_eq!(path, dir.path().join("multiqc").join("S1_vlod_mqc.json"));
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["plot_type"], "generalstats");
+        assert_eq!(json["data"]["S1"]["pct_detectable"], 50.0);
+        assert_eq!(json["data"]["S1"]["variants"], 4);
+    }
+}
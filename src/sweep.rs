@@ -0,0 +1,249 @@
+//! Threshold sweep: how classifications and, given truth labels, sensitivity and
+//! specificity change across candidate detection thresholds
+
+use crate::{
+    about::about_comment,
+    utils::create_output_file, DetectabilityCondition, DetectabilityResult, Variant, VlodError, VlodResult,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Evenly spaced candidate thresholds, `START:STOP:STEP` on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdSweep {
+    pub start: f64,
+    pub stop: f64,
+    pub step: f64,
+}
+
+impl ThresholdSweep {
+    pub fn validate(&self) -> VlodResult<()> {
+        if !(self.start.is_finite() && self.stop.is_finite() && self.start <= self.stop) {
+            return Err(VlodError::InvalidConfig(
+                "threshold sweep start must not exceed its stop".to_string(),
+            ));
+        }
+        if !(self.step > 0.0 && self.step.is_finite()) {
+            return Err(VlodError::InvalidConfig(
+                "threshold sweep step must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Candidate thresholds from start to stop inclusive, rounded to 1e-6 so that
+    /// steps like 0.1 do not accumulate floating-point drift
+    pub fn thresholds(&self) -> Vec<f64> {
+        let steps = ((self.stop - self.start) / self.step + 1e-9).floor() as usize;
+        (0..=steps)
+            .map(|i| ((self.start + i as f64 * self.step) * 1e6).round() / 1e6)
+            .collect()
+    }
+}
+
+impl FromStr for ThresholdSweep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid threshold sweep '{}' (expected START:STOP:STEP)", s);
+        let fields: Vec<f64> = s
+            .split(':')
+            .map(|field| field.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [start, stop, step] = fields[..] else {
+            return Err(invalid());
+        };
+        let sweep = ThresholdSweep { start, stop, step };
+        sweep.validate().map_err(|e| e.to_string())?;
+        Ok(sweep)
+    }
+}
+
+impl fmt::Display for ThresholdSweep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.start, self.stop, self.step)
+    }
+}
+
+/// Classification of the scored variants at one candidate threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub threshold: f64,
+    pub detectable: usize,
+    pub non_detectable: usize,
+    /// Variants reported non-detectable that this threshold would call detectable
+    pub flipped_to_detectable: usize,
+    /// Variants reported detectable that this threshold would call non-detectable
+    pub flipped_to_non_detectable: usize,
+    /// Against the truth labels, when given and both classes are present
+    pub sensitivity: Option<f64>,
+    pub specificity: Option<f64>,
+}
+
+/// Reclassify the scored results at every threshold of the sweep. Only
+/// Detectable/NonDetectable results take part; uncovered, monomorphic and failed
+/// sites have no score to threshold.
+pub fn sweep_thresholds(
+    results: &[DetectabilityResult],
+    sweep: &ThresholdSweep,
+    truth: Option<&HashMap<Variant, bool>>,
+) -> Vec<SweepPoint> {
+    let scored: Vec<(&DetectabilityResult, Option<bool>)> = results
+        .iter()
+        .filter(|result| {
+            matches!(
+                result.detectability_condition,
+                DetectabilityCondition::Detectable | DetectabilityCondition::NonDetectable
+            )
+        })
+        .map(|result| (result, truth.and_then(|truth| truth.get(&result.variant).copied())))
+        .collect();
+    let positives = scored.iter().filter(|(_, label)| *label == Some(true)).count();
+    let negatives = scored.iter().filter(|(_, label)| *label == Some(false)).count();
+
+    sweep
+        .thresholds()
+        .into_iter()
+        .map(|threshold| {
+            let mut point = SweepPoint {
+                threshold,
+                detectable: 0,
+                non_detectable: 0,
+                flipped_to_detectable: 0,
+                flipped_to_non_detectable: 0,
+                sensitivity: None,
+                specificity: None,
+            };
+            let (mut true_positives, mut true_negatives) = (0, 0);
+            for (result, label) in &scored {
+                let detectable = result.detectability_score >= threshold;
+                let reported = result.detectability_condition.is_detectable();
+                if detectable {
+                    point.detectable += 1;
+                } else {
+                    point.non_detectable += 1;
+                }
+                match (reported, detectable) {
+                    (false, true) => point.flipped_to_detectable += 1,
+                    (true, false) => point.flipped_to_non_detectable += 1,
+                    _ => {}
+                }
+                match (label, detectable) {
+                    (Some(true), true) => true_positives += 1,
+                    (Some(false), false) => true_negatives += 1,
+                    _ => {}
+                }
+            }
+            point.sensitivity = (positives > 0).then(|| true_positives as f64 / positives as f64);
+            point.specificity = (negatives > 0).then(|| true_negatives as f64 / negatives as f64);
+            point
+        })
+        .collect()
+}
+
+/// Write the sweep as CSV
+pub fn write_sweep_results<P: AsRef<Path>>(points: &[SweepPoint], path: P) -> VlodResult<()> {
+    let writer = BufWriter::new(create_output_file(path)?);
+    write_sweep_results_to_writer(points, writer)
+}
+
+/// Write the sweep to any writer; sensitivity and specificity are empty without
+/// truth labels
+pub fn write_sweep_results_to_writer<W: Write>(points: &[SweepPoint], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "threshold,detectable,non_detectable,flipped_to_detectable,flipped_to_non_detectable,sensitivity,specificity"
+    )?;
+
+    let format = |value: Option<f64>| value.map(|v| format!("{:.4}", v)).unwrap_or_default();
+    for point in points {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            point.threshold,
+            point.detectable,
+            point.non_detectable,
+            point.flipped_to_detectable,
+            point.flipped_to_non_detectable,
+            format(point.sensitivity),
+            format(point.specificity),
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(pos: u64, score: f64) -> DetectabilityResult {
+        DetectabilityResult::new(
+            Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()),
+            score,
+            DetectabilityResult::condition_from_score(score, &crate::LodConfig::default()),
+            100,
+            10,
+        )
+    }
+
+    #[test]
+    fn test_parse_threshold_sweep() {
+        let sweep: ThresholdSweep = "1.0:4.0:0.1".parse().unwrap();
+        let thresholds = sweep.thresholds();
+        assert_eq!(thresholds.len(), 31);
+        assert_eq!(thresholds[1], 1.1);
+        assert_eq!(thresholds[30], 4.0);
+        assert_eq!(sweep.to_string(), "1:4:0.1");
+
+        assert!("1.0:4.0".parse::<ThresholdSweep>().is_err());
+        assert!("4.0:1.0:0.1".parse::<ThresholdSweep>().is_err());
+        assert!("1.0:4.0:0".parse::<ThresholdSweep>().is_err());
+    }
+
+    #[test]
+    fn test_sweep_thresholds() {
+        let results = vec![
+            result(100, 1.5),
+            result(200, 2.0),
+            result(300, 3.0),
+            DetectabilityResult::new(
+                Variant::new("chr1".to_string(), 400, "A".to_string(), "G".to_string()),
+                0.0,
+                DetectabilityCondition::NoCoverage,
+                0,
+                0,
+            ),
+        ];
+        let sweep = ThresholdSweep { start: 1.0, stop: 3.0, step: 1.0 };
+
+        let points = sweep_thresholds(&results, &sweep, None);
+        assert_eq!(points.len(), 3);
+        // Uncovered sites are not reclassified
+        assert_eq!((points[0].detectable, points[0].non_detectable), (3, 0));
+        assert_eq!((points[0].flipped_to_detectable, points[0].flipped_to_non_detectable), (2, 0));
+        assert_eq!((points[1].detectable, points[1].flipped_to_detectable), (2, 1));
+        assert_eq!(points[2].flipped_to_non_detectable, 0);
+        assert_eq!(points[0].sensitivity, None);
+
+        let truth: HashMap<Variant, bool> = [(100, false), (200, true), (300, true)]
+            .into_iter()
+            .map(|(pos, label)| (Variant::new("chr1".to_string(), pos, "A".to_string(), "G".to_string()), label))
+            .collect();
+        let points = sweep_thresholds(&results, &sweep, Some(&truth));
+        assert_eq!((points[0].sensitivity, points[0].specificity), (Some(1.0), Some(0.0)));
+        assert_eq!((points[1].sensitivity, points[1].specificity), (Some(1.0), Some(1.0)));
+        assert_eq!((points[2].sensitivity, points[2].specificity), (Some(0.5), Some(1.0)));
+
+        let mut csv = Vec::new();
+        write_sweep_results_to_writer(&points[1..2], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(2), Some("2,2,1,1,0,1.0000,1.0000"));
+    }
+}
@@ -0,0 +1,396 @@
+//! Synthetic smoke-test data: a tiny reference, an indexed BAM and a VCF whose
+//! detectability outcomes are known in advance
+
+use crate::{
+    noise::{base_index, splitmix64},
+    DetectabilityCondition, Variant, VlodResult,
+};
+use rust_htslib::bam::{
+    self,
+    header::HeaderRecord,
+    record::{Cigar, CigarString, Record},
+    Header,
+};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the single synthetic contig
+pub const TEST_DATA_CONTIG: &str = "chr1";
+
+/// Base name of the generated files
+pub const TEST_DATA_PREFIX: &str = "vlod_test";
+
+const CONTIG_LENGTH: usize = 2000;
+const FASTA_LINE_WIDTH: usize = 60;
+const READ_LENGTH: usize = 50;
+const SITE_DEPTH: usize = 100;
+const BASE_QUALITY: u8 = 30;
+const MAPPING_QUALITY: u8 = 60;
+const SEQUENCE_SEED: u64 = 0x746573;
+
+/// A synthetic SNV site and the outcome vLoD must report for it
+struct TestSite {
+    /// 1-based position
+    pos: u64,
+    /// Reads carrying the ALT base, out of `SITE_DEPTH` (None: no reads at all)
+    alt_reads: Option<usize>,
+    expected: DetectabilityCondition,
+}
+
+fn test_sites() -> Vec<TestSite> {
+    vec![
+        // VAF 0.2 scores ~2.85 with the default priors
+        TestSite {
+            pos: 201,
+            alt_reads: Some(20),
+            expected: DetectabilityCondition::Detectable,
+        },
+        // VAF 0.01 scores ~1.96
+        TestSite {
+            pos: 801,
+            alt_reads: Some(1),
+            expected: DetectabilityCondition::NonDetectable,
+        },
+        TestSite {
+            pos: 1501,
+            alt_reads: None,
+            expected: DetectabilityCondition::NoCoverage,
+        },
+    ]
+}
+
+/// Paths of the generated files and the expected result of every VCF variant
+#[derive(Debug, Clone)]
+pub struct TestData {
+    pub reference: PathBuf,
+    pub bam: PathBuf,
+    pub vcf: PathBuf,
+    pub expected: Vec<(Variant, DetectabilityCondition)>,
+}
+
+impl TestData {
+    /// Paths `write_test_data` writes into a directory (including the indexes)
+    pub fn paths(dir: &Path) -> Vec<PathBuf> {
+        ["fa", "fa.fai", "bam", "bam.bai", "vcf"]
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", TEST_DATA_PREFIX, extension)))
+            .collect()
+    }
+}
+
+/// Write the synthetic reference (with .fai), coordinate-sorted BAM (with .bai)
+/// and VCF into `dir`. The VCF carries the expected DET status of each variant in
+/// an EXPECTED INFO field.
+pub fn write_test_data(dir: &Path) -> VlodResult<TestData> {
+    std::fs::create_dir_all(dir)?;
+    let reference = dir.join(format!("{}.fa", TEST_DATA_PREFIX));
+    let bam = dir.join(format!("{}.bam", TEST_DATA_PREFIX));
+    let vcf = dir.join(format!("{}.vcf", TEST_DATA_PREFIX));
+
+    let sequence = reference_sequence();
+    let sites = test_sites();
+    write_reference(&reference, &sequence)?;
+    write_bam(&bam, &sequence, &sites)?;
+    let expected = write_vcf(&vcf, &sequence, &sites)?;
+
+    Ok(TestData {
+        reference,
+        bam,
+        vcf,
+        expected,
+    })
+}
+
+/// Deterministic pseudo-random contig sequence
+fn reference_sequence() -> Vec<u8> {
+    let mut state = SEQUENCE_SEED;
+    (0..CONTIG_LENGTH)
+        .map(|_| b"ACGT"[(splitmix64(&mut state) % 4) as usize])
+        .collect()
+}
+
+/// A base other than the reference base at a 1-based position
+fn alt_base(sequence: &[u8], pos: u64) -> u8 {
+    let ref_base = sequence[pos as usize - 1];
+    b"ACGT"[(base_index(ref_base).unwrap_or(0) + 1) % 4]
+}
+
+fn write_reference(path: &Path, sequence: &[u8]) -> VlodResult<()> {
+    let mut fasta = BufWriter::new(File::create(path)?);
+    let header = format!(">{}\n", TEST_DATA_CONTIG);
+    fasta.write_all(header.as_bytes())?;
+    for line in sequence.chunks(FASTA_LINE_WIDTH) {
+        fasta.write_all(line)?;
+        fasta.write_all(b"\n")?;
+    }
+    fasta.flush()?;
+
+    // name, length, offset of the first base, bases per line, bytes per line
+    let mut fai = File::create(path.with_extension("fa.fai"))?;
+    writeln!(
+        fai,
+        "{}\t{}\t{}\t{}\t{}",
+        TEST_DATA_CONTIG,
+        sequence.len(),
+        header.len(),
+        FASTA_LINE_WIDTH,
+        FASTA_LINE_WIDTH + 1
+    )?;
+    Ok(())
+}
+
+/// Write `SITE_DEPTH` single-end reads centred on each covered site, the first
+/// `alt_reads` of them carrying the ALT base, and index the BAM
+fn write_bam(path: &Path, sequence: &[u8], sites: &[TestSite]) -> VlodResult<()> {
+    let mut header = Header::new();
+    let mut hd = HeaderRecord::new(b"HD");
+    hd.push_tag(b"VN", "1.6");
+    hd.push_tag(b"SO", "coordinate");
+    header.push_record(&hd);
+    let mut sq = HeaderRecord::new(b"SQ");
+    sq.push_tag(b"SN", TEST_DATA_CONTIG);
+    sq.push_tag(b"LN", sequence.len());
+    header.push_record(&sq);
+
+    let mut writer = bam::Writer::from_path(path, &header, bam::Format::Bam)?;
+    let cigar = CigarString(vec![Cigar::Match(READ_LENGTH as u32)]);
+    let qualities = vec![BASE_QUALITY; READ_LENGTH];
+
+    for site in sites {
+        let Some(alt_reads) = site.alt_reads else {
+            continue;
+        };
+        let site_offset = site.pos as usize - 1;
+        let start = site_offset - READ_LENGTH / 2;
+
+        for read in 0..SITE_DEPTH {
+            let mut bases = sequence[start..start + READ_LENGTH].to_vec();
+            if read < alt_reads {
+                bases[site_offset - start] = alt_base(sequence, site.pos);
+            }
+
+            let mut record = Record::new();
+            let qname = format!("site{}_read{}", site.pos, read);
+            record.set(qname.as_bytes(), Some(&cigar), &bases, &qualities);
+            record.set_tid(0);
+            record.set_pos(start as i64);
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            record.set_mapq(MAPPING_QUALITY);
+            record.set_flags(0);
+            writer.write(&record)?;
+        }
+    }
+    // Close the BAM before indexing it
+    drop(writer);
+
+    bam::index::build(path, None, bam::index::Type::Bai, 1)?;
+    Ok(())
+}
+
+fn write_vcf(
+    path: &Path,
+    sequence: &[u8],
+    sites: &[TestSite],
+) -> VlodResult<Vec<(Variant, DetectabilityCondition)>> {
+    let mut vcf = BufWriter::new(File::create(path)?);
+    writeln!(vcf, "##fileformat=VCFv4.2")?;
+    writeln!(vcf, "##contig=<ID={},length={}>", TEST_DATA_CONTIG, sequence.len())?;
+    writeln!(
+        vcf,
+        "##INFO=<ID=EXPECTED,Number=1,Type=String,Description=\"DET status vLoD is expected to report\">"
+    )?;
+    writeln!(vcf, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+
+    let mut expected = Vec::with_capacity(sites.len());
+    for site in sites {
+        let variant = Variant::new(
+            TEST_DATA_CONTIG.to_string(),
+            site.pos,
+            (sequence[site.pos as usize - 1] as char).to_string(),
+            (alt_base(sequence, site.pos) as char).to_string(),
+        );
+        writeln!(
+            vcf,
+            "{}\t{}\t.\t{}\t{}\t.\tPASS\tEXPECTED={}",
+            variant.chrom,
+            variant.pos,
+            variant.ref_allele,
+            variant.alt_allele,
+            site.expected.vcf_status()
+        )?;
+        expected.push((variant, site.expected.clone()));
+    }
+    vcf.flush()?;
+
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lod::{calculate_detectability_scores, calculate_detectability_scores_until},
+        vcf::read_vcf_variants,
+        LodConfig,
+    };
+    use crate::{
+        bam::AlleleCounts,
+        interrupt::CancellationToken,
+        observer::{ChunkProgress, Observer, Stage},
+        pipeline::{run_pipeline, PipelineConfig},
+        DetectabilityResult,
+    };
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_write_test_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        assert!(TestData::paths(dir.path()).iter().all(|path| path.exists()));
+
+        let variants = read_vcf_variants(&data.vcf).unwrap();
+        assert_eq!(variants.len(), data.expected.len());
+
+        let results = calculate_detectability_scores(variants, &data.bam, &LodConfig::default(), 1).unwrap();
+        for (variant, expected) in &data.expected {
+            let result = results.iter().find(|r| &r.variant == variant).unwrap();
+            assert_eq!(&result.detectability_condition, expected, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn test_coverage_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        let variants = read_vcf_variants(&data.vcf).unwrap();
+
+        let config = LodConfig { coverage_only: true, ..LodConfig::default() };
+        let results = calculate_detectability_scores(variants, &data.bam, &config, 1).unwrap();
+        assert_eq!(results.len(), data.expected.len());
+        for result in &results {
+            assert_eq!(result.variant_reads, 0);
+            if result.coverage == 0 {
+                assert_eq!(result.detectability_condition, DetectabilityCondition::NoCoverage);
+                assert_eq!(result.min_detectable_vaf, None);
+            } else {
+                assert_eq!(result.coverage, SITE_DEPTH as u32);
+                assert_eq!(result.detectability_condition, DetectabilityCondition::CoverageOnly);
+                let vaf = result.min_detectable_vaf.unwrap();
+                assert!(vaf > 0.0 && vaf < 0.2, "{}", vaf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scoring_stops_when_requested() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        let variants = read_vcf_variants(&data.vcf).unwrap();
+
+        let stop = AtomicBool::new(true);
+        let results =
+            calculate_detectability_scores_until(variants.clone(), &data.bam, &LodConfig::default(), 1, &stop).unwrap();
+        assert!(results.is_empty());
+
+        let stop = AtomicBool::new(false);
+        let results = calculate_detectability_scores_until(variants, &data.bam, &LodConfig::default(), 1, &stop).unwrap();
+        assert_eq!(results.len(), data.expected.len());
+    }
+
+    /// Records events and cancels after a number of variants
+    #[derive(Default)]
+    struct RecordingObserver {
+        stages: Mutex<Vec<Stage>>,
+        started: AtomicUsize,
+        done: AtomicUsize,
+        results: AtomicUsize,
+        chunks: AtomicUsize,
+        cancel_after: Option<usize>,
+    }
+
+    impl Observer for RecordingObserver {
+        fn on_stage(&self, stage: Stage) {
+            self.stages.lock().unwrap().push(stage);
+        }
+
+        fn on_variant_start(&self, _variant: &Variant) {
+            self.started.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_variant_done(&self, _variant: &Variant, _counts: &AlleleCounts) {
+            self.done.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_result(&self, _result: &DetectabilityResult) {
+            self.results.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_chunk_done(&self, progress: ChunkProgress) {
+            assert!(progress.chunks_done <= progress.chunks_total);
+            self.chunks.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn is_cancelled(&self) -> bool {
+            self.cancel_after.is_some_and(|limit| self.done.load(Ordering::Relaxed) >= limit)
+        }
+    }
+
+    #[test]
+    fn test_pipeline_observer() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        let vcf = std::fs::read(&data.vcf).unwrap();
+
+        let observer = Arc::new(RecordingObserver::default());
+        let config = PipelineConfig {
+            num_processes: 1,
+            observer: Some(observer.clone()),
+            ..PipelineConfig::default()
+        };
+        let output = run_pipeline(vcf.as_slice(), &data.bam, &config).unwrap();
+        assert_eq!(output.results.len(), data.expected.len());
+        assert_eq!(output.warnings.records, data.expected.len());
+        assert!(output.warnings.is_empty());
+        assert_eq!(
+            *observer.stages.lock().unwrap(),
+            [Stage::ReadingVariants, Stage::Scoring, Stage::Merging, Stage::Finished]
+        );
+        assert_eq!(observer.started.load(Ordering::Relaxed), data.expected.len());
+        assert_eq!(observer.done.load(Ordering::Relaxed), data.expected.len());
+        assert_eq!(observer.results.load(Ordering::Relaxed), output.results.len());
+        assert_eq!(observer.chunks.load(Ordering::Relaxed), 1);
+
+        let observer = Arc::new(RecordingObserver {
+            cancel_after: Some(2),
+            ..RecordingObserver::default()
+        });
+        let config = PipelineConfig {
+            num_processes: 1,
+            observer: Some(observer.clone()),
+            ..PipelineConfig::default()
+        };
+        let output = run_pipeline(vcf.as_slice(), &data.bam, &config).unwrap();
+        assert_eq!(output.results.len(), 2);
+        assert_eq!(observer.results.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_pipeline_cancellation_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = write_test_data(dir.path()).unwrap();
+        let vcf = std::fs::read(&data.vcf).unwrap();
+
+        let token = CancellationToken::new();
+        let config = PipelineConfig {
+            cancellation: Some(token.clone()),
+            ..PipelineConfig::default()
+        };
+        token.cancel();
+        let output = run_pipeline(vcf.as_slice(), &data.bam, &config).unwrap();
+        assert!(output.results.is_empty());
+        assert!(!String::from_utf8(output.annotated_vcf).unwrap().contains("DET="));
+    }
+}
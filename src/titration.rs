@@ -0,0 +1,141 @@
+//! Coverage titration: detectability scores at downsampled read fractions
+
+use crate::{about::about_comment, utils::create_output_file, DetectabilityResult, VlodError, VlodResult};
+use serde::{Deserialize, Serialize};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Detectability of a variant when only a fraction of the reads is kept
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TitrationPoint {
+    pub fraction: f64,
+    pub coverage: u32,
+    pub variant_reads: u32,
+    pub detectability_score: f64,
+}
+
+/// Build the list of fractions `step, 2*step, ..., 1.0`
+pub fn titration_fractions(step: f64) -> VlodResult<Vec<f64>> {
+    if !(step > 0.0 && step <= 1.0) {
+        return Err(VlodError::InvalidConfig(
+            "titration step must be between 0 and 1".to_string(),
+        ));
+    }
+
+    let steps = (1.0 / step).round() as usize;
+    let mut fractions: Vec<f64> = (1..=steps)
+        .map(|i| ((i as f64 * step) * 1e6).round() / 1e6)
+        .filter(|&f| f < 1.0)
+        .collect();
+    fractions.push(1.0);
+
+    Ok(fractions)
+}
+
+/// Deterministic uniform draw in [0, 1) for a read name, so that both mates of a
+/// pair are kept or dropped together (FNV-1a hash with a murmur3 finalizer)
+pub fn downsample_draw(qname: &[u8]) -> f64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in qname {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Write per-variant titration curves as tidy CSV (one row per variant and fraction)
+pub fn write_titration_results(results: &[DetectabilityResult], output_path: &Path) -> VlodResult<()> {
+    write_titration_results_to_writer(results, BufWriter::new(create_output_file(output_path)?))
+}
+
+/// Write per-variant titration curves as tidy CSV to `writer`
+pub fn write_titration_results_to_writer<W: Write>(results: &[DetectabilityResult], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "chrom,pos,ref,alt,fraction,coverage,variant_reads,detectability_score"
+    )?;
+
+    for result in results {
+        for point in &result.titration {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                result.variant.chrom,
+                result.variant.pos,
+                result.variant.ref_allele,
+                result.variant.alt_allele,
+                point.fraction,
+                point.coverage,
+                point.variant_reads,
+                point.detectability_score,
+            )?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DetectabilityCondition, Variant};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_titration_fractions() {
+        let fractions = titration_fractions(0.1).unwrap();
+        assert_eq!(fractions.len(), 10);
+        assert_eq!(fractions[0], 0.1);
+        assert_eq!(fractions[2], 0.3);
+        assert_eq!(fractions[9], 1.0);
+
+        assert_eq!(titration_fractions(1.0).unwrap(), vec![1.0]);
+        assert_eq!(titration_fractions(0.4).unwrap(), vec![0.4, 0.8, 1.0]);
+        assert!(titration_fractions(0.0).is_err());
+        assert!(titration_fractions(1.5).is_err());
+    }
+
+    #[test]
+    fn test_downsample_draw() {
+        let draw = downsample_draw(b"read_1");
+        assert!((0.0..1.0).contains(&draw));
+        assert_eq!(draw, downsample_draw(b"read_1"));
+        assert_ne!(draw, downsample_draw(b"read_2"));
+
+        // Draws should be spread roughly uniformly
+        let kept = (0..10_000)
+            .filter(|i| downsample_draw(format!("read_{}", i).as_bytes()) < 0.3)
+            .count();
+        assert!((2_700..3_300).contains(&kept));
+    }
+
+    #[test]
+    fn test_write_titration_results() {
+        let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        let mut result = DetectabilityResult::new(variant, 3.5, DetectabilityCondition::Detectable, 30, 15);
+        result.titration = vec![
+            TitrationPoint { fraction: 0.5, coverage: 14, variant_reads: 7, detectability_score: 3.4 },
+            TitrationPoint { fraction: 1.0, coverage: 30, variant_reads: 15, detectability_score: 3.5 },
+        ];
+
+        let output = NamedTempFile::new().unwrap();
+        write_titration_results(&[result], output.path()).unwrap();
+
+        let content = std::fs::read_to_string(output.path()).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("#about={"));
+        assert_eq!(lines[1], "chrom,pos,ref,alt,fraction,coverage,variant_reads,detectability_score");
+        assert_eq!(lines[2], "chr1,100,A,T,0.5,14,7,3.4");
+        assert_eq!(lines[3], "chr1,100,A,T,1,30,15,3.5");
+    }
+}
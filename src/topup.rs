@@ -0,0 +1,303 @@
+//! Top-up sequencing planning: the per-variant required depth of each assay claim,
+//! aggregated per region into the extra coverage, reads and lanes a run needs to
+//! meet its claims
+
+use crate::{
+    about::about_comment,
+    claims::{Claim, ClaimSet},
+    merge::{is_annotated_vcf, read_detectability_results, read_result_coverage},
+    regions::BedRegion,
+    utils::create_output_file,
+    vcf::DuplicatePolicy,
+    DetectabilityResult, LodConfig, Variant, VlodError, VlodResult,
+};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Sequencing assumptions turning missing depth into reads and lanes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopupOptions {
+    /// Read length, converting depth over a region into on-target reads
+    pub read_length: u32,
+    /// Lanes the current run was sequenced on
+    pub current_lanes: f64,
+}
+
+impl Default for TopupOptions {
+    fn default() -> Self {
+        Self {
+            read_length: 150,
+            current_lanes: 1.0,
+        }
+    }
+}
+
+impl TopupOptions {
+    pub fn validate(&self) -> VlodResult<()> {
+        if self.read_length == 0 {
+            return Err(VlodError::InvalidConfig("the read length must be at least 1".to_string()));
+        }
+        if self.current_lanes.is_nan() || self.current_lanes <= 0.0 {
+            return Err(VlodError::InvalidConfig("the current lane count must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Top-up needed for one region of a claim
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopupRow {
+    pub claim: String,
+    /// The claim's region, or the variant site for a claim without regions
+    pub region: BedRegion,
+    /// Variants in the scope of the claim within the region
+    pub variants: usize,
+    /// Variants short of the depth the claim requires
+    pub short: usize,
+    /// Short variants no top-up rescues: without coverage, or whose claimed VAF
+    /// cannot be detected at any depth
+    pub unreachable: usize,
+    /// Deepest depth required by a short variant that top-up rescues
+    pub required_depth: Option<u32>,
+    /// Largest depth missing at a short variant
+    pub additional_coverage: u32,
+    /// On-target reads adding `additional_coverage` over the whole region
+    pub additional_reads: u64,
+    /// Lanes to add to the run: the current lanes times the largest relative depth
+    /// increase a short variant needs, as top-up raises every region together
+    pub extra_lanes: f64,
+}
+
+/// Read results to plan a top-up for from a results TSV with a Coverage column
+pub fn read_topup_results<P: AsRef<Path>>(path: P) -> VlodResult<Vec<DetectabilityResult>> {
+    let path = path.as_ref();
+    if is_annotated_vcf(path)? {
+        return Err(VlodError::InvalidConfig(format!(
+            "{} is an annotated VCF, which records no coverage; plan a top-up from a results TSV",
+            path.display()
+        )));
+    }
+    let data = read_detectability_results(path)?;
+    let coverage = read_result_coverage(path, DuplicatePolicy::First)?;
+    if !data.is_empty() && coverage.is_empty() {
+        return Err(VlodError::InvalidConfig(format!(
+            "{} has no Coverage column to plan a top-up from",
+            path.display()
+        )));
+    }
+    let mut results: Vec<DetectabilityResult> = data
+        .into_iter()
+        .map(|(key, (condition, score))| {
+            let depth = coverage.get(&key).copied().unwrap_or(0);
+            let (chrom, pos, ref_allele, alt_allele) = key;
+            DetectabilityResult::new(Variant::new(chrom, pos, ref_allele, alt_allele), score, condition, depth, 0)
+        })
+        .collect();
+    results.sort_by(|a, b| {
+        let (a, b) = (&a.variant, &b.variant);
+        (&a.chrom, a.pos, &a.ref_allele, &a.alt_allele).cmp(&(&b.chrom, b.pos, &b.ref_allele, &b.alt_allele))
+    });
+    Ok(results)
+}
+
+/// Plan the top-up of every claim, one row per region holding variants in the
+/// claim's scope, claims in file order and regions in their file order
+pub fn plan_topup(
+    claims: &ClaimSet,
+    results: &[DetectabilityResult],
+    config: &LodConfig,
+    options: &TopupOptions,
+) -> Vec<TopupRow> {
+    claims.claims.iter().flat_map(|claim| plan_claim(claim, results, config, options)).collect()
+}
+
+fn plan_claim(
+    claim: &Claim,
+    results: &[DetectabilityResult],
+    config: &LodConfig,
+    options: &TopupOptions,
+) -> Vec<TopupRow> {
+    let in_scope: Vec<&DetectabilityResult> =
+        results.iter().filter(|result| claim.covers_class(&result.variant)).collect();
+    let groups: Vec<(BedRegion, Vec<&DetectabilityResult>)> = match claim.region_list() {
+        Some(regions) => {
+            // Per contig, the results in scope sorted by 0-based position
+            let mut by_chrom: HashMap<&str, Vec<(u64, &DetectabilityResult)>> = HashMap::new();
+            for &result in &in_scope {
+                by_chrom
+                    .entry(result.variant.chrom.as_str())
+                    .or_default()
+                    .push((result.variant.pos.saturating_sub(1), result));
+            }
+            for sites in by_chrom.values_mut() {
+                sites.sort_by_key(|&(pos, _)| pos);
+            }
+            regions
+                .iter()
+                .map(|region| {
+                    let within = by_chrom
+                        .get(region.chrom.as_str())
+                        .map(|sites| {
+                            let first = sites.partition_point(|&(pos, _)| pos < region.start);
+                            let last = sites.partition_point(|&(pos, _)| pos < region.end);
+                            sites[first..last].iter().map(|&(_, result)| result).collect()
+                        })
+                        .unwrap_or_default();
+                    (region.clone(), within)
+                })
+                .collect()
+        }
+        None => {
+            // One site per position, in the order the results first reach it
+            let mut sites: Vec<(BedRegion, Vec<&DetectabilityResult>)> = Vec::new();
+            let mut site_index: HashMap<(&str, u64), usize> = HashMap::new();
+            for result in in_scope {
+                let variant = &result.variant;
+                let start = variant.pos.saturating_sub(1);
+                let index = *site_index.entry((variant.chrom.as_str(), start)).or_insert_with(|| {
+                    let site = BedRegion { chrom: variant.chrom.clone(), start, end: start + 1, name: None };
+                    sites.push((site, Vec::new()));
+                    sites.len() - 1
+                });
+                sites[index].1.push(result);
+            }
+            sites
+        }
+    };
+
+    groups
+        .into_iter()
+        .filter(|(_, within)| !within.is_empty())
+        .map(|(region, within)| plan_region(claim, region, &within, config, options))
+        .collect()
+}
+
+fn plan_region(
+    claim: &Claim,
+    region: BedRegion,
+    within: &[&DetectabilityResult],
+    config: &LodConfig,
+    options: &TopupOptions,
+) -> TopupRow {
+    let mut row = TopupRow {
+        claim: claim.name.clone(),
+        region,
+        variants: within.len(),
+        short: 0,
+        unreachable: 0,
+        required_depth: None,
+        additional_coverage: 0,
+        additional_reads: 0,
+        extra_lanes: 0.0,
+    };
+    let mut fold_increase: f64 = 0.0;
+    for result in within {
+        let required = claim.required_depth(&result.variant, config);
+        if required.is_some_and(|required| result.coverage >= required) {
+            continue;
+        }
+        row.short += 1;
+        match required {
+            Some(required) if result.coverage > 0 => {
+                row.required_depth = Some(row.required_depth.map_or(required, |depth| depth.max(required)));
+                row.additional_coverage = row.additional_coverage.max(required - result.coverage);
+                fold_increase = fold_increase.max(required as f64 / result.coverage as f64 - 1.0);
+            }
+            _ => row.unreachable += 1,
+        }
+    }
+    let length = row.region.end - row.region.start;
+    row.additional_reads = (row.additional_coverage as u64 * length).div_ceil(options.read_length as u64);
+    row.extra_lanes = fold_increase * options.current_lanes;
+    row
+}
+
+/// Write a top-up plan as TSV
+pub fn write_topup_plan<P: AsRef<Path>>(rows: &[TopupRow], path: P) -> VlodResult<()> {
+    write_topup_plan_to_writer(rows, BufWriter::new(create_output_file(path)?))
+}
+
+/// Write a top-up plan to any writer
+pub fn write_topup_plan_to_writer<W: Write>(rows: &[TopupRow], mut writer: W) -> VlodResult<()> {
+    writeln!(writer, "{}", about_comment())?;
+    writeln!(
+        writer,
+        "Claim\tChrom\tStart\tEnd\tName\tVariants\tShort\tUnreachable\tRequired_Depth\t\
+         Additional_Coverage\tAdditional_Reads\tExtra_Lanes"
+    )?;
+    for row in rows {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.2}",
+            row.claim,
+            row.region.chrom,
+            row.region.start,
+            row.region.end,
+            row.region.name.as_deref().unwrap_or("."),
+            row.variants,
+            row.short,
+            row.unreachable,
+            row.required_depth.map_or(".".to_string(), |depth| depth.to_string()),
+            row.additional_coverage,
+            row.additional_reads,
+            row.extra_lanes
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rollup::required_depth_for_vaf, DetectabilityCondition};
+
+    fn result(pos: u64, alt: &str, coverage: u32) -> DetectabilityResult {
+        let variant = Variant::new("chr1".to_string(), pos, "A".to_string(), alt.to_string());
+        DetectabilityResult::new(variant, 1.0, DetectabilityCondition::NonDetectable, coverage, 0)
+    }
+
+    #[test]
+    fn test_plan_topup() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("panel.bed"), "chr1\t0\t150\tEXON1\nchr1\t150\t300\tEXON2\n").unwrap();
+        let json = r#"{"claims": [
+            {"name": "SNV 5%", "classes": ["snv"], "vaf": 0.05, "regions": "panel.bed"},
+            {"name": "SNV 5% at 1000x", "classes": ["snv"], "vaf": 0.05, "min_depth": 100000}
+        ]}"#;
+        let claims = ClaimSet::from_reader(json.as_bytes(), dir.path()).unwrap();
+        let config = LodConfig::default();
+        let required = required_depth_for_vaf(&result(100, "G", 0).variant, &config, 0.05).unwrap();
+        let results = vec![
+            result(100, "G", required / 2),
+            result(120, "G", 0),
+            result(200, "G", required),
+            result(200, "AG", 10),
+        ];
+        let options = TopupOptions { read_length: 100, current_lanes: 2.0 };
+        let rows = plan_topup(&claims, &results, &config, &options);
+
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0].region.name.as_deref(), Some("EXON1"));
+        assert_eq!((rows[0].variants, rows[0].short, rows[0].unreachable), (2, 2, 1));
+        assert_eq!(rows[0].required_depth, Some(required));
+        assert_eq!(rows[0].additional_coverage, required - required / 2);
+        assert_eq!(rows[0].additional_reads, (rows[0].additional_coverage as u64 * 150).div_ceil(100));
+        let fold = required as f64 / (required / 2) as f64 - 1.0;
+        assert!((rows[0].extra_lanes - 2.0 * fold).abs() < 1e-9);
+        // The insertion is outside the SNV claims
+        assert_eq!((rows[1].variants, rows[1].short, rows[1].extra_lanes), (1, 0, 0.0));
+
+        // Without regions, one row per site, and the claimed depth applies
+        assert_eq!((rows[2].region.start, rows[2].region.end), (99, 100));
+        assert_eq!((rows[3].short, rows[3].unreachable, rows[3].required_depth), (1, 1, None));
+        assert_eq!((rows[4].variants, rows[4].required_depth), (1, Some(100000)));
+        assert_eq!(rows[4].additional_coverage, 100000 - required);
+
+        let mut plan = Vec::new();
+        write_topup_plan_to_writer(&rows, &mut plan).unwrap();
+        let plan = String::from_utf8(plan).unwrap();
+        assert!(plan.contains("SNV 5%\tchr1\t150\t300\tEXON2\t1\t0\t0\t.\t0\t0\t0.00\n"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Coverage uniformity over the target regions: the fold-80 base penalty, the share
+//! of target bases reaching depth thresholds and the correlation of depth with GC
+//! content, so that detectability failures can be traced to the library rather than
+//! to the variants
+
+use crate::{bam::BamAnalyzer, reference::ReferenceFasta, regions::BedRegion, LodConfig, VlodResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Depths whose share of target bases is reported unless others are given
+pub const DEFAULT_COVERAGE_THRESHOLDS: [u32; 4] = [20, 100, 250, 500];
+
+/// Width of the target bins whose GC content is correlated with their mean depth
+pub const GC_BIN_SIZE: u64 = 100;
+
+/// Share of the target bases at one depth or more
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdCoverage {
+    pub min_depth: u32,
+    pub pct_bases: f64,
+}
+
+/// Coverage uniformity over the target regions, recorded in the run summary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoverageUniformity {
+    pub target_bases: u64,
+    pub mean_depth: f64,
+    /// Mean depth over the depth of the 20th percentile base: the sequencing
+    /// multiple raising 80% of the target bases to the mean (None when a fifth of
+    /// the bases or more have no reads)
+    pub fold_80_base_penalty: Option<f64>,
+    pub pct_bases_at_depth: Vec<ThresholdCoverage>,
+    /// Pearson correlation of the GC content and mean depth of target bins (None
+    /// without a reference or with fewer than two bins varying in both)
+    pub gc_coverage_correlation: Option<f64>,
+}
+
+impl CoverageUniformity {
+    pub fn log(&self) {
+        log::info!("Target coverage ({} bases):", self.target_bases);
+        log::info!("  Mean depth: {:.1}", self.mean_depth);
+        if let Some(penalty) = self.fold_80_base_penalty {
+            log::info!("  Fold-80 base penalty: {:.2}", penalty);
+        }
+        for threshold in &self.pct_bases_at_depth {
+            log::info!("  Bases at {}x or more: {:.1}%", threshold.min_depth, threshold.pct_bases);
+        }
+        if let Some(correlation) = self.gc_coverage_correlation {
+            log::info!("  GC/depth correlation: {:.3}", correlation);
+        }
+    }
+}
+
+/// Depth histogram of the target bases and the GC content and mean depth of
+/// their bins, summarized by `finish`
+#[derive(Debug, Clone, Default)]
+pub struct UniformityAccumulator {
+    histogram: BTreeMap<u32, u64>,
+    gc_bins: Vec<(f64, f64)>,
+}
+
+impl UniformityAccumulator {
+    pub fn add_bases(&mut self, depths: &[u32]) {
+        for &depth in depths {
+            *self.histogram.entry(depth).or_insert(0) += 1;
+        }
+    }
+
+    /// Add a bin from its reference bases and their depths; bins without A, C, G
+    /// or T bases are left out of the GC correlation
+    pub fn add_gc_bin(&mut self, bases: &[u8], depths: &[u32]) {
+        let called = bases.iter().filter(|base| b"ACGTacgt".contains(base)).count();
+        if called == 0 || depths.is_empty() {
+            return;
+        }
+        let gc = bases.iter().filter(|base| b"GCgc".contains(base)).count() as f64 / called as f64;
+        let mean_depth = depths.iter().map(|&depth| depth as f64).sum::<f64>() / depths.len() as f64;
+        self.gc_bins.push((gc, mean_depth));
+    }
+
+    pub fn finish(&self, thresholds: &[u32]) -> CoverageUniformity {
+        let target_bases: u64 = self.histogram.values().sum();
+        let total_depth: f64 = self.histogram.iter().map(|(&depth, &count)| depth as f64 * count as f64).sum();
+        let mean_depth = if target_bases == 0 { 0.0 } else { total_depth / target_bases as f64 };
+
+        // Depth of the base at the 20th percentile, counting from the shallowest
+        let mut below = 0;
+        let rank = (target_bases as f64 * 0.2).ceil() as u64;
+        let percentile_20 = self.histogram.iter().find_map(|(&depth, &count)| {
+            below += count;
+            (below >= rank.max(1)).then_some(depth)
+        });
+        let fold_80_base_penalty = percentile_20.filter(|&depth| depth > 0).map(|depth| mean_depth / depth as f64);
+
+        let pct_bases_at_depth = thresholds
+            .iter()
+            .map(|&min_depth| {
+                let at_depth: u64 = self.histogram.range(min_depth..).map(|(_, &count)| count).sum();
+                let pct_bases = if target_bases == 0 { 0.0 } else { 100.0 * at_depth as f64 / target_bases as f64 };
+                ThresholdCoverage { min_depth, pct_bases }
+            })
+            .collect();
+
+        CoverageUniformity {
+            target_bases,
+            mean_depth,
+            fold_80_base_penalty,
+            pct_bases_at_depth,
+            gc_coverage_correlation: pearson_correlation(&self.gc_bins),
+        }
+    }
+}
+
+/// Pearson correlation of paired values (None for fewer than two pairs or no
+/// variation in either)
+pub fn pearson_correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|&(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|&(_, y)| y).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for &(x, y) in pairs {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    (variance_x > 0.0 && variance_y > 0.0).then(|| covariance / (variance_x * variance_y).sqrt())
+}
+
+/// Sort regions and merge overlapping ones, so no base is counted twice
+fn merge_regions(regions: &[BedRegion]) -> Vec<BedRegion> {
+    let mut sorted = regions.to_vec();
+    sorted.sort_by(|a, b| (&a.chrom, a.start).cmp(&(&b.chrom, b.start)));
+    let mut merged: Vec<BedRegion> = Vec::new();
+    for region in sorted {
+        match merged.last_mut() {
+            Some(last) if last.chrom == region.chrom && region.start <= last.end => {
+                last.end = last.end.max(region.end);
+            }
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Measure the coverage uniformity of a BAM over target regions, with the GC
+/// correlation of `GC_BIN_SIZE` bins when a reference is given. Reads are counted
+/// as for `BamAnalyzer::region_depths`.
+pub fn measure_uniformity(
+    bam_path: &Path,
+    regions: &[BedRegion],
+    reference: Option<&ReferenceFasta>,
+    config: &LodConfig,
+    thresholds: &[u32],
+) -> VlodResult<CoverageUniformity> {
+    let mut analyzer = BamAnalyzer::new(bam_path)?.with_config(config);
+    let mut accumulator = UniformityAccumulator::default();
+    for region in merge_regions(regions) {
+        let depths = analyzer.region_depths(&region.chrom, region.start, region.end)?;
+        accumulator.add_bases(&depths);
+        let Some(reference) = reference else {
+            continue;
+        };
+        let bases = reference.fetch(&region.chrom, region.start + 1, region.end)?;
+        for (bin, bin_bases) in bases.as_bytes().chunks(GC_BIN_SIZE as usize).enumerate() {
+            let offset = bin * GC_BIN_SIZE as usize;
+            let bin_depths = &depths[offset.min(depths.len())..(offset + bin_bases.len()).min(depths.len())];
+            accumulator.add_gc_bin(bin_bases, bin_depths);
+        }
+    }
+    Ok(accumulator.finish(thresholds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniformity_metrics() {
+        let mut accumulator = UniformityAccumulator::default();
+        // 10 bases: two at 50x, eight at 100x
+        accumulator.add_bases(&[50, 50]);
+        accumulator.add_bases(&[100; 8]);
+        accumulator.add_gc_bin(b"GGGGCCCCAT", &[50; 10]);
+        accumulator.add_gc_bin(b"GCATATATAT", &[100; 10]);
+        accumulator.add_gc_bin(b"NNNN", &[0; 4]);
+        let uniformity = accumulator.finish(&[20, 100, 250]);
+
+        assert_eq!(uniformity.target_bases, 10);
+        assert!((uniformity.mean_depth - 90.0).abs() < 1e-9);
+        assert!((uniformity.fold_80_base_penalty.unwrap() - 1.8).abs() < 1e-9);
+        let pct: Vec<f64> = uniformity.pct_bases_at_depth.iter().map(|threshold| threshold.pct_bases).collect();
+        assert_eq!(pct, vec![100.0, 80.0, 0.0]);
+        assert!((uniformity.gc_coverage_correlation.unwrap() + 1.0).abs() < 1e-9);
+
+        // A fifth of the bases uncovered leaves no penalty
+        let mut sparse = UniformityAccumulator::default();
+        sparse.add_bases(&[0, 0, 100, 100, 100, 100, 100, 100, 100, 100]);
+        assert_eq!(sparse.finish(&[]).fold_80_base_penalty, None);
+        assert_eq!(pearson_correlation(&[(0.5, 10.0)]), None);
+    }
+
+    #[test]
+    fn test_measure_uniformity() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let reference = ReferenceFasta::open(&data.reference, false).unwrap();
+        let site = &data.expected[0].0;
+        let target = BedRegion { chrom: site.chrom.clone(), start: site.pos - 1, end: site.pos, name: None };
+        let overlapping = BedRegion { start: site.pos - 2, ..target.clone() };
+
+        let uniformity =
+            measure_uniformity(&data.bam, &[target, overlapping], Some(&reference), &LodConfig::default(), &[1])
+                .unwrap();
+        assert_eq!(uniformity.target_bases, 2);
+        assert!(uniformity.mean_depth > 0.0);
+        assert_eq!(uniformity.pct_bases_at_depth[0].pct_bases, 100.0);
+    }
+}
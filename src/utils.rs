@@ -1,9 +1,20 @@
 //! Utility functions for file handling and common operations
 
 use crate::{VlodError, VlodResult};
+use flate2::read::MultiGzDecoder;
+use rust_htslib::htslib;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Default limit on the length of a single line in VCF and TSV inputs (64 MiB)
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024 * 1024;
 
 /// Check if a file is gzip compressed
 pub fn is_gzipped<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
@@ -17,6 +28,127 @@ pub fn is_gzipped<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
     }
 }
 
+/// Check if a file is compressed with blocked gzip (BGZF), which htslib can index
+/// and seek into
+pub fn is_bgzipped<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
+    let mut header = Vec::with_capacity(18);
+    File::open(path)?.take(18).read_to_end(&mut header)?;
+    // BGZF sets FEXTRA and stores its block size in a `BC` extra subfield
+    Ok(header.len() == 18 && header.starts_with(&[0x1f, 0x8b]) && header[3] & 0x04 != 0 && header[12..14] == *b"BC")
+}
+
+/// Reader that fails on a line longer than a limit, so that a corrupted input (e.g.
+/// concatenated binary data without newlines) errors out instead of being
+/// buffered into memory
+pub struct LineLengthGuard<R> {
+    inner: R,
+    name: String,
+    max_line_length: usize,
+    line_length: usize,
+    line_number: usize,
+}
+
+impl<R: Read> LineLengthGuard<R> {
+    pub fn new(inner: R, max_line_length: usize) -> Self {
+        LineLengthGuard {
+            inner,
+            name: "input".to_string(),
+            max_line_length,
+            line_length: 0,
+            line_number: 1,
+        }
+    }
+
+    /// Name the input (usually its path) in error messages
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+}
+
+impl<R: Read> Read for LineLengthGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for &byte in &buf[..n] {
+            if byte == b'\n' {
+                self.line_number += 1;
+                self.line_length = 0;
+                continue;
+            }
+            self.line_length += 1;
+            if self.line_length > self.max_line_length {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "line {} of {} is longer than the maximum line length of {} bytes (see --max-line-length)",
+                        self.line_number, self.name, self.max_line_length
+                    ),
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Open a plain or gzipped text file for line-based reading, failing on lines
+/// longer than `max_line_length`
+pub fn open_text_input<P: AsRef<Path>>(path: P, max_line_length: usize) -> VlodResult<Box<dyn BufRead>> {
+    let file = File::open(platform_path(&path))
+        .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+    let name = path.as_ref().to_string_lossy().to_string();
+
+    let reader: Box<dyn BufRead> = if is_gzipped(&path)? {
+        let gz_decoder = MultiGzDecoder::new(file);
+        Box::new(BufReader::new(LineLengthGuard::new(gz_decoder, max_line_length).named(name)))
+    } else {
+        Box::new(BufReader::new(LineLengthGuard::new(file, max_line_length).named(name)))
+    };
+    Ok(reader)
+}
+
+/// Tally of invalid records skipped while reading a file, failing once a limit
+/// (`--max-errors`) is reached
+#[derive(Debug)]
+pub struct ParseErrorBudget {
+    source: String,
+    max_errors: Option<usize>,
+    errors: usize,
+}
+
+impl ParseErrorBudget {
+    pub fn new(source: &str, max_errors: Option<usize>) -> Self {
+        ParseErrorBudget {
+            source: source.to_string(),
+            max_errors,
+            errors: 0,
+        }
+    }
+
+    /// Record a skipped record, failing with a summary when the limit is reached
+    pub fn record(&mut self, error: VlodError) -> VlodResult<()> {
+        self.errors += 1;
+        log::warn!("Skipping invalid record: {}", error);
+        match self.max_errors {
+            Some(max_errors) if self.errors >= max_errors => Err(VlodError::InvalidVariant(format!(
+                "Stopped after {} parse errors in {} (most recent: {})",
+                self.errors, self.source, error
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Log how many records were skipped, if any
+    pub fn log_summary(&self) {
+        if self.errors > 0 {
+            log::warn!("Skipped {} invalid records in {}", self.errors, self.source);
+        }
+    }
+}
+
 /// Get the number of CPU cores, with a fallback default
 pub fn get_num_cpus() -> usize {
     std::thread::available_parallelism()
@@ -75,6 +207,101 @@ pub fn format_file_size(size: u64) -> String {
     format!("{:.2} {}", size, UNITS[unit_index])
 }
 
+/// `path` with `.extension` appended to its file name. Unlike `Path::with_extension`,
+/// this keeps names with dots intact (`sample.v2` -> `sample.v2.bai`, not `sample.bai`).
+pub fn append_extension<P: AsRef<Path>>(path: P, extension: &str) -> PathBuf {
+    let mut appended = path.as_ref().as_os_str().to_owned();
+    appended.push(".");
+    appended.push(extension);
+    PathBuf::from(appended)
+}
+
+/// Build a tabix index of the bgzipped file at `path` with the columns of `conf`:
+/// a .tbi for a `min_shift` of 0, else a .csi with that minimal interval size
+pub fn build_tabix_index(path: &Path, conf: &htslib::tbx_conf_t, min_shift: i32) -> VlodResult<()> {
+    let path_c = CString::new(path.to_string_lossy().as_bytes())
+        .map_err(|_| VlodError::InvalidConfig(format!("invalid path: {}", path.display())))?;
+    // SAFETY: `path_c` is a NUL-terminated string and `conf` a borrowed config, both
+    // live for the whole call; htslib only reads them and keeps neither pointer
+    let status = unsafe { htslib::tbx_index_build(path_c.as_ptr(), min_shift, conf) };
+    if status != 0 {
+        return Err(VlodError::InvalidConfig(format!(
+            "could not build a tabix index for {} (status {}); is it sorted?",
+            path.display(),
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Windows `MAX_PATH`: longer paths need the extended-length prefix
+#[cfg(windows)]
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Extended-length form of an absolute Windows path (`C:\x` -> `\\?\C:\x`,
+/// `\\server\share` -> `\\?\UNC\server\share`); already prefixed paths are kept
+pub fn extended_length_path(absolute: &str) -> String {
+    if absolute.starts_with(r"\\?\") {
+        absolute.to_string()
+    } else if let Some(unc) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", absolute)
+    }
+}
+
+/// Path to hand to the OS file APIs. On Windows, paths of `MAX_PATH` characters or
+/// more are made absolute and given the extended-length prefix; elsewhere the path
+/// is used as is.
+#[cfg(windows)]
+pub fn platform_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    if path.as_os_str().len() < WINDOWS_MAX_PATH {
+        return path.to_path_buf();
+    }
+    match std::path::absolute(path) {
+        // The prefix disables `/` separators, so use the normalised absolute form
+        Ok(absolute) => PathBuf::from(extended_length_path(&absolute.to_string_lossy())),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn platform_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().to_path_buf()
+}
+
+/// Whether an error means another process holds the file open without sharing it
+/// (e.g. a spreadsheet on Windows)
+#[cfg(windows)]
+fn is_sharing_violation(error: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    matches!(error.raw_os_error(), Some(32) | Some(33))
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation(_error: &std::io::Error) -> bool {
+    false
+}
+
+/// Create or truncate an output file, with a clear error when another program
+/// holds it open
+pub fn create_output_file<P: AsRef<Path>>(path: P) -> VlodResult<File> {
+    File::create(platform_path(&path)).map_err(|error| {
+        if is_sharing_violation(&error) {
+            VlodError::Io(std::io::Error::new(
+                error.kind(),
+                format!(
+                    "{} is open in another program; close it and retry",
+                    path.as_ref().display()
+                ),
+            ))
+        } else {
+            VlodError::Io(error)
+        }
+    })
+}
+
 /// Create parent directories if they don't exist
 pub fn ensure_parent_dirs<P: AsRef<Path>>(path: P) -> VlodResult<()> {
     if let Some(parent) = path.as_ref().parent() {
@@ -150,29 +377,216 @@ impl Drop for Timer {
     }
 }
 
+/// Intermediate file that is removed when dropped
+pub struct ScratchFile {
+    path: std::path::PathBuf,
+}
+
+impl ScratchFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ScratchFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        if self.path.exists() {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                log::warn!("Could not remove intermediate file {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
 /// Memory usage reporting utility
 pub fn log_memory_usage(context: &str) {
+    match resident_memory_kb() {
+        Some(memory_kb) => log::info!("Memory usage ({}): {} MB", context, memory_kb / 1024),
+        None => log::debug!("Memory usage logging not supported on this platform ({})", context),
+    }
+}
+
+/// Resident memory of this process in kB, where /proc exposes it
+pub fn resident_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// User plus system CPU time of this process so far, where the platform reports it
+pub fn cpu_time() -> Option<Duration> {
     #[cfg(unix)]
     {
-        use std::fs;
-        if let Ok(status) = fs::read_to_string("/proc/self/status") {
-            for line in status.lines() {
-                if line.starts_with("VmRSS:") {
-                    if let Some(memory_str) = line.split_whitespace().nth(1) {
-                        if let Ok(memory_kb) = memory_str.parse::<u64>() {
-                            let memory_mb = memory_kb / 1024;
-                            log::info!("Memory usage ({}): {} MB", context, memory_mb);
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+            return None;
+        }
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        };
+        Some(duration(usage.ru_utime) + duration(usage.ru_stime))
+    }
+
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// Interval at which `ResourceSampler` reads the resident memory
+pub const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wall time, CPU time and peak resident memory of one stage of a run, for sizing
+/// cluster job requests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageUsage {
+    pub stage: String,
+    pub wall_seconds: f64,
+    /// User plus system CPU time of the process over the stage
+    pub cpu_seconds: f64,
+    /// CPU time over wall time, in percent of one core
+    pub cpu_percent: f64,
+    /// Highest resident memory sampled during the stage (None without /proc)
+    pub peak_rss_mb: Option<f64>,
+}
+
+/// Samples the resident memory of the process in a background thread and records
+/// the usage of each stage begun with `stage`. Memory is sampled every interval,
+/// so a peak shorter than that may be missed.
+pub struct ResourceSampler {
+    peak_kb: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Name, start and CPU time at the start of the current stage
+    current: Option<(String, Instant, Option<Duration>)>,
+    stages: Vec<StageUsage>,
+}
+
+impl ResourceSampler {
+    pub fn start(interval: Duration) -> Self {
+        let peak_kb = Arc::new(AtomicU64::new(resident_memory_kb().unwrap_or(0)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (peak_kb, stop) = (peak_kb.clone(), stop.clone());
+            std::thread::Builder::new()
+                .name("vlod-resources".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        if let Some(memory_kb) = resident_memory_kb() {
+                            peak_kb.fetch_max(memory_kb, Ordering::Relaxed);
                         }
+                        std::thread::park_timeout(interval);
                     }
-                    break;
-                }
-            }
+                })
+                .ok()
+        };
+        if thread.is_none() {
+            log::warn!("Could not start the resource sampler; peak memory is only read between stages");
+        }
+        ResourceSampler {
+            peak_kb,
+            stop,
+            thread,
+            current: None,
+            stages: Vec::new(),
         }
     }
-    
+
+    /// End the current stage, if any, and begin the next
+    pub fn stage(&mut self, name: &str) {
+        self.end_stage();
+        self.peak_kb.store(resident_memory_kb().unwrap_or(0), Ordering::Relaxed);
+        self.current = Some((name.to_string(), Instant::now(), cpu_time()));
+    }
+
+    /// End the current stage and return the usage of every stage so far
+    pub fn usage(&mut self) -> Vec<StageUsage> {
+        self.end_stage();
+        self.stages.clone()
+    }
+
+    fn end_stage(&mut self) {
+        let Some((stage, start, start_cpu)) = self.current.take() else {
+            return;
+        };
+        let memory_kb = resident_memory_kb();
+        let peak_rss_mb = memory_kb.map(|memory_kb| {
+            let peak_kb = self.peak_kb.fetch_max(memory_kb, Ordering::Relaxed).max(memory_kb);
+            peak_kb as f64 / 1024.0
+        });
+        let wall_seconds = start.elapsed().as_secs_f64();
+        let cpu_seconds = match (start_cpu, cpu_time()) {
+            (Some(start_cpu), Some(end_cpu)) => end_cpu.saturating_sub(start_cpu).as_secs_f64(),
+            _ => 0.0,
+        };
+        let cpu_percent = if wall_seconds > 0.0 { 100.0 * cpu_seconds / wall_seconds } else { 0.0 };
+        log::info!(
+            "Stage {}: {:.1}s wall, {:.1}s CPU ({:.0}%), peak RSS {}",
+            stage,
+            wall_seconds,
+            cpu_seconds,
+            cpu_percent,
+            peak_rss_mb.map_or("unknown".to_string(), |mb| format!("{:.0} MB", mb))
+        );
+        self.stages.push(StageUsage {
+            stage,
+            wall_seconds,
+            cpu_seconds,
+            cpu_percent,
+            peak_rss_mb,
+        });
+    }
+}
+
+impl Drop for ResourceSampler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Open file descriptors of this process and their soft limit (`ulimit -n`), where
+/// the platform exposes them
+pub fn file_descriptor_usage() -> Option<(usize, Option<u64>)> {
+    #[cfg(unix)]
+    {
+        let open = ["/proc/self/fd", "/dev/fd"]
+            .iter()
+            .find_map(|dir| std::fs::read_dir(dir).ok())
+            // The directory listing itself holds one descriptor
+            .map(|entries| entries.count().saturating_sub(1))?;
+
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let soft_limit = (unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0
+            && limit.rlim_cur != libc::RLIM_INFINITY)
+            .then_some(limit.rlim_cur as u64);
+        Some((open, soft_limit))
+    }
+
     #[cfg(not(unix))]
     {
-        log::debug!("Memory usage logging not supported on this platform ({})", context);
+        None
+    }
+}
+
+/// File descriptor usage reporting utility
+pub fn log_file_descriptor_usage(context: &str) {
+    match file_descriptor_usage() {
+        Some((open, Some(limit))) => log::info!("Open file descriptors ({}): {} of limit {}", context, open, limit),
+        Some((open, None)) => log::info!("Open file descriptors ({}): {}", context, open),
+        None => log::debug!("File descriptor reporting not supported on this platform ({})", context),
     }
 }
 
@@ -182,6 +596,27 @@ mod tests {
     use std::io::Write;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn test_resource_sampler() {
+        let mut sampler = ResourceSampler::start(Duration::from_millis(5));
+        sampler.stage("allocating");
+        let buffer = vec![1u8; 32 * 1024 * 1024];
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(buffer.iter().map(|&byte| byte as usize).sum::<usize>(), buffer.len());
+        sampler.stage("idle");
+        let usage = sampler.usage();
+
+        let stages: Vec<&str> = usage.iter().map(|usage| usage.stage.as_str()).collect();
+        assert_eq!(stages, vec!["allocating", "idle"]);
+        assert!(usage[0].wall_seconds >= 0.02);
+        assert!(usage.iter().all(|usage| usage.cpu_seconds >= 0.0 && usage.cpu_percent >= 0.0));
+        if cfg!(target_os = "linux") {
+            assert!(usage[0].peak_rss_mb.unwrap() >= 32.0);
+        }
+        // Nothing left to end
+        assert_eq!(sampler.usage().len(), 2);
+    }
+
     #[test]
     fn test_is_gzipped() {
         // Test with a regular file
@@ -195,6 +630,37 @@ mod tests {
         assert_eq!(is_gzipped(temp_file.path()).unwrap(), true);
     }
 
+    #[test]
+    fn test_line_length_guard() {
+        let input = "short\nline\n".as_bytes();
+        let mut content = String::new();
+        LineLengthGuard::new(input, 5).read_to_string(&mut content).unwrap();
+        assert_eq!(content, "short\nline\n");
+
+        let input = "ok\nmuch too long\n".as_bytes();
+        let mut content = String::new();
+        let error = LineLengthGuard::new(input, 5)
+            .named("test.vcf")
+            .read_to_string(&mut content)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 2 of test.vcf"));
+    }
+
+    #[test]
+    fn test_parse_error_budget() {
+        let mut unlimited = ParseErrorBudget::new("test.vcf", None);
+        for _ in 0..10 {
+            assert!(unlimited.record(VlodError::InvalidVariant("bad".to_string())).is_ok());
+        }
+        assert_eq!(unlimited.errors(), 10);
+
+        let mut budget = ParseErrorBudget::new("test.vcf", Some(2));
+        assert!(budget.record(VlodError::InvalidVariant("bad".to_string())).is_ok());
+        let error = budget.record(VlodError::InvalidVariant("worse".to_string())).unwrap_err();
+        assert!(error.to_string().contains("Stopped after 2 parse errors in test.vcf"));
+    }
+
     #[test]
     fn test_get_num_cpus() {
         let num_cpus = get_num_cpus();
@@ -253,10 +719,61 @@ mod tests {
         assert!(chunks[0].is_empty());
     }
 
+    #[test]
+    fn test_scratch_file_removed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scratch.tmp");
+        {
+            let scratch = ScratchFile::new(&path);
+            std::fs::write(scratch.path(), "data").unwrap();
+            assert!(path.exists());
+        }
+        assert!(!path.exists());
+    }
+
     #[test]
     fn test_timer() {
         let timer = Timer::new("test");
         std::thread::sleep(std::time::Duration::from_millis(1));
         assert!(timer.elapsed().as_millis() >= 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_append_extension() {
+        assert_eq!(append_extension("sample.v2.bam", "bai"), PathBuf::from("sample.v2.bam.bai"));
+        assert_eq!(append_extension("dir.d/ref", "fai"), PathBuf::from("dir.d/ref.fai"));
+    }
+
+    #[test]
+    fn test_extended_length_path() {
+        assert_eq!(extended_length_path(r"C:\data\sample.bam"), r"\\?\C:\data\sample.bam");
+        assert_eq!(extended_length_path(r"\\server\share\x.vcf"), r"\\?\UNC\server\share\x.vcf");
+        assert_eq!(extended_length_path(r"\\?\C:\x.vcf"), r"\\?\C:\x.vcf");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_platform_path_unchanged() {
+        let long = format!("/tmp/{}/x.vcf", "d".repeat(300));
+        assert_eq!(platform_path(&long), PathBuf::from(long));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_platform_path_long() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(platform_path(dir.path()), dir.path());
+
+        // Nested directories beyond MAX_PATH are usable through the prefixed form
+        let mut long = dir.path().to_path_buf();
+        for _ in 0..10 {
+            long.push("d".repeat(30));
+        }
+        let prefixed = platform_path(&long);
+        assert!(prefixed.to_string_lossy().starts_with(r"\\?\"));
+        std::fs::create_dir_all(&prefixed).unwrap();
+        let output = long.join("out.vcf");
+        create_output_file(&output).unwrap();
+        assert!(platform_path(&output).exists());
+    }
+}
@@ -1,10 +1,479 @@
 //! VCF file processing functionality
 
-use crate::{Variant, VlodError, VlodResult};
-use flate2::read::MultiGzDecoder;
+use crate::{
+    reference::ReferenceFasta,
+    utils::{create_output_file, open_text_input, ParseErrorBudget, DEFAULT_MAX_LINE_LENGTH},
+    warnings::{WarningKind, Warnings},
+    DetectabilityResult, Variant, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufWriter, Read, Write};
 use std::path::Path;
+use std::str::FromStr;
+
+/// How to resolve a variant that appears more than once in a VCF or results file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the first occurrence
+    #[default]
+    First,
+    /// Keep the occurrence with the highest detectability score
+    Max,
+    /// Fail on the first duplicate
+    Error,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(DuplicatePolicy::First),
+            "max" => Ok(DuplicatePolicy::Max),
+            "error" => Ok(DuplicatePolicy::Error),
+            _ => Err(format!("unknown duplicate policy '{}' (expected first, max or error)", s)),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DuplicatePolicy::First => "first",
+            DuplicatePolicy::Max => "max",
+            DuplicatePolicy::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Variant caller whose VCF conventions are followed when extracting variants
+/// and reading allele depths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Caller {
+    /// Detect the caller from the VCF header (`##source=`, `##DRAGENCommandLine=`);
+    /// plain VCF conventions apply until a caller is recognised
+    #[default]
+    Auto,
+    /// Plain VCF: every ALT is a variant and only `PASS` passes
+    Generic,
+    /// DRAGEN (hard-filtered VCF or gVCF): `<NON_REF>` alleles and reference
+    /// blocks are dropped, `LowGQ` alone does not fail a record, and substitution
+    /// alleles padded to a multi-allelic record's REF are trimmed back
+    Dragen,
+    /// Strelka2: allele depths in the tier-1 counts `AU`/`CU`/`GU`/`TU` (SNVs) or
+    /// `TAR`/`TIR` (indels) of the last sample, the tumour of somatic VCFs
+    Strelka2,
+    /// VarDict: sample depth in `DP` and ALT depth in `VD`, or only `AF`
+    VarDict,
+}
+
+/// Symbolic ALT of gVCF reference blocks and of the non-reference likelihoods
+/// of variant records
+pub const NON_REF_ALLELE: &str = "<NON_REF>";
+
+/// DRAGEN filter on calls with low genotype quality; the allele evidence is
+/// unaffected, so it does not fail a record
+const DRAGEN_LOW_GQ_FILTER: &str = "LowGQ";
+
+/// Read depths a VCF record reports for its sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlleleDepths {
+    /// Reads covering the site
+    pub depth: u32,
+    /// Reads supporting the first ALT allele, when reported
+    pub alt_depth: Option<u32>,
+}
+
+impl Caller {
+    /// Caller named by a VCF header line, if recognised
+    pub fn detect(header_line: &str) -> Option<Caller> {
+        let line = header_line.to_ascii_lowercase();
+        if line.starts_with("##dragencommandline=") {
+            return Some(Caller::Dragen);
+        }
+        let source = line.strip_prefix("##source=")?;
+        if source.starts_with("dragen") {
+            Some(Caller::Dragen)
+        } else if source.starts_with("strelka") {
+            Some(Caller::Strelka2)
+        } else if source.starts_with("vardict") {
+            Some(Caller::VarDict)
+        } else {
+            None
+        }
+    }
+
+    /// Resolve `auto` with a VCF header line, logging the caller detected
+    pub fn resolve(self, header_line: &str) -> Caller {
+        match Caller::detect(header_line) {
+            Some(detected) if self == Caller::Auto => {
+                log::info!("Detected {} VCF conventions from the header", detected);
+                detected
+            }
+            _ => self,
+        }
+    }
+
+    /// Whether a FILTER value counts as PASS
+    pub fn is_pass(self, filter: &str) -> bool {
+        match self {
+            Caller::Dragen => filter == "PASS" || filter.split(';').all(|filter| filter == DRAGEN_LOW_GQ_FILTER),
+            _ => filter == "PASS",
+        }
+    }
+
+    /// ALT alleles of a record to analyse
+    pub fn alt_alleles(self, alt: &str) -> Vec<&str> {
+        match self {
+            Caller::Dragen => alt.split(',').filter(|allele| *allele != NON_REF_ALLELE).collect(),
+            _ => alt.split(',').collect(),
+        }
+    }
+
+    /// ALT column used to match a VCF record to its results
+    pub fn record_alt(self, alt: &str) -> String {
+        match self {
+            Caller::Dragen => self.alt_alleles(alt).join(","),
+            _ => alt.to_string(),
+        }
+    }
+
+    /// Variants of a record's analysed ALT alleles
+    pub(crate) fn record_variants(self, variant: &Variant) -> Vec<Variant> {
+        let alt_alleles = self.alt_alleles(&variant.alt_allele);
+        let multi_allelic = alt_alleles.len() > 1;
+        alt_alleles
+            .into_iter()
+            .map(|alt_allele| {
+                let allele = Variant::new(
+                    variant.chrom.clone(),
+                    variant.pos,
+                    variant.ref_allele.clone(),
+                    alt_allele.to_string(),
+                );
+                if self == Caller::Dragen && multi_allelic {
+                    trim_substitution(allele)
+                } else {
+                    allele
+                }
+            })
+            .collect()
+    }
+
+    /// Allele depths of a VCF data line's sample, read from the FORMAT fields the
+    /// caller writes them to; `DP` with `AD`, or with `AF` alone, otherwise
+    pub fn record_depths(self, line: &str) -> Option<AlleleDepths> {
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 10 {
+            return None;
+        }
+        let (ref_allele, alt_allele) = (columns[3], self.alt_alleles(columns[4]).first().copied()?);
+        let sample = match self {
+            Caller::Strelka2 => columns[columns.len() - 1],
+            _ => columns[9],
+        };
+        let format: Vec<&str> = columns[8].split(':').collect();
+        let values: Vec<&str> = sample.split(':').collect();
+        let value = |key: &str| {
+            let index = format.iter().position(|field| *field == key)?;
+            values.get(index).copied().filter(|value| *value != ".")
+        };
+        let count = |key: &str| value(key)?.parse::<u32>().ok();
+        // Strelka2 reports tier-1 and tier-2 counts as `t1,t2`
+        let tier1 = |key: &str| value(key)?.split(',').next()?.parse::<u32>().ok();
+
+        if self == Caller::Strelka2 {
+            if ref_allele.len() == 1 && alt_allele.len() == 1 {
+                let base_count = |base: &str| tier1(&format!("{}U", base.to_ascii_uppercase()));
+                let counts: Option<Vec<u32>> = ["A", "C", "G", "T"].iter().map(|base| base_count(base)).collect();
+                if let Some(counts) = counts {
+                    return Some(AlleleDepths {
+                        depth: counts.iter().sum(),
+                        alt_depth: base_count(alt_allele),
+                    });
+                }
+            } else if let (Some(ref_depth), Some(alt_depth)) = (tier1("TAR"), tier1("TIR")) {
+                return Some(AlleleDepths {
+                    depth: ref_depth + alt_depth + tier1("TOR").unwrap_or(0),
+                    alt_depth: Some(alt_depth),
+                });
+            }
+        }
+
+        let allele_depths: Option<Vec<u32>> = value("AD").and_then(|counts| {
+            counts.split(',').map(|count| count.parse::<u32>().ok()).collect()
+        });
+        let depth = count("DP").or_else(|| allele_depths.as_ref().map(|counts| counts.iter().sum()))?;
+        let alt_depth = match self {
+            Caller::VarDict => count("VD"),
+            _ => None,
+        }
+        .or_else(|| allele_depths.as_ref().and_then(|counts| counts.get(1).copied()))
+        .or_else(|| {
+            let fraction = value("AF")?.split(',').next()?.parse::<f64>().ok()?;
+            Some((fraction * depth as f64).round() as u32)
+        });
+        Some(AlleleDepths { depth, alt_depth })
+    }
+}
+
+/// Trim the bases a substitution shares with its REF at either end, advancing
+/// POS past shared leading bases (`CAT>GAT` at 100 becomes `C>G` at 100)
+fn trim_substitution(variant: Variant) -> Variant {
+    let (ref_bytes, alt_bytes) = (variant.ref_allele.as_bytes(), variant.alt_allele.as_bytes());
+    if ref_bytes.len() != alt_bytes.len() || ref_bytes == alt_bytes || variant.alt_allele.starts_with('<') {
+        return variant;
+    }
+    let leading = ref_bytes.iter().zip(alt_bytes).take_while(|(r, a)| r == a).count();
+    let trailing = ref_bytes.iter().rev().zip(alt_bytes.iter().rev()).take_while(|(r, a)| r == a).count();
+    let end = ref_bytes.len() - trailing;
+    Variant::new(
+        variant.chrom.clone(),
+        variant.pos + leading as u64,
+        variant.ref_allele[leading..end].to_string(),
+        variant.alt_allele[leading..end].to_string(),
+    )
+}
+
+impl FromStr for Caller {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Caller::Auto),
+            "generic" => Ok(Caller::Generic),
+            "dragen" => Ok(Caller::Dragen),
+            "strelka2" | "strelka" => Ok(Caller::Strelka2),
+            "vardict" => Ok(Caller::VarDict),
+            _ => Err(format!(
+                "unknown caller '{}' (expected auto, generic, dragen, strelka2 or vardict)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Caller::Auto => "auto",
+            Caller::Generic => "generic",
+            Caller::Dragen => "dragen",
+            Caller::Strelka2 => "strelka2",
+            Caller::VarDict => "vardict",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How to handle monomorphic reference sites (ALT `.`) in the input VCF
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MonomorphicPolicy {
+    /// Leave them out of the analysis (and unannotated)
+    #[default]
+    Skip,
+    /// Report the depth available to confirm the reference with a `Monomorphic` status
+    Report,
+}
+
+impl FromStr for MonomorphicPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(MonomorphicPolicy::Skip),
+            "report" => Ok(MonomorphicPolicy::Report),
+            _ => Err(format!("unknown monomorphic policy '{}' (expected skip or report)", s)),
+        }
+    }
+}
+
+impl fmt::Display for MonomorphicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MonomorphicPolicy::Skip => "skip",
+            MonomorphicPolicy::Report => "report",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Apply the monomorphic policy to variants before analysis
+pub fn apply_monomorphic_policy(variants: Vec<Variant>, policy: MonomorphicPolicy) -> Vec<Variant> {
+    if policy == MonomorphicPolicy::Report {
+        return variants;
+    }
+
+    let total = variants.len();
+    let kept: Vec<Variant> = variants.into_iter().filter(|variant| !variant.is_monomorphic()).collect();
+    if kept.len() < total {
+        log::warn!(
+            "Skipped {} monomorphic reference sites (ALT '.'); use --monomorphic-policy report to assess them",
+            total - kept.len()
+        );
+    }
+    kept
+}
+
+/// Remove repeated variants before analysis, returning the kept variants and the
+/// number of duplicates dropped. Duplicates score identically, so `first` and
+/// `max` both keep the first occurrence.
+pub fn dedup_variants(variants: Vec<Variant>, policy: DuplicatePolicy) -> VlodResult<(Vec<Variant>, usize)> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::with_capacity(variants.len());
+    let mut duplicates = 0;
+
+    for variant in variants {
+        if seen.contains(&variant) {
+            if policy == DuplicatePolicy::Error {
+                return Err(VlodError::InvalidVariant(format!(
+                    "Duplicate VCF record: {}:{} {}>{}",
+                    variant.chrom, variant.pos, variant.ref_allele, variant.alt_allele
+                )));
+            }
+            duplicates += 1;
+            continue;
+        }
+        seen.insert(variant.clone());
+        kept.push(variant);
+    }
+
+    Ok((kept, duplicates))
+}
+
+/// Input records of each variant whose alleles normalization changed, by the
+/// normalized variant
+pub type VariantOrigins = HashMap<Variant, Vec<Variant>>;
+
+/// Left-align and trim the alleles of a variant, keeping the VCF padding base of
+/// indels: bases shared at the end of REF and ALT are dropped (taking the base
+/// before POS from the reference when an allele runs out), then bases shared at
+/// the start. Without a reference, or when REF disagrees with it, the alleles are
+/// only trimmed. SNVs, symbolic and monomorphic alleles are returned as they are.
+pub fn normalize_variant(variant: &Variant, reference: Option<&ReferenceFasta>) -> VlodResult<Variant> {
+    let symbolic = |allele: &str| allele.is_empty() || allele.starts_with('<') || allele == "*" || allele.contains(',');
+    if variant.is_monomorphic()
+        || symbolic(&variant.ref_allele)
+        || symbolic(&variant.alt_allele)
+        || variant.ref_allele.eq_ignore_ascii_case(&variant.alt_allele)
+    {
+        return Ok(variant.clone());
+    }
+
+    let mut ref_allele = variant.ref_allele.to_ascii_uppercase().into_bytes();
+    let mut alt_allele = variant.alt_allele.to_ascii_uppercase().into_bytes();
+    let mut pos = variant.pos;
+    // The reference is only used for left shifts, once REF was checked against it
+    let mut reference = reference.filter(|reference| reference.has_contig(&variant.chrom));
+    let mut checked = false;
+    while ref_allele.last() == alt_allele.last() {
+        if ref_allele.len() == 1 || alt_allele.len() == 1 {
+            let Some(fasta) = reference.filter(|_| pos > 1) else {
+                break;
+            };
+            if !checked {
+                checked = true;
+                if !fasta.ref_matches(variant)? {
+                    reference = None;
+                    continue;
+                }
+            }
+            let base = fasta.fetch(&variant.chrom, pos - 1, pos - 1)?.into_bytes();
+            ref_allele.splice(0..0, base.iter().copied());
+            alt_allele.splice(0..0, base);
+            pos -= 1;
+        }
+        ref_allele.pop();
+        alt_allele.pop();
+    }
+    let leading = ref_allele
+        .iter()
+        .zip(&alt_allele)
+        .take_while(|(r, a)| r == a)
+        .count()
+        .min(ref_allele.len().min(alt_allele.len()) - 1);
+
+    let allele = |bases: &[u8]| String::from_utf8_lossy(&bases[leading..]).into_owned();
+    let normalized =
+        Variant::new(variant.chrom.clone(), pos + leading as u64, allele(&ref_allele), allele(&alt_allele));
+    // Alleles left unchanged keep their original case
+    if normalized.pos == variant.pos && normalized.ref_allele.eq_ignore_ascii_case(&variant.ref_allele) {
+        return Ok(variant.clone());
+    }
+    Ok(normalized)
+}
+
+/// Normalize variants before their BAM lookup (see `normalize_variant`), returning
+/// them with the input records of those that changed, for `restore_original_variants`
+pub fn normalize_variants(
+    variants: Vec<Variant>,
+    reference: Option<&ReferenceFasta>,
+) -> VlodResult<(Vec<Variant>, VariantOrigins)> {
+    let mut normalized = Vec::with_capacity(variants.len());
+    let mut origins = VariantOrigins::new();
+    for variant in variants {
+        let normal = normalize_variant(&variant, reference)?;
+        origins.entry(normal.clone()).or_default().push(variant);
+        normalized.push(normal);
+    }
+    // Keep the variants whose representation changed, with any record they now share
+    origins.retain(|normal, records| records.iter().any(|record| record != normal));
+    if !origins.is_empty() {
+        let changed: usize =
+            origins.iter().map(|(normal, records)| records.iter().filter(|record| *record != normal).count()).sum();
+        log::info!("Normalized the alleles of {} variants (left-aligned and trimmed)", changed);
+    }
+    Ok((normalized, origins))
+}
+
+/// Report the results of normalized variants under each of their input records,
+/// so that they annotate the records they came from
+pub fn restore_original_variants(
+    results: Vec<DetectabilityResult>,
+    origins: &VariantOrigins,
+) -> Vec<DetectabilityResult> {
+    if origins.is_empty() {
+        return results;
+    }
+    let mut restored = Vec::with_capacity(results.len());
+    for result in results {
+        match origins.get(&result.variant) {
+            Some(records) => restored.extend(records.iter().map(|record| {
+                let mut result = result.clone();
+                result.variant = record.clone();
+                result
+            })),
+            None => restored.push(result),
+        }
+    }
+    restored
+}
+
+/// Re-key the per-variant overrides of input records to their normalized variants
+pub fn normalize_overrides(overrides: &mut VariantOverrideMap, origins: &VariantOrigins) {
+    for (normal, records) in origins {
+        if let Some(record_overrides) = records.iter().find_map(|record| overrides.get(record)).copied() {
+            overrides.entry(normal.clone()).or_insert(record_overrides);
+        }
+    }
+}
+
+/// Combine the variants of several VCFs into one set sorted by `contig_order`, so
+/// that variants shared between inputs are analysed only once
+pub fn union_variants(variant_sets: Vec<Vec<Variant>>, contig_order: &[String]) -> Vec<Variant> {
+    let mut seen = HashSet::new();
+    let mut variants: Vec<Variant> = variant_sets
+        .into_iter()
+        .flatten()
+        .filter(|variant| seen.insert(variant.clone()))
+        .collect();
+    sort_variants(&mut variants, contig_order);
+    variants
+}
 
 /// Column indices for VCF parsing
 #[derive(Debug, Clone)]
@@ -63,6 +532,7 @@ impl VcfColumnIndices {
 #[derive(Debug, Clone)]
 pub struct VcfRecord {
     pub variant: Variant,
+    pub filter: String,
     pub info: String,
     pub format: Option<String>,
     pub samples: Vec<String>,
@@ -80,12 +550,13 @@ impl VcfRecord {
         }
 
         let chrom = fields[indices.chrom].to_string();
-        let pos = fields[indices.pos].parse::<u32>()
-            .map_err(|_| VlodError::InvalidVariant(format!("Invalid position: {}", fields[indices.pos])))?;
+        let pos = fields[indices.pos].parse::<u64>()
+            .map_err(|_| VlodError::in_column("POS", format!("Invalid position: {}", fields[indices.pos])))?;
         let ref_allele = fields[indices.ref_allele].to_string();
         let alt_allele = fields[indices.alt].to_string();
 
         let variant = Variant::new(chrom, pos, ref_allele, alt_allele);
+        let filter = fields[indices.filter].to_string();
         let info = fields[indices.info].to_string();
         let format = indices.format.and_then(|f| {
             if f < fields.len() {
@@ -102,6 +573,7 @@ impl VcfRecord {
 
         Ok(VcfRecord {
             variant,
+            filter,
             info,
             format,
             samples,
@@ -121,12 +593,13 @@ impl VcfRecord {
         }
 
         let chrom = fields[0].to_string();
-        let pos = fields[1].parse::<u32>()
-            .map_err(|_| VlodError::InvalidVariant(format!("Invalid position: {}", fields[1])))?;
+        let pos = fields[1].parse::<u64>()
+            .map_err(|_| VlodError::in_column("POS", format!("Invalid position: {}", fields[1])))?;
         let ref_allele = fields[3].to_string();
         let alt_allele = fields[4].to_string();
 
         let variant = Variant::new(chrom, pos, ref_allele, alt_allele);
+        let filter = fields[6].to_string();
         let info = fields[7].to_string();
         let format = if fields.len() > 8 {
             Some(fields[8].to_string())
@@ -141,19 +614,26 @@ impl VcfRecord {
 
         Ok(VcfRecord {
             variant,
+            filter,
             info,
             format,
             samples,
         })
     }
 
+    /// Whether the record passed all filters (FILTER is `PASS`)
+    pub fn is_pass(&self) -> bool {
+        self.filter == "PASS"
+    }
+
     pub fn to_line(&self) -> String {
         let mut line = format!(
-            "{}\t{}\t.\t{}\t{}\t.\tPASS\t{}",
+            "{}\t{}\t.\t{}\t{}\t.\t{}\t{}",
             self.variant.chrom,
             self.variant.pos,
             self.variant.ref_allele,
             self.variant.alt_allele,
+            self.filter,
             self.info
         );
 
@@ -171,29 +651,129 @@ impl VcfRecord {
     }
 }
 
+/// Coordinate sort keys following a reference contig order; contigs missing from
+/// the order rank after it, in order of first appearance
+struct SortKeys {
+    ranks: HashMap<String, usize>,
+}
+
+impl SortKeys {
+    fn new(contig_order: &[String]) -> Self {
+        let ranks = contig_order
+            .iter()
+            .enumerate()
+            .map(|(rank, contig)| (contig.clone(), rank))
+            .collect();
+        SortKeys { ranks }
+    }
+
+    fn key(&mut self, chrom: &str, pos: u64) -> (usize, u64) {
+        let next_rank = self.ranks.len();
+        let rank = *self.ranks.entry(chrom.to_string()).or_insert(next_rank);
+        (rank, pos)
+    }
+}
+
+/// Check that variants are sorted by contig (in reference order) and position
+pub fn check_sort_order(variants: &[Variant], contig_order: &[String]) -> VlodResult<()> {
+    let mut keys = SortKeys::new(contig_order);
+    let mut previous: Option<(&Variant, (usize, u64))> = None;
+
+    for variant in variants {
+        let key = keys.key(&variant.chrom, variant.pos);
+        if let Some((prev_variant, prev_key)) = previous {
+            if key < prev_key {
+                return Err(VlodError::InvalidVariant(format!(
+                    "Input VCF is not coordinate-sorted in BAM header contig order: {}:{} follows {}:{}. \
+                     Sort it (e.g. bcftools sort) or re-run with --sort-input",
+                    variant.chrom, variant.pos, prev_variant.chrom, prev_variant.pos
+                )));
+            }
+        }
+        previous = Some((variant, key));
+    }
+
+    Ok(())
+}
+
+/// Sort variants by contig (in reference order) and position, keeping input order for ties
+pub fn sort_variants(variants: &mut [Variant], contig_order: &[String]) {
+    let mut keys = SortKeys::new(contig_order);
+    // Assign ranks to unknown contigs in order of first appearance before sorting
+    for variant in variants.iter() {
+        keys.key(&variant.chrom, variant.pos);
+    }
+    variants.sort_by_cached_key(|variant| keys.key(&variant.chrom, variant.pos));
+}
+
+/// Write a coordinate-sorted copy of a VCF (uncompressed), holding its records in
+/// memory; meant for modest-size inputs
+pub fn sort_vcf_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input_path: P,
+    output_path: Q,
+    contig_order: &[String],
+    max_line_length: usize,
+) -> VlodResult<()> {
+    let reader = open_text_input(&input_path, max_line_length)?;
+
+    let mut keys = SortKeys::new(contig_order);
+    let mut header_lines = Vec::new();
+    let mut records: Vec<((usize, u64), String)> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            header_lines.push(line);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(3, '\t');
+        let chrom = fields.next().unwrap_or("");
+        let pos = fields.next().and_then(|p| p.parse::<u64>().ok()).unwrap_or(0);
+        records.push((keys.key(chrom, pos), line));
+    }
+    records.sort_by_key(|(key, _)| *key);
+
+    let mut writer = BufWriter::new(create_output_file(output_path)?);
+    for line in header_lines {
+        writeln!(writer, "{}", line)?;
+    }
+    for (_, line) in records {
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
 /// VCF file reader that handles both compressed and uncompressed files
 pub struct VcfReader {
     reader: Box<dyn BufRead>,
+    source: String,
+    line_number: u64,
 }
 
 impl VcfReader {
     pub fn new<P: AsRef<Path>>(path: P) -> VlodResult<Self> {
-        let file = File::open(&path)
-            .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
-
-        let reader: Box<dyn BufRead> = if is_gzipped(&path)? {
-            let gz_decoder = MultiGzDecoder::new(file);
-            Box::new(BufReader::new(gz_decoder))
-        } else {
-            Box::new(BufReader::new(file))
-        };
+        Self::with_max_line_length(path, DEFAULT_MAX_LINE_LENGTH)
+    }
 
-        Ok(VcfReader { reader })
+    /// Open a VCF, failing on lines longer than `max_line_length` bytes
+    pub fn with_max_line_length<P: AsRef<Path>>(path: P, max_line_length: usize) -> VlodResult<Self> {
+        let reader = open_text_input(&path, max_line_length)?;
+        Ok(VcfReader {
+            reader,
+            source: path.as_ref().to_string_lossy().to_string(),
+            line_number: 0,
+        })
     }
 
     pub fn records(&mut self) -> VcfRecordIterator {
         VcfRecordIterator {
             reader: &mut self.reader,
+            source: &self.source,
+            line_number: &mut self.line_number,
         }
     }
 
@@ -206,6 +786,7 @@ impl VcfReader {
             match self.reader.read_line(&mut line)? {
                 0 => break, // EOF
                 _ => {
+                    self.line_number += 1;
                     if line.starts_with('#') {
                         header_lines.push(line.trim_end().to_string());
                     } else {
@@ -223,6 +804,8 @@ impl VcfReader {
 /// Iterator over VCF records
 pub struct VcfRecordIterator<'a> {
     reader: &'a mut Box<dyn BufRead>,
+    source: &'a str,
+    line_number: &'a mut u64,
 }
 
 impl<'a> Iterator for VcfRecordIterator<'a> {
@@ -236,6 +819,7 @@ impl<'a> Iterator for VcfRecordIterator<'a> {
             match self.reader.read_line(&mut line) {
                 Ok(0) => return None, // EOF
                 Ok(_) => {
+                    *self.line_number += 1;
                     let line = line.trim_end();
                     if line.starts_with('#') {
                         continue; // Skip header lines
@@ -244,7 +828,9 @@ impl<'a> Iterator for VcfRecordIterator<'a> {
                         continue; // Skip empty lines
                     }
                     
-                    return Some(VcfRecord::from_line(line));
+                    return Some(
+                        VcfRecord::from_line(line).map_err(|e| e.at_line(self.source, *self.line_number)),
+                    );
                 }
                 Err(e) => return Some(Err(VlodError::Io(e))),
             }
@@ -264,32 +850,210 @@ pub fn is_gzipped<P: AsRef<Path>>(path: P) -> VlodResult<bool> {
     }
 }
 
+/// INFO tag overriding the sequencing error rate of a record
+pub const OVERRIDE_SE_TAG: &str = "VLOD_SE";
+/// INFO tag overriding the true positive rate of a record
+pub const OVERRIDE_TP_TAG: &str = "VLOD_TP";
+/// INFO tag overriding the detection threshold of a record
+pub const OVERRIDE_THRESHOLD_TAG: &str = "VLOD_THRESHOLD";
+
+/// Model parameters a VCF record overrides through its `VLOD_SE`, `VLOD_TP` and
+/// `VLOD_THRESHOLD` INFO tags, e.g. a raised error rate at a known noisy hotspot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct VariantOverrides {
+    pub p_se: Option<f64>,
+    pub p_tp: Option<f64>,
+    pub threshold: Option<f64>,
+}
+
+impl VariantOverrides {
+    /// Parse the override tags of an INFO field (None when it has none)
+    pub fn from_info(info: &str) -> VlodResult<Option<Self>> {
+        let mut overrides = VariantOverrides::default();
+        for field in info.split(';') {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let (slot, valid): (&mut Option<f64>, fn(f64) -> bool) = match key {
+                OVERRIDE_SE_TAG => (&mut overrides.p_se, |se| (0.0..1.0).contains(&se)),
+                OVERRIDE_TP_TAG => (&mut overrides.p_tp, |tp| tp > 0.0 && tp <= 1.0),
+                OVERRIDE_THRESHOLD_TAG => (&mut overrides.threshold, f64::is_finite),
+                _ => continue,
+            };
+            let parsed = value.parse::<f64>().ok().filter(|&parsed| valid(parsed));
+            *slot = Some(parsed.ok_or_else(|| VlodError::in_column("INFO", format!("Invalid {} value: {}", key, value)))?);
+        }
+        Ok((overrides != VariantOverrides::default()).then_some(overrides))
+    }
+}
+
+impl fmt::Display for VariantOverrides {
+    /// The applied overrides as INFO tags, e.g. `VLOD_SE=0.01;VLOD_THRESHOLD=3`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tags: Vec<String> = [
+            (OVERRIDE_SE_TAG, self.p_se),
+            (OVERRIDE_TP_TAG, self.p_tp),
+            (OVERRIDE_THRESHOLD_TAG, self.threshold),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| value.map(|value| format!("{}={}", tag, value)))
+        .collect();
+        write!(f, "{}", tags.join(";"))
+    }
+}
+
+/// Per-variant overrides read from an input VCF
+pub type VariantOverrideMap = HashMap<Variant, VariantOverrides>;
+
+/// Limits and caller conventions applied while reading a VCF
+#[derive(Debug, Clone, Copy)]
+pub struct VcfReadLimits {
+    /// Longest line accepted, in bytes
+    pub max_line_length: usize,
+    /// Stop after this many invalid records (None skips every invalid record)
+    pub max_errors: Option<usize>,
+    pub caller: Caller,
+}
+
+impl Default for VcfReadLimits {
+    fn default() -> Self {
+        Self {
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            max_errors: None,
+            caller: Caller::default(),
+        }
+    }
+}
+
 /// Read VCF variants from a file and return them as a vector
 pub fn read_vcf_variants<P: AsRef<Path>>(path: P) -> VlodResult<Vec<Variant>> {
-    let file = File::open(&path)
-        .map_err(|_| VlodError::FileNotFound(path.as_ref().to_string_lossy().to_string()))?;
+    read_vcf_variants_with_limits(path, &VcfReadLimits::default())
+}
+
+/// Read VCF variants from a file, enforcing line length and invalid record limits
+pub fn read_vcf_variants_with_limits<P: AsRef<Path>>(
+    path: P,
+    limits: &VcfReadLimits,
+) -> VlodResult<Vec<Variant>> {
+    Ok(without_filter_status(read_vcf_variants_with_filters(path, limits)?))
+}
 
-    let reader: Box<dyn BufRead> = if is_gzipped(&path)? {
-        let gz_decoder = MultiGzDecoder::new(file);
-        Box::new(BufReader::new(gz_decoder))
-    } else {
-        Box::new(BufReader::new(file))
-    };
+/// Read VCF variants from uncompressed VCF text
+pub fn read_vcf_variants_from_reader<R: BufRead>(reader: R) -> VlodResult<Vec<Variant>> {
+    Ok(without_filter_status(read_vcf_variants_with_filters_from_reader(reader)?))
+}
 
+/// Read VCF variants from a file, each paired with whether its record is PASS
+pub fn read_vcf_variants_with_filters<P: AsRef<Path>>(
+    path: P,
+    limits: &VcfReadLimits,
+) -> VlodResult<Vec<(Variant, bool)>> {
+    Ok(read_vcf_variants_with_warnings(path, limits)?.0)
+}
+
+/// Read VCF variants from uncompressed VCF text, each paired with whether its
+/// record is PASS
+pub fn read_vcf_variants_with_filters_from_reader<R: BufRead>(reader: R) -> VlodResult<Vec<(Variant, bool)>> {
+    Ok(read_vcf_variants_with_warnings_from_reader(reader)?.0)
+}
+
+/// Read VCF variants from a file with their PASS status, together with the
+/// records read and the invalid records skipped
+pub fn read_vcf_variants_with_warnings<P: AsRef<Path>>(
+    path: P,
+    limits: &VcfReadLimits,
+) -> VlodResult<(Vec<(Variant, bool)>, Warnings)> {
+    let input = read_vcf_input(path, limits)?;
+    Ok((input.variants, input.warnings))
+}
+
+/// Read VCF variants from uncompressed VCF text with their PASS status, together
+/// with the records read and the invalid records skipped
+pub fn read_vcf_variants_with_warnings_from_reader<R: BufRead>(
+    reader: R,
+) -> VlodResult<(Vec<(Variant, bool)>, Warnings)> {
+    let input = read_vcf_input_from_reader(reader, Caller::default())?;
+    Ok((input.variants, input.warnings))
+}
+
+/// Everything read from an input VCF in one pass
+#[derive(Debug, Clone, Default)]
+pub struct VcfInput {
+    /// Variants with whether their record is PASS
+    pub variants: Vec<(Variant, bool)>,
+    /// Parameters overridden by `VLOD_*` INFO tags, by variant
+    pub overrides: VariantOverrideMap,
+    pub warnings: Warnings,
+}
+
+/// Read the variants, per-variant overrides and warnings of a VCF file
+pub fn read_vcf_input<P: AsRef<Path>>(path: P, limits: &VcfReadLimits) -> VlodResult<VcfInput> {
+    let source = path.as_ref().to_string_lossy().to_string();
+    let reader = open_text_input(&path, limits.max_line_length)?;
+    parse_vcf_variants(reader, &source, limits.max_errors, limits.caller)
+}
+
+/// Read the variants, per-variant overrides and warnings of uncompressed VCF text
+/// written by `caller`
+pub fn read_vcf_input_from_reader<R: BufRead>(reader: R, caller: Caller) -> VlodResult<VcfInput> {
+    parse_vcf_variants(reader, "input", None, caller)
+}
+
+fn without_filter_status(variants: Vec<(Variant, bool)>) -> Vec<Variant> {
+    variants.into_iter().map(|(variant, _)| variant).collect()
+}
+
+/// Split variants read with their FILTER status into those to analyse (only PASS
+/// variants when `pass_only`) and the set of non-PASS variants, for reporting
+pub fn select_pass_variants(variants: Vec<(Variant, bool)>, pass_only: bool) -> (Vec<Variant>, HashSet<Variant>) {
+    let mut selected = Vec::with_capacity(variants.len());
+    let mut non_pass = HashSet::new();
+    for (variant, pass) in variants {
+        if !pass {
+            non_pass.insert(variant.clone());
+            if pass_only {
+                continue;
+            }
+        }
+        selected.push(variant);
+    }
+
+    if pass_only && !non_pass.is_empty() {
+        log::info!("Skipped {} non-PASS variants (--pass-only)", non_pass.len());
+    }
+    (selected, non_pass)
+}
+
+/// Parse VCF variants, skipping invalid records (up to `max_errors`) with their
+/// line numbers in `source`. A record with an invalid override tag is invalid.
+fn parse_vcf_variants<R: BufRead>(
+    reader: R,
+    source: &str,
+    max_errors: Option<usize>,
+    caller: Caller,
+) -> VlodResult<VcfInput> {
     let mut variants = Vec::new();
+    let mut overrides = VariantOverrideMap::new();
     let mut column_indices: Option<VcfColumnIndices> = None;
+    let mut errors = ParseErrorBudget::new(source, max_errors);
+    let mut warnings = Warnings::new();
+    let mut caller = caller;
 
-    for line in reader.lines() {
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index as u64 + 1;
         let line = line?;
         let line = line.trim();
 
         if line.starts_with("##") {
+            caller = caller.resolve(line);
             continue; // Skip metadata lines
         }
 
         if line.starts_with("#CHROM") || line.starts_with("#") {
             // Parse header to get column indices
-            column_indices = Some(VcfColumnIndices::from_header(&line)?);
+            column_indices = Some(
+                VcfColumnIndices::from_header(line).map_err(|e| e.at_line(source, line_number))?,
+            );
             continue;
         }
 
@@ -298,36 +1062,44 @@ pub fn read_vcf_variants<P: AsRef<Path>>(path: P) -> VlodResult<Vec<Variant>> {
         }
 
         // Parse variant line
+        warnings.records += 1;
         let record = if let Some(ref indices) = column_indices {
             // Use header-based parsing if we found a header
-            VcfRecord::from_line_with_indices(&line, indices)
+            VcfRecord::from_line_with_indices(line, indices)
         } else {
             // Fall back to standard VCF column order if no header found
-            VcfRecord::from_line(&line)
+            VcfRecord::from_line(line)
         };
+        let record = record.and_then(|record| {
+            let record_overrides = VariantOverrides::from_info(&record.info)?;
+            Ok((record, record_overrides))
+        });
 
         match record {
-            Ok(record) => {
+            Ok((record, record_overrides)) => {
                 // Handle multiple alternative alleles
-                let alt_alleles: Vec<&str> = record.variant.alt_allele.split(',').collect();
-                for alt_allele in alt_alleles {
-                    let variant = Variant::new(
-                        record.variant.chrom.clone(),
-                        record.variant.pos,
-                        record.variant.ref_allele.clone(),
-                        alt_allele.to_string(),
-                    );
-                    variants.push(variant);
+                let pass = caller.is_pass(&record.filter);
+                for variant in caller.record_variants(&record.variant) {
+                    if let Some(record_overrides) = record_overrides {
+                        overrides.insert(variant.clone(), record_overrides);
+                    }
+                    variants.push((variant, pass));
                 }
             }
             Err(e) => {
-                log::warn!("Skipping invalid VCF record: {}", e);
-                continue;
+                let e = e.at_line(source, line_number);
+                warnings.record(WarningKind::InvalidRecord, e.to_string());
+                errors.record(e)?
             }
         }
     }
+    errors.log_summary();
 
-    Ok(variants)
+    Ok(VcfInput {
+        variants,
+        overrides,
+        warnings,
+    })
 }
 
 #[cfg(test)]
@@ -336,6 +1108,48 @@ mod tests {
     use std::io::Write;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn test_normalize_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ref.fa");
+        std::fs::write(&fasta, ">chr1\nACGTTTTACG\n").unwrap();
+        let reference = ReferenceFasta::open(&fasta, true).unwrap();
+        let variant = |pos: u64, ref_allele: &str, alt_allele: &str| {
+            Variant::new("chr1".to_string(), pos, ref_allele.to_string(), alt_allele.to_string())
+        };
+        let normalize = |variant: Variant, reference: Option<&ReferenceFasta>| {
+            normalize_variant(&variant, reference).unwrap()
+        };
+
+        // A T deleted from the homopolymer at 4-7 is left-aligned onto the G at 3
+        assert_eq!(normalize(variant(6, "TT", "T"), Some(&reference)), variant(3, "GT", "G"));
+        assert_eq!(normalize(variant(7, "T", "TT"), Some(&reference)), variant(3, "G", "GT"));
+        // Without the reference, or with a REF it disagrees with, alleles are only trimmed
+        assert_eq!(normalize(variant(6, "TTA", "TA"), None), variant(6, "TT", "T"));
+        assert_eq!(normalize(variant(6, "AA", "A"), Some(&reference)), variant(6, "AA", "A"));
+        assert_eq!(normalize(variant(100, "CAT", "CGT"), None), variant(101, "A", "G"));
+        assert_eq!(normalize(variant(2, "c", "t"), Some(&reference)), variant(2, "c", "t"));
+
+        let (normalized, origins) =
+            normalize_variants(vec![variant(6, "TT", "T"), variant(5, "TT", "T"), variant(2, "C", "T")], Some(&reference))
+                .unwrap();
+        assert_eq!(normalized[0], normalized[1]);
+        assert_eq!(origins[&variant(3, "GT", "G")].len(), 2);
+
+        let mut overrides = VariantOverrideMap::new();
+        overrides.insert(variant(5, "TT", "T"), VariantOverrides::default());
+        normalize_overrides(&mut overrides, &origins);
+        assert!(overrides.contains_key(&variant(3, "GT", "G")));
+
+        let results = vec![
+            DetectabilityResult::new(normalized[0].clone(), 3.0, crate::DetectabilityCondition::Detectable, 40, 20),
+            DetectabilityResult::new(normalized[2].clone(), 1.0, crate::DetectabilityCondition::NonDetectable, 40, 2),
+        ];
+        let restored: Vec<Variant> =
+            restore_original_variants(results, &origins).into_iter().map(|result| result.variant).collect();
+        assert_eq!(restored, vec![variant(6, "TT", "T"), variant(5, "TT", "T"), variant(2, "C", "T")]);
+    }
+
     #[test]
     fn test_vcf_record_from_line() {
         let line = "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30";
@@ -353,6 +1167,7 @@ mod tests {
         let variant = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
         let record = VcfRecord {
             variant,
+            filter: "PASS".to_string(),
             info: "DP=30".to_string(),
             format: None,
             samples: Vec::new(),
@@ -362,6 +1177,69 @@ mod tests {
         assert_eq!(line, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30");
     }
 
+    #[test]
+    fn test_apply_monomorphic_policy() {
+        let variants = vec![
+            Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string()),
+            Variant::new("chr1".to_string(), 200, "G".to_string(), ".".to_string()),
+        ];
+
+        let kept = apply_monomorphic_policy(variants.clone(), MonomorphicPolicy::Skip);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].pos, 100);
+        assert_eq!(apply_monomorphic_policy(variants, MonomorphicPolicy::Report).len(), 2);
+        assert_eq!("REPORT".parse::<MonomorphicPolicy>(), Ok(MonomorphicPolicy::Report));
+    }
+
+    #[test]
+    fn test_select_pass_variants() {
+        let vcf = "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT\t.\tPASS\tDP=30\n\
+                   chr1\t200\t.\tG\tC,A\t.\tLowQual\tDP=40\n";
+        let variants = read_vcf_variants_with_filters_from_reader(vcf.as_bytes()).unwrap();
+        assert_eq!(variants.iter().filter(|(_, pass)| *pass).count(), 1);
+
+        let (selected, non_pass) = select_pass_variants(variants.clone(), false);
+        assert_eq!(selected.len(), 3);
+        assert_eq!(non_pass.len(), 2);
+
+        let (selected, _) = select_pass_variants(variants, true);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].pos, 100);
+    }
+
+    #[test]
+    fn test_read_vcf_variants_max_errors() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(temp_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(temp_file, "chr1\tabc\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(temp_file, "chr1\t200\t.\tG\tC\t.\tPASS\tDP=40").unwrap();
+        writeln!(temp_file, "chr1\t300").unwrap();
+
+        // Invalid records are skipped by default
+        let variants = read_vcf_variants(temp_file.path()).unwrap();
+        assert_eq!(variants.len(), 1);
+
+        // ... and reported as warnings
+        let (_, warnings) = read_vcf_variants_with_warnings(temp_file.path(), &VcfReadLimits::default()).unwrap();
+        assert_eq!(warnings.records, 3);
+        assert_eq!(warnings.count(WarningKind::InvalidRecord), 2);
+        assert!(warnings.warnings[0].examples[0].contains("line 3, column POS"));
+
+        let limits = VcfReadLimits {
+            max_errors: Some(1),
+            ..VcfReadLimits::default()
+        };
+        match read_vcf_variants_with_limits(temp_file.path(), &limits) {
+            Err(VlodError::InvalidVariant(msg)) => {
+                assert!(msg.contains("Stopped after 1 parse errors"));
+                assert!(msg.contains("line 3, column POS: Invalid position: abc"));
+            }
+            other => panic!("expected the error budget to be exhausted, got {:?}", other.map(|v| v.len())),
+        }
+    }
+
     #[test]
     fn test_read_vcf_variants() {
         let mut temp_file = NamedTempFile::new().unwrap();
@@ -382,4 +1260,168 @@ mod tests {
         assert_eq!(variants[2].chrom, "chr2");
         assert_eq!(variants[2].alt_allele, "A");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_read_vcf_input_dragen() {
+        let vcf = "##fileformat=VCFv4.2\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t90\t.\tG\t<NON_REF>\t.\tLowGQ\tEND=99\n\
+                   chr1\t100\t.\tA\tT,<NON_REF>\t.\tLowGQ\tDP=30\n\
+                   chr1\t200\t.\tCAT\tGAC,GAT\t.\tPASS\tDP=40\n\
+                   chr1\t300\t.\tC\tT\t.\tLowDepth;LowGQ\tDP=3\n";
+
+        // Generic VCFs keep every ALT and only PASS passes
+        let generic = read_vcf_input_from_reader(vcf.as_bytes(), Caller::Generic).unwrap();
+        assert_eq!(generic.variants.len(), 6);
+        assert!(!generic.variants[1].1);
+
+        let dragen = read_vcf_input_from_reader(vcf.as_bytes(), Caller::Dragen).unwrap();
+        let variants: Vec<(String, bool)> = dragen
+            .variants
+            .iter()
+            .map(|(v, pass)| (format!("{}:{}>{}", v.pos, v.ref_allele, v.alt_allele), *pass))
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("100:A>T".to_string(), true),
+                ("200:CAT>GAC".to_string(), true),
+                ("200:C>G".to_string(), true),
+                ("300:C>T".to_string(), false),
+            ]
+        );
+        assert_eq!("DRAGEN".parse::<Caller>(), Ok(Caller::Dragen));
+        assert_eq!(Caller::Dragen.record_alt("T,<NON_REF>"), "T");
+
+        // Auto-detection follows the DRAGEN conventions once the header names it
+        let detected = format!("##DRAGENCommandLine=<ID=dragen,Version=\"4.2\">\n{}", vcf);
+        let auto = read_vcf_input_from_reader(detected.as_bytes(), Caller::Auto).unwrap();
+        assert_eq!(auto.variants.len(), 4);
+        assert_eq!(read_vcf_input_from_reader(vcf.as_bytes(), Caller::Auto).unwrap().variants.len(), 6);
+    }
+
+    #[test]
+    fn test_caller_record_depths() {
+        assert_eq!(Caller::detect("##source=strelka"), Some(Caller::Strelka2));
+        assert_eq!(Caller::detect("##source=VarDict_v1.8.2"), Some(Caller::VarDict));
+        assert_eq!(Caller::detect("##source=GATK HaplotypeCaller"), None);
+        assert_eq!(Caller::Generic.resolve("##source=strelka"), Caller::Generic);
+        assert_eq!("Strelka2".parse::<Caller>(), Ok(Caller::Strelka2));
+        assert!("mutect2".parse::<Caller>().unwrap_err().contains("expected auto, generic"));
+
+        let depths = |caller: Caller, line: &str| {
+            caller.record_depths(line).map(|depths| (depths.depth, depths.alt_depth))
+        };
+        // Strelka2 somatic SNV and indel: tier-1 counts of the TUMOR sample
+        let snv = "chr1\t100\t.\tA\tG\t.\tPASS\tSOMATIC\tDP:AU:CU:GU:TU\t30:30,31:0,0:0,0:0,0\t40:30,32:0,0:10,11:0,1";
+        assert_eq!(depths(Caller::Strelka2, snv), Some((40, Some(10))));
+        let indel = "chr1\t200\t.\tAT\tA\t.\tPASS\tSOMATIC\tDP:TAR:TIR:TOR\t30:28,29:0,0:2,2\t40:25,27:12,12:3,3";
+        assert_eq!(depths(Caller::Strelka2, indel), Some((40, Some(12))));
+        // VarDict: ALT depth in VD, or from AF alone
+        let vardict = "chr1\t300\t.\tC\tT\t.\tPASS\tDP=50\tGT:DP:VD:AD:AF\t0/1:50:9:40,9:0.18";
+        assert_eq!(depths(Caller::VarDict, vardict), Some((50, Some(9))));
+        let fraction_only = "chr1\t300\t.\tC\tT\t.\tPASS\t.\tGT:DP:AF\t0/1:50:0.2";
+        assert_eq!(depths(Caller::VarDict, fraction_only), Some((50, Some(10))));
+        // Plain VCFs: DP, or summed AD
+        let generic = "chr1\t400\t.\tC\tT\t.\tPASS\t.\tGT:AD\t0/1:20,5";
+        assert_eq!(depths(Caller::Generic, generic), Some((25, Some(5))));
+        assert_eq!(depths(Caller::Generic, "chr1\t400\t.\tC\tT\t.\tPASS\t."), None);
+    }
+
+    #[test]
+    fn test_read_vcf_input_overrides() {
+        let vcf = "##fileformat=VCFv4.2\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   chr1\t100\t.\tA\tT,G\t.\tPASS\tDP=30;VLOD_SE=0.01;VLOD_THRESHOLD=4\n\
+                   chr1\t200\t.\tG\tC\t.\tPASS\tDP=40\n\
+                   chr1\t300\t.\tG\tC\t.\tPASS\tVLOD_TP=1.5\n";
+        let input = read_vcf_input_from_reader(vcf.as_bytes(), Caller::Generic).unwrap();
+
+        // The invalid override makes its record invalid
+        assert_eq!(input.variants.len(), 3);
+        assert_eq!(input.warnings.count(WarningKind::InvalidRecord), 1);
+        assert!(input.warnings.warnings[0].examples[0].contains("Invalid VLOD_TP value: 1.5"));
+
+        // Overrides apply to every ALT of the record
+        assert_eq!(input.overrides.len(), 2);
+        let overrides = input.overrides[&input.variants[1].0];
+        assert_eq!(overrides.p_se, Some(0.01));
+        assert_eq!(overrides.p_tp, None);
+        assert_eq!(overrides.to_string(), "VLOD_SE=0.01;VLOD_THRESHOLD=4");
+        assert_eq!(VariantOverrides::from_info("DP=40").unwrap(), None);
+        assert!(VariantOverrides::from_info("VLOD_SE=abc").is_err());
+    }
+
+    #[test]
+    fn test_dedup_variants() {
+        let snv = Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+        let other = Variant::new("chr1".to_string(), 100, "A".to_string(), "G".to_string());
+        let variants = vec![snv.clone(), other.clone(), snv.clone()];
+
+        let (kept, duplicates) = dedup_variants(variants.clone(), DuplicatePolicy::First).unwrap();
+        assert_eq!(kept, vec![snv, other]);
+        assert_eq!(duplicates, 1);
+
+        assert!(dedup_variants(variants, DuplicatePolicy::Error).is_err());
+        assert_eq!("MAX".parse::<DuplicatePolicy>(), Ok(DuplicatePolicy::Max));
+        assert!("last".parse::<DuplicatePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_union_variants() {
+        let variant = |chrom: &str, pos: u64| Variant::new(chrom.to_string(), pos, "A".to_string(), "T".to_string());
+        let order = contigs(&["chr1", "chr2"]);
+
+        let a = vec![variant("chr1", 5), variant("chr2", 1)];
+        let b = vec![variant("chr1", 2), variant("chr2", 1)];
+        assert_eq!(
+            union_variants(vec![a, b], &order),
+            vec![variant("chr1", 2), variant("chr1", 5), variant("chr2", 1)]
+        );
+    }
+
+    fn contigs(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check_sort_order() {
+        let order = contigs(&["chr1", "chr2", "chr10"]);
+        let variant = |chrom: &str, pos: u64| Variant::new(chrom.to_string(), pos, "A".to_string(), "T".to_string());
+
+        let sorted = vec![variant("chr1", 5), variant("chr1", 5), variant("chr2", 1), variant("chr10", 3)];
+        assert!(check_sort_order(&sorted, &order).is_ok());
+
+        // Lexicographic order puts chr10 before chr2, which is unsorted for this BAM
+        let lexicographic = vec![variant("chr1", 5), variant("chr10", 3), variant("chr2", 1)];
+        assert!(matches!(check_sort_order(&lexicographic, &order), Err(VlodError::InvalidVariant(_))));
+
+        let unsorted_positions = vec![variant("chr1", 50), variant("chr1", 5)];
+        assert!(check_sort_order(&unsorted_positions, &order).is_err());
+
+        let mut variants = lexicographic;
+        sort_variants(&mut variants, &order);
+        assert_eq!(variants, vec![variant("chr1", 5), variant("chr2", 1), variant("chr10", 3)]);
+    }
+
+    #[test]
+    fn test_sort_vcf_file() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "##fileformat=VCFv4.2").unwrap();
+        writeln!(temp_file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+        writeln!(temp_file, "chr2\t200\t.\tG\tC\t.\tPASS\tDP=40").unwrap();
+        writeln!(temp_file, "chr1\t300\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+        writeln!(temp_file, "chr1\t100\t.\tA\tT\t.\tPASS\tDP=30").unwrap();
+
+        let output = NamedTempFile::new().unwrap();
+        sort_vcf_file(temp_file.path(), output.path(), &contigs(&["chr1", "chr2"]), DEFAULT_MAX_LINE_LENGTH).unwrap();
+
+        let content = std::fs::read_to_string(output.path()).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("#CHROM"));
+        assert!(lines[2].starts_with("chr1\t100"));
+        assert!(lines[3].starts_with("chr1\t300"));
+        assert!(lines[4].starts_with("chr2\t200"));
+    }
+}
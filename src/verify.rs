@@ -0,0 +1,189 @@
+//! Spot-check verification: a random subset of variants is counted again with the
+//! read-iteration engine and any disagreement with the pileup counts is reported,
+//! as an internal consistency control for clinical runs
+
+use crate::{
+    bam::{AlleleCounts, BamAnalyzer},
+    noise::splitmix64,
+    warnings::{WarningKind, Warnings},
+    LodConfig, Variant, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Fixed seed so repeated runs check the same variants unless another is given
+pub const VERIFY_SAMPLING_SEED: u64 = 0x73706f74;
+
+/// Counts compared between the pileup and read-iteration engines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineCounts {
+    pub ref_reads: u32,
+    /// Reads supporting any ALT allele
+    pub alt_reads: u32,
+    pub other_reads: u32,
+    pub deleted_reads: u32,
+    pub site_depth: u32,
+}
+
+impl EngineCounts {
+    pub fn of(counts: &AlleleCounts) -> Self {
+        EngineCounts {
+            ref_reads: counts.ref_count,
+            alt_reads: counts.alt_counts.values().sum(),
+            other_reads: counts.other_count,
+            deleted_reads: counts.deleted_at_site,
+            site_depth: counts.site_depth,
+        }
+    }
+}
+
+/// A variant whose counts differ between the engines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountDisagreement {
+    pub variant: Variant,
+    pub pileup: EngineCounts,
+    pub iteration: EngineCounts,
+}
+
+/// Outcome of a spot-check verification pass, recorded in the run summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpotCheck {
+    /// Variants counted with both engines
+    pub checked: usize,
+    pub disagreements: Vec<CountDisagreement>,
+}
+
+impl SpotCheck {
+    /// Record every disagreement as a `count_disagreement` warning
+    pub fn record_warnings(&self, warnings: &mut Warnings) {
+        for disagreement in &self.disagreements {
+            let variant = &disagreement.variant;
+            warnings.record(
+                WarningKind::CountDisagreement,
+                format!(
+                    "{}:{} {}>{}: pileup {:?}, iteration {:?}",
+                    variant.chrom,
+                    variant.pos,
+                    variant.ref_allele,
+                    variant.alt_allele,
+                    disagreement.pileup,
+                    disagreement.iteration
+                ),
+            );
+        }
+    }
+}
+
+/// Check that a verification fraction is in (0, 1]
+pub fn validate_verify_fraction(fraction: f64) -> VlodResult<()> {
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(())
+    } else {
+        Err(VlodError::InvalidConfig(format!(
+            "the verification fraction must be in (0, 1], got {}",
+            fraction
+        )))
+    }
+}
+
+/// Indexes of `fraction` of `count` variants (rounded up), chosen deterministically
+/// from `seed`, in ascending order
+pub fn select_spot_checks(count: usize, fraction: f64, seed: u64) -> Vec<usize> {
+    let selected = ((count as f64 * fraction).ceil() as usize).min(count);
+    let mut indexes: Vec<usize> = (0..count).collect();
+    let mut state = seed;
+    // Partial Fisher-Yates shuffle: the first `selected` indexes are the sample
+    for i in 0..selected {
+        let j = i + (splitmix64(&mut state) % (count - i) as u64) as usize;
+        indexes.swap(i, j);
+    }
+    indexes.truncate(selected);
+    indexes.sort_unstable();
+    indexes
+}
+
+/// Count a random `fraction` of `variants` with both the pileup and the
+/// read-iteration engine and report the variants whose counts differ. Variants
+/// the contig policy does not read are skipped.
+pub fn verify_counts(
+    variants: &[Variant],
+    bam_path: &Path,
+    config: &LodConfig,
+    fraction: f64,
+    seed: u64,
+) -> VlodResult<SpotCheck> {
+    validate_verify_fraction(fraction)?;
+    let mut analyzer = BamAnalyzer::new(bam_path)?.with_config(config);
+    let mut check = SpotCheck::default();
+    for index in select_spot_checks(variants.len(), fraction, seed) {
+        let variant = &variants[index];
+        let Ok(locus) = analyzer.resolve_locus(variant) else {
+            continue;
+        };
+        let pileup = EngineCounts::of(&analyzer.analyze_variant(&locus)?);
+        let iteration = EngineCounts::of(&analyzer.analyze_variant_by_iteration(&locus)?);
+        check.checked += 1;
+        if pileup != iteration {
+            log::warn!(
+                "Spot check of {}:{} {}>{}: pileup counts {:?} differ from read iteration {:?}",
+                variant.chrom,
+                variant.pos,
+                variant.ref_allele,
+                variant.alt_allele,
+                pileup,
+                iteration
+            );
+            check.disagreements.push(CountDisagreement { variant: variant.clone(), pileup, iteration });
+        }
+    }
+    log::info!(
+        "Spot-checked {} variants with read iteration: {} count disagreements",
+        check.checked,
+        check.disagreements.len()
+    );
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_spot_checks() {
+        let selected = select_spot_checks(1000, 0.01, VERIFY_SAMPLING_SEED);
+        assert_eq!(selected.len(), 10);
+        assert!(selected.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(selected.iter().all(|&index| index < 1000));
+        assert_eq!(select_spot_checks(1000, 0.01, VERIFY_SAMPLING_SEED), selected);
+        assert_ne!(select_spot_checks(1000, 0.01, 7), selected);
+
+        // A small run still checks one variant; the whole run at most
+        assert_eq!(select_spot_checks(20, 0.01, 1).len(), 1);
+        assert_eq!(select_spot_checks(5, 1.0, 1), vec![0, 1, 2, 3, 4]);
+        assert!(select_spot_checks(0, 0.5, 1).is_empty());
+
+        assert!(validate_verify_fraction(0.01).is_ok());
+        assert!(validate_verify_fraction(0.0).is_err());
+        assert!(validate_verify_fraction(1.5).is_err());
+    }
+
+    #[test]
+    fn test_verify_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = crate::testdata::write_test_data(dir.path()).unwrap();
+        let variants: Vec<Variant> = data.expected.iter().map(|(variant, _)| variant.clone()).collect();
+
+        let check = verify_counts(&variants, &data.bam, &LodConfig::default(), 1.0, VERIFY_SAMPLING_SEED).unwrap();
+        assert_eq!(check.checked, variants.len());
+        assert!(check.disagreements.is_empty(), "{:?}", check.disagreements);
+
+        let mut warnings = Warnings::new();
+        let disagreement = CountDisagreement {
+            variant: variants[0].clone(),
+            pileup: EngineCounts::of(&AlleleCounts::new()),
+            iteration: EngineCounts { ref_reads: 1, ..EngineCounts::of(&AlleleCounts::new()) },
+        };
+        SpotCheck { checked: 1, disagreements: vec![disagreement] }.record_warnings(&mut warnings);
+        assert_eq!(warnings.count(WarningKind::CountDisagreement), 1);
+    }
+}
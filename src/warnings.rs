@@ -0,0 +1,209 @@
+//! Structured tally of the warnings raised while reading inputs and scoring, so
+//! that wrappers can alert on them (e.g. when 30% of VCF records were skipped)
+//! instead of scraping the log
+
+use crate::{DetectabilityCondition, DetectabilityResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Examples kept per warning kind
+pub const MAX_WARNING_EXAMPLES: usize = 5;
+
+/// What a warning is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A VCF record that could not be parsed was skipped
+    InvalidRecord,
+    /// A non-PASS variant was skipped (`--pass-only`)
+    NonPassSkipped,
+    /// A monomorphic reference site (ALT `.`) was skipped
+    MonomorphicSkipped,
+    /// A repeated variant was dropped
+    DuplicateVariant,
+    /// A variant was reported Not-assessable
+    NotAssessable,
+    /// A read without base qualities (QUAL `*`) was met at a variant site
+    MissingQuality,
+    /// A variant's counts are partial: its pileup reached the maximum depth
+    DepthCapped,
+    /// A spot-checked variant was counted differently by the read-iteration engine
+    CountDisagreement,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WarningKind::InvalidRecord => "invalid_record",
+            WarningKind::NonPassSkipped => "non_pass_skipped",
+            WarningKind::MonomorphicSkipped => "monomorphic_skipped",
+            WarningKind::DuplicateVariant => "duplicate_variant",
+            WarningKind::NotAssessable => "not_assessable",
+            WarningKind::MissingQuality => "missing_quality",
+            WarningKind::DepthCapped => "depth_capped",
+            WarningKind::CountDisagreement => "count_disagreement",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Occurrences of one kind of warning, with the first few examples
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub count: usize,
+    pub examples: Vec<String>,
+}
+
+/// Warnings of a run, in order of first occurrence
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warnings {
+    /// VCF data records read, the denominator of skipped-record fractions
+    pub records: usize,
+    pub warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry(&mut self, kind: WarningKind) -> &mut Warning {
+        match self.warnings.iter().position(|warning| warning.kind == kind) {
+            Some(index) => &mut self.warnings[index],
+            None => {
+                self.warnings.push(Warning {
+                    kind,
+                    count: 0,
+                    examples: Vec::new(),
+                });
+                self.warnings.last_mut().expect("warning was just added")
+            }
+        }
+    }
+
+    /// Record one occurrence, keeping its description as an example while fewer
+    /// than `MAX_WARNING_EXAMPLES` are kept
+    pub fn record(&mut self, kind: WarningKind, example: impl Into<String>) {
+        let warning = self.entry(kind);
+        warning.count += 1;
+        if warning.examples.len() < MAX_WARNING_EXAMPLES {
+            warning.examples.push(example.into());
+        }
+    }
+
+    /// Record `count` occurrences without examples
+    pub fn add(&mut self, kind: WarningKind, count: usize) {
+        if count > 0 {
+            self.entry(kind).count += count;
+        }
+    }
+
+    /// Occurrences of a kind of warning
+    pub fn count(&self, kind: WarningKind) -> usize {
+        self.warnings
+            .iter()
+            .find(|warning| warning.kind == kind)
+            .map_or(0, |warning| warning.count)
+    }
+
+    /// Occurrences of a kind of warning as a fraction of the records read, if any
+    /// were read
+    pub fn fraction(&self, kind: WarningKind) -> Option<f64> {
+        (self.records > 0).then(|| self.count(kind) as f64 / self.records as f64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Add the warnings of another input (e.g. a second VCF)
+    pub fn extend(&mut self, other: Warnings) {
+        self.records += other.records;
+        for other in other.warnings {
+            let warning = self.entry(other.kind);
+            warning.count += other.count;
+            let room = MAX_WARNING_EXAMPLES.saturating_sub(warning.examples.len());
+            warning.examples.extend(other.examples.into_iter().take(room));
+        }
+    }
+
+    /// Record the results reported Not-assessable, with their reasons, those whose
+    /// counts were cut short by the pileup depth cap, and the reads without base
+    /// qualities met while scoring them
+    pub fn record_not_assessable(&mut self, results: &[DetectabilityResult]) {
+        let missing_quality: u64 = results.iter().map(|result| u64::from(result.missing_quality_reads)).sum();
+        self.add(WarningKind::MissingQuality, missing_quality as usize);
+        for result in results {
+            let variant = &result.variant;
+            if let DetectabilityCondition::NotAssessable(reason) = &result.detectability_condition {
+                self.record(
+                    WarningKind::NotAssessable,
+                    format!(
+                        "{}:{} {}>{}: {}",
+                        variant.chrom, variant.pos, variant.ref_allele, variant.alt_allele, reason
+                    ),
+                );
+            }
+            if result.depth_capped {
+                self.record(
+                    WarningKind::DepthCapped,
+                    format!("{}:{} {}>{}", variant.chrom, variant.pos, variant.ref_allele, variant.alt_allele),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warnings() {
+        let mut warnings = Warnings::new();
+        assert!(warnings.is_empty());
+        warnings.records = 20;
+        for line in 0..8 {
+            warnings.record(WarningKind::InvalidRecord, format!("line {}", line));
+        }
+        warnings.add(WarningKind::DuplicateVariant, 2);
+        warnings.add(WarningKind::MonomorphicSkipped, 0);
+
+        assert_eq!(warnings.count(WarningKind::InvalidRecord), 8);
+        assert_eq!(warnings.warnings[0].examples.len(), MAX_WARNING_EXAMPLES);
+        assert_eq!(warnings.fraction(WarningKind::InvalidRecord), Some(0.4));
+        assert_eq!(warnings.count(WarningKind::MonomorphicSkipped), 0);
+        assert_eq!(warnings.warnings.len(), 2);
+
+        let mut other = Warnings::new();
+        other.records = 5;
+        other.record(WarningKind::DuplicateVariant, "chr1:100 A>G");
+        warnings.extend(other);
+        assert_eq!(warnings.records, 25);
+        assert_eq!(warnings.count(WarningKind::DuplicateVariant), 3);
+        assert_eq!(warnings.warnings[1].examples, vec!["chr1:100 A>G".to_string()]);
+
+        let json = serde_json::to_value(&warnings).unwrap();
+        assert_eq!(json["warnings"][0]["kind"], "invalid_record");
+        assert_eq!(Warnings::new().fraction(WarningKind::InvalidRecord), None);
+    }
+
+    #[test]
+    fn test_record_missing_quality() {
+        let result = |missing_quality_reads: u32| {
+            let variant = crate::Variant::new("chr1".to_string(), 100, "A".to_string(), "T".to_string());
+            let mut result = DetectabilityResult::new(variant, 3.5, DetectabilityCondition::Detectable, 30, 15);
+            result.missing_quality_reads = missing_quality_reads;
+            result
+        };
+
+        // Counted from each run's own results
+        let mut warnings = Warnings::new();
+        warnings.record_not_assessable(&[result(3), result(0), result(2)]);
+        assert_eq!(warnings.count(WarningKind::MissingQuality), 5);
+        let mut other_run = Warnings::new();
+        other_run.record_not_assessable(&[result(1)]);
+        assert_eq!(other_run.count(WarningKind::MissingQuality), 1);
+    }
+}
@@ -0,0 +1,516 @@
+//! Watch mode for directories of incoming samples: the inbox is polled for VCF
+//! and BAM pairs whose file names share a sample id, each pair is run through the
+//! pipeline once its files have stopped changing, and the results, annotated VCF
+//! and run summary are written to the outbox. The rules file is re-read whenever
+//! it changes, and every step is appended as a JSON line to the outbox's event log.
+//! With an audit log, each sample run is also recorded there under its request id.
+
+use crate::{
+    audit::{new_request_id, AuditEntry, AuditEvent, AuditLog},
+    bam::bam_index_candidates,
+    incremental::config_hash,
+    interrupt,
+    lod::{validate_lod_config, write_detectability_results},
+    pipeline::{run_pipeline, PipelineConfig},
+    summary::RunSummary,
+    utils::{append_extension, get_num_cpus, open_text_input, DEFAULT_MAX_LINE_LENGTH},
+    LodConfig, VlodError, VlodResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Stands for the sample id in the file name patterns of the rules
+pub const SAMPLE_PLACEHOLDER: &str = "{sample}";
+
+/// Event log written into the outbox
+pub const WATCH_EVENT_LOG: &str = "vlod_events.jsonl";
+
+/// Seconds between inbox scans unless the rules give another interval
+pub const DEFAULT_POLL_SECONDS: u64 = 10;
+
+/// Seconds a sample's files must stay unchanged before it is run, so that files
+/// still being copied into the inbox are not read
+pub const DEFAULT_SETTLE_SECONDS: u64 = 30;
+
+/// Rules of a watch, read from a TOML file. Keys left out keep their defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchRules {
+    /// File name of a sample's VCF, with `{sample}` standing for the sample id
+    pub vcf_pattern: String,
+    /// File name of a sample's BAM; its index must sit next to it
+    pub bam_pattern: String,
+    pub poll_seconds: u64,
+    pub settle_seconds: u64,
+    /// Analyse only variants whose FILTER is PASS
+    pub pass_only: bool,
+    /// Worker threads per sample; 0 uses every CPU
+    pub processes: usize,
+    pub p_tp: f64,
+    pub p_fp: f64,
+    pub p_se: f64,
+    pub score_threshold: f64,
+}
+
+impl Default for WatchRules {
+    fn default() -> Self {
+        let lod = LodConfig::default();
+        Self {
+            vcf_pattern: format!("{}.vcf.gz", SAMPLE_PLACEHOLDER),
+            bam_pattern: format!("{}.bam", SAMPLE_PLACEHOLDER),
+            poll_seconds: DEFAULT_POLL_SECONDS,
+            settle_seconds: DEFAULT_SETTLE_SECONDS,
+            pass_only: false,
+            processes: 0,
+            p_tp: lod.p_tp,
+            p_fp: lod.p_fp,
+            p_se: lod.p_se,
+            score_threshold: lod.score_threshold,
+        }
+    }
+}
+
+impl WatchRules {
+    /// Parse and check rules from TOML text
+    pub fn parse(text: &str) -> VlodResult<Self> {
+        let rules: WatchRules =
+            toml::from_str(text).map_err(|e| VlodError::InvalidConfig(format!("invalid watch rules: {}", e)))?;
+        for pattern in [&rules.vcf_pattern, &rules.bam_pattern] {
+            if pattern.matches(SAMPLE_PLACEHOLDER).count() != 1 || pattern.contains(['/', '\\']) {
+                return Err(VlodError::InvalidConfig(format!(
+                    "watch pattern '{}' must be a file name holding {} once",
+                    pattern, SAMPLE_PLACEHOLDER
+                )));
+            }
+        }
+        if rules.vcf_pattern == rules.bam_pattern {
+            return Err(VlodError::InvalidConfig("the VCF and BAM patterns of the watch rules are the same".to_string()));
+        }
+        validate_lod_config(&rules.lod_config())?;
+        Ok(rules)
+    }
+
+    pub fn load(path: &Path) -> VlodResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| VlodError::FileNotFound(format!("cannot read watch rules {}: {}", path.display(), e)))?;
+        Self::parse(&text)
+    }
+
+    pub fn lod_config(&self) -> LodConfig {
+        LodConfig {
+            p_tp: self.p_tp,
+            p_fp: self.p_fp,
+            p_se: self.p_se,
+            score_threshold: self.score_threshold,
+            ..LodConfig::default()
+        }
+    }
+
+    pub fn pipeline_config(&self) -> PipelineConfig {
+        PipelineConfig {
+            lod: self.lod_config(),
+            pass_only: self.pass_only,
+            num_processes: if self.processes == 0 { get_num_cpus() } else { self.processes },
+            ..PipelineConfig::default()
+        }
+    }
+}
+
+/// Sample id of a file name matching a pattern
+pub fn match_sample_pattern<'a>(pattern: &str, file_name: &'a str) -> Option<&'a str> {
+    let (prefix, suffix) = pattern.split_once(SAMPLE_PLACEHOLDER)?;
+    let sample = file_name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    (!sample.is_empty()).then_some(sample)
+}
+
+/// File name of a sample under a pattern
+pub fn fill_sample_pattern(pattern: &str, sample: &str) -> String {
+    pattern.replacen(SAMPLE_PLACEHOLDER, sample, 1)
+}
+
+/// What happened, in the event log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchEventKind {
+    WatchStarted,
+    WatchStopped,
+    RulesReloaded,
+    /// The changed rules file is invalid; the previous rules stay in force
+    RulesRejected,
+    ScanFailed,
+    SampleDetected,
+    SampleStarted,
+    SampleFinished,
+    /// The sample is retried once any of its files changes
+    SampleFailed,
+}
+
+/// One line of the event log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchEvent {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub event: WatchEventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+    /// Request id of a sample run, as in the audit log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detectable: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<f64>,
+}
+
+impl WatchEvent {
+    pub fn new(event: WatchEventKind, sample: Option<&str>, message: Option<String>) -> Self {
+        WatchEvent {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+            event,
+            sample: sample.map(str::to_string),
+            request_id: None,
+            message,
+            results: None,
+            detectable: None,
+            seconds: None,
+        }
+    }
+}
+
+/// Outputs of a sample in the outbox. The results TSV is written last and marks
+/// the sample as done, so a restarted watch does not run it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleOutputs {
+    pub results: PathBuf,
+    pub vcf: PathBuf,
+    pub summary: PathBuf,
+}
+
+impl SampleOutputs {
+    pub fn new(outbox: &Path, sample: &str) -> Self {
+        SampleOutputs {
+            results: outbox.join(format!("{}.vlod.tsv", sample)),
+            vcf: outbox.join(format!("{}.vlod.vcf", sample)),
+            summary: outbox.join(format!("{}.vlod.summary.json", sample)),
+        }
+    }
+}
+
+/// Size and modification time of a sample's VCF, BAM and BAM index
+type FileSignature = Vec<(u64, Option<SystemTime>)>;
+
+/// Size and modification time of a file, to notice when it changes
+fn file_state(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// Polls an inbox and runs the pipeline on every sample that arrives
+pub struct Watcher {
+    inbox: PathBuf,
+    outbox: PathBuf,
+    rules_path: PathBuf,
+    rules: WatchRules,
+    rules_state: Option<(u64, Option<SystemTime>)>,
+    /// Signature of each sample waiting for its files to settle, and since when
+    pending: HashMap<String, (FileSignature, Instant)>,
+    /// Signature of the files each failed sample was run on
+    failed: HashMap<String, FileSignature>,
+    events: File,
+    audit: Option<AuditLog>,
+}
+
+impl Watcher {
+    /// Load the rules and open the event log, creating the outbox if needed
+    pub fn new(inbox: &Path, outbox: &Path, rules_path: &Path) -> VlodResult<Self> {
+        if !inbox.is_dir() {
+            return Err(VlodError::FileNotFound(format!("inbox {} is not a directory", inbox.display())));
+        }
+        let rules = WatchRules::load(rules_path)?;
+        std::fs::create_dir_all(outbox)?;
+        let events = OpenOptions::new().create(true).append(true).open(outbox.join(WATCH_EVENT_LOG))?;
+        Ok(Watcher {
+            inbox: inbox.to_path_buf(),
+            outbox: outbox.to_path_buf(),
+            rules_path: rules_path.to_path_buf(),
+            rules,
+            rules_state: file_state(rules_path),
+            pending: HashMap::new(),
+            failed: HashMap::new(),
+            events,
+            audit: None,
+        })
+    }
+
+    /// Record every sample run in an audit log
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn rules(&self) -> &WatchRules {
+        &self.rules
+    }
+
+    /// Scan the inbox until SIGINT/SIGTERM (once
+    /// `interrupt::install_signal_handlers` was called)
+    pub fn run(&mut self) -> VlodResult<()> {
+        let message = format!("watching {} into {}", self.inbox.display(), self.outbox.display());
+        self.emit(WatchEvent::new(WatchEventKind::WatchStarted, None, Some(message)))?;
+        while !interrupt::is_interrupted() {
+            if let Err(e) = self.poll() {
+                self.emit(WatchEvent::new(WatchEventKind::ScanFailed, None, Some(e.to_string())))?;
+            }
+            let next_scan = Instant::now() + Duration::from_secs(self.rules.poll_seconds);
+            while Instant::now() < next_scan && !interrupt::is_interrupted() {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        self.emit(WatchEvent::new(WatchEventKind::WatchStopped, None, None))
+    }
+
+    /// Scan the inbox once, reloading changed rules and running every sample whose
+    /// files have settled; returns the samples finished
+    pub fn poll(&mut self) -> VlodResult<usize> {
+        self.reload_rules()?;
+        let mut finished = 0;
+        for (sample, vcf, bam) in self.arrived_samples()? {
+            if interrupt::is_interrupted() {
+                break;
+            }
+            if SampleOutputs::new(&self.outbox, &sample).results.exists() {
+                continue;
+            }
+            // The BAM index may arrive after the BAM
+            let index = bam_index_candidates(&bam).into_iter().find(|path| path.exists());
+            let Some(signature) = [Some(vcf.as_path()), Some(bam.as_path()), index.as_deref()]
+                .into_iter()
+                .map(|path| path.and_then(file_state))
+                .collect::<Option<FileSignature>>()
+            else {
+                continue;
+            };
+            if self.failed.get(&sample) == Some(&signature) {
+                continue;
+            }
+
+            let settle = Duration::from_secs(self.rules.settle_seconds);
+            match self.pending.get(&sample) {
+                Some((seen, since)) if *seen == signature => {
+                    if since.elapsed() < settle {
+                        continue;
+                    }
+                }
+                _ => {
+                    self.emit(WatchEvent::new(WatchEventKind::SampleDetected, Some(&sample), None))?;
+                    self.pending.insert(sample.clone(), (signature.clone(), Instant::now()));
+                    if !settle.is_zero() {
+                        continue;
+                    }
+                }
+            }
+            self.pending.remove(&sample);
+
+            let request_id = new_request_id();
+            let mut audit_entry = AuditEntry::new("watch", "analyze", &request_id);
+            audit_entry.samples = vec![sample.clone()];
+            audit_entry.config_hash = Some(config_hash(&self.rules.lod_config(), &bam)?);
+            audit_entry.inputs = [&vcf, &bam].iter().map(|path| path.to_string_lossy().to_string()).collect();
+            self.audit(&audit_entry, AuditEvent::Started)?;
+
+            let mut event = WatchEvent::new(WatchEventKind::SampleStarted, Some(&sample), None);
+            event.request_id = Some(request_id.clone());
+            self.emit(event)?;
+            let timer = Instant::now();
+            let mut event = match self.run_sample(&sample, &vcf, &bam) {
+                Ok((results, detectable)) => {
+                    self.failed.remove(&sample);
+                    finished += 1;
+                    let outputs = SampleOutputs::new(&self.outbox, &sample);
+                    audit_entry.add_outputs([&outputs.results, &outputs.vcf, &outputs.summary])?;
+                    self.audit(&audit_entry, AuditEvent::Finished)?;
+                    let mut event = WatchEvent::new(WatchEventKind::SampleFinished, Some(&sample), None);
+                    event.results = Some(results);
+                    event.detectable = Some(detectable);
+                    event.seconds = Some(timer.elapsed().as_secs_f64());
+                    event
+                }
+                Err(e) => {
+                    self.failed.insert(sample.clone(), signature);
+                    audit_entry.detail = Some(e.to_string());
+                    self.audit(&audit_entry, AuditEvent::Failed)?;
+                    WatchEvent::new(WatchEventKind::SampleFailed, Some(&sample), Some(e.to_string()))
+                }
+            };
+            event.request_id = Some(request_id);
+            self.emit(event)?;
+        }
+        Ok(finished)
+    }
+
+    /// Re-read the rules file when it changed, keeping the current rules if it is invalid
+    fn reload_rules(&mut self) -> VlodResult<()> {
+        let state = file_state(&self.rules_path);
+        if state == self.rules_state {
+            return Ok(());
+        }
+        self.rules_state = state;
+        match WatchRules::load(&self.rules_path) {
+            Ok(rules) if rules == self.rules => Ok(()),
+            Ok(rules) => {
+                self.rules = rules;
+                self.emit(WatchEvent::new(WatchEventKind::RulesReloaded, None, None))
+            }
+            Err(e) => self.emit(WatchEvent::new(WatchEventKind::RulesRejected, None, Some(e.to_string()))),
+        }
+    }
+
+    /// Samples with both a VCF and a BAM in the inbox, by sample id
+    fn arrived_samples(&self) -> VlodResult<Vec<(String, PathBuf, PathBuf)>> {
+        let mut samples = Vec::new();
+        for entry in std::fs::read_dir(&self.inbox)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            let Some(sample) = match_sample_pattern(&self.rules.vcf_pattern, &name) else {
+                continue;
+            };
+            let bam = self.inbox.join(fill_sample_pattern(&self.rules.bam_pattern, sample));
+            if bam.is_file() {
+                samples.push((sample.to_string(), self.inbox.join(&name), bam));
+            }
+        }
+        samples.sort();
+        Ok(samples)
+    }
+
+    /// Run the pipeline on a sample and write its outputs, each under a `.part`
+    /// name first; returns the results and detectable results
+    fn run_sample(&self, sample: &str, vcf: &Path, bam: &Path) -> VlodResult<(usize, usize)> {
+        let config = self.rules.pipeline_config();
+        let output = run_pipeline(open_text_input(vcf, DEFAULT_MAX_LINE_LENGTH)?, bam, &config)?;
+        if interrupt::is_interrupted() {
+            return Err(VlodError::InvalidConfig("the run was interrupted before every variant was scored".to_string()));
+        }
+
+        let outputs = SampleOutputs::new(&self.outbox, sample);
+        write_then_rename(&outputs.vcf, |path| Ok(std::fs::write(path, &output.annotated_vcf)?))?;
+        let mut summary = RunSummary::new(&output.results, output.warnings, false, &config.lod);
+        summary.sample = Some(sample.to_string());
+        summary.sample_source = Some("file-name".to_string());
+        write_then_rename(&outputs.summary, |path| summary.write(path))?;
+        write_then_rename(&outputs.results, |path| write_detectability_results(&output.results, path, &config.lod))?;
+
+        let detectable = output.results.iter().filter(|result| result.detectability_condition.is_detectable()).count();
+        Ok((output.results.len(), detectable))
+    }
+
+    /// Append an entry to the audit log, if there is one
+    fn audit(&self, entry: &AuditEntry, event: AuditEvent) -> VlodResult<()> {
+        match &self.audit {
+            Some(audit) => audit.record(entry, event),
+            None => Ok(()),
+        }
+    }
+
+    /// Append an event to the event log and the log
+    fn emit(&mut self, event: WatchEvent) -> VlodResult<()> {
+        let line = serde_json::to_string(&event)
+            .map_err(|e| VlodError::InvalidConfig(format!("cannot write watch event: {}", e)))?;
+        match event.event {
+            WatchEventKind::SampleFailed | WatchEventKind::RulesRejected | WatchEventKind::ScanFailed => {
+                log::warn!("{}", line)
+            }
+            _ => log::info!("{}", line),
+        }
+        writeln!(self.events, "{}", line)?;
+        self.events.flush()?;
+        Ok(())
+    }
+}
+
+/// Write a file under a `.part` name and move it into place, so that readers of
+/// the outbox never see it half written
+fn write_then_rename(path: &Path, write: impl FnOnce(&Path) -> VlodResult<()>) -> VlodResult<()> {
+    let part = append_extension(path, "part");
+    write(&part)?;
+    std::fs::rename(&part, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_rules() {
+        let rules = WatchRules::parse("vcf_pattern = \"{sample}_calls.vcf\"\nsettle_seconds = 0\np_se = 0.001\n").unwrap();
+        assert_eq!(rules.bam_pattern, "{sample}.bam");
+        assert_eq!((rules.settle_seconds, rules.lod_config().p_se), (0, 0.001));
+        assert_eq!(WatchRules::parse("").unwrap(), WatchRules::default());
+
+        assert!(WatchRules::parse("unknown = 1").is_err());
+        assert!(WatchRules::parse("vcf_pattern = \"calls.vcf\"").is_err());
+        assert!(WatchRules::parse("bam_pattern = \"in/{sample}.bam\"").is_err());
+        assert!(WatchRules::parse("p_tp = 0.0").is_err());
+
+        assert_eq!(match_sample_pattern("{sample}_calls.vcf", "S1_calls.vcf"), Some("S1"));
+        assert_eq!(match_sample_pattern("{sample}_calls.vcf", "_calls.vcf"), None);
+        assert_eq!(match_sample_pattern("{sample}_calls.vcf", "S1.bam"), None);
+        assert_eq!(fill_sample_pattern("{sample}.bam", "S1"), "S1.bam");
+    }
+
+    #[test]
+    fn test_watcher_poll() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = dir.path().join("inbox");
+        let outbox = dir.path().join("outbox");
+        let data = crate::testdata::write_test_data(&inbox).unwrap();
+        let rules_path = dir.path().join("rules.toml");
+        let rules = "vcf_pattern = \"{sample}.vcf\"\nsettle_seconds = 0\nprocesses = 1\n";
+        std::fs::write(&rules_path, rules).unwrap();
+
+        let audit_path = dir.path().join("audit.jsonl");
+        let audit = AuditLog::open(&audit_path).unwrap();
+        let mut watcher = Watcher::new(&inbox, &outbox, &rules_path).unwrap().with_audit_log(audit);
+        assert_eq!(watcher.poll().unwrap(), 1);
+        let outputs = SampleOutputs::new(&outbox, crate::testdata::TEST_DATA_PREFIX);
+        assert!(outputs.vcf.exists() && outputs.summary.exists());
+        let results = std::fs::read_to_string(&outputs.results).unwrap();
+        assert_eq!(results.lines().count(), data.expected.len() + 2);
+        // Done samples are not run again
+        assert_eq!(watcher.poll().unwrap(), 0);
+
+        // An invalid rules file is rejected and the rules in force are kept
+        std::fs::write(&rules_path, "settle_seconds = \"soon\"\n").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 0);
+        assert_eq!(watcher.rules().vcf_pattern, "{sample}.vcf");
+        std::fs::write(&rules_path, format!("{}poll_seconds = 1\n", rules)).unwrap();
+        watcher.poll().unwrap();
+        assert_eq!(watcher.rules().poll_seconds, 1);
+
+        let events = std::fs::read_to_string(outbox.join(WATCH_EVENT_LOG)).unwrap();
+        let kinds: Vec<String> = events
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["event"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            ["sample_detected", "sample_started", "sample_finished", "rules_rejected", "rules_reloaded"]
+        );
+
+        // The sample run is audited under one request id, with the checksum of each output
+        let entries = crate::audit::read_audit_log(&audit_path).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.event).collect::<Vec<_>>(), [AuditEvent::Started, AuditEvent::Finished]);
+        assert_eq!(entries[0].request_id, entries[1].request_id);
+        assert_eq!(entries[1].samples, [crate::testdata::TEST_DATA_PREFIX]);
+        assert_eq!(entries[1].outputs.len(), 3);
+        assert!(events.contains(&entries[1].request_id));
+    }
+}
@@ -0,0 +1,156 @@
+//! Excel workbook of the results for clinical reviewers: the detectability table
+//! with Non-detectable variants highlighted, and a summary sheet
+
+use crate::{
+    lod::RESULT_COLUMNS, summary::RunSummary, DetectabilityCondition, DetectabilityResult,
+    VlodError, VlodResult,
+};
+use rust_xlsxwriter::{
+    column_number_to_name, Color, ConditionalFormatFormula, Format, Workbook, XlsxError,
+};
+use std::path::Path;
+
+impl From<XlsxError> for VlodError {
+    fn from(error: XlsxError) -> Self {
+        VlodError::Io(std::io::Error::other(format!(
+            "cannot write workbook: {}",
+            error
+        )))
+    }
+}
+
+/// Columns written as text even when their values look numeric (e.g. chromosome `1`)
+const TEXT_COLUMNS: [&str; 4] = ["chrom", "ref", "alt", "sample"];
+
+/// Excel's "bad" cell style, used for Non-detectable rows
+const HIGHLIGHT_FILL: u32 = 0xFFC7CE;
+const HIGHLIGHT_FONT: u32 = 0x9C0006;
+
+const HEADER_FILL: u32 = 0xD9E1F2;
+
+/// Write results to an `.xlsx` workbook with a `Results` sheet (every TSV column,
+/// filterable, Non-detectable rows highlighted) and a `Summary` sheet
+pub fn write_xlsx_report<P: AsRef<Path>>(
+    results: &[DetectabilityResult],
+    summary: &RunSummary,
+    path: P,
+) -> VlodResult<()> {
+    let mut workbook = Workbook::new();
+    let header = Format::new()
+        .set_bold()
+        .set_background_color(Color::RGB(HEADER_FILL));
+    let highlight = Format::new()
+        .set_background_color(Color::RGB(HIGHLIGHT_FILL))
+        .set_font_color(Color::RGB(HIGHLIGHT_FONT));
+
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Results")?;
+    for (col, column) in RESULT_COLUMNS.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, column.header, &header)?;
+        sheet.set_column_width(col as u16, column.header.len().max(8) as f64 + 2.0)?;
+    }
+    for (row, result) in results.iter().enumerate() {
+        let row = row as u32 + 1;
+        for (col, column) in RESULT_COLUMNS.iter().enumerate() {
+            let value = column.format(result);
+            match value.parse::<f64>() {
+                Ok(number) if number.is_finite() && !TEXT_COLUMNS.contains(&column.name) => {
+                    sheet.write_number(row, col as u16, number)?
+                }
+                _ => sheet.write_string(row, col as u16, value)?,
+            };
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    if !results.is_empty() {
+        let (last_row, last_col) = (results.len() as u32, RESULT_COLUMNS.len() as u16 - 1);
+        sheet.autofilter(0, 0, last_row, last_col)?;
+        let condition_col = RESULT_COLUMNS
+            .iter()
+            .position(|column| column.name == "detectability_condition")
+            .expect("the condition is a result column");
+        let rule = format!(
+            "=${}2=\"{}\"",
+            column_number_to_name(condition_col as u16),
+            DetectabilityCondition::NonDetectable
+        );
+        let non_detectable = ConditionalFormatFormula::new()
+            .set_rule(rule.as_str())
+            .set_format(&highlight);
+        sheet.add_conditional_format(1, 0, last_row, last_col, &non_detectable)?;
+    }
+
+    let mut rows: Vec<(String, String)> = vec![
+        (
+            "vLoD version".to_string(),
+            summary.about.vlod_version.clone(),
+        ),
+        (
+            "Aligner preset".to_string(),
+            summary.preset.clone().unwrap_or_else(|| "none".to_string()),
+        ),
+        (
+            "Partial run".to_string(),
+            if summary.partial { "yes" } else { "no" }.to_string(),
+        ),
+        ("Variants".to_string(), summary.results.to_string()),
+    ];
+    for (status, count) in &summary.conditions {
+        rows.push((format!("DET={}", status), count.to_string()));
+    }
+    for warning in &summary.warnings.warnings {
+        rows.push((
+            format!("Warning: {}", warning.kind),
+            warning.count.to_string(),
+        ));
+    }
+
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Summary")?;
+    sheet.set_column_width(0, 28)?;
+    sheet.set_column_width(1, 16)?;
+    for (row, (label, value)) in rows.into_iter().enumerate() {
+        sheet.write_string_with_format(row as u32, 0, label, &header)?;
+        match value.parse::<u32>() {
+            Ok(count) => sheet.write_number(row as u32, 1, count)?,
+            Err(_) => sheet.write_string(row as u32, 1, value)?,
+        };
+    }
+
+    workbook.save(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{warnings::Warnings, LodConfig, Variant};
+
+    #[test]
+    fn test_write_xlsx_report() {
+        let result = |pos: u64, condition: DetectabilityCondition| {
+            DetectabilityResult::new(
+                Variant::new("1".to_string(), pos, "A".to_string(), "G".to_string()),
+                3.0,
+                condition,
+                100,
+                5,
+            )
+        };
+        let results = vec![
+            result(100, DetectabilityCondition::Detectable),
+            result(200, DetectabilityCondition::NonDetectable),
+        ];
+        let summary = RunSummary::new(&results, Warnings::new(), false, &LodConfig::default());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.xlsx");
+        write_xlsx_report(&results, &summary, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"PK"));
+
+        // An empty run still gets both sheets
+        let summary = RunSummary::new(&[], Warnings::new(), true, &LodConfig::default());
+        write_xlsx_report(&[], &summary, &path).unwrap();
+    }
+}